@@ -0,0 +1,126 @@
+#![doc = include_str!("../README.md")]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Fields, LitInt, LitStr, Path,
+};
+
+/// Implement `FromProperties` for a struct with named fields, see the [crate] documentation.
+#[proc_macro_derive(FromProperties, attributes(property))]
+pub fn derive_from_properties(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    from_properties(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Where the value of a field comes from.
+enum PropertySource {
+    Tag(LitInt),
+    Named { set: Path, name: NamedSource },
+}
+
+enum NamedSource {
+    Name(LitStr),
+    Lid(LitInt),
+}
+
+fn from_properties(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new(
+            input.span(),
+            "FromProperties can only be derived for a struct",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new(
+            input.span(),
+            "FromProperties needs a struct with named fields",
+        ));
+    };
+
+    let mut initializers = vec![];
+    for field in fields.named.iter() {
+        let ident = field.ident.as_ref().expect("named field");
+        let field_name = ident.to_string();
+        let source = property_source(field)?;
+        let value = match source {
+            PropertySource::Tag(tag) => quote! { fields.tag(#tag) },
+            PropertySource::Named { set, name } => {
+                let name = match name {
+                    NamedSource::Name(name) => quote! { #name },
+                    NamedSource::Lid(lid) => quote! { #lid as u32 },
+                };
+                quote! { fields.named(#field_name, &#set, #name)? }
+            }
+        };
+        initializers.push(quote! { #ident: fields.field(#field_name, #value)? });
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::outlook_pst::messaging::from_properties::FromProperties
+            for #ident #type_generics #where_clause
+        {
+            fn from_properties(
+                fields: &::outlook_pst::messaging::from_properties::PropertyFields,
+            ) -> ::std::io::Result<Self> {
+                Ok(Self {
+                    #(#initializers,)*
+                })
+            }
+        }
+    })
+}
+
+/// Parse `#[property(tag = 0x0037)]`, `#[property(set = PS_PUBLIC_STRINGS, name = "Keywords")]`
+/// or `#[property(set = PSETID_COMMON, lid = 0x8580)]`.
+fn property_source(field: &syn::Field) -> syn::Result<PropertySource> {
+    let mut attrs = field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("property"));
+    let Some(attr) = attrs.next() else {
+        return Err(Error::new(
+            field.span(),
+            "missing #[property(tag = ...)] or #[property(set = ..., name = ...)]",
+        ));
+    };
+    if let Some(attr) = attrs.next() {
+        return Err(Error::new(attr.span(), "duplicate #[property] attribute"));
+    }
+
+    let mut tag = None;
+    let mut set = None;
+    let mut name = None;
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("tag") {
+            let value: LitInt = meta.value()?.parse()?;
+            value.base10_parse::<u16>()?;
+            tag = Some(value);
+        } else if meta.path.is_ident("set") {
+            set = Some(meta.value()?.parse::<Path>()?);
+        } else if meta.path.is_ident("name") {
+            name = Some(NamedSource::Name(meta.value()?.parse()?));
+        } else if meta.path.is_ident("lid") {
+            let value: LitInt = meta.value()?.parse()?;
+            value.base10_parse::<u32>()?;
+            name = Some(NamedSource::Lid(value));
+        } else {
+            return Err(meta.error("expected `tag`, `set`, `name` or `lid`"));
+        }
+        Ok(())
+    })?;
+
+    match (tag, set, name) {
+        (Some(tag), None, None) => Ok(PropertySource::Tag(tag)),
+        (None, Some(set), Some(name)) => Ok(PropertySource::Named { set, name }),
+        _ => Err(Error::new(
+            attr.span(),
+            "expected either `tag`, or `set` with `name` or `lid`",
+        )),
+    }
+}
@@ -0,0 +1,59 @@
+use outlook_pst::{
+    ltp::prop_context::{PropertyValue, UnicodeValue},
+    messaging::{
+        from_properties::{FromProperties, PropertyFields},
+        named_prop::{NamedPropertyMapProperties, NamedPropertyName, PS_PUBLIC_STRINGS},
+        psetid::PSETID_COMMON,
+    },
+};
+use outlook_pst_derive::FromProperties;
+use std::collections::BTreeMap;
+
+#[derive(FromProperties)]
+struct Note {
+    #[property(tag = 0x0037)]
+    subject: String,
+    #[property(tag = 0x0E06)]
+    delivery_time: Option<i64>,
+    #[property(set = PS_PUBLIC_STRINGS, name = "Keywords")]
+    keywords: Option<Vec<String>>,
+    #[property(set = PSETID_COMMON, lid = 0x8516)]
+    reminder_time: Option<i64>,
+}
+
+fn unicode(value: &str) -> UnicodeValue {
+    UnicodeValue::new(value.encode_utf16().collect())
+}
+
+#[test]
+fn test_derive_from_properties() {
+    let mut named_properties =
+        NamedPropertyMapProperties::from(BTreeMap::from([(0x0001, PropertyValue::Integer32(251))]));
+    let keywords = named_properties
+        .insert(&PS_PUBLIC_STRINGS, &NamedPropertyName::from("Keywords"))
+        .unwrap();
+
+    let mut properties = BTreeMap::from([
+        (0x0037, PropertyValue::Unicode(unicode("Lunch"))),
+        (
+            keywords,
+            PropertyValue::MultipleUnicode(vec![unicode("food"), unicode("team")]),
+        ),
+    ]);
+    let fields = PropertyFields::new(&properties).with_named_properties(&named_properties);
+    let note = Note::from_properties(&fields).unwrap();
+    assert_eq!(note.subject, "Lunch");
+    assert_eq!(note.delivery_time, None);
+    assert_eq!(
+        note.keywords,
+        Some(vec!["food".to_string(), "team".to_string()])
+    );
+    assert_eq!(note.reminder_time, None);
+
+    properties.insert(0x0E06, PropertyValue::Boolean(false));
+    let fields = PropertyFields::new(&properties).with_named_properties(&named_properties);
+    assert!(Note::from_properties(&fields).is_err());
+
+    properties.remove(&0x0E06);
+    assert!(Note::from_properties(&PropertyFields::new(&properties)).is_err());
+}
@@ -0,0 +1,105 @@
+//! Compare the UTF-16 decoding in `ltp::utf16` with [`String::from_utf16_lossy`]:
+//!
+//! ```sh
+//! cargo bench -p outlook-pst --bench utf16
+//! ```
+
+use outlook_pst::ltp::{
+    prop_context::{build_property_context, read_property_context, PropertyValue, UnicodeValue},
+    utf16::{decode_utf16_lossy, Utf16Decoder},
+};
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+/// Number of times to decode each sample.
+const ITERATIONS: u32 = 20_000;
+
+fn time(iterations: u32, mut f: impl FnMut()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    start.elapsed() / iterations
+}
+
+fn report(name: &str, baseline: Duration, elapsed: Duration) {
+    println!(
+        "  {name:<24} {:>10.2?} {:>6.2}x",
+        elapsed,
+        baseline.as_secs_f64() / elapsed.as_secs_f64()
+    );
+}
+
+fn main() -> anyhow::Result<()> {
+    let corpora = [
+        (
+            "ASCII",
+            (0..256)
+                .map(|i| format!("IPM.Note message {i} with a typical ASCII subject line"))
+                .collect::<Vec<_>>(),
+        ),
+        (
+            "mixed",
+            (0..256)
+                .map(|i| format!("Grüße {i}: 会議の議事録 and some ASCII to follow 😀"))
+                .collect(),
+        ),
+    ];
+
+    for (name, corpus) in corpora.iter() {
+        let samples: Vec<Vec<u16>> = corpus
+            .iter()
+            .map(|text| text.encode_utf16().collect())
+            .collect();
+        println!("{name} ({} strings per iteration):", samples.len());
+
+        let baseline = time(ITERATIONS, || {
+            for sample in samples.iter() {
+                black_box(String::from_utf16_lossy(black_box(sample)));
+            }
+        });
+        report("String::from_utf16_lossy", baseline, baseline);
+
+        let elapsed = time(ITERATIONS, || {
+            for sample in samples.iter() {
+                black_box(decode_utf16_lossy(black_box(sample)));
+            }
+        });
+        report("decode_utf16_lossy", baseline, elapsed);
+
+        let mut decoder = Utf16Decoder::new();
+        let elapsed = time(ITERATIONS, || {
+            for sample in samples.iter() {
+                black_box(decoder.decode(black_box(sample)).len());
+            }
+        });
+        report("Utf16Decoder::decode", baseline, elapsed);
+    }
+
+    // Read a property context with one `PtypString` value per sample, which goes through the
+    // scratch buffer used by the property and table context readers.
+    let properties: Vec<_> = corpora[0]
+        .1
+        .iter()
+        .enumerate()
+        .map(|(index, text)| {
+            (
+                0x8000 + index as u16,
+                PropertyValue::Unicode(UnicodeValue::new(text.encode_utf16().collect())),
+            )
+        })
+        .collect();
+    let data = build_property_context(properties.iter().map(|(prop_id, value)| (prop_id, value)))?;
+    let iterations = (ITERATIONS / 10).max(1);
+    let elapsed = time(iterations, || {
+        black_box(read_property_context(black_box(&data)).expect("Failed to read PC"));
+    });
+    println!(
+        "read_property_context ({} PtypString values): {elapsed:.2?}",
+        properties.len()
+    );
+
+    Ok(())
+}
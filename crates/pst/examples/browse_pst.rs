@@ -40,6 +40,7 @@ struct IpmSubTree {
 impl IpmSubTree {
     fn new(path: impl AsRef<Path>) -> anyhow::Result<Self> {
         let pst_store = outlook_pst::open_store(path)?;
+        pst_store.set_code_page_decoder(Rc::new(encoding::CodePageStringsDecoder));
 
         Ok(Self {
             display_name: Default::default(),
@@ -79,8 +80,7 @@ impl IpmSubTree {
                         hierarchy_table
                             .rows_matrix()
                             .map(|row| {
-                                let node = NodeId::from(u32::from(row.id()));
-                                let entry_id = self.store().properties().make_entry_id(node)?;
+                                let entry_id = ipm_subtree_folder.entry_id_for_row(row);
                                 Ok(self.store().open_folder(&entry_id)?)
                             })
                             .collect()
@@ -137,9 +137,7 @@ impl Folder {
                             hierarchy_table
                                 .rows_matrix()
                                 .map(|row| {
-                                    let node = NodeId::from(u32::from(row.id()));
-                                    let entry_id =
-                                        self.pst_folder.store().properties().make_entry_id(node)?;
+                                    let entry_id = self.pst_folder.entry_id_for_row(row);
                                     Ok(self.pst_folder.store().open_folder(&entry_id)?)
                                 })
                                 .collect()
@@ -465,10 +463,8 @@ impl AppState {
             KeyCode::Char('h') | KeyCode::Left => self.go_back(),
             KeyCode::Char('j') | KeyCode::Down => list_state.select_next(),
             KeyCode::Char('k') | KeyCode::Up => list_state.select_previous(),
-            KeyCode::Char('l') | KeyCode::Right => {
-                if self.current_pane == Pane::Folders {
-                    self.change_folder(self.folder_state.selected());
-                }
+            KeyCode::Char('l') | KeyCode::Right if self.current_pane == Pane::Folders => {
+                self.change_folder(self.folder_state.selected());
             }
             KeyCode::Char('g') | KeyCode::Home => list_state.select_first(),
             KeyCode::Char('G') | KeyCode::End => list_state.select_last(),
@@ -0,0 +1,107 @@
+//! Measure the throughput of a bulk EML import, with and without parsing on worker threads:
+//!
+//! ```sh
+//! cargo run --release -p outlook-pst --example bulk_import_bench -- --messages 20000
+//! ```
+//!
+//! The messages are generated in a temporary directory, and each run imports them into a fresh
+//! copy of `Empty.pst`.
+
+use clap::Parser;
+use outlook_pst::{
+    messaging::{
+        import::bulk::{import_dir, BulkImportSummary},
+        object_id::FolderId,
+    },
+    UnicodePstFile,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+#[derive(Parser)]
+#[command(version, about, long_about)]
+struct Args {
+    #[clap(short, long, default_value = r#"crates/pst/examples/Empty.pst"#)]
+    template: PathBuf,
+    /// Number of messages to generate.
+    #[clap(short, long, default_value_t = 5_000)]
+    messages: usize,
+    /// Size of the base64 attachment on every message, in bytes.
+    #[clap(short, long, default_value_t = 4_096)]
+    attachment_size: usize,
+}
+
+fn write_messages(dir: &Path, count: usize, attachment_size: usize) -> anyhow::Result<()> {
+    let line = "QUJDREVGR0hJSktMTU5PUFFSU1RVVldYWVphYmNkZWZnaGlqa2xtbm9wcXJzdHV2d3h5ejAxMjM0\r\n";
+    let attachment = line.repeat(attachment_size.div_ceil(line.len()));
+    for index in 0..count {
+        let message = format!(
+            "From: Sender {index} <sender{index}@example.com>\r\n\
+             To: Recipient <recipient@example.com>\r\n\
+             Subject: Bulk import message {index}\r\n\
+             Date: Tue, 1 Jul 2003 08:52:37 +0000\r\n\
+             Message-ID: <{index}@example.com>\r\n\
+             MIME-Version: 1.0\r\n\
+             Content-Type: multipart/mixed; boundary=\"part\"\r\n\
+             \r\n\
+             --part\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             \r\n\
+             Body of message {index}, with a few lines of text.\r\n\
+             \r\n\
+             --part\r\n\
+             Content-Type: application/octet-stream; name=\"data{index}.bin\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\
+             Content-Disposition: attachment; filename=\"data{index}.bin\"\r\n\
+             \r\n\
+             {attachment}\
+             --part--\r\n"
+        );
+        fs::write(dir.join(format!("{index:08}.eml")), message)?;
+    }
+    Ok(())
+}
+
+fn run(
+    template: &Path,
+    dir: &Path,
+    parallel_parse: bool,
+) -> anyhow::Result<(BulkImportSummary, Duration)> {
+    let path = dir.with_extension("pst");
+    fs::copy(template, &path)?;
+
+    let store = outlook_pst::open_store(&path)?;
+    let folder = store.properties().ipm_sub_tree_entry_id()?;
+    let folder = FolderId::try_from(folder.node_id())?;
+    drop(store);
+
+    let mut pst = UnicodePstFile::open(&path)?;
+    let start = Instant::now();
+    let summary = import_dir(&mut pst, folder, dir, parallel_parse, |_| {})?;
+    let elapsed = start.elapsed();
+    drop(pst);
+    fs::remove_file(path)?;
+    Ok((summary, elapsed))
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::try_parse()?;
+    let dir = std::env::temp_dir().join(format!("bulk_import_bench-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    write_messages(&dir, args.messages, args.attachment_size)?;
+
+    for parallel_parse in [false, true] {
+        let (summary, elapsed) = run(&args.template, &dir, parallel_parse)?;
+        println!(
+            "parallel_parse: {parallel_parse:<5} {:>6} messages in {elapsed:>8.2?} ({:>8.0} messages per minute)",
+            summary.imported(),
+            summary.imported() as f64 * 60.0 / elapsed.as_secs_f64()
+        );
+    }
+
+    fs::remove_dir_all(dir)?;
+    Ok(())
+}
@@ -1,5 +1,16 @@
 use compressed_rtf::*;
-use outlook_pst::ltp::prop_context::PropertyValue;
+use outlook_pst::ltp::{code_page::CodePageDecoder, prop_context::PropertyValue};
+
+/// Decode `PtypString8` values in the code pages which `codepage-strings` supports, falling back
+/// to the built-in decoder for the rest.
+pub struct CodePageStringsDecoder;
+
+impl CodePageDecoder for CodePageStringsDecoder {
+    fn decode(&self, buffer: &[u8], code_page: u16) -> Option<String> {
+        let coding = codepage_strings::Coding::new(code_page).ok()?;
+        Some(coding.decode(buffer).ok()?.to_string())
+    }
+}
 
 pub fn decode_subject(value: &PropertyValue) -> Option<String> {
     match value {
@@ -0,0 +1,625 @@
+//! Export every message in a PST file to EML files, and print how long each subsystem took at the
+//! end, so the same file can be exported before and after a performance change to compare them.
+//!
+//! The phases are measured around the calls which do the work, and the time spent reading the
+//! file underneath them is counted as IO instead:
+//! - NDB reads: opening the store, folders, messages, and attachments.
+//! - LTP decode: reading the contents and attachment tables, and converting property values for
+//!   the EML headers and bodies.
+//! - RTF: decompressing `PidTagRtfCompressed`.
+//! - IO: reading the PST file, and writing the EML files.
+//!
+//! With `--attachment-store`, attachments are not embedded in the EML files. Each unique
+//! attachment is written once to the store, named by a hash of its content, and the attachments
+//! of each message are listed in a manifest next to its EML file, so an archive where the same
+//! newsletter images or signatures are attached to thousands of messages only stores them once.
+
+use clap::Parser;
+use compressed_rtf::decompress_rtf;
+use outlook_pst::{
+    facade::{MailFolder, MailMessage, MessageClassFilter, PstArchive},
+    ltp::prop_context::PropertyValue,
+    messaging::{
+        attachment::AttachmentMethod,
+        store::{AnsiStore, Store, UnicodeStore},
+    },
+    AnsiPstFile, UnicodePstFile,
+};
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+#[derive(Parser)]
+#[command(version, about, long_about)]
+struct Args {
+    #[clap(default_value = r#"crates/pst/examples/Empty.pst"#)]
+    file: String,
+    /// Directory for the EML files, with a sub-directory for each folder.
+    #[clap(short, long, default_value = "eml")]
+    output: PathBuf,
+    /// Write each unique attachment once to this directory, and list the attachments of each
+    /// message in a `.manifest` file next to its EML file instead of embedding them.
+    #[clap(long)]
+    attachment_store: Option<PathBuf>,
+    /// With `--attachment-store`, also hard link the attachments of each message into a
+    /// directory next to its EML file, under their original names.
+    #[clap(long, requires = "attachment_store")]
+    hard_links: bool,
+    /// Only export messages whose `PidTagMessageClass` matches one of these patterns, e.g.
+    /// `--include 'IPM.Note*'`.
+    #[clap(long)]
+    include: Vec<String>,
+    /// Skip messages whose `PidTagMessageClass` matches one of these patterns, e.g.
+    /// `--exclude 'IPM.Appointment*'`.
+    #[clap(long)]
+    exclude: Vec<String>,
+}
+
+/// The reader is sent to the [`UnicodePstFile`] or [`AnsiPstFile`], so the counters are atomic.
+#[derive(Default)]
+struct IoCounters {
+    elapsed_nanos: AtomicU64,
+    reads: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl IoCounters {
+    fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed_nanos.load(Ordering::Relaxed))
+    }
+
+    fn add_elapsed(&self, start: Instant) {
+        self.elapsed_nanos
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Count the time and bytes of every read and seek which reaches the PST file.
+struct TimedReader<R> {
+    inner: R,
+    counters: Arc<IoCounters>,
+}
+
+impl<R: Read> Read for TimedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let start = Instant::now();
+        let result = self.inner.read(buf);
+        let counters = &self.counters;
+        counters.add_elapsed(start);
+        counters.reads.fetch_add(1, Ordering::Relaxed);
+        if let Ok(size) = result {
+            counters.bytes.fetch_add(size as u64, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+impl<R: Seek> Seek for TimedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let start = Instant::now();
+        let result = self.inner.seek(pos);
+        self.counters.add_elapsed(start);
+        result
+    }
+}
+
+#[derive(Default)]
+struct Timings {
+    io: Arc<IoCounters>,
+    ndb: Cell<Duration>,
+    ltp: Cell<Duration>,
+    rtf: Cell<Duration>,
+    write: Cell<Duration>,
+    bytes_written: Cell<u64>,
+}
+
+impl Timings {
+    /// Run `f` and add the time it took to `phase`, except for the time spent reading the file.
+    fn measure<T>(&self, phase: &Cell<Duration>, f: impl FnOnce() -> T) -> T {
+        let io_before = self.io.elapsed();
+        let start = Instant::now();
+        let result = f();
+        let io = self.io.elapsed() - io_before;
+        phase.set(phase.get() + start.elapsed().saturating_sub(io));
+        result
+    }
+
+    fn ndb<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.measure(&self.ndb, f)
+    }
+
+    fn ltp<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.measure(&self.ltp, f)
+    }
+
+    fn rtf<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.measure(&self.rtf, f)
+    }
+
+    fn open_store(&self, path: &str) -> io::Result<Rc<dyn Store>> {
+        self.ndb(|| {
+            if let Ok(pst) = UnicodePstFile::read_from(self.reader(path)?) {
+                let store: Rc<dyn Store> = UnicodeStore::read(Rc::new(pst))?;
+                Ok(store)
+            } else {
+                let pst = AnsiPstFile::read_from(self.reader(path)?)?;
+                let store: Rc<dyn Store> = AnsiStore::read(Rc::new(pst))?;
+                Ok(store)
+            }
+        })
+    }
+
+    fn reader(&self, path: &str) -> io::Result<Box<TimedReader<BufReader<File>>>> {
+        Ok(Box::new(TimedReader {
+            inner: BufReader::new(File::open(path)?),
+            counters: self.io.clone(),
+        }))
+    }
+
+    fn write_file(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let start = Instant::now();
+        fs::write(path, contents)?;
+        self.write.set(self.write.get() + start.elapsed());
+        self.bytes_written
+            .set(self.bytes_written.get() + contents.len() as u64);
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    folders: usize,
+    messages: usize,
+    attachments: usize,
+    skipped_attachments: usize,
+    failures: usize,
+}
+
+/// An attachment with its data, read before deciding whether to embed or store it.
+struct AttachmentData {
+    name: String,
+    data: Vec<u8>,
+}
+
+/// Content-addressed store for `--attachment-store`. Files are named by the FNV-1a hash and the
+/// size of their content, and a new attachment with the same hash and size as a stored one is
+/// compared byte for byte before it is treated as a duplicate, so collisions are stored separately.
+struct AttachmentStore {
+    directory: PathBuf,
+    hard_links: bool,
+    stored: HashMap<(u64, usize), Vec<PathBuf>>,
+    unique: usize,
+    duplicates: usize,
+    saved_bytes: u64,
+}
+
+impl AttachmentStore {
+    fn new(directory: PathBuf, hard_links: bool) -> Self {
+        Self {
+            directory,
+            hard_links,
+            stored: Default::default(),
+            unique: 0,
+            duplicates: 0,
+            saved_bytes: 0,
+        }
+    }
+
+    /// Return the path of the stored copy of `data`, and write it first if it is new content.
+    fn store(&mut self, timings: &Timings, data: &[u8]) -> io::Result<PathBuf> {
+        let hash = fnv1a(data);
+        let candidates = self.stored.entry((hash, data.len())).or_default();
+        for path in candidates.iter() {
+            if fs::read(path)? == data {
+                self.duplicates += 1;
+                self.saved_bytes += data.len() as u64;
+                return Ok(path.clone());
+            }
+        }
+
+        // Spread the files over 256 sub-directories to keep each of them small.
+        let directory = self.directory.join(format!("{:02x}", hash >> 56));
+        fs::create_dir_all(&directory)?;
+        let mut name = format!("{hash:016x}-{:x}", data.len());
+        if !candidates.is_empty() {
+            name.push_str(&format!("-{}", candidates.len()));
+        }
+        let path = directory.join(name);
+        timings.write_file(&path, data)?;
+        candidates.push(path.clone());
+        self.unique += 1;
+        Ok(path)
+    }
+
+    /// Store the `attachments` of a message, and write its manifest with one line for each
+    /// attachment: the size, the path in the store, and the original name, separated by tabs.
+    fn write_manifest(
+        &mut self,
+        timings: &Timings,
+        path: &Path,
+        attachments: &[AttachmentData],
+    ) -> io::Result<()> {
+        let links = path.with_extension("");
+        let mut manifest = String::new();
+        for (index, attachment) in attachments.iter().enumerate() {
+            let stored = self.store(timings, &attachment.data)?;
+            manifest.push_str(&format!(
+                "{}\t{}\t{}\n",
+                attachment.data.len(),
+                stored.display(),
+                attachment.name.replace(['\t', '\r', '\n'], " ")
+            ));
+
+            if self.hard_links {
+                let start = Instant::now();
+                fs::create_dir_all(&links)?;
+                // Prefix the index, since a message can have several attachments with one name.
+                let link = links.join(format!("{index:02}-{}", file_name(&attachment.name)));
+                if link.exists() {
+                    fs::remove_file(&link)?;
+                }
+                fs::hard_link(&stored, &link)?;
+                timings.write.set(timings.write.get() + start.elapsed());
+            }
+        }
+        timings.write_file(&path.with_extension("manifest"), manifest.as_bytes())
+    }
+}
+
+/// 64-bit FNV-1a, which is enough to find candidates for [`AttachmentStore::store`] to compare.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+/// Keep the characters of an attachment name which are safe in a file name on any platform.
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '.' | '-' | '_' | ' ') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::try_parse()?;
+    let timings = Timings::default();
+    let mut totals = Totals::default();
+    let mut attachment_store = args
+        .attachment_store
+        .map(|directory| AttachmentStore::new(directory, args.hard_links));
+    let filter = args
+        .include
+        .into_iter()
+        .fold(MessageClassFilter::new(), MessageClassFilter::include);
+    let filter = args
+        .exclude
+        .into_iter()
+        .fold(filter, MessageClassFilter::exclude);
+    let start = Instant::now();
+
+    let archive = PstArchive::from(timings.open_store(&args.file)?);
+    let folders = timings.ndb(|| archive.folders())?;
+
+    for (index, folder) in folders.iter().enumerate() {
+        totals.folders += 1;
+        let directory = args.output.join(folder_directory(&timings, index, folder));
+        fs::create_dir_all(&directory)?;
+
+        let entry_ids = timings.ltp(|| folder.message_entry_ids_matching(&filter))?;
+        for (index, entry_id) in entry_ids.iter().enumerate() {
+            let result = timings
+                .ndb(|| archive.store().open_message(entry_id, None))
+                .and_then(|message| {
+                    let message = MailMessage::from(message);
+                    let attachments = read_attachments(&timings, &mut totals, &message)?;
+                    let path = directory.join(format!("{index:06}.eml"));
+                    match attachment_store.as_mut() {
+                        Some(store) => {
+                            let eml = build_eml(&timings, &message, &[]);
+                            timings.write_file(&path, eml.as_bytes())?;
+                            store.write_manifest(&timings, &path, &attachments)
+                        }
+                        None => {
+                            let eml = build_eml(&timings, &message, &attachments);
+                            timings.write_file(&path, eml.as_bytes())
+                        }
+                    }
+                });
+            match result {
+                Ok(()) => totals.messages += 1,
+                Err(err) => {
+                    totals.failures += 1;
+                    eprintln!("Failed to export {entry_id:?}: {err}");
+                }
+            }
+        }
+    }
+
+    print_summary(&timings, &totals, start.elapsed());
+    if let Some(store) = attachment_store {
+        println!(
+            "Stored {} unique attachments, and skipped {} duplicates ({} bytes)",
+            store.unique, store.duplicates, store.saved_bytes
+        );
+    }
+    Ok(())
+}
+
+fn folder_directory(timings: &Timings, index: usize, folder: &MailFolder) -> String {
+    let name = timings
+        .ltp(|| folder.display_name())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+    format!("{index:04}-{name}")
+}
+
+/// Read the data of each attachment which is stored by value, and count the others as skipped.
+fn read_attachments(
+    timings: &Timings,
+    totals: &mut Totals,
+    message: &MailMessage,
+) -> io::Result<Vec<AttachmentData>> {
+    let mut attachments = vec![];
+    for summary in timings.ltp(|| message.message().attachment_summaries())? {
+        totals.attachments += 1;
+        if summary.method() != Some(AttachmentMethod::ByValue) {
+            totals.skipped_attachments += 1;
+            continue;
+        }
+        let attachment = timings.ndb(|| {
+            message
+                .message()
+                .open_attachment(summary.sub_node(), Some(&[0x3701]))
+        })?;
+        let Some(PropertyValue::Binary(data)) = attachment.properties().get(0x3701) else {
+            totals.skipped_attachments += 1;
+            continue;
+        };
+        attachments.push(AttachmentData {
+            name: summary.name().unwrap_or("attachment").to_string(),
+            data: data.buffer().to_vec(),
+        });
+    }
+    Ok(attachments)
+}
+
+fn build_eml(timings: &Timings, message: &MailMessage, attachments: &[AttachmentData]) -> String {
+    let properties = message.message().properties();
+    let (headers, text, html) = timings.ltp(|| {
+        let mut headers = vec![];
+        if let Some(sender) = message.sender_name() {
+            headers.push(("From", encode_header(&sender)));
+        }
+        if let Some(to) = message.display_to().filter(|to| !to.is_empty()) {
+            headers.push(("To", encode_header(&to)));
+        }
+        if let Some(subject) = message.subject() {
+            headers.push(("Subject", encode_header(&subject)));
+        }
+        if let Some(date) = message.delivery_time().and_then(format_filetime) {
+            headers.push(("Date", date));
+        }
+        if let Some(PropertyValue::Unicode(message_id)) = properties.get(0x1035) {
+            headers.push(("Message-ID", message_id.to_string()));
+        }
+
+        let html = match properties.get(0x1013) {
+            Some(PropertyValue::Binary(html)) => {
+                let code_page = match properties.get(0x3FDE) {
+                    Some(PropertyValue::Integer32(code_page)) => *code_page as u16,
+                    _ => properties.string_code_page(),
+                };
+                Some((html.buffer().to_vec(), charset(code_page)))
+            }
+            _ => None,
+        };
+
+        (headers, message.body(), html)
+    });
+
+    let rtf = match properties.get(0x1009) {
+        Some(PropertyValue::Binary(rtf)) => timings.rtf(|| decompress_rtf(rtf.buffer()).ok()),
+        _ => None,
+    };
+
+    let mut parts = vec![];
+    if let Some(text) = text {
+        parts.push(body_part("text/plain; charset=utf-8", text.as_bytes()));
+    }
+    if let Some((html, charset)) = html {
+        parts.push(body_part(&format!("text/html; charset={charset}"), &html));
+    }
+    if parts.is_empty() {
+        if let Some(rtf) = rtf {
+            parts.push(body_part("text/rtf; charset=us-ascii", rtf.as_bytes()));
+        }
+    }
+
+    let attachments: Vec<_> = attachments
+        .iter()
+        .map(|attachment| {
+            format!(
+                "Content-Type: application/octet-stream\r\n\
+                 Content-Disposition: attachment; filename=\"{}\"\r\n\
+                 Content-Transfer-Encoding: base64\r\n\r\n{}",
+                encode_header(&attachment.name.replace('"', "'")),
+                base64_lines(&attachment.data)
+            )
+        })
+        .collect();
+
+    let mut eml = String::new();
+    for (name, value) in headers {
+        eml.push_str(&format!("{name}: {value}\r\n"));
+    }
+    eml.push_str("MIME-Version: 1.0\r\n");
+
+    let body = match parts.len() {
+        0 => body_part("text/plain; charset=utf-8", &[]),
+        1 => parts.remove(0),
+        _ => multipart("alternative", "=_alternative", &parts),
+    };
+    if attachments.is_empty() {
+        eml.push_str(&body);
+    } else {
+        let mut parts = vec![body];
+        parts.extend(attachments);
+        eml.push_str(&multipart("mixed", "=_mixed", &parts));
+    }
+    eml
+}
+
+fn body_part(content_type: &str, body: &[u8]) -> String {
+    format!(
+        "Content-Type: {content_type}\r\nContent-Transfer-Encoding: base64\r\n\r\n{}",
+        base64_lines(body)
+    )
+}
+
+fn multipart(subtype: &str, boundary: &str, parts: &[String]) -> String {
+    let mut body = format!("Content-Type: multipart/{subtype}; boundary=\"{boundary}\"\r\n\r\n");
+    for part in parts {
+        body.push_str(&format!("--{boundary}\r\n{part}\r\n"));
+    }
+    body.push_str(&format!("--{boundary}--\r\n"));
+    body
+}
+
+/// Use an RFC 2047 encoded-word for header values which are not printable ASCII.
+fn encode_header(value: &str) -> String {
+    if value.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        value.to_string()
+    } else {
+        format!("=?utf-8?B?{}?=", base64(value.as_bytes()))
+    }
+}
+
+fn charset(code_page: u16) -> String {
+    match code_page {
+        1200 => "utf-16le".to_string(),
+        20127 => "us-ascii".to_string(),
+        28591..=28599 => format!("iso-8859-{}", code_page - 28590),
+        65001 => "utf-8".to_string(),
+        code_page => format!("windows-{code_page}"),
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let value = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(value >> (18 - 6 * index)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn base64_lines(data: &[u8]) -> String {
+    data.chunks(57)
+        .map(|line| format!("{}\r\n", base64(line)))
+        .collect()
+}
+
+/// Format a `FILETIME` as an RFC 5322 date in UTC.
+fn format_filetime(filetime: i64) -> Option<String> {
+    const FILETIME_UNIX_EPOCH: i64 = 11_644_473_600;
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let seconds = (filetime / 10_000_000).checked_sub(FILETIME_UNIX_EPOCH)?;
+    let (days, seconds) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+
+    // Convert the days since 1970-01-01 to a proleptic Gregorian calendar date.
+    let shifted = days + 719_468;
+    let era = shifted.div_euclid(146_097);
+    let day_of_era = shifted.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    Some(format!(
+        "{}, {day:02} {} {year:04} {:02}:{:02}:{:02} +0000",
+        DAYS[days.rem_euclid(7) as usize],
+        MONTHS[month as usize - 1],
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    ))
+}
+
+fn print_summary(timings: &Timings, totals: &Totals, elapsed: Duration) {
+    println!(
+        "Exported {} messages from {} folders, with {} attachments ({} skipped) and {} failures",
+        totals.messages,
+        totals.folders,
+        totals.attachments,
+        totals.skipped_attachments,
+        totals.failures
+    );
+
+    let io = &timings.io;
+    let phases = [
+        ("NDB reads", timings.ndb.get()),
+        ("LTP decode", timings.ltp.get()),
+        ("RTF", timings.rtf.get()),
+        ("IO (PST reads)", io.elapsed()),
+        ("IO (EML writes)", timings.write.get()),
+    ];
+    let measured: Duration = phases.iter().map(|(_, duration)| *duration).sum();
+    let total = elapsed.as_secs_f64().max(f64::EPSILON);
+
+    println!("{:<16} {:>12} {:>7}", "Phase", "Time (ms)", "Share");
+    for (name, duration) in phases
+        .into_iter()
+        .chain([("Other", elapsed.saturating_sub(measured))])
+    {
+        println!(
+            "{name:<16} {:>12.3} {:>6.1}%",
+            duration.as_secs_f64() * 1000.0,
+            duration.as_secs_f64() / total * 100.0
+        );
+    }
+    println!("{:<16} {:>12.3}", "Total", elapsed.as_secs_f64() * 1000.0);
+    println!(
+        "Read {} bytes in {} reads, wrote {} bytes",
+        io.bytes.load(Ordering::Relaxed),
+        io.reads.load(Ordering::Relaxed),
+        timings.bytes_written.get()
+    );
+}
@@ -0,0 +1,71 @@
+//! Write every message in a PST as JSON Lines to stdout, e.g.:
+//!
+//! ```sh
+//! cargo run -p outlook-pst --example export_jsonl -- Archive.pst | gzip > archive.jsonl.gz
+//! ```
+
+use clap::Parser;
+use outlook_pst::{
+    export::{
+        jsonl::{write_store, JsonLinesOptions},
+        property_list::PropertyNaming,
+    },
+    messaging::message_class_filter::MessageClassFilter,
+};
+use std::io::{self, BufWriter};
+
+#[derive(Parser)]
+#[command(version, about, long_about)]
+struct Args {
+    #[clap(default_value = r#"crates/pst/examples/Empty.pst"#)]
+    file: String,
+    /// Only write these properties, as hex property IDs, e.g. `-p 0037 -p 0E06`.
+    #[clap(short, long, value_parser = parse_prop_id)]
+    prop_ids: Vec<u16>,
+    /// Name the properties with Windows Property System names, e.g. System.Subject.
+    #[clap(short, long)]
+    windows_names: bool,
+    /// Add the base64 encoded plain text and HTML bodies.
+    #[clap(short, long)]
+    bodies: bool,
+    /// Only write messages whose `PidTagMessageClass` matches one of these patterns, e.g.
+    /// `--include 'IPM.Note*'`.
+    #[clap(long)]
+    include: Vec<String>,
+    /// Skip messages whose `PidTagMessageClass` matches one of these patterns.
+    #[clap(long)]
+    exclude: Vec<String>,
+}
+
+fn parse_prop_id(value: &str) -> Result<u16, std::num::ParseIntError> {
+    u16::from_str_radix(value.trim_start_matches("0x"), 16)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::try_parse()?;
+    let store = outlook_pst::open_store(&args.file)?;
+
+    let naming = if args.windows_names {
+        PropertyNaming::WindowsPropertySystem
+    } else {
+        PropertyNaming::Mapi
+    };
+    let filter = args
+        .include
+        .into_iter()
+        .fold(MessageClassFilter::new(), MessageClassFilter::include);
+    let filter = args
+        .exclude
+        .into_iter()
+        .fold(filter, MessageClassFilter::exclude);
+    let options = JsonLinesOptions::new()
+        .with_prop_ids(&args.prop_ids)
+        .with_naming(naming)
+        .with_bodies(args.bodies)
+        .with_message_class_filter(filter);
+
+    let mut writer = BufWriter::new(io::stdout().lock());
+    let count = write_store(store.as_ref(), &mut writer, &options)?;
+    eprintln!("Exported {count} messages");
+    Ok(())
+}
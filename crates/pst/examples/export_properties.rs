@@ -0,0 +1,110 @@
+use clap::{Parser, ValueEnum};
+use outlook_pst::{
+    export::{
+        mode::ExportMode,
+        property_list::{PropertyListing, PropertyNaming},
+    },
+    messaging::{folder::Folder, store::Store},
+};
+use std::rc::Rc;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    Csv,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Names {
+    /// Canonical MS-OXPROPS names, e.g. PidTagSubject
+    Mapi,
+    /// Windows Property System names, e.g. System.Subject
+    Windows,
+}
+
+/// List the properties of the store, its folders, and optionally their messages, as JSON or CSV.
+#[derive(Parser)]
+#[command(version, about, long_about)]
+struct Args {
+    #[clap(default_value = r#"crates/pst/examples/Empty.pst"#)]
+    file: String,
+    #[clap(short, long, value_enum, default_value_t = Format::Json)]
+    format: Format,
+    /// Which names to use in the `name` field.
+    #[clap(short, long, value_enum, default_value_t = Names::Mapi)]
+    names: Names,
+    /// Include the properties of the messages in each folder.
+    #[clap(short, long)]
+    messages: bool,
+    /// Sort the output and leave out volatile values, so exports of the same content are
+    /// byte-identical.
+    #[clap(short, long)]
+    deterministic: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::try_parse()?;
+    let store = outlook_pst::open_store(&args.file)?;
+    let naming = match args.names {
+        Names::Mapi => PropertyNaming::Mapi,
+        Names::Windows => PropertyNaming::WindowsPropertySystem,
+    };
+
+    let mode = if args.deterministic {
+        ExportMode::Deterministic
+    } else {
+        ExportMode::AsStored
+    };
+
+    let mut listing = PropertyListing::new(naming).with_mode(mode);
+    let code_page = store.properties().string_code_page();
+    listing.add_properties("store", store.properties().iter(), code_page);
+
+    let root = store.open_folder(&store.properties().root_folder_entry_id()?)?;
+    add_folder(&mut listing, &store, &root, "", args.messages)?;
+    listing.resolve_named_properties(store.named_property_map()?.properties())?;
+
+    match args.format {
+        Format::Json => println!("{}", listing.to_json()),
+        Format::Csv => print!("{}", listing.to_csv()),
+    }
+    Ok(())
+}
+
+fn add_folder(
+    listing: &mut PropertyListing,
+    store: &Rc<dyn Store>,
+    folder: &Rc<dyn Folder>,
+    path: &str,
+    messages: bool,
+) -> anyhow::Result<()> {
+    let code_page = store.properties().string_code_page();
+    let object = if path.is_empty() { "/" } else { path };
+    listing.add_properties(object, folder.properties().iter(), code_page);
+
+    if messages {
+        if let Some(contents_table) = folder.contents_table() {
+            for row in contents_table.rows_matrix() {
+                let message = folder.open_message_at_row(row, None)?;
+                let properties = message.properties();
+                let object = format!("{path}/0x{:X}", u32::from(row.id()));
+                listing.add_properties(&object, properties.iter(), properties.string_code_page());
+            }
+        }
+    }
+
+    if let Some(hierarchy_table) = folder.hierarchy_table() {
+        for row in hierarchy_table.rows_matrix() {
+            let sub_folder = store.open_folder(&folder.entry_id_for_row(row))?;
+            let name = sub_folder.properties().display_name()?;
+            add_folder(
+                listing,
+                store,
+                &sub_folder,
+                &format!("{path}/{name}"),
+                messages,
+            )?;
+        }
+    }
+    Ok(())
+}
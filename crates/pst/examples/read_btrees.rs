@@ -28,8 +28,7 @@ fn main() -> anyhow::Result<()> {
     let root = header.root();
 
     {
-        let mut file = pst.reader().lock().expect("Failed to lock reader");
-        let file = &mut *file;
+        let file = &mut pst.reader();
 
         output_block_btree(file, None, *root.block_btree())?;
         println!();
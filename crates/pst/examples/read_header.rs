@@ -33,7 +33,7 @@ where
     let next_block = header.next_block();
     let next_page = header.next_page();
 
-    println!("File Version: {version:?}");
+    println!("File Version: {version:?} ({})", header.format_version());
     println!("Next Block: {next_block:?}");
     println!("Next Page: {next_page:?}");
 
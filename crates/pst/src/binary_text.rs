@@ -0,0 +1,94 @@
+//! Base64 and hex encodings of binary data, shared by the EWS IDs, the JSON Lines export and the
+//! importers.
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode `data` as padded base64.
+pub(crate) fn encode_base64(data: &[u8]) -> String {
+    let mut result = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let buffer = chunk
+            .iter()
+            .enumerate()
+            .fold(0_u32, |buffer, (index, byte)| {
+                buffer | (u32::from(*byte) << (16 - 8 * index))
+            });
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let value = (buffer >> (18 - 6 * index)) & 0x3F;
+                result.push(char::from(BASE64_ALPHABET[value as usize]));
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+/// Decode base64 `data` like a MIME body: characters outside the alphabet, e.g. line breaks, are
+/// skipped, and decoding stops at the first `=`. Use [`encode_base64`] on the result to check
+/// that the input was canonical.
+pub(crate) fn decode_base64(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0_u32, 0);
+    for &b in data {
+        let value = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => continue,
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+    result
+}
+
+/// Decode a pair of hex digits, in either case. Unlike [`u8::from_str_radix`], a sign is not
+/// accepted, so `+F` is not a valid byte.
+pub(crate) fn decode_hex_byte(hex: &[u8]) -> Option<u8> {
+    let [high, low] = hex else {
+        return None;
+    };
+    let digit = |b: &u8| char::from(*b).to_digit(16);
+    Some(((digit(high)? << 4) | digit(low)?) as u8)
+}
+
+/// Decode a string of hex digit pairs, or return `None` if it has an odd length or anything other
+/// than hex digits.
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes().chunks(2).map(decode_hex_byte).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(encode_base64(b"ab"), "YWI=");
+        assert_eq!(decode_base64(b"YWI="), b"ab");
+        assert_eq!(decode_base64(b"YW\r\nI="), b"ab");
+        assert_eq!(decode_base64(b"YWI"), b"ab");
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(decode_hex_byte(b"e9"), Some(0xE9));
+        assert_eq!(decode_hex_byte(b"E9"), Some(0xE9));
+        assert_eq!(decode_hex_byte(b"+F"), None);
+        assert_eq!(decode_hex_byte(b"-1"), None);
+        assert_eq!(decode_hex_byte(b"F"), None);
+        assert_eq!(decode_hex("00ff7A"), Some(vec![0x00, 0xFF, 0x7A]));
+        assert_eq!(decode_hex("00f"), None);
+        assert_eq!(decode_hex("+F00"), None);
+    }
+}
@@ -2,12 +2,22 @@
 //!
 //! This is a direct port of the block signature calculation code from the PST specification.
 
-/// Compute the block signature.
+/// Compute the block signature, i.e. `wSig` in a BLOCKTRAILER or PAGETRAILER, from the low 32
+/// bits of the `ib` file offset and of the `bid` of the block or page.
 pub fn compute_sig(index: u32, block_id: u32) -> u16 {
     let value = index ^ block_id;
     (value >> 16) as u16 ^ (value as u16)
 }
 
+/// [`compute_sig`] for a block or page at file offset `index` with the full `block_id`, which are
+/// truncated to 32 bits like the specification's `ComputeSig` arguments.
+pub fn block_signature(index: u64, block_id: u64) -> u16 {
+    compute_sig(
+        (index & u64::from(u32::MAX)) as u32,
+        (block_id & u64::from(u32::MAX)) as u32,
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -28,5 +38,6 @@ mod tests {
         assert_eq!(compute_sig(0x00000000, 0x00010000), 0x0001);
         assert_eq!(compute_sig(0x00010000, 0x00000000), 0x0001);
         assert_eq!(compute_sig(0x00010000, 0x00010000), 0x0000);
+        assert_eq!(block_signature(0x1_0000_4600, 0x2_0000_0024), 0x4624);
     }
 }
@@ -0,0 +1,129 @@
+//! The time a [`PstFileLockGuard`](crate::PstFileLockGuard) uses for `PidTagCreationTime` and
+//! `PidTagLastModificationTime` when it creates or updates a message.
+//!
+//! The guard reads the [`SystemClock`] by default. Tests can replace it with a [`FixedClock`]
+//! using [`PstFileLockGuard::set_clock`](crate::PstFileLockGuard::set_clock), so the messages
+//! they write have predictable timestamps and search keys.
+
+use std::{
+    cell::Cell,
+    rc::Rc,
+    time::{Duration, SystemTime},
+};
+
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+impl<C: Clock + ?Sized> Clock for Rc<C> {
+    fn now(&self) -> SystemTime {
+        self.as_ref().now()
+    }
+}
+
+/// Reads [`SystemTime::now`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Always returns the same time till it is moved with [`FixedClock::set`] or
+/// [`FixedClock::advance`]. Wrap it in an [`Rc`] to keep a handle to it after passing it to
+/// [`PstFileLockGuard::set_clock`](crate::PstFileLockGuard::set_clock).
+#[derive(Debug)]
+pub struct FixedClock {
+    time: Cell<SystemTime>,
+}
+
+impl FixedClock {
+    pub fn new(time: SystemTime) -> Self {
+        Self {
+            time: Cell::new(time),
+        }
+    }
+
+    pub fn set(&self, time: SystemTime) {
+        self.time.set(time);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.time.set(self.time.get() + duration);
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.time.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ltp::prop_context::PropertyValue,
+        messaging::message::set_default_message_properties,
+        ndb::node_id::{NodeId, NodeIdType},
+    };
+    use std::{collections::BTreeMap, time::UNIX_EPOCH};
+
+    #[test]
+    fn test_fixed_clock() {
+        let clock = Rc::new(FixedClock::new(UNIX_EPOCH));
+        let shared: Box<dyn Clock> = Box::new(clock.clone());
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(shared.now(), UNIX_EPOCH + Duration::from_secs(60));
+        clock.set(UNIX_EPOCH);
+        assert_eq!(shared.now(), UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_default_message_properties() {
+        // 2003-07-01 08:52:37 UTC
+        let filetime = 127_015_231_570_000_000;
+        let now = UNIX_EPOCH + Duration::from_secs(1_057_049_557);
+        let record_key = [0xA5; 16];
+        let message = NodeId::new(NodeIdType::NormalMessage, 0x10).unwrap();
+
+        let mut properties = BTreeMap::new();
+        assert!(set_default_message_properties(
+            &mut properties,
+            now,
+            &record_key,
+            message
+        ));
+        for prop_id in [0x3007, 0x3008] {
+            assert!(matches!(
+                properties.get(&prop_id),
+                Some(PropertyValue::Time(value)) if *value == filetime
+            ));
+        }
+        let Some(PropertyValue::Binary(record)) = properties.get(&0x0FF9) else {
+            panic!("PidTagRecordKey not set");
+        };
+        assert_eq!(record.buffer()[..16], record_key);
+        assert_eq!(record.buffer()[16..], u32::from(message).to_le_bytes());
+        let Some(PropertyValue::Binary(search)) = properties.get(&0x300B) else {
+            panic!("PidTagSearchKey not set");
+        };
+        assert_eq!(search.buffer().len(), 16);
+        assert_ne!(search.buffer(), record_key);
+
+        // Values which are already set are kept.
+        properties.insert(0x3008, PropertyValue::Time(1));
+        assert!(!set_default_message_properties(
+            &mut properties,
+            now + Duration::from_secs(1),
+            &record_key,
+            message
+        ));
+        assert!(matches!(
+            properties.get(&0x3008),
+            Some(PropertyValue::Time(1))
+        ));
+    }
+}
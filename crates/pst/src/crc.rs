@@ -1,8 +1,8 @@
 //! ## [CRC Calculation](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/39c35207-130f-4d83-96f8-2b311a285a8f)
 //!
-//! This is a direct port of the CRC calculation code from the PST specification.
-
-use core::mem;
+//! This uses the slicing-by-8 tables from the PST specification. Unlike the reference code, it
+//! does not step through unaligned bytes one at a time before the first 8-byte chunk, since
+//! reading the chunks with [`u32::from_le_bytes`] does not depend on alignment.
 
 const CRC_TABLE_OFFSET32: [u32; 256] = [
     0x00000000, 0x77073096, 0xEE0E612C, 0x990951BA, 0x076DC419, 0x706AF48F, 0xE963A535, 0x9E6495A3,
@@ -286,39 +286,18 @@ const CRC_TABLE_OFFSET88: [u32; 256] = [
 
 /// Compute the CRC of the data with the given initial CRC value.
 pub fn compute_crc(crc: u32, data: &[u8]) -> u32 {
-    let aligned_offset = if data.len() < mem::size_of::<u32>() {
-        0
-    } else {
-        data.as_ptr() as usize % mem::size_of::<u32>()
-    };
-    let initial_unaligned_bytes = if aligned_offset == 0 {
-        0
-    } else {
-        mem::size_of::<u32>() - aligned_offset
-    };
-    let running_length = if data.len() < mem::size_of::<u32>() {
-        0
-    } else {
-        (data.len() - initial_unaligned_bytes) / 8 * 8
-    };
-    let end_unaligned_bytes = data.len() - initial_unaligned_bytes - running_length;
-
     let mut crc = crc;
 
-    for unaligned in &data[..initial_unaligned_bytes] {
-        crc =
-            CRC_TABLE_OFFSET32[((crc ^ u32::from(*unaligned)) & 0x000000FF) as usize] ^ (crc >> 8);
-    }
-
-    for chunk in data[initial_unaligned_bytes..initial_unaligned_bytes + running_length].chunks(8) {
-        let first_chunk = u32::from_le_bytes(chunk[..4].try_into().expect("invalid chunk size"));
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let first_chunk = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
         crc ^= first_chunk;
         crc = CRC_TABLE_OFFSET88[(crc & 0x000000FF) as usize]
             ^ CRC_TABLE_OFFSET80[((crc >> 8) & 0x000000FF) as usize]
             ^ CRC_TABLE_OFFSET72[((crc >> 16) & 0x000000FF) as usize]
             ^ CRC_TABLE_OFFSET64[((crc >> 24) & 0x000000FF) as usize];
 
-        let second_chunk = u32::from_le_bytes(chunk[4..].try_into().expect("invalid chunk size"));
+        let second_chunk = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
         crc = crc
             ^ CRC_TABLE_OFFSET56[(second_chunk & 0x000000FF) as usize]
             ^ CRC_TABLE_OFFSET48[((second_chunk >> 8) & 0x000000FF) as usize]
@@ -326,10 +305,80 @@ pub fn compute_crc(crc: u32, data: &[u8]) -> u32 {
             ^ CRC_TABLE_OFFSET32[((second_chunk >> 24) & 0x000000FF) as usize];
     }
 
-    for unaligned in &data[data.len() - end_unaligned_bytes..] {
-        crc =
-            CRC_TABLE_OFFSET32[((crc ^ u32::from(*unaligned)) & 0x000000FF) as usize] ^ (crc >> 8);
+    for byte in chunks.remainder() {
+        crc = CRC_TABLE_OFFSET32[((crc ^ u32::from(*byte)) & 0x000000FF) as usize] ^ (crc >> 8);
     }
 
     crc
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bit-at-a-time CRC with the reflected polynomial that the tables are generated from.
+    fn reference_crc(crc: u32, data: &[u8]) -> u32 {
+        data.iter().fold(crc, |crc, &byte| {
+            (0..8).fold(crc ^ u32::from(byte), |crc, _| {
+                if crc & 1 == 0 {
+                    crc >> 1
+                } else {
+                    (crc >> 1) ^ 0xEDB88320
+                }
+            })
+        })
+    }
+
+    #[test]
+    fn test_crc_check_value() {
+        // The PST CRC does not invert the input and output, so this is the standard CRC-32 check
+        // value for "123456789" with the inversion done by the caller.
+        assert_eq!(
+            compute_crc(0xFFFFFFFF, b"123456789") ^ 0xFFFFFFFF,
+            0xCBF43926
+        );
+        assert_eq!(compute_crc(0, &[]), 0);
+        assert_eq!(compute_crc(0x12345678, &[]), 0x12345678);
+    }
+
+    #[test]
+    fn test_crc_header_vectors() {
+        const EMPTY_PST: &[u8] = include_bytes!("../examples/Empty.pst");
+
+        let crc_partial = u32::from_le_bytes(EMPTY_PST[4..8].try_into().unwrap());
+        let crc_full = u32::from_le_bytes(EMPTY_PST[524..528].try_into().unwrap());
+        assert_eq!(compute_crc(0, &EMPTY_PST[8..479]), crc_partial);
+        assert_eq!(compute_crc(0, &EMPTY_PST[8..524]), crc_full);
+    }
+
+    #[test]
+    fn test_crc_matches_reference() {
+        let mut state = 0x2545F491_u32;
+        let data: Vec<u8> = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+
+        for offset in 0..16 {
+            for length in (0..64).chain([511, 512, 513, 4000]) {
+                let data = &data[offset..offset + length];
+                let crc = (data.len() as u32).wrapping_mul(0x9E3779B9);
+                assert_eq!(
+                    compute_crc(crc, data),
+                    reference_crc(crc, data),
+                    "offset: {offset}, length: {length}"
+                );
+            }
+        }
+
+        let (first, second) = data.split_at(1234);
+        assert_eq!(
+            compute_crc(compute_crc(0, first), second),
+            compute_crc(0, &data)
+        );
+    }
+}
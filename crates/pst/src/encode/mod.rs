@@ -1,3 +1,13 @@
+//! Block encodings from [MS-PST](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/141923d5-15ab-4ef1-a524-6dce75aae546),
+//! and opt-in process-wide counters of how many blocks and bytes passed through each of them.
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::ndb::header::NdbCryptMethod;
+
 pub mod cyclic;
 pub mod permute;
 
@@ -42,16 +52,169 @@ static KEY_DATA: [u8; 768] = [
     108, 249, 236,
 ];
 
-fn key_data_r() -> &'static [u8] {
-    &KEY_DATA[..256]
+const fn key_table(index: usize) -> &'static [u8; 256] {
+    match KEY_DATA.split_at(index * 256).1.first_chunk() {
+        Some(table) => table,
+        None => panic!("KEY_DATA is too short"),
+    }
+}
+
+static KEY_DATA_R: &[u8; 256] = key_table(0);
+static KEY_DATA_S: &[u8; 256] = key_table(1);
+static KEY_DATA_I: &[u8; 256] = key_table(2);
+
+fn key_data_r() -> &'static [u8; 256] {
+    KEY_DATA_R
+}
+
+fn key_data_s() -> &'static [u8; 256] {
+    KEY_DATA_S
+}
+
+fn key_data_i() -> &'static [u8; 256] {
+    KEY_DATA_I
+}
+
+const CRYPT_METHOD_COUNT: usize = 3;
+
+struct EncodeCounters {
+    block_count: AtomicU64,
+    byte_count: AtomicU64,
+    elapsed_nanos: AtomicU64,
+}
+
+impl EncodeCounters {
+    const fn new() -> Self {
+        Self {
+            block_count: AtomicU64::new(0),
+            byte_count: AtomicU64::new(0),
+            elapsed_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+static ENCODE_COUNTERS: [EncodeCounters; CRYPT_METHOD_COUNT] = [
+    EncodeCounters::new(),
+    EncodeCounters::new(),
+    EncodeCounters::new(),
+];
+
+static ENCODE_STATS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Start or stop collecting the [`encode_stats`]. They are off by default, so the encoders don't
+/// pay for reading the clock and updating the counters unless somebody is looking at them.
+pub fn set_encode_stats_enabled(enabled: bool) {
+    ENCODE_STATS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Check if [`set_encode_stats_enabled`] turned on the counters.
+pub fn encode_stats_enabled() -> bool {
+    ENCODE_STATS_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Totals for the blocks which were encoded or decoded with one [`NdbCryptMethod`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EncodeMethodStats {
+    block_count: u64,
+    byte_count: u64,
+    elapsed: Duration,
+}
+
+impl EncodeMethodStats {
+    pub fn block_count(&self) -> u64 {
+        self.block_count
+    }
+
+    pub fn byte_count(&self) -> u64 {
+        self.byte_count
+    }
+
+    /// Time spent in the encoder, not including the I/O or the CRC.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Average time spent encoding or decoding each block.
+    pub fn cost_per_block(&self) -> Option<Duration> {
+        let block_count = u32::try_from(self.block_count).ok()?;
+        self.elapsed.checked_div(block_count)
+    }
+}
+
+/// Snapshot of the encode counters for every [`NdbCryptMethod`], across all of the open stores.
+/// Blocks in stores which use [`NdbCryptMethod::None`] are counted too, so comparing the methods
+/// shows how prevalent each of them is in the data that was read or written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EncodeStats {
+    methods: [EncodeMethodStats; CRYPT_METHOD_COUNT],
+}
+
+impl EncodeStats {
+    pub fn method(&self, crypt_method: NdbCryptMethod) -> EncodeMethodStats {
+        self.methods[crypt_method as usize]
+    }
+
+    /// Number of bytes which had to be encoded or decoded, i.e. not [`NdbCryptMethod::None`].
+    pub fn encoded_byte_count(&self) -> u64 {
+        self.methods[NdbCryptMethod::Permute as usize].byte_count
+            + self.methods[NdbCryptMethod::Cyclic as usize].byte_count
+    }
 }
 
-fn key_data_s() -> &'static [u8] {
-    &KEY_DATA[256..512]
+/// Read the current totals. These stay at 0 until [`set_encode_stats_enabled`] is called.
+pub fn encode_stats() -> EncodeStats {
+    let mut stats = EncodeStats::default();
+    for (method, counters) in stats.methods.iter_mut().zip(ENCODE_COUNTERS.iter()) {
+        *method = EncodeMethodStats {
+            block_count: counters.block_count.load(Ordering::Relaxed),
+            byte_count: counters.byte_count.load(Ordering::Relaxed),
+            elapsed: Duration::from_nanos(counters.elapsed_nanos.load(Ordering::Relaxed)),
+        };
+    }
+    stats
+}
+
+/// Set all of the totals back to 0, e.g. before profiling an export.
+pub fn reset_encode_stats() {
+    for counters in ENCODE_COUNTERS.iter() {
+        counters.block_count.store(0, Ordering::Relaxed);
+        counters.byte_count.store(0, Ordering::Relaxed);
+        counters.elapsed_nanos.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Encode or decode a block's data with `crypt_method`, and add it to the [`encode_stats`] if they
+/// are enabled.
+pub(crate) fn encode_block_data(
+    data: &mut [u8],
+    crypt_method: NdbCryptMethod,
+    cyclic_key: u32,
+    decode: bool,
+) {
+    if !encode_stats_enabled() {
+        encode_block(data, crypt_method, cyclic_key, decode);
+        return;
+    }
+
+    let start = Instant::now();
+    encode_block(data, crypt_method, cyclic_key, decode);
+    let elapsed = u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+
+    let counters = &ENCODE_COUNTERS[crypt_method as usize];
+    counters.block_count.fetch_add(1, Ordering::Relaxed);
+    counters
+        .byte_count
+        .fetch_add(data.len() as u64, Ordering::Relaxed);
+    counters.elapsed_nanos.fetch_add(elapsed, Ordering::Relaxed);
 }
 
-fn key_data_i() -> &'static [u8] {
-    &KEY_DATA[512..]
+fn encode_block(data: &mut [u8], crypt_method: NdbCryptMethod, cyclic_key: u32, decode: bool) {
+    match crypt_method {
+        NdbCryptMethod::Cyclic => cyclic::encode_decode_block(data, cyclic_key),
+        NdbCryptMethod::Permute if decode => permute::decode_block(data),
+        NdbCryptMethod::Permute => permute::encode_block(data),
+        NdbCryptMethod::None => {}
+    }
 }
 
 #[cfg(test)]
@@ -64,4 +227,18 @@ mod tests {
         assert_eq!(key_data_s().len(), 256);
         assert_eq!(key_data_i().len(), 256);
     }
+
+    #[test]
+    fn test_encode_stats() {
+        set_encode_stats_enabled(true);
+        let before = encode_stats().method(NdbCryptMethod::Permute);
+        let mut data = vec![0_u8; 512];
+        encode_block_data(&mut data, NdbCryptMethod::Permute, 0, false);
+        encode_block_data(&mut data, NdbCryptMethod::Permute, 0, true);
+        assert_eq!(data, [0_u8; 512]);
+
+        let after = encode_stats().method(NdbCryptMethod::Permute);
+        assert!(after.block_count() >= before.block_count() + 2);
+        assert!(after.byte_count() >= before.byte_count() + 1024);
+    }
 }
@@ -14,9 +14,9 @@ pub fn decode_block(data: &mut [u8]) {
     permute(data, key_data_i());
 }
 
-fn permute(data: &mut [u8], table: &[u8]) {
+fn permute(data: &mut [u8], table: &[u8; 256]) {
     for b in data.iter_mut() {
-        *b = table[*b as usize];
+        *b = table[usize::from(*b)];
     }
 }
 
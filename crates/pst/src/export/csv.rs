@@ -0,0 +1,12 @@
+//! Minimal CSV writing helpers shared by the exporters, following RFC 4180.
+
+/// Append `value` to `csv`, quoted if it contains a comma, quote, or line break.
+pub(crate) fn write_csv_field(csv: &mut String, value: &str) {
+    if value.contains([',', '"', '\r', '\n']) {
+        csv.push('"');
+        csv.push_str(&value.replace('"', "\"\""));
+        csv.push('"');
+    } else {
+        csv.push_str(value);
+    }
+}
@@ -0,0 +1,23 @@
+//! Minimal JSON writing helpers shared by the exporters and reports, so the crate does not need
+//! a serialization dependency.
+
+use std::fmt::Write;
+
+/// Append `value` to `json` as a quoted JSON string.
+pub(crate) fn write_json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if u32::from(c) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", u32::from(c));
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
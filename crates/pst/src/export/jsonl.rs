@@ -0,0 +1,359 @@
+//! Write every message in a store as [JSON Lines](https://jsonlines.org/), one JSON object per
+//! line, e.g. to pipe into `jq` or to bulk load into Elasticsearch.
+//!
+//! [`write_store`] walks the folders beneath the IPM subtree and writes each message as soon as
+//! it has been read, so memory use does not grow with the number of messages. Only the contents
+//! table of the current folder and the message being written are held at once. The output goes
+//! to any [`Write`], so it can be compressed by wrapping the writer, e.g. in a
+//! `flate2::write::GzEncoder`.
+//!
+//! Each line has the fields:
+//! - `folder`: the path of the folder beneath the IPM subtree, with names joined by `/`.
+//! - `entry_id`: the `PidTagEntryId` of the message in hex, see [`EwsIdFormat::HexEntryId`].
+//! - `properties`: an object with one field per property, named and formatted the same way as a
+//!   [`PropertyListing`], with an array of strings for multi-valued properties.
+//! - `body` and `html`: with [`JsonLinesOptions::with_bodies`], `PidTagBody` as base64 encoded
+//!   UTF-8, and `PidTagHtml` as base64, or `null` if the message does not have them.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use super::{
+    json::write_json_string,
+    property_list::{PropertyListing, PropertyNaming},
+};
+use crate::{
+    binary_text::encode_base64,
+    intern::StringInterner,
+    ltp::prop_context::PropertyValue,
+    messaging::{
+        ews_id::{to_ews_id, EwsIdFormat},
+        folder::Folder,
+        message::Message,
+        message_class_filter::MessageClassFilter,
+        named_prop::NamedPropertyMapProperties,
+        store::Store,
+    },
+    ndb::node_id::NodeIdType,
+};
+
+/// Properties which hold the body of a message, which are only written with
+/// [`JsonLinesOptions::with_bodies`].
+const BODY_PROP_IDS: [u16; 3] = [
+    0x1000, // PidTagBody
+    0x1009, // PidTagRtfCompressed
+    0x1013, // PidTagHtml
+];
+
+/// Which properties [`write_store`] writes for each message.
+#[derive(Clone, Debug, Default)]
+pub struct JsonLinesOptions {
+    prop_ids: Vec<u16>,
+    naming: PropertyNaming,
+    bodies: bool,
+    string_interning: bool,
+    message_class_filter: MessageClassFilter,
+}
+
+impl JsonLinesOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Only write the properties in `prop_ids`, and only read those from each message. By
+    /// default every property is written, except for the body.
+    pub fn with_prop_ids(mut self, prop_ids: &[u16]) -> Self {
+        self.prop_ids = prop_ids.to_vec();
+        self
+    }
+
+    /// Name the fields of the `properties` object in the `naming` scheme.
+    pub fn with_naming(mut self, naming: PropertyNaming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Add the `body` and `html` fields with the base64 encoded body of each message.
+    pub fn with_bodies(mut self, bodies: bool) -> Self {
+        self.bodies = bodies;
+        self
+    }
+
+    /// Pool the folder paths and property names in a [`StringInterner`] for the whole store, and
+    /// look up each named property name only once, instead of for every message.
+    pub fn with_string_interning(mut self, string_interning: bool) -> Self {
+        self.string_interning = string_interning;
+        self
+    }
+
+    /// Only write the messages whose `PidTagMessageClass` passes the `filter`. The others are
+    /// skipped without opening them.
+    pub fn with_message_class_filter(mut self, filter: MessageClassFilter) -> Self {
+        self.message_class_filter = filter;
+        self
+    }
+
+    pub fn prop_ids(&self) -> &[u16] {
+        &self.prop_ids
+    }
+
+    pub fn naming(&self) -> PropertyNaming {
+        self.naming
+    }
+
+    pub fn bodies(&self) -> bool {
+        self.bodies
+    }
+
+    pub fn string_interning(&self) -> bool {
+        self.string_interning
+    }
+
+    pub fn message_class_filter(&self) -> &MessageClassFilter {
+        &self.message_class_filter
+    }
+
+    /// The properties to read from each message, or `None` to read all of them.
+    fn read_prop_ids(&self) -> Option<Vec<u16>> {
+        if self.prop_ids.is_empty() {
+            return None;
+        }
+        let mut prop_ids = self.prop_ids.clone();
+        if self.bodies {
+            prop_ids.extend_from_slice(&BODY_PROP_IDS);
+        }
+        Some(prop_ids)
+    }
+
+    fn writes_property(&self, prop_id: u16) -> bool {
+        if self.prop_ids.is_empty() {
+            !BODY_PROP_IDS.contains(&prop_id)
+        } else {
+            self.prop_ids.contains(&prop_id)
+        }
+    }
+}
+
+/// Write each message in the normal folders beneath the IPM subtree of `store` as a line of JSON,
+/// and return the number of messages. Search folders are skipped, since their messages live in
+/// other folders. The writer is flushed at the end, but not finished, so a compressing writer
+/// still needs to be finished by the caller.
+pub fn write_store<S: Store + ?Sized>(
+    store: &S,
+    writer: &mut dyn Write,
+    options: &JsonLinesOptions,
+) -> io::Result<u64> {
+    let named_property_map = store.named_property_map()?;
+    let named_properties = named_property_map.properties();
+    let read_prop_ids = options.read_prop_ids();
+    // The interner caches named property names, so it must not outlive this store.
+    let interner = options.string_interning.then(StringInterner::new);
+
+    let root = store.properties().ipm_sub_tree_entry_id()?;
+    let mut visited = BTreeSet::new();
+    let mut pending = vec![(root, String::new())];
+    let mut count = 0;
+    while let Some((entry_id, path)) = pending.pop() {
+        if !visited.insert(u32::from(entry_id.node_id())) {
+            continue;
+        }
+        let folder = store.open_folder(&entry_id)?;
+
+        // Push the sub-folders in reverse, so they are written in the order of the hierarchy
+        // table.
+        if let Some(hierarchy_table) = folder.hierarchy_table() {
+            let mut sub_folders = vec![];
+            for row in hierarchy_table.rows_matrix() {
+                let entry_id = folder.entry_id_for_row(row);
+                if !matches!(entry_id.node_id().id_type(), Ok(NodeIdType::NormalFolder)) {
+                    continue;
+                }
+                let sub_folder = store.open_folder(&entry_id)?;
+                let name = sub_folder.properties().display_name()?;
+                let path = if path.is_empty() {
+                    name
+                } else {
+                    format!("{path}/{name}")
+                };
+                sub_folders.push((entry_id, path));
+            }
+            pending.extend(sub_folders.into_iter().rev());
+        }
+
+        count += write_folder(
+            folder,
+            &path,
+            named_properties,
+            read_prop_ids.as_deref(),
+            interner.as_ref(),
+            options,
+            writer,
+        )?;
+    }
+
+    writer.flush()?;
+    Ok(count)
+}
+
+fn write_folder(
+    folder: Rc<dyn Folder>,
+    path: &str,
+    named_properties: &NamedPropertyMapProperties,
+    read_prop_ids: Option<&[u16]>,
+    interner: Option<&StringInterner>,
+    options: &JsonLinesOptions,
+    writer: &mut dyn Write,
+) -> io::Result<u64> {
+    let mut count = 0;
+    let mut line = String::new();
+    for row in folder.contents_rows_matching(&options.message_class_filter)? {
+        let message = folder.open_message_at_row(row, read_prop_ids)?;
+        let entry_id = to_ews_id(&folder.entry_id_for_row(row), EwsIdFormat::HexEntryId)?;
+
+        line.clear();
+        write_message(
+            &mut line,
+            message.as_ref(),
+            path,
+            &entry_id,
+            named_properties,
+            interner,
+            options,
+        )?;
+        line.push('\n');
+        writer.write_all(line.as_bytes())?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn write_message(
+    json: &mut String,
+    message: &dyn Message,
+    path: &str,
+    entry_id: &str,
+    named_properties: &NamedPropertyMapProperties,
+    interner: Option<&StringInterner>,
+    options: &JsonLinesOptions,
+) -> io::Result<()> {
+    let properties = message.properties();
+    let mut listing = PropertyListing::new(options.naming);
+    if let Some(interner) = interner {
+        listing = listing.with_interner(interner.clone());
+    }
+    listing.add_properties(
+        path,
+        properties
+            .iter()
+            .filter(|(prop_id, _)| options.writes_property(**prop_id)),
+        properties.string_code_page(),
+    );
+    listing.resolve_named_properties(named_properties)?;
+
+    json.push_str("{\"folder\":");
+    write_json_string(json, path);
+    json.push_str(",\"entry_id\":");
+    write_json_string(json, entry_id);
+    json.push_str(",\"properties\":{");
+    for (index, entry) in listing.entries().iter().enumerate() {
+        if index > 0 {
+            json.push(',');
+        }
+        write_json_string(json, &entry.name(options.naming));
+        json.push(':');
+        if entry.is_multiple() {
+            json.push('[');
+            for (index, value) in entry.values().iter().enumerate() {
+                if index > 0 {
+                    json.push(',');
+                }
+                write_json_string(json, value);
+            }
+            json.push(']');
+        } else {
+            write_json_string(json, entry.values().first().map_or("", String::as_str));
+        }
+    }
+    json.push('}');
+
+    if options.bodies {
+        let body = match properties.get(0x1000) {
+            Some(PropertyValue::String8(value)) => Some(encode_base64(
+                value
+                    .to_string_with_decoder(properties.string_decoder())
+                    .as_bytes(),
+            )),
+            Some(PropertyValue::Unicode(value)) => {
+                Some(encode_base64(value.to_string().as_bytes()))
+            }
+            _ => None,
+        };
+        let html = match properties.get(0x1013) {
+            Some(PropertyValue::Binary(value)) => Some(encode_base64(value.buffer())),
+            _ => None,
+        };
+        for (field, value) in [("body", body), ("html", html)] {
+            json.push_str(",\"");
+            json.push_str(field);
+            json.push_str("\":");
+            match value {
+                Some(value) => write_json_string(json, &value),
+                None => json.push_str("null"),
+            }
+        }
+    }
+
+    json.push('}');
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{open_store, temp_path::TempPath};
+
+    #[test]
+    fn test_json_lines_options() {
+        let options = JsonLinesOptions::new();
+        assert_eq!(options.read_prop_ids(), None);
+        assert!(options.writes_property(0x0037));
+        assert!(!options.writes_property(0x1000));
+
+        let options = JsonLinesOptions::new()
+            .with_prop_ids(&[0x0037, 0x0E06])
+            .with_bodies(true);
+        assert_eq!(
+            options.read_prop_ids().unwrap(),
+            [0x0037, 0x0E06, 0x1000, 0x1009, 0x1013]
+        );
+        assert!(options.writes_property(0x0E06));
+        assert!(!options.writes_property(0x1000));
+        assert!(!options.writes_property(0x0E07));
+        assert!(!options.string_interning());
+    }
+
+    #[test]
+    fn test_write_store_message_class_filter() {
+        let path = TempPath::empty_pst_with_messages(
+            "json-lines-message-class-filter",
+            &["IPM.Note", "IPM.Appointment", "IPM.Note"],
+        );
+        let store = open_store(&path).unwrap();
+
+        let options = JsonLinesOptions::new()
+            .with_prop_ids(&[0x001A])
+            .with_message_class_filter(MessageClassFilter::new().exclude("IPM.Note"));
+        let mut json = vec![];
+        assert_eq!(write_store(store.as_ref(), &mut json, &options).unwrap(), 1);
+        let json = String::from_utf8(json).unwrap();
+        assert_eq!(json.lines().count(), 1);
+        assert!(json.contains(r#""PidTagMessageClass":"IPM.Appointment""#));
+
+        let mut json = vec![];
+        let options = JsonLinesOptions::new().with_prop_ids(&[0x001A]);
+        assert_eq!(write_store(store.as_ref(), &mut json, &options).unwrap(), 3);
+    }
+}
@@ -0,0 +1,10 @@
+//! Building blocks for writing the folders, messages and attachments in a PST to other formats.
+
+pub(crate) mod csv;
+pub(crate) mod json;
+pub mod jsonl;
+pub mod mode;
+pub mod path;
+pub mod property_list;
+pub mod table;
+pub(crate) mod xml;
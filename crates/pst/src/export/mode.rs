@@ -0,0 +1,44 @@
+//! Options which the exporters in this module share.
+
+/// How an exporter orders the objects it writes, and whether it keeps the values of properties
+/// which change without the content changing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExportMode {
+    /// Write objects in the order they are read from the PST, with every value as it is stored.
+    #[default]
+    AsStored,
+    /// Sort objects and table rows by their path or row ID, and write the values of the
+    /// [`VOLATILE_PROP_IDS`] as empty, so repeated exports of the same content are byte-identical,
+    /// e.g. for reproducible e-discovery packages.
+    Deterministic,
+}
+
+impl ExportMode {
+    /// Whether the value of `prop_id` should be written as empty in this mode.
+    pub fn normalizes(&self, prop_id: u16) -> bool {
+        *self == Self::Deterministic && VOLATILE_PROP_IDS.contains(&prop_id)
+    }
+}
+
+/// Properties which Outlook or a sync client update when it opens or synchronizes a PST, even
+/// though the content of the object is the same.
+pub const VOLATILE_PROP_IDS: &[u16] = &[
+    0x65E2, // PidTagChangeKey
+    0x65E3, // PidTagPredecessorChangeList
+    0x6709, // PidTagLocalCommitTime
+    0x670A, // PidTagLocalCommitTimeMax
+    0x670B, // PidTagDeletedCountTotal
+    0x67F3, // PidTagLtpRowVer
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_mode_normalizes() {
+        assert!(!ExportMode::AsStored.normalizes(0x6709));
+        assert!(ExportMode::Deterministic.normalizes(0x6709));
+        assert!(!ExportMode::Deterministic.normalizes(0x0037));
+    }
+}
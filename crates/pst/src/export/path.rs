@@ -0,0 +1,360 @@
+//! Turn folder names, subjects and attachment file names into path components which are safe to
+//! create on Windows and Unix file systems.
+//!
+//! A [`FileNamePolicy`] replaces the characters which the [`PathStyle`] does not allow, avoids
+//! reserved names, and shortens names to fit the length limits. [`DirectoryNames`] applies the
+//! policy to every entry in one directory and makes the names unique, so two attachments called
+//! `report.pdf` do not overwrite each other.
+
+use std::{collections::BTreeSet, io, path::Path};
+
+/// Which file system rules a [`FileNamePolicy`] follows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PathStyle {
+    /// Reserved characters `<>:"/\|?*`, device names like `CON` and `LPT1`, no trailing dots or
+    /// spaces, lengths counted in UTF-16 code units, and names compared without regard to case.
+    Windows,
+    /// Only `/` and NUL are reserved, lengths are counted in bytes of UTF-8, and names are
+    /// compared exactly.
+    Unix,
+}
+
+impl PathStyle {
+    /// The style of the platform this was built for.
+    pub fn native() -> Self {
+        if cfg!(windows) {
+            Self::Windows
+        } else {
+            Self::Unix
+        }
+    }
+
+    /// Length of `value` in the units which the file system limits.
+    fn len(self, value: &str) -> usize {
+        match self {
+            Self::Windows => value.encode_utf16().count(),
+            Self::Unix => value.len(),
+        }
+    }
+
+    fn char_len(self, c: char) -> usize {
+        match self {
+            Self::Windows => c.len_utf16(),
+            Self::Unix => c.len_utf8(),
+        }
+    }
+
+    fn is_reserved(self, c: char) -> bool {
+        match self {
+            Self::Windows => {
+                c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*')
+            }
+            Self::Unix => c.is_control() || c == '/',
+        }
+    }
+}
+
+/// Device names which Windows reserves in every directory, with or without an extension.
+const WINDOWS_DEVICE_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Extensions longer than this are treated as part of the name when it is shortened.
+const MAX_EXTENSION_LEN: usize = 16;
+
+/// How names from the PST are turned into path components. The defaults follow the
+/// [`PathStyle::native`] rules, with components of up to 255 units, and a Windows path limit of
+/// 259 UTF-16 code units, which is `MAX_PATH` without the terminating NUL.
+#[derive(Clone, Debug)]
+pub struct FileNamePolicy {
+    style: PathStyle,
+    replacement: char,
+    max_name_len: usize,
+    max_path_len: Option<usize>,
+}
+
+impl Default for FileNamePolicy {
+    fn default() -> Self {
+        Self::new(PathStyle::native())
+    }
+}
+
+impl FileNamePolicy {
+    pub fn new(style: PathStyle) -> Self {
+        Self {
+            style,
+            replacement: '_',
+            max_name_len: 255,
+            max_path_len: match style {
+                PathStyle::Windows => Some(259),
+                PathStyle::Unix => None,
+            },
+        }
+    }
+
+    /// Use `replacement` instead of `_` for reserved characters. A `replacement` which is itself
+    /// reserved, or whitespace, leaves the current one unchanged.
+    pub fn replacement(mut self, replacement: char) -> Self {
+        if !self.style.is_reserved(replacement) && !replacement.is_whitespace() {
+            self.replacement = replacement;
+        }
+        self
+    }
+
+    /// Limit each path component to `max_name_len` units of the [`PathStyle`].
+    pub fn max_name_len(mut self, max_name_len: usize) -> Self {
+        self.max_name_len = max_name_len.max(1);
+        self
+    }
+
+    /// Limit the whole path, or pass `None` if the target supports long paths, e.g. with the
+    /// `\\?\` prefix on Windows.
+    pub fn max_path_len(mut self, max_path_len: Option<usize>) -> Self {
+        self.max_path_len = max_path_len;
+        self
+    }
+
+    pub fn style(&self) -> PathStyle {
+        self.style
+    }
+
+    /// Make `name` safe to use as a single path component, limited to
+    /// [`FileNamePolicy::max_name_len`].
+    pub fn sanitize(&self, name: &str) -> String {
+        self.sanitize_to(name, self.max_name_len)
+    }
+
+    fn sanitize_to(&self, name: &str, max_len: usize) -> String {
+        let name: String = name
+            .chars()
+            .map(|c| {
+                if self.style.is_reserved(c) {
+                    self.replacement
+                } else {
+                    c
+                }
+            })
+            .collect();
+        let name = self.truncate(self.trim(&name), max_len);
+
+        if name.is_empty() || name == "." || name == ".." {
+            return self.replacement.to_string();
+        }
+
+        if self.style == PathStyle::Windows {
+            let stem = name.split('.').next().unwrap_or_default().trim_end();
+            if WINDOWS_DEVICE_NAMES
+                .iter()
+                .any(|device| stem.eq_ignore_ascii_case(device))
+            {
+                let name = format!("{stem}{}{}", self.replacement, &name[stem.len()..]);
+                return self.truncate(&name, max_len);
+            }
+        }
+
+        name
+    }
+
+    /// Windows drops trailing dots and spaces when it creates a file, so the name on disk would
+    /// not match. Leading and trailing whitespace is removed from names in either style.
+    fn trim<'a>(&self, name: &'a str) -> &'a str {
+        match self.style {
+            PathStyle::Windows => name.trim().trim_end_matches(['.', ' ']),
+            PathStyle::Unix => name.trim(),
+        }
+    }
+
+    /// Shorten `name` to `max_len` units, keeping the extension if it is short enough.
+    fn truncate(&self, name: &str, max_len: usize) -> String {
+        if self.style.len(name) <= max_len {
+            return name.to_string();
+        }
+
+        let (stem, extension) = split_extension(name);
+        let extension_len = self.style.len(extension);
+        let (stem, extension) = if extension_len <= max_len / 2 {
+            (stem, extension)
+        } else {
+            (name, "")
+        };
+
+        let mut budget = max_len - self.style.len(extension);
+        let stem: String = stem
+            .chars()
+            .take_while(|&c| {
+                let len = self.style.char_len(c);
+                let fits = len <= budget;
+                budget = budget.saturating_sub(len);
+                fits
+            })
+            .collect();
+        format!("{}{extension}", self.trim(&stem))
+    }
+
+    /// The longest component which fits in a directory at `parent`, which is at most
+    /// [`FileNamePolicy::max_name_len`].
+    pub fn max_name_len_in(&self, parent: &Path) -> io::Result<usize> {
+        let Some(max_path_len) = self.max_path_len else {
+            return Ok(self.max_name_len);
+        };
+
+        let parent = parent.to_string_lossy();
+        let parent_len = match self.style.len(&parent) {
+            0 => 0,
+            len if parent.ends_with(['/', '\\']) => len,
+            len => len + 1,
+        };
+        match max_path_len.checked_sub(parent_len) {
+            Some(len) if len > 0 => Ok(len.min(self.max_name_len)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Path is too long for any file name: {parent}"),
+            )),
+        }
+    }
+}
+
+/// Split `name` at the last `.`, unless it is at the start of the name or the extension is too
+/// long to be one.
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(index)
+            if index > 0
+                && name.len() - index <= MAX_EXTENSION_LEN
+                && !name[index..].contains(' ') =>
+        {
+            name.split_at(index)
+        }
+        _ => (name, ""),
+    }
+}
+
+/// The names already used in one directory. Each name is sanitized with the [`FileNamePolicy`]
+/// and, if it collides with an earlier one, gets a ` (2)`, ` (3)`, ... suffix before the
+/// extension. On [`PathStyle::Windows`], names which only differ in case collide.
+#[derive(Clone, Debug)]
+pub struct DirectoryNames {
+    policy: FileNamePolicy,
+    max_name_len: usize,
+    used: BTreeSet<String>,
+}
+
+impl DirectoryNames {
+    /// Track the names in the directory at `parent`, which limits how long they can be if the
+    /// policy has a [`FileNamePolicy::max_path_len`].
+    pub fn new(policy: FileNamePolicy, parent: &Path) -> io::Result<Self> {
+        let max_name_len = policy.max_name_len_in(parent)?;
+        Ok(Self {
+            policy,
+            max_name_len,
+            used: Default::default(),
+        })
+    }
+
+    pub fn policy(&self) -> &FileNamePolicy {
+        &self.policy
+    }
+
+    /// Sanitize `name` and make it unique in the directory.
+    pub fn insert(&mut self, name: &str) -> String {
+        let name = self.policy.sanitize_to(name, self.max_name_len);
+        if self.used.insert(self.key(&name)) {
+            return name;
+        }
+
+        let (stem, extension) = split_extension(&name);
+        (2_usize..)
+            .find_map(|index| {
+                let suffix = format!(" ({index}){extension}");
+                let max_len = self
+                    .max_name_len
+                    .saturating_sub(self.policy.style.len(&suffix));
+                let stem = self.policy.truncate(stem, max_len);
+                let name = format!("{stem}{suffix}");
+                self.used.insert(self.key(&name)).then_some(name)
+            })
+            .unwrap_or_default()
+    }
+
+    fn key(&self, name: &str) -> String {
+        match self.policy.style {
+            PathStyle::Windows => name.to_lowercase(),
+            PathStyle::Unix => name.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windows_file_names() {
+        let policy = FileNamePolicy::new(PathStyle::Windows);
+        assert_eq!(policy.sanitize("Re: a/b\\c?*"), "Re_ a_b_c__");
+        assert_eq!(policy.sanitize("report.pdf. . "), "report.pdf");
+        assert_eq!(policy.sanitize("con"), "con_");
+        assert_eq!(policy.sanitize("LPT1.txt"), "LPT1_.txt");
+        assert_eq!(policy.sanitize("Console.txt"), "Console.txt");
+        assert_eq!(policy.sanitize(" .. "), "_");
+        assert_eq!(policy.sanitize("tab\there"), "tab_here");
+
+        let name = format!("{}.docx", "\u{1F600}".repeat(200));
+        let sanitized = policy.sanitize(&name);
+        assert!(sanitized.ends_with(".docx"));
+        assert_eq!(sanitized.encode_utf16().count(), 255);
+
+        let policy = policy.replacement('-').max_name_len(12);
+        assert_eq!(policy.sanitize("a:b"), "a-b");
+        assert_eq!(policy.sanitize("quarterly report.xlsx"), "quarter.xlsx");
+        assert_eq!(
+            policy.clone().replacement('?').sanitize("a:b"),
+            "a-b",
+            "reserved replacement is ignored"
+        );
+    }
+
+    #[test]
+    fn test_unix_file_names() {
+        let policy = FileNamePolicy::new(PathStyle::Unix);
+        assert_eq!(policy.sanitize("Re: a/b\\c?*"), "Re: a_b\\c?*");
+        assert_eq!(policy.sanitize("con"), "con");
+        assert_eq!(policy.sanitize("notes. "), "notes.");
+        assert_eq!(policy.sanitize(".."), "_");
+        assert_eq!(policy.sanitize("nul\0byte"), "nul_byte");
+
+        let name = format!("{}.txt", "\u{e9}".repeat(200));
+        let sanitized = policy.sanitize(&name);
+        assert!(sanitized.ends_with(".txt"));
+        assert_eq!(sanitized.len(), 254);
+    }
+
+    #[test]
+    fn test_directory_names() {
+        let mut names =
+            DirectoryNames::new(FileNamePolicy::new(PathStyle::Windows), Path::new("")).unwrap();
+        assert_eq!(names.insert("report.pdf"), "report.pdf");
+        assert_eq!(names.insert("Report.PDF"), "Report (2).PDF");
+        assert_eq!(names.insert("report.pdf"), "report (3).pdf");
+        assert_eq!(names.insert("report (2).pdf"), "report (2) (2).pdf");
+
+        let mut names =
+            DirectoryNames::new(FileNamePolicy::new(PathStyle::Unix), Path::new("out")).unwrap();
+        assert_eq!(names.insert("report.pdf"), "report.pdf");
+        assert_eq!(names.insert("Report.PDF"), "Report.PDF");
+        assert_eq!(names.insert("report.pdf"), "report (2).pdf");
+
+        let parent = format!("C:\\export\\{}", "x".repeat(236));
+        let policy = FileNamePolicy::new(PathStyle::Windows);
+        assert_eq!(policy.max_name_len_in(Path::new(&parent)).unwrap(), 12);
+        let mut names = DirectoryNames::new(policy.clone(), Path::new(&parent)).unwrap();
+        assert_eq!(names.insert("attachment.png"), "attachme.png");
+        assert_eq!(names.insert("attachment.png"), "atta (2).png");
+
+        let parent = format!("C:\\{}", "x".repeat(300));
+        assert!(DirectoryNames::new(policy.clone(), Path::new(&parent)).is_err());
+        let policy = policy.max_path_len(None);
+        assert_eq!(policy.max_name_len_in(Path::new(&parent)).unwrap(), 255);
+    }
+}
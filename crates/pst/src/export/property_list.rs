@@ -0,0 +1,468 @@
+//! List the properties of the objects in a PST as JSON or CSV, for interop with Windows Search and
+//! forensic tools. Nothing is written to the PST.
+//!
+//! Each property is listed with its tag, type, canonical MS-OXPROPS name, and its
+//! [Windows Property System](https://learn.microsoft.com/en-us/windows/win32/properties/props)
+//! name where there is one, see [`windows_property_name`]. The [`PropertyNaming`] of the listing
+//! picks which of them goes in the `name` field, and its [`ExportMode`] whether the output is
+//! sorted and normalized.
+
+use std::{fmt::Write, io, rc::Rc};
+
+use super::{csv::write_csv_field, json::write_json_string, mode::ExportMode};
+use crate::{
+    intern::StringInterner,
+    ltp::{
+        prop_context::PropertyValue,
+        prop_tags::{property_tag_name, windows_property_name},
+        prop_type::PropertyType,
+    },
+    messaging::named_prop::NamedPropertyMapProperties,
+    time::UtcDateTime,
+};
+
+/// Which name a [`PropertyListing`] uses for the `name` of each property. Properties without a
+/// name in the chosen scheme fall back to the canonical MS-OXPROPS name, and then to the tag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PropertyNaming {
+    /// Canonical MS-OXPROPS names, e.g. `PidTagSubject`.
+    #[default]
+    Mapi,
+    /// Windows Property System names, e.g. `System.Subject`.
+    WindowsPropertySystem,
+}
+
+/// One property of one object in a [`PropertyListing`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PropertyListingEntry {
+    object: Rc<str>,
+    prop_id: u16,
+    prop_type: PropertyType,
+    mapi_name: Option<Rc<str>>,
+    windows_name: Option<&'static str>,
+    values: Vec<String>,
+    multiple: bool,
+}
+
+impl PropertyListingEntry {
+    /// The object the property belongs to, as passed to [`PropertyListing::add_properties`].
+    pub fn object(&self) -> &str {
+        &self.object
+    }
+
+    /// The [`Self::object`], which is shared with the other entries of the same object, and with
+    /// other listings which use the same [`StringInterner`].
+    pub fn shared_object(&self) -> Rc<str> {
+        self.object.clone()
+    }
+
+    pub fn prop_id(&self) -> u16 {
+        self.prop_id
+    }
+
+    pub fn prop_type(&self) -> PropertyType {
+        self.prop_type
+    }
+
+    /// The property tag, e.g. `0x0037001F`.
+    pub fn tag(&self) -> String {
+        format!("0x{:04X}{:04X}", self.prop_id, u16::from(self.prop_type))
+    }
+
+    /// The canonical MS-OXPROPS name, or the display name of a named property once
+    /// [`PropertyListing::resolve_named_properties`] has been called.
+    pub fn mapi_name(&self) -> Option<&str> {
+        self.mapi_name.as_deref()
+    }
+
+    /// The [`Self::mapi_name`], which is shared like [`Self::shared_object`].
+    pub fn shared_mapi_name(&self) -> Option<Rc<str>> {
+        self.mapi_name.clone()
+    }
+
+    pub fn windows_name(&self) -> Option<&'static str> {
+        self.windows_name
+    }
+
+    /// The name of the property in the `naming` scheme, see [`PropertyNaming`].
+    pub fn name(&self, naming: PropertyNaming) -> String {
+        let name = match naming {
+            PropertyNaming::Mapi => self.mapi_name(),
+            PropertyNaming::WindowsPropertySystem => self.windows_name.or(self.mapi_name()),
+        };
+        name.map_or_else(|| self.tag(), String::from)
+    }
+
+    /// The value formatted as text, with one item per value of a multi-valued property. Times are
+    /// written in ISO 8601 format in UTC, and binary values in hexadecimal.
+    pub fn values(&self) -> &[String] {
+        &self.values
+    }
+
+    pub fn is_multiple(&self) -> bool {
+        self.multiple
+    }
+}
+
+/// Properties of store, folder, message, or attachment objects, which can be written out as JSON
+/// or CSV.
+#[derive(Clone, Debug, Default)]
+pub struct PropertyListing {
+    naming: PropertyNaming,
+    mode: ExportMode,
+    interner: Option<StringInterner>,
+    entries: Vec<PropertyListingEntry>,
+}
+
+impl PropertyListing {
+    pub fn new(naming: PropertyNaming) -> Self {
+        Self {
+            naming,
+            mode: Default::default(),
+            interner: None,
+            entries: Default::default(),
+        }
+    }
+
+    /// Use [`ExportMode::Deterministic`] to sort the output by object and property ID, and to
+    /// leave out the values of volatile properties.
+    pub fn with_mode(mut self, mode: ExportMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Share the object names and property names of the entries through `interner`, instead of
+    /// allocating them for every property. Listings which share an interner share the strings.
+    pub fn with_interner(mut self, interner: StringInterner) -> Self {
+        self.interner = Some(interner);
+        self
+    }
+
+    pub fn interner(&self) -> Option<&StringInterner> {
+        self.interner.as_ref()
+    }
+
+    pub fn naming(&self) -> PropertyNaming {
+        self.naming
+    }
+
+    pub fn mode(&self) -> ExportMode {
+        self.mode
+    }
+
+    /// The entries in the order they were added.
+    pub fn entries(&self) -> &[PropertyListingEntry] {
+        &self.entries
+    }
+
+    /// The entries in the order they are written, which is sorted by object and property ID for
+    /// [`ExportMode::Deterministic`].
+    fn ordered_entries(&self) -> Vec<&PropertyListingEntry> {
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        if self.mode == ExportMode::Deterministic {
+            entries.sort_by(|a, b| (&a.object, a.prop_id).cmp(&(&b.object, b.prop_id)));
+        }
+        entries
+    }
+
+    /// Add the `properties` of one `object`, e.g. its folder path or entry ID. `PtypString8`
+    /// values are decoded with `string_code_page`.
+    pub fn add_properties<'a>(
+        &mut self,
+        object: &str,
+        properties: impl IntoIterator<Item = (&'a u16, &'a PropertyValue)>,
+        string_code_page: u16,
+    ) {
+        let object = self.intern(object);
+        for (prop_id, value) in properties {
+            let (mut values, multiple) = format_value(value, string_code_page);
+            if self.mode.normalizes(*prop_id) {
+                values.clear();
+            }
+            self.entries.push(PropertyListingEntry {
+                object: object.clone(),
+                prop_id: *prop_id,
+                prop_type: PropertyType::from(value),
+                mapi_name: property_tag_name(*prop_id).map(|name| self.intern(name)),
+                windows_name: windows_property_name(*prop_id),
+                values,
+                multiple,
+            });
+        }
+    }
+
+    /// Fill in the names of named properties from the store's named property map, like
+    /// [`NamedPropertyMapProperties::resolve_schema`] does for table columns.
+    pub fn resolve_named_properties(&mut self, map: &NamedPropertyMapProperties) -> io::Result<()> {
+        for entry in self
+            .entries
+            .iter_mut()
+            .filter(|entry| entry.mapi_name.is_none() && entry.prop_id >= 0x8000)
+        {
+            entry.mapi_name = match self.interner.as_ref() {
+                Some(interner) => interner.property_name(map, entry.prop_id)?,
+                None => map.property_name(entry.prop_id)?.map(Rc::from),
+            };
+        }
+        Ok(())
+    }
+
+    fn intern(&self, value: &str) -> Rc<str> {
+        match self.interner.as_ref() {
+            Some(interner) => interner.intern(value),
+            None => Rc::from(value),
+        }
+    }
+
+    /// Write the listing as a JSON array with one object per property, with the fields `object`,
+    /// `tag`, `type`, `name`, `mapi_name`, `windows_name` and `value`. The value is a string, or
+    /// an array of strings for a multi-valued property.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("[");
+        for (index, entry) in self.ordered_entries().into_iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            json.push_str("{\"object\":");
+            write_json_string(&mut json, &entry.object);
+            json.push_str(",\"tag\":");
+            write_json_string(&mut json, &entry.tag());
+            json.push_str(",\"type\":");
+            write_json_string(&mut json, &format!("{:?}", entry.prop_type));
+            json.push_str(",\"name\":");
+            write_json_string(&mut json, &entry.name(self.naming));
+            for (field, name) in [
+                ("mapi_name", entry.mapi_name()),
+                ("windows_name", entry.windows_name()),
+            ] {
+                let _ = write!(json, ",\"{field}\":");
+                match name {
+                    Some(name) => write_json_string(&mut json, name),
+                    None => json.push_str("null"),
+                }
+            }
+            json.push_str(",\"value\":");
+            if entry.multiple {
+                json.push('[');
+                for (index, value) in entry.values.iter().enumerate() {
+                    if index > 0 {
+                        json.push(',');
+                    }
+                    write_json_string(&mut json, value);
+                }
+                json.push(']');
+            } else {
+                write_json_string(&mut json, entry.values.first().map_or("", String::as_str));
+            }
+            json.push('}');
+        }
+        json.push(']');
+        json
+    }
+
+    /// Write the listing as CSV with a header row, and the columns `object`, `tag`, `type`,
+    /// `name`, `mapi_name`, `windows_name` and `value`. The values of a multi-valued property are
+    /// joined with `"; "`.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("object,tag,type,name,mapi_name,windows_name,value\r\n");
+        for entry in self.ordered_entries() {
+            let fields = [
+                entry.object.to_string(),
+                entry.tag(),
+                format!("{:?}", entry.prop_type),
+                entry.name(self.naming),
+                entry.mapi_name().unwrap_or_default().to_string(),
+                entry.windows_name().unwrap_or_default().to_string(),
+                entry.values.join("; "),
+            ];
+            for (index, field) in fields.iter().enumerate() {
+                if index > 0 {
+                    csv.push(',');
+                }
+                write_csv_field(&mut csv, field);
+            }
+            csv.push_str("\r\n");
+        }
+        csv
+    }
+}
+
+/// Format `value` as text, with one item per value of a multi-valued property, and whether it is
+/// multi-valued.
+pub(crate) fn format_value(value: &PropertyValue, string_code_page: u16) -> (Vec<String>, bool) {
+    fn single(value: String) -> (Vec<String>, bool) {
+        (vec![value], false)
+    }
+    fn multiple<T>(values: &[T], format: impl Fn(&T) -> String) -> (Vec<String>, bool) {
+        (values.iter().map(format).collect(), true)
+    }
+    fn hex(buffer: &[u8]) -> String {
+        buffer.iter().fold(String::new(), |mut hex, b| {
+            let _ = write!(hex, "{b:02x}");
+            hex
+        })
+    }
+
+    match value {
+        PropertyValue::Null | PropertyValue::Object(_) => (vec![], false),
+        PropertyValue::Integer16(value) => single(value.to_string()),
+        PropertyValue::Integer32(value) => single(value.to_string()),
+        PropertyValue::Floating32(value) => single(value.to_string()),
+        PropertyValue::Floating64(value) => single(value.to_string()),
+        PropertyValue::Currency(value) => single(value.to_string()),
+        PropertyValue::FloatingTime(value) => single(value.to_string()),
+        PropertyValue::ErrorCode(value) => single(format!("0x{value:08X}")),
+        PropertyValue::Boolean(value) => single(value.to_string()),
+        PropertyValue::Integer64(value) => single(value.to_string()),
+        PropertyValue::String8(value) => single(value.to_string_with_code_page(string_code_page)),
+        PropertyValue::Unicode(value) => single(value.to_string()),
+        PropertyValue::Time(value) => single(format_filetime(*value)),
+        PropertyValue::Guid(value) => single(value.to_string()),
+        PropertyValue::Binary(value) => single(hex(value.buffer())),
+        PropertyValue::MultipleInteger16(values) => multiple(values, i16::to_string),
+        PropertyValue::MultipleInteger32(values) => multiple(values, i32::to_string),
+        PropertyValue::MultipleFloating32(values) => multiple(values, f32::to_string),
+        PropertyValue::MultipleFloating64(values) => multiple(values, f64::to_string),
+        PropertyValue::MultipleCurrency(values) => multiple(values, i64::to_string),
+        PropertyValue::MultipleFloatingTime(values) => multiple(values, f64::to_string),
+        PropertyValue::MultipleInteger64(values) => multiple(values, i64::to_string),
+        PropertyValue::MultipleString8(values) => multiple(values, |value| {
+            value.to_string_with_code_page(string_code_page)
+        }),
+        PropertyValue::MultipleUnicode(values) => multiple(values, ToString::to_string),
+        PropertyValue::MultipleTime(values) => multiple(values, |value| format_filetime(*value)),
+        PropertyValue::MultipleGuid(values) => multiple(values, ToString::to_string),
+        PropertyValue::MultipleBinary(values) => multiple(values, |value| hex(value.buffer())),
+    }
+}
+
+/// Format a `FILETIME` as ISO 8601 in UTC, with a fraction of a second only if it has one.
+fn format_filetime(filetime: i64) -> String {
+    UtcDateTime::from_filetime(filetime).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ltp::prop_context::{BinaryValue, UnicodeValue};
+    use std::collections::BTreeMap;
+
+    fn listing(naming: PropertyNaming) -> PropertyListing {
+        let properties = BTreeMap::from([
+            (
+                0x0037,
+                PropertyValue::Unicode(UnicodeValue::new("Hi, \"you\"".encode_utf16().collect())),
+            ),
+            (0x0E06, PropertyValue::Time(127_015_231_570_000_000)),
+            (
+                0x0FF9,
+                PropertyValue::Binary(BinaryValue::new(vec![0xAB, 1])),
+            ),
+            (0x8001, PropertyValue::MultipleInteger32(vec![1, 2])),
+        ]);
+        let mut listing = PropertyListing::new(naming);
+        listing.add_properties("Inbox/1", properties.iter(), 1252);
+        listing
+    }
+
+    #[test]
+    fn test_property_listing_names() {
+        let listing = listing(PropertyNaming::WindowsPropertySystem);
+        let names: Vec<_> = listing
+            .entries()
+            .iter()
+            .map(|entry| entry.name(listing.naming()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "System.Subject",
+                "System.Message.DateReceived",
+                "PidTagRecordKey",
+                "0x80011003"
+            ]
+        );
+        assert_eq!(
+            listing.entries()[0].name(PropertyNaming::Mapi),
+            "PidTagSubject"
+        );
+        assert_eq!(listing.entries()[1].values(), ["2003-07-01T08:52:37Z"]);
+        assert_eq!(format_filetime(1), "1601-01-01T00:00:00.0000001Z");
+    }
+
+    #[test]
+    fn test_property_listing_json_and_csv() {
+        let listing = listing(PropertyNaming::WindowsPropertySystem);
+        let json = listing.to_json();
+        assert!(json.starts_with(
+            "[{\"object\":\"Inbox/1\",\"tag\":\"0x0037001F\",\"type\":\"Unicode\",\
+             \"name\":\"System.Subject\",\"mapi_name\":\"PidTagSubject\",\
+             \"windows_name\":\"System.Subject\",\"value\":\"Hi, \\\"you\\\"\"}"
+        ));
+        assert!(json.ends_with("\"windows_name\":null,\"value\":[\"1\",\"2\"]}]"));
+
+        let csv = listing.to_csv();
+        let lines: Vec<_> = csv.split("\r\n").collect();
+        assert_eq!(
+            lines[0],
+            "object,tag,type,name,mapi_name,windows_name,value"
+        );
+        assert_eq!(
+            lines[1],
+            "Inbox/1,0x0037001F,Unicode,System.Subject,PidTagSubject,System.Subject,\"Hi, \"\"you\"\"\""
+        );
+        assert_eq!(
+            lines[3],
+            "Inbox/1,0x0FF90102,Binary,PidTagRecordKey,PidTagRecordKey,,ab01"
+        );
+        assert_eq!(
+            lines[4],
+            "Inbox/1,0x80011003,MultipleInteger32,0x80011003,,,1; 2"
+        );
+    }
+
+    #[test]
+    fn test_property_listing_deterministic() {
+        let first = BTreeMap::from([
+            (0x0E07, PropertyValue::Integer32(1)),
+            (0x6709, PropertyValue::Time(127_015_231_570_000_000)),
+        ]);
+        let second = BTreeMap::from([(0x0E07, PropertyValue::Integer32(2))]);
+
+        let mut listing =
+            PropertyListing::new(PropertyNaming::Mapi).with_mode(ExportMode::Deterministic);
+        listing.add_properties("Inbox/2", first.iter(), 1252);
+        listing.add_properties("Inbox/1", second.iter(), 1252);
+        assert_eq!(listing.entries()[0].object(), "Inbox/2");
+
+        let interner = StringInterner::new();
+        let mut interned =
+            PropertyListing::new(PropertyNaming::Mapi).with_interner(interner.clone());
+        interned.add_properties("Inbox/2", first.iter(), 1252);
+        interned.add_properties("Inbox/2", second.iter(), 1252);
+        let entries = interned.entries();
+        assert!(Rc::ptr_eq(
+            &entries[0].shared_object(),
+            &entries[2].shared_object()
+        ));
+        assert!(Rc::ptr_eq(
+            &entries[0].shared_mapi_name().unwrap(),
+            &entries[2].shared_mapi_name().unwrap()
+        ));
+        assert_eq!(interned.to_csv(), {
+            let mut plain = PropertyListing::new(PropertyNaming::Mapi);
+            plain.add_properties("Inbox/2", first.iter(), 1252);
+            plain.add_properties("Inbox/2", second.iter(), 1252);
+            plain.to_csv()
+        });
+        assert_eq!(interner.len(), 2);
+
+        let csv = listing.to_csv();
+        let lines: Vec<_> = csv.split("\r\n").collect();
+        assert_eq!(
+            lines[1],
+            "Inbox/1,0x0E070003,Integer32,PidTagMessageFlags,PidTagMessageFlags,System.Message.Flags,2"
+        );
+        assert_eq!(lines[3], "Inbox/2,0x67090040,Time,0x67090040,,,");
+    }
+}
@@ -0,0 +1,286 @@
+//! Write the rows of a table context as CSV, e.g. to load the metadata of every message in a
+//! mailbox into an analytics pipeline.
+//!
+//! Each row is formatted and written as soon as it is read, so the output for a folder with
+//! millions of messages never has to fit in memory. Values are formatted the same way as in a
+//! [`PropertyListing`](super::property_list::PropertyListing), and the values of multi-valued
+//! columns are joined with `"; "`. [`ExportMode::Deterministic`] sorts the rows by row ID, which
+//! means buffering the formatted rows until the whole table has been read.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use super::{csv::write_csv_field, mode::ExportMode, property_list::format_value};
+use crate::{
+    ltp::{
+        prop_tags::property_tag_name,
+        table_context::{TableContext, TableRowView},
+    },
+    messaging::{folder::Folder, message_class_filter::MessageClassFilter},
+};
+
+/// `PidTagLtpRowId` and `PidTagLtpRowVer`, which every table has and which are written as the
+/// `row_id` column instead.
+const LTP_ROW_COLUMNS: [u16; 2] = [0x67F2, 0x67F3];
+
+/// Write the contents table of `folder` as CSV, see [`write_table_csv`]. `PtypString8` values are
+/// decoded with the code page of the store. Only the rows whose `PidTagMessageClass` passes the
+/// `filter` are written.
+pub fn write_csv<F: Folder + ?Sized>(
+    folder: &F,
+    columns: &[u16],
+    filter: &MessageClassFilter,
+    mode: ExportMode,
+    writer: &mut dyn Write,
+) -> io::Result<u64> {
+    let code_page = folder.store().properties().string_code_page();
+    let string_decoder = folder.store().string_decoder();
+    match folder.contents_table() {
+        Some(contents_table) => write_rows_csv(
+            contents_table.as_ref(),
+            columns,
+            code_page,
+            mode,
+            &mut |row| filter.matches_row(contents_table.as_ref(), row, &string_decoder),
+            writer,
+        ),
+        None => {
+            write_header(columns, writer)?;
+            Ok(0)
+        }
+    }
+}
+
+/// Write the `columns` of each row in `table` as CSV, and return the number of rows.
+///
+/// The first column is the `row_id`, which is the NID of the message for a contents table. The
+/// header names the other columns by their canonical property name, or their property ID in hex if
+/// the name is not known. If `columns` is empty, every column in the table is written. Columns
+/// which are not in the table, and volatile columns in [`ExportMode::Deterministic`], are written
+/// as empty values.
+pub fn write_table_csv(
+    table: &dyn TableContext,
+    columns: &[u16],
+    string_code_page: u16,
+    mode: ExportMode,
+    writer: &mut dyn Write,
+) -> io::Result<u64> {
+    write_rows_csv(
+        table,
+        columns,
+        string_code_page,
+        mode,
+        &mut |_| Ok(true),
+        writer,
+    )
+}
+
+/// Write the rows of `table` for which `include` returns `true`, see [`write_table_csv`].
+fn write_rows_csv(
+    table: &dyn TableContext,
+    columns: &[u16],
+    string_code_page: u16,
+    mode: ExportMode,
+    include: &mut dyn FnMut(&TableRowView<'_>) -> io::Result<bool>,
+    writer: &mut dyn Write,
+) -> io::Result<u64> {
+    let context = table.context();
+    let all_columns: Vec<_>;
+    let columns = if columns.is_empty() {
+        all_columns = context
+            .columns()
+            .iter()
+            .map(|column| column.prop_id())
+            .filter(|prop_id| !LTP_ROW_COLUMNS.contains(prop_id))
+            .collect();
+        all_columns.as_slice()
+    } else {
+        columns
+    };
+    write_header(columns, writer)?;
+
+    let indices: Vec<_> = columns
+        .iter()
+        .map(|prop_id| {
+            if mode.normalizes(*prop_id) {
+                return None;
+            }
+            context
+                .columns()
+                .iter()
+                .position(|column| column.prop_id() == *prop_id)
+        })
+        .collect();
+
+    let mut rows = 0;
+    let mut sorted_rows = BTreeMap::new();
+    let mut line = String::new();
+    table.for_each_row(&mut |row| {
+        if !include(&row)? {
+            return Ok(());
+        }
+        line.clear();
+        line.push_str(&u32::from(row.id()).to_string());
+        for index in indices.iter() {
+            line.push(',');
+            let Some(index) = *index else {
+                continue;
+            };
+            let Some(value) = row.column(index)? else {
+                continue;
+            };
+            let value = table.read_column(&value, context.columns()[index].prop_type())?;
+            let (values, _) = format_value(&value, string_code_page);
+            write_csv_field(&mut line, &values.join("; "));
+        }
+        line.push_str("\r\n");
+        match mode {
+            ExportMode::AsStored => writer.write_all(line.as_bytes())?,
+            ExportMode::Deterministic => {
+                sorted_rows.insert(u32::from(row.id()), line.clone());
+            }
+        }
+        rows += 1;
+        Ok(())
+    })?;
+    for line in sorted_rows.values() {
+        writer.write_all(line.as_bytes())?;
+    }
+    Ok(rows)
+}
+
+fn write_header(columns: &[u16], writer: &mut dyn Write) -> io::Result<()> {
+    let mut header = String::from("row_id");
+    for prop_id in columns {
+        header.push(',');
+        match property_tag_name(*prop_id) {
+            Some(name) => header.push_str(name),
+            None => header.push_str(&format!("0x{prop_id:04X}")),
+        }
+    }
+    header.push_str("\r\n");
+    writer.write_all(header.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{open_store, temp_path::TempPath};
+    use std::path::Path;
+
+    #[test]
+    fn test_write_table_csv() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst");
+        let store = open_store(path).unwrap();
+        let root = store
+            .open_folder(&store.properties().root_folder_entry_id().unwrap())
+            .unwrap();
+        let hierarchy_table = root.hierarchy_table().unwrap();
+
+        let mut csv = vec![];
+        let rows = write_table_csv(
+            hierarchy_table.as_ref(),
+            &[0x3001, 0x3602, 0x8000],
+            1252,
+            ExportMode::AsStored,
+            &mut csv,
+        )
+        .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "row_id,PidTagDisplayName,PidTagContentCount,0x8000"
+        );
+        assert_eq!(rows as usize, lines.len() - 1);
+        assert_eq!(lines[1], "32802,Top of Outlook data file,0,");
+
+        let mut csv = vec![];
+        write_table_csv(
+            hierarchy_table.as_ref(),
+            &[],
+            1252,
+            ExportMode::AsStored,
+            &mut csv,
+        )
+        .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("row_id,"));
+        assert!(!csv.lines().next().unwrap().contains("PidTagLtpRowId"));
+
+        let mut sorted = vec![];
+        write_table_csv(
+            hierarchy_table.as_ref(),
+            &[0x3001, 0x67F3],
+            1252,
+            ExportMode::Deterministic,
+            &mut sorted,
+        )
+        .unwrap();
+        let sorted = String::from_utf8(sorted).unwrap();
+        let row_ids: Vec<u32> = sorted
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().parse().unwrap())
+            .collect();
+        assert!(row_ids.is_sorted());
+        assert!(sorted.lines().skip(1).all(|line| line.ends_with(',')));
+
+        let mut csv = vec![];
+        assert_eq!(
+            write_csv(
+                root.as_ref(),
+                &[0x0037],
+                &MessageClassFilter::new(),
+                ExportMode::AsStored,
+                &mut csv
+            )
+            .unwrap(),
+            0
+        );
+        assert_eq!(csv, b"row_id,PidTagSubject\r\n");
+    }
+
+    #[test]
+    fn test_write_csv_message_class_filter() {
+        let path = TempPath::empty_pst_with_messages(
+            "write-csv-message-class-filter",
+            &["IPM.Note", "IPM.Appointment", "IPM.Note.SMIME"],
+        );
+        let store = open_store(&path).unwrap();
+        let folder = store
+            .open_folder(&store.properties().ipm_sub_tree_entry_id().unwrap())
+            .unwrap();
+
+        let mut csv = vec![];
+        let rows = write_csv(
+            folder.as_ref(),
+            &[0x001A],
+            &MessageClassFilter::new().include("IPM.Note*"),
+            ExportMode::Deterministic,
+            &mut csv,
+        )
+        .unwrap();
+        assert_eq!(rows, 2);
+        let csv = String::from_utf8(csv).unwrap();
+        let message_classes: Vec<_> = csv
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').nth(1).unwrap())
+            .collect();
+        assert_eq!(message_classes, ["IPM.Note", "IPM.Note.SMIME"]);
+
+        let mut csv = vec![];
+        let rows = write_csv(
+            folder.as_ref(),
+            &[0x001A],
+            &MessageClassFilter::new(),
+            ExportMode::AsStored,
+            &mut csv,
+        )
+        .unwrap();
+        assert_eq!(rows, 3);
+    }
+}
@@ -0,0 +1,305 @@
+//! Simple, read-only entry point for the common cases: open a PST file, walk its folders and read
+//! the messages in them.
+//!
+//! [`PstArchive`], [`MailFolder`] and [`MailMessage`] are concrete types around the `dyn` traits
+//! in [`messaging`](crate::messaging), so there are no [`PstFile`](crate::PstFile) generics to
+//! spell out. When you need more than they offer, [`PstArchive::store`], [`MailFolder::folder`]
+//! and [`MailMessage::message`] give you the underlying objects.
+
+use std::{io, path::Path, rc::Rc, time::SystemTime};
+
+use crate::{
+    ltp::prop_context::PropertyValue,
+    messaging::{
+        completeness::MessageCompleteness, folder::Folder, folder_type::FolderType,
+        message::Message, store::*,
+    },
+    open_store,
+    time::{self, InvalidTimePolicy},
+};
+
+pub use crate::messaging::message_class_filter::MessageClassFilter;
+
+/// An open PST file, either Unicode or ANSI.
+#[derive(Clone)]
+pub struct PstArchive {
+    store: Rc<dyn Store>,
+}
+
+impl PstArchive {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::from(open_store(path)?))
+    }
+
+    pub fn store(&self) -> &Rc<dyn Store> {
+        &self.store
+    }
+
+    pub fn display_name(&self) -> io::Result<String> {
+        self.store.properties().display_name()
+    }
+
+    /// The top of the folder tree shown in Outlook, i.e. the IPM subtree.
+    pub fn root_folder(&self) -> io::Result<MailFolder> {
+        let entry_id = self.store.properties().ipm_sub_tree_entry_id()?;
+        self.open_folder(&entry_id)
+    }
+
+    pub fn open_folder(&self, entry_id: &EntryId) -> io::Result<MailFolder> {
+        Ok(MailFolder::from(self.store.open_folder(entry_id)?))
+    }
+
+    pub fn open_message(&self, entry_id: &EntryId) -> io::Result<MailMessage> {
+        Ok(MailMessage::from(self.store.open_message(entry_id, None)?))
+    }
+
+    /// List the [`root_folder`](Self::root_folder) and all of the folders below it, depth-first.
+    pub fn folders(&self) -> io::Result<Vec<MailFolder>> {
+        let mut folders = vec![];
+        let mut pending = vec![self.root_folder()?];
+        while let Some(folder) = pending.pop() {
+            pending.extend(folder.sub_folders()?.into_iter().rev());
+            folders.push(folder);
+        }
+        Ok(folders)
+    }
+
+    /// Open every message in every folder whose `PidTagMessageClass` passes the `filter`, see
+    /// [`Store::iter_messages`].
+    pub fn iter_messages<'a>(
+        &'a self,
+        filter: &'a MessageClassFilter,
+    ) -> io::Result<impl Iterator<Item = io::Result<MailMessage>> + 'a> {
+        Ok(self
+            .store
+            .iter_messages(filter)?
+            .map(|message| Ok(MailMessage::from(message?))))
+    }
+}
+
+impl From<Rc<dyn Store>> for PstArchive {
+    fn from(store: Rc<dyn Store>) -> Self {
+        Self { store }
+    }
+}
+
+/// A folder in a [`PstArchive`].
+#[derive(Clone)]
+pub struct MailFolder {
+    folder: Rc<dyn Folder>,
+}
+
+impl MailFolder {
+    pub fn folder(&self) -> &Rc<dyn Folder> {
+        &self.folder
+    }
+
+    pub fn entry_id(&self) -> io::Result<EntryId> {
+        self.folder
+            .store()
+            .properties()
+            .make_entry_id(self.folder.properties().node_id())
+    }
+
+    pub fn display_name(&self) -> io::Result<String> {
+        self.folder.properties().display_name()
+    }
+
+    pub fn content_count(&self) -> io::Result<i32> {
+        self.folder.properties().content_count()
+    }
+
+    pub fn unread_count(&self) -> io::Result<i32> {
+        self.folder.properties().unread_count()
+    }
+
+    /// The type of the folder, detected from the message classes in its contents table if it
+    /// does not have a `PidTagContainerClass`.
+    pub fn folder_type(&self) -> io::Result<FolderType> {
+        self.folder.folder_type()
+    }
+
+    /// Open each of the folders listed in the hierarchy table.
+    pub fn sub_folders(&self) -> io::Result<Vec<MailFolder>> {
+        let Some(hierarchy_table) = self.folder.hierarchy_table() else {
+            return Ok(Default::default());
+        };
+
+        let store = self.folder.store();
+        hierarchy_table
+            .rows_matrix()
+            .map(|row| {
+                let entry_id = self.folder.entry_id_for_row(row);
+                Ok(MailFolder::from(store.open_folder(&entry_id)?))
+            })
+            .collect()
+    }
+
+    /// List the [`EntryId`] of each message in the contents table without opening them.
+    pub fn message_entry_ids(&self) -> Vec<EntryId> {
+        self.folder
+            .contents_table()
+            .map(|contents_table| {
+                contents_table
+                    .rows_matrix()
+                    .map(|row| self.folder.entry_id_for_row(row))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// List the [`EntryId`] of each message in the contents table whose `PidTagMessageClass`
+    /// column passes the `filter`. Messages without a message class are treated as `IPM`.
+    pub fn message_entry_ids_matching(
+        &self,
+        filter: &MessageClassFilter,
+    ) -> io::Result<Vec<EntryId>> {
+        self.folder.message_entry_ids_matching(filter)
+    }
+
+    /// Open each of the messages in the contents table as the iterator reaches it.
+    pub fn messages(&self) -> impl Iterator<Item = io::Result<MailMessage>> + '_ {
+        self.folder
+            .contents_table()
+            .into_iter()
+            .flat_map(move |contents_table| {
+                contents_table.rows_matrix().map(move |row| {
+                    Ok(MailMessage::from(
+                        self.folder.open_message_at_row(row, None)?,
+                    ))
+                })
+            })
+    }
+}
+
+impl From<Rc<dyn Folder>> for MailFolder {
+    fn from(folder: Rc<dyn Folder>) -> Self {
+        Self { folder }
+    }
+}
+
+/// A message in a [`PstArchive`]. The string accessors decode `PtypString8` values with the
+/// message's [code page](crate::messaging::message::MessageProperties::string_code_page), and
+/// return `None` if the property is missing or has some other type.
+#[derive(Clone)]
+pub struct MailMessage {
+    message: Rc<dyn Message>,
+}
+
+impl MailMessage {
+    pub fn message(&self) -> &Rc<dyn Message> {
+        &self.message
+    }
+
+    pub fn message_class(&self) -> io::Result<String> {
+        self.message.properties().message_class()
+    }
+
+    /// Which of the mandatory properties, e.g. `PidTagMessageClass`, the message is missing or
+    /// has with the wrong type. An export can still write an incomplete message, see
+    /// [`MessageProperties::relaxed`](crate::messaging::message::MessageProperties::relaxed).
+    pub fn completeness(&self) -> MessageCompleteness {
+        self.message.properties().completeness()
+    }
+
+    /// `PidTagSubject`, without the length marker for the `PidTagSubjectPrefix`.
+    pub fn subject(&self) -> Option<String> {
+        let subject = self.string_property(0x0037)?;
+        Some(match subject.strip_prefix('\u{1}') {
+            Some(subject) => subject.chars().skip(1).collect(),
+            None => subject,
+        })
+    }
+
+    /// `PidTagSenderName`
+    pub fn sender_name(&self) -> Option<String> {
+        self.string_property(0x0C1A)
+    }
+
+    /// `PidTagDisplayTo`
+    pub fn display_to(&self) -> Option<String> {
+        self.string_property(0x0E04)
+    }
+
+    /// `PidTagMessageDeliveryTime`, as a `FILETIME`.
+    pub fn delivery_time(&self) -> Option<i64> {
+        match self.message.properties().get(0x0E06)? {
+            PropertyValue::Time(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// [`Self::delivery_time`] as a [`SystemTime`], or `None` if it is not set or is not a valid
+    /// time, see [`time::to_system_time`].
+    pub fn delivered_at(&self) -> Option<SystemTime> {
+        time::to_system_time(self.delivery_time()?, InvalidTimePolicy::Ignore)
+            .ok()
+            .flatten()
+    }
+
+    /// `PidTagBody`, the plain text body.
+    pub fn body(&self) -> Option<String> {
+        self.string_property(0x1000)
+    }
+
+    pub fn attachment_count(&self) -> usize {
+        self.message
+            .attachment_table()
+            .map(|attachment_table| attachment_table.rows_matrix().count())
+            .unwrap_or_default()
+    }
+
+    fn string_property(&self, prop_id: u16) -> Option<String> {
+        let properties = self.message.properties();
+        match properties.get(prop_id)? {
+            PropertyValue::String8(value) => {
+                Some(value.to_string_with_decoder(properties.string_decoder()))
+            }
+            PropertyValue::Unicode(value) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Rc<dyn Message>> for MailMessage {
+    fn from(message: Rc<dyn Message>) -> Self {
+        Self { message }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_path::TempPath;
+
+    #[test]
+    fn test_message_entry_ids_matching() {
+        let path = TempPath::empty_pst_with_messages(
+            "facade-message-class-filter",
+            &["IPM.Note", "IPM.Appointment"],
+        );
+        let archive = PstArchive::open(&path).unwrap();
+        let root_folder = archive.root_folder().unwrap();
+        assert_eq!(root_folder.message_entry_ids().len(), 2);
+
+        let filter = MessageClassFilter::new().include("IPM.Appointment");
+        let entry_ids = root_folder.message_entry_ids_matching(&filter).unwrap();
+        assert_eq!(entry_ids.len(), 1);
+        assert_eq!(
+            archive
+                .open_message(&entry_ids[0])
+                .unwrap()
+                .message_class()
+                .unwrap(),
+            "IPM.Appointment"
+        );
+
+        let messages = archive
+            .iter_messages(&filter)
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].message_class().unwrap(), "IPM.Appointment");
+    }
+}
@@ -0,0 +1,156 @@
+//! Implicit flushes for long-running writers, e.g. importing a large mailbox with one
+//! [`PstFileLockGuard`](crate::PstFileLockGuard).
+//!
+//! Every page and block written while the file is locked is held in memory till the transaction
+//! is committed, and anything which was not committed is lost if the process crashes. An
+//! [`AutoFlushPolicy`] bounds both by committing the pending writes and starting a new
+//! transaction once enough data is pending or enough time has passed. The policy is checked
+//! before each message is created, updated, copied, or deleted, so a flush never splits one of
+//! those operations.
+
+use std::time::Duration;
+
+/// When a [`PstFileLockGuard`](crate::PstFileLockGuard) flushes without an explicit call to
+/// [`flush`](crate::PstFileLockGuard::flush). The default policy never flushes implicitly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AutoFlushPolicy {
+    max_dirty_bytes: Option<u64>,
+    max_interval: Option<Duration>,
+}
+
+impl AutoFlushPolicy {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Flush once the pages and blocks waiting to be committed add up to `bytes`.
+    pub fn with_max_dirty_bytes(self, bytes: u64) -> Self {
+        Self {
+            max_dirty_bytes: Some(bytes),
+            ..self
+        }
+    }
+
+    /// Flush once `interval` has passed since the last flush, explicit or implicit.
+    pub fn with_max_interval(self, interval: Duration) -> Self {
+        Self {
+            max_interval: Some(interval),
+            ..self
+        }
+    }
+
+    pub fn max_dirty_bytes(&self) -> Option<u64> {
+        self.max_dirty_bytes
+    }
+
+    pub fn max_interval(&self) -> Option<Duration> {
+        self.max_interval
+    }
+
+    /// Whether a transaction with `dirty_bytes` pending, which started `elapsed` ago, should be
+    /// flushed. There is nothing to flush if nothing is pending, whatever the interval.
+    pub fn is_due(&self, dirty_bytes: u64, elapsed: Duration) -> bool {
+        dirty_bytes > 0
+            && (self.max_dirty_bytes.is_some_and(|max| dirty_bytes >= max)
+                || self.max_interval.is_some_and(|max| elapsed >= max))
+    }
+}
+
+/// How often, and for how long, a [`PstFileLockGuard`](crate::PstFileLockGuard) has flushed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FlushMetrics {
+    explicit_flushes: u64,
+    implicit_flushes: u64,
+    flushed_bytes: u64,
+    total_duration: Duration,
+    longest_duration: Duration,
+    last_duration: Option<Duration>,
+}
+
+impl FlushMetrics {
+    pub(crate) fn record(&mut self, implicit: bool, bytes: u64, duration: Duration) {
+        if implicit {
+            self.implicit_flushes += 1;
+        } else {
+            self.explicit_flushes += 1;
+        }
+        self.flushed_bytes += bytes;
+        self.total_duration += duration;
+        self.longest_duration = self.longest_duration.max(duration);
+        self.last_duration = Some(duration);
+    }
+
+    /// Calls to [`flush`](crate::PstFileLockGuard::flush).
+    pub fn explicit_flushes(&self) -> u64 {
+        self.explicit_flushes
+    }
+
+    /// Flushes triggered by the [`AutoFlushPolicy`].
+    pub fn implicit_flushes(&self) -> u64 {
+        self.implicit_flushes
+    }
+
+    pub fn flushes(&self) -> u64 {
+        self.explicit_flushes + self.implicit_flushes
+    }
+
+    /// Bytes of pages and blocks which were pending when each flush started.
+    pub fn flushed_bytes(&self) -> u64 {
+        self.flushed_bytes
+    }
+
+    pub fn total_duration(&self) -> Duration {
+        self.total_duration
+    }
+
+    pub fn longest_duration(&self) -> Duration {
+        self.longest_duration
+    }
+
+    pub fn last_duration(&self) -> Option<Duration> {
+        self.last_duration
+    }
+
+    pub fn average_duration(&self) -> Option<Duration> {
+        u32::try_from(self.flushes())
+            .ok()
+            .filter(|flushes| *flushes > 0)
+            .map(|flushes| self.total_duration / flushes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auto_flush_policy() {
+        let policy = AutoFlushPolicy::new();
+        assert!(!policy.is_due(u64::MAX, Duration::MAX));
+
+        let policy = policy.with_max_dirty_bytes(1024);
+        assert!(!policy.is_due(1023, Duration::MAX));
+        assert!(policy.is_due(1024, Duration::ZERO));
+
+        let policy = AutoFlushPolicy::new().with_max_interval(Duration::from_secs(30));
+        assert!(!policy.is_due(1, Duration::from_secs(29)));
+        assert!(policy.is_due(1, Duration::from_secs(30)));
+        assert!(!policy.is_due(0, Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_flush_metrics() {
+        let mut metrics = FlushMetrics::default();
+        assert_eq!(metrics.average_duration(), None);
+
+        metrics.record(true, 100, Duration::from_millis(30));
+        metrics.record(true, 50, Duration::from_millis(10));
+        metrics.record(false, 10, Duration::from_millis(20));
+        assert_eq!(metrics.implicit_flushes(), 2);
+        assert_eq!(metrics.explicit_flushes(), 1);
+        assert_eq!(metrics.flushed_bytes(), 160);
+        assert_eq!(metrics.longest_duration(), Duration::from_millis(30));
+        assert_eq!(metrics.last_duration(), Some(Duration::from_millis(20)));
+        assert_eq!(metrics.average_duration(), Some(Duration::from_millis(20)));
+    }
+}
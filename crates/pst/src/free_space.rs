@@ -0,0 +1,192 @@
+//! The free space recorded in a PST file, as returned by
+//! [`PstFile::free_space_map`](crate::PstFile::free_space_map), e.g. to visualize fragmentation or
+//! to check the free space accounting with another tool.
+//!
+//! The AMap pages are the authoritative record of which 64 byte slots are allocated. The header
+//! `rgbFM` entries, the density list, and `ibAMapFree` in the root are hints derived from them,
+//! which a [`FreeSpaceMap`] reports as they are in the file, so they can be compared with the AMap
+//! pages.
+
+use std::ops::Range;
+
+use crate::{
+    compare_free_space_maps, ndb::page::DensityListPageEntry, AllocationMapPageDensity,
+    AllocationMapStatistics, FreeSpaceMapMismatch,
+};
+
+/// Free space in a PST file, read from every AMap page and the density list.
+#[derive(Clone, Debug)]
+pub struct FreeSpaceMap {
+    pub(crate) file_eof: u64,
+    pub(crate) root_free_bytes: u64,
+    pub(crate) pages: Vec<AllocationMapFreeSpace>,
+    pub(crate) density_list: Option<DensityListHints>,
+}
+
+impl FreeSpaceMap {
+    /// `ibFileEof` in the root.
+    pub fn file_eof(&self) -> u64 {
+        self.file_eof
+    }
+
+    /// `ibAMapFree` in the root, which should match [`Self::free_bytes`].
+    pub fn root_free_bytes(&self) -> u64 {
+        self.root_free_bytes
+    }
+
+    /// The free space in each AMap page, in file order.
+    pub fn pages(&self) -> &[AllocationMapFreeSpace] {
+        &self.pages
+    }
+
+    /// The density list, or `None` if the file does not have a readable one.
+    pub fn density_list(&self) -> Option<&DensityListHints> {
+        self.density_list.as_ref()
+    }
+
+    /// Total free bytes in all of the AMap pages.
+    pub fn free_bytes(&self) -> u64 {
+        self.pages
+            .iter()
+            .map(AllocationMapFreeSpace::free_bytes)
+            .sum()
+    }
+
+    /// Every run of free bytes in the file, in file order. A run which continues in the next AMap
+    /// page is split at the page boundary.
+    pub fn free_ranges(&self) -> impl Iterator<Item = Range<u64>> + '_ {
+        self.pages
+            .iter()
+            .flat_map(|page| page.free_ranges.iter().cloned())
+    }
+
+    /// The same summary as
+    /// [`PstFileLockGuard::allocation_statistics`](crate::PstFileLockGuard::allocation_statistics),
+    /// without needing write access.
+    pub fn statistics(&self) -> AllocationMapStatistics {
+        let mut statistics = AllocationMapStatistics::default();
+        for run in self.free_ranges() {
+            let length = run.end - run.start;
+            statistics.free_bytes += length;
+            statistics.free_run_count += 1;
+            statistics.largest_free_run = statistics.largest_free_run.max(length);
+        }
+        statistics
+    }
+
+    /// Compare the header `rgbFM` entries and the density list with the AMap pages, like
+    /// [`PstFileLockGuard::check_free_space_maps`](crate::PstFileLockGuard::check_free_space_maps).
+    /// An empty list means they are consistent.
+    pub fn mismatches(&self) -> Vec<FreeSpaceMapMismatch> {
+        let densities: Vec<_> = self.pages.iter().map(|page| page.density).collect();
+        let free_map: Vec<_> = self
+            .pages
+            .iter()
+            .map_while(|page| page.free_map_entry)
+            .collect();
+        let density_list = self
+            .density_list
+            .as_ref()
+            .map(|density_list| density_list.entries.as_slice())
+            .unwrap_or_default();
+        compare_free_space_maps(&densities, &free_map, density_list)
+    }
+}
+
+/// Free space in the range of the file covered by one AMap page.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AllocationMapFreeSpace {
+    pub(crate) density: AllocationMapPageDensity,
+    pub(crate) range: Range<u64>,
+    pub(crate) free_ranges: Vec<Range<u64>>,
+    pub(crate) free_map_entry: Option<u8>,
+}
+
+impl AllocationMapFreeSpace {
+    pub fn amap_index(&self) -> u64 {
+        self.density.amap_index()
+    }
+
+    /// The bytes of the file covered by this AMap page, starting with the AMap page itself and
+    /// ending at `ibFileEof` for the last page.
+    pub fn range(&self) -> Range<u64> {
+        self.range.clone()
+    }
+
+    /// The runs of free bytes in [`Self::range`], in file order.
+    pub fn free_ranges(&self) -> &[Range<u64>] {
+        &self.free_ranges
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        self.free_ranges.iter().map(|run| run.end - run.start).sum()
+    }
+
+    /// The free space derived from the AMap page, which is what the hints should contain.
+    pub fn density(&self) -> AllocationMapPageDensity {
+        self.density
+    }
+
+    /// The `rgbFM` entry for this page in the header, which only has entries for the first 128
+    /// AMap pages.
+    pub fn free_map_entry(&self) -> Option<u8> {
+        self.free_map_entry
+    }
+}
+
+/// The [`DensityListPage`](crate::ndb::page::DensityListPage) as it was read from the file.
+#[derive(Clone, Debug)]
+pub struct DensityListHints {
+    pub(crate) backfill_complete: bool,
+    pub(crate) current_page: u32,
+    pub(crate) entries: Vec<DensityListPageEntry>,
+}
+
+impl DensityListHints {
+    pub fn backfill_complete(&self) -> bool {
+        self.backfill_complete
+    }
+
+    /// `ulCurrentPage`: the AMap page which is currently being filled.
+    pub fn current_page(&self) -> u32 {
+        self.current_page
+    }
+
+    /// The AMap pages with the most free space, from the most to the least.
+    pub fn entries(&self) -> &[DensityListPageEntry] {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{PstFile, UnicodePstFile};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_free_space_map() {
+        const EMPTY_PST: &[u8] = include_bytes!("../examples/Empty.pst");
+
+        let pst = UnicodePstFile::read_from(Box::new(Cursor::new(EMPTY_PST))).unwrap();
+        let free_space = pst.free_space_map().unwrap();
+        assert_eq!(free_space.file_eof(), EMPTY_PST.len() as u64);
+        assert_eq!(free_space.free_bytes(), free_space.root_free_bytes());
+        assert_eq!(free_space.mismatches(), vec![]);
+
+        let page = &free_space.pages()[0];
+        assert_eq!(page.amap_index(), 0);
+        assert_eq!(page.range().start, 0x4400);
+        assert_eq!(page.free_map_entry(), Some(page.density().max_free_slots()));
+        assert!(page
+            .free_ranges()
+            .iter()
+            .all(|run| run.start > page.range().start && run.end <= page.range().end));
+
+        let statistics = free_space.statistics();
+        assert_eq!(statistics.free_bytes(), free_space.free_bytes());
+        assert_eq!(
+            statistics.free_run_count(),
+            free_space.free_ranges().count()
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Summary of the header and root fields which describe how a PST file was written, including
+//! the traces which crash recovery and repair tools such as `scanpst.exe` leave behind.
+
+use crate::{
+    ndb::{
+        header::{Header, NdbCryptMethod, NdbVersion},
+        read_write::RootReadWrite,
+        root::{AmapStatus, Root},
+    },
+    PstFile,
+};
+
+/// A field in the header or root of a [`PstInfo`] whose value suggests that the file was not
+/// closed cleanly, or that it has been rebuilt by a repair tool.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RepairTrace {
+    /// `fAMapValid` is `INVALID_AMAP`. Outlook clears it while it updates the allocation maps,
+    /// so the file was not closed cleanly, and the maps will be rebuilt the next time it is
+    /// opened.
+    AllocationMapInvalid,
+    /// `fAMapValid` is the deprecated `VALID_AMAP1`, which older versions of Outlook and of the
+    /// Inbox Repair Tool write after rebuilding the allocation maps.
+    LegacyAllocationMapRebuild,
+    /// `dwReserved`, `bReserved`, or `wReserved` in the root is not zero. Outlook leaves them
+    /// zero, and the specification says that implementations should not modify them.
+    RootReservedValues,
+}
+
+/// Header and root fields of a PST file, see [`PstInfo::read`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PstInfo {
+    version: NdbVersion,
+    format_version: u16,
+    crypt_method: NdbCryptMethod,
+    client_version: u16,
+    platform_create: u8,
+    platform_access: u8,
+    unique_value: u32,
+    amap_status: AmapStatus,
+    header_reserved_values: (u32, u32),
+    header_reserved_fields_are_zero: bool,
+    root_reserved_values: (u32, u8, u16),
+    has_standard_fill_maps: bool,
+    density_list_backfill_complete: Option<bool>,
+}
+
+impl PstInfo {
+    pub fn read<Pst>(pst: &Pst) -> Self
+    where
+        Pst: PstFile,
+        <Pst as PstFile>::Root: RootReadWrite<Pst>,
+    {
+        let header = pst.header();
+        let root = header.root();
+        Self {
+            version: header.version(),
+            format_version: header.format_version(),
+            crypt_method: header.crypt_method(),
+            client_version: header.client_version(),
+            platform_create: header.platform_create(),
+            platform_access: header.platform_access(),
+            unique_value: header.unique_value(),
+            amap_status: root.amap_is_valid(),
+            header_reserved_values: header.reserved_values(),
+            header_reserved_fields_are_zero: header.reserved_fields_are_zero(),
+            root_reserved_values: (root.reserved1(), root.reserved2(), root.reserved3()),
+            has_standard_fill_maps: header.has_standard_fill_maps(),
+            density_list_backfill_complete: pst
+                .density_list()
+                .ok()
+                .map(|density_list| density_list.backfill_complete()),
+        }
+    }
+
+    pub fn version(&self) -> NdbVersion {
+        self.version
+    }
+
+    /// `wVer`, see [`Header::format_version`].
+    pub fn format_version(&self) -> u16 {
+        self.format_version
+    }
+
+    pub fn crypt_method(&self) -> NdbCryptMethod {
+        self.crypt_method
+    }
+
+    /// `wVerClient`
+    pub fn client_version(&self) -> u16 {
+        self.client_version
+    }
+
+    /// `bPlatformCreate`
+    pub fn platform_create(&self) -> u8 {
+        self.platform_create
+    }
+
+    /// `bPlatformAccess`
+    pub fn platform_access(&self) -> u8 {
+        self.platform_access
+    }
+
+    /// `dwUnique`, which changes every time the header is written.
+    pub fn unique_value(&self) -> u32 {
+        self.unique_value
+    }
+
+    /// `fAMapValid`
+    pub fn amap_status(&self) -> AmapStatus {
+        self.amap_status
+    }
+
+    /// `dwReserved1` and `dwReserved2` from the header. Outlook fills them in, so they are not a
+    /// repair trace, but a change between two copies of the same file shows that it was
+    /// rewritten by a different client.
+    pub fn header_reserved_values(&self) -> (u32, u32) {
+        self.header_reserved_values
+    }
+
+    /// See [`Header::reserved_fields_are_zero`]. Outlook also writes to `rgbReserved2`, so this
+    /// is not a repair trace by itself.
+    pub fn header_reserved_fields_are_zero(&self) -> bool {
+        self.header_reserved_fields_are_zero
+    }
+
+    /// `dwReserved`, `bReserved`, and `wReserved` from the root.
+    pub fn root_reserved_values(&self) -> (u32, u8, u16) {
+        self.root_reserved_values
+    }
+
+    /// See [`Header::has_standard_fill_maps`]. Files written by older versions of Outlook still
+    /// have the deprecated map values, so this is not a repair trace by itself.
+    pub fn has_standard_fill_maps(&self) -> bool {
+        self.has_standard_fill_maps
+    }
+
+    /// `DFL_BACKFILL_COMPLETE` from the density list, or [`None`] if the file does not have a
+    /// readable density list, e.g. because it is an ANSI file. Outlook does not always set it,
+    /// so this is not a repair trace by itself.
+    pub fn density_list_backfill_complete(&self) -> Option<bool> {
+        self.density_list_backfill_complete
+    }
+
+    /// List the fields whose values suggest a crash or a repair, see [`RepairTrace`].
+    pub fn repair_traces(&self) -> Vec<RepairTrace> {
+        let mut traces = vec![];
+        match self.amap_status {
+            AmapStatus::Invalid => traces.push(RepairTrace::AllocationMapInvalid),
+            AmapStatus::Valid1 => traces.push(RepairTrace::LegacyAllocationMapRebuild),
+            AmapStatus::Valid2 => {}
+        }
+        if self.root_reserved_values != (0, 0, 0) {
+            traces.push(RepairTrace::RootReservedValues);
+        }
+        traces
+    }
+
+    /// Heuristic for whether the file has been through a crash recovery or a repair tool since it
+    /// was created, i.e. whether there are any [`PstInfo::repair_traces`]. None of the fields are
+    /// conclusive, so treat this as a reason to look closer rather than as proof.
+    pub fn was_repaired(&self) -> bool {
+        !self.repair_traces().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UnicodePstFile;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_repair_traces() {
+        const EMPTY_PST: &[u8] = include_bytes!("../examples/Empty.pst");
+
+        let pst = UnicodePstFile::read_from(Box::new(Cursor::new(EMPTY_PST.to_vec()))).unwrap();
+        let mut info = PstInfo::read(&pst);
+        assert_eq!(info.version(), NdbVersion::Unicode);
+        assert_eq!(info.amap_status(), AmapStatus::Valid2);
+        assert_eq!(info.repair_traces(), vec![]);
+        assert!(!info.was_repaired());
+
+        info.amap_status = AmapStatus::Valid1;
+        info.root_reserved_values = (0, 1, 0);
+        assert_eq!(
+            info.repair_traces(),
+            vec![
+                RepairTrace::LegacyAllocationMapRebuild,
+                RepairTrace::RootReservedValues,
+            ]
+        );
+        assert!(info.was_repaired());
+    }
+}
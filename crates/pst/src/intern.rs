@@ -0,0 +1,223 @@
+//! Optional pooling of strings which repeat across the objects in a large scan, e.g. the folder
+//! path, message class, sender address, and named property names in every line of a
+//! [JSON Lines](crate::export::jsonl) export.
+//!
+//! A [`StringInterner`] hands out [`Rc<str>`] values, so each distinct string is allocated once
+//! and downstream code can keep, compare, and hash the shared values without copying them. It is
+//! a cheap handle to a shared pool, so clones of it can be passed to several listings or exports
+//! which then share the same strings. [`StringInterner::disabled`] allocates a new string every
+//! time, which is what the exports do unless they are given an interner.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashSet},
+    io,
+    rc::Rc,
+};
+
+use crate::{
+    ltp::{code_page::String8Decoder, prop_context::PropertyValue},
+    messaging::named_prop::NamedPropertyMapProperties,
+};
+
+/// Strings longer than this are not pooled by default, since bodies and other long values rarely
+/// repeat.
+const DEFAULT_MAX_LEN: usize = 256;
+
+#[derive(Debug, Default)]
+struct StringPool {
+    strings: HashSet<Rc<str>>,
+    property_names: BTreeMap<u16, Option<Rc<str>>>,
+    hits: u64,
+    misses: u64,
+}
+
+/// A shared pool of [`Rc<str>`] values, see the [module](self) documentation.
+#[derive(Clone, Debug)]
+pub struct StringInterner {
+    enabled: bool,
+    max_len: usize,
+    pool: Rc<RefCell<StringPool>>,
+}
+
+impl Default for StringInterner {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_len: DEFAULT_MAX_LEN,
+            pool: Default::default(),
+        }
+    }
+}
+
+impl StringInterner {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// An interner which never pools anything, so every call allocates a new string.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Default::default()
+        }
+    }
+
+    /// Only pool strings of at most `max_len` bytes. The default is 256.
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// The pooled copy of `value`, which is added to the pool if it is not there yet.
+    pub fn intern(&self, value: &str) -> Rc<str> {
+        if !self.enabled || value.len() > self.max_len {
+            return Rc::from(value);
+        }
+
+        let mut pool = self.pool.borrow_mut();
+        if let Some(interned) = pool.strings.get(value) {
+            let interned = interned.clone();
+            pool.hits += 1;
+            return interned;
+        }
+        let interned: Rc<str> = Rc::from(value);
+        pool.strings.insert(interned.clone());
+        pool.misses += 1;
+        interned
+    }
+
+    /// The pooled text of a `PtypString8` or `PtypString` value, or `None` for any other type.
+    pub fn intern_value(&self, value: &PropertyValue, decoder: &String8Decoder) -> Option<Rc<str>> {
+        match value {
+            PropertyValue::String8(value) => {
+                Some(self.intern(&value.to_string_with_decoder(decoder)))
+            }
+            PropertyValue::Unicode(value) => Some(self.intern(&value.to_string())),
+            _ => None,
+        }
+    }
+
+    /// [`NamedPropertyMapProperties::property_name`], which is only looked up in `map` the first
+    /// time for each `prop_id`. The names are cached by property ID alone, so an interner must
+    /// only be used with the named property map of one store.
+    pub fn property_name(
+        &self,
+        map: &NamedPropertyMapProperties,
+        prop_id: u16,
+    ) -> io::Result<Option<Rc<str>>> {
+        if !self.enabled {
+            return Ok(map.property_name(prop_id)?.map(Rc::from));
+        }
+
+        if let Some(name) = self.pool.borrow().property_names.get(&prop_id) {
+            return Ok(name.clone());
+        }
+        let name = map.property_name(prop_id)?.map(|name| self.intern(&name));
+        self.pool
+            .borrow_mut()
+            .property_names
+            .insert(prop_id, name.clone());
+        Ok(name)
+    }
+
+    /// Number of distinct strings in the pool.
+    pub fn len(&self) -> usize {
+        self.pool.borrow().strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pool.borrow().strings.is_empty()
+    }
+
+    /// Number of calls to [`Self::intern`] which returned a string already in the pool, i.e. the
+    /// allocations which were saved.
+    pub fn hits(&self) -> u64 {
+        self.pool.borrow().hits
+    }
+
+    /// Number of calls to [`Self::intern`] which added a string to the pool.
+    pub fn misses(&self) -> u64 {
+        self.pool.borrow().misses
+    }
+
+    /// Drop the pooled strings and cached property names. Strings which were handed out stay
+    /// valid, but they are no longer shared with new ones.
+    pub fn clear(&self) {
+        *self.pool.borrow_mut() = Default::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ltp::prop_context::UnicodeValue,
+        messaging::store::{Store, UnicodeStore},
+        UnicodePstFile,
+    };
+    use std::io::Cursor;
+
+    #[test]
+    fn test_intern() {
+        let interner = StringInterner::new().with_max_len(8);
+        let first = interner.intern("IPM.Note");
+        let shared = interner.clone();
+        assert!(Rc::ptr_eq(&first, &shared.intern("IPM.Note")));
+        assert!(!Rc::ptr_eq(
+            &interner.intern("IPM.Note.Long"),
+            &interner.intern("IPM.Note.Long")
+        ));
+        let value = PropertyValue::Unicode(UnicodeValue::new("IPM.Note".encode_utf16().collect()));
+        assert!(Rc::ptr_eq(
+            &first,
+            &interner.intern_value(&value, &Default::default()).unwrap()
+        ));
+        assert_eq!(
+            interner.intern_value(&PropertyValue::Integer32(1), &Default::default()),
+            None
+        );
+        assert_eq!(interner.len(), 1);
+        assert_eq!((interner.hits(), interner.misses()), (2, 1));
+
+        interner.clear();
+        assert!(interner.is_empty());
+        assert!(!Rc::ptr_eq(&first, &interner.intern("IPM.Note")));
+
+        let disabled = StringInterner::disabled();
+        assert!(!Rc::ptr_eq(
+            &disabled.intern("IPM.Note"),
+            &disabled.intern("IPM.Note")
+        ));
+        assert!(disabled.is_empty());
+    }
+
+    #[test]
+    fn test_property_name() {
+        const EMPTY_PST: &[u8] = include_bytes!("../examples/Empty.pst");
+
+        let pst = UnicodePstFile::read_from(Box::new(Cursor::new(EMPTY_PST))).unwrap();
+        let store = UnicodeStore::read(Rc::new(pst)).unwrap();
+        let map = store.named_property_map().unwrap();
+        let map = map.properties();
+
+        let interner = StringInterner::new();
+        for prop_id in [0x8000, 0x8001, 0xFFFE] {
+            let expected = map.property_name(prop_id).unwrap();
+            let name = interner.property_name(map, prop_id).unwrap();
+            assert_eq!(name.as_deref(), expected.as_deref());
+            let cached = interner.property_name(map, prop_id).unwrap();
+            if let (Some(name), Some(cached)) = (name, cached) {
+                assert!(Rc::ptr_eq(&name, &cached));
+            }
+        }
+    }
+}
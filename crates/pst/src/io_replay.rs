@@ -0,0 +1,429 @@
+//! Record the reads which reach the underlying [`PstReader`] into a trace file, and replay them
+//! later without the original PST file, e.g. to reproduce a bug report about a damaged file.
+//!
+//! Wrap the reader in a [`RecordingReader`] and pass it to
+//! [`UnicodePstFile::read_from`](crate::UnicodePstFile::read_from) or
+//! [`AnsiPstFile::read_from`](crate::AnsiPstFile::read_from), run the code which fails, and save
+//! the [`ReadTrace`] with [`ReadRecording::save`]. Passing a [`ReplayReader`] for the saved trace
+//! to `read_from` then serves the same reads from the trace.
+//!
+//! The trace holds the offset, length and CRC of every read in order, and one copy of each range
+//! of bytes that was read. Only those bytes are shared, but they may still include the contents
+//! of the messages which were read, so check what the failing code reads before sharing a trace.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{crc::compute_crc, PstReader};
+
+const READ_TRACE_MAGIC: &[u8; 8] = b"PSTREAD1";
+
+/// A single read from the underlying [`PstReader`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecordedRead {
+    offset: u64,
+    length: u32,
+    crc: u32,
+}
+
+impl RecordedRead {
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+
+    /// CRC of the bytes which were read, see [`ReadTrace::verify`].
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+}
+
+/// The reads recorded by a [`RecordingReader`], with the bytes they returned.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReadTrace {
+    file_size: u64,
+    reads: Vec<RecordedRead>,
+    /// Non-overlapping ranges of bytes keyed by their offset. Adjacent ranges are merged.
+    extents: BTreeMap<u64, Vec<u8>>,
+}
+
+impl ReadTrace {
+    /// Size of the file when the [`RecordingReader`] was created, which a [`ReplayReader`] uses
+    /// for [`SeekFrom::End`].
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Every read in the order it was made.
+    pub fn reads(&self) -> &[RecordedRead] {
+        &self.reads
+    }
+
+    /// Number of distinct bytes held in the trace.
+    pub fn byte_count(&self) -> u64 {
+        self.extents.values().map(|data| data.len() as u64).sum()
+    }
+
+    /// The bytes at `offset`, up to the end of the range of bytes which contains it.
+    fn bytes_at(&self, offset: u64) -> Option<&[u8]> {
+        let (start, data) = self.extents.range(..=offset).next_back()?;
+        let skip = usize::try_from(offset - start).ok()?;
+        data.get(skip..).filter(|data| !data.is_empty())
+    }
+
+    fn record(&mut self, offset: u64, data: &[u8]) {
+        self.reads.push(RecordedRead {
+            offset,
+            length: data.len() as u32,
+            crc: compute_crc(0, data),
+        });
+
+        // Merge the new bytes with every range they overlap or touch.
+        let mut start = offset;
+        let mut merged = data.to_vec();
+        let end = offset + data.len() as u64;
+        if let Some((&prev_start, prev)) = self.extents.range(..=offset).next_back() {
+            let prev_end = prev_start + prev.len() as u64;
+            if prev_end >= offset {
+                let mut combined = prev.clone();
+                combined.truncate((offset - prev_start) as usize);
+                combined.extend_from_slice(&merged);
+                if prev_end > end {
+                    combined.extend_from_slice(&prev[(end - prev_start) as usize..]);
+                }
+                start = prev_start;
+                merged = combined;
+                self.extents.remove(&prev_start);
+            }
+        }
+        let following: Vec<_> = self
+            .extents
+            .range(offset..=end)
+            .map(|(&next_start, _)| next_start)
+            .collect();
+        for next_start in following {
+            let next = self.extents.remove(&next_start).unwrap_or_default();
+            let next_end = next_start + next.len() as u64;
+            let merged_end = start + merged.len() as u64;
+            if next_end > merged_end {
+                merged.extend_from_slice(&next[(merged_end - next_start) as usize..]);
+            }
+        }
+        self.extents.insert(start, merged);
+    }
+
+    /// Check the CRC of every recorded read against the bytes in the trace. Fails with
+    /// [`io::ErrorKind::InvalidData`] if the bytes of a read are missing or do not match.
+    pub fn verify(&self) -> io::Result<()> {
+        for read in self.reads.iter() {
+            let data = self
+                .bytes_at(read.offset)
+                .and_then(|data| data.get(..read.length as usize))
+                .ok_or(io::ErrorKind::InvalidData)?;
+            if compute_crc(0, data) != read.crc {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read(f: &mut dyn Read) -> io::Result<Self> {
+        let mut magic = [0; READ_TRACE_MAGIC.len()];
+        f.read_exact(&mut magic)?;
+        if magic != *READ_TRACE_MAGIC {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        let file_size = f.read_u64::<LittleEndian>()?;
+
+        let read_count = f.read_u32::<LittleEndian>()?;
+        let mut reads = vec![];
+        for _ in 0..read_count {
+            let offset = f.read_u64::<LittleEndian>()?;
+            let length = f.read_u32::<LittleEndian>()?;
+            let crc = f.read_u32::<LittleEndian>()?;
+            reads.push(RecordedRead {
+                offset,
+                length,
+                crc,
+            });
+        }
+
+        let extent_count = f.read_u32::<LittleEndian>()?;
+        let mut extents = BTreeMap::new();
+        let mut next_offset = 0;
+        for _ in 0..extent_count {
+            let offset = f.read_u64::<LittleEndian>()?;
+            if offset < next_offset {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            let size = u64::from(f.read_u32::<LittleEndian>()?);
+            let mut data = vec![];
+            if f.take(size).read_to_end(&mut data)? as u64 != size {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            next_offset = offset + size;
+            extents.insert(offset, data);
+        }
+
+        Ok(Self {
+            file_size,
+            reads,
+            extents,
+        })
+    }
+
+    pub fn write(&self, f: &mut dyn Write) -> io::Result<()> {
+        let invalid_input = |_| io::Error::from(io::ErrorKind::InvalidInput);
+
+        f.write_all(READ_TRACE_MAGIC)?;
+        f.write_u64::<LittleEndian>(self.file_size)?;
+
+        f.write_u32::<LittleEndian>(u32::try_from(self.reads.len()).map_err(invalid_input)?)?;
+        for read in self.reads.iter() {
+            f.write_u64::<LittleEndian>(read.offset)?;
+            f.write_u32::<LittleEndian>(read.length)?;
+            f.write_u32::<LittleEndian>(read.crc)?;
+        }
+
+        f.write_u32::<LittleEndian>(u32::try_from(self.extents.len()).map_err(invalid_input)?)?;
+        for (offset, data) in self.extents.iter() {
+            f.write_u64::<LittleEndian>(*offset)?;
+            f.write_u32::<LittleEndian>(u32::try_from(data.len()).map_err(invalid_input)?)?;
+            f.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    /// Read a trace file which was written with [`ReadRecording::save`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+/// Shared handle to the [`ReadTrace`] recorded by a [`RecordingReader`]. Cloning it shares the
+/// same trace.
+#[derive(Clone, Default)]
+pub struct ReadRecording {
+    trace: Arc<Mutex<ReadTrace>>,
+}
+
+impl ReadRecording {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// A copy of the reads recorded so far.
+    pub fn trace(&self) -> ReadTrace {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ReadTrace> {
+        self.trace.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Write the reads recorded so far to a trace file at `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.lock().write(&mut writer)?;
+        writer.flush()
+    }
+}
+
+/// Wrap a [`PstReader`] to record each read, and the bytes it returned, in a [`ReadRecording`].
+pub struct RecordingReader<R>
+where
+    R: PstReader,
+{
+    reader: R,
+    position: u64,
+    recording: ReadRecording,
+}
+
+impl<R> RecordingReader<R>
+where
+    R: PstReader,
+{
+    pub fn new(mut reader: R, recording: ReadRecording) -> io::Result<Self> {
+        let position = reader.stream_position()?;
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(position))?;
+        recording.lock().file_size = file_size;
+        Ok(Self {
+            reader,
+            position,
+            recording,
+        })
+    }
+
+    pub fn recording(&self) -> &ReadRecording {
+        &self.recording
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R> Read for RecordingReader<R>
+where
+    R: PstReader,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.reader.read(buf)?;
+        if length > 0 {
+            self.recording.lock().record(self.position, &buf[..length]);
+        }
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+impl<R> Seek for RecordingReader<R>
+where
+    R: PstReader,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.reader.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+/// Serve reads from a [`ReadTrace`] instead of a file. Reading bytes which were not recorded
+/// fails with [`io::ErrorKind::NotFound`], and reading at the end of the recorded file returns
+/// no bytes.
+pub struct ReplayReader {
+    trace: ReadTrace,
+    position: u64,
+}
+
+impl ReplayReader {
+    pub fn new(trace: ReadTrace) -> Self {
+        Self { trace, position: 0 }
+    }
+
+    /// Load and [verify](ReadTrace::verify) the trace file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let trace = ReadTrace::load(path)?;
+        trace.verify()?;
+        Ok(Self::new(trace))
+    }
+
+    pub fn trace(&self) -> &ReadTrace {
+        &self.trace
+    }
+}
+
+impl Read for ReplayReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.trace.file_size {
+            return Ok(0);
+        }
+        let data = self.trace.bytes_at(self.position).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Offset 0x{:X} was not recorded in the trace", self.position),
+            )
+        })?;
+        let length = buf.len().min(data.len());
+        buf[..length].copy_from_slice(&data[..length]);
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+impl Seek for ReplayReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.trace.file_size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or(io::ErrorKind::InvalidInput)?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{messaging::store::*, UnicodePstFile};
+    use std::{io::Cursor, rc::Rc};
+
+    #[test]
+    fn test_extents_merge() {
+        let mut trace = ReadTrace::default();
+        trace.record(10, &[1, 2, 3]);
+        trace.record(20, &[7, 8]);
+        trace.record(12, &[3, 4, 5, 6, 7, 8, 9, 10]);
+        trace.record(0, &[0; 4]);
+        assert_eq!(trace.extents.len(), 2);
+        assert_eq!(
+            trace.bytes_at(10),
+            Some(&[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 7, 8][..])
+        );
+        assert_eq!(trace.bytes_at(21), Some(&[8][..]));
+        assert_eq!(trace.bytes_at(5), None);
+        assert_eq!(trace.reads().len(), 4);
+        trace.verify().unwrap();
+
+        // A later read which returned different bytes replaces the earlier ones.
+        trace.record(1, &[9]);
+        assert_eq!(trace.bytes_at(0), Some(&[0, 9, 0, 0][..]));
+        assert!(trace.verify().is_err());
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        const EMPTY_PST: &[u8] = include_bytes!("../examples/Empty.pst");
+
+        let recording = ReadRecording::new();
+        let reader = RecordingReader::new(Cursor::new(EMPTY_PST), recording.clone()).unwrap();
+        let pst = UnicodePstFile::read_from(Box::new(reader)).unwrap();
+        let store = UnicodeStore::read(Rc::new(pst)).unwrap();
+        let display_name = store.properties().display_name().unwrap();
+        let root_folders = store
+            .root_folder()
+            .unwrap()
+            .hierarchy_table()
+            .map(|table| table.rows_matrix().count());
+
+        let trace = recording.trace();
+        assert_eq!(trace.file_size(), EMPTY_PST.len() as u64);
+        assert!(trace.byte_count() < EMPTY_PST.len() as u64);
+        trace.verify().unwrap();
+
+        let mut data = vec![];
+        trace.write(&mut data).unwrap();
+        let trace = ReadTrace::read(&mut Cursor::new(data)).unwrap();
+        let pst = UnicodePstFile::read_from(Box::new(ReplayReader::new(trace))).unwrap();
+        let store = UnicodeStore::read(Rc::new(pst)).unwrap();
+        assert_eq!(store.properties().display_name().unwrap(), display_name);
+        assert_eq!(
+            store
+                .root_folder()
+                .unwrap()
+                .hierarchy_table()
+                .map(|table| table.rows_matrix().count()),
+            root_folders
+        );
+
+        // Anything which was not read while recording is missing from the trace.
+        let mut reader = ReplayReader::new(recording.trace());
+        reader.seek(SeekFrom::End(-1)).unwrap();
+        let mut buf = [0; 1];
+        assert_eq!(
+            reader.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
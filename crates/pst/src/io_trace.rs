@@ -0,0 +1,281 @@
+//! Opt-in tracing of the reads which reach the underlying [`PstReader`], to help with tuning the
+//! page and block caches.
+//!
+//! Wrap the reader in an [`IoAccessTraceReader`] and pass it to
+//! [`UnicodePstFile::read_from`](crate::UnicodePstFile::read_from) or
+//! [`AnsiPstFile::read_from`](crate::AnsiPstFile::read_from). Every read is emitted as a
+//! `PstIoAccess` [`tracing`] event, recorded in the shared [`IoAccessTrace`], and passed to the
+//! callback if there is one. Call [`IoAccessTrace::summary`] to get the seek distance histogram and
+//! the byte counts for each purpose.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io::{self, Read, Seek, SeekFrom},
+    rc::Rc,
+};
+use tracing::trace;
+
+use crate::PstReader;
+
+/// The purpose tag used till [`IoAccessTrace::set_purpose`] is called.
+pub const DEFAULT_IO_PURPOSE: &str = "unspecified";
+
+/// A single read from the underlying [`PstReader`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoAccess {
+    offset: u64,
+    length: usize,
+    purpose: &'static str,
+}
+
+impl IoAccess {
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn purpose(&self) -> &'static str {
+        self.purpose
+    }
+}
+
+type IoAccessCallback = Box<dyn FnMut(&IoAccess)>;
+
+struct IoAccessTraceState {
+    purpose: &'static str,
+    accesses: Vec<IoAccess>,
+    callback: Option<IoAccessCallback>,
+}
+
+/// Shared handle to the accesses recorded by an [`IoAccessTraceReader`]. Cloning it shares the
+/// same trace.
+#[derive(Clone)]
+pub struct IoAccessTrace {
+    state: Rc<RefCell<IoAccessTraceState>>,
+}
+
+impl Default for IoAccessTrace {
+    fn default() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(IoAccessTraceState {
+                purpose: DEFAULT_IO_PURPOSE,
+                accesses: Default::default(),
+                callback: None,
+            })),
+        }
+    }
+}
+
+impl IoAccessTrace {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Invoke `callback` for every access as well as recording it.
+    pub fn with_callback(callback: impl FnMut(&IoAccess) + 'static) -> Self {
+        let trace = Self::new();
+        trace.state.borrow_mut().callback = Some(Box::new(callback));
+        trace
+    }
+
+    /// Tag all of the following accesses with `purpose`, e.g. `"hierarchy"` or `"export"`, till
+    /// it is changed again.
+    pub fn set_purpose(&self, purpose: &'static str) {
+        self.state.borrow_mut().purpose = purpose;
+    }
+
+    pub fn accesses(&self) -> Vec<IoAccess> {
+        self.state.borrow().accesses.clone()
+    }
+
+    pub fn clear(&self) {
+        self.state.borrow_mut().accesses.clear();
+    }
+
+    pub fn summary(&self) -> IoAccessSummary {
+        IoAccessSummary::new(&self.state.borrow().accesses)
+    }
+
+    fn record(&self, offset: u64, length: usize) {
+        let mut state = self.state.borrow_mut();
+        let access = IoAccess {
+            offset,
+            length,
+            purpose: state.purpose,
+        };
+
+        trace!(
+            name: "PstIoAccess",
+            offset,
+            length,
+            purpose = access.purpose,
+        );
+
+        if let Some(callback) = state.callback.as_mut() {
+            callback(&access);
+        }
+        state.accesses.push(access);
+    }
+}
+
+/// Wrap a [`PstReader`] to record each read in an [`IoAccessTrace`].
+pub struct IoAccessTraceReader<R>
+where
+    R: PstReader,
+{
+    reader: R,
+    position: u64,
+    trace: IoAccessTrace,
+}
+
+impl<R> IoAccessTraceReader<R>
+where
+    R: PstReader,
+{
+    pub fn new(mut reader: R, trace: IoAccessTrace) -> io::Result<Self> {
+        let position = reader.stream_position()?;
+        Ok(Self {
+            reader,
+            position,
+            trace,
+        })
+    }
+
+    pub fn trace(&self) -> &IoAccessTrace {
+        &self.trace
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R> Read for IoAccessTraceReader<R>
+where
+    R: PstReader,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.reader.read(buf)?;
+        if length > 0 {
+            self.trace.record(self.position, length);
+        }
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+impl<R> Seek for IoAccessTraceReader<R>
+where
+    R: PstReader,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = self.reader.seek(pos)?;
+        Ok(self.position)
+    }
+}
+
+/// Aggregate statistics for a sequence of [`IoAccess`] records.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IoAccessSummary {
+    read_count: u64,
+    byte_count: u64,
+    seek_histogram: BTreeMap<u32, u64>,
+    bytes_by_purpose: BTreeMap<&'static str, u64>,
+}
+
+impl IoAccessSummary {
+    pub fn new(accesses: &[IoAccess]) -> Self {
+        let mut summary = Self::default();
+        let mut next_offset = None;
+
+        for access in accesses {
+            summary.read_count += 1;
+            summary.byte_count += access.length as u64;
+            *summary.bytes_by_purpose.entry(access.purpose).or_default() += access.length as u64;
+
+            if let Some(next_offset) = next_offset {
+                let distance = access.offset.abs_diff(next_offset);
+                *summary
+                    .seek_histogram
+                    .entry(Self::seek_bucket(distance))
+                    .or_default() += 1;
+            }
+            next_offset = Some(access.offset + access.length as u64);
+        }
+
+        summary
+    }
+
+    /// Bucket 0 holds sequential reads, and bucket `n > 0` holds seeks of at least `2^(n-1)` and
+    /// less than `2^n` bytes in either direction.
+    pub fn seek_bucket(distance: u64) -> u32 {
+        u64::BITS - distance.leading_zeros()
+    }
+
+    pub fn read_count(&self) -> u64 {
+        self.read_count
+    }
+
+    pub fn byte_count(&self) -> u64 {
+        self.byte_count
+    }
+
+    /// Number of reads in each [seek bucket](Self::seek_bucket), measured from the end of the
+    /// previous read.
+    pub fn seek_histogram(&self) -> &BTreeMap<u32, u64> {
+        &self.seek_histogram
+    }
+
+    pub fn bytes_by_purpose(&self) -> &BTreeMap<&'static str, u64> {
+        &self.bytes_by_purpose
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_seek_bucket() {
+        assert_eq!(IoAccessSummary::seek_bucket(0), 0);
+        assert_eq!(IoAccessSummary::seek_bucket(1), 1);
+        assert_eq!(IoAccessSummary::seek_bucket(2), 2);
+        assert_eq!(IoAccessSummary::seek_bucket(3), 2);
+        assert_eq!(IoAccessSummary::seek_bucket(512), 10);
+    }
+
+    #[test]
+    fn test_trace_summary() {
+        let trace = IoAccessTrace::new();
+        let mut reader = IoAccessTraceReader::new(Cursor::new(vec![0_u8; 4096]), trace.clone())
+            .expect("Failed to wrap reader");
+        let mut buffer = [0_u8; 16];
+
+        reader.read_exact(&mut buffer).expect("Failed to read");
+        reader.read_exact(&mut buffer).expect("Failed to read");
+        trace.set_purpose("block");
+        reader.seek(SeekFrom::Start(1024)).expect("Failed to seek");
+        reader.read_exact(&mut buffer).expect("Failed to read");
+
+        let summary = trace.summary();
+        assert_eq!(summary.read_count(), 3);
+        assert_eq!(summary.byte_count(), 48);
+        assert_eq!(summary.seek_histogram().get(&0), Some(&1));
+        assert_eq!(
+            summary
+                .seek_histogram()
+                .get(&IoAccessSummary::seek_bucket(1024 - 32)),
+            Some(&1)
+        );
+        assert_eq!(
+            summary.bytes_by_purpose().get(DEFAULT_IO_PURPOSE),
+            Some(&32)
+        );
+        assert_eq!(summary.bytes_by_purpose().get("block"), Some(&16));
+    }
+}
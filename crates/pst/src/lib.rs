@@ -1,32 +1,78 @@
 #![doc = include_str!("../README.md")]
 
 use std::{
-    cell::RefMut,
+    cmp,
+    collections::{BTreeMap, BTreeSet, HashSet},
     fmt::Debug,
     fs::{File, OpenOptions},
     io::{self, BufWriter, Read, Seek, SeekFrom, Write},
-    mem,
+    iter, mem,
+    ops::Range,
     path::Path,
     rc::Rc,
-    sync::Mutex,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Instant,
 };
 use thiserror::Error;
 use tracing::{error, instrument, warn};
 
+pub mod block_sig;
+pub mod clock;
+pub mod crc;
+pub mod encode;
+pub mod export;
+pub mod facade;
+pub mod flush;
+pub mod free_space;
+pub mod info;
+pub mod intern;
+pub mod io_replay;
+pub mod io_trace;
 pub mod ltp;
 pub mod messaging;
 pub mod ndb;
-
-mod block_sig;
-mod crc;
-mod encode;
-
-use ltp::{heap::*, prop_context::*, table_context::*, tree::*};
-use messaging::{folder::*, message::*, named_prop::*, search::*, store::*};
+pub mod positioned;
+pub mod prefetch;
+pub mod quick_check;
+pub mod repair;
+pub mod report;
+pub mod time;
+pub mod validate;
+pub mod visit;
+
+mod binary_text;
+mod transaction;
+
+pub use transaction::TransactionReader;
+
+#[cfg(test)]
+mod temp_path;
+
+#[cfg(feature = "derive")]
+pub use outlook_pst_derive::FromProperties;
+
+use block_sig::block_signature;
+use clock::*;
+use crc::compute_crc;
+use encode::encode_block_data;
+use flush::*;
+use free_space::*;
+use transaction::*;
+
+use ltp::{heap::*, prop_context::*, prop_type::PropertyType, table_context::*, tree::*};
+use messaging::{
+    annotations::*, categories::*, folder::*, message::*, named_prop::*, object_id::*, scrub::*,
+    search::*, store::*, time_index::*, MessagingError,
+};
 use ndb::{
-    block::*, block_id::*, block_ref::*, byte_index::*, header::*, node_id::*, page::*,
-    read_write::*, root::*, *,
+    block::*, block_id::*, block_ref::*, byte_index::*, header::*, node_data::*, node_id::*,
+    page::*, read_write::*, root::*, *,
 };
+use positioned::{PositionedFile, ReadAt, SharedReader};
+use quick_check::*;
+use repair::*;
+use validate::*;
+use visit::*;
 
 #[derive(Error, Debug)]
 pub enum PstError {
@@ -48,6 +94,14 @@ pub enum PstError {
     AllocationMapPageNotFound(usize),
     #[error("Invalid BTree page: offset: 0x{0:X}")]
     InvalidBTreePage(u64),
+    #[error("Not enough free space in the allocation map: 0x{0:X} bytes")]
+    AllocationFailed(u64),
+    #[error("Offset is not covered by the allocation map: 0x{0:X}")]
+    InvalidAllocationMapOffset(u64),
+    #[error("Structural validation failed: {0}")]
+    StructuralValidation(StructuralIssue),
+    #[error("File would grow to 0x{0:X} bytes, which is more than the format allows")]
+    FileSizeLimit(u64),
 }
 
 impl From<&PstError> for io::Error {
@@ -88,11 +142,60 @@ trait PstFileLock<Pst>
 where
     Pst: PstFile,
 {
+    fn ndb_version(&self) -> NdbVersion;
     fn start_write(&mut self) -> io::Result<()>;
     fn finish_write(&mut self) -> io::Result<()>;
-
-    fn block_cache(&self) -> RefMut<'_, RootBTreePageCache<<Pst as PstFile>::BlockBTree>>;
-    fn node_cache(&self) -> RefMut<'_, RootBTreePageCache<<Pst as PstFile>::NodeBTree>>;
+    fn discard_write(&mut self) -> io::Result<()>;
+    fn pending_write_bytes(&self) -> u64;
+    /// The source which [`PstFile::reader`] reads committed data from.
+    fn source(&self) -> Arc<dyn ReadAt>;
+    /// Keep the space which a [`Store::snapshot`] might read from being freed till the returned
+    /// pin and every clone of it are dropped.
+    fn snapshot_pin(&self) -> SnapshotPin;
+
+    fn block_cache(&self) -> MutexGuard<'_, RootBTreePageCache<<Pst as PstFile>::BlockBTree>>;
+    fn node_cache(&self) -> MutexGuard<'_, RootBTreePageCache<<Pst as PstFile>::NodeBTree>>;
+
+    fn allocation_strategy(&self) -> AllocationStrategy;
+    fn set_allocation_strategy(&mut self, strategy: AllocationStrategy);
+    fn allocation_statistics(&self) -> io::Result<AllocationMapStatistics>;
+    fn allocation_map_densities(&self) -> io::Result<Vec<AllocationMapPageDensity>>;
+    fn check_free_space_maps(&self) -> io::Result<Vec<FreeSpaceMapMismatch>>;
+    fn rebuild_free_space_maps(&mut self) -> io::Result<()>;
+    fn validate_before_commit(&self) -> bool;
+    fn set_validate_before_commit(&mut self, validate: bool);
+    fn validate_structures(&self) -> io::Result<Vec<StructuralIssue>>;
+    fn grow(&mut self, size: u64) -> io::Result<()>;
+    fn allocate_nid(&mut self, id_type: NodeIdType) -> io::Result<NodeId>;
+    /// Advance `dwUnique` in the header and return it, to stamp the table rows being written.
+    fn allocate_row_version(&mut self) -> RowVersion;
+
+    fn read_node_data(&self, node: NodeId) -> io::Result<NodeData>;
+    fn read_node_parent(&self, node: NodeId) -> io::Result<Option<NodeId>>;
+    fn create_node(
+        &mut self,
+        node: NodeId,
+        parent: Option<NodeId>,
+        data: &NodeData,
+    ) -> io::Result<()>;
+    fn replace_node(&mut self, node: NodeId, data: &NodeData) -> io::Result<()>;
+    fn write_data_tree(&mut self, f: &mut dyn Read) -> io::Result<(Pst::BlockId, u32)>;
+    fn write_sub_node(&mut self, node: NodeId, sub_node: NodeId, data: &[u8]) -> io::Result<()>;
+    fn remove_sub_node(&mut self, node: NodeId, sub_node: NodeId) -> io::Result<()>;
+    fn copy_message(
+        &mut self,
+        message: NodeId,
+        folder: NodeId,
+        reuse_blocks: bool,
+    ) -> io::Result<NodeId>;
+    fn copy_node(
+        &mut self,
+        node: NodeId,
+        parent: Option<NodeId>,
+        reuse_blocks: bool,
+    ) -> io::Result<NodeId>;
+    fn delete_message(&mut self, message: NodeId) -> io::Result<()>;
+    fn fix_crcs(&mut self) -> io::Result<Vec<RepairedLocation>>;
 }
 
 /// This is the public interface for writing to a PST.
@@ -101,6 +204,10 @@ where
     Pst: PstFile,
 {
     pst: &'a mut dyn PstFileLock<Pst>,
+    auto_flush_policy: AutoFlushPolicy,
+    flush_metrics: FlushMetrics,
+    last_flush: Instant,
+    clock: Box<dyn Clock>,
 }
 
 impl<'a, Pst> PstFileLockGuard<'a, Pst>
@@ -109,810 +216,2617 @@ where
 {
     fn new(pst: &'a mut dyn PstFileLock<Pst>) -> io::Result<Self> {
         pst.start_write()?;
-        Ok(Self { pst })
+        Ok(Self {
+            pst,
+            auto_flush_policy: Default::default(),
+            flush_metrics: Default::default(),
+            last_flush: Instant::now(),
+            clock: Box::new(SystemClock),
+        })
     }
 
     /// Explicitly flush pending updates to the PST file. This will still happen implicitly when
     /// the [`PstFileLockGuard`] is dropped, but this allows you to handle errors.
+    ///
+    /// With [`Self::set_validate_before_commit`], this fails with
+    /// [`PstError::StructuralValidation`] instead of completing the transaction if
+    /// [`Self::validate_structures`] finds any issues. The pages and blocks written in the
+    /// transaction are discarded, and the file is left as it was when the lock was taken.
     #[instrument(skip_all)]
     pub fn flush(&mut self) -> io::Result<()> {
-        self.pst.finish_write().inspect_err(|err| {
-            error!(
-                name: "PstFinishWriteFailed",
-                ?err,
-                "PstFileLock::finish_write failed"
-            );
-        })?;
+        self.commit(false)
+    }
 
-        Ok(())
+    /// The [`AutoFlushPolicy`] for this lock, which never flushes implicitly by default.
+    pub fn auto_flush_policy(&self) -> AutoFlushPolicy {
+        self.auto_flush_policy
     }
-}
 
-impl<Pst> Drop for PstFileLockGuard<'_, Pst>
-where
-    Pst: PstFile,
-{
-    #[instrument(skip_all)]
-    fn drop(&mut self) {
-        if let Err(err) = self.flush() {
-            error!(
-                name: "PstFileLockGuardFlushFailed",
-                ?err,
-                "Writing to the PST file failed"
-            );
-        }
+    /// Flush implicitly when the [`AutoFlushPolicy`] is due. This is checked before each message
+    /// is created, updated, copied, or deleted, and each implicit flush commits the transaction
+    /// and starts a new one, so a crash only loses the writes since the last flush.
+    pub fn set_auto_flush_policy(&mut self, policy: AutoFlushPolicy) {
+        self.auto_flush_policy = policy;
     }
-}
 
-pub trait PstReader: Read + Seek {}
+    /// Bytes of pages and blocks which are held in memory till the next flush.
+    pub fn dirty_bytes(&self) -> u64 {
+        self.pst.pending_write_bytes()
+    }
 
-impl<T> PstReader for T where T: Read + Seek {}
+    pub fn flush_metrics(&self) -> FlushMetrics {
+        self.flush_metrics
+    }
 
-/// [PST File](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/6b57253b-0853-47bb-99bb-d4b8f78105f0)
-pub trait PstFile: Sized {
-    type BlockId: BlockId<Index = Self::BTreeKey> + BlockIdReadWrite;
-    type PageId: BlockId<Index = Self::BTreeKey> + BlockIdReadWrite;
-    type ByteIndex: ByteIndex + ByteIndexReadWrite;
-    type BlockRef: BlockRef<Block = Self::BlockId, Index = Self::ByteIndex> + BlockRefReadWrite;
-    type PageRef: BlockRef<Block = Self::PageId, Index = Self::ByteIndex> + BlockRefReadWrite;
-    type Root: Root<Self>;
-    type Header: Header<Self>;
-    type PageTrailer: PageTrailer<BlockId = Self::PageId> + PageTrailerReadWrite;
-    type BTreeKey: BTreeEntryKey;
-    type NodeBTreeEntry: NodeBTreeEntry<Block = Self::BlockId> + BTreeEntry<Key = Self::BTreeKey>;
-    type NodeBTree: NodeBTree<Self, Self::NodeBTreeEntry>;
-    type BlockBTreeEntry: BlockBTreeEntry<Block = Self::BlockRef> + BTreeEntry<Key = Self::BTreeKey>;
-    type BlockBTree: BlockBTree<Self, Self::BlockBTreeEntry>;
-    type BlockTrailer: BlockTrailer<BlockId = Self::BlockId>;
-    type AllocationMapPage: AllocationMapPage<Self>;
-    type AllocationPageMapPage: AllocationPageMapPage<Self>;
-    type FreeMapPage: FreeMapPage<Self>;
-    type FreePageMapPage: FreePageMapPage<Self>;
-    type DensityListPage: DensityListPage<Self>;
-    type DataTreeEntry: IntermediateTreeEntry + IntermediateDataTreeEntry<Self>;
-    type DataTreeBlock: IntermediateTreeBlock<
-        Header = DataTreeBlockHeader,
-        Entry = Self::DataTreeEntry,
-        Trailer = Self::BlockTrailer,
-    >;
-    type DataBlock: Block<Trailer = Self::BlockTrailer>;
-    type SubNodeTreeBlockHeader: IntermediateTreeHeader;
-    type SubNodeTreeBlock: IntermediateTreeBlock<
-        Header = Self::SubNodeTreeBlockHeader,
-        Entry = IntermediateSubNodeTreeEntry<Self::BlockId>,
-        Trailer = Self::BlockTrailer,
-    >;
-    type SubNodeBlock: IntermediateTreeBlock<
-        Header = Self::SubNodeTreeBlockHeader,
-        Entry = LeafSubNodeTreeEntry<Self::BlockId>,
-        Trailer = Self::BlockTrailer,
-    >;
-    type TableContext: TableContext;
-    type PropertyContext: PropertyContext;
-    type HeapNode: HeapNode;
-    type PropertyTree: HeapTree<Key = PropertyTreeRecordKey, Value = PropertyTreeRecordValue>;
-    type Store: Store;
-    type Folder: Folder;
-    type Message: Message;
-    type NamedPropertyMap: NamedPropertyMap;
-    type SearchUpdateQueue: SearchUpdateQueue;
+    /// Replace the [`SystemClock`] used for the `PidTagCreationTime` and
+    /// `PidTagLastModificationTime` of the messages written with this lock, e.g. with a
+    /// [`FixedClock`] in tests.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Box::new(clock);
+    }
 
-    fn header(&self) -> &Self::Header;
-    fn density_list(&self) -> Result<&dyn DensityListPage<Self>, &io::Error>;
-    fn reader(&self) -> &Mutex<Box<dyn PstReader>>;
-    fn lock(&mut self) -> io::Result<PstFileLockGuard<'_, Self>>;
+    /// Flush and start a new transaction if the [`AutoFlushPolicy`] is due.
+    #[instrument(skip_all)]
+    fn auto_flush(&mut self) -> io::Result<()> {
+        if !self
+            .auto_flush_policy
+            .is_due(self.dirty_bytes(), self.last_flush.elapsed())
+        {
+            return Ok(());
+        }
 
-    fn read_node(&self, node: NodeId) -> io::Result<Self::NodeBTreeEntry>;
-    fn read_block(&self, block: Self::BlockId) -> io::Result<Vec<u8>>;
-}
+        self.commit(true)?;
+        self.pst.start_write()
+    }
 
-struct PstFileInner<Pst>
-where
-    Pst: PstFile,
-{
-    reader: Mutex<Box<dyn PstReader>>,
-    writer: PstResult<Mutex<BufWriter<File>>>,
-    header: Pst::Header,
-    density_list: io::Result<Pst::DensityListPage>,
-    node_cache: NodeBTreePageCache<Pst>,
-    block_cache: BlockBTreePageCache<Pst>,
-}
+    fn commit(&mut self, implicit: bool) -> io::Result<()> {
+        let start = Instant::now();
+        let bytes = self.dirty_bytes();
+
+        if self.pst.validate_before_commit() {
+            let issues = self.validate_structures()?;
+            for issue in issues.iter() {
+                error!(
+                    name: "PstStructuralValidationFailed",
+                    %issue,
+                    "Structural validation failed before commit"
+                );
+            }
+            if let Some(issue) = issues.into_iter().next() {
+                self.pst.discard_write()?;
+                return Err(PstError::StructuralValidation(issue).into());
+            }
+        }
 
-pub struct UnicodePstFile {
-    inner: PstFileInner<Self>,
-}
+        self.pst.finish_write().inspect_err(|err| {
+            error!(
+                name: "PstFinishWriteFailed",
+                ?err,
+                "PstFileLock::finish_write failed"
+            );
+        })?;
 
-impl UnicodePstFile {
-    pub fn read_from(reader: Box<dyn PstReader>) -> io::Result<Self> {
-        let inner = PstFileInner::read_from(reader)?;
-        Ok(Self { inner })
+        self.flush_metrics.record(implicit, bytes, start.elapsed());
+        self.last_flush = Instant::now();
+        Ok(())
     }
 
-    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
-        let inner = PstFileInner::open(path)?;
-        Ok(Self { inner })
+    /// The [`AllocationStrategy`] used for new pages and blocks. This is remembered for the
+    /// lifetime of the PST file, not just this lock.
+    pub fn allocation_strategy(&self) -> AllocationStrategy {
+        self.pst.allocation_strategy()
     }
-}
 
-impl PstFileLock<UnicodePstFile> for UnicodePstFile {
-    fn start_write(&mut self) -> io::Result<()> {
-        self.inner.start_write()
+    pub fn set_allocation_strategy(&mut self, strategy: AllocationStrategy) {
+        self.pst.set_allocation_strategy(strategy);
     }
 
-    fn finish_write(&mut self) -> io::Result<()> {
-        self.inner.finish_write()
+    /// Measure the free space and fragmentation in the allocation map.
+    pub fn allocation_statistics(&self) -> io::Result<AllocationMapStatistics> {
+        self.pst.allocation_statistics()
     }
 
-    fn block_cache(&self) -> RefMut<'_, RootBTreePageCache<<Self as PstFile>::BlockBTree>> {
-        self.inner.block_cache.borrow_mut()
+    /// Derive the free space in each AMap page, which is what the header `rgbFM` entries and the
+    /// density list are built from.
+    pub fn allocation_map_densities(&self) -> io::Result<Vec<AllocationMapPageDensity>> {
+        self.pst.allocation_map_densities()
     }
 
-    fn node_cache(&self) -> RefMut<'_, RootBTreePageCache<<Self as PstFile>::NodeBTree>> {
-        self.inner.node_cache.borrow_mut()
+    /// Check the header `rgbFM` entries and the density list against the AMap pages, e.g. after
+    /// recovering from an interrupted write. An empty list means they are consistent.
+    pub fn check_free_space_maps(&self) -> io::Result<Vec<FreeSpaceMapMismatch>> {
+        self.pst.check_free_space_maps()
     }
-}
-
-impl PstFile for UnicodePstFile {
-    type BlockId = UnicodeBlockId;
-    type PageId = UnicodePageId;
-    type ByteIndex = UnicodeByteIndex;
-    type BlockRef = UnicodeBlockRef;
-    type PageRef = UnicodePageRef;
-    type Root = UnicodeRoot;
-    type Header = UnicodeHeader;
-    type PageTrailer = UnicodePageTrailer;
-    type BTreeKey = u64;
-    type NodeBTreeEntry = UnicodeNodeBTreeEntry;
-    type NodeBTree = UnicodeNodeBTree;
-    type BlockBTreeEntry = UnicodeBlockBTreeEntry;
-    type BlockBTree = UnicodeBlockBTree;
-    type BlockTrailer = UnicodeBlockTrailer;
-    type AllocationMapPage = UnicodeMapPage<{ PageType::AllocationMap as u8 }>;
-    type AllocationPageMapPage = UnicodeMapPage<{ PageType::AllocationPageMap as u8 }>;
-    type FreeMapPage = UnicodeMapPage<{ PageType::FreeMap as u8 }>;
-    type FreePageMapPage = UnicodeMapPage<{ PageType::FreePageMap as u8 }>;
-    type DensityListPage = UnicodeDensityListPage;
-    type DataTreeEntry = UnicodeDataTreeEntry;
-    type DataTreeBlock = UnicodeDataTreeBlock;
-    type DataBlock = UnicodeDataBlock;
-    type SubNodeTreeBlockHeader = UnicodeSubNodeTreeBlockHeader;
-    type SubNodeTreeBlock = UnicodeIntermediateSubNodeTreeBlock;
-    type SubNodeBlock = UnicodeLeafSubNodeTreeBlock;
-    type HeapNode = UnicodeHeapNode;
-    type PropertyTree = UnicodeHeapTree<PropertyTreeRecordKey, PropertyTreeRecordValue>;
-    type TableContext = UnicodeTableContext;
-    type PropertyContext = UnicodePropertyContext;
-    type Store = UnicodeStore;
-    type Folder = UnicodeFolder;
-    type Message = UnicodeMessage;
-    type NamedPropertyMap = UnicodeNamedPropertyMap;
-    type SearchUpdateQueue = UnicodeSearchUpdateQueue;
 
-    fn header(&self) -> &Self::Header {
-        &self.inner.header
+    /// Recompute the header `rgbFM` entries and the density list from the AMap pages. They are
+    /// written to the file when this lock is [flushed](Self::flush).
+    pub fn rebuild_free_space_maps(&mut self) -> io::Result<()> {
+        self.pst.rebuild_free_space_maps()
     }
 
-    fn density_list(&self) -> Result<&dyn DensityListPage<Self>, &io::Error> {
-        self.inner.density_list.as_ref().map(|dl| dl as _)
+    /// Whether [`Self::flush`] runs [`Self::validate_structures`] before committing the
+    /// transaction. This is remembered for the lifetime of the PST file, not just this lock.
+    pub fn validate_before_commit(&self) -> bool {
+        self.pst.validate_before_commit()
     }
 
-    fn reader(&self) -> &Mutex<Box<dyn PstReader>> {
-        &self.inner.reader
+    pub fn set_validate_before_commit(&mut self, validate: bool) {
+        self.pst.set_validate_before_commit(validate);
     }
 
-    fn lock(&mut self) -> io::Result<PstFileLockGuard<'_, Self>> {
-        PstFileLockGuard::new(self)
+    /// Check the structures written since the transaction started: the heap, BTH, and TC
+    /// structures of every node which was created or replaced, including its sub-nodes, and the
+    /// key ordering in every page of the Node BTree and Block BTree. An empty list means they are
+    /// consistent.
+    pub fn validate_structures(&self) -> io::Result<Vec<StructuralIssue>> {
+        self.pst.validate_structures()
     }
 
-    fn read_node(&self, node: NodeId) -> io::Result<UnicodeNodeBTreeEntry> {
-        self.inner.read_node(node)
+    /// Extend the PST file by at least `size` bytes of free space. This happens automatically
+    /// when an allocation does not fit in the existing file, but reserving space up front avoids
+    /// growing the file in many small steps.
+    pub fn grow(&mut self, size: u64) -> io::Result<()> {
+        self.pst.grow(size)
     }
 
-    fn read_block(&self, block: UnicodeBlockId) -> io::Result<Vec<u8>> {
-        self.inner.read_block(block)
+    /// Reserve the next [`NodeId`] of the given type from the counters in the header, skipping
+    /// any which are already in the Node BTree. The updated counters are written to the file
+    /// along with the rest of the header when this lock is [flushed](Self::flush).
+    pub fn allocate_nid(&mut self, id_type: NodeIdType) -> io::Result<NodeId> {
+        self.pst.allocate_nid(id_type)
     }
-}
 
-pub struct AnsiPstFile {
-    inner: PstFileInner<Self>,
-}
+    /// Write streams which may not fit in a single data block, e.g. attachment data or message
+    /// bodies, to new data trees.
+    pub fn data_tree_writer(&mut self) -> DataTreeWriter<'_, Pst> {
+        DataTreeWriter::new(self.pst)
+    }
 
-impl AnsiPstFile {
-    pub fn read_from(reader: Box<dyn PstReader>) -> io::Result<Self> {
-        let inner = PstFileInner::read_from(reader)?;
-        Ok(Self { inner })
+    /// Add or replace `sub_node` in the sub-node tree of `node`, e.g. the property context of an
+    /// attachment or the recipient table of a message. The sub-node tree is rewritten and
+    /// promoted from an SLBLOCK to an SIBLOCK when the entries no longer fit in one block.
+    pub fn write_sub_node(
+        &mut self,
+        node: NodeId,
+        sub_node: NodeId,
+        data: &[u8],
+    ) -> io::Result<()> {
+        self.pst.write_sub_node(node, sub_node, data)
     }
 
-    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
-        let inner = PstFileInner::open(path)?;
-        Ok(Self { inner })
+    /// Remove `sub_node` from the sub-node tree of `node`, and release the blocks it used.
+    pub fn remove_sub_node(&mut self, node: NodeId, sub_node: NodeId) -> io::Result<()> {
+        self.pst.remove_sub_node(node, sub_node)
     }
-}
 
-impl PstFileLock<AnsiPstFile> for AnsiPstFile {
-    fn start_write(&mut self) -> io::Result<()> {
-        self.inner.start_write()
+    /// Copy the node of a message, with its recipients and attachments, to a new [`NodeId`] whose
+    /// parent is `folder`. With `reuse_blocks`, the copy shares the existing blocks by adding a
+    /// reference to them in the Block BTree instead of duplicating the bytes, and either message
+    /// gets its own copy of the sub-nodes if they are changed later. The copy gets a new
+    /// `PidTagRecordKey` and `PidTagSearchKey` for its [`NodeId`].
+    ///
+    /// This works at the level of the NDB layer, so the caller is responsible for adding a row
+    /// for the new message to the contents table of `folder`.
+    pub fn copy_message(
+        &mut self,
+        message: MessageId,
+        folder: FolderId,
+        reuse_blocks: bool,
+    ) -> io::Result<MessageId> {
+        self.auto_flush()?;
+        let copy = self
+            .pst
+            .copy_message(message.into(), folder.into(), reuse_blocks)?;
+        self.regenerate_keys(copy)?;
+        Ok(MessageId::try_from(copy)?)
     }
 
-    fn finish_write(&mut self) -> io::Result<()> {
-        self.inner.finish_write()
+    /// Replace the `PidTagRecordKey` and `PidTagSearchKey` which a copy made by
+    /// [`PstFileLock::copy_node`] shares with the original, and return the new values of the ones
+    /// the node has.
+    fn regenerate_keys(&mut self, copy: NodeId) -> io::Result<BTreeMap<u16, PropertyValue>> {
+        let mut properties = read_property_context(&self.pst.read_node_data(copy)?)?;
+        let mut keys = BTreeMap::new();
+        set_default_message_properties(
+            &mut keys,
+            self.clock.now(),
+            &self.store_record_key()?,
+            copy,
+        );
+        keys.retain(|prop_id, _| {
+            matches!(*prop_id, 0x0FF9 | 0x300B) && properties.contains_key(prop_id)
+        });
+        if keys.is_empty() {
+            return Ok(keys);
+        }
+
+        properties.extend(keys.clone());
+        let data = build_property_context(properties.iter())?;
+        self.pst.replace_node(copy, &data)?;
+        Ok(keys)
     }
 
-    fn block_cache(&self) -> RefMut<'_, RootBTreePageCache<<Self as PstFile>::BlockBTree>> {
-        self.inner.block_cache.borrow_mut()
+    /// Remove the node of a message and release the blocks it used. Blocks shared with a copy
+    /// made by [`Self::copy_message`] are only freed once the copy is deleted as well.
+    ///
+    /// Like [`Self::copy_message`], this does not update the contents table of the folder.
+    pub fn delete_message(&mut self, message: MessageId) -> io::Result<()> {
+        self.auto_flush()?;
+        self.pst.delete_message(message.into())
     }
 
-    fn node_cache(&self) -> RefMut<'_, RootBTreePageCache<<Self as PstFile>::NodeBTree>> {
-        self.inner.node_cache.borrow_mut()
+    /// Clone `folder` and every normal folder beneath it to new [`NodeId`]s, with `parent` as the
+    /// parent of the new root folder, and return the new root folder. Like
+    /// [`Self::copy_message`] with `reuse_blocks`, each folder and message shares the blocks of
+    /// its sub-nodes with the original by adding a reference to them, so the clone takes up little
+    /// space till either copy is changed. The property context of each copy is rewritten with a
+    /// new `PidTagRecordKey` and `PidTagSearchKey`, and the hierarchy, contents and associated
+    /// contents tables are rewritten, since their rows list the new [`NodeId`]s.
+    ///
+    /// Search folders and rows which do not resolve to a node are left out of the clone. This
+    /// works at the level of the NDB layer, so the caller is responsible for adding a row for the
+    /// new root folder to the hierarchy table of `parent`, and for updating the counts and sizes
+    /// of `parent` and the store.
+    pub fn clone_subtree(&mut self, folder: FolderId, parent: FolderId) -> io::Result<FolderId> {
+        if folder.is_search_folder() {
+            return Err(MessagingError::InvalidFolderEntryIdType(NodeIdType::SearchFolder).into());
+        }
+        self.auto_flush()?;
+        let clone = self.clone_folder(folder.node_id(), parent.node_id())?;
+        Ok(FolderId::try_from(clone)?)
     }
-}
 
-impl PstFile for AnsiPstFile {
-    type BlockId = AnsiBlockId;
-    type PageId = AnsiPageId;
-    type ByteIndex = AnsiByteIndex;
-    type BlockRef = AnsiBlockRef;
-    type PageRef = AnsiPageRef;
-    type Root = AnsiRoot;
-    type Header = AnsiHeader;
-    type PageTrailer = AnsiPageTrailer;
-    type BTreeKey = u32;
-    type NodeBTreeEntry = AnsiNodeBTreeEntry;
-    type NodeBTree = AnsiNodeBTree;
-    type BlockBTreeEntry = AnsiBlockBTreeEntry;
-    type BlockBTree = AnsiBlockBTree;
-    type BlockTrailer = AnsiBlockTrailer;
-    type AllocationMapPage = AnsiMapPage<{ PageType::AllocationMap as u8 }>;
-    type AllocationPageMapPage = AnsiMapPage<{ PageType::AllocationPageMap as u8 }>;
-    type FreeMapPage = AnsiMapPage<{ PageType::FreeMap as u8 }>;
-    type FreePageMapPage = AnsiMapPage<{ PageType::FreePageMap as u8 }>;
-    type DensityListPage = AnsiDensityListPage;
-    type DataTreeEntry = AnsiDataTreeEntry;
-    type DataTreeBlock = AnsiDataTreeBlock;
-    type DataBlock = AnsiDataBlock;
-    type SubNodeTreeBlockHeader = AnsiSubNodeTreeBlockHeader;
-    type SubNodeTreeBlock = AnsiIntermediateSubNodeTreeBlock;
-    type SubNodeBlock = AnsiLeafSubNodeTreeBlock;
-    type HeapNode = AnsiHeapNode;
-    type PropertyTree = AnsiHeapTree<PropertyTreeRecordKey, PropertyTreeRecordValue>;
-    type TableContext = AnsiTableContext;
-    type PropertyContext = AnsiPropertyContext;
-    type Store = AnsiStore;
-    type Folder = AnsiFolder;
-    type Message = AnsiMessage;
-    type NamedPropertyMap = AnsiNamedPropertyMap;
-    type SearchUpdateQueue = AnsiSearchUpdateQueue;
+    fn clone_folder(&mut self, folder: NodeId, parent: NodeId) -> io::Result<NodeId> {
+        let clone = self.pst.copy_node(folder, Some(parent), true)?;
+        self.regenerate_keys(clone)?;
+
+        for table_type in [
+            NodeIdType::HierarchyTable,
+            NodeIdType::ContentsTable,
+            NodeIdType::AssociatedContentsTable,
+        ] {
+            let table = NodeId::new(table_type, folder.index())?;
+            let (columns, rows) = read_table_context(&self.pst.read_node_data(table)?)?;
+            let mut cloned_rows = TableRows::new();
+            for (row, mut values) in rows {
+                let node = NodeId::from(u32::from(row));
+                if !self.row_resolves(row, table_type)? {
+                    continue;
+                }
+                let node = match table_type {
+                    NodeIdType::HierarchyTable => {
+                        if !matches!(node.id_type(), Ok(NodeIdType::NormalFolder)) {
+                            continue;
+                        }
+                        self.clone_folder(node, clone)?
+                    }
+                    _ => {
+                        let copy = self.pst.copy_node(node, Some(clone), true)?;
+                        for (prop_id, value) in self.regenerate_keys(copy)? {
+                            if let Some(column) = values.get_mut(&prop_id) {
+                                *column = value;
+                            }
+                        }
+                        copy
+                    }
+                };
+                cloned_rows.insert(TableRowId::new(u32::from(node)), values);
+            }
 
-    fn header(&self) -> &Self::Header {
-        &self.inner.header
+            let table_parent = self.pst.read_node_parent(table)?.map(|table_parent| {
+                if table_parent == folder {
+                    clone
+                } else {
+                    table_parent
+                }
+            });
+            let data = build_table_context(self.pst.ndb_version(), &columns, &cloned_rows)?;
+            self.pst
+                .create_node(NodeId::new(table_type, clone.index())?, table_parent, &data)?;
+        }
+
+        Ok(clone)
     }
 
-    fn density_list(&self) -> Result<&dyn DensityListPage<Self>, &io::Error> {
-        self.inner.density_list.as_ref().map(|dl| dl as _)
+    /// Read the data tree and, recursively, the sub-node tree of `node` into memory, e.g. to
+    /// decode a table context with [`read_table_context`] while the file is locked for writing.
+    pub fn read_node_data(&self, node: NodeId) -> io::Result<NodeData> {
+        self.pst.read_node_data(node)
     }
 
-    fn reader(&self) -> &Mutex<Box<dyn PstReader>> {
-        &self.inner.reader
+    /// Add a new node with `data` and all of its sub-nodes to the Node BTree. The caller is
+    /// responsible for allocating the [`NodeId`] and for adding the node to any tables which
+    /// should list it.
+    pub fn create_node(
+        &mut self,
+        node: NodeId,
+        parent: Option<NodeId>,
+        data: &NodeData,
+    ) -> io::Result<()> {
+        self.pst.create_node(node, parent, data)
     }
 
-    fn lock(&mut self) -> io::Result<PstFileLockGuard<'_, Self>> {
-        PstFileLockGuard::new(self)
+    /// Replace the data tree of `node` and the sub-nodes in `data`. Existing sub-nodes which are
+    /// not in `data` are kept, e.g. the attachments of a message, except for the
+    /// [`NodeIdType::ListsTablesProperties`] sub-nodes which held values of the previous property
+    /// or table context.
+    pub fn replace_node(&mut self, node: NodeId, data: &NodeData) -> io::Result<()> {
+        self.pst.replace_node(node, data)
     }
 
-    fn read_node(&self, node: NodeId) -> io::Result<AnsiNodeBTreeEntry> {
-        self.inner.read_node(node)
+    /// Replace the [`NID_MESSAGE_STORE`] property context with the updated [`StorePropertiesMut`].
+    #[instrument(skip_all)]
+    pub fn update_store_properties(&mut self, properties: &StorePropertiesMut) -> io::Result<()> {
+        let data = build_property_context(properties.properties().iter())?;
+        self.pst.replace_node(NID_MESSAGE_STORE, &data)
     }
 
-    fn read_block(&self, block: AnsiBlockId) -> io::Result<Vec<u8>> {
-        self.inner.read_block(block)
-    }
-}
-
-const AMAP_FIRST_OFFSET: u64 = 0x4400;
-const AMAP_DATA_SIZE: u64 = size_of::<MapBits>() as u64 * 8 * 64;
-
-const PMAP_FIRST_OFFSET: u64 = AMAP_FIRST_OFFSET + PAGE_SIZE as u64;
-const PMAP_PAGE_COUNT: u64 = 8;
-const PMAP_DATA_SIZE: u64 = AMAP_DATA_SIZE * PMAP_PAGE_COUNT;
-
-const FMAP_FIRST_SIZE: u64 = 128;
-const FMAP_FIRST_DATA_SIZE: u64 = AMAP_DATA_SIZE * FMAP_FIRST_SIZE;
-const FMAP_FIRST_OFFSET: u64 = AMAP_FIRST_OFFSET + FMAP_FIRST_DATA_SIZE + (2 * PAGE_SIZE) as u64;
-const FMAP_PAGE_COUNT: u64 = size_of::<MapBits>() as u64;
-const FMAP_DATA_SIZE: u64 = AMAP_DATA_SIZE * FMAP_PAGE_COUNT;
+    /// Replace the property context of `message` with the properties in the [`MessageBuilder`],
+    /// and write its recipients to the [`NID_RECIPIENT_TABLE`] sub-node.
+    ///
+    /// The attachments of the message are left unchanged, so any attachments added to the
+    /// [`MessageBuilder`] are ignored. This does not update the row for the message in the
+    /// contents table of its folder, but the change in size is added to the
+    /// `PidTagMessageSizeExtended` of the folder and the message store.
+    ///
+    /// `PidTagLastModificationTime` is set from the clock, see [`Self::set_clock`], unless the
+    /// [`MessageBuilder`] changes it to something else.
+    #[instrument(skip_all)]
+    pub fn update_message(
+        &mut self,
+        message: MessageId,
+        builder: &MessageBuilder,
+    ) -> io::Result<()> {
+        self.auto_flush()?;
+        let message = NodeId::from(message);
+        let mut properties = builder.properties().clone();
+        let previous = read_property_context(&self.pst.read_node_data(message)?)?;
+        let modified = match (properties.get(&0x3008), previous.get(&0x3008)) {
+            (None, _) => true,
+            (Some(PropertyValue::Time(time)), Some(PropertyValue::Time(previous))) => {
+                time == previous
+            }
+            _ => false,
+        };
+        if modified {
+            properties.insert(0x3008, PropertyValue::from(self.clock.now()));
+        }
 
-const FPMAP_FIRST_SIZE: u64 = 128 * 64;
-const FPMAP_FIRST_DATA_SIZE: u64 = AMAP_DATA_SIZE * FPMAP_FIRST_SIZE;
-const FPMAP_FIRST_OFFSET: u64 = AMAP_FIRST_OFFSET + FPMAP_FIRST_DATA_SIZE + (3 * PAGE_SIZE) as u64;
-const FPMAP_PAGE_COUNT: u64 = size_of::<MapBits>() as u64 * 64;
-const FPMAP_DATA_SIZE: u64 = AMAP_DATA_SIZE * FPMAP_PAGE_COUNT;
+        let recipients = build_table_context(
+            self.pst.ndb_version(),
+            &builder.recipient_columns(),
+            builder.recipients(),
+        )?;
+        let mut data = build_property_context(properties.iter())?;
+        data.insert_sub_node(NID_RECIPIENT_TABLE, recipients);
 
-struct AllocationMapPageInfo<Pst>
-where
-    Pst: PstFile,
-    <Pst as PstFile>::AllocationMapPage: AllocationMapPageReadWrite<Pst>,
-{
-    amap_page: <Pst as PstFile>::AllocationMapPage,
-    free_space: u64,
-}
+        let previous_size = self.message_size(message)?;
+        self.pst.replace_node(message, &data)?;
 
-impl<Pst> AllocationMapPageInfo<Pst>
-where
-    Pst: PstFile,
-    <Pst as PstFile>::AllocationMapPage: AllocationMapPageReadWrite<Pst>,
-{
-    fn max_free_slots(&self) -> u8 {
-        u8::try_from(self.amap_page.find_free_bits(0xFF).len()).unwrap_or(0xFF)
+        let size = self.message_size(message)?;
+        if let Some(folder) = self.pst.read_node_parent(message)? {
+            self.add_message_size(folder, size - previous_size)?;
+        }
+        self.add_message_size(NID_MESSAGE_STORE, size - previous_size)?;
+        Ok(())
     }
-}
-
-type PstFileReadWriteBTree<Pst, BTree> = RootBTreePage<
-    Pst,
-    <BTree as RootBTree>::Entry,
-    <BTree as RootBTree>::IntermediatePage,
-    <BTree as RootBTree>::LeafPage,
->;
-
-type PstFileReadWriteNodeBTree<Pst> = PstFileReadWriteBTree<Pst, <Pst as PstFile>::NodeBTree>;
 
-type PstFileReadWriteBlockBTree<Pst> = PstFileReadWriteBTree<Pst, <Pst as PstFile>::BlockBTree>;
+    /// Remove every attachment of `message`, and return how many there were. The attachment
+    /// sub-nodes and the attachment table are released, `mfHasAttach` is cleared, and the number
+    /// of attachments is recorded in the [`SCRUBBED_ATTACHMENTS_NAME`] named property, see
+    /// [`ScrubTombstone`].
+    ///
+    /// The `PidTagMessageSize` of the message, the row for it in the contents table of its
+    /// folder, and the `PidTagMessageSizeExtended` of the folder and the message store are
+    /// updated. Nothing is committed until the guard is flushed.
+    #[instrument(skip_all)]
+    pub fn strip_attachments(&mut self, message: MessageId) -> io::Result<usize> {
+        self.auto_flush()?;
+        let message = NodeId::from(message);
+        let previous_size = self.message_size(message)?;
+        let data = self.pst.read_node_data(message)?;
+        let mut properties = read_property_context(&data)?;
+
+        let attachments: Vec<_> = data
+            .sub_nodes()
+            .keys()
+            .filter(|sub_node| matches!(sub_node.id_type(), Ok(NodeIdType::Attachment)))
+            .copied()
+            .collect();
+        for sub_node in attachments.iter() {
+            self.pst.remove_sub_node(message, *sub_node)?;
+        }
+        if data.sub_nodes().contains_key(&NID_ATTACHMENT_TABLE) {
+            self.pst.remove_sub_node(message, NID_ATTACHMENT_TABLE)?;
+        }
 
-impl<Pst> PstFileInner<Pst>
-where
-    Pst: PstFile + PstFileLock<Pst>,
-    <Pst as PstFile>::BlockId: BlockId<Index = <Pst as PstFile>::BTreeKey>
-        + From<<<Pst as PstFile>::ByteIndex as ByteIndex>::Index>
-        + Debug,
-    <Pst as PstFile>::PageId: From<<<Pst as PstFile>::ByteIndex as ByteIndex>::Index> + Debug,
-    <Pst as PstFile>::ByteIndex: ByteIndex<Index: TryFrom<u64>> + Debug,
-    <Pst as PstFile>::BlockRef: Debug,
-    <Pst as PstFile>::PageRef: Debug,
-    <Pst as PstFile>::Root: RootReadWrite<Pst>,
-    <Pst as PstFile>::Header: HeaderReadWrite<Pst>,
-    <Pst as PstFile>::DensityListPage: DensityListPageReadWrite<Pst>,
-    <Pst as PstFile>::PageTrailer: PageTrailerReadWrite,
-    <Pst as PstFile>::BTreeKey: BTreePageKeyReadWrite,
-    <Pst as PstFile>::NodeBTreeEntry: NodeBTreeEntryReadWrite,
-    <Pst as PstFile>::NodeBTree: NodeBTreeReadWrite<Pst, <Pst as PstFile>::NodeBTreeEntry>,
-    <<Pst as PstFile>::NodeBTree as RootBTree>::IntermediatePage:
-        RootBTreeIntermediatePageReadWrite<
-            Pst,
-            <Pst as PstFile>::NodeBTreeEntry,
-            <<Pst as PstFile>::NodeBTree as RootBTree>::LeafPage,
-        >,
-    <<<Pst as PstFile>::NodeBTree as RootBTree>::IntermediatePage as BTreePage>::Entry:
-        BTreePageEntryReadWrite,
-    <<Pst as PstFile>::NodeBTree as RootBTree>::LeafPage: RootBTreeLeafPageReadWrite<Pst>,
-    <Pst as PstFile>::BlockBTreeEntry: BlockBTreeEntryReadWrite,
-    <Pst as PstFile>::BlockBTree: BlockBTreeReadWrite<Pst, <Pst as PstFile>::BlockBTreeEntry>,
-    <<Pst as PstFile>::BlockBTree as RootBTree>::IntermediatePage:
-        RootBTreeIntermediatePageReadWrite<
-            Pst,
-            <Pst as PstFile>::BlockBTreeEntry,
-            <<Pst as PstFile>::BlockBTree as RootBTree>::LeafPage,
-        >,
-    <<<Pst as PstFile>::BlockBTree as RootBTree>::IntermediatePage as BTreePage>::Entry:
-        BTreePageEntryReadWrite,
-    <<Pst as PstFile>::BlockBTree as RootBTree>::LeafPage: RootBTreeLeafPageReadWrite<Pst>,
-    <Pst as PstFile>::BlockTrailer: BlockTrailerReadWrite,
-    <Pst as PstFile>::AllocationMapPage: AllocationMapPageReadWrite<Pst>,
-    <Pst as PstFile>::AllocationPageMapPage: AllocationPageMapPageReadWrite<Pst>,
-    <Pst as PstFile>::FreeMapPage: FreeMapPageReadWrite<Pst>,
-    <Pst as PstFile>::FreePageMapPage: FreePageMapPageReadWrite<Pst>,
-    <Pst as PstFile>::DensityListPage: DensityListPageReadWrite<Pst>,
-    <Pst as PstFile>::DataTreeBlock: IntermediateTreeBlockReadWrite,
-    <Pst as PstFile>::DataTreeEntry:
-        IntermediateTreeEntryReadWrite + From<<Pst as PstFile>::BlockId>,
-    <Pst as PstFile>::DataBlock: BlockReadWrite + Clone,
-    <Pst as PstFile>::SubNodeTreeBlockHeader: SubNodeTreeBlockHeaderReadWrite,
-    <Pst as PstFile>::SubNodeTreeBlock: IntermediateTreeBlockReadWrite,
-    <<Pst as PstFile>::SubNodeTreeBlock as IntermediateTreeBlock>::Entry:
-        IntermediateTreeEntryReadWrite,
-    <Pst as PstFile>::SubNodeBlock: IntermediateTreeBlockReadWrite,
-    <<Pst as PstFile>::SubNodeBlock as IntermediateTreeBlock>::Entry:
-        IntermediateTreeEntryReadWrite,
-{
-    fn read_from(mut reader: Box<dyn PstReader>) -> io::Result<Self> {
-        let header = <<Pst as PstFile>::Header as HeaderReadWrite<Pst>>::read(&mut reader)?;
-        let density_list =
-            <<Pst as PstFile>::DensityListPage as DensityListPageReadWrite<Pst>>::read(&mut reader);
-        Ok(Self {
-            reader: Mutex::new(Box::new(reader)),
-            writer: Err(PstError::OpenedReadOnly),
-            header,
-            density_list,
-            node_cache: Default::default(),
-            block_cache: Default::default(),
-        })
+        strip_attachment_properties(&mut properties);
+        let count = i32::try_from(attachments.len()).unwrap_or(i32::MAX);
+        self.set_scrub_tombstone(
+            &mut properties,
+            SCRUBBED_ATTACHMENTS_NAME,
+            PropertyValue::Integer32(count),
+        )?;
+        self.replace_scrubbed_message(
+            message,
+            properties,
+            previous_size,
+            STRIPPED_ATTACHMENT_PROP_IDS,
+        )?;
+        Ok(attachments.len())
     }
 
-    fn open(path: impl AsRef<Path>) -> io::Result<Self> {
-        let reader = Box::new(File::open(&path)?);
-        let writer = OpenOptions::new()
-            .write(true)
-            .open(&path)
-            .map(BufWriter::new)
-            .map(Mutex::new)
-            .map_err(|_| PstError::NoWriteAccess(path.as_ref().display().to_string()));
-        Ok(Self {
-            writer,
-            ..Self::read_from(reader)?
-        })
+    /// Replace the body of `message` with `text` as `PidTagBody`, and remove the HTML and RTF
+    /// bodies and `PidTagPreview`, releasing the blocks which held them. The time is recorded in
+    /// the [`SCRUBBED_BODY_NAME`] named property, see [`ScrubTombstone`].
+    ///
+    /// The sizes and the contents table row are updated like [`Self::strip_attachments`], and
+    /// nothing is committed until the guard is flushed.
+    #[instrument(skip_all)]
+    pub fn replace_body(&mut self, message: MessageId, text: &str) -> io::Result<()> {
+        self.auto_flush()?;
+        let message = NodeId::from(message);
+        let previous_size = self.message_size(message)?;
+        let mut properties = read_property_context(&self.pst.read_node_data(message)?)?;
+
+        replace_body_properties(&mut properties, text);
+        let now = PropertyValue::from(self.clock.now());
+        self.set_scrub_tombstone(&mut properties, SCRUBBED_BODY_NAME, now)?;
+        let changed = [BODY_PROP_IDS, REPLACED_BODY_PROP_IDS].concat();
+        self.replace_scrubbed_message(message, properties, previous_size, &changed)
     }
 
-    /// Begin a transaction by rebuilding the allocation map if needed and initializing the density
-    /// list, then set [`AmapStatus::Invalid`] in the header till the transaction is finished.
-    ///
-    /// See also [Transactional Semantics](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/bc5a92df-7fc1-4dc2-9c7c-5677237dd73a).
-    fn start_write(&mut self) -> io::Result<()> {
-        self.rebuild_allocation_map()?;
-        self.ensure_density_list()?;
+    /// Map the tombstone `name` in [`PS_PUBLIC_STRINGS`] to a property ID, and set it to `value`.
+    fn set_scrub_tombstone(
+        &mut self,
+        properties: &mut BTreeMap<u16, PropertyValue>,
+        name: &str,
+        value: PropertyValue,
+    ) -> io::Result<()> {
+        let tombstone = BTreeMap::from([((PS_PUBLIC_STRINGS, name.into()), value)]);
+        properties.extend(self.map_named_properties(tombstone)?);
+        Ok(())
+    }
 
-        let header = {
-            self.header.update_unique();
+    /// Write the scrubbed `properties` of `message`, set its `PidTagMessageSize` to the size of
+    /// what is left, and update the sizes of its folder and the message store, and the `changed`
+    /// columns and `PidTagMessageSize` in its row of the contents table.
+    fn replace_scrubbed_message(
+        &mut self,
+        message: NodeId,
+        mut properties: BTreeMap<u16, PropertyValue>,
+        previous_size: i64,
+        changed: &[u16],
+    ) -> io::Result<()> {
+        // PidTagMessageSize has a fixed size, so the property context is the same size whatever
+        // it is set to. The sub-nodes which replace_node keeps are counted along with it.
+        properties.insert(0x0E08, PropertyValue::Integer32(0));
+        let data = build_property_context(properties.iter())?;
+        let kept_size: usize = self
+            .pst
+            .read_node_data(message)?
+            .sub_nodes()
+            .iter()
+            .filter(|(sub_node, _)| {
+                !matches!(sub_node.id_type(), Ok(NodeIdType::ListsTablesProperties))
+                    && data.sub_node(**sub_node).is_none()
+            })
+            .map(|(_, sub_node)| sub_node.size())
+            .sum();
+        let size = (data.size() + kept_size) as i64;
+        properties.insert(
+            0x0E08,
+            PropertyValue::Integer32(i32::try_from(size).unwrap_or(i32::MAX)),
+        );
+        self.pst
+            .replace_node(message, &build_property_context(properties.iter())?)?;
 
-            let root = self.header.root_mut();
-            root.set_amap_status(AmapStatus::Invalid);
-            self.header.clone()
+        let Some(folder) = self.pst.read_node_parent(message)? else {
+            return Ok(());
         };
+        self.add_message_size(folder, size - previous_size)?;
+        self.add_message_size(NID_MESSAGE_STORE, size - previous_size)?;
 
-        let mut writer = self
-            .writer
-            .as_ref()?
-            .lock()
-            .map_err(|_| PstError::LockError)?;
-        let writer = &mut *writer;
-        writer.seek(SeekFrom::Start(0))?;
-        header.write(writer)?;
-        writer.flush()
+        let table_type = match message.id_type()? {
+            NodeIdType::AssociatedMessage => NodeIdType::AssociatedContentsTable,
+            _ => NodeIdType::ContentsTable,
+        };
+        let contents_table = NodeId::new(table_type, folder.index())?;
+        let (columns, mut rows) = read_table_context(&self.pst.read_node_data(contents_table)?)?;
+        let Some(row) = rows.get_mut(&TableRowId::new(u32::from(message))) else {
+            return Ok(());
+        };
+        let mut modified = false;
+        for (prop_id, prop_type) in columns.iter() {
+            if *prop_id != 0x0E08 && !changed.contains(prop_id) {
+                continue;
+            }
+            match properties
+                .get(prop_id)
+                .filter(|value| PropertyType::from(*value) == *prop_type)
+            {
+                Some(value) => {
+                    row.insert(*prop_id, value.clone());
+                }
+                None => {
+                    row.remove(prop_id);
+                }
+            }
+            modified = true;
+        }
+        if modified {
+            row.insert(
+                LTP_ROW_VERSION_PROP_ID,
+                self.pst.allocate_row_version().into(),
+            );
+            let data = build_table_context(self.pst.ndb_version(), &columns, &rows)?;
+            self.pst.replace_node(contents_table, &data)?;
+        }
+        Ok(())
     }
 
-    /// Complete a transaction by writing the header and density list to the file, and setting
-    /// [`AmapStatus::Valid2`].
+    /// Create a new message in `folder` with the properties, recipients and attachments in the
+    /// [`MessageBuilder`], and return its [`MessageId`]. This adds a row for the message to the
+    /// contents table of the folder, and updates the content and unread counts of the folder, and
+    /// the `PidTagMessageSizeExtended` of the folder and the message store.
     ///
-    /// See also [Transactional Semantics](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/bc5a92df-7fc1-4dc2-9c7c-5677237dd73a).
+    /// Named properties in the [`MessageBuilder`] are mapped to property IDs first with
+    /// [`Self::resolve_named_properties`]. If the [`MessageBuilder`] does not set
+    /// `PidTagCreationTime`, `PidTagLastModificationTime`, `PidTagSearchKey` or
+    /// `PidTagRecordKey`, the times are taken from the clock, see [`Self::set_clock`], and the keys
+    /// are derived from the record key of the store and the NID of the new message.
     #[instrument(skip_all)]
-    fn finish_write(&mut self) -> io::Result<()> {
-        // Reset AmapStatus::Valid2 to complete the transaction and then rewrite the updated
-        // density list.
-        let header = {
-            self.header.update_unique();
-            let root = self.header.root_mut();
-            root.set_amap_status(AmapStatus::Valid2);
-            self.header.clone()
-        };
-
-        self.update_density_list_page_id()?;
-        let density_list = {
-            self.density_list.as_ref().ok().and_then(|dl| {
-                <<Pst as PstFile>::DensityListPage as DensityListPageReadWrite<Pst>>::new(
-                    dl.backfill_complete(),
-                    dl.current_page(),
-                    dl.entries(),
-                    *dl.trailer(),
-                )
-                .ok()
-            })
-        };
+    pub fn create_message(
+        &mut self,
+        folder: FolderId,
+        builder: &MessageBuilder,
+    ) -> io::Result<MessageId> {
+        if !builder.has_named_properties() {
+            let data = builder.build(self.pst.ndb_version())?;
+            return self.create_built_message(folder, builder, &data);
+        }
 
-        let mut writer = self
-            .writer
-            .as_ref()?
-            .lock()
-            .map_err(|_| PstError::LockError)?;
-        let writer = &mut *writer;
-        writer.seek(SeekFrom::Start(0))?;
-        header.write(writer)?;
-        writer.flush()?;
+        let mut builder = builder.clone();
+        self.resolve_named_properties(&mut builder)?;
+        let data = builder.build(self.pst.ndb_version())?;
+        self.create_built_message(folder, &builder, &data)
+    }
 
-        if let Some(density_list) = density_list {
-            density_list.write(writer)?;
-            writer.flush()?;
+    /// Look up or add each of the named properties in the [`MessageBuilder`] and its embedded
+    /// messages in the [`NID_NAME_TO_ID_MAP`] node, and move their values to the property IDs
+    /// they are mapped to. The map is only rewritten if a new named property was added.
+    pub fn resolve_named_properties(&mut self, builder: &mut MessageBuilder) -> io::Result<()> {
+        let named_properties = builder.take_named_properties();
+        for (prop_id, value) in self.map_named_properties(named_properties)? {
+            builder.set_property(prop_id, value);
+        }
+        for message in builder.embedded_messages_mut() {
+            self.resolve_named_properties(message)?;
         }
-
         Ok(())
     }
 
-    /// [Crash Recovery and AMap Rebuilding](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/d9bcc1fd-c66a-41b3-b6d7-ed09d2a25ced)
-    fn rebuild_allocation_map(&mut self) -> io::Result<()> {
-        let root = self.header.root();
-        if AmapStatus::Invalid != root.amap_is_valid() {
-            return Ok(());
+    /// Same as [`Self::resolve_named_properties`] for `named_properties` which are not in a
+    /// [`MessageBuilder`], returning the values keyed by the property IDs they are mapped to.
+    fn map_named_properties(
+        &mut self,
+        named_properties: BTreeMap<(GuidValue, NamedPropertyName), PropertyValue>,
+    ) -> io::Result<BTreeMap<u16, PropertyValue>> {
+        if named_properties.is_empty() {
+            return Ok(Default::default());
         }
 
-        let num_amap_pages = root.file_eof_index().index().into() - AMAP_FIRST_OFFSET;
-        let num_amap_pages = num_amap_pages.div_ceil(AMAP_DATA_SIZE);
-
-        let mut amap_pages: Vec<_> = (0..num_amap_pages)
-            .map(|index| {
-                let has_pmap_page = index % 8 == 0;
-                let has_fmap_page = has_pmap_page
-                    && index >= FMAP_FIRST_SIZE
-                    && (index - FMAP_FIRST_SIZE) % FMAP_PAGE_COUNT == 0;
-                let has_fpmap_page = has_pmap_page
-                    && index >= FPMAP_FIRST_SIZE
-                    && (index - FPMAP_FIRST_SIZE) % FPMAP_PAGE_COUNT == 0;
+        let mut map = NamedPropertyMapProperties::from(read_property_context(
+            &self.pst.read_node_data(NID_NAME_TO_ID_MAP)?,
+        )?);
+        let mut modified = false;
+        let mut properties = BTreeMap::new();
+        for ((guid, name), value) in named_properties {
+            let prop_id = match map.find(&guid, &name)? {
+                Some(prop_id) => prop_id,
+                None => {
+                    modified = true;
+                    map.insert(&guid, &name)?
+                }
+            };
+            properties.insert(prop_id, value);
+        }
 
-                let index =
-                    <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(
-                        index * AMAP_DATA_SIZE + AMAP_FIRST_OFFSET,
-                    )
-                    .map_err(|_| PstError::IntegerConversion)?;
-                let block_id = <Pst as PstFile>::PageId::from(index);
+        if modified {
+            let data = build_property_context(map.iter())?;
+            self.pst.replace_node(NID_NAME_TO_ID_MAP, &data)?;
+        }
+        Ok(properties)
+    }
 
-                let trailer = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
-                    PageType::AllocationMap,
-                    0,
-                    block_id,
-                    0,
-                );
+    pub(crate) fn ndb_version(&self) -> NdbVersion {
+        self.pst.ndb_version()
+    }
 
-                let mut map_bits = [0; mem::size_of::<MapBits>()];
-                let mut reserved = 1;
-                if has_pmap_page {
-                    reserved += 1;
-                }
-                if has_fmap_page {
-                    reserved += 1;
-                }
-                if has_fpmap_page {
-                    reserved += 1;
-                }
+    /// Same as [`Self::create_message`], with the `data` from [`MessageBuilder::build`], so the
+    /// caller can tell whether an error happened before anything was written to the file.
+    pub(crate) fn create_built_message(
+        &mut self,
+        folder: FolderId,
+        builder: &MessageBuilder,
+        data: &NodeData,
+    ) -> io::Result<MessageId> {
+        self.create_message_node(folder, builder, data, false)
+    }
 
-                let free_space = AMAP_DATA_SIZE - (reserved * PAGE_SIZE) as u64;
+    /// Same as [`Self::create_built_message`] for a batch of messages, which are added to the
+    /// contents table and the counts of `folder` together.
+    pub(crate) fn create_built_messages(
+        &mut self,
+        folder: FolderId,
+        messages: &[(&MessageBuilder, &NodeData)],
+    ) -> io::Result<Vec<MessageId>> {
+        self.create_message_nodes(folder, messages, false)
+    }
 
-                let reserved = &[0xFF; 4][..reserved];
-                map_bits[..reserved.len()].copy_from_slice(reserved);
+    /// Create a new folder associated information (FAI) message in `folder`, e.g. to hold view
+    /// or index settings, and return its [`MessageId`]. This adds a row for the message to the
+    /// associated contents table of the folder and updates its `PidTagAssociatedContentCount`,
+    /// but the content and unread counts are left unchanged.
+    #[instrument(skip_all)]
+    pub fn create_associated_message(
+        &mut self,
+        folder: FolderId,
+        builder: &MessageBuilder,
+    ) -> io::Result<MessageId> {
+        let mut builder = builder.clone();
+        let flags = match builder.properties().get(&0x0E07) {
+            Some(PropertyValue::Integer32(flags)) => *flags,
+            _ => MSGFLAG_READ,
+        };
+        builder.set_property(0x0E07, PropertyValue::Integer32(flags | MSGFLAG_ASSOCIATED));
+        self.resolve_named_properties(&mut builder)?;
+        let data = builder.build(self.pst.ndb_version())?;
+        self.create_message_node(folder, &builder, &data, true)
+    }
 
-                let amap_page =
-                    <<Pst as PstFile>::AllocationMapPage as AllocationMapPageReadWrite<Pst>>::new(
-                        map_bits, trailer,
-                    )?;
-                Ok(AllocationMapPageInfo::<Pst> {
-                    amap_page,
-                    free_space,
-                })
-            })
-            .collect::<PstResult<Vec<_>>>()?;
+    /// Save `index` in the FAI message of `folder` which holds it, or create that message if
+    /// there is none yet, and return its [`MessageId`].
+    #[instrument(skip_all)]
+    pub fn save_time_index(
+        &mut self,
+        folder: FolderId,
+        index: &FolderTimeIndex,
+    ) -> io::Result<MessageId> {
+        let builder = index.to_message_builder()?;
+        self.save_associated_message(folder, &builder, is_time_index_message)
+    }
 
-        {
-            let mut reader = self.reader.lock().map_err(|_| PstError::LockError)?;
-            let reader = &mut *reader;
+    /// Save `annotations` in the FAI message of `folder` which holds them, or create that message
+    /// if there is none yet, and return its [`MessageId`]. This replaces all of the annotations
+    /// saved before, so load them with [`Folder::annotations`] first to change only some of them.
+    #[instrument(skip_all)]
+    pub fn save_annotations(
+        &mut self,
+        folder: FolderId,
+        annotations: &FolderAnnotations,
+    ) -> io::Result<MessageId> {
+        let builder = annotations.to_message_builder()?;
+        self.save_associated_message(folder, &builder, is_annotations_message)
+    }
 
-            let node_btree =
-                <Pst::NodeBTree as RootBTreeReadWrite>::read(reader, *root.node_btree())?;
+    /// Save `categories` in the FAI message of `folder` which holds the category list, or create
+    /// that message if there is none yet, and return its [`MessageId`]. Outlook reads the list
+    /// from [`Store::category_list_folder`], so save it there to change the categories it offers.
+    #[instrument(skip_all)]
+    pub fn save_categories(
+        &mut self,
+        folder: FolderId,
+        categories: &CategoryList,
+    ) -> io::Result<MessageId> {
+        let builder = categories.to_message_builder();
+        self.save_associated_message(folder, &builder, is_category_list_message)
+    }
 
-            Self::mark_node_btree_allocations(
-                reader,
-                root.node_btree().index(),
-                &node_btree,
-                &mut amap_pages,
-            )?;
+    /// Update the first FAI message in `folder` whose properties match `is_match` with the
+    /// [`MessageBuilder`], or create a new one if there is none.
+    fn save_associated_message(
+        &mut self,
+        folder: FolderId,
+        builder: &MessageBuilder,
+        is_match: fn(&BTreeMap<u16, PropertyValue>) -> bool,
+    ) -> io::Result<MessageId> {
+        let associated_table = NodeId::new(
+            NodeIdType::AssociatedContentsTable,
+            folder.node_id().index(),
+        )?;
+        let (_, rows) = read_table_context(&self.pst.read_node_data(associated_table)?)?;
+        for row_id in rows.keys() {
+            let message = MessageId::try_from(NodeId::from(u32::from(*row_id)))?;
+            let properties = read_property_context(&self.pst.read_node_data(message.into())?)?;
+            if is_match(&properties) {
+                self.update_message(message, builder)?;
+                return Ok(message);
+            }
+        }
+        self.create_associated_message(folder, builder)
+    }
 
-            let block_btree =
-                <Pst::BlockBTree as RootBTreeReadWrite>::read(reader, *root.block_btree())?;
+    fn create_message_node(
+        &mut self,
+        folder: FolderId,
+        builder: &MessageBuilder,
+        data: &NodeData,
+        associated: bool,
+    ) -> io::Result<MessageId> {
+        let messages = self.create_message_nodes(folder, &[(builder, data)], associated)?;
+        Ok(messages[0])
+    }
 
-            Self::mark_block_btree_allocations(
-                reader,
-                root.block_btree().index(),
-                &block_btree,
-                &mut amap_pages,
-            )?;
+    /// Create a node for each of the `messages`, then add their rows to the contents table and
+    /// their counts and sizes to the folder and the message store, rewriting each of those only
+    /// once for the whole batch.
+    fn create_message_nodes(
+        &mut self,
+        folder: FolderId,
+        messages: &[(&MessageBuilder, &NodeData)],
+        associated: bool,
+    ) -> io::Result<Vec<MessageId>> {
+        if folder.is_search_folder() {
+            return Err(MessagingError::InvalidFolderEntryIdType(NodeIdType::SearchFolder).into());
         }
+        self.auto_flush()?;
+        let folder = folder.node_id();
 
-        let free_bytes =
-            <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(
-                amap_pages.iter().map(|page| page.free_space).sum(),
+        let (message_type, table_type) = if associated {
+            (
+                NodeIdType::AssociatedMessage,
+                NodeIdType::AssociatedContentsTable,
             )
-            .map_err(|_| PstError::IntegerConversion)?;
-        let free_bytes = <<Pst as PstFile>::ByteIndex as ByteIndexReadWrite>::new(free_bytes);
-
-        let mut first_fmap = [0; FMAP_FIRST_SIZE as usize];
-        for (entry, free_space) in first_fmap
-            .iter_mut()
-            .zip(amap_pages.iter().map(|page| page.max_free_slots()))
-        {
-            *entry = free_space;
+        } else {
+            (NodeIdType::NormalMessage, NodeIdType::ContentsTable)
+        };
+        let store_record_key = self.store_record_key()?;
+        let contents_table = NodeId::new(table_type, folder.index())?;
+        let (columns, mut rows) = read_table_context(&self.pst.read_node_data(contents_table)?)?;
+
+        let row_version = self.pst.allocate_row_version();
+        let mut created = Vec::with_capacity(messages.len());
+        let mut unread_count = 0;
+        let mut size = 0;
+        for &(builder, data) in messages {
+            let message = self.pst.allocate_nid(message_type)?;
+
+            let mut properties = builder.properties().clone();
+            let rebuilt;
+            let data = if set_default_message_properties(
+                &mut properties,
+                self.clock.now(),
+                &store_record_key,
+                message,
+            ) {
+                let mut data_with_defaults = build_property_context(properties.iter())?;
+                for (sub_node, sub_node_data) in data.sub_nodes() {
+                    data_with_defaults.insert_sub_node(*sub_node, sub_node_data.clone());
+                }
+                rebuilt = data_with_defaults;
+                &rebuilt
+            } else {
+                data
+            };
+            self.pst.create_node(message, Some(folder), data)?;
+            size += match builder.properties().get(&0x0E08) {
+                Some(PropertyValue::Integer32(size)) => i64::from(*size),
+                _ => data.size() as i64,
+            };
+
+            let mut row: BTreeMap<_, _> = columns
+                .iter()
+                .filter_map(|(prop_id, prop_type)| {
+                    let value = properties.get(prop_id)?;
+                    (PropertyType::from(value) == *prop_type).then(|| (*prop_id, value.clone()))
+                })
+                .collect();
+            row.insert(LTP_ROW_VERSION_PROP_ID, row_version.into());
+            rows.insert(TableRowId::new(u32::from(message)), row);
+
+            let is_unread = match builder.properties().get(&0x0E07) {
+                Some(PropertyValue::Integer32(flags)) => flags & MSGFLAG_READ == 0,
+                _ => true,
+            };
+            if is_unread {
+                unread_count += 1;
+            }
+            created.push(MessageId::try_from(message)?);
         }
+        let data = build_table_context(self.pst.ndb_version(), &columns, &rows)?;
+        self.pst.replace_node(contents_table, &data)?;
 
-        let pmap_pages: Vec<_> = (0..=(num_amap_pages / 8))
-            .map(|index| {
-                let index =
-                    <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(
-                        index * PMAP_DATA_SIZE + PMAP_FIRST_OFFSET,
-                    )
-                    .map_err(|_| PstError::IntegerConversion)?;
-                let block_id = <Pst as PstFile>::PageId::from(index);
-
-                let trailer = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
-                    PageType::AllocationPageMap,
-                    0,
-                    block_id,
-                    0,
-                );
+        let count = i32::try_from(messages.len()).map_err(|_| PstError::IntegerConversion)?;
+        let counts: &[(u16, i32)] = if associated {
+            &[(0x3617, count)]
+        } else {
+            &[(0x3602, count), (0x3603, unread_count)]
+        };
+        let mut properties = read_property_context(&self.pst.read_node_data(folder)?)?;
+        for &(prop_id, increment) in counts {
+            if increment == 0 {
+                continue;
+            }
+            let count = match properties.get(&prop_id) {
+                Some(PropertyValue::Integer32(count)) => *count,
+                _ => 0,
+            };
+            properties.insert(prop_id, PropertyValue::Integer32(count + increment));
+        }
+        add_message_size(&mut properties, size);
+        let data = build_property_context(properties.iter())?;
+        self.pst.replace_node(folder, &data)?;
+        self.add_message_size(NID_MESSAGE_STORE, size)?;
 
-                let map_bits = [0xFF; mem::size_of::<MapBits>()];
+        Ok(created)
+    }
 
-                let pmap_page =
-                    <<Pst as PstFile>::AllocationPageMapPage as AllocationPageMapPageReadWrite<
-                        Pst,
-                    >>::new(map_bits, trailer)?;
-                Ok(pmap_page)
-            })
-            .collect::<PstResult<Vec<_>>>()?;
+    /// `PidTagRecordKey` of the message store, which the search and record keys of new messages
+    /// are derived from.
+    fn store_record_key(&self) -> io::Result<[u8; 16]> {
+        let properties = read_property_context(&self.pst.read_node_data(NID_MESSAGE_STORE)?)?;
+        match properties.get(&0x0FF9) {
+            Some(PropertyValue::Binary(value)) => Ok(value
+                .buffer()
+                .try_into()
+                .map_err(|_| MessagingError::InvalidStoreRecordKeySize(value.buffer().len()))?),
+            Some(invalid) => {
+                Err(MessagingError::InvalidStoreRecordKey(PropertyType::from(invalid)).into())
+            }
+            None => Err(MessagingError::StoreRecordKeyNotFound.into()),
+        }
+    }
 
-        let fmap_pages: Vec<_> = (0..(num_amap_pages.max(FMAP_FIRST_SIZE) - FMAP_FIRST_SIZE)
-            .div_ceil(FMAP_PAGE_COUNT))
-            .map(|index| {
-                let amap_index =
-                    FMAP_FIRST_SIZE as usize + (index as usize * mem::size_of::<MapBits>());
-                let index =
-                    <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(
-                        index * FMAP_DATA_SIZE + FMAP_FIRST_OFFSET,
-                    )
-                    .map_err(|_| PstError::IntegerConversion)?;
-                let block_id = <Pst as PstFile>::PageId::from(index);
+    /// `PidTagMessageSize` of `message`, or the size of its node data and sub-nodes if it does not
+    /// have one.
+    fn message_size(&self, message: NodeId) -> io::Result<i64> {
+        let data = self.pst.read_node_data(message)?;
+        match read_property_context(&data)?.get(&0x0E08) {
+            Some(PropertyValue::Integer32(size)) => Ok(i64::from(*size)),
+            _ => Ok(data.size() as i64),
+        }
+    }
 
-                let trailer = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
-                    PageType::FreeMap,
-                    0,
-                    block_id,
-                    0,
-                );
+    /// Add `size` to the `PidTagMessageSizeExtended` of `folder` or the message store.
+    fn add_message_size(&mut self, node: NodeId, size: i64) -> io::Result<()> {
+        if size == 0 {
+            return Ok(());
+        }
+        let mut properties = read_property_context(&self.pst.read_node_data(node)?)?;
+        add_message_size(&mut properties, size);
+        let data = build_property_context(properties.iter())?;
+        self.pst.replace_node(node, &data)
+    }
 
-                let mut map_bits = [0; mem::size_of::<MapBits>()];
-                for (entry, free_space) in map_bits.iter_mut().zip(
-                    amap_pages
-                        .iter()
-                        .skip(amap_index)
-                        .map(|page| page.max_free_slots()),
-                ) {
-                    *entry = free_space;
+    /// Recalculate the `PidTagMessageSizeExtended` of every folder from the messages in its
+    /// contents and associated contents tables, and of the message store from the folders, and
+    /// rewrite the ones which have drifted. Outlook shows these sizes in its quota displays.
+    ///
+    /// The size of a message is its `PidTagMessageSize`, taken from the contents table if it has
+    /// that column, or else the size of its node data. Search folders only link to messages in
+    /// other folders, so they are left out.
+    #[instrument(skip_all)]
+    pub fn recompute_sizes(&mut self) -> io::Result<Vec<SizeCorrection>> {
+        let mut corrections = vec![];
+        let mut store_size = 0;
+        for folder in self.normal_folders()? {
+            let mut size = 0;
+            for table_type in [
+                NodeIdType::ContentsTable,
+                NodeIdType::AssociatedContentsTable,
+            ] {
+                let contents_table = NodeId::new(table_type, folder.index())?;
+                let (_, rows) = read_table_context(&self.pst.read_node_data(contents_table)?)?;
+                for (row, columns) in rows.iter() {
+                    if !self.row_resolves(*row, table_type)? {
+                        continue;
+                    }
+                    size += match columns.get(&0x0E08) {
+                        Some(PropertyValue::Integer32(size)) => i64::from(*size),
+                        _ => self.message_size(NodeId::from(u32::from(*row)))?,
+                    };
                 }
+            }
+            store_size += size;
 
-                let fmap_page = <<Pst as PstFile>::FreeMapPage as FreeMapPageReadWrite<Pst>>::new(
-                    map_bits, trailer,
-                )?;
-                Ok(fmap_page)
-            })
-            .collect::<PstResult<Vec<_>>>()?;
-
-        let fpmap_pages: Vec<_> = (0..(num_amap_pages.max(FPMAP_FIRST_SIZE) - FPMAP_FIRST_SIZE)
-            .div_ceil(FPMAP_PAGE_COUNT))
-            .map(|index| {
-                let index =
-                    <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(
-                        index * FPMAP_DATA_SIZE + FPMAP_FIRST_OFFSET,
-                    )
-                    .map_err(|_| PstError::IntegerConversion)?;
-                let block_id = <Pst as PstFile>::PageId::from(index);
+            if let Some(correction) = self.set_message_size(folder, size)? {
+                corrections.push(correction);
+            }
+        }
 
-                let trailer = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
-                    PageType::FreePageMap,
-                    0,
-                    block_id,
-                    0,
-                );
+        if let Some(correction) = self.set_message_size(NID_MESSAGE_STORE, store_size)? {
+            corrections.push(correction);
+        }
+        Ok(corrections)
+    }
 
-                let map_bits = [0xFF; mem::size_of::<MapBits>()];
+    /// Recount the messages in the contents and associated contents tables of `folder`, the
+    /// unread messages, and whether its hierarchy table has any sub-folders, and rewrite the
+    /// `PidTagContentCount`, `PidTagContentUnreadCount`, `PidTagAssociatedContentCount` and
+    /// `PidTagSubfolders` properties which disagree. The same columns are corrected in the row
+    /// for `folder` in the hierarchy table of its parent. Rows whose `dwRowID` does not resolve to
+    /// a node are not counted, see [`Store::verify_table_rows`].
+    ///
+    /// The changes are written with the rest of the transaction when the guard is flushed.
+    #[instrument(skip_all)]
+    pub fn repair_folder_counts(&mut self, folder: FolderId) -> io::Result<Vec<CountCorrection>> {
+        if folder.is_search_folder() {
+            return Err(MessagingError::InvalidFolderEntryIdType(NodeIdType::SearchFolder).into());
+        }
+        self.repair_counts_of(folder.node_id())
+    }
 
-                let fpmap_page = <<Pst as PstFile>::FreePageMapPage as FreePageMapPageReadWrite<
-                    Pst,
-                >>::new(map_bits, trailer)?;
-                Ok(fpmap_page)
-            })
-            .collect::<PstResult<Vec<_>>>()?;
+    /// [`Self::repair_folder_counts`] for every folder beneath the root folder, except for
+    /// search folders, whose contents tables only link to messages in other folders.
+    #[instrument(skip_all)]
+    pub fn repair_counts(&mut self) -> io::Result<Vec<CountCorrection>> {
+        let mut corrections = vec![];
+        for folder in self.normal_folders()? {
+            corrections.extend(self.repair_counts_of(folder)?);
+        }
+        Ok(corrections)
+    }
 
-        {
-            let mut writer = self
-                .writer
-                .as_ref()?
-                .lock()
-                .map_err(|_| PstError::LockError)?;
-            let writer = &mut *writer;
+    /// The root folder and every normal folder beneath it, depth-first.
+    fn normal_folders(&self) -> io::Result<Vec<NodeId>> {
+        let mut folders = vec![];
+        let mut pending = vec![NID_ROOT_FOLDER];
+        while let Some(folder) = pending.pop() {
+            let hierarchy_table = NodeId::new(NodeIdType::HierarchyTable, folder.index())?;
+            let (_, rows) = read_table_context(&self.pst.read_node_data(hierarchy_table)?)?;
+            for row in rows.keys() {
+                let node = NodeId::from(u32::from(*row));
+                if matches!(node.id_type(), Ok(NodeIdType::NormalFolder))
+                    && self.row_resolves(*row, NodeIdType::HierarchyTable)?
+                {
+                    pending.push(node);
+                }
+            }
+            folders.push(folder);
+        }
+        Ok(folders)
+    }
 
-            for page in amap_pages.into_iter().map(|info| info.amap_page) {
-                writer.seek(SeekFrom::Start(page.trailer().block_id().into_u64()))?;
-                <Pst::AllocationMapPage as AllocationMapPageReadWrite<Pst>>::write(&page, writer)?;
+    fn repair_counts_of(&mut self, folder: NodeId) -> io::Result<Vec<CountCorrection>> {
+        let counts = self.folder_counts(folder)?;
+        let mut corrections = vec![];
+
+        let mut properties = read_property_context(&self.pst.read_node_data(folder)?)?;
+        for &(counter, value) in counts.iter() {
+            let previous = properties.get(&counter.prop_id()).and_then(counter_value);
+            if previous == Some(value)
+                || (previous.is_none() && !counter.is_required() && value == 0)
+            {
+                continue;
             }
+            properties.insert(counter.prop_id(), counter.property_value(value));
+            corrections.push(CountCorrection {
+                folder,
+                counter,
+                location: CountLocation::FolderProperties,
+                previous,
+                value,
+            });
+        }
+        if !corrections.is_empty() {
+            let data = build_property_context(properties.iter())?;
+            self.pst.replace_node(folder, &data)?;
+        }
 
-            for page in pmap_pages.into_iter() {
-                writer.seek(SeekFrom::Start(page.trailer().block_id().into_u64()))?;
-                <Pst::AllocationPageMapPage as AllocationPageMapPageReadWrite<Pst>>::write(
-                    &page, writer,
-                )?;
+        let Some(parent) = self
+            .pst
+            .read_node_parent(folder)?
+            .filter(|parent| *parent != folder)
+        else {
+            return Ok(corrections);
+        };
+        let hierarchy_table = NodeId::new(NodeIdType::HierarchyTable, parent.index())?;
+        let (columns, mut rows) = read_table_context(&self.pst.read_node_data(hierarchy_table)?)?;
+        let Some(row) = rows.get_mut(&TableRowId::new(u32::from(folder))) else {
+            return Ok(corrections);
+        };
+        let mut modified = false;
+        for &(counter, value) in counts.iter() {
+            if !columns
+                .iter()
+                .any(|(prop_id, _)| *prop_id == counter.prop_id())
+            {
+                continue;
             }
+            let previous = row.get(&counter.prop_id()).and_then(counter_value);
+            if previous != Some(value) {
+                row.insert(counter.prop_id(), counter.property_value(value));
+                modified = true;
+                corrections.push(CountCorrection {
+                    folder,
+                    counter,
+                    location: CountLocation::HierarchyTable,
+                    previous,
+                    value,
+                });
+            }
+        }
+        if modified {
+            row.insert(
+                LTP_ROW_VERSION_PROP_ID,
+                self.pst.allocate_row_version().into(),
+            );
+            let data = build_table_context(self.pst.ndb_version(), &columns, &rows)?;
+            self.pst.replace_node(hierarchy_table, &data)?;
+        }
 
-            for page in fmap_pages.into_iter() {
-                writer.seek(SeekFrom::Start(page.trailer().block_id().into_u64()))?;
-                <Pst::FreeMapPage as FreeMapPageReadWrite<Pst>>::write(&page, writer)?;
+        Ok(corrections)
+    }
+
+    fn folder_counts(&self, folder: NodeId) -> io::Result<[(FolderCounter, i32); 4]> {
+        let contents_table = NodeId::new(NodeIdType::ContentsTable, folder.index())?;
+        let (_, rows) = read_table_context(&self.pst.read_node_data(contents_table)?)?;
+        let mut content_count = 0;
+        let mut unread_count = 0;
+        for (row, columns) in rows.iter() {
+            if !self.row_resolves(*row, NodeIdType::ContentsTable)? {
+                continue;
+            }
+            content_count += 1;
+            let flags = match columns.get(&0x0E07) {
+                Some(PropertyValue::Integer32(flags)) => *flags,
+                _ => {
+                    let message = NodeId::from(u32::from(*row));
+                    match read_property_context(&self.pst.read_node_data(message)?)?.get(&0x0E07) {
+                        Some(PropertyValue::Integer32(flags)) => *flags,
+                        _ => 0,
+                    }
+                }
+            };
+            if flags & MSGFLAG_READ == 0 {
+                unread_count += 1;
             }
+        }
 
-            for page in fpmap_pages.into_iter() {
-                writer.seek(SeekFrom::Start(page.trailer().block_id().into_u64()))?;
-                <Pst::FreePageMapPage as FreePageMapPageReadWrite<Pst>>::write(&page, writer)?;
+        let associated_table = NodeId::new(NodeIdType::AssociatedContentsTable, folder.index())?;
+        let (_, rows) = read_table_context(&self.pst.read_node_data(associated_table)?)?;
+        let mut associated_count = 0;
+        for row in rows.keys() {
+            if self.row_resolves(*row, NodeIdType::AssociatedContentsTable)? {
+                associated_count += 1;
             }
+        }
 
-            writer.flush()?;
+        let hierarchy_table = NodeId::new(NodeIdType::HierarchyTable, folder.index())?;
+        let (_, rows) = read_table_context(&self.pst.read_node_data(hierarchy_table)?)?;
+        let mut has_subfolders = 0;
+        for row in rows.keys() {
+            if self.row_resolves(*row, NodeIdType::HierarchyTable)? {
+                has_subfolders = 1;
+                break;
+            }
         }
 
-        let header = {
-            <<Pst as PstFile>::Header as HeaderReadWrite<Pst>>::first_free_map(&mut self.header)
-                .copy_from_slice(&first_fmap);
-            self.header.update_unique();
+        Ok([
+            (FolderCounter::ContentCount, content_count),
+            (FolderCounter::ContentUnreadCount, unread_count),
+            (FolderCounter::AssociatedContentCount, associated_count),
+            (FolderCounter::Subfolders, has_subfolders),
+        ])
+    }
 
-            let root = self.header.root_mut();
-            root.reset_free_size(free_bytes)?;
-            root.set_amap_status(AmapStatus::Valid2);
+    /// Whether the `dwRowID` of a row in a table of `table_type` resolves to a node, see
+    /// [`UnresolvedRowReason`].
+    fn row_resolves(&self, row: TableRowId, table_type: NodeIdType) -> io::Result<bool> {
+        let reason = UnresolvedRowReason::classify(u32::from(row), table_type, |node| {
+            Ok(optional_btree_entry(self.pst.read_node_parent(node))?.is_some())
+        })?;
+        Ok(reason.is_none())
+    }
 
-            self.header.clone()
-        };
+    fn set_message_size(&mut self, node: NodeId, size: i64) -> io::Result<Option<SizeCorrection>> {
+        let mut properties = read_property_context(&self.pst.read_node_data(node)?)?;
+        let previous = message_size_extended(&properties);
+        if previous == Some(size) {
+            return Ok(None);
+        }
 
-        let mut writer = self
-            .writer
-            .as_ref()?
-            .lock()
-            .map_err(|_| PstError::LockError)?;
-        let writer = &mut *writer;
-        writer.seek(SeekFrom::Start(0))?;
-        header.write(writer)?;
-        writer.flush()
+        properties.insert(0x0E08, PropertyValue::Integer64(size));
+        let data = build_property_context(properties.iter())?;
+        self.pst.replace_node(node, &data)?;
+        Ok(Some(SizeCorrection {
+            node,
+            previous,
+            size,
+        }))
     }
+}
 
-    /// Recursively mark all of the pages in the [`Node BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
-    /// as allocated. This does not include any blocks referenced in the nodes or the sub-trees in
-    /// those blocks, blocks will be marked by [`Self::mark_block_btree_allocations`].
-    ///
-    /// See also [Crash Recovery and AMap Rebuilding](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/d9bcc1fd-c66a-41b3-b6d7-ed09d2a25ced).
-    #[instrument(skip_all)]
-    fn mark_node_btree_allocations<R: PstReader>(
-        reader: &mut R,
-        page_index: Pst::ByteIndex,
-        node_btree: &PstFileReadWriteNodeBTree<Pst>,
-        amap_pages: &mut Vec<AllocationMapPageInfo<Pst>>,
-    ) -> io::Result<()> {
-        Self::mark_page_allocation(page_index.index().into(), amap_pages)?;
-
-        if let RootBTreePage::Intermediate(page, ..) = node_btree {
-            let level = page.level();
-            for entry in page.entries() {
-                let block = entry.block();
-                let node_btree = <Pst::NodeBTree as RootBTreeReadWrite>::read(reader, block)?;
-                match &node_btree {
-                    RootBTreePage::Intermediate(page, ..) if page.level() + 1 != level => {
-                        error!(
-                            name: "PstUnexpectedBTreeIntermediatePage",
-                            block = ?block.block(),
-                            index = ?block.index(),
-                            parent = level,
-                            child = page.level(),
-                            "Possible NBT page cycle detected, expected child == parent - 1"
-                        );
-                        return Err(PstError::InvalidBTreePage(block.index().index().into()).into());
-                    }
-                    RootBTreePage::Leaf(_) if level != 1 => {
-                        error!(
-                            name: "PstUnexpectedBTreeLeafPage",
-                            block = ?block.block(),
-                            index = ?block.index(),
-                            parent = level,
-                            child = page.level(),
-                            "Corrupted NBT intermediate page detected, unexpected child leaf page"
-                        );
-                        return Err(PstError::InvalidBTreePage(block.index().index().into()).into());
-                    }
-                    _ => (),
-                }
-                Self::mark_node_btree_allocations(reader, block.index(), &node_btree, amap_pages)?;
-            }
-        }
+/// `PidTagMessageSizeExtended` of a folder or the message store. Some writers store it as a
+/// `PtypInteger32`.
+fn message_size_extended(properties: &BTreeMap<u16, PropertyValue>) -> Option<i64> {
+    match properties.get(&0x0E08)? {
+        PropertyValue::Integer64(size) => Some(*size),
+        PropertyValue::Integer32(size) => Some(i64::from(*size)),
+        _ => None,
+    }
+}
 
-        Ok(())
+/// The value of a [`FolderCounter`], with `PidTagSubfolders` as `0` or `1`.
+fn counter_value(value: &PropertyValue) -> Option<i32> {
+    match value {
+        PropertyValue::Integer32(value) => Some(*value),
+        PropertyValue::Boolean(value) => Some(i32::from(*value)),
+        _ => None,
     }
+}
 
-    /// Recursively mark all of the pages and blocks in the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
-    ///
-    /// See also [Crash Recovery and AMap Rebuilding](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/d9bcc1fd-c66a-41b3-b6d7-ed09d2a25ced).
+fn add_message_size(properties: &mut BTreeMap<u16, PropertyValue>, size: i64) {
+    let total = message_size_extended(properties).unwrap_or_default() + size;
+    properties.insert(0x0E08, PropertyValue::Integer64(total.max(0)));
+}
+
+impl<Pst> Drop for PstFileLockGuard<'_, Pst>
+where
+    Pst: PstFile,
+{
+    #[instrument(skip_all)]
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            error!(
+                name: "PstFileLockGuardFlushFailed",
+                ?err,
+                "Writing to the PST file failed"
+            );
+        }
+    }
+}
+
+pub trait PstReader: Read + Seek {}
+
+impl<T> PstReader for T where T: Read + Seek {}
+
+/// [PST File](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/6b57253b-0853-47bb-99bb-d4b8f78105f0)
+pub trait PstFile: Sized {
+    type BlockId: BlockId<Index = Self::BTreeKey> + BlockIdReadWrite;
+    type PageId: BlockId<Index = Self::BTreeKey> + BlockIdReadWrite;
+    type ByteIndex: ByteIndex + ByteIndexReadWrite;
+    type BlockRef: BlockRef<Block = Self::BlockId, Index = Self::ByteIndex> + BlockRefReadWrite;
+    type PageRef: BlockRef<Block = Self::PageId, Index = Self::ByteIndex> + BlockRefReadWrite;
+    type Root: Root<Self>;
+    type Header: Header<Self>;
+    type PageTrailer: PageTrailer<BlockId = Self::PageId> + PageTrailerReadWrite;
+    type BTreeKey: BTreeEntryKey;
+    type NodeBTreeEntry: NodeBTreeEntry<Block = Self::BlockId> + BTreeEntry<Key = Self::BTreeKey>;
+    type NodeBTree: NodeBTree<Self, Self::NodeBTreeEntry>;
+    type BlockBTreeEntry: BlockBTreeEntry<Block = Self::BlockRef> + BTreeEntry<Key = Self::BTreeKey>;
+    type BlockBTree: BlockBTree<Self, Self::BlockBTreeEntry>;
+    type BlockTrailer: BlockTrailer<BlockId = Self::BlockId>;
+    type AllocationMapPage: AllocationMapPage<Self>;
+    type AllocationPageMapPage: AllocationPageMapPage<Self>;
+    type FreeMapPage: FreeMapPage<Self>;
+    type FreePageMapPage: FreePageMapPage<Self>;
+    type DensityListPage: DensityListPage<Self>;
+    type DataTreeEntry: IntermediateTreeEntry + IntermediateDataTreeEntry<Self>;
+    type DataTreeBlock: IntermediateTreeBlock<
+        Header = DataTreeBlockHeader,
+        Entry = Self::DataTreeEntry,
+        Trailer = Self::BlockTrailer,
+    >;
+    type DataBlock: Block<Trailer = Self::BlockTrailer>;
+    type SubNodeTreeBlockHeader: IntermediateTreeHeader;
+    type SubNodeTreeBlock: IntermediateTreeBlock<
+        Header = Self::SubNodeTreeBlockHeader,
+        Entry = IntermediateSubNodeTreeEntry<Self::BlockId>,
+        Trailer = Self::BlockTrailer,
+    >;
+    type SubNodeBlock: IntermediateTreeBlock<
+        Header = Self::SubNodeTreeBlockHeader,
+        Entry = LeafSubNodeTreeEntry<Self::BlockId>,
+        Trailer = Self::BlockTrailer,
+    >;
+    type TableContext: TableContext;
+    type PropertyContext: PropertyContext;
+    type HeapNode: HeapNode;
+    type PropertyTree: HeapTree<Key = PropertyTreeRecordKey, Value = PropertyTreeRecordValue>;
+    type Store: Store;
+    type Folder: Folder;
+    type Message: Message;
+    type NamedPropertyMap: NamedPropertyMap;
+    type SearchUpdateQueue: SearchUpdateQueue;
+
+    fn header(&self) -> &Self::Header;
+    fn density_list(&self) -> Result<&dyn DensityListPage<Self>, &io::Error>;
+    /// Start a new reader at the beginning of the file. Readers are independent of each other,
+    /// and they don't take a lock unless a transaction has pending writes, so a file which is
+    /// shared between threads can be read from all of them at once.
+    fn reader(&self) -> TransactionReader<'_>;
+    fn lock(&mut self) -> io::Result<PstFileLockGuard<'_, Self>>;
+
+    /// Whether the file can be written, so tools can check before calling [`PstFile::lock`].
+    fn write_support(&self) -> WriteSupport;
+
+    fn read_node(&self, node: NodeId) -> io::Result<Self::NodeBTreeEntry>;
+    fn read_block(&self, block: Self::BlockId) -> io::Result<Vec<u8>>;
+    /// Call `visitor` with every block in the Block BTree, in file offset order. See
+    /// [`visit`] for how the blocks are decoded and classified.
+    fn visit_blocks<V: BlockVisitor<Self>>(&self, visitor: &mut V) -> io::Result<()>;
+    /// Check a sample of the pages in the file within the budget in `options`, see [`quick_check`].
+    fn quick_check(&self, options: &QuickCheckOptions) -> io::Result<QuickCheckReport>;
+    /// Read the free space in every AMap page, and the hints about it in the header and the
+    /// density list, see [`free_space`].
+    fn free_space_map(&self) -> io::Result<FreeSpaceMap>;
+}
+
+/// What a [`PstFileLockGuard`] can do with an open file, see [`PstFile::write_support`].
+///
+/// ANSI and Unicode files share the same write path, including page and block allocation,
+/// so both formats can be modified once the file is opened with write access. The difference is
+/// that an ANSI file cannot grow past [`NdbVersion::max_file_size`], and a transaction which
+/// needs more space fails with [`PstError::FileSizeLimit`] instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteSupport {
+    version: NdbVersion,
+    writable: bool,
+}
+
+impl WriteSupport {
+    pub fn version(&self) -> NdbVersion {
+        self.version
+    }
+
+    /// Whether the file was opened with write access. Files from
+    /// [`UnicodePstFile::read_from`] or [`AnsiPstFile::read_from`] are always read-only.
+    pub fn is_writable(&self) -> bool {
+        self.writable
+    }
+
+    /// See [`NdbVersion::max_file_size`].
+    pub fn max_file_size(&self) -> Option<u64> {
+        self.version.max_file_size()
+    }
+}
+
+/// A BTree page which passed the checks in [`PstFileInner::quick_check_btree_page`].
+struct QuickCheckBTreePage<Pst>
+where
+    Pst: PstFile,
+{
+    level: u8,
+    keys: Vec<u64>,
+    children: Vec<<Pst as PstFile>::PageRef>,
+}
+
+/// What a released block holds, which determines the child blocks it references.
+#[derive(Clone, Copy)]
+enum BlockContents {
+    DataTree,
+    SubNodeTree { with_entries: bool },
+}
+
+struct PstFileInner<Pst>
+where
+    Pst: PstFile,
+{
+    /// Where [`Self::reader`] reads from. Committed writes go through [`Self::writer`], which
+    /// has its own handle to the same file.
+    source: Arc<dyn ReadAt>,
+    writer: PstResult<Mutex<BufWriter<File>>>,
+    /// Pages and blocks which were written since the last commit, and which are read back through
+    /// [`Self::reader`] till they are committed to [`Self::writer`].
+    transaction: Arc<SharedTransaction>,
+    header: Pst::Header,
+    /// The last header which was committed to the file, which [`Self::discard_write`] restores.
+    committed_header: Pst::Header,
+    density_list: io::Result<Pst::DensityListPage>,
+    node_cache: NodeBTreePageCache<Pst>,
+    block_cache: BlockBTreePageCache<Pst>,
+    allocation_strategy: AllocationStrategy,
+    validate_before_commit: bool,
+    /// Nodes which were created or replaced since [`Self::start_write`].
+    dirty_nodes: BTreeSet<NodeId>,
+    /// File offsets of the pages and blocks which were allocated since [`Self::start_write`].
+    /// Nothing which was committed refers to them, so they can be rewritten or freed right away.
+    fresh_allocations: BTreeSet<u64>,
+    /// Offset and size of the space which was released since [`Self::start_write`], but which the
+    /// committed file still refers to, so it is only freed when the transaction is finished.
+    released_space: Vec<(u64, u64)>,
+    /// Space which was released while a [`Store::snapshot`] of this file was open. It is freed
+    /// by the first transaction which finishes after every snapshot has been dropped.
+    pinned_space: Vec<(u64, u64)>,
+    snapshot_pin: SnapshotPin,
+}
+
+/// Held by every [`Store::snapshot`] of a [`PstFile`], so transactions on that [`PstFile`] do
+/// not free the pages and blocks which the snapshot might still read till it is dropped.
+#[derive(Clone, Default)]
+pub(crate) struct SnapshotPin(Arc<()>);
+
+impl SnapshotPin {
+    fn is_held(&self) -> bool {
+        Arc::strong_count(&self.0) > 1
+    }
+}
+
+pub struct UnicodePstFile {
+    inner: PstFileInner<Self>,
+}
+
+impl UnicodePstFile {
+    pub fn read_from(reader: Box<dyn PstReader + Send>) -> io::Result<Self> {
+        Self::read_from_with_header_mode(reader, Default::default())
+    }
+
+    /// Same as [`Self::open_with_header_mode`], but read from `reader` instead of a file. Reads
+    /// from `reader` take turns on a lock, see [`SharedReader`].
+    pub fn read_from_with_header_mode(
+        reader: Box<dyn PstReader + Send>,
+        mode: HeaderFieldMode,
+    ) -> io::Result<Self> {
+        Self::read_from_source(Arc::new(SharedReader::new(reader)), mode)
+    }
+
+    /// Same as [`Self::read_from_with_header_mode`], but read from a [`ReadAt`] source, which
+    /// does not need a lock if it supports positioned reads.
+    pub fn read_from_source(source: Arc<dyn ReadAt>, mode: HeaderFieldMode) -> io::Result<Self> {
+        let inner = PstFileInner::read_from(source, mode)?;
+        Ok(Self { inner })
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with_header_mode(path, Default::default())
+    }
+
+    /// Open the file with [`HeaderFieldMode::Preserve`] to accept, and write back unchanged,
+    /// header fields which do not have the values in the specification.
+    pub fn open_with_header_mode(
+        path: impl AsRef<Path>,
+        mode: HeaderFieldMode,
+    ) -> io::Result<Self> {
+        let inner = PstFileInner::open(path, mode)?;
+        Ok(Self { inner })
+    }
+}
+
+impl PstFileLock<UnicodePstFile> for UnicodePstFile {
+    fn ndb_version(&self) -> NdbVersion {
+        NdbVersion::Unicode
+    }
+
+    fn start_write(&mut self) -> io::Result<()> {
+        self.inner.start_write()
+    }
+
+    fn finish_write(&mut self) -> io::Result<()> {
+        self.inner.finish_write()
+    }
+
+    fn discard_write(&mut self) -> io::Result<()> {
+        self.inner.discard_write()
+    }
+
+    fn pending_write_bytes(&self) -> u64 {
+        self.inner.transaction.pending_bytes()
+    }
+
+    fn source(&self) -> Arc<dyn ReadAt> {
+        self.inner.source.clone()
+    }
+
+    fn snapshot_pin(&self) -> SnapshotPin {
+        self.inner.snapshot_pin.clone()
+    }
+
+    fn block_cache(&self) -> MutexGuard<'_, RootBTreePageCache<<Self as PstFile>::BlockBTree>> {
+        self.inner.block_cache.shard()
+    }
+
+    fn node_cache(&self) -> MutexGuard<'_, RootBTreePageCache<<Self as PstFile>::NodeBTree>> {
+        self.inner.node_cache.shard()
+    }
+
+    fn allocation_strategy(&self) -> AllocationStrategy {
+        self.inner.allocation_strategy
+    }
+
+    fn set_allocation_strategy(&mut self, strategy: AllocationStrategy) {
+        self.inner.allocation_strategy = strategy;
+    }
+
+    fn allocation_statistics(&self) -> io::Result<AllocationMapStatistics> {
+        self.inner.allocation_statistics()
+    }
+
+    fn allocation_map_densities(&self) -> io::Result<Vec<AllocationMapPageDensity>> {
+        self.inner.allocation_map_densities()
+    }
+
+    fn check_free_space_maps(&self) -> io::Result<Vec<FreeSpaceMapMismatch>> {
+        self.inner.check_free_space_maps()
+    }
+
+    fn rebuild_free_space_maps(&mut self) -> io::Result<()> {
+        self.inner.rebuild_free_space_maps()
+    }
+
+    fn validate_before_commit(&self) -> bool {
+        self.inner.validate_before_commit
+    }
+
+    fn set_validate_before_commit(&mut self, validate: bool) {
+        self.inner.validate_before_commit = validate;
+    }
+
+    fn validate_structures(&self) -> io::Result<Vec<StructuralIssue>> {
+        self.inner.validate_structures(self.ndb_version())
+    }
+
+    fn grow(&mut self, size: u64) -> io::Result<()> {
+        self.inner.grow(size)
+    }
+
+    fn allocate_nid(&mut self, id_type: NodeIdType) -> io::Result<NodeId> {
+        self.inner.allocate_unused_nid(id_type)
+    }
+
+    fn allocate_row_version(&mut self) -> RowVersion {
+        self.inner.header.update_unique();
+        RowVersion::new(self.inner.header.unique_value())
+    }
+
+    fn read_node_data(&self, node: NodeId) -> io::Result<NodeData> {
+        self.inner.read_node_data(node)
+    }
+
+    fn read_node_parent(&self, node: NodeId) -> io::Result<Option<NodeId>> {
+        Ok(PstFile::read_node(self, node)?.parent())
+    }
+
+    fn create_node(
+        &mut self,
+        node: NodeId,
+        parent: Option<NodeId>,
+        data: &NodeData,
+    ) -> io::Result<()> {
+        self.inner.create_node(node, parent, data)
+    }
+
+    fn replace_node(&mut self, node: NodeId, data: &NodeData) -> io::Result<()> {
+        self.inner.replace_node(node, data)
+    }
+
+    fn write_data_tree(&mut self, f: &mut dyn Read) -> io::Result<(UnicodeBlockId, u32)> {
+        self.inner.write_data_tree(f)
+    }
+
+    fn write_sub_node(&mut self, node: NodeId, sub_node: NodeId, data: &[u8]) -> io::Result<()> {
+        self.inner.write_sub_node(node, sub_node, data)
+    }
+
+    fn remove_sub_node(&mut self, node: NodeId, sub_node: NodeId) -> io::Result<()> {
+        self.inner.remove_sub_node(node, sub_node)
+    }
+
+    fn copy_message(
+        &mut self,
+        message: NodeId,
+        folder: NodeId,
+        reuse_blocks: bool,
+    ) -> io::Result<NodeId> {
+        self.inner.copy_node(message, Some(folder), reuse_blocks)
+    }
+
+    fn copy_node(
+        &mut self,
+        node: NodeId,
+        parent: Option<NodeId>,
+        reuse_blocks: bool,
+    ) -> io::Result<NodeId> {
+        self.inner.copy_node(node, parent, reuse_blocks)
+    }
+
+    fn delete_message(&mut self, message: NodeId) -> io::Result<()> {
+        self.inner.delete_message(message)
+    }
+
+    fn fix_crcs(&mut self) -> io::Result<Vec<RepairedLocation>> {
+        self.inner.fix_crcs()
+    }
+}
+
+impl PstFile for UnicodePstFile {
+    type BlockId = UnicodeBlockId;
+    type PageId = UnicodePageId;
+    type ByteIndex = UnicodeByteIndex;
+    type BlockRef = UnicodeBlockRef;
+    type PageRef = UnicodePageRef;
+    type Root = UnicodeRoot;
+    type Header = UnicodeHeader;
+    type PageTrailer = UnicodePageTrailer;
+    type BTreeKey = u64;
+    type NodeBTreeEntry = UnicodeNodeBTreeEntry;
+    type NodeBTree = UnicodeNodeBTree;
+    type BlockBTreeEntry = UnicodeBlockBTreeEntry;
+    type BlockBTree = UnicodeBlockBTree;
+    type BlockTrailer = UnicodeBlockTrailer;
+    type AllocationMapPage = UnicodeMapPage<{ PageType::AllocationMap as u8 }>;
+    type AllocationPageMapPage = UnicodeMapPage<{ PageType::AllocationPageMap as u8 }>;
+    type FreeMapPage = UnicodeMapPage<{ PageType::FreeMap as u8 }>;
+    type FreePageMapPage = UnicodeMapPage<{ PageType::FreePageMap as u8 }>;
+    type DensityListPage = UnicodeDensityListPage;
+    type DataTreeEntry = UnicodeDataTreeEntry;
+    type DataTreeBlock = UnicodeDataTreeBlock;
+    type DataBlock = UnicodeDataBlock;
+    type SubNodeTreeBlockHeader = UnicodeSubNodeTreeBlockHeader;
+    type SubNodeTreeBlock = UnicodeIntermediateSubNodeTreeBlock;
+    type SubNodeBlock = UnicodeLeafSubNodeTreeBlock;
+    type HeapNode = UnicodeHeapNode;
+    type PropertyTree = UnicodeHeapTree<PropertyTreeRecordKey, PropertyTreeRecordValue>;
+    type TableContext = UnicodeTableContext;
+    type PropertyContext = UnicodePropertyContext;
+    type Store = UnicodeStore;
+    type Folder = UnicodeFolder;
+    type Message = UnicodeMessage;
+    type NamedPropertyMap = UnicodeNamedPropertyMap;
+    type SearchUpdateQueue = UnicodeSearchUpdateQueue;
+
+    fn header(&self) -> &Self::Header {
+        &self.inner.header
+    }
+
+    fn density_list(&self) -> Result<&dyn DensityListPage<Self>, &io::Error> {
+        self.inner.density_list.as_ref().map(|dl| dl as _)
+    }
+
+    fn reader(&self) -> TransactionReader<'_> {
+        self.inner.reader()
+    }
+
+    fn lock(&mut self) -> io::Result<PstFileLockGuard<'_, Self>> {
+        PstFileLockGuard::new(self)
+    }
+
+    fn write_support(&self) -> WriteSupport {
+        self.inner.write_support()
+    }
+
+    fn read_node(&self, node: NodeId) -> io::Result<UnicodeNodeBTreeEntry> {
+        self.inner.read_node(node)
+    }
+
+    fn read_block(&self, block: UnicodeBlockId) -> io::Result<Vec<u8>> {
+        self.inner.read_block(block)
+    }
+
+    fn visit_blocks<V: BlockVisitor<Self>>(&self, visitor: &mut V) -> io::Result<()> {
+        self.inner.visit_blocks(visitor)
+    }
+
+    fn quick_check(&self, options: &QuickCheckOptions) -> io::Result<QuickCheckReport> {
+        self.inner.quick_check(options)
+    }
+
+    fn free_space_map(&self) -> io::Result<FreeSpaceMap> {
+        self.inner.free_space_map()
+    }
+}
+
+pub struct AnsiPstFile {
+    inner: PstFileInner<Self>,
+}
+
+impl AnsiPstFile {
+    pub fn read_from(reader: Box<dyn PstReader + Send>) -> io::Result<Self> {
+        Self::read_from_with_header_mode(reader, Default::default())
+    }
+
+    /// Same as [`Self::open_with_header_mode`], but read from `reader` instead of a file. Reads
+    /// from `reader` take turns on a lock, see [`SharedReader`].
+    pub fn read_from_with_header_mode(
+        reader: Box<dyn PstReader + Send>,
+        mode: HeaderFieldMode,
+    ) -> io::Result<Self> {
+        Self::read_from_source(Arc::new(SharedReader::new(reader)), mode)
+    }
+
+    /// Same as [`Self::read_from_with_header_mode`], but read from a [`ReadAt`] source, which
+    /// does not need a lock if it supports positioned reads.
+    pub fn read_from_source(source: Arc<dyn ReadAt>, mode: HeaderFieldMode) -> io::Result<Self> {
+        let inner = PstFileInner::read_from(source, mode)?;
+        Ok(Self { inner })
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::open_with_header_mode(path, Default::default())
+    }
+
+    /// Open the file with [`HeaderFieldMode::Preserve`] to accept, and write back unchanged,
+    /// header fields which do not have the values in the specification.
+    pub fn open_with_header_mode(
+        path: impl AsRef<Path>,
+        mode: HeaderFieldMode,
+    ) -> io::Result<Self> {
+        let inner = PstFileInner::open(path, mode)?;
+        Ok(Self { inner })
+    }
+}
+
+impl PstFileLock<AnsiPstFile> for AnsiPstFile {
+    fn ndb_version(&self) -> NdbVersion {
+        NdbVersion::Ansi
+    }
+
+    fn start_write(&mut self) -> io::Result<()> {
+        self.inner.start_write()
+    }
+
+    fn finish_write(&mut self) -> io::Result<()> {
+        self.inner.finish_write()
+    }
+
+    fn discard_write(&mut self) -> io::Result<()> {
+        self.inner.discard_write()
+    }
+
+    fn pending_write_bytes(&self) -> u64 {
+        self.inner.transaction.pending_bytes()
+    }
+
+    fn source(&self) -> Arc<dyn ReadAt> {
+        self.inner.source.clone()
+    }
+
+    fn snapshot_pin(&self) -> SnapshotPin {
+        self.inner.snapshot_pin.clone()
+    }
+
+    fn block_cache(&self) -> MutexGuard<'_, RootBTreePageCache<<Self as PstFile>::BlockBTree>> {
+        self.inner.block_cache.shard()
+    }
+
+    fn node_cache(&self) -> MutexGuard<'_, RootBTreePageCache<<Self as PstFile>::NodeBTree>> {
+        self.inner.node_cache.shard()
+    }
+
+    fn allocation_strategy(&self) -> AllocationStrategy {
+        self.inner.allocation_strategy
+    }
+
+    fn set_allocation_strategy(&mut self, strategy: AllocationStrategy) {
+        self.inner.allocation_strategy = strategy;
+    }
+
+    fn allocation_statistics(&self) -> io::Result<AllocationMapStatistics> {
+        self.inner.allocation_statistics()
+    }
+
+    fn allocation_map_densities(&self) -> io::Result<Vec<AllocationMapPageDensity>> {
+        self.inner.allocation_map_densities()
+    }
+
+    fn check_free_space_maps(&self) -> io::Result<Vec<FreeSpaceMapMismatch>> {
+        self.inner.check_free_space_maps()
+    }
+
+    fn rebuild_free_space_maps(&mut self) -> io::Result<()> {
+        self.inner.rebuild_free_space_maps()
+    }
+
+    fn validate_before_commit(&self) -> bool {
+        self.inner.validate_before_commit
+    }
+
+    fn set_validate_before_commit(&mut self, validate: bool) {
+        self.inner.validate_before_commit = validate;
+    }
+
+    fn validate_structures(&self) -> io::Result<Vec<StructuralIssue>> {
+        self.inner.validate_structures(self.ndb_version())
+    }
+
+    fn grow(&mut self, size: u64) -> io::Result<()> {
+        self.inner.grow(size)
+    }
+
+    fn allocate_nid(&mut self, id_type: NodeIdType) -> io::Result<NodeId> {
+        self.inner.allocate_unused_nid(id_type)
+    }
+
+    fn allocate_row_version(&mut self) -> RowVersion {
+        self.inner.header.update_unique();
+        RowVersion::new(self.inner.header.unique_value())
+    }
+
+    fn read_node_data(&self, node: NodeId) -> io::Result<NodeData> {
+        self.inner.read_node_data(node)
+    }
+
+    fn read_node_parent(&self, node: NodeId) -> io::Result<Option<NodeId>> {
+        Ok(PstFile::read_node(self, node)?.parent())
+    }
+
+    fn create_node(
+        &mut self,
+        node: NodeId,
+        parent: Option<NodeId>,
+        data: &NodeData,
+    ) -> io::Result<()> {
+        self.inner.create_node(node, parent, data)
+    }
+
+    fn replace_node(&mut self, node: NodeId, data: &NodeData) -> io::Result<()> {
+        self.inner.replace_node(node, data)
+    }
+
+    fn write_data_tree(&mut self, f: &mut dyn Read) -> io::Result<(AnsiBlockId, u32)> {
+        self.inner.write_data_tree(f)
+    }
+
+    fn write_sub_node(&mut self, node: NodeId, sub_node: NodeId, data: &[u8]) -> io::Result<()> {
+        self.inner.write_sub_node(node, sub_node, data)
+    }
+
+    fn remove_sub_node(&mut self, node: NodeId, sub_node: NodeId) -> io::Result<()> {
+        self.inner.remove_sub_node(node, sub_node)
+    }
+
+    fn copy_message(
+        &mut self,
+        message: NodeId,
+        folder: NodeId,
+        reuse_blocks: bool,
+    ) -> io::Result<NodeId> {
+        self.inner.copy_node(message, Some(folder), reuse_blocks)
+    }
+
+    fn copy_node(
+        &mut self,
+        node: NodeId,
+        parent: Option<NodeId>,
+        reuse_blocks: bool,
+    ) -> io::Result<NodeId> {
+        self.inner.copy_node(node, parent, reuse_blocks)
+    }
+
+    fn delete_message(&mut self, message: NodeId) -> io::Result<()> {
+        self.inner.delete_message(message)
+    }
+
+    fn fix_crcs(&mut self) -> io::Result<Vec<RepairedLocation>> {
+        self.inner.fix_crcs()
+    }
+}
+
+impl PstFile for AnsiPstFile {
+    type BlockId = AnsiBlockId;
+    type PageId = AnsiPageId;
+    type ByteIndex = AnsiByteIndex;
+    type BlockRef = AnsiBlockRef;
+    type PageRef = AnsiPageRef;
+    type Root = AnsiRoot;
+    type Header = AnsiHeader;
+    type PageTrailer = AnsiPageTrailer;
+    type BTreeKey = u32;
+    type NodeBTreeEntry = AnsiNodeBTreeEntry;
+    type NodeBTree = AnsiNodeBTree;
+    type BlockBTreeEntry = AnsiBlockBTreeEntry;
+    type BlockBTree = AnsiBlockBTree;
+    type BlockTrailer = AnsiBlockTrailer;
+    type AllocationMapPage = AnsiMapPage<{ PageType::AllocationMap as u8 }>;
+    type AllocationPageMapPage = AnsiMapPage<{ PageType::AllocationPageMap as u8 }>;
+    type FreeMapPage = AnsiMapPage<{ PageType::FreeMap as u8 }>;
+    type FreePageMapPage = AnsiMapPage<{ PageType::FreePageMap as u8 }>;
+    type DensityListPage = AnsiDensityListPage;
+    type DataTreeEntry = AnsiDataTreeEntry;
+    type DataTreeBlock = AnsiDataTreeBlock;
+    type DataBlock = AnsiDataBlock;
+    type SubNodeTreeBlockHeader = AnsiSubNodeTreeBlockHeader;
+    type SubNodeTreeBlock = AnsiIntermediateSubNodeTreeBlock;
+    type SubNodeBlock = AnsiLeafSubNodeTreeBlock;
+    type HeapNode = AnsiHeapNode;
+    type PropertyTree = AnsiHeapTree<PropertyTreeRecordKey, PropertyTreeRecordValue>;
+    type TableContext = AnsiTableContext;
+    type PropertyContext = AnsiPropertyContext;
+    type Store = AnsiStore;
+    type Folder = AnsiFolder;
+    type Message = AnsiMessage;
+    type NamedPropertyMap = AnsiNamedPropertyMap;
+    type SearchUpdateQueue = AnsiSearchUpdateQueue;
+
+    fn header(&self) -> &Self::Header {
+        &self.inner.header
+    }
+
+    fn density_list(&self) -> Result<&dyn DensityListPage<Self>, &io::Error> {
+        self.inner.density_list.as_ref().map(|dl| dl as _)
+    }
+
+    fn reader(&self) -> TransactionReader<'_> {
+        self.inner.reader()
+    }
+
+    fn lock(&mut self) -> io::Result<PstFileLockGuard<'_, Self>> {
+        PstFileLockGuard::new(self)
+    }
+
+    fn write_support(&self) -> WriteSupport {
+        self.inner.write_support()
+    }
+
+    fn read_node(&self, node: NodeId) -> io::Result<AnsiNodeBTreeEntry> {
+        self.inner.read_node(node)
+    }
+
+    fn read_block(&self, block: AnsiBlockId) -> io::Result<Vec<u8>> {
+        self.inner.read_block(block)
+    }
+
+    fn visit_blocks<V: BlockVisitor<Self>>(&self, visitor: &mut V) -> io::Result<()> {
+        self.inner.visit_blocks(visitor)
+    }
+
+    fn quick_check(&self, options: &QuickCheckOptions) -> io::Result<QuickCheckReport> {
+        self.inner.quick_check(options)
+    }
+
+    fn free_space_map(&self) -> io::Result<FreeSpaceMap> {
+        self.inner.free_space_map()
+    }
+}
+
+/// Number of bytes read by [`HeaderReadWrite::read_with_mode`] for each [`NdbVersion`].
+const UNICODE_HEADER_SIZE: u64 = 564;
+const ANSI_HEADER_SIZE: u64 = 512;
+
+const AMAP_FIRST_OFFSET: u64 = 0x4400;
+const AMAP_DATA_SIZE: u64 = size_of::<MapBits>() as u64 * 8 * 64;
+
+const PMAP_FIRST_OFFSET: u64 = AMAP_FIRST_OFFSET + PAGE_SIZE as u64;
+const PMAP_PAGE_COUNT: u64 = 8;
+const PMAP_DATA_SIZE: u64 = AMAP_DATA_SIZE * PMAP_PAGE_COUNT;
+
+const FMAP_FIRST_SIZE: u64 = 128;
+const FMAP_FIRST_DATA_SIZE: u64 = AMAP_DATA_SIZE * FMAP_FIRST_SIZE;
+const FMAP_FIRST_OFFSET: u64 = AMAP_FIRST_OFFSET + FMAP_FIRST_DATA_SIZE + (2 * PAGE_SIZE) as u64;
+const FMAP_PAGE_COUNT: u64 = size_of::<MapBits>() as u64;
+const FMAP_DATA_SIZE: u64 = AMAP_DATA_SIZE * FMAP_PAGE_COUNT;
+
+const FPMAP_FIRST_SIZE: u64 = 128 * 64;
+const FPMAP_FIRST_DATA_SIZE: u64 = AMAP_DATA_SIZE * FPMAP_FIRST_SIZE;
+const FPMAP_FIRST_OFFSET: u64 = AMAP_FIRST_OFFSET + FPMAP_FIRST_DATA_SIZE + (3 * PAGE_SIZE) as u64;
+const FPMAP_PAGE_COUNT: u64 = size_of::<MapBits>() as u64 * 64;
+const FPMAP_DATA_SIZE: u64 = AMAP_DATA_SIZE * FPMAP_PAGE_COUNT;
+
+/// Split a file offset into the index of the AMap page which covers it, and the offset within the
+/// range of that page. Offsets are kept in `u64` throughout, so files larger than `usize::MAX`
+/// bytes work on 32-bit targets, and a corrupt offset before the first AMap page is an error
+/// rather than an underflow.
+fn amap_page_index(index: u64) -> PstResult<(u64, u64)> {
+    let offset = index
+        .checked_sub(AMAP_FIRST_OFFSET)
+        .ok_or(PstError::InvalidAllocationMapOffset(index))?;
+    Ok((offset / AMAP_DATA_SIZE, offset % AMAP_DATA_SIZE))
+}
+
+/// File offset of the AMap page at `amap_index`.
+fn amap_page_offset(amap_index: u64) -> PstResult<u64> {
+    amap_index
+        .checked_mul(AMAP_DATA_SIZE)
+        .and_then(|offset| offset.checked_add(AMAP_FIRST_OFFSET))
+        .ok_or(PstError::IntegerConversion)
+}
+
+/// Strategy used to pick free space in the allocation map for new pages and blocks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AllocationStrategy {
+    /// Take the first free space which fits, visiting the AMap pages in [`DensityListPage`] order
+    /// before the rest of the file.
+    #[default]
+    FirstFit,
+    /// Take the smallest free space which fits anywhere in the file, which leaves the larger runs
+    /// of free space intact for bigger allocations.
+    BestFit,
+    /// Only take free space at the end of the file, which keeps new data contiguous.
+    AppendAtEof,
+}
+
+/// Summary of the free space in the allocation map.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AllocationMapStatistics {
+    free_bytes: u64,
+    free_run_count: usize,
+    largest_free_run: u64,
+}
+
+impl AllocationMapStatistics {
+    pub fn free_bytes(&self) -> u64 {
+        self.free_bytes
+    }
+
+    /// Number of separate runs of contiguous free space.
+    pub fn free_run_count(&self) -> usize {
+        self.free_run_count
+    }
+
+    /// Size in bytes of the largest run of contiguous free space.
+    pub fn largest_free_run(&self) -> u64 {
+        self.largest_free_run
+    }
+
+    /// Ratio of free space which is not part of the largest free run, from `0.0` when all of the
+    /// free space is contiguous, approaching `1.0` as it is split into smaller runs.
+    pub fn fragmentation(&self) -> f64 {
+        if self.free_bytes == 0 {
+            return 0.0;
+        }
+        1.0 - (self.largest_free_run as f64 / self.free_bytes as f64)
+    }
+}
+
+/// Find the runs of free 64 byte slots in the first `slots` bits of an AMap page.
+fn free_allocation_runs(map_bits: &MapBits, slots: u64) -> Vec<Range<u64>> {
+    let is_free = |bit: u64| map_bits[(bit / 8) as usize] & (0x80 >> (bit % 8)) == 0;
+
+    let mut runs = vec![];
+    let mut current: Option<Range<u64>> = None;
+    for bit in 0..slots {
+        match (is_free(bit), current.as_mut()) {
+            (true, Some(run)) => run.end = bit + 1,
+            (true, None) => current = Some(bit..bit + 1),
+            (false, Some(_)) => runs.extend(current.take()),
+            (false, None) => {}
+        }
+    }
+    runs.extend(current);
+    runs
+}
+
+/// Free space in one AMap page. This is the single source for both the page's `rgbFM` entry in
+/// the header and its entry in the [`DensityListPage`], so the two are always derived from the
+/// same AMap state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AllocationMapPageDensity {
+    amap_index: u64,
+    free_slots: u16,
+    max_free_slots: u8,
+}
+
+impl AllocationMapPageDensity {
+    pub fn amap_index(&self) -> u64 {
+        self.amap_index
+    }
+
+    /// Total number of free 64 byte slots in the page, as recorded in the density list.
+    pub fn free_slots(&self) -> u16 {
+        self.free_slots
+    }
+
+    /// Longest run of free 64 byte slots in the page, capped at `0xFF`, as recorded in `rgbFM`.
+    pub fn max_free_slots(&self) -> u8 {
+        self.max_free_slots
+    }
+}
+
+/// A difference between the free space recorded in the header or density list, and the free
+/// space in the AMap pages. These can drift apart if a write is interrupted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FreeSpaceMapMismatch {
+    /// The header `rgbFM` entry for an AMap page is stale.
+    FreeMap {
+        amap_index: u64,
+        expected: u8,
+        actual: u8,
+    },
+    /// A density list entry has a stale free slot count, or refers to a page past the end of the
+    /// file, in which case `expected` is `None`.
+    DensityListEntry {
+        amap_index: u64,
+        expected: Option<u16>,
+        actual: u16,
+    },
+    /// The density list is not sorted from the most to the least free space.
+    DensityListOrder,
+}
+
+/// Build the density list from the free slot counts of `(amap_index, free_slots)`, sorted from
+/// the most to the least free space. Full pages and pages which cannot be numbered in a density
+/// list entry are left out.
+fn density_list_entries(
+    pages: impl IntoIterator<Item = (u64, u16)>,
+    max_entries: usize,
+) -> NdbResult<Vec<DensityListPageEntry>> {
+    let mut pages: Vec<_> = pages
+        .into_iter()
+        .filter(|&(amap_index, free_slots)| {
+            free_slots > 0 && amap_index <= u64::from(DENSITY_LIST_ENTRY_PAGE_NUMBER_MASK)
+        })
+        .collect();
+    pages.sort_by_key(|&(amap_index, free_slots)| (cmp::Reverse(free_slots), amap_index));
+    pages
+        .into_iter()
+        .take(max_entries)
+        .map(|(amap_index, free_slots)| DensityListPageEntry::new(amap_index as u32, free_slots))
+        .collect()
+}
+
+/// Compare the header `rgbFM` entries and the density list with the [`AllocationMapPageDensity`]
+/// of every AMap page.
+fn compare_free_space_maps(
+    densities: &[AllocationMapPageDensity],
+    free_map: &[u8],
+    density_list: &[DensityListPageEntry],
+) -> Vec<FreeSpaceMapMismatch> {
+    let mut mismatches: Vec<_> = densities
+        .iter()
+        .zip(free_map.iter())
+        .filter(|(density, &actual)| density.max_free_slots != actual)
+        .map(|(density, &actual)| FreeSpaceMapMismatch::FreeMap {
+            amap_index: density.amap_index,
+            expected: density.max_free_slots,
+            actual,
+        })
+        .collect();
+
+    for entry in density_list {
+        let amap_index = u64::from(entry.page());
+        let expected = densities
+            .get(amap_index as usize)
+            .map(|density| density.free_slots);
+        if expected != Some(entry.free_slots()) {
+            mismatches.push(FreeSpaceMapMismatch::DensityListEntry {
+                amap_index,
+                expected,
+                actual: entry.free_slots(),
+            });
+        }
+    }
+
+    if density_list
+        .windows(2)
+        .any(|pair| pair[0].free_slots() < pair[1].free_slots())
+    {
+        mismatches.push(FreeSpaceMapMismatch::DensityListOrder);
+    }
+
+    mismatches
+}
+
+struct AllocationMapPageInfo<Pst>
+where
+    Pst: PstFile,
+    <Pst as PstFile>::AllocationMapPage: AllocationMapPageReadWrite<Pst>,
+{
+    amap_page: <Pst as PstFile>::AllocationMapPage,
+    free_space: u64,
+}
+
+impl<Pst> AllocationMapPageInfo<Pst>
+where
+    Pst: PstFile,
+    <Pst as PstFile>::AllocationMapPage: AllocationMapPageReadWrite<Pst>,
+{
+    fn max_free_slots(&self) -> u8 {
+        u8::try_from(self.amap_page.find_free_bits(0xFF).len()).unwrap_or(0xFF)
+    }
+}
+
+type PstFileReadWriteBTree<Pst, BTree> = RootBTreePage<
+    Pst,
+    <BTree as RootBTree>::Entry,
+    <BTree as RootBTree>::IntermediatePage,
+    <BTree as RootBTree>::LeafPage,
+>;
+
+type PstFileReadWriteNodeBTree<Pst> = PstFileReadWriteBTree<Pst, <Pst as PstFile>::NodeBTree>;
+
+type PstFileReadWriteBlockBTree<Pst> = PstFileReadWriteBTree<Pst, <Pst as PstFile>::BlockBTree>;
+
+impl<Pst> PstFileInner<Pst>
+where
+    Pst: PstFile + PstFileLock<Pst>,
+    <Pst as PstFile>::BlockId: BlockId<Index = <Pst as PstFile>::BTreeKey>
+        + From<<<Pst as PstFile>::ByteIndex as ByteIndex>::Index>
+        + Debug,
+    <Pst as PstFile>::PageId: From<<<Pst as PstFile>::ByteIndex as ByteIndex>::Index> + Debug,
+    <Pst as PstFile>::ByteIndex: ByteIndex<Index: TryFrom<u64>> + Debug,
+    <Pst as PstFile>::BlockRef: Debug,
+    <Pst as PstFile>::PageRef: Debug,
+    <Pst as PstFile>::Root: RootReadWrite<Pst>,
+    <Pst as PstFile>::Header: HeaderReadWrite<Pst>,
+    <Pst as PstFile>::DensityListPage: DensityListPageReadWrite<Pst>,
+    <Pst as PstFile>::PageTrailer: PageTrailerReadWrite,
+    <Pst as PstFile>::BTreeKey: BTreePageKeyReadWrite,
+    <Pst as PstFile>::NodeBTreeEntry: NodeBTreeEntryReadWrite,
+    <Pst as PstFile>::NodeBTree: NodeBTreeReadWrite<Pst, <Pst as PstFile>::NodeBTreeEntry>,
+    <<Pst as PstFile>::NodeBTree as RootBTree>::IntermediatePage:
+        RootBTreeIntermediatePageReadWrite<
+            Pst,
+            <Pst as PstFile>::NodeBTreeEntry,
+            <<Pst as PstFile>::NodeBTree as RootBTree>::LeafPage,
+        >,
+    <<<Pst as PstFile>::NodeBTree as RootBTree>::IntermediatePage as BTreePage>::Entry:
+        BTreePageEntryReadWrite,
+    <<Pst as PstFile>::NodeBTree as RootBTree>::LeafPage: RootBTreeLeafPageReadWrite<Pst>,
+    <Pst as PstFile>::BlockBTreeEntry: BlockBTreeEntryReadWrite,
+    <Pst as PstFile>::BlockBTree: BlockBTreeReadWrite<Pst, <Pst as PstFile>::BlockBTreeEntry>,
+    <<Pst as PstFile>::BlockBTree as RootBTree>::IntermediatePage:
+        RootBTreeIntermediatePageReadWrite<
+            Pst,
+            <Pst as PstFile>::BlockBTreeEntry,
+            <<Pst as PstFile>::BlockBTree as RootBTree>::LeafPage,
+        >,
+    <<<Pst as PstFile>::BlockBTree as RootBTree>::IntermediatePage as BTreePage>::Entry:
+        BTreePageEntryReadWrite,
+    <<Pst as PstFile>::BlockBTree as RootBTree>::LeafPage: RootBTreeLeafPageReadWrite<Pst>,
+    <Pst as PstFile>::BlockTrailer: BlockTrailerReadWrite,
+    <Pst as PstFile>::AllocationMapPage: AllocationMapPageReadWrite<Pst>,
+    <Pst as PstFile>::AllocationPageMapPage: AllocationPageMapPageReadWrite<Pst>,
+    <Pst as PstFile>::FreeMapPage: FreeMapPageReadWrite<Pst>,
+    <Pst as PstFile>::FreePageMapPage: FreePageMapPageReadWrite<Pst>,
+    <Pst as PstFile>::DensityListPage: DensityListPageReadWrite<Pst>,
+    <Pst as PstFile>::DataTreeBlock: IntermediateTreeBlockReadWrite,
+    <Pst as PstFile>::DataTreeEntry:
+        IntermediateTreeEntryReadWrite + From<<Pst as PstFile>::BlockId>,
+    <Pst as PstFile>::DataBlock: BlockReadWrite + Clone,
+    <Pst as PstFile>::SubNodeTreeBlockHeader: SubNodeTreeBlockHeaderReadWrite,
+    <Pst as PstFile>::SubNodeTreeBlock: IntermediateTreeBlockReadWrite,
+    <<Pst as PstFile>::SubNodeTreeBlock as IntermediateTreeBlock>::Entry:
+        IntermediateTreeEntryReadWrite,
+    <Pst as PstFile>::SubNodeBlock: IntermediateTreeBlockReadWrite,
+    <<Pst as PstFile>::SubNodeBlock as IntermediateTreeBlock>::Entry:
+        IntermediateTreeEntryReadWrite,
+{
+    fn read_from(source: Arc<dyn ReadAt>, mode: HeaderFieldMode) -> io::Result<Self> {
+        let transaction: Arc<SharedTransaction> = Default::default();
+        let (header, density_list) = {
+            let mut reader = TransactionReader::new(&*source, &transaction);
+            let header = <<Pst as PstFile>::Header as HeaderReadWrite<Pst>>::read_with_mode(
+                &mut reader,
+                mode,
+            )?;
+            let density_list = <<Pst as PstFile>::DensityListPage as DensityListPageReadWrite<
+                Pst,
+            >>::read(&mut reader);
+            (header, density_list)
+        };
+        Ok(Self {
+            source,
+            writer: Err(PstError::OpenedReadOnly),
+            transaction,
+            committed_header: header.clone(),
+            header,
+            density_list,
+            node_cache: Default::default(),
+            block_cache: Default::default(),
+            allocation_strategy: Default::default(),
+            validate_before_commit: false,
+            dirty_nodes: Default::default(),
+            fresh_allocations: Default::default(),
+            released_space: Default::default(),
+            pinned_space: Default::default(),
+            snapshot_pin: Default::default(),
+        })
+    }
+
+    fn open(path: impl AsRef<Path>, mode: HeaderFieldMode) -> io::Result<Self> {
+        let source = Arc::new(PositionedFile::open(&path)?);
+        let writer = OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .map(BufWriter::new)
+            .map(Mutex::new)
+            .map_err(|_| PstError::NoWriteAccess(path.as_ref().display().to_string()));
+        Ok(Self {
+            writer,
+            ..Self::read_from(source, mode)?
+        })
+    }
+
+    fn reader(&self) -> TransactionReader<'_> {
+        TransactionReader::new(&*self.source, &self.transaction)
+    }
+
+    /// Begin a transaction by rebuilding the allocation map if needed and initializing the density
+    /// list, then set [`AmapStatus::Invalid`] in the header till the transaction is finished.
+    ///
+    /// See also [Transactional Semantics](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/bc5a92df-7fc1-4dc2-9c7c-5677237dd73a).
+    fn start_write(&mut self) -> io::Result<()> {
+        self.ensure_density_list()?;
+        self.rebuild_allocation_map()?;
+        self.dirty_nodes.clear();
+        self.fresh_allocations.clear();
+        self.released_space.clear();
+
+        let header = {
+            self.header.update_unique();
+
+            let root = self.header.root_mut();
+            root.set_amap_status(AmapStatus::Invalid);
+            self.header.clone()
+        };
+
+        let writer = &mut self.transaction_writer(WriteKind::Header)?;
+        writer.seek(SeekFrom::Start(0))?;
+        header.write(writer)?;
+        self.commit_writes()?;
+        self.committed_header = header;
+        Ok(())
+    }
+
+    /// Complete a transaction by writing the header and density list to the file, and setting
+    /// [`AmapStatus::Valid2`]. Every page and block written in the transaction is committed to the
+    /// file first, see [`Self::commit_writes`].
+    ///
+    /// See also [Transactional Semantics](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/bc5a92df-7fc1-4dc2-9c7c-5677237dd73a).
+    #[instrument(skip_all)]
+    fn finish_write(&mut self) -> io::Result<()> {
+        self.free_released_space()?;
+
+        // Reset AmapStatus::Valid2 to complete the transaction and then rewrite the updated
+        // density list.
+        let header = {
+            self.header.update_unique();
+            let root = self.header.root_mut();
+            root.set_amap_status(AmapStatus::Valid2);
+            self.header.clone()
+        };
+
+        self.update_density_list_page_id()?;
+        let density_list = {
+            self.density_list.as_ref().ok().and_then(|dl| {
+                <<Pst as PstFile>::DensityListPage as DensityListPageReadWrite<Pst>>::new(
+                    dl.backfill_complete(),
+                    dl.current_page(),
+                    dl.entries(),
+                    *dl.trailer(),
+                )
+                .ok()
+            })
+        };
+
+        let writer = &mut self.transaction_writer(WriteKind::Header)?;
+        writer.seek(SeekFrom::Start(0))?;
+        header.write(writer)?;
+
+        if let Some(density_list) = density_list {
+            let writer = &mut self.transaction_writer(WriteKind::AllocationMap)?;
+            density_list.write(writer)?;
+        }
+
+        self.commit_writes()?;
+        self.committed_header = header;
+        self.dirty_nodes.clear();
+        self.fresh_allocations.clear();
+        Ok(())
+    }
+
+    /// Free the space which was released in this transaction, now that nothing in the new header
+    /// refers to it, unless a [`Store::snapshot`] might still read it. In that case it stays
+    /// allocated till a later transaction finishes after the last snapshot was dropped.
+    fn free_released_space(&mut self) -> io::Result<()> {
+        let mut released = mem::take(&mut self.released_space);
+        if self.snapshot_pin.is_held() {
+            self.pinned_space.append(&mut released);
+            return Ok(());
+        }
+
+        released.append(&mut self.pinned_space);
+        for (index, size) in released {
+            self.free_now(index, size)?;
+        }
+        Ok(())
+    }
+
+    /// Write every page and block in the [`TransactionBuffer`] to the file, once each, in
+    /// [`WriteKind`] order: blocks, then the Block BTree pages which reference them, then the Node
+    /// BTree pages, then the allocation map pages and density list, and finally the header which
+    /// points at the BTree roots. The file is synced before the header is written, so the header
+    /// never refers to pages which are not on disk yet.
+    fn commit_writes(&mut self) -> io::Result<()> {
+        let (file_len, writes) = self.transaction.update(TransactionBuffer::take_ordered);
+
+        let mut writer = self
+            .writer
+            .as_ref()?
+            .lock()
+            .map_err(|_| PstError::LockError)?;
+        let writer = &mut *writer;
+
+        if let Some(file_len) = file_len {
+            writer.flush()?;
+            writer.get_ref().set_len(file_len)?;
+        }
+
+        let mut last_kind = None;
+        for (kind, offset, data) in writes {
+            if last_kind != Some(kind) {
+                writer.flush()?;
+                if kind == WriteKind::Header {
+                    writer.get_ref().sync_data()?;
+                }
+                last_kind = Some(kind);
+            }
+            writer.seek(SeekFrom::Start(offset))?;
+            writer.write_all(&data)?;
+        }
+        writer.flush()
+    }
+
+    /// Roll back a transaction which has not been committed yet: forget every pending page and
+    /// block, restore the header and density list which were committed by [`Self::start_write`],
+    /// and then finish the transaction so the file is left as it was before it started.
+    fn discard_write(&mut self) -> io::Result<()> {
+        self.transaction.update(TransactionBuffer::clear);
+        self.header = self.committed_header.clone();
+        self.density_list = {
+            let mut reader = self.reader();
+            let reader = &mut reader;
+            <<Pst as PstFile>::DensityListPage as DensityListPageReadWrite<Pst>>::read(reader)
+        };
+        self.node_cache.clear();
+        self.block_cache.clear();
+        self.dirty_nodes.clear();
+        self.fresh_allocations.clear();
+        self.released_space.clear();
+        self.finish_write()
+    }
+
+    /// Record writes of `kind` in the [`TransactionBuffer`] till they are committed by
+    /// [`Self::commit_writes`].
+    fn transaction_writer(&self, kind: WriteKind) -> io::Result<TransactionWriter> {
+        self.writer.as_ref()?;
+        Ok(TransactionWriter::new(self.transaction.clone(), kind))
+    }
+
+    /// [Crash Recovery and AMap Rebuilding](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/d9bcc1fd-c66a-41b3-b6d7-ed09d2a25ced)
+    fn rebuild_allocation_map(&mut self) -> io::Result<()> {
+        let root = self.header.root();
+        if AmapStatus::Invalid != root.amap_is_valid() {
+            return Ok(());
+        }
+
+        let num_amap_pages = root
+            .file_eof_index()
+            .index()
+            .into()
+            .checked_sub(AMAP_FIRST_OFFSET)
+            .ok_or(PstError::InvalidAllocationMapOffset(
+                root.file_eof_index().index().into(),
+            ))?
+            .div_ceil(AMAP_DATA_SIZE);
+
+        let mut amap_pages: Vec<_> = (0..num_amap_pages)
+            .map(Self::new_allocation_map_page)
+            .collect::<PstResult<Vec<_>>>()?;
+
+        {
+            let mut reader = self.reader();
+            let reader = &mut reader;
+
+            let node_btree =
+                <Pst::NodeBTree as RootBTreeReadWrite>::read(reader, *root.node_btree())?;
+
+            Self::mark_node_btree_allocations(
+                reader,
+                root.node_btree().index(),
+                &node_btree,
+                &mut amap_pages,
+            )?;
+
+            let block_btree =
+                <Pst::BlockBTree as RootBTreeReadWrite>::read(reader, *root.block_btree())?;
+
+            Self::mark_block_btree_allocations(
+                reader,
+                root.block_btree().index(),
+                &block_btree,
+                &mut amap_pages,
+            )?;
+        }
+
+        let free_bytes =
+            <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(
+                amap_pages.iter().map(|page| page.free_space).sum(),
+            )
+            .map_err(|_| PstError::IntegerConversion)?;
+        let free_bytes = <<Pst as PstFile>::ByteIndex as ByteIndexReadWrite>::new(free_bytes);
+
+        let densities: Vec<_> = amap_pages
+            .iter()
+            .enumerate()
+            .map(|(amap_index, info)| {
+                let amap_index = amap_index as u64;
+                Self::allocation_map_page_density(
+                    amap_index,
+                    &info.amap_page,
+                    self.allocation_map_page_slots(amap_index),
+                )
+            })
+            .collect();
+
+        let pmap_pages: Vec<_> = (0..=(num_amap_pages / 8))
+            .map(|index| {
+                let index =
+                    <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(
+                        index * PMAP_DATA_SIZE + PMAP_FIRST_OFFSET,
+                    )
+                    .map_err(|_| PstError::IntegerConversion)?;
+                let block_id = <Pst as PstFile>::PageId::from(index);
+
+                let trailer = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
+                    PageType::AllocationPageMap,
+                    0,
+                    block_id,
+                    0,
+                );
+
+                let map_bits = [0xFF; mem::size_of::<MapBits>()];
+
+                let pmap_page =
+                    <<Pst as PstFile>::AllocationPageMapPage as AllocationPageMapPageReadWrite<
+                        Pst,
+                    >>::new(map_bits, trailer)?;
+                Ok(pmap_page)
+            })
+            .collect::<PstResult<Vec<_>>>()?;
+
+        let fmap_pages: Vec<_> = (0..(num_amap_pages.max(FMAP_FIRST_SIZE) - FMAP_FIRST_SIZE)
+            .div_ceil(FMAP_PAGE_COUNT))
+            .map(|index| {
+                let amap_index =
+                    FMAP_FIRST_SIZE as usize + (index as usize * mem::size_of::<MapBits>());
+                let index =
+                    <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(
+                        index * FMAP_DATA_SIZE + FMAP_FIRST_OFFSET,
+                    )
+                    .map_err(|_| PstError::IntegerConversion)?;
+                let block_id = <Pst as PstFile>::PageId::from(index);
+
+                let trailer = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
+                    PageType::FreeMap,
+                    0,
+                    block_id,
+                    0,
+                );
+
+                let mut map_bits = [0; mem::size_of::<MapBits>()];
+                for (entry, free_space) in map_bits.iter_mut().zip(
+                    amap_pages
+                        .iter()
+                        .skip(amap_index)
+                        .map(|page| page.max_free_slots()),
+                ) {
+                    *entry = free_space;
+                }
+
+                let fmap_page = <<Pst as PstFile>::FreeMapPage as FreeMapPageReadWrite<Pst>>::new(
+                    map_bits, trailer,
+                )?;
+                Ok(fmap_page)
+            })
+            .collect::<PstResult<Vec<_>>>()?;
+
+        let fpmap_pages: Vec<_> = (0..(num_amap_pages.max(FPMAP_FIRST_SIZE) - FPMAP_FIRST_SIZE)
+            .div_ceil(FPMAP_PAGE_COUNT))
+            .map(|index| {
+                let index =
+                    <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(
+                        index * FPMAP_DATA_SIZE + FPMAP_FIRST_OFFSET,
+                    )
+                    .map_err(|_| PstError::IntegerConversion)?;
+                let block_id = <Pst as PstFile>::PageId::from(index);
+
+                let trailer = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
+                    PageType::FreePageMap,
+                    0,
+                    block_id,
+                    0,
+                );
+
+                let map_bits = [0xFF; mem::size_of::<MapBits>()];
+
+                let fpmap_page = <<Pst as PstFile>::FreePageMapPage as FreePageMapPageReadWrite<
+                    Pst,
+                >>::new(map_bits, trailer)?;
+                Ok(fpmap_page)
+            })
+            .collect::<PstResult<Vec<_>>>()?;
+
+        {
+            let writer = &mut self.transaction_writer(WriteKind::AllocationMap)?;
+
+            for page in amap_pages.into_iter().map(|info| info.amap_page) {
+                writer.seek(SeekFrom::Start(page.trailer().block_id().into_u64()))?;
+                <Pst::AllocationMapPage as AllocationMapPageReadWrite<Pst>>::write(&page, writer)?;
+            }
+
+            for page in pmap_pages.into_iter() {
+                writer.seek(SeekFrom::Start(page.trailer().block_id().into_u64()))?;
+                <Pst::AllocationPageMapPage as AllocationPageMapPageReadWrite<Pst>>::write(
+                    &page, writer,
+                )?;
+            }
+
+            for page in fmap_pages.into_iter() {
+                writer.seek(SeekFrom::Start(page.trailer().block_id().into_u64()))?;
+                <Pst::FreeMapPage as FreeMapPageReadWrite<Pst>>::write(&page, writer)?;
+            }
+
+            for page in fpmap_pages.into_iter() {
+                writer.seek(SeekFrom::Start(page.trailer().block_id().into_u64()))?;
+                <Pst::FreePageMapPage as FreePageMapPageReadWrite<Pst>>::write(&page, writer)?;
+            }
+
+            writer.flush()?;
+        }
+
+        self.update_free_space_maps(&densities, true)?;
+        let header = {
+            self.header.update_unique();
+
+            let root = self.header.root_mut();
+            root.reset_free_size(free_bytes)?;
+            root.set_amap_status(AmapStatus::Valid2);
+
+            self.header.clone()
+        };
+
+        let writer = &mut self.transaction_writer(WriteKind::Header)?;
+        writer.seek(SeekFrom::Start(0))?;
+        header.write(writer)?;
+        writer.flush()
+    }
+
+    /// Recursively mark all of the pages in the [`Node BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    /// as allocated. This does not include any blocks referenced in the nodes or the sub-trees in
+    /// those blocks, blocks will be marked by [`Self::mark_block_btree_allocations`].
+    ///
+    /// See also [Crash Recovery and AMap Rebuilding](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/d9bcc1fd-c66a-41b3-b6d7-ed09d2a25ced).
+    #[instrument(skip_all)]
+    fn mark_node_btree_allocations<R: PstReader>(
+        reader: &mut R,
+        page_index: Pst::ByteIndex,
+        node_btree: &PstFileReadWriteNodeBTree<Pst>,
+        amap_pages: &mut Vec<AllocationMapPageInfo<Pst>>,
+    ) -> io::Result<()> {
+        Self::mark_page_allocation(page_index.index().into(), amap_pages)?;
+
+        if let RootBTreePage::Intermediate(page, ..) = node_btree {
+            let level = page.level();
+            for entry in page.entries() {
+                let block = entry.block();
+                let node_btree = <Pst::NodeBTree as RootBTreeReadWrite>::read(reader, block)?;
+                match &node_btree {
+                    RootBTreePage::Intermediate(page, ..) if page.level() + 1 != level => {
+                        error!(
+                            name: "PstUnexpectedBTreeIntermediatePage",
+                            block = ?block.block(),
+                            index = ?block.index(),
+                            parent = level,
+                            child = page.level(),
+                            "Possible NBT page cycle detected, expected child == parent - 1"
+                        );
+                        return Err(PstError::InvalidBTreePage(block.index().index().into()).into());
+                    }
+                    RootBTreePage::Leaf(_) if level != 1 => {
+                        error!(
+                            name: "PstUnexpectedBTreeLeafPage",
+                            block = ?block.block(),
+                            index = ?block.index(),
+                            parent = level,
+                            child = page.level(),
+                            "Corrupted NBT intermediate page detected, unexpected child leaf page"
+                        );
+                        return Err(PstError::InvalidBTreePage(block.index().index().into()).into());
+                    }
+                    _ => (),
+                }
+                Self::mark_node_btree_allocations(reader, block.index(), &node_btree, amap_pages)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Recursively mark all of the pages and blocks in the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    ///
+    /// See also [Crash Recovery and AMap Rebuilding](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/d9bcc1fd-c66a-41b3-b6d7-ed09d2a25ced).
     #[instrument(skip_all)]
     fn mark_block_btree_allocations<R: PstReader>(
         reader: &mut R,
@@ -920,271 +2834,3929 @@ where
         block_btree: &PstFileReadWriteBlockBTree<Pst>,
         amap_pages: &mut Vec<AllocationMapPageInfo<Pst>>,
     ) -> io::Result<()> {
-        Self::mark_page_allocation(page_index.index().into(), amap_pages)?;
+        Self::mark_page_allocation(page_index.index().into(), amap_pages)?;
+
+        match block_btree {
+            RootBTreePage::Intermediate(page, ..) => {
+                let level = page.level();
+                for entry in page.entries() {
+                    let block = entry.block();
+                    let block_btree = <Pst::BlockBTree as RootBTreeReadWrite>::read(reader, block)?;
+                    match &block_btree {
+                        RootBTreePage::Intermediate(page, ..) if page.level() + 1 != level => {
+                            error!(
+                                name: "PstUnexpectedBTreeIntermediatePage",
+                                block = ?block.block(),
+                                index = ?block.index(),
+                                parent = level,
+                                child = page.level(),
+                                "Possible BBT page cycle detected, expected child == parent - 1"
+                            );
+                            return Err(
+                                PstError::InvalidBTreePage(block.index().index().into()).into()
+                            );
+                        }
+                        RootBTreePage::Leaf(_) if level != 1 => {
+                            error!(
+                                name: "PstUnexpectedBTreeLeafPage",
+                                block = ?block.block(),
+                                index = ?block.index(),
+                                parent = level,
+                                child = page.level(),
+                                "Corrupted BBT intermediate page detected, unexpected child leaf page"
+                            );
+                            return Err(
+                                PstError::InvalidBTreePage(block.index().index().into()).into()
+                            );
+                        }
+                        _ => (),
+                    }
+                    Self::mark_block_btree_allocations(
+                        reader,
+                        block.index(),
+                        &block_btree,
+                        amap_pages,
+                    )?;
+                }
+            }
+            RootBTreePage::Leaf(page) => {
+                for entry in page.entries() {
+                    Self::mark_block_allocation(
+                        entry.block().index().index().into(),
+                        entry.size(),
+                        amap_pages,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Mark a page at the given file offset as allocated.
+    fn mark_page_allocation(
+        index: u64,
+        amap_pages: &mut [AllocationMapPageInfo<Pst>],
+    ) -> io::Result<()> {
+        let (amap_index, offset) = amap_page_index(index)?;
+        let amap_index = usize::try_from(amap_index).map_err(|_| PstError::IntegerConversion)?;
+        let entry = amap_pages
+            .get_mut(amap_index)
+            .ok_or(PstError::AllocationMapPageNotFound(amap_index))?;
+        entry.free_space = entry.free_space.saturating_sub(PAGE_SIZE as u64);
+
+        let bytes = entry.amap_page.map_bits_mut();
+
+        let bit_index = usize::try_from(offset / 64).map_err(|_| PstError::IntegerConversion)?;
+        let byte_index = bit_index / 8;
+        let bit_index = bit_index % 8;
+
+        if bit_index == 0 {
+            bytes[byte_index] = 0xFF;
+        } else {
+            let mask = 0x80_u8 >> bit_index;
+            let mask = mask | (mask - 1);
+            bytes[byte_index] |= mask;
+            bytes[byte_index + 1] |= !mask;
+        }
+
+        Ok(())
+    }
+
+    /// Mark a block at the given file offset and size as allocated.
+    fn mark_block_allocation(
+        index: u64,
+        size: u16,
+        amap_pages: &mut [AllocationMapPageInfo<Pst>],
+    ) -> io::Result<()> {
+        let (amap_index, offset) = amap_page_index(index)?;
+        let amap_index = usize::try_from(amap_index).map_err(|_| PstError::IntegerConversion)?;
+        let entry = amap_pages
+            .get_mut(amap_index)
+            .ok_or(PstError::AllocationMapPageNotFound(amap_index))?;
+        let size = u64::from(block_size(
+            size + <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::SIZE,
+        ));
+        entry.free_space = entry.free_space.saturating_sub(size);
+
+        let bytes = entry.amap_page.map_bits_mut();
+
+        let bit_start = usize::try_from(offset / 64).map_err(|_| PstError::IntegerConversion)?;
+        let bit_end =
+            bit_start + usize::try_from(size / 64).map_err(|_| PstError::IntegerConversion)?;
+        let byte_start = bit_start / 8;
+        let bit_start = bit_start % 8;
+        let byte_end = bit_end / 8;
+        let bit_end = bit_end % 8;
+
+        if byte_start == byte_end {
+            // The allocation fits in a single byte
+            if bit_end > bit_start {
+                let mask_start = 0x80_u8 >> bit_start;
+                let mask_start = mask_start | (mask_start - 1);
+                let mask_end = 0x80_u8 >> bit_end;
+                let mask_end = !(mask_end | (mask_end - 1));
+                let mask = mask_start & mask_end;
+                bytes[byte_start] |= mask;
+            }
+            return Ok(());
+        }
+
+        let byte_start = if bit_start == 0 {
+            byte_start
+        } else {
+            let mask_start = 0x80_u8 >> bit_start;
+            let mask_start = mask_start | (mask_start - 1);
+            bytes[byte_start] |= mask_start;
+            byte_start + 1
+        };
+
+        if bit_end != 0 {
+            let mask_end = 0x80_u8 >> bit_end;
+            let mask_end = !(mask_end | (mask_end - 1));
+            bytes[byte_end] |= mask_end;
+        };
+
+        if byte_end > byte_start {
+            for byte in bytes[byte_start..byte_end].iter_mut() {
+                *byte = 0xFF;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Which of the PMap, FMap, and FPMap pages follow the AMap page at `amap_index`.
+    fn allocation_map_page_layout(amap_index: u64) -> (bool, bool, bool) {
+        let has_pmap_page = amap_index % 8 == 0;
+        let has_fmap_page = has_pmap_page
+            && amap_index >= FMAP_FIRST_SIZE
+            && (amap_index - FMAP_FIRST_SIZE) % FMAP_PAGE_COUNT == 0;
+        let has_fpmap_page = has_pmap_page
+            && amap_index >= FPMAP_FIRST_SIZE
+            && (amap_index - FPMAP_FIRST_SIZE) % FPMAP_PAGE_COUNT == 0;
+        (has_pmap_page, has_fmap_page, has_fpmap_page)
+    }
+
+    /// Create the trailer for an AMap, PMap, FMap, or FPMap page at the given file offset.
+    fn map_page_trailer(
+        page_type: PageType,
+        index: u64,
+    ) -> PstResult<<Pst as PstFile>::PageTrailer> {
+        let index =
+            <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(index)
+                .map_err(|_| PstError::IntegerConversion)?;
+        let block_id = <Pst as PstFile>::PageId::from(index);
+
+        Ok(<<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(page_type, 0, block_id, 0))
+    }
+
+    /// Create an empty AMap page, with only the map pages at the beginning of its range marked as
+    /// allocated.
+    fn new_allocation_map_page(amap_index: u64) -> PstResult<AllocationMapPageInfo<Pst>> {
+        let (has_pmap_page, has_fmap_page, has_fpmap_page) =
+            Self::allocation_map_page_layout(amap_index);
+
+        let trailer =
+            Self::map_page_trailer(PageType::AllocationMap, amap_page_offset(amap_index)?)?;
+
+        let mut map_bits = [0; mem::size_of::<MapBits>()];
+        let mut reserved = 1;
+        if has_pmap_page {
+            reserved += 1;
+        }
+        if has_fmap_page {
+            reserved += 1;
+        }
+        if has_fpmap_page {
+            reserved += 1;
+        }
+
+        let free_space = AMAP_DATA_SIZE - (reserved * PAGE_SIZE) as u64;
+
+        let reserved = &[0xFF; 4][..reserved];
+        map_bits[..reserved.len()].copy_from_slice(reserved);
+
+        let amap_page =
+            <<Pst as PstFile>::AllocationMapPage as AllocationMapPageReadWrite<Pst>>::new(
+                map_bits, trailer,
+            )?;
+        Ok(AllocationMapPageInfo::<Pst> {
+            amap_page,
+            free_space,
+        })
+    }
+
+    /// Extend the file by at least `size` bytes, appending whole AMap ranges along with any PMap,
+    /// FMap, or FPMap pages which belong at the start of each range.
+    #[instrument(skip_all)]
+    fn grow(&mut self, size: u64) -> io::Result<()> {
+        let root = self.header.root();
+        let free_bytes: u64 = root.amap_free_size().index().into();
+        let first_index = self.allocation_map_page_count();
+        let count = size.div_ceil(AMAP_DATA_SIZE).max(1);
+        let amap_indices = first_index..first_index + count;
+        let file_eof = amap_page_offset(amap_indices.end)?;
+        check_file_size_limit(self.header.version(), file_eof)?;
+
+        // If the file ended part way through the range of the last AMap page, the rest of that
+        // range is inside the file now, so its free slots count towards the free space.
+        let last_index = first_index - 1;
+        let last_page = self.read_allocation_map_page(last_index)?;
+        let free_slots = |slots| -> u64 {
+            free_allocation_runs(last_page.map_bits(), slots)
+                .iter()
+                .map(|run| run.end - run.start)
+                .sum()
+        };
+        let max_slots = mem::size_of::<MapBits>() as u64 * 8;
+        let tail_slots =
+            free_slots(max_slots) - free_slots(self.allocation_map_page_slots(last_index));
+
+        let mut free_space = tail_slots * 64;
+        let mut densities = vec![Self::allocation_map_page_density(
+            last_index, &last_page, max_slots,
+        )];
+        {
+            let writer = &mut self.transaction_writer(WriteKind::AllocationMap)?;
+
+            for amap_index in amap_indices.clone() {
+                let amap_offset = amap_page_offset(amap_index)?;
+                let (has_pmap_page, has_fmap_page, has_fpmap_page) =
+                    Self::allocation_map_page_layout(amap_index);
+
+                let info = Self::new_allocation_map_page(amap_index)?;
+                free_space += info.free_space;
+                let max_free_slots = info.max_free_slots();
+                densities.push(Self::allocation_map_page_density(
+                    amap_index,
+                    &info.amap_page,
+                    mem::size_of::<MapBits>() as u64 * 8,
+                ));
+
+                writer.seek(SeekFrom::Start(amap_offset))?;
+                <Pst::AllocationMapPage as AllocationMapPageReadWrite<Pst>>::write(
+                    &info.amap_page,
+                    writer,
+                )?;
+
+                if has_pmap_page {
+                    let offset = amap_offset + PAGE_SIZE as u64;
+                    let trailer = Self::map_page_trailer(PageType::AllocationPageMap, offset)?;
+                    let page = <Pst::AllocationPageMapPage as AllocationPageMapPageReadWrite<
+                        Pst,
+                    >>::new(
+                        [0xFF; mem::size_of::<MapBits>()], trailer
+                    )?;
+                    writer.seek(SeekFrom::Start(offset))?;
+                    <Pst::AllocationPageMapPage as AllocationPageMapPageReadWrite<Pst>>::write(
+                        &page, writer,
+                    )?;
+                }
+
+                if has_fmap_page {
+                    // Only the first entry in a new FMap page refers to an existing AMap page,
+                    // the rest will be filled in by later calls to grow or rebuilding the AMap.
+                    let offset = amap_offset + 2 * PAGE_SIZE as u64;
+                    let trailer = Self::map_page_trailer(PageType::FreeMap, offset)?;
+                    let mut map_bits = [0; mem::size_of::<MapBits>()];
+                    map_bits[0] = max_free_slots;
+                    let page =
+                        <Pst::FreeMapPage as FreeMapPageReadWrite<Pst>>::new(map_bits, trailer)?;
+                    writer.seek(SeekFrom::Start(offset))?;
+                    <Pst::FreeMapPage as FreeMapPageReadWrite<Pst>>::write(&page, writer)?;
+                } else if amap_index >= FMAP_FIRST_SIZE {
+                    // Update the entry in the FMap page which covers this AMap page.
+                    let fmap_index = (amap_index - FMAP_FIRST_SIZE) / FMAP_PAGE_COUNT;
+                    let entry = (amap_index - FMAP_FIRST_SIZE) % FMAP_PAGE_COUNT;
+                    let offset = fmap_index * FMAP_DATA_SIZE + FMAP_FIRST_OFFSET;
+                    writer.seek(SeekFrom::Start(offset))?;
+                    writer.flush()?;
+                    let mut reader = self.reader();
+                    let reader = &mut reader;
+                    reader.seek(SeekFrom::Start(offset))?;
+                    let mut page = <Pst::FreeMapPage as FreeMapPageReadWrite<Pst>>::read(reader)?;
+                    page.map_bits_mut()[entry as usize] = max_free_slots;
+                    <Pst::FreeMapPage as FreeMapPageReadWrite<Pst>>::write(&page, writer)?;
+                }
+
+                if has_fpmap_page {
+                    let offset = amap_offset + 3 * PAGE_SIZE as u64;
+                    let trailer = Self::map_page_trailer(PageType::FreePageMap, offset)?;
+                    let page = <Pst::FreePageMapPage as FreePageMapPageReadWrite<Pst>>::new(
+                        [0xFF; mem::size_of::<MapBits>()],
+                        trailer,
+                    )?;
+                    writer.seek(SeekFrom::Start(offset))?;
+                    <Pst::FreePageMapPage as FreePageMapPageReadWrite<Pst>>::write(&page, writer)?;
+                }
+            }
+
+            self.transaction
+                .update(|transaction| transaction.set_file_len(file_eof));
+        }
+
+        let amap_last = amap_page_offset(amap_indices.end - 1)?;
+        let to_byte_index = |index: u64| {
+            <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(index)
+                .map(<<Pst as PstFile>::ByteIndex as ByteIndexReadWrite>::new)
+                .map_err(|_| PstError::IntegerConversion)
+        };
+        let file_eof = to_byte_index(file_eof)?;
+        let amap_last = to_byte_index(amap_last)?;
+        let free_bytes = to_byte_index(free_bytes + free_space)?;
+
+        let root = self.header.root_mut();
+        root.set_file_eof_index(file_eof);
+        root.set_amap_last_index(amap_last);
+        root.reset_free_size(free_bytes)?;
+
+        Ok(self.update_free_space_maps(&densities, false)?)
+    }
+
+    /// Initialize the density list at the beginning of a transaction if it is missing, corrupt, or
+    /// the page ID doesn't match the next page ID in the header.
+    fn ensure_density_list(&mut self) -> PstResult<()> {
+        if let Ok(density_list) = self.density_list.as_ref() {
+            if density_list.trailer().block_id() == self.header.next_page() {
+                return Ok(());
+            }
+        }
+
+        let (current_page, _) =
+            amap_page_index(self.header.root().amap_last_index().index().into())?;
+        let current_page = u32::try_from(current_page).map_err(|_| PstError::IntegerConversion)?;
+        let block_id = self.header.next_page();
+        let signature = PageType::DensityList
+            .signature(ndb::page::DENSITY_LIST_FILE_OFFSET, block_id.into_u64());
+        let trailer = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
+            PageType::DensityList,
+            signature,
+            block_id,
+            0,
+        );
+        let density_list =
+            <<Pst as PstFile>::DensityListPage as DensityListPageReadWrite<Pst>>::new(
+                false,
+                current_page,
+                &[],
+                trailer,
+            )?;
+
+        self.density_list = Ok(density_list);
+        Ok(())
+    }
+
+    /// Similar to [`Self::ensure_density_list`], but instead of resetting the density list, it
+    /// assumes that it's already initialized and only updates the page ID if it doesn't match.
+    fn update_density_list_page_id(&mut self) -> PstResult<()> {
+        let Ok(density_list) = self.density_list.as_ref() else {
+            return Ok(());
+        };
+
+        let next_page = self.header.next_page();
+        if density_list.trailer().block_id() == next_page {
+            return Ok(());
+        }
+
+        let signature = PageType::DensityList
+            .signature(ndb::page::DENSITY_LIST_FILE_OFFSET, next_page.into_u64());
+        let trailer = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
+            PageType::DensityList,
+            signature,
+            next_page,
+            0,
+        );
+
+        let density_list =
+            <<Pst as PstFile>::DensityListPage as DensityListPageReadWrite<Pst>>::new(
+                density_list.backfill_complete(),
+                density_list.current_page(),
+                density_list.entries(),
+                trailer,
+            )?;
+
+        self.density_list = Ok(density_list);
+        Ok(())
+    }
+
+    fn read_allocation_map_page(
+        &self,
+        amap_index: u64,
+    ) -> io::Result<<Pst as PstFile>::AllocationMapPage> {
+        let mut reader = self.reader();
+        let reader = &mut reader;
+        reader.seek(SeekFrom::Start(amap_page_offset(amap_index)?))?;
+        <<Pst as PstFile>::AllocationMapPage as AllocationMapPageReadWrite<Pst>>::read(reader)
+    }
+
+    /// Write back a modified [`AllocationMapPage`], and update the free space in the root, the
+    /// header `rgbFM` entry and the density list entry for that page.
+    fn write_allocation_map_page(
+        &mut self,
+        amap_index: u64,
+        amap_page: &<Pst as PstFile>::AllocationMapPage,
+        free_space: u64,
+    ) -> io::Result<()> {
+        {
+            let writer = &mut self.transaction_writer(WriteKind::AllocationMap)?;
+            writer.seek(SeekFrom::Start(amap_page_offset(amap_index)?))?;
+            <Pst::AllocationMapPage as AllocationMapPageReadWrite<Pst>>::write(amap_page, writer)?;
+            writer.flush()?;
+        }
+
+        let density = Self::allocation_map_page_density(
+            amap_index,
+            amap_page,
+            self.allocation_map_page_slots(amap_index),
+        );
+        self.update_free_space_maps(&[density], false)?;
+
+        let free_space =
+            <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(
+                free_space,
+            )
+            .map_err(|_| PstError::IntegerConversion)?;
+        let free_space = <<Pst as PstFile>::ByteIndex as ByteIndexReadWrite>::new(free_space);
+        self.header.root_mut().reset_free_size(free_space)?;
+        Ok(())
+    }
+
+    /// Number of 64 byte slots tracked by the AMap page at `amap_index` which are inside the file.
+    fn allocation_map_page_slots(&self, amap_index: u64) -> u64 {
+        let file_eof = self.header.root().file_eof_index().index().into();
+        let amap_offset = amap_page_offset(amap_index).unwrap_or(u64::MAX);
+        (file_eof.saturating_sub(amap_offset) / 64).min(mem::size_of::<MapBits>() as u64 * 8)
+    }
+
+    fn allocation_map_page_count(&self) -> u64 {
+        let amap_last = self.header.root().amap_last_index().index().into();
+        amap_last.saturating_sub(AMAP_FIRST_OFFSET) / AMAP_DATA_SIZE + 1
+    }
+
+    fn allocation_map_page_density(
+        amap_index: u64,
+        amap_page: &<Pst as PstFile>::AllocationMapPage,
+        slots: u64,
+    ) -> AllocationMapPageDensity {
+        let free_slots: u64 = free_allocation_runs(amap_page.map_bits(), slots)
+            .iter()
+            .map(|run| run.end - run.start)
+            .sum();
+        AllocationMapPageDensity {
+            amap_index,
+            free_slots: u16::try_from(free_slots).unwrap_or(u16::MAX),
+            max_free_slots: u8::try_from(amap_page.find_free_bits(0xFF).len()).unwrap_or(0xFF),
+        }
+    }
+
+    /// Derive the [`AllocationMapPageDensity`] of every AMap page in the file.
+    fn allocation_map_densities(&self) -> io::Result<Vec<AllocationMapPageDensity>> {
+        (0..self.allocation_map_page_count())
+            .map(|amap_index| {
+                let amap_page = self.read_allocation_map_page(amap_index)?;
+                Ok(Self::allocation_map_page_density(
+                    amap_index,
+                    &amap_page,
+                    self.allocation_map_page_slots(amap_index),
+                ))
+            })
+            .collect()
+    }
+
+    /// See [`PstFile::free_space_map`].
+    fn free_space_map(&self) -> io::Result<FreeSpaceMap> {
+        let root = self.header.root();
+        let free_map = self.header.free_map();
+        let pages = (0..self.allocation_map_page_count())
+            .map(|amap_index| {
+                let amap_page = self.read_allocation_map_page(amap_index)?;
+                let slots = self.allocation_map_page_slots(amap_index);
+                let offset = amap_page_offset(amap_index)?;
+                let free_ranges = free_allocation_runs(amap_page.map_bits(), slots)
+                    .into_iter()
+                    .map(|run| offset + run.start * 64..offset + run.end * 64)
+                    .collect();
+                Ok(AllocationMapFreeSpace {
+                    density: Self::allocation_map_page_density(amap_index, &amap_page, slots),
+                    range: offset..offset + slots * 64,
+                    free_ranges,
+                    free_map_entry: usize::try_from(amap_index)
+                        .ok()
+                        .and_then(|amap_index| free_map.get(amap_index))
+                        .copied(),
+                })
+            })
+            .collect::<io::Result<_>>()?;
+        let density_list = self
+            .density_list
+            .as_ref()
+            .ok()
+            .map(|density_list| DensityListHints {
+                backfill_complete: density_list.backfill_complete(),
+                current_page: density_list.current_page(),
+                entries: density_list.entries().to_vec(),
+            });
+
+        Ok(FreeSpaceMap {
+            file_eof: root.file_eof_index().index().into(),
+            root_free_bytes: root.amap_free_size().index().into(),
+            pages,
+            density_list,
+        })
+    }
+
+    /// Update the header `rgbFM` entries and the density list from `densities`. If `replace` is
+    /// set, the density list is rebuilt from `densities` alone, otherwise they are merged into the
+    /// existing entries.
+    fn update_free_space_maps(
+        &mut self,
+        densities: &[AllocationMapPageDensity],
+        replace: bool,
+    ) -> PstResult<()> {
+        let free_map =
+            <<Pst as PstFile>::Header as HeaderReadWrite<Pst>>::first_free_map(&mut self.header);
+        for density in densities {
+            if let Some(entry) = usize::try_from(density.amap_index)
+                .ok()
+                .and_then(|amap_index| free_map.get_mut(amap_index))
+            {
+                *entry = density.max_free_slots;
+            }
+        }
+
+        let page_count = self.allocation_map_page_count();
+        let Ok(density_list) = self.density_list.as_ref() else {
+            return Ok(());
+        };
+
+        let mut pages: BTreeMap<_, _> = if replace {
+            Default::default()
+        } else {
+            density_list
+                .entries()
+                .iter()
+                .map(|entry| (u64::from(entry.page()), entry.free_slots()))
+                .collect()
+        };
+        pages.extend(
+            densities
+                .iter()
+                .map(|density| (density.amap_index, density.free_slots)),
+        );
+        pages.retain(|&amap_index, _| amap_index < page_count);
+
+        let entries = density_list_entries(
+            pages,
+            <<Pst as PstFile>::DensityListPage as DensityListPageReadWrite<Pst>>::MAX_ENTRIES,
+        )?;
+        let density_list =
+            <<Pst as PstFile>::DensityListPage as DensityListPageReadWrite<Pst>>::new(
+                density_list.backfill_complete(),
+                density_list.current_page(),
+                &entries,
+                *density_list.trailer(),
+            )?;
+
+        self.density_list = Ok(density_list);
+        Ok(())
+    }
+
+    /// Compare the header `rgbFM` entries and the density list with the AMap pages.
+    fn check_free_space_maps(&self) -> io::Result<Vec<FreeSpaceMapMismatch>> {
+        let densities = self.allocation_map_densities()?;
+        let density_list = self
+            .density_list
+            .as_ref()
+            .map(|density_list| density_list.entries())
+            .unwrap_or_default();
+        Ok(compare_free_space_maps(
+            &densities,
+            self.header.free_map(),
+            density_list,
+        ))
+    }
+
+    /// Recompute the header `rgbFM` entries and the density list from the AMap pages.
+    fn rebuild_free_space_maps(&mut self) -> io::Result<()> {
+        let densities = self.allocation_map_densities()?;
+        Ok(self.update_free_space_maps(&densities, true)?)
+    }
+
+    /// Find a run of free space in the allocation map which fits `size` bytes according to the
+    /// current [`AllocationStrategy`], mark it as allocated, and return the file offset. Pages are
+    /// aligned on a [`PAGE_SIZE`] boundary. If there is not enough free space, the file will
+    /// [grow](Self::grow) to make room.
+    fn allocate(&mut self, size: u64, is_page: bool) -> io::Result<u64> {
+        let index = self.allocate_or_grow(size, is_page, true)?;
+        self.fresh_allocations.insert(index);
+        Ok(index)
+    }
+
+    fn allocate_or_grow(&mut self, size: u64, is_page: bool, grow: bool) -> io::Result<u64> {
+        let free_bytes = self.header.root().amap_free_size().index().into();
+        let bit_count = size.div_ceil(64);
+        let alignment = if is_page { PAGE_SIZE as u64 / 64 } else { 1 };
+        let fit = |run: &Range<u64>| {
+            let start = run.start.next_multiple_of(alignment);
+            (start + bit_count <= run.end).then_some(start)
+        };
+
+        let num_amap_pages = self.allocation_map_page_count();
+        let amap_indices: Vec<_> = match self.allocation_strategy {
+            AllocationStrategy::FirstFit => {
+                // Visit the pages in the density list first, since it's sorted by free space.
+                let mut amap_indices: Vec<_> = self
+                    .density_list
+                    .as_ref()
+                    .map(|density_list| {
+                        density_list
+                            .entries()
+                            .iter()
+                            .map(|entry| u64::from(entry.page()))
+                            .filter(|&page| page < num_amap_pages)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let listed: HashSet<_> = amap_indices.iter().copied().collect();
+                amap_indices.extend((0..num_amap_pages).filter(|page| !listed.contains(page)));
+                amap_indices
+            }
+            AllocationStrategy::BestFit => (0..num_amap_pages).collect(),
+            AllocationStrategy::AppendAtEof => vec![num_amap_pages - 1],
+        };
+
+        // (amap_index, start, run_length)
+        let mut found: Option<(u64, u64, u64)> = None;
+        for amap_index in amap_indices {
+            let amap_page = self.read_allocation_map_page(amap_index)?;
+            let runs = free_allocation_runs(
+                amap_page.map_bits(),
+                self.allocation_map_page_slots(amap_index),
+            );
+
+            match self.allocation_strategy {
+                AllocationStrategy::FirstFit => {
+                    if let Some((run, start)) = runs
+                        .iter()
+                        .find_map(|run| fit(run).map(|start| (run, start)))
+                    {
+                        found = Some((amap_index, start, run.end - run.start));
+                        break;
+                    }
+                }
+                AllocationStrategy::BestFit => {
+                    for run in runs.iter() {
+                        let Some(start) = fit(run) else {
+                            continue;
+                        };
+                        let length = run.end - run.start;
+                        if found.is_none_or(|(_, _, best)| length < best) {
+                            found = Some((amap_index, start, length));
+                        }
+                    }
+                    if found.is_some_and(|(_, _, best)| best == bit_count) {
+                        break;
+                    }
+                }
+                AllocationStrategy::AppendAtEof => {
+                    let max_bits = self.allocation_map_page_slots(amap_index);
+                    if let Some((run, start)) = runs
+                        .last()
+                        .filter(|run| run.end == max_bits)
+                        .and_then(|run| fit(run).map(|start| (run, start)))
+                    {
+                        found = Some((amap_index, start, run.end - run.start));
+                    }
+                }
+            }
+        }
+
+        let Some((amap_index, start, _)) = found else {
+            if grow {
+                self.grow(size)?;
+                return self.allocate_or_grow(size, is_page, false);
+            }
+            return Err(PstError::AllocationFailed(size).into());
+        };
+
+        let mut amap_page = self.read_allocation_map_page(amap_index)?;
+        let map_bits = amap_page.map_bits_mut();
+        for bit in start..start + bit_count {
+            map_bits[(bit / 8) as usize] |= 0x80 >> (bit % 8);
+        }
+
+        let size = bit_count * 64;
+        self.write_allocation_map_page(amap_index, &amap_page, free_bytes.saturating_sub(size))?;
+
+        Ok(amap_page_offset(amap_index)? + start * 64)
+    }
+
+    /// Measure the free space and fragmentation across all of the AMap pages.
+    fn allocation_statistics(&self) -> io::Result<AllocationMapStatistics> {
+        let mut statistics = AllocationMapStatistics::default();
+        for amap_index in 0..self.allocation_map_page_count() {
+            let amap_page = self.read_allocation_map_page(amap_index)?;
+            let runs = free_allocation_runs(
+                amap_page.map_bits(),
+                self.allocation_map_page_slots(amap_index),
+            );
+
+            for run in runs {
+                let length = (run.end - run.start) * 64;
+                statistics.free_bytes += length;
+                statistics.free_run_count += 1;
+                statistics.largest_free_run = statistics.largest_free_run.max(length);
+            }
+        }
+        Ok(statistics)
+    }
+
+    /// Mark the space allocated with [`Self::allocate`] at `index` as free again. Space which was
+    /// allocated in this transaction is freed right away, but the committed file might still refer
+    /// to anything else, e.g. a BTree page which was replaced by a copy, so that is only freed when
+    /// the transaction is finished.
+    fn free(&mut self, index: u64, size: u64) -> io::Result<()> {
+        if self.fresh_allocations.remove(&index) {
+            self.free_now(index, size)
+        } else {
+            self.released_space.push((index, size));
+            Ok(())
+        }
+    }
+
+    fn free_now(&mut self, index: u64, size: u64) -> io::Result<()> {
+        let free_bytes = self.header.root().amap_free_size().index().into();
+
+        let (amap_index, offset) = amap_page_index(index)?;
+        let start = offset / 64;
+        let bit_count = size.div_ceil(64);
+
+        let mut amap_page = self.read_allocation_map_page(amap_index)?;
+        let map_bits = amap_page.map_bits_mut();
+        for bit in start..start + bit_count {
+            map_bits[(bit / 8) as usize] &= !(0x80 >> (bit % 8));
+        }
+
+        self.write_allocation_map_page(amap_index, &amap_page, free_bytes + bit_count * 64)
+    }
+
+    /// Allocate space for a new [`PAGE_SIZE`] page and assign it the next page ID.
+    fn allocate_page(&mut self) -> io::Result<<Pst as PstFile>::PageRef> {
+        let index = self.allocate(PAGE_SIZE as u64, true)?;
+        let index =
+            <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(index)
+                .map_err(|_| PstError::IntegerConversion)?;
+        let index = <<Pst as PstFile>::ByteIndex as ByteIndexReadWrite>::new(index);
+
+        let page_id = self.header.next_page();
+        self.header.set_next_page(page_id.next()?);
+
+        Ok(<<Pst as PstFile>::PageRef as BlockRefReadWrite>::new(
+            page_id, index,
+        ))
+    }
+
+    /// Allocate space for a block holding `size` bytes of data and assign it the next block ID.
+    fn allocate_block(
+        &mut self,
+        size: u16,
+        is_internal: bool,
+    ) -> io::Result<<Pst as PstFile>::BlockRef> {
+        let size = size + <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::SIZE;
+        let index = self.allocate(u64::from(block_size(size)), false)?;
+        let index =
+            <<<Pst as PstFile>::ByteIndex as ByteIndex>::Index as TryFrom<u64>>::try_from(index)
+                .map_err(|_| PstError::IntegerConversion)?;
+        let index = <<Pst as PstFile>::ByteIndex as ByteIndexReadWrite>::new(index);
+
+        let next_block = self.header.next_block();
+        let block_id =
+            <<Pst as PstFile>::BlockId as BlockIdReadWrite>::new(is_internal, next_block.index())?;
+        self.header.set_next_block(next_block.next()?);
+
+        Ok(<<Pst as PstFile>::BlockRef as BlockRefReadWrite>::new(
+            block_id, index,
+        ))
+    }
+
+    /// Write a new external data block and add it to the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    fn write_data_block(&mut self, data: &[u8]) -> io::Result<<Pst as PstFile>::BlockId> {
+        let max_size =
+            MAX_BLOCK_SIZE - <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::SIZE;
+        let size = u16::try_from(data.len())
+            .ok()
+            .filter(|size| (1..=max_size).contains(size))
+            .ok_or(NdbError::InvalidBlockSize(max_size))?;
+
+        let block = self.allocate_block(size, false)?;
+        let index = block.index().index().into();
+        let block_id = block.block();
+        let signature = block_signature(index, block_id.into_u64());
+        let trailer = <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::new(
+            size, signature, 0, block_id,
+        )?;
+        let data_block = <<Pst as PstFile>::DataBlock as BlockReadWrite>::new(
+            self.header.crypt_method(),
+            data.to_vec(),
+            trailer,
+        )?;
+
+        {
+            let writer = &mut self.transaction_writer(WriteKind::Block)?;
+            writer.seek(SeekFrom::Start(index))?;
+            data_block.write(writer)?;
+            writer.flush()?;
+        }
+
+        let entry =
+            <<Pst as PstFile>::BlockBTreeEntry as BlockBTreeEntryReadWrite>::new(block, size);
+        self.insert_block_btree_entry(entry)?;
+
+        Ok(block_id)
+    }
+
+    /// Insert a new entry in the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    fn insert_block_btree_entry(
+        &mut self,
+        entry: <Pst as PstFile>::BlockBTreeEntry,
+    ) -> io::Result<()> {
+        self.change_block_btree(|reader, writer, root, pages| {
+            let root = PstFileReadWriteBlockBTree::<Pst>::insert_entry(
+                reader, writer, root, entry, pages,
+            )?;
+            Ok(((), root))
+        })
+    }
+
+    /// Make a copy-on-write `change` to the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085),
+    /// with enough pages allocated up front to copy and split every level of the tree, see
+    /// [`BTreePageAllocation`]. Then switch the header to the new root which `change` returns,
+    /// and free the pages it replaced.
+    fn change_block_btree<T>(
+        &mut self,
+        change: impl FnOnce(
+            &mut TransactionReader<'_>,
+            &mut TransactionWriter,
+            <Pst as PstFile>::PageRef,
+            &mut BTreePageAllocation<'_, Pst>,
+        ) -> io::Result<(T, <Pst as PstFile>::PageRef)>,
+    ) -> io::Result<T> {
+        let root = *self.header.root().block_btree();
+        let level =
+            <Pst::BlockBTree as RootBTreeReadWrite>::read(&mut self.reader(), root)?.level();
+        let free_pages = (0..BTreePageAllocation::<Pst>::pages_needed(level))
+            .map(|_| self.allocate_page())
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let (result, root, unused, retired) = {
+            let reader = &mut self.reader();
+            let writer = &mut self.transaction_writer(WriteKind::BlockBTree)?;
+            let mut pages = BTreePageAllocation::new(&self.fresh_allocations, free_pages);
+            let (result, root) = change(reader, writer, root, &mut pages)?;
+            writer.flush()?;
+            let (unused, retired) = pages.into_pages();
+            (result, root, unused, retired)
+        };
+
+        self.header.root_mut().set_block_btree(root);
+        for page in unused.into_iter().chain(retired) {
+            self.free(page.index().index().into(), PAGE_SIZE as u64)?;
+        }
+        self.block_cache.clear();
+        Ok(result)
+    }
+
+    /// Same as [`Self::change_block_btree`], but for the [`Node BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    fn change_node_btree<T>(
+        &mut self,
+        change: impl FnOnce(
+            &mut TransactionReader<'_>,
+            &mut TransactionWriter,
+            <Pst as PstFile>::PageRef,
+            &mut BTreePageAllocation<'_, Pst>,
+        ) -> io::Result<(T, <Pst as PstFile>::PageRef)>,
+    ) -> io::Result<T> {
+        let root = *self.header.root().node_btree();
+        let level = <Pst::NodeBTree as RootBTreeReadWrite>::read(&mut self.reader(), root)?.level();
+        let free_pages = (0..BTreePageAllocation::<Pst>::pages_needed(level))
+            .map(|_| self.allocate_page())
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let (result, root, unused, retired) = {
+            let reader = &mut self.reader();
+            let writer = &mut self.transaction_writer(WriteKind::NodeBTree)?;
+            let mut pages = BTreePageAllocation::new(&self.fresh_allocations, free_pages);
+            let (result, root) = change(reader, writer, root, &mut pages)?;
+            writer.flush()?;
+            let (unused, retired) = pages.into_pages();
+            (result, root, unused, retired)
+        };
+
+        self.header.root_mut().set_node_btree(root);
+        for page in unused.into_iter().chain(retired) {
+            self.free(page.index().index().into(), PAGE_SIZE as u64)?;
+        }
+        self.node_cache.clear();
+        Ok(result)
+    }
+
+    /// Decrement the reference count on a block, and once nothing references it anymore, remove it
+    /// from the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085)
+    /// and free the space it used, along with any child blocks in a data tree.
+    fn release_block(&mut self, block: <Pst as PstFile>::BlockId) -> io::Result<()> {
+        self.release_block_contents(block, BlockContents::DataTree)
+    }
+
+    /// Same as [`Self::release_block`], but the children of the block depend on its `contents`.
+    fn release_block_contents(
+        &mut self,
+        block: <Pst as PstFile>::BlockId,
+        contents: BlockContents,
+    ) -> io::Result<()> {
+        let encoding = self.header.crypt_method();
+        let root = *self.header.root().block_btree();
+
+        let (mut entry, children) = {
+            let mut reader = self.reader();
+            let reader = &mut reader;
+
+            let block_btree = <Pst::BlockBTree as RootBTreeReadWrite>::read(reader, root)?;
+            let mut page_cache = self.block_cache.shard();
+            let entry = block_btree.find_entry(reader, block.search_key(), &mut page_cache)?;
+            if entry.ref_count() > UNSHARED_BLOCK_REF_COUNT {
+                (entry, vec![])
+            } else {
+                let children = match contents {
+                    BlockContents::DataTree => {
+                        match DataTree::<Pst>::read(reader, encoding, &entry)? {
+                            DataTree::Intermediate(block) => block
+                                .entries()
+                                .iter()
+                                .map(|entry| (entry.block(), BlockContents::DataTree))
+                                .collect(),
+                            DataTree::Leaf(_) => vec![],
+                        }
+                    }
+                    BlockContents::SubNodeTree { with_entries } => {
+                        match SubNodeTree::<Pst>::read(reader, &entry)? {
+                            SubNodeTree::Intermediate(block) => block
+                                .entries()
+                                .iter()
+                                .map(|entry| (entry.block(), contents))
+                                .collect(),
+                            SubNodeTree::Leaf(block) if with_entries => block
+                                .entries()
+                                .iter()
+                                .flat_map(|entry| {
+                                    iter::once((entry.block(), BlockContents::DataTree)).chain(
+                                        entry.sub_node().map(|sub_node| (sub_node, contents)),
+                                    )
+                                })
+                                .collect(),
+                            SubNodeTree::Leaf(_) => vec![],
+                        }
+                    }
+                };
+                (entry, children)
+            }
+        };
+
+        if entry.ref_count() > UNSHARED_BLOCK_REF_COUNT {
+            entry.set_ref_count(entry.ref_count() - 1);
+            return self.change_block_btree(|reader, writer, root, pages| {
+                let root = PstFileReadWriteBlockBTree::<Pst>::update_entry(
+                    reader, writer, root, entry, pages,
+                )?;
+                Ok(((), root))
+            });
+        }
+
+        let entry = self.change_block_btree(|reader, writer, root, pages| {
+            PstFileReadWriteBlockBTree::<Pst>::remove_entry(
+                reader,
+                writer,
+                root,
+                entry.key(),
+                pages,
+            )
+        })?;
+
+        let size = entry.size() + <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::SIZE;
+        self.free(
+            entry.block().index().index().into(),
+            u64::from(block_size(size)),
+        )?;
+
+        for (child, contents) in children {
+            self.release_block_contents(child, contents)?;
+        }
+
+        Ok(())
+    }
+
+    /// Overwrite the entry for a node in the [`Node BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    fn update_node_entry(&mut self, entry: <Pst as PstFile>::NodeBTreeEntry) -> io::Result<()> {
+        self.change_node_btree(|reader, writer, root, pages| {
+            let root =
+                PstFileReadWriteNodeBTree::<Pst>::update_entry(reader, writer, root, entry, pages)?;
+            Ok(((), root))
+        })
+    }
+
+    /// Read the data blocks of `node` and of every sub-node in its sub-node tree.
+    fn read_node_data(&self, node: NodeId) -> io::Result<NodeData> {
+        let entry = self.read_node(node)?;
+        self.read_sub_node_data(entry.data(), entry.sub_node())
+    }
+
+    fn read_sub_node_data(
+        &self,
+        block: <Pst as PstFile>::BlockId,
+        sub_node: Option<<Pst as PstFile>::BlockId>,
+    ) -> io::Result<NodeData> {
+        let mut data = NodeData::new(self.read_data_blocks(block)?);
+        for entry in self.read_sub_node_entries(sub_node)? {
+            let sub_node = self.read_sub_node_data(entry.block(), entry.sub_node())?;
+            data.insert_sub_node(entry.node(), sub_node);
+        }
+        Ok(data)
+    }
+
+    /// Write the data blocks of `data` to a new data tree, and each of its sub-nodes to a new
+    /// sub-node tree. Returns the data tree and sub-node tree for a [`NodeBTreeEntry`] or a
+    /// [`LeafSubNodeTreeEntry`].
+    fn write_node_data(
+        &mut self,
+        data: &NodeData,
+    ) -> io::Result<(<Pst as PstFile>::BlockId, Option<<Pst as PstFile>::BlockId>)> {
+        let (block, _) = self.write_data_blocks(data.blocks())?;
+        let entries = data
+            .sub_nodes()
+            .iter()
+            .map(|(node, data)| {
+                let (block, sub_node) = self.write_node_data(data)?;
+                Ok(LeafSubNodeTreeEntry::new(*node, block, sub_node))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let sub_node = self.write_sub_node_tree(&entries)?;
+        Ok((block, sub_node))
+    }
+
+    /// Write `data` and its sub-nodes, and insert a new entry for `node` which points at them.
+    fn create_node(
+        &mut self,
+        node: NodeId,
+        parent: Option<NodeId>,
+        data: &NodeData,
+    ) -> io::Result<()> {
+        // Check for an existing node before writing any blocks, so they are not left behind.
+        if optional_btree_entry(self.read_node(node))?.is_some() {
+            return Err(NdbError::DuplicateBTreeEntry(u64::from(u32::from(node))).into());
+        }
+        let (block, sub_node) = self.write_node_data(data)?;
+        self.insert_node_btree_entry(
+            <<Pst as PstFile>::NodeBTreeEntry as NodeBTreeEntryReadWrite>::new(
+                node, block, sub_node, parent,
+            ),
+        )?;
+        self.dirty_nodes.insert(node);
+        self.block_cache.clear();
+        Ok(())
+    }
+
+    /// Write `data` to a new data tree and point the node at it, then release the previous data
+    /// tree. The sub-nodes in `data` are added to the sub-node tree, replacing any existing
+    /// sub-nodes with the same [`NodeId`], and every other [`NodeIdType::ListsTablesProperties`]
+    /// sub-node is released because it belonged to the previous data tree.
+    fn replace_node(&mut self, node: NodeId, data: &NodeData) -> io::Result<()> {
+        let entry = self.read_node(node)?;
+        let entry = self.unshare_sub_node_tree(entry)?;
+        let (block, _) = self.write_data_blocks(data.blocks())?;
+
+        let (mut entries, released): (Vec<_>, Vec<_>) = self
+            .read_sub_node_entries(entry.sub_node())?
+            .into_iter()
+            .partition(|entry| {
+                !matches!(
+                    entry.node().id_type(),
+                    Ok(NodeIdType::ListsTablesProperties)
+                ) && data.sub_node(entry.node()).is_none()
+            });
+        for (sub_node, data) in data.sub_nodes() {
+            let (block, nested) = self.write_node_data(data)?;
+            insert_sub_node_entry(
+                &mut entries,
+                LeafSubNodeTreeEntry::new(*sub_node, block, nested),
+            );
+        }
+        let sub_node = self.write_sub_node_tree(&entries)?;
+
+        self.update_node_entry(
+            <<Pst as PstFile>::NodeBTreeEntry as NodeBTreeEntryReadWrite>::new(
+                entry.node(),
+                block,
+                sub_node,
+                entry.parent(),
+            ),
+        )?;
+
+        self.release_block(entry.data())?;
+        if let Some(sub_node) = entry.sub_node() {
+            self.release_block_contents(
+                sub_node,
+                BlockContents::SubNodeTree {
+                    with_entries: false,
+                },
+            )?;
+        }
+        for released in released {
+            self.release_block(released.block())?;
+            if let Some(nested) = released.sub_node() {
+                self.release_block_contents(
+                    nested,
+                    BlockContents::SubNodeTree { with_entries: true },
+                )?;
+            }
+        }
+        self.dirty_nodes.insert(node);
+        self.block_cache.clear();
+        Ok(())
+    }
+
+    /// Read every [SLENTRY](LeafSubNodeTreeEntry) in the sub-node tree starting at `sub_node`,
+    /// sorted by [`NodeId`].
+    fn read_sub_node_entries(
+        &self,
+        sub_node: Option<<Pst as PstFile>::BlockId>,
+    ) -> io::Result<Vec<LeafSubNodeTreeEntry<<Pst as PstFile>::BlockId>>> {
+        let Some(sub_node) = sub_node else {
+            return Ok(vec![]);
+        };
+
+        let block_btree = *self.header.root().block_btree();
+        let mut reader = self.reader();
+        let reader = &mut reader;
+        let block_btree =
+            <<Pst as PstFile>::BlockBTree as RootBTreeReadWrite>::read(reader, block_btree)?;
+        let mut page_cache = self.block_cache.shard();
+        let block = block_btree.find_entry(reader, sub_node.search_key(), &mut page_cache)?;
+        let sub_nodes = SubNodeTree::<Pst>::read(reader, &block)?;
+        let entries = sub_nodes.entries(reader, &block_btree, &mut page_cache)?;
+        Ok(entries.collect())
+    }
+
+    /// Write a new XBLOCK, XXBLOCK, SLBLOCK or SIBLOCK and add it to the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    fn write_intermediate_block<IntermediateBlock>(
+        &mut self,
+        header: <IntermediateBlock as IntermediateTreeBlock>::Header,
+        entries: Vec<<IntermediateBlock as IntermediateTreeBlock>::Entry>,
+    ) -> io::Result<<Pst as PstFile>::BlockId>
+    where
+        IntermediateBlock: IntermediateTreeBlockReadWrite<Trailer = <Pst as PstFile>::BlockTrailer>,
+        <IntermediateBlock as IntermediateTreeBlock>::Header: IntermediateTreeHeaderReadWrite,
+        <IntermediateBlock as IntermediateTreeBlock>::Entry: IntermediateTreeEntryReadWrite,
+    {
+        let size = <<IntermediateBlock as IntermediateTreeBlock>::Header as IntermediateTreeHeaderReadWrite>::HEADER_SIZE
+            + header.entry_count()
+                * <<IntermediateBlock as IntermediateTreeBlock>::Entry as IntermediateTreeEntryReadWrite>::ENTRY_SIZE;
+
+        let block = self.allocate_block(size, true)?;
+        let index = block.index().index().into();
+        let block_id = block.block();
+        let signature = block_signature(index, block_id.into_u64());
+        let trailer = <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::new(
+            size, signature, 0, block_id,
+        )?;
+        let intermediate_block = IntermediateBlock::new(header, entries, trailer)?;
+
+        {
+            let writer = &mut self.transaction_writer(WriteKind::Block)?;
+            writer.seek(SeekFrom::Start(index))?;
+            intermediate_block.write(writer)?;
+            writer.flush()?;
+        }
+
+        let entry =
+            <<Pst as PstFile>::BlockBTreeEntry as BlockBTreeEntryReadWrite>::new(block, size);
+        self.insert_block_btree_entry(entry)?;
+
+        Ok(block_id)
+    }
+
+    /// Write everything in `f` to a new data tree. If it does not fit in a single data block,
+    /// the data blocks are grouped under XBLOCKs, and if there is more than one XBLOCK, they are
+    /// grouped under an XXBLOCK.
+    fn write_data_tree(
+        &mut self,
+        f: &mut dyn Read,
+    ) -> io::Result<(<Pst as PstFile>::BlockId, u32)> {
+        let max_size = DataTree::<Pst>::max_leaf_size();
+        let mut blocks = vec![];
+        loop {
+            let mut data = Vec::with_capacity(usize::from(max_size));
+            f.take(u64::from(max_size)).read_to_end(&mut data)?;
+            if data.is_empty() && !blocks.is_empty() {
+                break;
+            }
+
+            let size = data.len() as u32;
+            blocks.push((self.write_data_block(&data)?, size));
+            if size < u32::from(max_size) {
+                break;
+            }
+        }
+
+        self.write_data_tree_levels(blocks)
+    }
+
+    /// Write each of `blocks` to its own data block, instead of splitting a stream at
+    /// [`DataTree::max_leaf_size`], so formats like the heap of an LTP node keep their layout.
+    fn write_data_blocks(
+        &mut self,
+        blocks: &[Vec<u8>],
+    ) -> io::Result<(<Pst as PstFile>::BlockId, u32)> {
+        let blocks = blocks
+            .iter()
+            .map(|data| Ok((self.write_data_block(data)?, data.len() as u32)))
+            .collect::<io::Result<Vec<_>>>()?;
+        if blocks.is_empty() {
+            return Err(NdbError::InvalidBlockSize(DataTree::<Pst>::max_leaf_size()).into());
+        }
+        self.write_data_tree_levels(blocks)
+    }
+
+    /// Group the data blocks of a data tree under XBLOCKs, and if there is more than one XBLOCK,
+    /// group them under an XXBLOCK.
+    fn write_data_tree_levels(
+        &mut self,
+        mut blocks: Vec<(<Pst as PstFile>::BlockId, u32)>,
+    ) -> io::Result<(<Pst as PstFile>::BlockId, u32)> {
+        for level in 1..=2 {
+            if let [(block, size)] = blocks.as_slice() {
+                return Ok((*block, *size));
+            }
+
+            let parents = DataTree::<Pst>::intermediate_blocks(&blocks);
+            if level == 2 && parents.len() > 1 {
+                return Err(NdbError::DataTreeTooLarge(blocks.len()).into());
+            }
+
+            blocks = parents
+                .into_iter()
+                .map(|(children, total_size)| {
+                    let header = DataTreeBlockHeader::new(level, children.len() as u16, total_size);
+                    let entries = children
+                        .into_iter()
+                        .map(<Pst as PstFile>::DataTreeEntry::from)
+                        .collect();
+                    let block = self.write_intermediate_block::<<Pst as PstFile>::DataTreeBlock>(
+                        header, entries,
+                    )?;
+                    Ok((block, total_size))
+                })
+                .collect::<io::Result<_>>()?;
+        }
+
+        let [(block, size)] = blocks.as_slice() else {
+            unreachable!("data tree has more than one XXBLOCK");
+        };
+        Ok((*block, *size))
+    }
+
+    /// Write a new sub-node tree holding `entries`, which must be sorted by [`NodeId`]. They fit
+    /// in a single SLBLOCK until there are more than [`SubNodeTree::max_leaf_entries`], and then
+    /// the tree is promoted to an SIBLOCK pointing at each of the SLBLOCKs.
+    fn write_sub_node_tree(
+        &mut self,
+        entries: &[LeafSubNodeTreeEntry<<Pst as PstFile>::BlockId>],
+    ) -> io::Result<Option<<Pst as PstFile>::BlockId>> {
+        let leaves = SubNodeTree::<Pst>::leaf_blocks(entries)?;
+        let mut blocks = Vec::with_capacity(leaves.len());
+        for leaf in leaves {
+            let header =
+                <<Pst as PstFile>::SubNodeTreeBlockHeader as SubNodeTreeBlockHeaderReadWrite>::new(
+                    0,
+                    leaf.len() as u16,
+                );
+            let block = self.write_intermediate_block::<<Pst as PstFile>::SubNodeBlock>(
+                header,
+                leaf.to_vec(),
+            )?;
+            blocks.push(IntermediateSubNodeTreeEntry::new(leaf[0].node(), block));
+        }
+
+        match blocks.as_slice() {
+            [] => Ok(None),
+            [leaf] => Ok(Some(leaf.block())),
+            _ => {
+                let header = <<Pst as PstFile>::SubNodeTreeBlockHeader as SubNodeTreeBlockHeaderReadWrite>::new(
+                    1,
+                    blocks.len() as u16,
+                );
+                Ok(Some(
+                    self.write_intermediate_block::<<Pst as PstFile>::SubNodeTreeBlock>(
+                        header, blocks,
+                    )?,
+                ))
+            }
+        }
+    }
+
+    /// Point the node at a new sub-node tree holding `entries`, then release the SLBLOCKs and
+    /// SIBLOCKs of the previous tree. The sub-nodes themselves are still referenced by the new
+    /// tree, so they are left alone.
+    fn replace_sub_node_tree(
+        &mut self,
+        entry: <Pst as PstFile>::NodeBTreeEntry,
+        entries: &[LeafSubNodeTreeEntry<<Pst as PstFile>::BlockId>],
+    ) -> io::Result<()> {
+        let sub_node = self.write_sub_node_tree(entries)?;
+
+        self.update_node_entry(
+            <<Pst as PstFile>::NodeBTreeEntry as NodeBTreeEntryReadWrite>::new(
+                entry.node(),
+                entry.data(),
+                sub_node,
+                entry.parent(),
+            ),
+        )?;
+
+        if let Some(sub_node) = entry.sub_node() {
+            self.release_block_contents(
+                sub_node,
+                BlockContents::SubNodeTree {
+                    with_entries: false,
+                },
+            )?;
+        }
+        self.block_cache.clear();
+        Ok(())
+    }
+
+    /// Write `data` to a new data tree and add `sub_node` to the sub-node tree of `node`. If the
+    /// sub-node already exists, its data is replaced and its own sub-node tree is kept.
+    fn write_sub_node(
+        &mut self,
+        node: NodeId,
+        sub_node: NodeId,
+        mut data: &[u8],
+    ) -> io::Result<()> {
+        let entry = self.read_node(node)?;
+        let entry = self.unshare_sub_node_tree(entry)?;
+        let mut entries = self.read_sub_node_entries(entry.sub_node())?;
+        let nested = entries
+            .iter()
+            .find(|entry| u32::from(entry.node()) == u32::from(sub_node))
+            .and_then(|entry| entry.sub_node());
+
+        let (block, _) = self.write_data_tree(&mut data)?;
+        let replaced = insert_sub_node_entry(
+            &mut entries,
+            LeafSubNodeTreeEntry::new(sub_node, block, nested),
+        );
+        self.replace_sub_node_tree(entry, &entries)?;
+
+        if let Some(replaced) = replaced {
+            self.release_block(replaced.block())?;
+        }
+        self.dirty_nodes.insert(node);
+        self.block_cache.clear();
+        Ok(())
+    }
+
+    /// Remove `sub_node` from the sub-node tree of `node`, and release its data and its own
+    /// sub-node tree.
+    fn remove_sub_node(&mut self, node: NodeId, sub_node: NodeId) -> io::Result<()> {
+        let entry = self.read_node(node)?;
+        let entry = self.unshare_sub_node_tree(entry)?;
+        let mut entries = self.read_sub_node_entries(entry.sub_node())?;
+        let removed = remove_sub_node_entry(&mut entries, sub_node)
+            .ok_or(NdbError::SubNodeNotFound(sub_node))?;
+        self.replace_sub_node_tree(entry, &entries)?;
+
+        self.release_block(removed.block())?;
+        if let Some(nested) = removed.sub_node() {
+            self.release_block_contents(nested, BlockContents::SubNodeTree { with_entries: true })?;
+        }
+        self.dirty_nodes.insert(node);
+        self.block_cache.clear();
+        Ok(())
+    }
+
+    /// Look up the number of references to a block in the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    fn block_ref_count(&self, block: <Pst as PstFile>::BlockId) -> io::Result<u16> {
+        let root = *self.header.root().block_btree();
+        let mut reader = self.reader();
+        let reader = &mut reader;
+        let block_btree = <Pst::BlockBTree as RootBTreeReadWrite>::read(reader, root)?;
+        let mut page_cache = self.block_cache.shard();
+        let entry = block_btree.find_entry(reader, block.search_key(), &mut page_cache)?;
+        Ok(entry.ref_count())
+    }
+
+    /// Increment the reference count on a block, so another node can share it instead of
+    /// copying it. Returns `false` without changing anything if the reference count is already
+    /// at its maximum, in which case the caller needs to make a copy.
+    fn add_block_reference(&mut self, block: <Pst as PstFile>::BlockId) -> io::Result<bool> {
+        let root = *self.header.root().block_btree();
+        let mut entry = {
+            let mut reader = self.reader();
+            let reader = &mut reader;
+            let block_btree = <Pst::BlockBTree as RootBTreeReadWrite>::read(reader, root)?;
+            let mut page_cache = self.block_cache.shard();
+            block_btree.find_entry(reader, block.search_key(), &mut page_cache)?
+        };
+        let Some(ref_count) = entry.ref_count().checked_add(1) else {
+            return Ok(false);
+        };
+
+        entry.set_ref_count(ref_count);
+        self.change_block_btree(|reader, writer, root, pages| {
+            let root = PstFileReadWriteBlockBTree::<Pst>::update_entry(
+                reader, writer, root, entry, pages,
+            )?;
+            Ok(((), root))
+        })?;
+        Ok(true)
+    }
+
+    /// Write a copy of the data tree starting at `block` to new blocks.
+    fn copy_data_tree(
+        &mut self,
+        block: <Pst as PstFile>::BlockId,
+    ) -> io::Result<<Pst as PstFile>::BlockId> {
+        let data = self.read_block(block)?;
+        let (block, _) = self.write_data_tree(&mut data.as_slice())?;
+        Ok(block)
+    }
+
+    /// Write a copy of the sub-node tree starting at `block` to new blocks, including the data and
+    /// sub-node trees of every sub-node.
+    fn copy_sub_node_tree(
+        &mut self,
+        block: <Pst as PstFile>::BlockId,
+    ) -> io::Result<Option<<Pst as PstFile>::BlockId>> {
+        let entries = self
+            .read_sub_node_entries(Some(block))?
+            .into_iter()
+            .map(|entry| {
+                let data = self.copy_data_tree(entry.block())?;
+                let sub_node = match entry.sub_node() {
+                    Some(sub_node) => self.copy_sub_node_tree(sub_node)?,
+                    None => None,
+                };
+                Ok(LeafSubNodeTreeEntry::new(entry.node(), data, sub_node))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        self.write_sub_node_tree(&entries)
+    }
+
+    /// Before changing the sub-node tree of a node, give it a copy of its own if the tree is
+    /// shared with another node by [`Self::copy_message`].
+    fn unshare_sub_node_tree(
+        &mut self,
+        entry: <Pst as PstFile>::NodeBTreeEntry,
+    ) -> io::Result<<Pst as PstFile>::NodeBTreeEntry> {
+        let Some(sub_node) = entry.sub_node() else {
+            return Ok(entry);
+        };
+        if self.block_ref_count(sub_node)? <= UNSHARED_BLOCK_REF_COUNT {
+            return Ok(entry);
+        }
+
+        let entry = <<Pst as PstFile>::NodeBTreeEntry as NodeBTreeEntryReadWrite>::new(
+            entry.node(),
+            entry.data(),
+            self.copy_sub_node_tree(sub_node)?,
+            entry.parent(),
+        );
+        self.update_node_entry(entry)?;
+        self.release_block_contents(sub_node, BlockContents::SubNodeTree { with_entries: true })?;
+        Ok(entry)
+    }
+
+    /// Insert a new entry in the [`Node BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    fn insert_node_btree_entry(
+        &mut self,
+        entry: <Pst as PstFile>::NodeBTreeEntry,
+    ) -> io::Result<()> {
+        self.change_node_btree(|reader, writer, root, pages| {
+            let root =
+                PstFileReadWriteNodeBTree::<Pst>::insert_entry(reader, writer, root, entry, pages)?;
+            Ok(((), root))
+        })
+    }
+
+    /// Allocate a [`NodeId`] of `id_type` which is not already in the Node BTree. Some files,
+    /// including `Empty.pst`, have `rgnid` counters which were not advanced past the last node of
+    /// that type.
+    fn allocate_unused_nid(&mut self, id_type: NodeIdType) -> io::Result<NodeId> {
+        loop {
+            let node = self.header.allocate_nid(id_type)?;
+            if optional_btree_entry(self.read_node(node))?.is_none() {
+                return Ok(node);
+            }
+        }
+    }
+
+    /// Copy a node to a new [`NodeId`] of the same type under `parent`, e.g. a message with its
+    /// recipients, attachments and any other sub-nodes. With `reuse_blocks`, the copy shares the
+    /// data tree and sub-node tree of the original by adding a reference to them, rather than
+    /// duplicating the bytes.
+    fn copy_node(
+        &mut self,
+        node: NodeId,
+        parent: Option<NodeId>,
+        reuse_blocks: bool,
+    ) -> io::Result<NodeId> {
+        let entry = self.read_node(node)?;
+
+        let data = if reuse_blocks && self.add_block_reference(entry.data())? {
+            entry.data()
+        } else {
+            self.copy_data_tree(entry.data())?
+        };
+        let sub_node = match entry.sub_node() {
+            Some(sub_node) if reuse_blocks && self.add_block_reference(sub_node)? => Some(sub_node),
+            Some(sub_node) => self.copy_sub_node_tree(sub_node)?,
+            None => None,
+        };
+
+        let copy = self.allocate_unused_nid(node.id_type()?)?;
+        self.insert_node_btree_entry(
+            <<Pst as PstFile>::NodeBTreeEntry as NodeBTreeEntryReadWrite>::new(
+                copy, data, sub_node, parent,
+            ),
+        )?;
+        Ok(copy)
+    }
+
+    /// Remove a message node from the [`Node BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085)
+    /// and release its data tree and sub-node tree. Blocks which are shared with a copy of the
+    /// message are only freed once the last reference to them is released.
+    fn delete_message(&mut self, message: NodeId) -> io::Result<()> {
+        let entry = self.read_node(message)?;
+
+        self.change_node_btree(|reader, writer, root, pages| {
+            PstFileReadWriteNodeBTree::<Pst>::remove_entry(reader, writer, root, entry.key(), pages)
+        })?;
+        self.dirty_nodes.remove(&message);
+
+        self.release_block(entry.data())?;
+        if let Some(sub_node) = entry.sub_node() {
+            self.release_block_contents(
+                sub_node,
+                BlockContents::SubNodeTree { with_entries: true },
+            )?;
+        }
+        self.block_cache.clear();
+        Ok(())
+    }
+
+    /// See [`PstFileLockGuard::validate_structures`].
+    #[instrument(skip_all)]
+    fn validate_structures(&self, version: NdbVersion) -> io::Result<Vec<StructuralIssue>> {
+        let mut issues = vec![];
+        for &node in self.dirty_nodes.iter() {
+            match self.read_node_data(node) {
+                Ok(data) => issues.extend(validate_node_data(version, node, &data)),
+                Err(err) => issues.push(StructuralIssue {
+                    target: ValidationTarget::Node {
+                        node,
+                        sub_node: None,
+                    },
+                    description: err.to_string(),
+                }),
+            }
+        }
+
+        let root = self.header.root();
+        self.validate_node_btree(*root.node_btree(), None, None, None, &mut issues)?;
+        self.validate_block_btree(*root.block_btree(), None, None, None, &mut issues)?;
+        Ok(issues)
+    }
+
+    /// Recursively check the level and the key ordering of every page in the [`Node BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    fn validate_node_btree(
+        &self,
+        page: <Pst as PstFile>::PageRef,
+        level: Option<u8>,
+        lower: Option<u64>,
+        upper: Option<u64>,
+        issues: &mut Vec<StructuralIssue>,
+    ) -> io::Result<()> {
+        let offset = page.index().index().into();
+        let node_btree = {
+            let mut reader = self.reader();
+            <Pst::NodeBTree as RootBTreeReadWrite>::read(&mut reader, page)
+        };
+        let node_btree = match node_btree {
+            Ok(node_btree) => node_btree,
+            Err(err) => {
+                issues.push(StructuralIssue {
+                    target: ValidationTarget::NodeBTreePage(offset),
+                    description: err.to_string(),
+                });
+                return Ok(());
+            }
+        };
+
+        let (page_level, keys, children) = match &node_btree {
+            RootBTreePage::Intermediate(page, ..) => (
+                page.level(),
+                page.entries()
+                    .iter()
+                    .map(|entry| entry.key().into())
+                    .collect::<Vec<_>>(),
+                page.entries().iter().map(|entry| entry.block()).collect(),
+            ),
+            RootBTreePage::Leaf(page) => (
+                page.level(),
+                page.entries()
+                    .iter()
+                    .map(|entry| entry.key().into())
+                    .collect(),
+                vec![],
+            ),
+        };
+        if let Some(description) = check_btree_page_level(page_level, level)
+            .or_else(|| check_btree_page_keys(&keys, lower, upper))
+        {
+            issues.push(StructuralIssue {
+                target: ValidationTarget::NodeBTreePage(offset),
+                description,
+            });
+            return Ok(());
+        }
+
+        for (index, child) in children.into_iter().enumerate() {
+            self.validate_node_btree(
+                child,
+                Some(page_level.saturating_sub(1)),
+                keys.get(index).copied(),
+                keys.get(index + 1).copied().or(upper),
+                issues,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Recursively check the level and the key ordering of every page in the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    fn validate_block_btree(
+        &self,
+        page: <Pst as PstFile>::PageRef,
+        level: Option<u8>,
+        lower: Option<u64>,
+        upper: Option<u64>,
+        issues: &mut Vec<StructuralIssue>,
+    ) -> io::Result<()> {
+        let offset = page.index().index().into();
+        let block_btree = {
+            let mut reader = self.reader();
+            <Pst::BlockBTree as RootBTreeReadWrite>::read(&mut reader, page)
+        };
+        let block_btree = match block_btree {
+            Ok(block_btree) => block_btree,
+            Err(err) => {
+                issues.push(StructuralIssue {
+                    target: ValidationTarget::BlockBTreePage(offset),
+                    description: err.to_string(),
+                });
+                return Ok(());
+            }
+        };
+
+        let (page_level, keys, children) = match &block_btree {
+            RootBTreePage::Intermediate(page, ..) => (
+                page.level(),
+                page.entries()
+                    .iter()
+                    .map(|entry| entry.key().into())
+                    .collect::<Vec<_>>(),
+                page.entries().iter().map(|entry| entry.block()).collect(),
+            ),
+            RootBTreePage::Leaf(page) => (
+                page.level(),
+                page.entries()
+                    .iter()
+                    .map(|entry| entry.key().into())
+                    .collect(),
+                vec![],
+            ),
+        };
+        if let Some(description) = check_btree_page_level(page_level, level)
+            .or_else(|| check_btree_page_keys(&keys, lower, upper))
+        {
+            issues.push(StructuralIssue {
+                target: ValidationTarget::BlockBTreePage(offset),
+                description,
+            });
+            return Ok(());
+        }
+
+        for (index, child) in children.into_iter().enumerate() {
+            self.validate_block_btree(
+                child,
+                Some(page_level.saturating_sub(1)),
+                keys.get(index).copied(),
+                keys.get(index + 1).copied().or(upper),
+                issues,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// See [`repair::fix_crcs`].
+    #[instrument(skip_all)]
+    fn fix_crcs(&mut self) -> io::Result<Vec<RepairedLocation>> {
+        let mut repaired = vec![];
+
+        for amap_index in 0..self.allocation_map_page_count() {
+            let (has_pmap_page, has_fmap_page, has_fpmap_page) =
+                Self::allocation_map_page_layout(amap_index);
+            let page_count = [has_pmap_page, has_fmap_page, has_fpmap_page]
+                .into_iter()
+                .filter(|has_page| *has_page)
+                .count()
+                + 1;
+            let amap_offset = amap_page_offset(amap_index)?;
+            for page in 0..page_count {
+                let offset = amap_offset + (page * PAGE_SIZE) as u64;
+                repaired.extend(self.fix_page_trailer(offset)?);
+            }
+        }
+
+        let root = self.header.root();
+        let node_btree = *root.node_btree();
+        let block_btree = *root.block_btree();
+        self.fix_node_btree_crcs(node_btree, &mut repaired)?;
+        self.fix_block_btree_crcs(block_btree, &mut repaired)?;
+
+        self.node_cache.clear();
+        self.block_cache.clear();
+        Ok(repaired)
+    }
+
+    /// Recursively fix the trailers of every page in the [`Node BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    fn fix_node_btree_crcs(
+        &mut self,
+        page: <Pst as PstFile>::PageRef,
+        repaired: &mut Vec<RepairedLocation>,
+    ) -> io::Result<()> {
+        repaired.extend(self.fix_page_trailer(page.index().index().into())?);
+
+        let node_btree = {
+            let mut reader = self.reader();
+            <Pst::NodeBTree as RootBTreeReadWrite>::read(&mut reader, page)?
+        };
+        if let RootBTreePage::Intermediate(page, ..) = node_btree {
+            for entry in page.entries() {
+                self.fix_node_btree_crcs(entry.block(), repaired)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Recursively fix the trailers of every page in the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085),
+    /// and every block referenced by its leaf pages.
+    fn fix_block_btree_crcs(
+        &mut self,
+        page: <Pst as PstFile>::PageRef,
+        repaired: &mut Vec<RepairedLocation>,
+    ) -> io::Result<()> {
+        repaired.extend(self.fix_page_trailer(page.index().index().into())?);
+
+        let block_btree = {
+            let mut reader = self.reader();
+            <Pst::BlockBTree as RootBTreeReadWrite>::read(&mut reader, page)?
+        };
+        match block_btree {
+            RootBTreePage::Intermediate(page, ..) => {
+                for entry in page.entries() {
+                    self.fix_block_btree_crcs(entry.block(), repaired)?;
+                }
+            }
+            RootBTreePage::Leaf(page) => {
+                for entry in page.entries() {
+                    repaired.extend(self.fix_block_trailer(entry.block(), entry.size())?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Recompute the `dwCRC` and `wSig` fields of the page at `offset`, and rewrite the trailer
+    /// if either of them does not match.
+    fn fix_page_trailer(&mut self, offset: u64) -> io::Result<Option<RepairedLocation>> {
+        let mut buffer = [0_u8; PAGE_SIZE];
+        {
+            let mut reader = self.reader();
+            reader.seek(SeekFrom::Start(offset))?;
+            reader.read_exact(&mut buffer)?;
+        }
+
+        let trailer_size = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::SIZE;
+        let (data, mut trailer) = buffer.split_at(PAGE_SIZE - trailer_size);
+        let Ok(trailer) =
+            <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::read(&mut trailer)
+        else {
+            warn!(
+                name: "PstUnreadablePageTrailer",
+                offset,
+                "Skipping page with an unreadable trailer"
+            );
+            return Ok(None);
+        };
+
+        let page_type = trailer.page_type();
+        let crc = compute_crc(0, data);
+        let signature = page_type.signature(offset, trailer.block_id().into_u64());
+        if crc == trailer.crc() && signature == trailer.signature() {
+            return Ok(None);
+        }
+
+        let fixed = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
+            page_type,
+            signature,
+            trailer.block_id(),
+            crc,
+        );
+        {
+            let writer = &mut self.transaction_writer(page_type.into())?;
+            writer.seek(SeekFrom::Start(offset + data.len() as u64))?;
+            fixed.write(writer)?;
+            writer.flush()?;
+        }
+
+        let repaired = RepairedLocation {
+            offset,
+            target: RepairTarget::Page(page_type),
+            fixed_crc: crc != trailer.crc(),
+            fixed_signature: signature != trailer.signature(),
+        };
+        warn!(
+            name: "PstRepairedPageTrailer",
+            offset,
+            ?page_type,
+            fixed_crc = repaired.fixed_crc,
+            fixed_signature = repaired.fixed_signature,
+            "Rewrote page trailer"
+        );
+        Ok(Some(repaired))
+    }
+
+    /// Recompute the `dwCRC` and `wSig` fields of a block in the BBT, and rewrite the trailer if
+    /// either of them does not match.
+    fn fix_block_trailer(
+        &mut self,
+        block: <Pst as PstFile>::BlockRef,
+        size: u16,
+    ) -> io::Result<Option<RepairedLocation>> {
+        let offset = block.index().index().into();
+        let trailer_size = <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::SIZE;
+        let mut buffer = vec![0_u8; usize::from(block_size(size + trailer_size))];
+        {
+            let mut reader = self.reader();
+            reader.seek(SeekFrom::Start(offset))?;
+            reader.read_exact(&mut buffer)?;
+        }
+
+        let trailer_offset = buffer.len() - usize::from(trailer_size);
+        let mut trailer = &buffer[trailer_offset..];
+        let trailer =
+            match <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::read(&mut trailer) {
+                Ok(trailer) if trailer.size() == size => trailer,
+                _ => {
+                    warn!(
+                        name: "PstUnreadableBlockTrailer",
+                        offset,
+                        block = ?block.block(),
+                        "Skipping block with an unreadable trailer"
+                    );
+                    return Ok(None);
+                }
+            };
+
+        let block_id = trailer.block_id();
+        let crc = compute_crc(0, &buffer[..usize::from(size)]);
+        let signature = block_signature(offset, block_id.into_u64());
+        if crc == trailer.crc() && signature == trailer.signature() {
+            return Ok(None);
+        }
+
+        let fixed = <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::new(
+            size, signature, crc, block_id,
+        )?;
+        {
+            let writer = &mut self.transaction_writer(WriteKind::Block)?;
+            writer.seek(SeekFrom::Start(offset + trailer_offset as u64))?;
+            fixed.write(writer)?;
+            writer.flush()?;
+        }
+
+        let repaired = RepairedLocation {
+            offset,
+            target: RepairTarget::Block(block_id.into_u64()),
+            fixed_crc: crc != trailer.crc(),
+            fixed_signature: signature != trailer.signature(),
+        };
+        warn!(
+            name: "PstRepairedBlockTrailer",
+            offset,
+            ?block_id,
+            fixed_crc = repaired.fixed_crc,
+            fixed_signature = repaired.fixed_signature,
+            "Rewrote block trailer"
+        );
+        Ok(Some(repaired))
+    }
+
+    fn write_support(&self) -> WriteSupport {
+        WriteSupport {
+            version: self.header.version(),
+            writable: self.writer.is_ok(),
+        }
+    }
+
+    fn read_node(&self, node: NodeId) -> io::Result<<Pst as PstFile>::NodeBTreeEntry> {
+        let node_btree = *self.header.root().node_btree();
+        let mut reader = self.reader();
+        let reader = &mut reader;
+        let node_btree =
+            <<Pst as PstFile>::NodeBTree as RootBTreeReadWrite>::read(reader, node_btree)?;
+        let mut page_cache = self.node_cache.shard();
+        let node_id: <Pst as PstFile>::BTreeKey = u32::from(node).into();
+        let node = node_btree.find_entry(reader, node_id, &mut page_cache)?;
+        Ok(node)
+    }
+
+    /// Read each of the data blocks in the data tree starting at `block`, without concatenating
+    /// them like [`Self::read_block`].
+    fn read_data_blocks(&self, block: <Pst as PstFile>::BlockId) -> io::Result<Vec<Vec<u8>>> {
+        let encoding = self.header.crypt_method();
+        let block_btree = *self.header.root().block_btree();
+        let mut reader = self.reader();
+        let reader = &mut reader;
+        let block_btree =
+            <<Pst as PstFile>::BlockBTree as RootBTreeReadWrite>::read(reader, block_btree)?;
+        let mut page_cache = self.block_cache.shard();
+        let block = block_btree.find_entry(reader, block.search_key(), &mut page_cache)?;
+        let block = DataTree::<Pst>::read(reader, encoding, &block)?;
+        let mut block_cache = Default::default();
+        let blocks = block
+            .blocks(
+                reader,
+                encoding,
+                &block_btree,
+                &mut page_cache,
+                &mut block_cache,
+            )?
+            .map(|block| block.data().to_vec())
+            .collect();
+        Ok(blocks)
+    }
+
+    fn read_block(&self, block: <Pst as PstFile>::BlockId) -> io::Result<Vec<u8>> {
+        let encoding = self.header.crypt_method();
+        let block_btree = *self.header.root().block_btree();
+        let mut reader = self.reader();
+        let reader = &mut reader;
+        let block_btree =
+            <<Pst as PstFile>::BlockBTree as RootBTreeReadWrite>::read(reader, block_btree)?;
+        let mut page_cache = self.block_cache.shard();
+        let block = block_btree.find_entry(reader, block.search_key(), &mut page_cache)?;
+        let block = DataTree::<Pst>::read(reader, encoding, &block)?;
+        let mut block_cache = Default::default();
+        let mut data = vec![];
+        let _ = block
+            .reader(
+                reader,
+                encoding,
+                &block_btree,
+                &mut page_cache,
+                &mut block_cache,
+            )?
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn visit_blocks(&self, visitor: &mut dyn BlockVisitor<Pst>) -> io::Result<()> {
+        let mut entries = vec![];
+        self.collect_block_btree_entries(*self.header.root().block_btree(), &mut entries)?;
+        entries.sort_by_key(|entry| -> u64 { entry.block().index().index().into() });
+
+        let encoding = self.header.crypt_method();
+        for entry in entries {
+            let block = entry.block();
+            let mut data = vec![0_u8; usize::from(entry.size())];
+            {
+                let mut reader = self.reader();
+                reader.seek(SeekFrom::Start(block.index().index().into()))?;
+                reader.read_exact(&mut data)?;
+            }
 
-        match block_btree {
-            RootBTreePage::Intermediate(page, ..) => {
-                let level = page.level();
-                for entry in page.entries() {
-                    let block = entry.block();
-                    let block_btree = <Pst::BlockBTree as RootBTreeReadWrite>::read(reader, block)?;
-                    match &block_btree {
-                        RootBTreePage::Intermediate(page, ..) if page.level() + 1 != level => {
-                            error!(
-                                name: "PstUnexpectedBTreeIntermediatePage",
-                                block = ?block.block(),
-                                index = ?block.index(),
-                                parent = level,
-                                child = page.level(),
-                                "Possible BBT page cycle detected, expected child == parent - 1"
-                            );
-                            return Err(
-                                PstError::InvalidBTreePage(block.index().index().into()).into()
-                            );
+            let is_internal = block.block().is_internal();
+            if !is_internal {
+                let search_key: u64 = block.block().search_key().into();
+                encode_block_data(&mut data, encoding, search_key as u32, true);
+            }
+            let kind = BlockKind::classify(is_internal, &data);
+            visitor.visit_block(&entry, kind, &data)?;
+        }
+        Ok(())
+    }
+
+    /// See [`quick_check`](crate::quick_check).
+    #[instrument(skip_all)]
+    fn quick_check(&self, options: &QuickCheckOptions) -> io::Result<QuickCheckReport> {
+        let mut run = QuickCheckRun::new(*options);
+
+        // Read the header again, so the CRCs are checked against what is in the file now.
+        let header_size = match self.header.version() {
+            NdbVersion::Unicode => UNICODE_HEADER_SIZE,
+            NdbVersion::Ansi => ANSI_HEADER_SIZE,
+        };
+        if run.start_check(header_size) {
+            let header = {
+                let mut reader = self.reader();
+                let reader = &mut reader;
+                reader.seek(SeekFrom::Start(0))?;
+                <<Pst as PstFile>::Header as HeaderReadWrite<Pst>>::read_with_mode(
+                    reader,
+                    HeaderFieldMode::Preserve,
+                )
+            };
+            run.add_bytes(header_size);
+            run.finish_check(
+                QuickCheckTarget::Header,
+                header.err().map(|err| err.to_string()),
+            );
+        }
+
+        let root = self.header.root();
+        let file_eof: u64 = root.file_eof_index().index().into();
+        let amap_last: u64 = root.amap_last_index().index().into();
+        let node_btree = *root.node_btree();
+        let block_btree = *root.block_btree();
+        if run.start_check(0) {
+            let file_size = {
+                let mut reader = self.reader();
+                reader.seek(SeekFrom::End(0))?
+            };
+            let page_end = |offset: u64| {
+                (offset % PAGE_SIZE as u64 == 0)
+                    .then(|| offset.checked_add(PAGE_SIZE as u64))
+                    .flatten()
+            };
+            let problem = if file_eof > file_size {
+                Some(format!(
+                    "ibFileEof 0x{file_eof:X} is past the end of the file at 0x{file_size:X}"
+                ))
+            } else if !matches!(amap_page_index(amap_last), Ok((_, 0)))
+                || page_end(amap_last).is_none_or(|end| end > file_eof)
+            {
+                Some(format!("ibAMapLast 0x{amap_last:X} is not an AMap page"))
+            } else {
+                [("NBT", node_btree), ("BBT", block_btree)]
+                    .into_iter()
+                    .find_map(|(name, page)| {
+                        let offset: u64 = page.index().index().into();
+                        page_end(offset)
+                            .is_none_or(|end| end > file_eof)
+                            .then(|| format!("{name} root 0x{offset:X} is not a page in the file"))
+                    })
+            };
+            run.finish_check(QuickCheckTarget::Root, problem);
+        }
+
+        let mut amap_pages = vec![AMAP_FIRST_OFFSET];
+        if amap_last > AMAP_FIRST_OFFSET {
+            amap_pages.push(amap_last);
+        }
+        for offset in amap_pages {
+            if !run.start_check(PAGE_SIZE as u64) {
+                continue;
+            }
+            let amap_page = {
+                let mut reader = self.reader();
+                let reader = &mut reader;
+                reader.seek(SeekFrom::Start(offset))?;
+                <<Pst as PstFile>::AllocationMapPage as AllocationMapPageReadWrite<Pst>>::read(
+                    reader,
+                )
+            };
+            run.add_bytes(PAGE_SIZE as u64);
+            run.finish_check(
+                QuickCheckTarget::AllocationMapPage(offset),
+                amap_page.err().map(|err| err.to_string()),
+            );
+        }
+
+        // Only BTrees whose root is an intermediate page have leaf pages to sample.
+        let mut sample_roots = vec![];
+        for (page_type, page) in [
+            (PageType::NodeBTree, node_btree),
+            (PageType::BlockBTree, block_btree),
+        ] {
+            if !run.start_check(PAGE_SIZE as u64) {
+                continue;
+            }
+            let result = self.quick_check_btree_page(page, page_type, None, None, None)?;
+            run.add_bytes(PAGE_SIZE as u64);
+            let target = quick_check_btree_target(page_type, page.index().index().into());
+            match result {
+                Ok(root) => {
+                    run.finish_check(target, None);
+                    if root.level > 0 && !root.children.is_empty() {
+                        sample_roots.push((page_type, root));
+                    }
+                }
+                Err(problem) => run.finish_check(target, Some(problem)),
+            }
+        }
+
+        let mut rng = SampleRng::new(run.options().seed());
+        if !sample_roots.is_empty() {
+            for sample in 0..run.options().leaf_samples() {
+                let (page_type, root) = &sample_roots[sample % sample_roots.len()];
+                if !run.start_check(u64::from(root.level) * PAGE_SIZE as u64) {
+                    continue;
+                }
+
+                let mut parent_level = root.level;
+                let mut keys = root.keys.clone();
+                let mut children = root.children.clone();
+                let mut upper = None;
+                loop {
+                    let index = rng.next_index(children.len());
+                    let page = children[index];
+                    let lower = keys.get(index).copied();
+                    upper = keys.get(index + 1).copied().or(upper);
+                    let result = self.quick_check_btree_page(
+                        page,
+                        *page_type,
+                        Some(parent_level - 1),
+                        lower,
+                        upper,
+                    )?;
+                    run.add_bytes(PAGE_SIZE as u64);
+                    let target = quick_check_btree_target(*page_type, page.index().index().into());
+                    match result {
+                        Ok(page) if page.level > 0 && !page.children.is_empty() => {
+                            parent_level = page.level;
+                            keys = page.keys;
+                            children = page.children;
                         }
-                        RootBTreePage::Leaf(_) if level != 1 => {
-                            error!(
-                                name: "PstUnexpectedBTreeLeafPage",
-                                block = ?block.block(),
-                                index = ?block.index(),
-                                parent = level,
-                                child = page.level(),
-                                "Corrupted BBT intermediate page detected, unexpected child leaf page"
-                            );
-                            return Err(
-                                PstError::InvalidBTreePage(block.index().index().into()).into()
-                            );
+                        Ok(_) => {
+                            run.finish_check(target, None);
+                            break;
+                        }
+                        Err(problem) => {
+                            run.finish_check(target, Some(problem));
+                            break;
                         }
-                        _ => (),
                     }
-                    Self::mark_block_btree_allocations(
-                        reader,
-                        block.index(),
-                        &block_btree,
-                        amap_pages,
-                    )?;
                 }
             }
-            RootBTreePage::Leaf(page) => {
+        }
+
+        Ok(run.finish())
+    }
+
+    /// Read a page of the Node BTree or the Block BTree, depending on `page_type`, and check its
+    /// trailer, its level, and its keys. Returns what is wrong with the page if it fails a check.
+    fn quick_check_btree_page(
+        &self,
+        page: <Pst as PstFile>::PageRef,
+        page_type: PageType,
+        level: Option<u8>,
+        lower: Option<u64>,
+        upper: Option<u64>,
+    ) -> io::Result<Result<QuickCheckBTreePage<Pst>, String>> {
+        let trailer_fields = |trailer: &<Pst as PstFile>::PageTrailer| {
+            (
+                trailer.page_type(),
+                trailer.signature(),
+                trailer.block_id().into_u64(),
+            )
+        };
+        let ((trailer_type, signature, block_id), btree_page) = {
+            let mut reader = self.reader();
+            let reader = &mut reader;
+            if page_type == PageType::NodeBTree {
+                match <Pst::NodeBTree as RootBTreeReadWrite>::read(reader, page) {
+                    Ok(RootBTreePage::Intermediate(page, ..)) => (
+                        trailer_fields(page.trailer()),
+                        QuickCheckBTreePage {
+                            level: page.level(),
+                            keys: page
+                                .entries()
+                                .iter()
+                                .map(|entry| entry.key().into())
+                                .collect(),
+                            children: page.entries().iter().map(|entry| entry.block()).collect(),
+                        },
+                    ),
+                    Ok(RootBTreePage::Leaf(page)) => (
+                        trailer_fields(page.trailer()),
+                        QuickCheckBTreePage {
+                            level: page.level(),
+                            keys: page
+                                .entries()
+                                .iter()
+                                .map(|entry| entry.key().into())
+                                .collect(),
+                            children: vec![],
+                        },
+                    ),
+                    Err(err) => return Ok(Err(err.to_string())),
+                }
+            } else {
+                match <Pst::BlockBTree as RootBTreeReadWrite>::read(reader, page) {
+                    Ok(RootBTreePage::Intermediate(page, ..)) => (
+                        trailer_fields(page.trailer()),
+                        QuickCheckBTreePage {
+                            level: page.level(),
+                            keys: page
+                                .entries()
+                                .iter()
+                                .map(|entry| entry.key().into())
+                                .collect(),
+                            children: page.entries().iter().map(|entry| entry.block()).collect(),
+                        },
+                    ),
+                    Ok(RootBTreePage::Leaf(page)) => (
+                        trailer_fields(page.trailer()),
+                        QuickCheckBTreePage {
+                            level: page.level(),
+                            keys: page
+                                .entries()
+                                .iter()
+                                .map(|entry| entry.key().into())
+                                .collect(),
+                            children: vec![],
+                        },
+                    ),
+                    Err(err) => return Ok(Err(err.to_string())),
+                }
+            }
+        };
+
+        let offset: u64 = page.index().index().into();
+        let expected_block_id = page.block().into_u64();
+        let problem = if trailer_type != page_type {
+            Some(format!("ptype is {trailer_type:?}, expected {page_type:?}"))
+        } else if block_id != expected_block_id {
+            Some(format!(
+                "bid is 0x{block_id:X}, but the parent references 0x{expected_block_id:X}"
+            ))
+        } else if signature != page_type.signature(offset, block_id) {
+            Some(format!("wSig 0x{signature:04X} does not match"))
+        } else {
+            check_btree_page_level(btree_page.level, level)
+                .or_else(|| check_btree_page_keys(&btree_page.keys, lower, upper))
+        };
+        Ok(match problem {
+            Some(problem) => Err(problem),
+            None => Ok(btree_page),
+        })
+    }
+
+    /// Recursively collect the entries in the leaf pages of the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085).
+    fn collect_block_btree_entries(
+        &self,
+        page: <Pst as PstFile>::PageRef,
+        entries: &mut Vec<<Pst as PstFile>::BlockBTreeEntry>,
+    ) -> io::Result<()> {
+        let block_btree = {
+            let mut reader = self.reader();
+            <Pst::BlockBTree as RootBTreeReadWrite>::read(&mut reader, page)?
+        };
+        match block_btree {
+            RootBTreePage::Intermediate(page, ..) => {
                 for entry in page.entries() {
-                    Self::mark_block_allocation(
-                        entry.block().index().index().into(),
-                        entry.size(),
-                        amap_pages,
-                    )?;
+                    self.collect_block_btree_entries(entry.block(), entries)?;
                 }
             }
+            RootBTreePage::Leaf(page) => entries.extend(page.entries().iter().copied()),
+        }
+        Ok(())
+    }
+}
+
+/// The [`QuickCheckTarget`] for a page of the BTree with the given `page_type`.
+fn quick_check_btree_target(page_type: PageType, offset: u64) -> QuickCheckTarget {
+    if page_type == PageType::NodeBTree {
+        QuickCheckTarget::NodeBTreePage(offset)
+    } else {
+        QuickCheckTarget::BlockBTreePage(offset)
+    }
+}
+
+/// Fail before writing anything if growing the file to `file_eof` bytes would exceed
+/// [`NdbVersion::max_file_size`].
+fn check_file_size_limit(version: NdbVersion, file_eof: u64) -> PstResult<()> {
+    match version.max_file_size() {
+        Some(max_file_size) if file_eof > max_file_size => Err(PstError::FileSizeLimit(file_eof)),
+        _ => Ok(()),
+    }
+}
+
+/// A PST file opened by [`open_any`], with the [`PstFile`] as well as its [`Store`], so file-level
+/// operations like [`PstFile::free_space_map`] are still available after opening the store.
+///
+/// The file is shared with the store, so writing to it needs a separate handle from
+/// [`UnicodePstFile::open`] or [`AnsiPstFile::open`], which can be [locked](PstFile::lock).
+#[derive(Clone)]
+pub enum PstAny {
+    Unicode {
+        file: Rc<UnicodePstFile>,
+        store: Rc<UnicodeStore>,
+    },
+    Ansi {
+        file: Rc<AnsiPstFile>,
+        store: Rc<AnsiStore>,
+    },
+}
+
+impl PstAny {
+    pub fn store(&self) -> Rc<dyn Store> {
+        match self {
+            Self::Unicode { store, .. } => store.clone(),
+            Self::Ansi { store, .. } => store.clone(),
+        }
+    }
+
+    /// The format of the file from its header.
+    pub fn ndb_version(&self) -> NdbVersion {
+        match self {
+            Self::Unicode { file, .. } => file.header().version(),
+            Self::Ansi { file, .. } => file.header().version(),
+        }
+    }
+
+    pub fn is_unicode(&self) -> bool {
+        matches!(self, Self::Unicode { .. })
+    }
+}
+
+impl From<PstAny> for Rc<dyn Store> {
+    fn from(value: PstAny) -> Self {
+        value.store()
+    }
+}
+
+/// Open a PST file as Unicode if it is one, otherwise as ANSI.
+pub fn open_any(path: impl AsRef<Path>) -> io::Result<PstAny> {
+    Ok(if let Ok(file) = UnicodePstFile::open(path.as_ref()) {
+        let file = Rc::new(file);
+        let store = UnicodeStore::read(file.clone())?;
+        PstAny::Unicode { file, store }
+    } else {
+        let file = Rc::new(AnsiPstFile::open(path.as_ref())?);
+        let store = AnsiStore::read(file.clone())?;
+        PstAny::Ansi { file, store }
+    })
+}
+
+/// Like [`open_any`], for callers which only need the [`Store`].
+pub fn open_store(path: impl AsRef<Path>) -> io::Result<Rc<dyn Store>> {
+    Ok(open_any(path)?.store())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ltp::code_page::encode_string8, temp_path::TempPath};
+
+    const LARGE_FILE_OFFSET: u64 = 60 * 1024 * 1024 * 1024 + 0x1240;
+
+    /// Copy `examples/Empty.pst` to a file in the temp directory which the test can write to.
+    fn writable_copy(name: &str) -> TempPath {
+        TempPath::empty_pst(name)
+    }
+
+    fn root_folder_id(path: &Path) -> FolderId {
+        let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(path).unwrap())).unwrap();
+        let entry_id = store.properties().root_folder_entry_id().unwrap();
+        FolderId::try_from(entry_id.node_id()).unwrap()
+    }
+
+    #[test]
+    fn test_open_any() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst");
+        let pst = open_any(&path).unwrap();
+        assert!(pst.is_unicode());
+        assert_eq!(pst.ndb_version(), NdbVersion::Unicode);
+        let PstAny::Unicode { file, .. } = &pst else {
+            panic!("expected a Unicode file");
+        };
+        assert_eq!(
+            file.free_space_map().unwrap().file_eof(),
+            std::fs::metadata(&path).unwrap().len()
+        );
+        assert_eq!(
+            open_store(&path).unwrap().unique_value(),
+            pst.store().unique_value()
+        );
+    }
+
+    #[test]
+    fn test_amap_page_index_large_file() {
+        let (amap_index, offset) =
+            amap_page_index(LARGE_FILE_OFFSET).expect("Failed to split offset");
+        assert!(amap_index > u64::from(u16::MAX));
+        assert_eq!(
+            amap_page_offset(amap_index).expect("Failed to compute offset") + offset,
+            LARGE_FILE_OFFSET
+        );
+    }
+
+    #[test]
+    fn test_allocation_map_page_layout_large_file() {
+        let amap_index = FPMAP_FIRST_SIZE + 7 * FPMAP_PAGE_COUNT;
+        assert!(amap_page_offset(amap_index).expect("Failed to compute offset") > 50 << 30);
+        assert_eq!(
+            PstFileInner::<UnicodePstFile>::allocation_map_page_layout(amap_index),
+            (true, false, true)
+        );
+    }
+
+    fn density(amap_index: u64, free_slots: u16, max_free_slots: u8) -> AllocationMapPageDensity {
+        AllocationMapPageDensity {
+            amap_index,
+            free_slots,
+            max_free_slots,
+        }
+    }
+
+    #[test]
+    fn test_free_space_maps_consistent() {
+        let densities = [density(0, 10, 4), density(1, 0, 0), density(2, 300, 255)];
+        let density_list = density_list_entries(
+            densities
+                .iter()
+                .map(|density| (density.amap_index, density.free_slots)),
+            8,
+        )
+        .unwrap();
+        assert_eq!(
+            density_list
+                .iter()
+                .map(|entry| (entry.page(), entry.free_slots()))
+                .collect::<Vec<_>>(),
+            [(2, 300), (0, 10)]
+        );
+
+        let mut free_map = [0xFF; 128];
+        for density in densities.iter() {
+            free_map[density.amap_index as usize] = density.max_free_slots;
+        }
+        assert!(compare_free_space_maps(&densities, &free_map, &density_list).is_empty());
+    }
+
+    #[test]
+    fn test_free_space_maps_after_interrupted_write() {
+        // The AMap page and rgbFM were updated for an allocation in page 0, but the density list
+        // was not rewritten before the crash.
+        let before = [density(0, 10, 4), density(1, 20, 20)];
+        let density_list = density_list_entries(
+            before
+                .iter()
+                .map(|density| (density.amap_index, density.free_slots)),
+            8,
+        )
+        .unwrap();
+        let after = [density(0, 6, 4), density(1, 20, 20)];
+        let free_map = [4, 20];
+        assert_eq!(
+            compare_free_space_maps(&after, &free_map, &density_list),
+            [FreeSpaceMapMismatch::DensityListEntry {
+                amap_index: 0,
+                expected: Some(6),
+                actual: 10,
+            }]
+        );
+
+        // The file was truncated back to the first AMap page after growing, but rgbFM and the
+        // density list still describe the second one.
+        let truncated = [density(0, 6, 2)];
+        assert_eq!(
+            compare_free_space_maps(&truncated, &free_map, &density_list),
+            [
+                FreeSpaceMapMismatch::FreeMap {
+                    amap_index: 0,
+                    expected: 2,
+                    actual: 4,
+                },
+                FreeSpaceMapMismatch::DensityListEntry {
+                    amap_index: 1,
+                    expected: None,
+                    actual: 20,
+                },
+                FreeSpaceMapMismatch::DensityListEntry {
+                    amap_index: 0,
+                    expected: Some(6),
+                    actual: 10,
+                },
+            ]
+        );
+
+        let unsorted = [
+            DensityListPageEntry::new(0, 6).unwrap(),
+            DensityListPageEntry::new(1, 20).unwrap(),
+        ];
+        assert_eq!(
+            compare_free_space_maps(&after, &free_map, &unsorted),
+            [FreeSpaceMapMismatch::DensityListOrder]
+        );
+    }
+
+    #[test]
+    fn test_ansi_file_size_limit() {
+        let amap_index = ANSI_MAX_FILE_SIZE / AMAP_DATA_SIZE;
+        let file_eof = amap_page_offset(amap_index).expect("Failed to compute offset");
+        assert!(check_file_size_limit(NdbVersion::Ansi, file_eof).is_ok());
+
+        let file_eof = amap_page_offset(amap_index + 1).expect("Failed to compute offset");
+        assert!(matches!(
+            check_file_size_limit(NdbVersion::Ansi, file_eof),
+            Err(PstError::FileSizeLimit(size)) if size == file_eof
+        ));
+        assert!(check_file_size_limit(NdbVersion::Unicode, file_eof).is_ok());
+    }
+
+    #[test]
+    fn test_write_support() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst");
+        let pst = UnicodePstFile::read_from(Box::new(PositionedFile::open(path).unwrap())).unwrap();
+        let support = pst.write_support();
+        assert_eq!(support.version(), NdbVersion::Unicode);
+        assert!(!support.is_writable());
+        assert_eq!(support.max_file_size(), None);
+    }
+
+    #[test]
+    fn test_concurrent_reads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<UnicodePstFile>();
+        assert_send_sync::<AnsiPstFile>();
+
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst");
+        let pst = Arc::new(UnicodePstFile::open(path).unwrap());
+        fn read_nodes(pst: &UnicodePstFile) -> Vec<Vec<u8>> {
+            [NID_MESSAGE_STORE, NID_ROOT_FOLDER, NID_NAME_TO_ID_MAP]
+                .into_iter()
+                .map(|node| {
+                    let node = pst.read_node(node).unwrap();
+                    pst.read_block(node.data()).unwrap()
+                })
+                .collect()
+        }
+        let expected = read_nodes(&pst);
+
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let pst = pst.clone();
+                std::thread::spawn(move || (0..16).map(|_| read_nodes(&pst)).collect::<Vec<_>>())
+            })
+            .collect();
+        for thread in threads {
+            for blocks in thread.join().unwrap() {
+                assert_eq!(blocks, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ansi_write_round_trip() {
+        let path = TempPath::empty_ansi_pst("ansi-write");
+        let check = |path: &Path| {
+            let pst = AnsiPstFile::open(path).unwrap();
+            let report = pst.quick_check(&QuickCheckOptions::new()).unwrap();
+            assert!(report.is_healthy(), "{:?}", report.samples());
+            AnsiStore::read(Rc::new(pst)).unwrap()
+        };
+
+        let support = AnsiPstFile::open(&path).unwrap().write_support();
+        assert_eq!(support.version(), NdbVersion::Ansi);
+        assert!(support.is_writable());
+
+        let store = check(&path);
+        let folder = store.properties().ipm_sub_tree_entry_id().unwrap();
+        assert_eq!(
+            store
+                .open_folder(&folder)
+                .unwrap()
+                .properties()
+                .display_name()
+                .unwrap(),
+            "Top of Outlook data file"
+        );
+        let folder = FolderId::try_from(folder.node_id()).unwrap();
+        let mut properties = StorePropertiesMut::from(store.properties());
+        properties.set_display_name("Archiv März");
+        drop(store);
+
+        let message = {
+            let mut pst = AnsiPstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            guard.update_store_properties(&properties).unwrap();
+            let mut builder = MessageBuilder::new("IPM.Note");
+            builder.set_property(
+                0x0037,
+                PropertyValue::String8(String8Value::new(encode_string8("Grüße", 1252))),
+            );
+            let message = guard.create_message(folder, &builder).unwrap();
+            guard.flush().unwrap();
+            message
+        };
+
+        let store = check(&path);
+        assert!(matches!(
+            store.properties().get(0x3001),
+            Some(PropertyValue::String8(_))
+        ));
+        assert_eq!(store.properties().display_name().unwrap(), "Archiv März");
+
+        let entry_id = store.properties().make_entry_id(message.into()).unwrap();
+        let message = store.open_message(&entry_id, None).unwrap();
+        let properties = message.properties();
+        assert_eq!(properties.message_class().unwrap(), "IPM.Note");
+        let Some(PropertyValue::String8(subject)) = properties.get(0x0037) else {
+            panic!("expected a PtypString8 subject");
+        };
+        assert_eq!(
+            subject.to_string_with_decoder(properties.string_decoder()),
+            "Grüße"
+        );
+
+        let folder = store.open_folder(&store.properties().ipm_sub_tree_entry_id().unwrap());
+        let contents_table = folder.unwrap().contents_table().cloned().unwrap();
+        assert_eq!(contents_table.rows_matrix().count(), 1);
+    }
+
+    /// Read the property context of `node` from the file at `path`.
+    fn node_properties(path: &Path, node: NodeId) -> BTreeMap<u16, PropertyValue> {
+        let pst = UnicodePstFile::open(path).unwrap();
+        read_property_context(&pst.inner.read_node_data(node).unwrap()).unwrap()
+    }
+
+    fn node_message_size(path: &Path, node: NodeId) -> i64 {
+        message_size_extended(&node_properties(path, node)).unwrap_or_default()
+    }
+
+    fn node_data_size(path: &Path, node: NodeId) -> i64 {
+        let pst = UnicodePstFile::open(path).unwrap();
+        pst.inner.read_node_data(node).unwrap().size() as i64
+    }
+
+    #[test]
+    fn test_update_message_sizes() {
+        let path = writable_copy("update-message");
+        let folder = root_folder_id(&path);
+
+        let message = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            guard.recompute_sizes().unwrap();
+            let mut builder = MessageBuilder::new("IPM.Note");
+            builder.set_property(0x1000, unicode_value("Short body."));
+            let message = guard.create_message(folder, &builder).unwrap();
+            guard.flush().unwrap();
+            message
+        };
+        let folder_size = node_message_size(&path, folder.into());
+        let store_size = node_message_size(&path, NID_MESSAGE_STORE);
+        let message_size = node_data_size(&path, message.into());
+        assert!(folder_size >= message_size);
+
+        {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let mut builder = MessageBuilder::new("IPM.Note");
+            builder.set_property(0x1000, unicode_value(&"Longer body. ".repeat(1000)));
+            guard.update_message(message, &builder).unwrap();
+            assert!(guard.recompute_sizes().unwrap().is_empty());
+            guard.flush().unwrap();
+        }
+
+        let delta = node_data_size(&path, message.into()) - message_size;
+        assert!(delta > 0);
+        assert_eq!(node_message_size(&path, folder.into()), folder_size + delta);
+        assert_eq!(
+            node_message_size(&path, NID_MESSAGE_STORE),
+            store_size + delta
+        );
+    }
+
+    #[test]
+    fn test_create_message_skips_used_nid() {
+        let path = writable_copy("create-message-used-nid");
+        let folder = root_folder_id(&path);
+
+        let first = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let message = guard
+                .create_message(folder, &MessageBuilder::new("IPM.Note"))
+                .unwrap();
+            guard.flush().unwrap();
+            NodeId::from(message)
+        };
+
+        // Point rgnid[NID_TYPE_NORMAL_MESSAGE] back at the message which was just created, and
+        // update both header CRCs to match.
+        {
+            let mut file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let mut header = [0_u8; 528];
+            file.read_exact(&mut header).unwrap();
+            let offset = 44 + 4 * NodeIdType::NormalMessage as usize;
+            header[offset..offset + 4].copy_from_slice(&first.index().to_le_bytes());
+            let crc_partial = compute_crc(0, &header[8..8 + 471]);
+            header[4..8].copy_from_slice(&crc_partial.to_le_bytes());
+            let crc_full = compute_crc(0, &header[8..524]);
+            header[524..528].copy_from_slice(&crc_full.to_le_bytes());
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file.write_all(&header).unwrap();
+        }
+
+        let second = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let message = guard
+                .create_message(folder, &MessageBuilder::new("IPM.Note"))
+                .unwrap();
+            guard.flush().unwrap();
+            NodeId::from(message)
+        };
+        assert_ne!(second, first);
+        assert_eq!(second.index(), first.index() + 1);
+        assert_quick_check(&path);
+    }
+
+    #[test]
+    fn test_repair_counts() {
+        let path = writable_copy("repair-counts");
+        let folder = root_folder_id(&path);
+
+        {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            guard.repair_counts().unwrap();
+            guard
+                .create_message(folder, &MessageBuilder::new("IPM.Note"))
+                .unwrap();
+
+            let node = NodeId::from(folder);
+            let mut properties =
+                read_property_context(&guard.read_node_data(node).unwrap()).unwrap();
+            properties.insert(0x3602, PropertyValue::Integer32(7));
+            properties.insert(0x3603, PropertyValue::Integer32(4));
+            properties.insert(0x360A, PropertyValue::Boolean(false));
+            let data = build_property_context(properties.iter()).unwrap();
+            guard.replace_node(node, &data).unwrap();
+            guard.flush().unwrap();
+        }
+
+        {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let correction = |counter, previous, value| CountCorrection {
+                folder: folder.into(),
+                counter,
+                location: CountLocation::FolderProperties,
+                previous: Some(previous),
+                value,
+            };
+            assert_eq!(
+                guard.repair_counts().unwrap(),
+                [
+                    correction(FolderCounter::ContentCount, 7, 1),
+                    correction(FolderCounter::ContentUnreadCount, 4, 1),
+                    correction(FolderCounter::Subfolders, 0, 1),
+                ]
+            );
+            guard.flush().unwrap();
+        }
+
+        let properties = node_properties(&path, folder.into());
+        for counter in [
+            FolderCounter::ContentCount,
+            FolderCounter::ContentUnreadCount,
+            FolderCounter::Subfolders,
+        ] {
+            let value = properties.get(&counter.prop_id()).and_then(counter_value);
+            assert_eq!(value, Some(1), "{counter:?}");
+        }
+        {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            assert!(guard.repair_counts().unwrap().is_empty());
+        }
+    }
+
+    fn assert_quick_check(path: &Path) {
+        let pst = UnicodePstFile::open(path).unwrap();
+        let report = pst.quick_check(&QuickCheckOptions::new()).unwrap();
+        assert!(report.is_healthy(), "{:?}", report.samples());
+    }
+
+    #[test]
+    fn test_fix_crcs() {
+        let path = writable_copy("fix-crcs");
+        let (page_offset, block, block_offset, trailer_offset) = {
+            let pst = UnicodePstFile::open(&path).unwrap();
+            let page_offset = pst.header().root().node_btree().index().index();
+            let block = pst.read_node(NID_MESSAGE_STORE).unwrap().data();
+            let mut location = None;
+            pst.visit_blocks(
+                &mut |entry: &UnicodeBlockBTreeEntry, _: BlockKind, _: &[u8]| {
+                    if entry.block().block() == block {
+                        location = Some((entry.block().index().index(), entry.size()));
+                    }
+                    Ok(())
+                },
+            )
+            .unwrap();
+            let (block_offset, size) = location.unwrap();
+            let size = size + <UnicodeBlockTrailer as BlockTrailerReadWrite>::SIZE;
+            let trailer_offset = block_offset + u64::from(block_size(size))
+                - u64::from(<UnicodeBlockTrailer as BlockTrailerReadWrite>::SIZE);
+            (page_offset, block, block_offset, trailer_offset)
+        };
+
+        // Flip bits in the wSig field of the NBT root page and the dwCRC field of the message
+        // store block, leaving the contents alone.
+        {
+            let mut file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            for offset in [page_offset + PAGE_SIZE as u64 - 14, trailer_offset + 4] {
+                let mut value = [0_u8; 2];
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                file.read_exact(&mut value).unwrap();
+                file.seek(SeekFrom::Start(offset)).unwrap();
+                file.write_all(&[!value[0], value[1]]).unwrap();
+            }
+        }
+
+        {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let mut repaired = fix_crcs(&mut guard).unwrap();
+            repaired.sort_by_key(RepairedLocation::offset);
+            let mut expected = [
+                RepairedLocation {
+                    offset: page_offset,
+                    target: RepairTarget::Page(PageType::NodeBTree),
+                    fixed_crc: false,
+                    fixed_signature: true,
+                },
+                RepairedLocation {
+                    offset: block_offset,
+                    target: RepairTarget::Block(block.into_u64()),
+                    fixed_crc: true,
+                    fixed_signature: false,
+                },
+            ];
+            expected.sort_by_key(RepairedLocation::offset);
+            assert_eq!(repaired, expected);
+            guard.flush().unwrap();
+        }
+
+        let mut pst = UnicodePstFile::open(&path).unwrap();
+        pst.read_node_data(NID_MESSAGE_STORE).unwrap();
+        let mut guard = pst.lock().unwrap();
+        assert_eq!(fix_crcs(&mut guard).unwrap(), []);
+        drop(guard);
+        drop(pst);
+        assert_quick_check(&path);
+    }
+
+    #[test]
+    fn test_scrub_message() {
+        use messaging::attachment::AttachmentBuilder;
+
+        let path = writable_copy("scrub-message");
+        let folder = root_folder_id(&path);
+
+        let message = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            guard.recompute_sizes().unwrap();
+            let mut builder = MessageBuilder::new("IPM.Note");
+            builder.set_property(0x1000, unicode_value(&"Sensitive body. ".repeat(1000)));
+            builder.set_property(0x0E1B, PropertyValue::Boolean(true));
+            for name in ["first.bin", "second.bin"] {
+                let data = vec![0xA5; 20_000];
+                builder.add_attachment(AttachmentBuilder::by_value(name, None, data));
+            }
+            let message = guard.create_message(folder, &builder).unwrap();
+            guard.flush().unwrap();
+            message
+        };
+
+        let sizes = || {
+            [
+                node_data_size(&path, message.into()),
+                node_message_size(&path, folder.into()),
+                node_message_size(&path, NID_MESSAGE_STORE),
+            ]
+        };
+        let assert_sizes_moved = |before: [i64; 3]| {
+            let after = sizes();
+            let delta = after[0] - before[0];
+            assert!(delta < 0);
+            assert_eq!(after[1], before[1] + delta);
+            assert_eq!(after[2], before[2] + delta);
+            let properties = node_properties(&path, message.into());
+            let Some(PropertyValue::Integer32(size)) = properties.get(&0x0E08) else {
+                panic!("missing PidTagMessageSize");
+            };
+            assert_eq!(i64::from(*size), after[0]);
+        };
+
+        let before = sizes();
+        {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            assert_eq!(guard.strip_attachments(message).unwrap(), 2);
+            assert!(guard.validate_structures().unwrap().is_empty());
+            guard.flush().unwrap();
+        }
+
+        let pst = UnicodePstFile::open(&path).unwrap();
+        let data = pst.inner.read_node_data(message.into()).unwrap();
+        assert!(!data.sub_nodes().keys().any(|sub_node| {
+            *sub_node == NID_ATTACHMENT_TABLE
+                || matches!(sub_node.id_type(), Ok(NodeIdType::Attachment))
+        }));
+        drop(pst);
+        let properties = node_properties(&path, message.into());
+        assert!(matches!(
+            properties.get(&0x0E1B),
+            Some(PropertyValue::Boolean(false))
+        ));
+        let Some(PropertyValue::Integer32(flags)) = properties.get(&0x0E07) else {
+            panic!("missing PidTagMessageFlags");
+        };
+        assert_eq!(flags & MSGFLAG_HASATTACH, 0);
+        assert_sizes_moved(before);
+        assert_quick_check(&path);
+
+        let before = sizes();
+        {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            guard.replace_body(message, "Removed.").unwrap();
+            assert!(guard.recompute_sizes().unwrap().is_empty());
+            guard.flush().unwrap();
+        }
+
+        let properties = node_properties(&path, message.into());
+        let Some(PropertyValue::Unicode(body)) = properties.get(&0x1000) else {
+            panic!("missing PidTagBody");
+        };
+        assert_eq!(body.to_string(), "Removed.");
+        assert_sizes_moved(before);
+        assert_quick_check(&path);
+    }
+
+    /// Save each of the `values` in turn to the root folder of a copy of `Empty.pst` with `save`,
+    /// and check that `load` reads it back after reopening the file. Every save must replace the
+    /// same folder associated information (FAI) message, and it must be the only one in the
+    /// associated contents table which `is_match`.
+    fn save_associated_round_trip<T: PartialEq + Debug>(
+        path: &Path,
+        values: &[T],
+        save: fn(&mut PstFileLockGuard<UnicodePstFile>, FolderId, &T) -> io::Result<MessageId>,
+        load: fn(&UnicodeStore) -> T,
+        is_match: fn(&BTreeMap<u16, PropertyValue>) -> bool,
+    ) {
+        let folder = root_folder_id(path);
+        let mut saved = None;
+        for value in values {
+            let message = {
+                let mut pst = UnicodePstFile::open(path).unwrap();
+                let mut guard = pst.lock().unwrap();
+                let message = save(&mut guard, folder, value).unwrap();
+                assert!(guard.validate_structures().unwrap().is_empty());
+                guard.flush().unwrap();
+                message
+            };
+            assert!(matches!(
+                NodeId::from(message).id_type(),
+                Ok(NodeIdType::AssociatedMessage)
+            ));
+            assert_eq!(*saved.get_or_insert(message), message);
+
+            let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(path).unwrap())).unwrap();
+            assert_eq!(load(&store), *value);
+        }
+
+        let pst = UnicodePstFile::open(path).unwrap();
+        let associated_table = NodeId::new(
+            NodeIdType::AssociatedContentsTable,
+            folder.node_id().index(),
+        )
+        .unwrap();
+        let (_, rows) =
+            read_table_context(&pst.inner.read_node_data(associated_table).unwrap()).unwrap();
+        let matching = rows
+            .keys()
+            .filter(|row| {
+                let message = NodeId::from(u32::from(**row));
+                is_match(
+                    &read_property_context(&pst.inner.read_node_data(message).unwrap()).unwrap(),
+                )
+            })
+            .count();
+        assert_eq!(matching, 1);
+    }
+
+    #[test]
+    fn test_save_time_index() {
+        let path = writable_copy("save-time-index");
+        let folder = root_folder_id(&path);
+
+        {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            for delivery_time in [0x01D0_0000_0000_0000, 0x01C0_0000_0000_0000] {
+                let mut builder = MessageBuilder::new("IPM.Note");
+                builder.set_property(0x0E06, PropertyValue::Time(delivery_time));
+                guard.create_message(folder, &builder).unwrap();
+            }
+            guard.flush().unwrap();
         }
-        Ok(())
+
+        let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(&path).unwrap())).unwrap();
+        let index = FolderTimeIndex::build(store.root_folder().unwrap().as_ref()).unwrap();
+        assert_eq!(index.entries().len(), 2);
+        assert!(index.entries()[0].0 < index.entries()[1].0);
+        drop(store);
+
+        save_associated_round_trip(
+            &path,
+            &[index],
+            |guard, folder, index| guard.save_time_index(folder, index),
+            |store| {
+                FolderTimeIndex::load(store.root_folder().unwrap().as_ref())
+                    .unwrap()
+                    .unwrap()
+            },
+            is_time_index_message,
+        );
     }
 
-    /// Mark a page at the given file offset as allocated.
-    fn mark_page_allocation(
-        index: u64,
-        amap_pages: &mut [AllocationMapPageInfo<Pst>],
-    ) -> io::Result<()> {
-        let index = index - AMAP_FIRST_OFFSET;
-        let amap_index =
-            usize::try_from(index / AMAP_DATA_SIZE).map_err(|_| PstError::IntegerConversion)?;
-        let entry = amap_pages
-            .get_mut(amap_index)
-            .ok_or(PstError::AllocationMapPageNotFound(amap_index))?;
-        entry.free_space -= PAGE_SIZE as u64;
+    #[test]
+    fn test_save_annotations() {
+        let mut first = FolderAnnotations::new();
+        first.insert("reviewed", "yes");
+        first.insert("tag", vec![1, 2, 3]);
+        let mut second = first.clone();
+        second.remove("tag");
+        second.insert("reviewed", "no");
+
+        save_associated_round_trip(
+            &writable_copy("save-annotations"),
+            &[first, second],
+            |guard, folder, annotations| guard.save_annotations(folder, annotations),
+            |store| store.root_folder().unwrap().annotations().unwrap(),
+            is_annotations_message,
+        );
+    }
 
-        let bytes = entry.amap_page.map_bits_mut();
+    #[test]
+    fn test_save_categories() {
+        let mut first = CategoryList::new();
+        first.add(Category::new("Red Category", 0));
+        first.add(Category::new("Travel", 7).with_keyboard_shortcut(2));
+        let mut second = first.clone();
+        second.remove("Red Category");
+
+        save_associated_round_trip(
+            &writable_copy("save-categories"),
+            &[first, second],
+            |guard, folder, categories| guard.save_categories(folder, categories),
+            |store| store.categories().unwrap(),
+            is_category_list_message,
+        );
+    }
 
-        let bit_index = usize::try_from((index % AMAP_DATA_SIZE) / 64)
-            .map_err(|_| PstError::IntegerConversion)?;
-        let byte_index = bit_index / 8;
-        let bit_index = bit_index % 8;
+    /// Write `payload` with the [`DataTreeWriter`], point a new node at the tree, and read it back
+    /// after reopening the file. Returns the [`BlockKind`] of the root block.
+    fn write_data_tree_round_trip(name: &str, payload: &[u8]) -> BlockKind {
+        let path = writable_copy(name);
+
+        let mut pst = UnicodePstFile::open(&path).unwrap();
+        let (node, block) = {
+            let mut guard = pst.lock().unwrap();
+            let node = guard
+                .allocate_nid(NodeIdType::ListsTablesProperties)
+                .unwrap();
+            let (block, size) = guard.data_tree_writer().write(&mut &payload[..]).unwrap();
+            assert_eq!(size as usize, payload.len());
+            guard.flush().unwrap();
+            (node, block)
+        };
+        // The guard only creates nodes from NodeData, so add the NBT entry for the tree directly.
+        pst.inner.start_write().unwrap();
+        pst.inner
+            .insert_node_btree_entry(UnicodeNodeBTreeEntry::new(node, block, None, None))
+            .unwrap();
+        pst.inner.finish_write().unwrap();
+        drop(pst);
+
+        let pst = UnicodePstFile::open(&path).unwrap();
+        assert!(block.is_internal());
+        let mut kind = None;
+        pst.visit_blocks(
+            &mut |entry: &UnicodeBlockBTreeEntry, block_kind: BlockKind, _: &[u8]| {
+                if entry.block().block() == block {
+                    kind = Some(block_kind);
+                }
+                Ok(())
+            },
+        )
+        .unwrap();
+        let data = pst.inner.read_node_data(node).unwrap();
+        assert_eq!(data.blocks().concat(), payload);
+        drop(pst);
+        assert_quick_check(&path);
 
-        if bit_index == 0 {
-            bytes[byte_index] = 0xFF;
-        } else {
-            let mask = 0x80_u8 >> bit_index;
-            let mask = mask | (mask - 1);
-            bytes[byte_index] |= mask;
-            bytes[byte_index + 1] |= !mask;
+        kind.expect("missing root block")
+    }
+
+    #[test]
+    fn test_write_data_tree() {
+        let leaf_size = usize::from(UnicodeDataTree::max_leaf_size());
+        let fan_out = UnicodeDataTree::max_intermediate_entries();
+        let payload = |size: usize| (0..size).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        let kind = write_data_tree_round_trip("data-tree-xblock", &payload(3 * leaf_size + 100));
+        assert_eq!(kind, BlockKind::DataTree);
+
+        let kind = write_data_tree_round_trip(
+            "data-tree-xxblock",
+            &payload(fan_out * leaf_size + leaf_size / 2),
+        );
+        assert_eq!(kind, BlockKind::DataTreeIndirect);
+    }
+
+    fn node_leaf_sizes(reader: &mut TransactionReader<'_>, page: UnicodePageRef) -> Vec<usize> {
+        match UnicodeNodeBTree::read(reader, page).unwrap() {
+            RootBTreePage::Intermediate(page, ..) => page
+                .entries()
+                .iter()
+                .flat_map(|entry| node_leaf_sizes(reader, entry.block()))
+                .collect(),
+            RootBTreePage::Leaf(page) => vec![page.entries().len()],
         }
+    }
 
-        Ok(())
+    fn block_leaf_sizes(reader: &mut TransactionReader<'_>, page: UnicodePageRef) -> Vec<usize> {
+        match UnicodeBlockBTree::read(reader, page).unwrap() {
+            RootBTreePage::Intermediate(page, ..) => page
+                .entries()
+                .iter()
+                .flat_map(|entry| block_leaf_sizes(reader, entry.block()))
+                .collect(),
+            RootBTreePage::Leaf(page) => vec![page.entries().len()],
+        }
     }
 
-    /// Mark a block at the given file offset and size as allocated.
-    fn mark_block_allocation(
-        index: u64,
-        size: u16,
-        amap_pages: &mut [AllocationMapPageInfo<Pst>],
-    ) -> io::Result<()> {
-        let index = index - AMAP_FIRST_OFFSET;
-        let amap_index =
-            usize::try_from(index / AMAP_DATA_SIZE).map_err(|_| PstError::IntegerConversion)?;
-        let entry = amap_pages
-            .get_mut(amap_index)
-            .ok_or(PstError::AllocationMapPageNotFound(amap_index))?;
-        let size = u64::from(block_size(
-            size + <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::SIZE,
-        ));
-        entry.free_space -= size;
+    /// Walk the NBT and the BBT of the file at `path`, and return the level of the NBT root and
+    /// the number of entries in each leaf page of both BTrees.
+    fn btree_leaf_sizes(path: &Path) -> (u8, Vec<usize>, Vec<usize>) {
+        let pst = UnicodePstFile::open(path).unwrap();
+        let root = pst.header().root();
+        let reader = &mut pst.reader();
+        let level = UnicodeNodeBTree::read(reader, *root.node_btree())
+            .unwrap()
+            .level();
+        let nodes = node_leaf_sizes(reader, *root.node_btree());
+        let blocks = block_leaf_sizes(reader, *root.block_btree());
+        (level, nodes, blocks)
+    }
 
-        let bytes = entry.amap_page.map_bits_mut();
+    #[test]
+    fn test_btree_shape_after_insert_and_remove() {
+        let path = writable_copy("btree-shape");
+        let folder = root_folder_id(&path);
+        let message = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let mut builder = MessageBuilder::new("IPM.Note");
+            builder.set_property(0x1000, unicode_value(&"Shared body. ".repeat(1000)));
+            let message = guard.create_message(folder, &builder).unwrap();
+            guard.flush().unwrap();
+            message
+        };
+        let (node_level, node_sizes, block_sizes) = btree_leaf_sizes(&path);
+        let node_count: usize = node_sizes.iter().sum();
+        let block_count: usize = block_sizes.iter().sum();
 
-        let bit_start = usize::try_from((index % AMAP_DATA_SIZE) / 64)
-            .map_err(|_| PstError::IntegerConversion)?;
-        let bit_end =
-            bit_start + usize::try_from(size / 64).map_err(|_| PstError::IntegerConversion)?;
-        let byte_start = bit_start / 8;
-        let bit_start = bit_start % 8;
-        let byte_end = bit_end / 8;
-        let bit_end = bit_end % 8;
+        let root = |pst: UnicodePstFile| {
+            let root = pst.header().root();
+            (*root.node_btree(), *root.block_btree())
+        };
+        let (old_node_root, old_block_root) = root(UnicodePstFile::open(&path).unwrap());
+        let page_bytes = |page: UnicodePageRef| {
+            let offset = page.index().index() as usize;
+            std::fs::read(&path).unwrap()[offset..offset + PAGE_SIZE].to_vec()
+        };
+        let old_node_page = page_bytes(old_node_root);
+        let old_block_page = page_bytes(old_block_root);
+
+        let copies = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let copies: Vec<_> = (0..400)
+                .map(|_| {
+                    guard
+                        .pst
+                        .copy_node(message.into(), Some(folder.into()), true)
+                        .unwrap()
+                })
+                .collect();
+            guard.flush().unwrap();
+            copies
+        };
+        assert_quick_check(&path);
+
+        // The committed pages were copied rather than rewritten, and the header points at the
+        // copies.
+        let (node_root, block_root) = root(UnicodePstFile::open(&path).unwrap());
+        assert_ne!(node_root.index().index(), old_node_root.index().index());
+        assert_ne!(block_root.index().index(), old_block_root.index().index());
+        assert_eq!(page_bytes(old_node_root), old_node_page);
+        assert_eq!(page_bytes(old_block_root), old_block_page);
+
+        // Appending ascending keys fills the new pages instead of leaving them half empty.
+        let (level, sizes, _) = btree_leaf_sizes(&path);
+        assert!(level > node_level);
+        let max_entries = sizes.iter().copied().max().unwrap();
+        let partial_pages = sizes.iter().filter(|&&size| size < max_entries).count();
+        assert!(partial_pages <= node_sizes.len() + 1);
 
-        if byte_start == byte_end {
-            // The allocation fits in a single byte
-            if bit_end > bit_start {
-                let mask_start = 0x80_u8 >> bit_start;
-                let mask_start = mask_start | (mask_start - 1);
-                let mask_end = 0x80_u8 >> bit_end;
-                let mask_end = !(mask_end | (mask_end - 1));
-                let mask = mask_start & mask_end;
-                bytes[byte_start] |= mask;
+        {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            for copy in copies {
+                guard.pst.delete_message(copy).unwrap();
             }
-            return Ok(());
+            guard.flush().unwrap();
         }
+        assert_quick_check(&path);
+
+        // Every page which was split is merged again, and the blocks which only the deleted
+        // copies referred to were freed.
+        let (level, sizes, blocks) = btree_leaf_sizes(&path);
+        assert_eq!(level, node_level);
+        assert!(sizes.iter().all(|&size| size > 0));
+        assert_eq!(sizes.iter().sum::<usize>(), node_count);
+        assert!(blocks.iter().all(|&size| size > 0));
+        assert_eq!(blocks.iter().sum::<usize>(), block_count);
+    }
 
-        let byte_start = if bit_start == 0 {
-            byte_start
-        } else {
-            let mask_start = 0x80_u8 >> bit_start;
-            let mask_start = mask_start | (mask_start - 1);
-            bytes[byte_start] |= mask_start;
-            byte_start + 1
+    #[test]
+    fn test_new_blocks_are_unshared() {
+        let path = writable_copy("unshared-blocks");
+        let folder = root_folder_id(&path);
+        let ref_count = |block: UnicodeBlockId| {
+            let pst = UnicodePstFile::open(&path).unwrap();
+            pst.inner.block_ref_count(block)
         };
 
-        if bit_end != 0 {
-            let mask_end = 0x80_u8 >> bit_end;
-            let mask_end = !(mask_end | (mask_end - 1));
-            bytes[byte_end] |= mask_end;
+        let (message, sub_node) = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let mut builder = MessageBuilder::new("IPM.Note");
+            builder.set_property(0x1000, unicode_value(&"Body. ".repeat(2000)));
+            let message = guard.create_message(folder, &builder).unwrap();
+            guard.flush().unwrap();
+            drop(guard);
+            let sub_node = pst.read_node(message.into()).unwrap().sub_node().unwrap();
+            (message, sub_node)
         };
+        assert_eq!(ref_count(sub_node).unwrap(), UNSHARED_BLOCK_REF_COUNT);
+
+        let copy = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let copy = guard
+                .pst
+                .copy_node(message.into(), Some(folder.into()), true)
+                .unwrap();
+            guard.flush().unwrap();
+            copy
+        };
+        assert_eq!(ref_count(sub_node).unwrap(), UNSHARED_BLOCK_REF_COUNT + 1);
+
+        let mut pst = UnicodePstFile::open(&path).unwrap();
+        let mut guard = pst.lock().unwrap();
+        guard.pst.delete_message(copy).unwrap();
+        guard.flush().unwrap();
+        assert_eq!(ref_count(sub_node).unwrap(), UNSHARED_BLOCK_REF_COUNT);
+        guard.delete_message(message).unwrap();
+        guard.flush().unwrap();
+        drop(guard);
+        drop(pst);
+        assert!(ref_count(sub_node).is_err());
+        assert_quick_check(&path);
+    }
 
-        if byte_end > byte_start {
-            for byte in bytes[byte_start..byte_end].iter_mut() {
-                *byte = 0xFF;
+    #[test]
+    fn test_large_property_values() {
+        let path = writable_copy("large-values");
+        let folder = root_folder_id(&path);
+        let value = |i: usize, len: usize| {
+            (0..len)
+                .map(|j| char::from(b'a' + ((i + j) % 26) as u8))
+                .collect::<String>()
+        };
+        // Fill several heap blocks with values just under the largest heap allocation, and spill
+        // the body into its own sub-node.
+        let properties: Vec<_> = [
+            0x0037, 0x0E1D, 0x0070, 0x0C1A, 0x0E02, 0x0E03, 0x0E04, 0x0042,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, prop_id)| (prop_id, value(i, 1700)))
+        .chain(iter::once((0x1000, value(0, 50_000))))
+        .collect();
+
+        let message = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let mut builder = MessageBuilder::new("IPM.Note");
+            for (prop_id, value) in &properties {
+                builder.set_property(*prop_id, unicode_value(value));
             }
+            let message = guard.create_message(folder, &builder).unwrap();
+            guard.flush().unwrap();
+            message
+        };
+        assert_quick_check(&path);
+
+        let read = node_properties(&path, message.into());
+        for (prop_id, value) in properties {
+            let Some(PropertyValue::Unicode(read)) = read.get(&prop_id) else {
+                panic!("missing property 0x{prop_id:04X}");
+            };
+            assert_eq!(read.to_string(), value);
         }
+    }
 
-        Ok(())
+    #[test]
+    fn test_snapshot_pinned_to_old_roots() {
+        let path = writable_copy("snapshot");
+        let folder = root_folder_id(&path);
+        let body = "Snapshot body. ".repeat(1000);
+        let existing = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let mut builder = MessageBuilder::new("IPM.Note");
+            builder.set_property(0x1000, unicode_value(&body));
+            let message = guard.create_message(folder, &builder).unwrap();
+            guard.flush().unwrap();
+            message
+        };
+
+        let pst = Rc::new(UnicodePstFile::open(&path).unwrap());
+        let store = UnicodeStore::read(pst.clone()).unwrap();
+        let snapshot = store.snapshot().unwrap();
+        drop(store);
+
+        // Change the file through the same PstFile, so the space which the snapshot still reads is
+        // released and would be reused by the following transactions if it were not pinned.
+        let mut pst = Rc::try_unwrap(pst).ok().unwrap();
+        let message = {
+            let mut guard = pst.lock().unwrap();
+            let message = guard
+                .create_message(folder, &MessageBuilder::new("IPM.Note"))
+                .unwrap();
+            guard.flush().unwrap();
+            guard.delete_message(existing).unwrap();
+            guard.flush().unwrap();
+            for _ in 0..100 {
+                guard
+                    .pst
+                    .copy_node(message.into(), Some(folder.into()), false)
+                    .unwrap();
+            }
+            guard.flush().unwrap();
+            message
+        };
+        assert!(!pst.inner.pinned_space.is_empty());
+
+        let row_count = |store: &dyn Store| {
+            let root_folder = store.root_folder().unwrap();
+            root_folder.contents_table().unwrap().rows_matrix().count()
+        };
+        let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(&path).unwrap())).unwrap();
+        let entry_id = store.properties().make_entry_id(message.into()).unwrap();
+        assert_eq!(
+            store.contains(&entry_id).unwrap(),
+            Some(NodeIdType::NormalMessage)
+        );
+        assert_eq!(snapshot.contains(&entry_id).unwrap(), None);
+        assert_eq!(row_count(store.as_ref()), row_count(snapshot.as_ref()) + 1);
+
+        let entry_id = store.properties().make_entry_id(existing.into()).unwrap();
+        assert_eq!(store.contains(&entry_id).unwrap(), None);
+        let existing = snapshot.open_message(&entry_id, None).unwrap();
+        let Some(PropertyValue::Unicode(value)) = existing.properties().get(0x1000) else {
+            panic!("missing PidTagBody");
+        };
+        assert_eq!(value.to_string(), body);
+        drop(existing);
+        drop(snapshot);
+
+        // The next transaction frees the space once the snapshot is gone.
+        pst.lock().unwrap().flush().unwrap();
+        assert!(pst.inner.pinned_space.is_empty());
+        drop(pst);
+        assert_quick_check(&path);
     }
 
-    /// Initialize the density list at the beginning of a transaction if it is missing, corrupt, or
-    /// the page ID doesn't match the next page ID in the header.
-    fn ensure_density_list(&mut self) -> PstResult<()> {
-        if let Ok(density_list) = self.density_list.as_ref() {
-            if density_list.trailer().block_id() == self.header.next_page() {
-                return Ok(());
+    #[test]
+    fn test_auto_flush() {
+        let path = writable_copy("auto-flush");
+        let folder = root_folder_id(&path);
+        let contains = |message: MessageId| {
+            let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(&path).unwrap())).unwrap();
+            let entry_id = store.properties().make_entry_id(message.into()).unwrap();
+            store.contains(&entry_id).unwrap().is_some()
+        };
+
+        let mut pst = UnicodePstFile::open(&path).unwrap();
+        let mut guard = pst.lock().unwrap();
+        guard.set_auto_flush_policy(AutoFlushPolicy::new().with_max_dirty_bytes(1));
+        let messages: Vec<_> = ["First", "Second", "Third"]
+            .into_iter()
+            .map(|subject| {
+                let mut builder = MessageBuilder::new("IPM.Note");
+                builder.set_property(0x0037, unicode_value(subject));
+                guard.create_message(folder, &builder).unwrap()
+            })
+            .collect();
+
+        // Each message after the first flushes the one before it, and the last one is still
+        // pending.
+        assert_eq!(guard.flush_metrics().implicit_flushes(), 2);
+        assert_eq!(guard.flush_metrics().explicit_flushes(), 0);
+        assert!(guard.dirty_bytes() > 0);
+        assert!(contains(messages[0]));
+        assert!(contains(messages[1]));
+        assert!(!contains(messages[2]));
+
+        drop(guard);
+        drop(pst);
+        assert!(contains(messages[2]));
+    }
+
+    #[test]
+    fn test_copy_and_delete_message() {
+        let path = writable_copy("copy-message");
+        let folder = root_folder_id(&path);
+        let body = "Copied body. ".repeat(1000);
+
+        let (message, copy) = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let mut builder = MessageBuilder::new("IPM.Note");
+            builder.set_property(0x1000, unicode_value(&body));
+            let message = guard.create_message(folder, &builder).unwrap();
+            let copy = guard.copy_message(message, folder, true).unwrap();
+            let keys = |node: MessageId| {
+                record_and_search_keys(
+                    &read_property_context(&guard.read_node_data(node.into()).unwrap()).unwrap(),
+                )
+            };
+            let (record_key, search_key) = keys(copy);
+            assert_ne!(record_key, keys(message).0);
+            assert_ne!(search_key, keys(message).1);
+            assert!(record_key.ends_with(&u32::from(NodeId::from(copy)).to_le_bytes()));
+            guard.delete_message(message).unwrap();
+            assert!(guard.validate_structures().unwrap().is_empty());
+            guard.flush().unwrap();
+            (message, copy)
+        };
+
+        let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(&path).unwrap())).unwrap();
+        let entry_id = store.properties().make_entry_id(message.into()).unwrap();
+        assert_eq!(store.contains(&entry_id).unwrap(), None);
+        let entry_id = store.properties().make_entry_id(copy.into()).unwrap();
+        let message = store.open_message(&entry_id, None).unwrap();
+        let Some(PropertyValue::Unicode(value)) = message.properties().get(0x1000) else {
+            panic!("missing PidTagBody");
+        };
+        assert_eq!(value.to_string(), body);
+    }
+
+    #[test]
+    fn test_clone_subtree() {
+        let path = writable_copy("clone-subtree");
+        let root = root_folder_id(&path);
+        let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(&path).unwrap())).unwrap();
+        let entry_id = store.properties().ipm_sub_tree_entry_id().unwrap();
+        let folder = FolderId::try_from(entry_id.node_id()).unwrap();
+        drop(store);
+
+        let clone = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            for subject in ["First", "Second"] {
+                let mut builder = MessageBuilder::new("IPM.Note");
+                builder.set_property(0x0037, unicode_value(subject));
+                guard.create_message(folder, &builder).unwrap();
             }
-        }
+            let clone = guard.clone_subtree(folder, root).unwrap();
+            assert!(guard.validate_structures().unwrap().is_empty());
+            guard.flush().unwrap();
+            clone
+        };
+        assert_ne!(clone, folder);
 
-        let current_page = u32::try_from(
-            (self.header.root().amap_last_index().index().into() - AMAP_FIRST_OFFSET)
-                / AMAP_DATA_SIZE,
-        )
-        .map_err(|_| PstError::IntegerConversion)?;
-        let block_id = self.header.next_page();
-        let signature = PageType::DensityList
-            .signature(ndb::page::DENSITY_LIST_FILE_OFFSET, block_id.into_u64());
-        let trailer = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
-            PageType::DensityList,
-            signature,
-            block_id,
-            0,
+        let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(&path).unwrap())).unwrap();
+        let open_folder = |folder: FolderId| {
+            let entry_id = store.properties().make_entry_id(folder.into()).unwrap();
+            store.open_folder(&entry_id).unwrap()
+        };
+        let row_count = |table: Option<&Rc<dyn TableContext>>| table.unwrap().rows_matrix().count();
+        let original = open_folder(folder);
+        let cloned = open_folder(clone);
+        assert_eq!(row_count(original.contents_table()), 2);
+        assert_eq!(
+            row_count(cloned.contents_table()),
+            row_count(original.contents_table())
         );
-        let density_list =
-            <<Pst as PstFile>::DensityListPage as DensityListPageReadWrite<Pst>>::new(
-                false,
-                current_page,
-                &[],
-                trailer,
-            )?;
+        assert_eq!(
+            cloned.properties().content_count().unwrap(),
+            original.properties().content_count().unwrap()
+        );
+        assert!(row_count(original.hierarchy_table()) > 0);
+        assert_eq!(
+            row_count(cloned.hierarchy_table()),
+            row_count(original.hierarchy_table())
+        );
+        for row in cloned.hierarchy_table().unwrap().rows_matrix() {
+            let child = FolderId::try_from(NodeId::from(u32::from(row.id()))).unwrap();
+            open_folder(child);
+        }
+        let original_keys: Vec<_> = original
+            .contents_table()
+            .unwrap()
+            .rows_matrix()
+            .map(|row| {
+                let node = NodeId::from(u32::from(row.id()));
+                record_and_search_keys(&node_properties(&path, node))
+            })
+            .collect();
+        for row in cloned.contents_table().unwrap().rows_matrix() {
+            let node = NodeId::from(u32::from(row.id()));
+            let entry_id = store.properties().make_entry_id(node).unwrap();
+            store.open_message(&entry_id, None).unwrap();
+
+            let (record_key, search_key) = record_and_search_keys(&node_properties(&path, node));
+            assert!(record_key.ends_with(&u32::from(node).to_le_bytes()));
+            assert!(original_keys
+                .iter()
+                .all(|keys| keys.0 != record_key && keys.1 != search_key));
+        }
+        assert!(store.verify_table_rows().unwrap().is_empty());
+    }
 
-        self.density_list = Ok(density_list);
-        Ok(())
+    /// `PidTagRecordKey` and `PidTagSearchKey` of a message.
+    fn record_and_search_keys(properties: &BTreeMap<u16, PropertyValue>) -> (Vec<u8>, Vec<u8>) {
+        let key = |prop_id| match properties.get(&prop_id) {
+            Some(PropertyValue::Binary(value)) => value.buffer().to_vec(),
+            _ => panic!("missing key {prop_id:#06X}"),
+        };
+        (key(0x0FF9), key(0x300B))
     }
 
-    /// Similar to [`Self::ensure_density_list`], but instead of resetting the density list, it
-    /// assumes that it's already initialized and only updates the page ID if it doesn't match.
-    fn update_density_list_page_id(&mut self) -> PstResult<()> {
-        let Ok(density_list) = self.density_list.as_ref() else {
-            return Ok(());
+    /// `PidTagSubject` of `message`, and the attachments in its attachment table in order.
+    fn subject_and_attachments(
+        message: &dyn Message,
+    ) -> (String, Vec<Rc<dyn messaging::attachment::Attachment>>) {
+        let Some(PropertyValue::Unicode(subject)) = message.properties().get(0x0037) else {
+            panic!("missing PidTagSubject");
         };
+        let attachments = message
+            .attachment_table()
+            .map(|table| {
+                table
+                    .rows_matrix()
+                    .map(|row| {
+                        let attachment =
+                            AttachmentId::try_from(NodeId::from(u32::from(row.id()))).unwrap();
+                        message.open_attachment(attachment, None).unwrap()
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        (subject.to_string(), attachments)
+    }
 
-        let next_page = self.header.next_page();
-        if density_list.trailer().block_id() == next_page {
-            return Ok(());
+    #[test]
+    fn test_open_embedded_message() {
+        use messaging::attachment::{AttachmentBuilder, AttachmentData, AttachmentMethod};
+
+        let path = writable_copy("embedded-message");
+        let folder = root_folder_id(&path);
+
+        let mut nested = MessageBuilder::new("IPM.Note");
+        nested.set_property(0x0037, unicode_value("Nested"));
+        nested.add_attachment(AttachmentBuilder::by_value(
+            "notes.txt",
+            Some("text/plain"),
+            b"Nested attachment".to_vec(),
+        ));
+        let mut embedded = MessageBuilder::new("IPM.Note");
+        embedded.set_property(0x0037, unicode_value("Embedded"));
+        embedded.add_attachment(AttachmentBuilder::embedded_message("Nested", nested));
+        let mut builder = MessageBuilder::new("IPM.Note");
+        builder.set_property(0x0037, unicode_value("Outer"));
+        builder.add_attachment(AttachmentBuilder::by_value("a.txt", None, b"A".to_vec()));
+        builder.add_attachment(AttachmentBuilder::embedded_message("Embedded", embedded));
+
+        let message = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let message = guard.create_message(folder, &builder).unwrap();
+            assert!(guard.validate_structures().unwrap().is_empty());
+            guard.flush().unwrap();
+            message
+        };
+        assert_quick_check(&path);
+
+        let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(&path).unwrap())).unwrap();
+        let entry_id = store.properties().make_entry_id(message.into()).unwrap();
+        let message = store.open_message(&entry_id, None).unwrap();
+        let (subject, attachments) = subject_and_attachments(message.as_ref());
+        assert_eq!(subject, "Outer");
+        let [by_value, embedded] = attachments.as_slice() else {
+            panic!("expected 2 attachments");
+        };
+        let Err(err) = by_value.open_embedded_message(None) else {
+            panic!("opened a by value attachment as an embedded message");
+        };
+        assert!(matches!(
+            err.get_ref()
+                .and_then(|err| err.downcast_ref::<MessagingError>()),
+            Some(MessagingError::AttachmentNotEmbeddedMessage(
+                AttachmentMethod::ByValue
+            ))
+        ));
+        assert!(matches!(embedded.data(), Some(AttachmentData::Message(_))));
+
+        let embedded = embedded.open_embedded_message(None).unwrap();
+        let (subject, attachments) = subject_and_attachments(embedded.as_ref());
+        assert_eq!(subject, "Embedded");
+        let [nested] = attachments.as_slice() else {
+            panic!("expected 1 attachment");
+        };
+
+        let nested = nested.open_embedded_message(Some(&[0x0037])).unwrap();
+        let (subject, attachments) = subject_and_attachments(nested.as_ref());
+        assert_eq!(subject, "Nested");
+        let [attachment] = attachments.as_slice() else {
+            panic!("expected 1 attachment");
+        };
+        let Some(AttachmentData::Binary(data)) = attachment.data() else {
+            panic!("expected a binary attachment");
+        };
+        assert_eq!(data.buffer(), b"Nested attachment");
+    }
+
+    #[test]
+    fn test_folder_row_helpers() {
+        let path = writable_copy("folder-rows");
+        let folder = root_folder_id(&path);
+
+        let (message, associated) = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let mut builder = MessageBuilder::new("IPM.Note");
+            builder.set_property(0x0037, unicode_value("Normal message"));
+            let message = guard.create_message(folder, &builder).unwrap();
+            let mut builder = MessageBuilder::new("IPM.Configuration.Test");
+            builder.set_property(0x0037, unicode_value("Associated message"));
+            let associated = guard.create_associated_message(folder, &builder).unwrap();
+            guard.flush().unwrap();
+            (message, associated)
+        };
+
+        let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(&path).unwrap())).unwrap();
+        let root_folder = store.root_folder().unwrap();
+        for (table, message, subject) in [
+            (root_folder.contents_table(), message, "Normal message"),
+            (
+                root_folder.associated_table(),
+                associated,
+                "Associated message",
+            ),
+        ] {
+            let rows: Vec<_> = table.unwrap().rows_matrix().collect();
+            assert_eq!(rows.len(), 1);
+
+            let entry_id = root_folder.entry_id_for_row(rows[0]);
+            assert_eq!(entry_id.node_id(), NodeId::from(message));
+            assert!(store.properties().matches_record_key(&entry_id).unwrap());
+
+            let opened = root_folder
+                .open_message_at_row(rows[0], Some(&[0x0037]))
+                .unwrap();
+            let Some(PropertyValue::Unicode(value)) = opened.properties().get(0x0037) else {
+                panic!("missing PidTagSubject");
+            };
+            assert_eq!(value.to_string(), subject);
+            assert!(opened.properties().get(0x001A).is_none());
         }
+    }
 
-        let signature = PageType::DensityList
-            .signature(ndb::page::DENSITY_LIST_FILE_OFFSET, next_page.into_u64());
-        let trailer = <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
-            PageType::DensityList,
-            signature,
-            next_page,
-            0,
+    #[test]
+    fn test_rename_store() {
+        let path = writable_copy("rename-store");
+        let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(&path).unwrap())).unwrap();
+        let mut properties = StorePropertiesMut::from(store.properties());
+        let root_folder = store.properties().root_folder_entry_id().unwrap();
+        drop(store);
+
+        properties.set_display_name("Renamed store");
+        {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            guard.update_store_properties(&properties).unwrap();
+            assert!(guard.validate_structures().unwrap().is_empty());
+            guard.flush().unwrap();
+        }
+
+        let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(&path).unwrap())).unwrap();
+        assert_eq!(store.properties().display_name().unwrap(), "Renamed store");
+        assert_eq!(
+            store.properties().root_folder_entry_id().unwrap().node_id(),
+            root_folder.node_id()
         );
+        store.root_folder().unwrap();
+    }
 
-        let density_list =
-            <<Pst as PstFile>::DensityListPage as DensityListPageReadWrite<Pst>>::new(
-                density_list.backfill_complete(),
-                density_list.current_page(),
-                density_list.entries(),
-                trailer,
-            )?;
+    /// Leave only the `holes` free in the second AMap page, fill the first one, and allocate 192
+    /// bytes with `strategy`.
+    fn allocate_with_strategy(
+        strategy: AllocationStrategy,
+        holes: &[Range<u64>],
+    ) -> (u64, AllocationMapStatistics) {
+        let path = writable_copy(&format!("allocate-{strategy:?}"));
+        let mut pst = UnicodePstFile::open(&path).unwrap();
+        let inner = &mut pst.inner;
+        inner.start_write().unwrap();
+        inner.grow(1).unwrap();
+
+        let free_bytes = holes.iter().map(|hole| (hole.end - hole.start) * 64).sum();
+        for (amap_index, holes) in [(0, &[][..]), (1, holes)] {
+            let mut amap_page = inner.read_allocation_map_page(amap_index).unwrap();
+            let map_bits = amap_page.map_bits_mut();
+            map_bits.fill(0xFF);
+            for bit in holes.iter().flat_map(Range::clone) {
+                map_bits[(bit / 8) as usize] &= !(0x80 >> (bit % 8));
+            }
+            inner
+                .write_allocation_map_page(amap_index, &amap_page, free_bytes)
+                .unwrap();
+        }
 
-        self.density_list = Ok(density_list);
-        Ok(())
+        inner.allocation_strategy = strategy;
+        let offset = inner.allocate(192, false).unwrap();
+        let statistics = inner.allocation_statistics().unwrap();
+        inner.discard_write().unwrap();
+        drop(pst);
+        (offset, statistics)
     }
 
-    fn read_node(&self, node: NodeId) -> io::Result<<Pst as PstFile>::NodeBTreeEntry> {
-        let node_btree = *self.header.root().node_btree();
-        let mut reader = self.reader.lock().map_err(|_| PstError::LockError)?;
-        let reader = &mut *reader;
-        let node_btree =
-            <<Pst as PstFile>::NodeBTree as RootBTreeReadWrite>::read(reader, node_btree)?;
-        let mut page_cache = self.node_cache.borrow_mut();
-        let node_id: <Pst as PstFile>::BTreeKey = u32::from(node).into();
-        let node = node_btree.find_entry(reader, node_id, &mut page_cache)?;
-        Ok(node)
+    #[test]
+    fn test_allocation_strategies() {
+        let max_slots = mem::size_of::<MapBits>() as u64 * 8;
+        let holes = [100..116, 200..203, 3000..max_slots];
+        let page = amap_page_offset(1).unwrap();
+        let free_bytes = (16 + 3 + max_slots - 3000) * 64 - 192;
+
+        let (offset, statistics) = allocate_with_strategy(AllocationStrategy::FirstFit, &holes);
+        assert_eq!(offset, page + 100 * 64);
+        assert_eq!(statistics.free_bytes(), free_bytes);
+        assert_eq!(statistics.free_run_count(), 3);
+        assert_eq!(statistics.largest_free_run(), (max_slots - 3000) * 64);
+
+        let (offset, statistics) = allocate_with_strategy(AllocationStrategy::BestFit, &holes);
+        assert_eq!(offset, page + 200 * 64);
+        assert_eq!(statistics.free_bytes(), free_bytes);
+        assert_eq!(statistics.free_run_count(), 2);
+        assert_eq!(statistics.largest_free_run(), (max_slots - 3000) * 64);
+
+        let (offset, statistics) = allocate_with_strategy(AllocationStrategy::AppendAtEof, &holes);
+        assert_eq!(offset, page + 3000 * 64);
+        assert_eq!(statistics.free_bytes(), free_bytes);
+        assert_eq!(statistics.free_run_count(), 3);
+        assert_eq!(statistics.largest_free_run(), (max_slots - 3003) * 64);
+        let fragmentation = 1.0 - (max_slots - 3003) as f64 * 64.0 / free_bytes as f64;
+        assert!((statistics.fragmentation() - fragmentation).abs() < f64::EPSILON);
     }
 
-    fn read_block(&self, block: <Pst as PstFile>::BlockId) -> io::Result<Vec<u8>> {
-        let encoding = self.header.crypt_method();
-        let block_btree = *self.header.root().block_btree();
-        let mut reader = self.reader.lock().map_err(|_| PstError::LockError)?;
-        let reader = &mut *reader;
-        let block_btree =
-            <<Pst as PstFile>::BlockBTree as RootBTreeReadWrite>::read(reader, block_btree)?;
-        let mut page_cache = self.block_cache.borrow_mut();
-        let block = block_btree.find_entry(reader, block.search_key(), &mut page_cache)?;
-        let block = DataTree::<Pst>::read(reader, encoding, &block)?;
-        let mut block_cache = Default::default();
-        let mut data = vec![];
-        let _ = block
-            .reader(
-                reader,
-                encoding,
-                &block_btree,
-                &mut page_cache,
-                &mut block_cache,
-            )?
-            .read_to_end(&mut data)?;
-        Ok(data)
+    #[test]
+    fn test_grow() {
+        let path = writable_copy("grow");
+        let before = UnicodePstFile::open(&path)
+            .unwrap()
+            .free_space_map()
+            .unwrap();
+        assert_eq!(before.pages().len(), 1);
+
+        {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            guard.grow(AMAP_DATA_SIZE + 1).unwrap();
+            assert!(guard.validate_structures().unwrap().is_empty());
+            guard.flush().unwrap();
+        }
+
+        let pst = UnicodePstFile::open(&path).unwrap();
+        let after = pst.free_space_map().unwrap();
+        assert_eq!(after.pages().len(), 3);
+        assert_eq!(after.file_eof(), amap_page_offset(3).unwrap());
+        assert_eq!(
+            after.free_bytes(),
+            before.free_bytes() + after.pages()[1].free_bytes() + after.pages()[2].free_bytes()
+        );
+        assert_eq!(after.root_free_bytes(), after.free_bytes());
+        assert!(after.mismatches().is_empty(), "{:?}", after.mismatches());
+        let density_list = after.density_list().expect("missing density list");
+        for amap_index in 1..3 {
+            assert!(density_list
+                .entries()
+                .iter()
+                .any(|entry| u64::from(entry.page()) == amap_index));
+        }
     }
-}
 
-pub fn open_store(path: impl AsRef<Path>) -> io::Result<Rc<dyn Store>> {
-    Ok(if let Ok(pst_file) = UnicodePstFile::open(path.as_ref()) {
-        UnicodeStore::read(Rc::new(pst_file))?
-    } else {
-        let pst_file = AnsiPstFile::open(path.as_ref())?;
-        AnsiStore::read(Rc::new(pst_file))?
-    })
+    #[test]
+    fn test_grow_partial_range() {
+        let path = writable_copy("grow-partial");
+        let before = UnicodePstFile::open(&path)
+            .unwrap()
+            .free_space_map()
+            .unwrap();
+        let file_eof = before.file_eof() - 0x8000;
+        let tail: u64 = before
+            .free_ranges()
+            .map(|run| run.end.saturating_sub(run.start.max(file_eof)))
+            .sum();
+        assert!(tail > 0);
+
+        {
+            // Move ibFileEof back part way through the only AMap range, as if the file was written
+            // by a client which doesn't grow in whole ranges.
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let root = pst.inner.header.root_mut();
+            root.set_file_eof_index(UnicodeByteIndex::new(file_eof));
+            root.reset_free_size(UnicodeByteIndex::new(before.free_bytes() - tail))
+                .unwrap();
+            let mut guard = pst.lock().unwrap();
+            guard.grow(1).unwrap();
+            guard.flush().unwrap();
+        }
+
+        let after = UnicodePstFile::open(&path)
+            .unwrap()
+            .free_space_map()
+            .unwrap();
+        assert_eq!(after.pages().len(), 2);
+        assert_eq!(
+            after.free_bytes(),
+            before.free_bytes() + after.pages()[1].free_bytes()
+        );
+        assert_eq!(after.root_free_bytes(), after.free_bytes());
+        assert!(after.mismatches().is_empty(), "{:?}", after.mismatches());
+    }
+
+    #[test]
+    fn test_amap_page_index_out_of_range() {
+        assert!(matches!(
+            amap_page_index(AMAP_FIRST_OFFSET - 1),
+            Err(PstError::InvalidAllocationMapOffset(_))
+        ));
+        assert!(matches!(
+            amap_page_offset(u64::MAX / AMAP_DATA_SIZE + 1),
+            Err(PstError::IntegerConversion)
+        ));
+    }
 }
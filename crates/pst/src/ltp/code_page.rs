@@ -0,0 +1,436 @@
+//! Decoding for [PropertyValue::String8](super::prop_context::PropertyValue::String8) values,
+//! which are stored in a code page that the PST file does not always record.
+//!
+//! [`decode_string8`] has built-in support for the code pages in [`SUPPORTED_CODE_PAGES`], and
+//! [`detect_code_page`] guesses one of those from a sample of strings in the store. Embedders
+//! which need other code pages can plug in their own [`CodePageDecoder`], e.g. one backed by ICU,
+//! with [`Store::set_code_page_decoder`](crate::messaging::store::Store::set_code_page_decoder).
+//! [`encode_string8`] writes strings back in one of the built-in code pages.
+
+use encoding_rs::{Encoding, BIG5, EUC_KR, GBK, SHIFT_JIS};
+use std::{fmt, rc::Rc};
+
+/// `CP_ACP`: The default ANSI code page, decoded as [`DEFAULT_ANSI_CODE_PAGE`].
+pub const CP_ACP: u16 = 0;
+
+/// Windows-1252 (Western European), which is what most ANSI PST files use.
+pub const DEFAULT_ANSI_CODE_PAGE: u16 = 1252;
+
+/// Code pages which [`decode_string8`] can decode without falling back to ISO-8859-1.
+pub const SUPPORTED_CODE_PAGES: &[u16] =
+    &[CP_ACP, 932, 936, 949, 950, 1251, 1252, 20127, 28591, 65001];
+
+/// Windows-1252 mappings for `0x80..=0x9F`, the rest of the code page matches ISO-8859-1.
+const WINDOWS_1252_HIGH: [u16; 32] = [
+    0x20AC, 0x0081, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160, 0x2039,
+    0x0152, 0x008D, 0x017D, 0x008F, 0x0090, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014,
+    0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0x009D, 0x017E, 0x0178,
+];
+
+/// Windows-1251 mappings for `0x80..=0xBF`, and `0xC0..=0xFF` map to `U+0410..=U+044F`.
+const WINDOWS_1251_HIGH: [u16; 64] = [
+    0x0402, 0x0403, 0x201A, 0x0453, 0x201E, 0x2026, 0x2020, 0x2021, 0x20AC, 0x2030, 0x0409, 0x2039,
+    0x040A, 0x040C, 0x040B, 0x040F, 0x0452, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014,
+    0x0098, 0x2122, 0x0459, 0x203A, 0x045A, 0x045C, 0x045B, 0x045F, 0x00A0, 0x040E, 0x045E, 0x0408,
+    0x00A4, 0x0490, 0x00A6, 0x00A7, 0x0401, 0x00A9, 0x0404, 0x00AB, 0x00AC, 0x00AD, 0x00AE, 0x0407,
+    0x00B0, 0x00B1, 0x0406, 0x0456, 0x0491, 0x00B5, 0x00B6, 0x00B7, 0x0451, 0x2116, 0x0454, 0x00BB,
+    0x0458, 0x0405, 0x0455, 0x0457,
+];
+
+/// Decode a `PtypString8` buffer in `code_page`. Code pages which are not in
+/// [`SUPPORTED_CODE_PAGES`] are decoded as ISO-8859-1, so every byte still maps to a character.
+pub fn decode_string8(buffer: &[u8], code_page: u16) -> String {
+    if let Some(encoding) = double_byte_encoding(code_page) {
+        return encoding.decode_without_bom_handling(buffer).0.into_owned();
+    }
+    match code_page {
+        65001 => String::from_utf8_lossy(buffer).into_owned(),
+        _ => {
+            let buffer: Vec<_> = buffer
+                .iter()
+                .map(|&b| decode_single_byte(b, code_page))
+                .collect();
+            String::from_utf16_lossy(&buffer)
+        }
+    }
+}
+
+/// Encode `value` as a `PtypString8` buffer in `code_page`, the reverse of [`decode_string8`].
+/// Characters which the code page cannot represent are replaced with `?`.
+pub fn encode_string8(value: &str, code_page: u16) -> Vec<u8> {
+    if let Some(encoding) = double_byte_encoding(code_page) {
+        let mut buffer = [0; 4];
+        return value
+            .chars()
+            .flat_map(|ch| {
+                let (bytes, _, unmappable) = encoding.encode(ch.encode_utf8(&mut buffer));
+                if unmappable {
+                    vec![b'?']
+                } else {
+                    bytes.into_owned()
+                }
+            })
+            .collect();
+    }
+    match code_page {
+        65001 => value.as_bytes().to_vec(),
+        _ => value
+            .encode_utf16()
+            .map(|ch| {
+                (0..=u8::MAX)
+                    .find(|&b| decode_single_byte(b, code_page) == ch)
+                    .unwrap_or(b'?')
+            })
+            .collect(),
+    }
+}
+
+/// The East Asian code pages, which mix single byte ASCII with double byte characters.
+fn double_byte_encoding(code_page: u16) -> Option<&'static Encoding> {
+    match code_page {
+        932 => Some(SHIFT_JIS),
+        936 => Some(GBK),
+        949 => Some(EUC_KR),
+        950 => Some(BIG5),
+        _ => None,
+    }
+}
+
+fn decode_single_byte(b: u8, code_page: u16) -> u16 {
+    match (code_page, b) {
+        (CP_ACP | 1252, 0x80..=0x9F) => WINDOWS_1252_HIGH[usize::from(b - 0x80)],
+        (1251, 0x80..=0xBF) => WINDOWS_1251_HIGH[usize::from(b - 0x80)],
+        (1251, 0xC0..=0xFF) => 0x0410 + u16::from(b - 0xC0),
+        (20127, 0x80..=0xFF) => 0xFFFD,
+        _ => u16::from(b),
+    }
+}
+
+/// Map a MIME or IANA `charset` name, e.g. `Shift_JIS` or `windows-1251`, to the Windows code page
+/// with the same encoding. Names of the form `windows-NNNN`, `cpNNNN` and `iso-8859-N` are mapped
+/// by their number, so a [`CodePageDecoder`] can decode code pages which [`decode_string8`] does
+/// not support.
+pub fn code_page_from_charset(charset: &str) -> Option<u16> {
+    let charset = charset.trim().to_ascii_lowercase();
+    let code_page = match charset.as_str() {
+        "utf-8" | "utf8" => 65001,
+        "us-ascii" | "ascii" => 20127,
+        "iso_8859-1" | "latin1" | "l1" => 28591,
+        "shift_jis" | "shift-jis" | "sjis" | "x-sjis" | "windows-31j" => 932,
+        "gb2312" | "gbk" | "x-gbk" => 936,
+        "ks_c_5601-1987" | "euc-kr" => 949,
+        "big5" | "big5-hkscs" => 950,
+        "koi8-r" => 20866,
+        "koi8-u" => 21866,
+        "iso-2022-jp" => 50220,
+        "euc-jp" => 51932,
+        "gb18030" => 54936,
+        _ => {
+            if let Some(code_page) = charset
+                .strip_prefix("windows-")
+                .or_else(|| charset.strip_prefix("cp"))
+            {
+                return code_page.parse().ok();
+            }
+            let part = charset.strip_prefix("iso-8859-")?.parse::<u16>().ok()?;
+            return (1..=16).contains(&part).then_some(28590 + part);
+        }
+    };
+    Some(code_page)
+}
+
+/// Decodes `PtypString8` values for the objects opened from a store.
+pub trait CodePageDecoder {
+    /// Decode `buffer` in `code_page`, or return `None` if the decoder does not support
+    /// `code_page`, in which case [`decode_string8`] is used instead.
+    fn decode(&self, buffer: &[u8], code_page: u16) -> Option<String>;
+}
+
+/// The default [`CodePageDecoder`], which only uses [`decode_string8`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BuiltinCodePageDecoder;
+
+impl CodePageDecoder for BuiltinCodePageDecoder {
+    fn decode(&self, buffer: &[u8], code_page: u16) -> Option<String> {
+        SUPPORTED_CODE_PAGES
+            .contains(&code_page)
+            .then(|| decode_string8(buffer, code_page))
+    }
+}
+
+/// A code page and the [`CodePageDecoder`] to decode it with, which folders and messages keep from
+/// the store they were opened from.
+#[derive(Clone)]
+pub struct String8Decoder {
+    code_page: u16,
+    decoder: Rc<dyn CodePageDecoder>,
+}
+
+impl String8Decoder {
+    pub fn new(code_page: u16, decoder: Rc<dyn CodePageDecoder>) -> Self {
+        Self { code_page, decoder }
+    }
+
+    pub fn code_page(&self) -> u16 {
+        self.code_page
+    }
+
+    pub fn decoder(&self) -> &Rc<dyn CodePageDecoder> {
+        &self.decoder
+    }
+
+    /// The same decoder with a different code page, e.g. `PidTagMessageCodepage`.
+    pub fn with_code_page(&self, code_page: u16) -> Self {
+        Self::new(code_page, self.decoder.clone())
+    }
+
+    pub fn decode(&self, buffer: &[u8]) -> String {
+        self.decoder
+            .decode(buffer, self.code_page)
+            .unwrap_or_else(|| decode_string8(buffer, self.code_page))
+    }
+}
+
+impl Default for String8Decoder {
+    fn default() -> Self {
+        Self::new(CP_ACP, Rc::new(BuiltinCodePageDecoder))
+    }
+}
+
+impl fmt::Debug for String8Decoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("String8Decoder")
+            .field("code_page", &self.code_page)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Guess the code page of a sample of `PtypString8` values, e.g. the folder names in the store.
+/// Only code pages which [`decode_string8`] supports are returned, so this is the same as
+/// [`detect_code_page_with`] and the [`BuiltinCodePageDecoder`].
+pub fn detect_code_page<'a>(samples: impl IntoIterator<Item = &'a [u8]>) -> Option<u16> {
+    detect_code_page_with(samples, &BuiltinCodePageDecoder)
+}
+
+/// Guess the code page of a sample of `PtypString8` values, and only return it if `decoder` can
+/// decode it. Returns `None` if the samples are plain ASCII, or if the heuristics can't tell.
+///
+/// - Valid UTF-8 with at least one multibyte sequence is `65001`.
+/// - Valid Shift-JIS with double-byte characters in the common kana and kanji ranges is `932`.
+/// - Text where more words are spelled only with letters above `0x7F` than mix them with ASCII
+///   letters is Windows-1251, since that is how Cyrillic words look. Western European words
+///   have accented letters next to ASCII letters, e.g. `Grüße`, which is Windows-1252.
+pub fn detect_code_page_with<'a>(
+    samples: impl IntoIterator<Item = &'a [u8]>,
+    decoder: &dyn CodePageDecoder,
+) -> Option<u16> {
+    let samples: Vec<_> = samples
+        .into_iter()
+        .filter(|sample| sample.iter().any(|b| !b.is_ascii()))
+        .collect();
+    let code_page = guess_code_page(&samples)?;
+    decoder.decode(samples[0], code_page).map(|_| code_page)
+}
+
+fn guess_code_page(samples: &[&[u8]]) -> Option<u16> {
+    if samples.is_empty() {
+        return None;
+    }
+
+    if samples
+        .iter()
+        .all(|sample| std::str::from_utf8(sample).is_ok())
+    {
+        return Some(65001);
+    }
+
+    if samples.iter().all(|sample| is_shift_jis(sample)) {
+        return Some(932);
+    }
+
+    let (mut cyrillic, mut western) = (0_usize, 0_usize);
+    for sample in samples {
+        let words = sample.split(|&b| !b.is_ascii_alphabetic() && b < 0xC0);
+        for word in words {
+            let high = word.iter().filter(|&&b| b >= 0xC0).count();
+            match high {
+                0 => {}
+                _ if high < word.len() => western += 1,
+                _ if high > 1 => cyrillic += 1,
+                _ => {}
+            }
+        }
+    }
+
+    match (cyrillic, western) {
+        (0, 0) => None,
+        _ if cyrillic > western => Some(1251),
+        _ => Some(DEFAULT_ANSI_CODE_PAGE),
+    }
+}
+
+fn is_shift_jis(sample: &[u8]) -> bool {
+    let mut double_byte = false;
+    let mut bytes = sample.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            0x00..=0x7F | 0xA1..=0xDF => {}
+            0x81..=0x9F | 0xE0..=0xEF => match bytes.next() {
+                Some(0x40..=0x7E | 0x80..=0xFC) => double_byte |= b <= 0x9F,
+                _ => return false,
+            },
+            _ => return false,
+        }
+    }
+    double_byte
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CYRILLIC_INBOX: &[u8] = &[0xC2, 0xF5, 0xEE, 0xE4, 0xFF, 0xF9, 0xE8, 0xE5];
+    const FRENCH_SENT_ITEMS: &[u8] = b"\xC9l\xE9ments envoy\xE9s";
+    const JAPANESE_INBOX: &[u8] = &[0x8E, 0xF3, 0x90, 0x4D, 0x83, 0x67, 0x83, 0x8C, 0x83, 0x43];
+    const CHINESE_INBOX: &[u8] = &[0xCA, 0xD5, 0xBC, 0xFE, 0xCF, 0xE4];
+
+    #[test]
+    fn test_decode_string8() {
+        assert_eq!(decode_string8(CYRILLIC_INBOX, 1251), "Входящие");
+        assert_eq!(
+            decode_string8(FRENCH_SENT_ITEMS, CP_ACP),
+            "Éléments envoyés"
+        );
+        assert_eq!(decode_string8(b"\x80 5", 1252), "€ 5");
+        assert_eq!(decode_string8("Входящие".as_bytes(), 65001), "Входящие");
+    }
+
+    #[test]
+    fn test_decode_double_byte_string8() {
+        assert_eq!(decode_string8(JAPANESE_INBOX, 932), "受信トレイ");
+        assert_eq!(decode_string8(CHINESE_INBOX, 936), "收件箱");
+        assert_eq!(
+            decode_string8(b"\xB9\xDE\xC0\xBA \xC6\xED\xC1\xF6\xC7\xD4", 949),
+            "받은 편지함"
+        );
+        assert_eq!(decode_string8(b"\xA6\xAC\xA5\xF3\xA7\x58", 950), "收件匣");
+
+        assert_eq!(encode_string8("受信トレイ", 932), JAPANESE_INBOX);
+        assert_eq!(encode_string8("收件箱", 936), CHINESE_INBOX);
+        assert_eq!(encode_string8("受信 \u{1F4E7}", 932), b"\x8E\xF3\x90\x4D ?");
+        assert_eq!(encode_string8("\u{1F4E7} 5", 949), b"? 5");
+    }
+
+    #[test]
+    fn test_encode_string8() {
+        assert_eq!(encode_string8("Входящие", 1251), CYRILLIC_INBOX);
+        assert_eq!(
+            encode_string8("Éléments envoyés", CP_ACP),
+            FRENCH_SENT_ITEMS
+        );
+        assert_eq!(encode_string8("€ 5", 1252), b"\x80 5");
+        assert_eq!(encode_string8("Входящие", 1252), b"????????");
+        assert_eq!(encode_string8("Входящие", 65001), "Входящие".as_bytes());
+    }
+
+    #[test]
+    fn test_code_page_from_charset() {
+        assert_eq!(code_page_from_charset("UTF-8"), Some(65001));
+        assert_eq!(code_page_from_charset(" Shift_JIS "), Some(932));
+        assert_eq!(code_page_from_charset("gb2312"), Some(936));
+        assert_eq!(code_page_from_charset("ks_c_5601-1987"), Some(949));
+        assert_eq!(code_page_from_charset("windows-1251"), Some(1251));
+        assert_eq!(code_page_from_charset("cp874"), Some(874));
+        assert_eq!(code_page_from_charset("ISO-8859-1"), Some(28591));
+        assert_eq!(code_page_from_charset("iso-8859-15"), Some(28605));
+        assert_eq!(code_page_from_charset("iso-8859-17"), None);
+        assert_eq!(code_page_from_charset("x-unknown"), None);
+    }
+
+    #[test]
+    fn test_detect_code_page() {
+        assert_eq!(detect_code_page([b"Inbox".as_slice()]), None);
+        assert_eq!(detect_code_page([CYRILLIC_INBOX, b"Outbox"]), Some(1251));
+        assert_eq!(detect_code_page([FRENCH_SENT_ITEMS]), Some(1252));
+        assert_eq!(detect_code_page([JAPANESE_INBOX]), Some(932));
+        assert_eq!(detect_code_page(["Входящие".as_bytes()]), Some(65001));
+    }
+
+    #[test]
+    fn test_detect_western_code_page() {
+        const GREETINGS: &[u8] = b"Gr\xFC\xDFe";
+        const INFORMATION: &[u8] = b"Informa\xE7\xF5es";
+        const SPANISH_DRAFTS: &[u8] = b"Borradores \xB7 Se\xF1al";
+        assert_eq!(detect_code_page([GREETINGS]), Some(1252));
+        assert_eq!(detect_code_page([INFORMATION]), Some(1252));
+        assert_eq!(detect_code_page([SPANISH_DRAFTS]), Some(1252));
+        assert_eq!(
+            detect_code_page([GREETINGS, INFORMATION, FRENCH_SENT_ITEMS]),
+            Some(1252)
+        );
+        assert_eq!(decode_string8(GREETINGS, 1252), "Grüße");
+        assert_eq!(decode_string8(INFORMATION, 1252), "Informações");
+
+        const CYRILLIC_MIXED: &[u8] = b"\xC2\xF5\xEE\xE4\xFF\xF9\xE8\xE5 Exchange";
+        assert_eq!(detect_code_page([CYRILLIC_MIXED]), Some(1251));
+    }
+
+    #[test]
+    fn test_detect_code_page_with() {
+        assert_eq!(
+            detect_code_page_with([JAPANESE_INBOX], &BuiltinCodePageDecoder),
+            Some(932)
+        );
+        assert_eq!(detect_code_page_with([JAPANESE_INBOX], &NoDecoder), None);
+        assert_eq!(
+            detect_code_page_with([JAPANESE_INBOX], &KanaDecoder),
+            Some(932)
+        );
+        assert_eq!(detect_code_page_with([CYRILLIC_INBOX], &KanaDecoder), None);
+    }
+
+    /// Does not decode any code page, so [`decode_string8`] is always used instead.
+    struct NoDecoder;
+
+    impl CodePageDecoder for NoDecoder {
+        fn decode(&self, _buffer: &[u8], _code_page: u16) -> Option<String> {
+            None
+        }
+    }
+
+    /// Decodes Shift-JIS kana only, like a stand-in for an ICU converter.
+    struct KanaDecoder;
+
+    impl CodePageDecoder for KanaDecoder {
+        fn decode(&self, buffer: &[u8], code_page: u16) -> Option<String> {
+            if code_page != 932 {
+                return None;
+            }
+            Some(
+                buffer
+                    .chunks(2)
+                    .map(|pair| {
+                        match pair {
+                            [0x83, b @ 0x40..=0x7E] => char::from_u32(0x30A1 + u32::from(b - 0x40)),
+                            [0x83, b @ 0x80..=0x96] => char::from_u32(0x30E0 + u32::from(b - 0x80)),
+                            _ => None,
+                        }
+                        .unwrap_or(char::REPLACEMENT_CHARACTER)
+                    })
+                    .collect(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_string8_decoder() {
+        let decoder = String8Decoder::default();
+        assert_eq!(decoder.code_page(), CP_ACP);
+        assert_eq!(decoder.decode(FRENCH_SENT_ITEMS), "Éléments envoyés");
+
+        let decoder = String8Decoder::new(932, Rc::new(KanaDecoder));
+        assert_eq!(decoder.decode(&JAPANESE_INBOX[4..8]), "トレ");
+        let decoder = decoder.with_code_page(1251);
+        assert_eq!(decoder.decode(CYRILLIC_INBOX), "Входящие");
+    }
+}
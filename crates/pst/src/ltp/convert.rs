@@ -0,0 +1,276 @@
+//! Conversions between [`PropertyValue`] and standard Rust types, so callers can write
+//! `i32::try_from(value)?` instead of matching on every variant.
+//!
+//! Reading a value only accepts the property types which convert without loss, e.g. an `i64`
+//! can be read from a [`PropertyValue::Integer32`], but an `i32` cannot be read from a
+//! [`PropertyValue::Integer64`]. Building a value picks the usual property type for each Rust
+//! type, e.g. a `String` becomes a [`PropertyValue::Unicode`].
+
+use std::time::SystemTime;
+
+use super::{
+    prop_context::{BinaryValue, GuidValue, PropertyValue, UnicodeValue},
+    prop_type::PropertyType,
+    LtpError,
+};
+use crate::time;
+
+fn conversion_error(value: &PropertyValue, target: &'static str) -> LtpError {
+    LtpError::InvalidPropertyValueConversion(PropertyType::from(value), target)
+}
+
+impl TryFrom<&PropertyValue> for i32 {
+    type Error = LtpError;
+
+    fn try_from(value: &PropertyValue) -> Result<Self, Self::Error> {
+        match value {
+            PropertyValue::Integer16(value) => Ok(i32::from(*value)),
+            PropertyValue::Integer32(value) => Ok(*value),
+            _ => Err(conversion_error(value, "i32")),
+        }
+    }
+}
+
+impl TryFrom<&PropertyValue> for i64 {
+    type Error = LtpError;
+
+    fn try_from(value: &PropertyValue) -> Result<Self, Self::Error> {
+        match value {
+            PropertyValue::Integer16(value) => Ok(i64::from(*value)),
+            PropertyValue::Integer32(value) => Ok(i64::from(*value)),
+            PropertyValue::Integer64(value) => Ok(*value),
+            _ => Err(conversion_error(value, "i64")),
+        }
+    }
+}
+
+impl TryFrom<&PropertyValue> for bool {
+    type Error = LtpError;
+
+    fn try_from(value: &PropertyValue) -> Result<Self, Self::Error> {
+        match value {
+            PropertyValue::Boolean(value) => Ok(*value),
+            _ => Err(conversion_error(value, "bool")),
+        }
+    }
+}
+
+/// A [`PropertyValue::String8`] is decoded as ISO-8859-1, like its `Display` implementation. Use
+/// [`String8Value::to_string_with_code_page`](super::prop_context::String8Value::to_string_with_code_page)
+/// to decode it with the code page of the message or store instead.
+impl TryFrom<&PropertyValue> for String {
+    type Error = LtpError;
+
+    fn try_from(value: &PropertyValue) -> Result<Self, Self::Error> {
+        match value {
+            PropertyValue::String8(value) => Ok(value.to_string()),
+            PropertyValue::Unicode(value) => Ok(value.to_string()),
+            _ => Err(conversion_error(value, "String")),
+        }
+    }
+}
+
+impl TryFrom<&PropertyValue> for Vec<u8> {
+    type Error = LtpError;
+
+    fn try_from(value: &PropertyValue) -> Result<Self, Self::Error> {
+        match value {
+            PropertyValue::Binary(value) => Ok(value.buffer().to_vec()),
+            _ => Err(conversion_error(value, "Vec<u8>")),
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a PropertyValue> for &'a [u8] {
+    type Error = LtpError;
+
+    fn try_from(value: &'a PropertyValue) -> Result<Self, Self::Error> {
+        match value {
+            PropertyValue::Binary(value) => Ok(value.buffer()),
+            _ => Err(conversion_error(value, "&[u8]")),
+        }
+    }
+}
+
+impl TryFrom<&PropertyValue> for GuidValue {
+    type Error = LtpError;
+
+    fn try_from(value: &PropertyValue) -> Result<Self, Self::Error> {
+        match value {
+            PropertyValue::Guid(value) => Ok(*value),
+            _ => Err(conversion_error(value, "GuidValue")),
+        }
+    }
+}
+
+/// Fails with [`LtpError::InvalidPropertyTime`] if the time cannot be represented by
+/// [`SystemTime`] on this platform. Sentinel values like 0 are converted as they are, see
+/// [`time::to_system_time`] to treat them as times which are not set.
+impl TryFrom<&PropertyValue> for SystemTime {
+    type Error = LtpError;
+
+    fn try_from(value: &PropertyValue) -> Result<Self, Self::Error> {
+        let PropertyValue::Time(filetime) = value else {
+            return Err(conversion_error(value, "SystemTime"));
+        };
+        time::filetime_to_system_time(*filetime).ok_or(LtpError::InvalidPropertyTime(*filetime))
+    }
+}
+
+impl From<i32> for PropertyValue {
+    fn from(value: i32) -> Self {
+        Self::Integer32(value)
+    }
+}
+
+impl From<i64> for PropertyValue {
+    fn from(value: i64) -> Self {
+        Self::Integer64(value)
+    }
+}
+
+impl From<bool> for PropertyValue {
+    fn from(value: bool) -> Self {
+        Self::Boolean(value)
+    }
+}
+
+impl From<&str> for PropertyValue {
+    fn from(value: &str) -> Self {
+        Self::Unicode(UnicodeValue::new(value.encode_utf16().collect()))
+    }
+}
+
+impl From<String> for PropertyValue {
+    fn from(value: String) -> Self {
+        Self::from(value.as_str())
+    }
+}
+
+impl From<&[u8]> for PropertyValue {
+    fn from(value: &[u8]) -> Self {
+        Self::Binary(BinaryValue::new(value.to_vec()))
+    }
+}
+
+impl From<Vec<u8>> for PropertyValue {
+    fn from(value: Vec<u8>) -> Self {
+        Self::Binary(BinaryValue::new(value))
+    }
+}
+
+impl From<GuidValue> for PropertyValue {
+    fn from(value: GuidValue) -> Self {
+        Self::Guid(value)
+    }
+}
+
+/// Times outside the range of `PtypTime`, roughly 29,000 years either side of 1601, are clamped
+/// to the nearest value which fits.
+impl From<SystemTime> for PropertyValue {
+    fn from(value: SystemTime) -> Self {
+        Self::Time(time::from_system_time(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::{FILETIME_TICKS_PER_SECOND, FILETIME_UNIX_EPOCH_SECONDS};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_integer_conversions() {
+        assert_eq!(i32::try_from(&PropertyValue::Integer16(-2)).unwrap(), -2);
+        assert_eq!(i64::try_from(&PropertyValue::from(7)).unwrap(), 7);
+        assert_eq!(
+            i64::try_from(&PropertyValue::from(1_i64 << 40)).unwrap(),
+            1 << 40
+        );
+        assert!(matches!(
+            i32::try_from(&PropertyValue::Integer64(1)),
+            Err(LtpError::InvalidPropertyValueConversion(
+                PropertyType::Integer64,
+                "i32"
+            ))
+        ));
+        assert!(bool::try_from(&PropertyValue::from(true)).unwrap());
+        assert!(bool::try_from(&PropertyValue::Integer32(1)).is_err());
+    }
+
+    #[test]
+    fn test_string_and_binary_conversions() {
+        let value = PropertyValue::from("Inbox");
+        assert_eq!(PropertyType::from(&value), PropertyType::Unicode);
+        assert_eq!(String::try_from(&value).unwrap(), "Inbox");
+
+        let value = PropertyValue::from(vec![1, 2, 3]);
+        assert_eq!(Vec::<u8>::try_from(&value).unwrap(), [1, 2, 3]);
+        assert_eq!(<&[u8]>::try_from(&value).unwrap(), [1, 2, 3]);
+        assert!(String::try_from(&value).is_err());
+
+        let guid = GuidValue::new(1, 2, 3, [4; 8]);
+        assert_eq!(
+            GuidValue::try_from(&PropertyValue::from(guid)).unwrap(),
+            guid
+        );
+    }
+
+    #[test]
+    fn test_time_conversions() {
+        // 2003-07-01 08:52:37 UTC
+        let filetime = 127_015_231_570_000_000;
+        let time = SystemTime::try_from(&PropertyValue::Time(filetime)).unwrap();
+        assert_eq!(
+            time.duration_since(UNIX_EPOCH).unwrap(),
+            Duration::from_secs(1_057_049_557)
+        );
+        assert!(matches!(
+            PropertyValue::from(time),
+            PropertyValue::Time(value) if value == filetime
+        ));
+
+        let time = UNIX_EPOCH - Duration::from_nanos(100);
+        assert!(matches!(
+            PropertyValue::from(time),
+            PropertyValue::Time(value)
+                if value == FILETIME_UNIX_EPOCH_SECONDS * FILETIME_TICKS_PER_SECOND - 1
+        ));
+        assert!(SystemTime::try_from(&PropertyValue::Integer64(filetime)).is_err());
+    }
+
+    #[test]
+    fn test_guid_string_and_bytes() {
+        use crate::messaging::psetid::PSETID_COMMON;
+        use std::str::FromStr;
+
+        let text = "{00062008-0000-0000-C000-000000000046}";
+        assert_eq!(GuidValue::from_str(text).unwrap(), PSETID_COMMON);
+        assert_eq!(PSETID_COMMON.to_string(), text);
+        assert_eq!(
+            "00062008-0000-0000-c000-000000000046"
+                .parse::<GuidValue>()
+                .unwrap(),
+            PSETID_COMMON
+        );
+        for invalid in [
+            "",
+            "{00062008-0000-0000-C000-000000000046",
+            "00062008-0000-0000-C000-0000000000046",
+            "00062008-0000-0000-C0000-00000000046",
+            "+0062008-0000-0000-C000-000000000046",
+        ] {
+            assert!(matches!(
+                GuidValue::from_str(invalid),
+                Err(LtpError::InvalidGuidString(_))
+            ));
+        }
+
+        let bytes = [
+            0x08, 0x20, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, 0xC0, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x46,
+        ];
+        assert_eq!(PSETID_COMMON.to_bytes_le(), bytes);
+        assert_eq!(GuidValue::from_bytes_le(bytes), PSETID_COMMON);
+    }
+}
@@ -8,7 +8,7 @@ use crate::{
     ndb::{
         block::*,
         block_id::BlockId,
-        header::NdbCryptMethod,
+        header::{NdbCryptMethod, NdbVersion},
         node_id::*,
         page::{AnsiBlockBTree, RootBTree, UnicodeBlockBTree},
         read_write::*,
@@ -19,7 +19,7 @@ use crate::{
 pub const HEAP_INDEX_MASK: u32 = (1_u16.rotate_right(5) - 1) as u32;
 
 /// [HID](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/85b9e985-ea53-447f-b70c-eb82bfbdcbc9)
-#[derive(Clone, Copy, Default, Debug)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
 pub struct HeapId(NodeId);
 
 impl HeapId {
@@ -213,8 +213,30 @@ impl HeapFillLevel {
     fn pack_fill_levels(fill_levels: &[HeapFillLevel; 8]) -> u32 {
         fill_levels
             .iter()
+            .rev()
             .fold(0, |acc, &x| (acc << 4) | (x as u32))
     }
+
+    fn from_free_space(free_space: usize) -> Self {
+        match free_space {
+            3584.. => Self::Empty,
+            2560.. => Self::Level1,
+            2048.. => Self::Level2,
+            1792.. => Self::Level3,
+            1536.. => Self::Level4,
+            1280.. => Self::Level5,
+            1024.. => Self::Level6,
+            768.. => Self::Level7,
+            512.. => Self::Level8,
+            256.. => Self::Level9,
+            128.. => Self::Level10,
+            64.. => Self::Level11,
+            32.. => Self::Level12,
+            16.. => Self::Level13,
+            8.. => Self::Level14,
+            _ => Self::Level15,
+        }
+    }
 }
 
 /// [HNHDR](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/8e4ae05c-3c24-4103-b7e5-ffef6f244834)
@@ -503,9 +525,194 @@ impl HeapNodePageReadWrite for HeapNodePageMap {
     }
 }
 
+/// Maximum size of a single allocation on the heap.
+pub const MAX_HEAP_ALLOCATION_SIZE: usize = 3580;
+
+/// The smallest data block payload across Unicode and ANSI PST files, so the same heap fits in
+/// either format.
+const MAX_HEAP_NODE_SIZE: usize = max_block_data_size(NdbVersion::Unicode) as usize;
+
+/// Largest `hidIndex` in a [`HeapId`], which limits the number of allocations in each block.
+const MAX_HEAP_BLOCK_ALLOCATIONS: usize = HEAP_INDEX_MASK as usize;
+
+/// Size of the header at the start of each block in the heap: [`HeapNodeHeader`] in the first
+/// block, [`HeapNodeBitmapHeader`] in block 8 and every 128 blocks after that, and
+/// [`HeapNodePageHeader`] in the rest.
+const fn heap_block_header_size(block_index: usize) -> usize {
+    match block_index {
+        0 => 12,
+        bitmap if bitmap % 128 == 8 => 66,
+        _ => 2,
+    }
+}
+
+/// Size of a heap block with `allocations`, including the header and the [`HeapNodePageMap`].
+fn heap_block_size(block_index: usize, allocations: &[Vec<u8>]) -> usize {
+    let data_size =
+        heap_block_header_size(block_index) + allocations.iter().map(Vec::len).sum::<usize>();
+    data_size + data_size % 2 + 4 + 2 * (allocations.len() + 1)
+}
+
+/// Build the contents of a new [HN (Heap-on-Node)](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/77ce49a3-3772-4d8d-bb2c-2f7520a238a6).
+/// Allocations are packed into blocks in the order they are made, and a new block is started
+/// when the next allocation does not fit in the current one.
+pub struct HeapNodeBuilder {
+    client_signature: HeapNodeType,
+    user_root: HeapId,
+    blocks: Vec<Vec<Vec<u8>>>,
+}
+
+impl HeapNodeBuilder {
+    pub fn new(client_signature: HeapNodeType) -> Self {
+        Self {
+            client_signature,
+            user_root: Default::default(),
+            blocks: vec![vec![]],
+        }
+    }
+
+    /// Append a copy of `data` to the heap and return the [`HeapId`] which refers to it.
+    pub fn allocate(&mut self, data: &[u8]) -> LtpResult<HeapId> {
+        if data.is_empty() || data.len() > MAX_HEAP_ALLOCATION_SIZE {
+            return Err(LtpError::InvalidHeapAllocationSize(data.len()));
+        }
+
+        let block_index = self.blocks.len() - 1;
+        let block = &mut self.blocks[block_index];
+        block.push(data.to_vec());
+        if block.len() > MAX_HEAP_BLOCK_ALLOCATIONS
+            || heap_block_size(block_index, block) > MAX_HEAP_NODE_SIZE
+        {
+            block.pop();
+            self.blocks.push(vec![data.to_vec()]);
+        }
+
+        let block_index = self.blocks.len() - 1;
+        let index = self.blocks[block_index].len();
+        let block_index = u16::try_from(block_index).map_err(|_| LtpError::HeapPageOutOfSpace)?;
+        HeapId::new(index as u16, block_index)
+    }
+
+    pub fn set_user_root(&mut self, user_root: HeapId) {
+        self.user_root = user_root;
+    }
+
+    /// Serialize the [`HeapNodeHeader`], allocations, and [`HeapNodePageMap`] into the data for a
+    /// single block. Fails if the allocations do not fit in one block, use
+    /// [`Self::build_blocks`] for larger heaps.
+    pub fn build(self) -> io::Result<Vec<u8>> {
+        let mut blocks = self.build_blocks()?;
+        match blocks.len() {
+            1 => Ok(blocks.remove(0)),
+            _ => Err(LtpError::HeapPageOutOfSpace.into()),
+        }
+    }
+
+    /// Serialize each block of the heap, which become the data blocks of the node's data tree.
+    /// Every block after the first starts with an [`HeapNodePageHeader`], or an
+    /// [`HeapNodeBitmapHeader`] with the `rgbFillLevel` of the next 128 blocks.
+    pub fn build_blocks(self) -> io::Result<Vec<Vec<u8>>> {
+        let mut fill_levels = Vec::with_capacity(self.blocks.len());
+        let mut blocks = Vec::with_capacity(self.blocks.len());
+        for (block_index, allocations) in self.blocks.into_iter().enumerate() {
+            let mut offsets = Vec::with_capacity(allocations.len() + 1);
+            let mut data = vec![0; heap_block_header_size(block_index)];
+            for allocation in allocations {
+                offsets.push(u16::try_from(data.len()).map_err(|_| LtpError::HeapPageOutOfSpace)?);
+                data.extend(allocation);
+            }
+            offsets.push(u16::try_from(data.len()).map_err(|_| LtpError::HeapPageOutOfSpace)?);
+
+            if data.len() % 2 != 0 {
+                data.push(0);
+            }
+            let page_map_offset =
+                u16::try_from(data.len()).map_err(|_| LtpError::HeapPageOutOfSpace)?;
+            let alloc_count =
+                u16::try_from(offsets.len() - 1).map_err(|_| LtpError::HeapPageOutOfSpace)?;
+            let page_map =
+                HeapNodePageMap::new(alloc_count, 0, HeapNodePageAllocOffsets::new(offsets))?;
+            page_map.write(&mut data)?;
+
+            let free_space = MAX_HEAP_NODE_SIZE
+                .checked_sub(data.len())
+                .ok_or(LtpError::HeapPageOutOfSpace)?;
+            fill_levels.push(HeapFillLevel::from_free_space(free_space));
+            blocks.push((page_map_offset, data));
+        }
+
+        let fill_levels_from = |start: usize, levels: &mut [HeapFillLevel]| {
+            for (level, fill_level) in levels.iter_mut().zip(fill_levels.iter().skip(start)) {
+                *level = *fill_level;
+            }
+        };
+
+        blocks
+            .into_iter()
+            .enumerate()
+            .map(|(block_index, (page_map_offset, mut data))| {
+                match block_index {
+                    0 => {
+                        let mut levels = [HeapFillLevel::Empty; 8];
+                        fill_levels_from(0, &mut levels);
+                        let header = HeapNodeHeader::new(
+                            page_map_offset,
+                            self.client_signature,
+                            self.user_root,
+                            levels,
+                        );
+                        header.write(&mut &mut data[..12])?;
+                    }
+                    bitmap if bitmap % 128 == 8 => {
+                        let mut levels = [HeapFillLevel::Empty; 128];
+                        fill_levels_from(bitmap, &mut levels);
+                        let header = HeapNodeBitmapHeader::new(page_map_offset, levels);
+                        header.write(&mut &mut data[..66])?;
+                    }
+                    _ => {
+                        let header = HeapNodePageHeader::new(page_map_offset);
+                        header.write(&mut &mut data[..2])?;
+                    }
+                }
+                Ok(data)
+            })
+            .collect()
+    }
+}
+
 pub trait HeapNode {
     fn header(&self) -> io::Result<HeapNodeHeader>;
     fn find_entry(&self, heap_id: HeapId) -> io::Result<&[u8]>;
+
+    /// Like [`HeapNode::find_entry`], but a dangling `heap_id`, see [`is_dangling_heap_id`], is
+    /// not an error.
+    fn find_optional_entry(&self, heap_id: HeapId) -> io::Result<Option<&[u8]>> {
+        match self.find_entry(heap_id) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(err) if is_dangling_heap_id(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// How reads from a PC or TC treat a value whose HID does not point at an allocation in the
+/// heap, e.g. because the heap block which held it was truncated.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum HeapIdMode {
+    /// Fail with [`LtpError::HeapBlockIndexNotFound`] or [`LtpError::HeapAllocIndexNotFound`].
+    #[default]
+    Strict,
+    /// Treat the value as missing, so the rest of the object can still be read.
+    Lenient,
+}
+
+/// Check whether `err` is a [`LtpError::HeapBlockIndexNotFound`] or
+/// [`LtpError::HeapAllocIndexNotFound`] error for a HID which is outside of the heap.
+pub fn is_dangling_heap_id(err: &io::Error) -> bool {
+    matches!(
+        err.get_ref().and_then(|err| err.downcast_ref::<LtpError>()),
+        Some(LtpError::HeapBlockIndexNotFound { .. } | LtpError::HeapAllocIndexNotFound { .. })
+    )
 }
 
 struct HeapNodeInner<Pst>
@@ -573,42 +780,154 @@ where
         <Pst as PstFile>::DataBlock: 'a,
     {
         let block_index = heap_id.block_index();
-        let block = self
-            .data
-            .get(block_index as usize)
-            .ok_or(LtpError::HeapBlockIndexNotFound(block_index))?
-            .data();
-
-        let mut cursor = Cursor::new(block);
-
-        let page_map_offset = match block_index {
-            0 => {
-                let header = HeapNodeHeader::read(&mut cursor)?;
-                header.page_map_offset()
+        let block =
+            self.data
+                .get(block_index as usize)
+                .ok_or(LtpError::HeapBlockIndexNotFound {
+                    block_index,
+                    block_count: self.data.len(),
+                })?;
+        find_heap_entry(
+            block.data(),
+            heap_id,
+            Some(block.trailer().block_id().into_u64()),
+        )
+    }
+}
+
+/// Find the allocation for `heap_id` in `block`, which is the block at
+/// [`HeapId::block_index`] in the heap. The `block_id` is only used to describe the block in
+/// errors, since the blocks of a heap which has not been written yet do not have one.
+fn find_heap_entry(block: &[u8], heap_id: HeapId, block_id: Option<u64>) -> io::Result<&[u8]> {
+    let block_index = heap_id.block_index();
+    let mut cursor = Cursor::new(block);
+
+    let page_map_offset = match block_index {
+        0 => {
+            let header = HeapNodeHeader::read(&mut cursor)?;
+            header.page_map_offset()
+        }
+        bitmap if bitmap % 128 == 8 => {
+            let header = HeapNodeBitmapHeader::read(&mut cursor)?;
+            header.page_map_offset()
+        }
+        _ => {
+            let header = HeapNodePageHeader::read(&mut cursor)?;
+            header.page_map_offset()
+        }
+    };
+
+    cursor.seek(SeekFrom::Start(u64::from(page_map_offset)))?;
+    let page_map = HeapNodePageMap::read(&mut cursor)?;
+    let allocations = page_map.allocations();
+
+    let index = heap_id.index()?;
+    if index as usize >= allocations.len() {
+        return Err(LtpError::HeapAllocIndexNotFound {
+            block_id,
+            block_index,
+            alloc_count: allocations.len(),
+            index,
+        }
+        .into());
+    }
+
+    let alloc = &allocations[index as usize];
+    let start = alloc.offset() as usize;
+    let end = start + alloc.size() as usize;
+    block
+        .get(start..end)
+        .ok_or(LtpError::InvalidHeapPageAllocOffset(alloc.offset()).into())
+}
+
+/// A [`HeapNode`] over blocks which have already been read, e.g. the
+/// [`NodeData`](crate::ndb::node_data::NodeData) of a node which is about to be rewritten.
+pub struct HeapNodeBlocks<'a> {
+    blocks: &'a [Vec<u8>],
+}
+
+impl<'a> HeapNodeBlocks<'a> {
+    pub fn new(blocks: &'a [Vec<u8>]) -> Self {
+        Self { blocks }
+    }
+
+    /// Check the [`HeapNodePageMap`] of every block, and the `rgbFillLevel` entries in the
+    /// [`HeapNodeHeader`] and each [`HeapNodeBitmapHeader`] against the free space in the blocks
+    /// they describe.
+    pub fn validate(&self) -> io::Result<()> {
+        let mut declared_fill_levels = vec![];
+        let mut fill_levels = Vec::with_capacity(self.blocks.len());
+        for (block_index, block) in self.blocks.iter().enumerate() {
+            let mut cursor = Cursor::new(block.as_slice());
+            let page_map_offset = match block_index {
+                0 => {
+                    let header = HeapNodeHeader::read(&mut cursor)?;
+                    declared_fill_levels.push((block_index, header.fill_levels().to_vec()));
+                    header.page_map_offset()
+                }
+                bitmap if bitmap % 128 == 8 => {
+                    let header = HeapNodeBitmapHeader::read(&mut cursor)?;
+                    declared_fill_levels.push((block_index, header.fill_levels().to_vec()));
+                    header.page_map_offset()
+                }
+                _ => HeapNodePageHeader::read(&mut cursor)?.page_map_offset(),
+            };
+
+            let header_size = heap_block_header_size(block_index);
+            if usize::from(page_map_offset) < header_size
+                || usize::from(page_map_offset) > block.len()
+            {
+                return Err(LtpError::InvalidHeapPageMapOffset(page_map_offset).into());
             }
-            bitmap if bitmap % 128 == 8 => {
-                let header = HeapNodeBitmapHeader::read(&mut cursor)?;
-                header.page_map_offset()
+
+            cursor.seek(SeekFrom::Start(u64::from(page_map_offset)))?;
+            let page_map = HeapNodePageMap::read(&mut cursor)?;
+            if let Some(first) = page_map.allocations().first() {
+                if usize::from(first.offset()) < header_size {
+                    return Err(LtpError::InvalidHeapPageAllocOffset(first.offset()).into());
+                }
             }
-            _ => {
-                let header = HeapNodePageHeader::read(&mut cursor)?;
-                header.page_map_offset()
+            if page_map.next_offset() > page_map_offset {
+                return Err(LtpError::InvalidHeapPageAllocOffset(page_map.next_offset()).into());
             }
-        };
 
-        cursor.seek(SeekFrom::Start(u64::from(page_map_offset)))?;
-        let page_map = HeapNodePageMap::read(&mut cursor)?;
-        let allocations = page_map.allocations();
+            fill_levels.push(HeapFillLevel::from_free_space(
+                MAX_HEAP_NODE_SIZE.saturating_sub(block.len()),
+            ));
+        }
 
-        let index = heap_id.index()?;
-        if index as usize >= allocations.len() {
-            return Err(LtpError::HeapAllocIndexNotFound(index).into());
+        for (first_block, levels) in declared_fill_levels {
+            for (block_index, level) in (first_block..).zip(levels) {
+                let expected = fill_levels
+                    .get(block_index)
+                    .copied()
+                    .unwrap_or(HeapFillLevel::Empty);
+                if level != expected {
+                    return Err(LtpError::InvalidHeapBlockFillLevel(block_index, level).into());
+                }
+            }
         }
 
-        let alloc = &allocations[index as usize];
-        let start = alloc.offset() as usize;
-        let end = start + alloc.size() as usize;
-        Ok(&block[start..end])
+        Ok(())
+    }
+}
+
+impl HeapNode for HeapNodeBlocks<'_> {
+    fn header(&self) -> io::Result<HeapNodeHeader> {
+        let data = self.blocks.first().ok_or(io::ErrorKind::UnexpectedEof)?;
+        HeapNodeHeader::read(&mut data.as_slice())
+    }
+
+    fn find_entry(&self, heap_id: HeapId) -> io::Result<&[u8]> {
+        let block_index = heap_id.block_index();
+        let block =
+            self.blocks
+                .get(block_index as usize)
+                .ok_or(LtpError::HeapBlockIndexNotFound {
+                    block_index,
+                    block_count: self.blocks.len(),
+                })?;
+        find_heap_entry(block, heap_id, None)
     }
 }
 
@@ -665,3 +984,68 @@ impl HeapNodeReadWrite<AnsiPstFile> for AnsiHeapNode {
         Ok(Self { inner })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_fill_levels() {
+        // rgbFillLevel starts with the first block in the low nibble of the first byte.
+        let fill_levels = [
+            HeapFillLevel::Level1,
+            HeapFillLevel::Level2,
+            HeapFillLevel::Level3,
+            HeapFillLevel::Level4,
+            HeapFillLevel::Level5,
+            HeapFillLevel::Level6,
+            HeapFillLevel::Level7,
+            HeapFillLevel::Level8,
+        ];
+        let packed = HeapFillLevel::pack_fill_levels(&fill_levels);
+        assert_eq!(packed, 0x8765_4321);
+        assert_eq!(HeapFillLevel::unpack_fill_levels(packed), fill_levels);
+
+        let header = HeapNodeHeader::new(12, HeapNodeType::Properties, Default::default(), {
+            let mut fill_levels = [HeapFillLevel::Empty; 8];
+            fill_levels[0] = HeapFillLevel::Level15;
+            fill_levels
+        });
+        let mut data = vec![];
+        header.write(&mut data).unwrap();
+        assert_eq!(data[8..], [0x0F, 0x00, 0x00, 0x00]);
+        let header = HeapNodeHeader::read(&mut data.as_slice()).unwrap();
+        assert_eq!(header.fill_levels()[0], HeapFillLevel::Level15);
+    }
+
+    #[test]
+    fn test_dangling_heap_id() {
+        let mut builder = HeapNodeBuilder::new(HeapNodeType::Properties);
+        let heap_id = builder.allocate(&[1, 2, 3, 4]).unwrap();
+        let blocks = builder.build_blocks().unwrap();
+        let heap = HeapNodeBlocks::new(&blocks);
+        assert_eq!(heap.find_entry(heap_id).unwrap(), [1, 2, 3, 4]);
+
+        let missing_index = HeapId::new(heap_id.index().unwrap() + 4, 0).unwrap();
+        let err = heap.find_entry(missing_index).unwrap_err();
+        assert!(is_dangling_heap_id(&err));
+        assert!(matches!(
+            err.get_ref().and_then(|err| err.downcast_ref::<LtpError>()),
+            Some(LtpError::HeapAllocIndexNotFound {
+                block_id: None,
+                block_index: 0,
+                index,
+                ..
+            }) if *index == missing_index.index().unwrap()
+        ));
+        assert_eq!(heap.find_optional_entry(missing_index).unwrap(), None);
+
+        let missing_block = HeapId::new(0, blocks.len() as u16).unwrap();
+        let err = heap.find_entry(missing_block).unwrap_err();
+        assert!(matches!(
+            err.get_ref().and_then(|err| err.downcast_ref::<LtpError>()),
+            Some(LtpError::HeapBlockIndexNotFound { block_count, .. }) if *block_count == blocks.len()
+        ));
+        assert_eq!(heap.find_optional_entry(missing_block).unwrap(), None);
+    }
+}
@@ -3,11 +3,15 @@
 use std::io;
 use thiserror::Error;
 
+pub mod code_page;
+pub mod convert;
 pub mod heap;
 pub mod prop_context;
+pub mod prop_tags;
 pub mod prop_type;
 pub mod table_context;
 pub mod tree;
+pub mod utf16;
 
 pub(crate) mod read_write;
 
@@ -27,6 +31,8 @@ pub enum LtpError {
     InvalidHeapFillLevel(u8),
     #[error("HNPAGEMAP is out of space")]
     HeapPageOutOfSpace,
+    #[error("Invalid HN allocation size: 0x{0:X}")]
+    InvalidHeapAllocationSize(usize),
     #[error("Empty HNPAGEMAP rgibAlloc")]
     EmptyHeapPageAlloc,
     #[error("Invalid HNPAGEMAP rgibAlloc entry: 0x{0:04X}")]
@@ -35,16 +41,33 @@ pub enum LtpError {
     InvalidHeapPageAllocCount(u16),
     #[error("Invalid HNPAGEMAP cFree: 0x{0:04X}")]
     InvalidHeapPageFreeCount(u16),
+    #[error("Invalid HN ibHnpm: 0x{0:04X}")]
+    InvalidHeapPageMapOffset(u16),
+    #[error("Invalid HN rgbFillLevel for block {0}: {1:?}")]
+    InvalidHeapBlockFillLevel(usize, heap::HeapFillLevel),
     #[error("Invalid BTHHEADER bType: {0:?}")]
     InvalidHeapTreeNodeType(heap::HeapNodeType),
     #[error("Invalid BTHHEADER cbKey: 0x{0:02X}")]
     InvalidHeapTreeKeySize(u8),
     #[error("Invalid BTHHEADER cbEnt: 0x{0:02X}")]
     InvalidHeapTreeDataSize(u8),
-    #[error("Missing HID hidBlockIndex: {0}")]
-    HeapBlockIndexNotFound(u16),
-    #[error("Missing HID hidIndex: {0}")]
-    HeapAllocIndexNotFound(u16),
+    #[error("BTH keys are out of order at record {0}")]
+    UnsortedHeapTreeKeys(usize),
+    #[error("Missing HID hidBlockIndex: {block_index}, the heap has {block_count} blocks")]
+    HeapBlockIndexNotFound {
+        block_index: u16,
+        block_count: usize,
+    },
+    #[error(
+        "Missing HID hidIndex: {index} in block {block_index}{}, cAlloc: {alloc_count}",
+        .block_id.map(|block_id| format!(" (BID 0x{block_id:X})")).unwrap_or_default()
+    )]
+    HeapAllocIndexNotFound {
+        block_id: Option<u64>,
+        block_index: u16,
+        alloc_count: usize,
+        index: u16,
+    },
     #[error("Invalid PC BTH Record wPropType: 0x{0:04X}")]
     InvalidPropertyType(u16),
     #[error("Invalid variable length PC value property type: {0:?}")]
@@ -55,8 +78,18 @@ pub enum LtpError {
     InvalidMultiValuePropertyCount(usize),
     #[error("Missing PC sub-node value: 0x{0:08X}")]
     PropertySubNodeValueNotFound(u32),
+    #[error("Cannot decode truncated PC value preview: {0:?}")]
+    TruncatedPropertyPreview(prop_type::PropertyType),
     #[error("Invalid small PC value property type: {0:?}")]
     InvalidSmallPropertyType(prop_type::PropertyType),
+    #[error("Cannot convert a {0:?} property value to {1}")]
+    InvalidPropertyValueConversion(prop_type::PropertyType, &'static str),
+    #[error("PtypTime value out of range: {0}")]
+    InvalidPropertyTime(i64),
+    #[error("Invalid PidLidTimeZoneStruct size: {0}")]
+    InvalidTimeZoneStructSize(usize),
+    #[error("Invalid GUID string: {0:?}")]
+    InvalidGuidString(String),
     #[error("Invalid PC property tree key size: 0x{0:X}")]
     InvalidPropertyTreeKeySize(u8),
     #[error("Invalid PC property tree entry size: 0x{0:X}")]
@@ -93,8 +126,18 @@ pub enum LtpError {
     InvalidTableColumnBitmaskOffset(u8),
     #[error("Invalid TCOLDESC BOOL value: 0x{0:02X}")]
     InvalidTableColumnBooleanValue(u8),
+    #[error("Invalid TCINFO rgTCOLDESC index: {0}")]
+    InvalidTableColumnIndex(usize),
     #[error("Missing TCROWID: 0x{0:08X}")]
     TableRowIdNotFound(u32),
+    #[error("Invalid TC value for column 0x{0:04X}: {1:?}")]
+    InvalidTableColumnValue(u16, prop_type::PropertyType),
+    #[error("Invalid TCROWID dwRowIndex: 0x{0:X}")]
+    InvalidTableContextRowIndex(usize),
+    #[error("TCROWID 0x{0:08X} points at row 0x{1:X} with a different dwRowID")]
+    MismatchedTableRowIndex(u32, usize),
+    #[error("Invalid TC row count: {0} TCROWID records for {1} rows")]
+    InvalidTableContextRowCount(usize, usize),
 }
 
 impl From<LtpError> for io::Error {
@@ -7,15 +7,25 @@ use std::{
     collections::BTreeMap,
     fmt::{Debug, Display},
     io::{self, Cursor, Read, Write},
+    str::FromStr,
 };
 
-use super::{heap::*, prop_type::*, read_write::*, tree::*, *};
+use super::{
+    code_page::{decode_string8, String8Decoder},
+    heap::*,
+    prop_type::*,
+    read_write::*,
+    tree::*,
+    utf16::{decode_utf16_lossy, utf16le_units, with_scratch},
+    *,
+};
 use crate::{
     ndb::{
         block::{DataBlockCache, DataTree, IntermediateTreeBlock, SubNodeTree},
         block_id::BlockId,
         block_ref::BlockRef,
         header::NdbCryptMethod,
+        node_data::{NodeData, FIRST_LOCAL_NODE_INDEX},
         node_id::{NodeId, NodeIdType},
         page::{
             AnsiBlockBTree, AnsiNodeBTreeEntry, BlockBTreeEntry, NodeBTreeEntry, RootBTree,
@@ -206,9 +216,25 @@ pub struct String8Value {
 }
 
 impl String8Value {
+    pub fn new(buffer: Vec<u8>) -> Self {
+        Self { buffer }
+    }
+
     pub fn buffer(&self) -> &[u8] {
         &self.buffer
     }
+
+    /// Decode the string with [`decode_string8`]. The [`Display`] implementation always decodes it
+    /// as ISO-8859-1.
+    pub fn to_string_with_code_page(&self, code_page: u16) -> String {
+        decode_string8(&self.buffer, code_page)
+    }
+
+    /// Decode the string with a [`String8Decoder`], which may use a
+    /// [`CodePageDecoder`](super::code_page::CodePageDecoder) supplied by the embedder.
+    pub fn to_string_with_decoder(&self, decoder: &String8Decoder) -> String {
+        decoder.decode(&self.buffer)
+    }
 }
 
 impl Display for String8Value {
@@ -232,6 +258,10 @@ pub struct UnicodeValue {
 }
 
 impl UnicodeValue {
+    pub fn new(buffer: Vec<u16>) -> Self {
+        Self { buffer }
+    }
+
     pub fn buffer(&self) -> &[u16] {
         &self.buffer
     }
@@ -239,7 +269,7 @@ impl UnicodeValue {
 
 impl Display for UnicodeValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let value = String::from_utf16_lossy(&self.buffer);
+        let value = decode_utf16_lossy(&self.buffer);
         write!(f, "{value}")
     }
 }
@@ -251,7 +281,7 @@ impl Debug for UnicodeValue {
     }
 }
 
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct GuidValue {
     data1: u32,
     data2: u16,
@@ -284,6 +314,100 @@ impl GuidValue {
     pub fn data4(&self) -> &[u8; 8] {
         &self.data4
     }
+
+    /// Read the 16-byte packet representation of a GUID from MS-DTYP, where `Data1`, `Data2`, and
+    /// `Data3` are in little-endian byte order, which is how GUIDs are stored in a PST file.
+    pub const fn from_bytes_le(bytes: [u8; 16]) -> Self {
+        Self {
+            data1: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            data2: u16::from_le_bytes([bytes[4], bytes[5]]),
+            data3: u16::from_le_bytes([bytes[6], bytes[7]]),
+            data4: [
+                bytes[8], bytes[9], bytes[10], bytes[11], bytes[12], bytes[13], bytes[14],
+                bytes[15],
+            ],
+        }
+    }
+
+    /// The inverse of [`GuidValue::from_bytes_le`].
+    pub fn to_bytes_le(&self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        bytes[..4].copy_from_slice(&self.data1.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.data2.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.data3.to_le_bytes());
+        bytes[8..].copy_from_slice(&self.data4);
+        bytes
+    }
+
+    /// A GUID whose bytes are the 128-bit FNV-1a hash of `data`, for a GUID which must be the
+    /// same every time it is made from the same name.
+    pub(crate) fn from_hash(data: &[u8]) -> Self {
+        const FNV_OFFSET_BASIS: u128 = 0x6C62_272E_07BB_0142_62B8_2175_6295_C58D;
+        const FNV_PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013B;
+        let hash = data.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ u128::from(*byte)).wrapping_mul(FNV_PRIME)
+        });
+        Self::from_bytes_le(hash.to_le_bytes())
+    }
+
+    fn read(f: &mut dyn Read) -> io::Result<Self> {
+        let mut bytes = [0; 16];
+        f.read_exact(&mut bytes)?;
+        Ok(Self::from_bytes_le(bytes))
+    }
+}
+
+/// Parse the registry format of [`Display`], e.g. `{00062008-0000-0000-C000-000000000046}`. The
+/// braces are optional, and the hex digits are not case sensitive.
+impl FromStr for GuidValue {
+    type Err = LtpError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || LtpError::InvalidGuidString(value.to_string());
+        let digits = value
+            .strip_prefix('{')
+            .and_then(|value| value.strip_suffix('}'))
+            .unwrap_or(value);
+        let groups: Vec<_> = digits.split('-').collect();
+        if groups.iter().map(|group| group.len()).ne([8, 4, 4, 4, 12])
+            || !groups
+                .iter()
+                .all(|group| group.bytes().all(|digit| digit.is_ascii_hexdigit()))
+        {
+            return Err(invalid());
+        }
+
+        let data1 = u32::from_str_radix(groups[0], 16).map_err(|_| invalid())?;
+        let data2 = u16::from_str_radix(groups[1], 16).map_err(|_| invalid())?;
+        let data3 = u16::from_str_radix(groups[2], 16).map_err(|_| invalid())?;
+        let data4_digits = [groups[3], groups[4]].concat();
+        let mut data4 = [0; 8];
+        for (index, byte) in data4.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&data4_digits[index * 2..index * 2 + 2], 16)
+                .map_err(|_| invalid())?;
+        }
+        Ok(Self::new(data1, data2, data3, data4))
+    }
+}
+
+impl Display for GuidValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{{{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}}}",
+            self.data1,
+            self.data2,
+            self.data3,
+            self.data4[0],
+            self.data4[1],
+            self.data4[2],
+            self.data4[3],
+            self.data4[4],
+            self.data4[5],
+            self.data4[6],
+            self.data4[7]
+        )
+    }
 }
 
 impl Debug for GuidValue {
@@ -340,6 +464,10 @@ pub struct ObjectValue {
 }
 
 impl ObjectValue {
+    pub fn new(node_id: NodeId, size: u32) -> Self {
+        Self { node_id, size }
+    }
+
     pub fn node(&self) -> NodeId {
         self.node_id
     }
@@ -476,6 +604,146 @@ impl From<&PropertyValue> for PropertyType {
     }
 }
 
+/// A property value which may still be in its raw heap or sub-node encoding. Reading a
+/// [`MaybeDecoded::Raw`] value only copies the bytes, and the conversion (UTF-16 decoding,
+/// splitting multi-valued properties, parsing GUIDs) waits till the value is accessed.
+#[derive(Clone, Debug)]
+pub enum MaybeDecoded<T> {
+    Raw {
+        prop_type: PropertyType,
+        buffer: Vec<u8>,
+    },
+    Decoded(T),
+}
+
+impl<T> From<T> for MaybeDecoded<T> {
+    fn from(value: T) -> Self {
+        Self::Decoded(value)
+    }
+}
+
+impl MaybeDecoded<PropertyValue> {
+    pub fn raw(prop_type: PropertyType, buffer: Vec<u8>) -> Self {
+        Self::Raw { prop_type, buffer }
+    }
+
+    pub fn prop_type(&self) -> PropertyType {
+        match self {
+            Self::Raw { prop_type, .. } => *prop_type,
+            Self::Decoded(value) => PropertyType::from(value),
+        }
+    }
+
+    pub fn is_decoded(&self) -> bool {
+        matches!(self, Self::Decoded(_))
+    }
+
+    /// The encoded bytes, if the value has not been decoded yet.
+    pub fn raw_buffer(&self) -> Option<&[u8]> {
+        match self {
+            Self::Raw { buffer, .. } => Some(buffer),
+            Self::Decoded(_) => None,
+        }
+    }
+
+    /// Decode the value in place if it is still raw. If decoding fails, the raw buffer is kept.
+    pub fn decode(&mut self) -> io::Result<&PropertyValue> {
+        if let Self::Raw { prop_type, buffer } = self {
+            let mut cursor = Cursor::new(buffer.as_slice());
+            *self = Self::Decoded(PropertyValue::read(&mut cursor, *prop_type)?);
+        }
+
+        match self {
+            Self::Decoded(value) => Ok(value),
+            Self::Raw { .. } => unreachable!(),
+        }
+    }
+
+    pub fn into_decoded(self) -> io::Result<PropertyValue> {
+        match self {
+            Self::Raw { prop_type, buffer } => {
+                let mut cursor = Cursor::new(buffer);
+                PropertyValue::read(&mut cursor, prop_type)
+            }
+            Self::Decoded(value) => Ok(value),
+        }
+    }
+}
+
+/// The start of a property value, which was read without reading the rest of a large value, e.g.
+/// to show the beginning of a body in a list view.
+#[derive(Clone, Debug)]
+pub struct PropertyPreview {
+    prop_type: PropertyType,
+    buffer: Vec<u8>,
+    total_size: usize,
+}
+
+impl PropertyPreview {
+    pub fn prop_type(&self) -> PropertyType {
+        self.prop_type
+    }
+
+    /// The encoded bytes at the start of the value. Values which are stored inline in the BTH
+    /// record have the 4 bytes of `dwValueHnid`.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Size of the whole encoded value.
+    pub fn total_size(&self) -> usize {
+        self.total_size
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.buffer.len() < self.total_size
+    }
+
+    /// Decode the bytes in the preview. Truncated `PtypString8`, `PtypString`, and `PtypBinary`
+    /// values decode to their first part, but other types can only be decoded if they are
+    /// complete.
+    pub fn decode(&self) -> io::Result<PropertyValue> {
+        if self.is_truncated()
+            && !matches!(
+                self.prop_type,
+                PropertyType::String8 | PropertyType::Unicode | PropertyType::Binary
+            )
+        {
+            return Err(LtpError::TruncatedPropertyPreview(self.prop_type).into());
+        }
+
+        // Values which fit in 4 bytes are stored inline in the BTH record.
+        let small = <[u8; 4]>::try_from(self.buffer.as_slice())
+            .ok()
+            .and_then(|value| {
+                PropertyValueRecord::Small(u32::from_le_bytes(value)).small_value(self.prop_type)
+            });
+        match small {
+            Some(value) => Ok(value),
+            None => PropertyValue::read(&mut Cursor::new(self.buffer.as_slice()), self.prop_type),
+        }
+    }
+
+    fn new(
+        prop_type: PropertyType,
+        mut buffer: Vec<u8>,
+        total_size: usize,
+        max_bytes: usize,
+    ) -> Self {
+        // Keep whole UTF-16 code units.
+        let max_bytes = match prop_type {
+            PropertyType::Unicode => max_bytes & !1,
+            _ => max_bytes,
+        };
+        buffer.truncate(max_bytes);
+        Self {
+            prop_type,
+            buffer,
+            total_size,
+        }
+    }
+}
+
 impl PropertyValueReadWrite for PropertyValue {
     fn read(f: &mut dyn Read, prop_type: PropertyType) -> io::Result<Self> {
         match prop_type {
@@ -509,13 +777,7 @@ impl PropertyValueReadWrite for PropertyValue {
             }
 
             PropertyType::Unicode => {
-                let mut buffer = Vec::new();
-                while let Ok(ch) = f.read_u16::<LittleEndian>() {
-                    if ch == 0 {
-                        break;
-                    }
-                    buffer.push(ch);
-                }
+                let buffer = with_scratch(f, |bytes| Ok(utf16le_units(bytes)))?;
                 Ok(Self::Unicode(UnicodeValue { buffer }))
             }
 
@@ -524,19 +786,7 @@ impl PropertyValueReadWrite for PropertyValue {
                 Ok(Self::Time(value))
             }
 
-            PropertyType::Guid => {
-                let data1 = f.read_u32::<LittleEndian>()?;
-                let data2 = f.read_u16::<LittleEndian>()?;
-                let data3 = f.read_u16::<LittleEndian>()?;
-                let mut data4 = [0; 8];
-                f.read_exact(&mut data4)?;
-                Ok(Self::Guid(GuidValue {
-                    data1,
-                    data2,
-                    data3,
-                    data4,
-                }))
-            }
+            PropertyType::Guid => Ok(Self::Guid(GuidValue::read(f)?)),
 
             PropertyType::Binary => {
                 let mut buffer = Vec::new();
@@ -666,38 +916,36 @@ impl PropertyValueReadWrite for PropertyValue {
                 }
 
                 // rgDataItems
-                let mut start = (offsets.len() + 1) * mem::size_of::<u32>();
-                let mut values = Vec::with_capacity(offsets.len());
-                for i in 0..offsets.len() {
-                    let next = offsets[i];
-                    if next != start {
-                        return Err(LtpError::InvalidMultiValuePropertyOffset(next).into());
-                    }
-
-                    let mut buffer = Vec::new();
-                    if i < offsets.len() - 1 {
-                        let next = offsets[i + 1];
-                        if next < start {
+                let data_start = (offsets.len() + 1) * mem::size_of::<u32>();
+                let values = with_scratch(f, |data| {
+                    let mut start = data_start;
+                    let mut values = Vec::with_capacity(offsets.len());
+                    for i in 0..offsets.len() {
+                        let next = offsets[i];
+                        if next != start {
                             return Err(LtpError::InvalidMultiValuePropertyOffset(next).into());
                         }
 
-                        while start < next {
-                            let ch = f.read_u16::<LittleEndian>()?;
-                            buffer.push(ch);
-                            start += mem::size_of::<u16>();
-                        }
-                    } else {
-                        while let Ok(ch) = f.read_u16::<LittleEndian>() {
-                            buffer.push(ch);
-                        }
-                    };
+                        let item = if i < offsets.len() - 1 {
+                            let next = offsets[i + 1];
+                            if next < start || (next - start) % mem::size_of::<u16>() != 0 {
+                                return Err(LtpError::InvalidMultiValuePropertyOffset(next).into());
+                            }
+                            let item = data
+                                .get(start - data_start..next - data_start)
+                                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+                            start = next;
+                            item
+                        } else {
+                            &data[(start - data_start).min(data.len())..]
+                        };
 
-                    if let Some(end) = buffer.iter().position(|&ch| ch == 0) {
-                        buffer.truncate(end);
+                        values.push(UnicodeValue {
+                            buffer: utf16le_units(item),
+                        });
                     }
-
-                    values.push(UnicodeValue { buffer });
-                }
+                    Ok(values)
+                })?;
 
                 Ok(Self::MultipleUnicode(values))
             }
@@ -714,17 +962,7 @@ impl PropertyValueReadWrite for PropertyValue {
                 let count = f.read_u32::<LittleEndian>()? as usize;
                 let mut values = Vec::with_capacity(count);
                 for _ in 0..count {
-                    let data1 = f.read_u32::<LittleEndian>()?;
-                    let data2 = f.read_u16::<LittleEndian>()?;
-                    let data3 = f.read_u16::<LittleEndian>()?;
-                    let mut data4 = [0; 8];
-                    f.read_exact(&mut data4)?;
-                    values.push(GuidValue {
-                        data1,
-                        data2,
-                        data3,
-                        data4,
-                    });
+                    values.push(GuidValue::read(f)?);
                 }
 
                 Ok(Self::MultipleGuid(values))
@@ -800,12 +1038,7 @@ impl PropertyValueReadWrite for PropertyValue {
 
             Self::Time(value) => f.write_i64::<LittleEndian>(*value),
 
-            Self::Guid(value) => {
-                f.write_u32::<LittleEndian>(value.data1)?;
-                f.write_u16::<LittleEndian>(value.data2)?;
-                f.write_u16::<LittleEndian>(value.data3)?;
-                f.write_all(&value.data4)
-            }
+            Self::Guid(value) => f.write_all(&value.to_bytes_le()),
 
             Self::Binary(value) => f.write_all(value.buffer()),
 
@@ -920,10 +1153,7 @@ impl PropertyValueReadWrite for PropertyValue {
 
             Self::MultipleGuid(values) => {
                 for value in values {
-                    f.write_u32::<LittleEndian>(value.data1)?;
-                    f.write_u16::<LittleEndian>(value.data2)?;
-                    f.write_u16::<LittleEndian>(value.data3)?;
-                    f.write_all(&value.data4)?;
+                    f.write_all(&value.to_bytes_le())?;
                 }
                 Ok(())
             }
@@ -963,6 +1193,115 @@ pub trait PropertyContext {
     fn properties(&self) -> io::Result<BTreeMap<PropertyTreeRecordKey, PropertyTreeRecordValue>>;
 }
 
+/// Serialize a set of properties into a new PC. Fixed size values up to 4 bytes are stored inline
+/// in the BTH records, values which fit in [`MAX_HEAP_ALLOCATION_SIZE`] are allocated on the
+/// heap, and anything larger is stored in a sub-node of the PC.
+pub fn build_property_context<'a>(
+    properties: impl IntoIterator<Item = (&'a PropertyTreeRecordKey, &'a PropertyValue)>,
+) -> io::Result<NodeData> {
+    let mut heap = HeapNodeBuilder::new(HeapNodeType::Properties);
+    let mut sub_nodes = BTreeMap::new();
+    let mut records = properties
+        .into_iter()
+        .map(|(&prop_id, value)| {
+            let prop_type = PropertyType::from(value);
+            let value = match value {
+                PropertyValue::Null => PropertyValueRecord::Small(0),
+                PropertyValue::Integer16(value) => PropertyValueRecord::Small(*value as u16 as u32),
+                PropertyValue::Integer32(value) => PropertyValueRecord::Small(*value as u32),
+                PropertyValue::Floating32(value) => PropertyValueRecord::Small(value.to_bits()),
+                PropertyValue::ErrorCode(value) => PropertyValueRecord::Small(*value as u32),
+                PropertyValue::Boolean(value) => PropertyValueRecord::Small(u32::from(*value)),
+                value => {
+                    let mut data = vec![];
+                    value.write(&mut data)?;
+                    if data.is_empty() {
+                        PropertyValueRecord::Heap(Default::default())
+                    } else if data.len() <= MAX_HEAP_ALLOCATION_SIZE {
+                        PropertyValueRecord::Heap(heap.allocate(&data)?)
+                    } else {
+                        let sub_node = NodeId::new(
+                            NodeIdType::ListsTablesProperties,
+                            FIRST_LOCAL_NODE_INDEX + sub_nodes.len() as u32,
+                        )?;
+                        sub_nodes.insert(sub_node, NodeData::from_bytes(&data));
+                        PropertyValueRecord::Node(sub_node)
+                    }
+                }
+            };
+            Ok(PropertyTreeRecord::new(prop_id, prop_type, value))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    records.sort_by_key(PropertyTreeRecord::prop_id);
+
+    let records: Vec<_> = records
+        .into_iter()
+        .map(|record| HeapTreeLeafEntry::new(record.key, record.data))
+        .collect();
+    let user_root = build_heap_tree(&mut heap, &records)?;
+    heap.set_user_root(user_root);
+
+    let mut data = NodeData::new(heap.build_blocks()?);
+    for (sub_node, value) in sub_nodes {
+        data.insert_sub_node(sub_node, value);
+    }
+    Ok(data)
+}
+
+/// Decode every property in a PC which has already been read into memory, including the values
+/// stored in its sub-nodes.
+pub fn read_property_context(data: &NodeData) -> io::Result<BTreeMap<u16, PropertyValue>> {
+    let heap = HeapNodeBlocks::new(data.blocks());
+    let header = heap.header()?;
+    if header.client_signature() != HeapNodeType::Properties {
+        return Err(LtpError::InvalidHeapNodeTypeSignature(header.client_signature() as u8).into());
+    }
+
+    heap_tree_entries::<PropertyTreeRecordKey, PropertyTreeRecordValue>(&heap, header.user_root())?
+        .into_iter()
+        .map(|entry| {
+            let prop_type = entry.data().prop_type();
+            let value = match entry.data().value() {
+                PropertyValueRecord::Heap(heap_id) if u32::from(heap_id) == 0 => {
+                    PropertyValue::read(&mut io::empty(), prop_type).unwrap_or(PropertyValue::Null)
+                }
+                PropertyValueRecord::Heap(heap_id) => {
+                    PropertyValue::read(&mut heap.find_entry(heap_id)?, prop_type)?
+                }
+                PropertyValueRecord::Node(sub_node) => {
+                    let value = data
+                        .sub_node(sub_node)
+                        .ok_or(LtpError::PropertySubNodeValueNotFound(u32::from(sub_node)))?;
+                    PropertyValue::read(&mut value.data().as_slice(), prop_type)?
+                }
+                _ if prop_type == PropertyType::Null => PropertyValue::Null,
+                small => small
+                    .small_value(prop_type)
+                    .ok_or(LtpError::InvalidSmallPropertyType(prop_type))?,
+            };
+            Ok((entry.key(), value))
+        })
+        .collect()
+}
+
+/// Check the heap of a PC which has already been read into memory, the order of the keys in its
+/// BTH, and that every property value can be decoded.
+pub fn validate_property_context(data: &NodeData) -> io::Result<()> {
+    let heap = HeapNodeBlocks::new(data.blocks());
+    heap.validate()?;
+    let header = heap.header()?;
+    if header.client_signature() != HeapNodeType::Properties {
+        return Err(LtpError::InvalidHeapNodeTypeSignature(header.client_signature() as u8).into());
+    }
+
+    validate_heap_tree::<PropertyTreeRecordKey, PropertyTreeRecordValue>(
+        &heap,
+        header.user_root(),
+    )?;
+    read_property_context(data)?;
+    Ok(())
+}
+
 struct PropertyContextInner<Pst>
 where
     Pst: PstFile,
@@ -1033,46 +1372,126 @@ where
         page_cache: &mut RootBTreePageCache<<Pst as PstFile>::BlockBTree>,
         value: PropertyTreeRecordValue,
     ) -> io::Result<PropertyValue> {
+        self.read_property_lazy(f, encoding, block_btree, page_cache, value)?
+            .into_decoded()
+    }
+
+    fn read_property_lazy<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &PstFileReadWriteBlockBTree<Pst>,
+        page_cache: &mut RootBTreePageCache<<Pst as PstFile>::BlockBTree>,
+        value: PropertyTreeRecordValue,
+    ) -> io::Result<MaybeDecoded<PropertyValue>> {
         match value.value() {
             PropertyValueRecord::Heap(heap_id) => {
                 if u32::from(heap_id) == 0 {
-                    return Ok(PropertyValue::Null);
+                    return Ok(MaybeDecoded::Decoded(PropertyValue::Null));
                 }
 
                 let data = self.tree.heap().find_entry(heap_id)?;
-                let mut cursor = Cursor::new(data);
-                PropertyValueReadWrite::read(&mut cursor, value.prop_type())
+                Ok(MaybeDecoded::raw(value.prop_type(), data.to_vec()))
             }
             PropertyValueRecord::Node(sub_node_id) => {
-                let sub_node =
-                    self.node
-                        .sub_node()
-                        .ok_or(LtpError::PropertySubNodeValueNotFound(u32::from(
-                            sub_node_id,
-                        )))?;
-                let block = block_btree.find_entry(f, sub_node.search_key(), page_cache)?;
-                let sub_node_tree = SubNodeTree::<Pst>::read(f, &block)?;
-                let block = sub_node_tree.find_entry(f, block_btree, sub_node_id, page_cache)?;
-                let block = block_btree.find_entry(f, block.search_key(), page_cache)?;
-                let mut block_cache = self.block_cache.borrow_mut();
-                let data_tree = match block_cache.remove(&block.block().block()) {
-                    Some(data_tree) => data_tree,
-                    None => DataTree::read(f, encoding, &block)?,
-                };
-                let mut data = vec![];
-                let result = data_tree
-                    .reader(f, encoding, block_btree, page_cache, &mut block_cache)
-                    .and_then(|mut r| r.read_to_end(&mut data));
-                block_cache.insert(block.block().block(), data_tree);
-                let _ = result?;
-                let mut cursor = Cursor::new(data);
-                PropertyValueReadWrite::read(&mut cursor, value.prop_type())
+                let (data, _) = self.read_sub_node_value(
+                    f,
+                    encoding,
+                    block_btree,
+                    page_cache,
+                    sub_node_id,
+                    None,
+                )?;
+                Ok(MaybeDecoded::raw(value.prop_type(), data))
             }
             small => small
                 .small_value(value.prop_type())
+                .map(MaybeDecoded::Decoded)
                 .ok_or(LtpError::InvalidSmallPropertyType(value.prop_type()).into()),
         }
     }
+
+    fn get_preview<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &PstFileReadWriteBlockBTree<Pst>,
+        page_cache: &mut RootBTreePageCache<<Pst as PstFile>::BlockBTree>,
+        prop_id: u16,
+        max_bytes: usize,
+    ) -> io::Result<Option<PropertyPreview>> {
+        let Some(value) = self.properties()?.remove(&prop_id) else {
+            return Ok(None);
+        };
+        let prop_type = value.prop_type();
+
+        let (buffer, total_size) = match value.value() {
+            PropertyValueRecord::Heap(heap_id) if u32::from(heap_id) == 0 => (vec![], 0),
+            PropertyValueRecord::Heap(heap_id) => {
+                let data = self.tree.heap().find_entry(heap_id)?;
+                (data[..data.len().min(max_bytes)].to_vec(), data.len())
+            }
+            PropertyValueRecord::Node(sub_node_id) => self.read_sub_node_value(
+                f,
+                encoding,
+                block_btree,
+                page_cache,
+                sub_node_id,
+                Some(max_bytes),
+            )?,
+            small => {
+                small
+                    .small_value(prop_type)
+                    .ok_or(LtpError::InvalidSmallPropertyType(prop_type))?;
+                let data = u32::from(small).to_le_bytes();
+                (data.to_vec(), data.len())
+            }
+        };
+
+        Ok(Some(PropertyPreview::new(
+            prop_type, buffer, total_size, max_bytes,
+        )))
+    }
+
+    /// Read the data tree of a value stored in a sub-node, stopping after `limit` bytes if there
+    /// is one, and return the bytes along with the total size of the value. Only the leaf blocks
+    /// which hold the bytes before the limit are read.
+    fn read_sub_node_value<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &PstFileReadWriteBlockBTree<Pst>,
+        page_cache: &mut RootBTreePageCache<<Pst as PstFile>::BlockBTree>,
+        sub_node_id: NodeId,
+        limit: Option<usize>,
+    ) -> io::Result<(Vec<u8>, usize)> {
+        let sub_node = self
+            .node
+            .sub_node()
+            .ok_or(LtpError::PropertySubNodeValueNotFound(u32::from(
+                sub_node_id,
+            )))?;
+        let block = block_btree.find_entry(f, sub_node.search_key(), page_cache)?;
+        let sub_node_tree = SubNodeTree::<Pst>::read(f, &block)?;
+        let block = sub_node_tree.find_entry(f, block_btree, sub_node_id, page_cache)?;
+        let block = block_btree.find_entry(f, block.search_key(), page_cache)?;
+        let mut block_cache = self.block_cache.borrow_mut();
+        let data_tree = match block_cache.remove(&block.block().block()) {
+            Some(data_tree) => data_tree,
+            None => DataTree::read(f, encoding, &block)?,
+        };
+        let total_size = data_tree.total_size() as usize;
+        let mut data = vec![];
+        let result = data_tree
+            .reader(f, encoding, block_btree, page_cache, &mut block_cache)
+            .and_then(|r| {
+                r.take(limit.map_or(u64::MAX, |limit| limit as u64))
+                    .read_to_end(&mut data)
+            });
+        block_cache.insert(block.block().block(), data_tree);
+        let _ = result?;
+        Ok((data, total_size))
+    }
 }
 
 pub struct UnicodePropertyContext {
@@ -1104,6 +1523,46 @@ impl UnicodePropertyContext {
             value,
         )
     }
+
+    pub fn read_property_lazy<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &UnicodeBlockBTree,
+        page_cache: &mut RootBTreePageCache<UnicodeBlockBTree>,
+        value: PropertyTreeRecordValue,
+    ) -> io::Result<MaybeDecoded<PropertyValue>> {
+        <Self as PropertyContextReadWrite<UnicodePstFile>>::read_property_lazy(
+            self,
+            f,
+            encoding,
+            block_btree,
+            page_cache,
+            value,
+        )
+    }
+
+    /// Read at most `max_bytes` from the start of the value of `prop_id`, see
+    /// [`PropertyPreview`]. Returns [`None`] if the PC does not have the property.
+    pub fn get_preview<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &UnicodeBlockBTree,
+        page_cache: &mut RootBTreePageCache<UnicodeBlockBTree>,
+        prop_id: u16,
+        max_bytes: usize,
+    ) -> io::Result<Option<PropertyPreview>> {
+        <Self as PropertyContextReadWrite<UnicodePstFile>>::get_preview(
+            self,
+            f,
+            encoding,
+            block_btree,
+            page_cache,
+            prop_id,
+            max_bytes,
+        )
+    }
 }
 
 impl PropertyContext for UnicodePropertyContext {
@@ -1133,6 +1592,31 @@ impl PropertyContextReadWrite<UnicodePstFile> for UnicodePropertyContext {
         self.inner
             .read_property(f, encoding, block_btree, page_cache, value)
     }
+
+    fn read_property_lazy<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &UnicodeBlockBTree,
+        page_cache: &mut RootBTreePageCache<UnicodeBlockBTree>,
+        value: PropertyTreeRecordValue,
+    ) -> io::Result<MaybeDecoded<PropertyValue>> {
+        self.inner
+            .read_property_lazy(f, encoding, block_btree, page_cache, value)
+    }
+
+    fn get_preview<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &UnicodeBlockBTree,
+        page_cache: &mut RootBTreePageCache<UnicodeBlockBTree>,
+        prop_id: u16,
+        max_bytes: usize,
+    ) -> io::Result<Option<PropertyPreview>> {
+        self.inner
+            .get_preview(f, encoding, block_btree, page_cache, prop_id, max_bytes)
+    }
 }
 
 pub struct AnsiPropertyContext {
@@ -1161,6 +1645,46 @@ impl AnsiPropertyContext {
             value,
         )
     }
+
+    pub fn read_property_lazy<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &AnsiBlockBTree,
+        page_cache: &mut RootBTreePageCache<AnsiBlockBTree>,
+        value: PropertyTreeRecordValue,
+    ) -> io::Result<MaybeDecoded<PropertyValue>> {
+        <Self as PropertyContextReadWrite<AnsiPstFile>>::read_property_lazy(
+            self,
+            f,
+            encoding,
+            block_btree,
+            page_cache,
+            value,
+        )
+    }
+
+    /// Read at most `max_bytes` from the start of the value of `prop_id`, see
+    /// [`PropertyPreview`]. Returns [`None`] if the PC does not have the property.
+    pub fn get_preview<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &AnsiBlockBTree,
+        page_cache: &mut RootBTreePageCache<AnsiBlockBTree>,
+        prop_id: u16,
+        max_bytes: usize,
+    ) -> io::Result<Option<PropertyPreview>> {
+        <Self as PropertyContextReadWrite<AnsiPstFile>>::get_preview(
+            self,
+            f,
+            encoding,
+            block_btree,
+            page_cache,
+            prop_id,
+            max_bytes,
+        )
+    }
 }
 
 impl PropertyContext for AnsiPropertyContext {
@@ -1190,4 +1714,29 @@ impl PropertyContextReadWrite<AnsiPstFile> for AnsiPropertyContext {
         self.inner
             .read_property(f, encoding, block_btree, page_cache, value)
     }
+
+    fn read_property_lazy<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &AnsiBlockBTree,
+        page_cache: &mut RootBTreePageCache<AnsiBlockBTree>,
+        value: PropertyTreeRecordValue,
+    ) -> io::Result<MaybeDecoded<PropertyValue>> {
+        self.inner
+            .read_property_lazy(f, encoding, block_btree, page_cache, value)
+    }
+
+    fn get_preview<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &AnsiBlockBTree,
+        page_cache: &mut RootBTreePageCache<AnsiBlockBTree>,
+        prop_id: u16,
+        max_bytes: usize,
+    ) -> io::Result<Option<PropertyPreview>> {
+        self.inner
+            .get_preview(f, encoding, block_btree, page_cache, prop_id, max_bytes)
+    }
 }
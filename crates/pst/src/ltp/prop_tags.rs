@@ -0,0 +1,204 @@
+//! Canonical [MS-OXPROPS](https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxprops/f6ab1613-aefe-447d-a49c-18217230b148)
+//! names for the tagged properties which commonly appear in PST files.
+
+/// `(prop_id, canonical name)` pairs, sorted by `prop_id`.
+pub const PROPERTY_TAG_NAMES: &[(u16, &str)] = &[
+    (0x0002, "PidTagAlternateRecipientAllowed"),
+    (0x0017, "PidTagImportance"),
+    (0x001A, "PidTagMessageClass"),
+    (0x0023, "PidTagOriginatorDeliveryReportRequested"),
+    (0x0026, "PidTagPriority"),
+    (0x0029, "PidTagReadReceiptRequested"),
+    (0x0036, "PidTagSensitivity"),
+    (0x0037, "PidTagSubject"),
+    (0x0039, "PidTagClientSubmitTime"),
+    (0x003D, "PidTagSubjectPrefix"),
+    (0x0042, "PidTagSentRepresentingName"),
+    (0x0057, "PidTagMessageToMe"),
+    (0x0058, "PidTagMessageCcMe"),
+    (0x0064, "PidTagSentRepresentingAddressType"),
+    (0x0065, "PidTagSentRepresentingEmailAddress"),
+    (0x0070, "PidTagConversationTopic"),
+    (0x0071, "PidTagConversationIndex"),
+    (0x007D, "PidTagTransportMessageHeaders"),
+    (0x0C15, "PidTagRecipientType"),
+    (0x0C17, "PidTagReplyRequested"),
+    (0x0C1A, "PidTagSenderName"),
+    (0x0C1E, "PidTagSenderAddressType"),
+    (0x0C1F, "PidTagSenderEmailAddress"),
+    (0x0E01, "PidTagDeleteAfterSubmit"),
+    (0x0E02, "PidTagDisplayBcc"),
+    (0x0E03, "PidTagDisplayCc"),
+    (0x0E04, "PidTagDisplayTo"),
+    (0x0E05, "PidTagParentDisplay"),
+    (0x0E06, "PidTagMessageDeliveryTime"),
+    (0x0E07, "PidTagMessageFlags"),
+    (0x0E08, "PidTagMessageSize"),
+    (0x0E09, "PidTagParentEntryId"),
+    (0x0E0A, "PidTagSentMailEntryId"),
+    (0x0E0F, "PidTagResponsibility"),
+    (0x0E12, "PidTagMessageRecipients"),
+    (0x0E13, "PidTagMessageAttachments"),
+    (0x0E17, "PidTagMessageStatus"),
+    (0x0E1B, "PidTagHasAttachments"),
+    (0x0E1F, "PidTagRtfInSync"),
+    (0x0E20, "PidTagAttachSize"),
+    (0x0E21, "PidTagAttachNumber"),
+    (0x0E23, "PidTagInternetArticleNumber"),
+    (0x0E30, "PidTagReplItemid"),
+    (0x0E33, "PidTagReplChangenum"),
+    (0x0E34, "PidTagReplVersionhistory"),
+    (0x0E38, "PidTagReplFlags"),
+    (0x0E3C, "PidTagReplCopiedfromVersionhistory"),
+    (0x0E3D, "PidTagReplCopiedfromItemid"),
+    (0x0E69, "PidTagRead"),
+    (0x0E79, "PidTagTrustSender"),
+    (0x0FF6, "PidTagInstanceKey"),
+    (0x0FF9, "PidTagRecordKey"),
+    (0x0FFA, "PidTagStoreRecordKey"),
+    (0x0FFB, "PidTagStoreEntryId"),
+    (0x0FFE, "PidTagObjectType"),
+    (0x0FFF, "PidTagEntryId"),
+    (0x1000, "PidTagBody"),
+    (0x1009, "PidTagRtfCompressed"),
+    (0x1013, "PidTagBodyHtml"),
+    (0x1035, "PidTagInternetMessageId"),
+    (0x1039, "PidTagInternetReferences"),
+    (0x1042, "PidTagInReplyToId"),
+    (0x1080, "PidTagIconIndex"),
+    (0x1081, "PidTagLastVerbExecuted"),
+    (0x1082, "PidTagLastVerbExecutionTime"),
+    (0x1090, "PidTagFlagStatus"),
+    (0x10F4, "PidTagAttributeHidden"),
+    (0x3001, "PidTagDisplayName"),
+    (0x3002, "PidTagAddressType"),
+    (0x3003, "PidTagEmailAddress"),
+    (0x3007, "PidTagCreationTime"),
+    (0x3008, "PidTagLastModificationTime"),
+    (0x300B, "PidTagSearchKey"),
+    (0x340D, "PidTagStoreSupportMask"),
+    (0x340E, "PidTagStoreState"),
+    (0x35DF, "PidTagValidFolderMask"),
+    (0x35E0, "PidTagIpmSubtreeEntryId"),
+    (0x35E3, "PidTagIpmWastebasketEntryId"),
+    (0x35E7, "PidTagFinderEntryId"),
+    (0x3602, "PidTagContentCount"),
+    (0x3603, "PidTagContentUnreadCount"),
+    (0x360A, "PidTagSubfolders"),
+    (0x3613, "PidTagContainerClass"),
+    (0x3617, "PidTagAssociatedContentCount"),
+    (0x3701, "PidTagAttachDataBinary"),
+    (0x3703, "PidTagAttachExtension"),
+    (0x3704, "PidTagAttachFilename"),
+    (0x3705, "PidTagAttachMethod"),
+    (0x3707, "PidTagAttachLongFilename"),
+    (0x370B, "PidTagRenderingPosition"),
+    (0x370E, "PidTagAttachMimeTag"),
+    (0x3712, "PidTagAttachContentId"),
+    (0x3714, "PidTagAttachFlags"),
+    (0x3900, "PidTagDisplayType"),
+    (0x39FE, "PidTagSmtpAddress"),
+    (0x3A00, "PidTagAccount"),
+    (0x3A20, "PidTagTransmittableDisplayName"),
+    (0x3A40, "PidTagSendRichInfo"),
+    (0x3FDE, "PidTagInternetCodepage"),
+    (0x3FF1, "PidTagMessageLocaleId"),
+    (0x3FF8, "PidTagCreatorName"),
+    (0x3FFA, "PidTagLastModifierName"),
+    (0x3FFD, "PidTagMessageCodepage"),
+    (0x5FF6, "PidTagRecipientDisplayName"),
+    (0x5FFD, "PidTagRecipientFlags"),
+    (0x5FFF, "PidTagRecipientTrackStatus"),
+    (0x6633, "PidTagPstLrNoRestrictions"),
+    (0x6635, "PidTagPstHiddenCount"),
+    (0x6636, "PidTagPstHiddenUnread"),
+    (0x67F1, "PidTagLtpParentNid"),
+    (0x67F2, "PidTagLtpRowId"),
+    (0x67F3, "PidTagLtpRowVer"),
+    (0x67FF, "PidTagPstPassword"),
+];
+
+/// Look up the canonical name of a tagged property. Named properties (`0x8000` and above) are
+/// only meaningful in the context of a store's
+/// [`NamedPropertyMap`](crate::messaging::named_prop::NamedPropertyMap).
+pub fn property_tag_name(prop_id: u16) -> Option<&'static str> {
+    PROPERTY_TAG_NAMES
+        .binary_search_by_key(&prop_id, |(id, _)| *id)
+        .ok()
+        .map(|index| PROPERTY_TAG_NAMES[index].1)
+}
+
+/// `(prop_id, canonical name)` pairs for the tagged properties which have an equivalent in the
+/// [Windows Property System](https://learn.microsoft.com/en-us/windows/win32/properties/props),
+/// the names used by Windows Search and many forensic tools, sorted by `prop_id`.
+pub const WINDOWS_PROPERTY_NAMES: &[(u16, &str)] = &[
+    (0x0017, "System.Importance"),
+    (0x001A, "System.Message.MessageClass"),
+    (0x0036, "System.Sensitivity"),
+    (0x0037, "System.Subject"),
+    (0x0039, "System.Message.DateSent"),
+    (0x003D, "System.Message.SubjectPrefix"),
+    (0x0042, "System.Message.FromName"),
+    (0x0065, "System.Message.FromAddress"),
+    (0x0071, "System.Message.ConversationIndex"),
+    (0x0C1A, "System.Message.SenderName"),
+    (0x0C1F, "System.Message.SenderAddress"),
+    (0x0E02, "System.Message.BccName"),
+    (0x0E03, "System.Message.CcName"),
+    (0x0E04, "System.Message.ToName"),
+    (0x0E05, "System.ItemFolderNameDisplay"),
+    (0x0E06, "System.Message.DateReceived"),
+    (0x0E07, "System.Message.Flags"),
+    (0x0E08, "System.Size"),
+    (0x0E1B, "System.Message.HasAttachments"),
+    (0x0E20, "System.Size"),
+    (0x0E69, "System.IsRead"),
+    (0x3001, "System.ItemNameDisplay"),
+    (0x3007, "System.DateCreated"),
+    (0x3008, "System.DateModified"),
+    (0x3703, "System.FileExtension"),
+    (0x3707, "System.FileName"),
+    (0x370E, "System.MIMEType"),
+    (0x3A06, "System.Contact.FirstName"),
+    (0x3A08, "System.Contact.BusinessTelephone"),
+    (0x3A09, "System.Contact.HomeTelephone"),
+    (0x3A11, "System.Contact.LastName"),
+    (0x3A16, "System.Company"),
+    (0x3A17, "System.Contact.JobTitle"),
+    (0x3A1C, "System.Contact.MobileTelephone"),
+];
+
+/// Look up the Windows Property System name, e.g. `System.Subject`, of a tagged property.
+pub fn windows_property_name(prop_id: u16) -> Option<&'static str> {
+    WINDOWS_PROPERTY_NAMES
+        .binary_search_by_key(&prop_id, |(id, _)| *id)
+        .ok()
+        .map(|index| WINDOWS_PROPERTY_NAMES[index].1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_property_tag_names_sorted() {
+        assert!(PROPERTY_TAG_NAMES
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(property_tag_name(0x0037), Some("PidTagSubject"));
+        assert_eq!(property_tag_name(0x8000), None);
+    }
+
+    #[test]
+    fn test_windows_property_names_sorted() {
+        assert!(WINDOWS_PROPERTY_NAMES
+            .windows(2)
+            .all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(windows_property_name(0x0037), Some("System.Subject"));
+        assert_eq!(
+            windows_property_name(0x0E06),
+            Some("System.Message.DateReceived")
+        );
+        assert_eq!(windows_property_name(0x0FF9), None);
+    }
+}
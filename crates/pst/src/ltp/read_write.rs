@@ -61,6 +61,41 @@ where
         page_cache: &mut RootBTreePageCache<<Pst as PstFile>::BlockBTree>,
         value: PropertyTreeRecordValue,
     ) -> io::Result<PropertyValue>;
+    fn read_property_lazy<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &PstFileReadWriteBlockBTree<Pst>,
+        page_cache: &mut RootBTreePageCache<<Pst as PstFile>::BlockBTree>,
+        value: PropertyTreeRecordValue,
+    ) -> io::Result<MaybeDecoded<PropertyValue>>;
+    fn get_preview<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &PstFileReadWriteBlockBTree<Pst>,
+        page_cache: &mut RootBTreePageCache<<Pst as PstFile>::BlockBTree>,
+        prop_id: u16,
+        max_bytes: usize,
+    ) -> io::Result<Option<PropertyPreview>>;
+
+    /// Like [`PropertyContextReadWrite::read_property`], but with [`HeapIdMode::Lenient`] a value
+    /// whose HID is dangling is returned as [`None`].
+    fn read_property_with_mode<R: PstReader>(
+        &self,
+        f: &mut R,
+        encoding: NdbCryptMethod,
+        block_btree: &PstFileReadWriteBlockBTree<Pst>,
+        page_cache: &mut RootBTreePageCache<<Pst as PstFile>::BlockBTree>,
+        value: PropertyTreeRecordValue,
+        mode: HeapIdMode,
+    ) -> io::Result<Option<PropertyValue>> {
+        match self.read_property(f, encoding, block_btree, page_cache, value) {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if mode == HeapIdMode::Lenient && is_dangling_heap_id(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
 }
 
 pub trait TableContextInfoReadWrite: Sized {
@@ -10,17 +10,19 @@ use std::{
     rc::Rc,
 };
 
-use super::{heap::*, prop_context::*, prop_type::*, read_write::*, tree::*, *};
+use super::{heap::*, prop_context::*, prop_tags::*, prop_type::*, read_write::*, tree::*, *};
 use crate::{
     messaging::{
         read_write::StoreReadWrite,
         store::{AnsiStore, UnicodeStore},
     },
     ndb::{
+        block::max_block_data_size,
         block::{Block, DataBlockCache, DataTree, IntermediateTreeBlock, SubNodeTree},
         block_id::BlockId,
         block_ref::BlockRef,
-        header::Header,
+        header::{Header, NdbVersion},
+        node_data::{NodeData, FIRST_LOCAL_NODE_INDEX},
         node_id::{NodeId, NodeIdType},
         page::{
             AnsiNodeBTreeEntry, BlockBTreeEntry, NodeBTreeEntry, RootBTree, UnicodeNodeBTreeEntry,
@@ -34,6 +36,44 @@ use crate::{
 pub const LTP_ROW_ID_PROP_ID: u16 = 0x67F2;
 pub const LTP_ROW_VERSION_PROP_ID: u16 = 0x67F3;
 
+/// `dwUnique` in the row matrix, which is exposed as `PidTagLtpRowVer`. Outlook stamps a row with
+/// the next value of `dwUnique` in the [`Header`] whenever it adds or changes the row, so a row
+/// whose version is greater than [`Store::row_version`](crate::messaging::store::Store::row_version)
+/// at some earlier point has been added or changed since then.
+///
+/// Versions are compared as plain `u32` values, so this does not account for the counter wrapping
+/// around. Rows written by tools which do not maintain the counter have a version of 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RowVersion(u32);
+
+impl RowVersion {
+    pub const fn new(value: u32) -> Self {
+        Self(value)
+    }
+
+    pub const fn value(&self) -> u32 {
+        self.0
+    }
+}
+
+impl From<u32> for RowVersion {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<RowVersion> for u32 {
+    fn from(value: RowVersion) -> Self {
+        value.0
+    }
+}
+
+impl From<RowVersion> for PropertyValue {
+    fn from(value: RowVersion) -> Self {
+        PropertyValue::Integer32(value.0 as i32)
+    }
+}
+
 pub const fn existence_bitmap_size(column_count: usize) -> usize {
     column_count / 8 + if column_count % 8 == 0 { 0 } else { 1 }
 }
@@ -45,6 +85,19 @@ pub const fn check_existence_bitmap(column: usize, existence_bitmap: &[u8]) -> L
     Ok(existence_bitmap[column / 8] & (1_u8 << (7 - (column % 8))) != 0)
 }
 
+/// Where the row matrix of a [`TableContextInfo`] is stored, according to `hnidRows`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TableRowStorage {
+    /// The table has no rows.
+    Empty,
+    /// The row matrix fits in a single heap allocation.
+    Heap(HeapId),
+    /// The row matrix is the data tree of a sub-node, with a whole number of rows in each block.
+    /// Tables which are too large for the heap, e.g. the contents tables of large folders, are
+    /// stored this way.
+    SubNode(NodeId),
+}
+
 /// [TCINFO](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/45b3a0c5-d6d6-4e02-aebf-13766ff693f0)
 #[derive(Clone, Default, Debug)]
 pub struct TableContextInfo {
@@ -273,6 +326,41 @@ impl TableContextInfo {
     pub fn columns(&self) -> &[TableColumnDescriptor] {
         &self.columns
     }
+
+    /// Size of each row in the row matrix.
+    pub fn row_size(&self) -> usize {
+        usize::from(self.end_existence_bitmap)
+    }
+
+    /// Whether the row matrix is on the heap or in a sub-node. Every [`TableContext`] reads both
+    /// into the same [`TableContext::rows_matrix`].
+    pub fn row_storage(&self) -> TableRowStorage {
+        match self.rows {
+            None => TableRowStorage::Empty,
+            Some(rows) => match rows.id_type() {
+                Ok(NodeIdType::HeapNode) => TableRowStorage::Heap(HeapId::from(u32::from(rows))),
+                _ => TableRowStorage::SubNode(rows),
+            },
+        }
+    }
+
+    /// Split the blocks of the row matrix into rows. Rows never span two blocks, but a block may
+    /// be padded after the last row which fits in it.
+    fn read_rows(&self, blocks: Vec<Vec<u8>>) -> io::Result<Vec<TableRowData>> {
+        let row_size = self.row_size();
+        if row_size == 0 {
+            return Ok(Default::default());
+        }
+
+        let mut rows = Vec::new();
+        for block in blocks {
+            let mut cursor = Cursor::new(block.as_slice());
+            for _ in 0..block.len() / row_size {
+                rows.push(TableRowData::read(&mut cursor, self)?);
+            }
+        }
+        Ok(rows)
+    }
 }
 
 impl TableContextInfoReadWrite for TableContextInfo {
@@ -595,6 +683,23 @@ impl TableRowData {
         self.unique
     }
 
+    /// [`Self::unique`] as a [`RowVersion`].
+    pub fn version(&self) -> RowVersion {
+        RowVersion(self.unique)
+    }
+
+    pub fn existence_bitmap(&self) -> &[u8] {
+        &self.existence_bitmap
+    }
+
+    /// Check the existence bitmap for the column without reading its value.
+    pub fn column_exists(&self, column: &TableColumnDescriptor) -> LtpResult<bool> {
+        check_existence_bitmap(
+            column.existence_bitmap_index() as usize,
+            &self.existence_bitmap,
+        )
+    }
+
     pub fn columns(
         &self,
         context: &TableContextInfo,
@@ -602,130 +707,133 @@ impl TableRowData {
         context
             .columns()
             .iter()
-            .map(|column| {
-                let existence_bit = column.existence_bitmap_index() as usize;
-                if !check_existence_bitmap(existence_bit, &self.existence_bitmap)? {
-                    return Ok(None);
-                }
+            .map(|column| self.column(context, column))
+            .collect()
+    }
 
-                match (column.prop_type(), column.offset(), column.size()) {
-                    (PropertyType::Null, _, 0) => Ok(None),
-                    (PropertyType::Integer16, offset, 2) => {
-                        let mut cursor = self.read_2byte_offset(context, offset)?;
-                        let value = cursor.read_i16::<LittleEndian>()?;
-                        Ok(Some(TableRowColumnValue::Small(PropertyValue::Integer16(
-                            value,
-                        ))))
-                    }
-                    (PropertyType::Integer32, 0, 4) => Ok(Some(TableRowColumnValue::Small(
-                        PropertyValue::Integer32(u32::from(self.id) as i32),
-                    ))),
-                    (PropertyType::Integer32, 4, 4) => Ok(Some(TableRowColumnValue::Small(
-                        PropertyValue::Integer32(self.unique as i32),
-                    ))),
-                    (PropertyType::Integer32, offset, 4) => {
-                        let mut cursor = self.read_4byte_offset(offset)?;
-                        let value = cursor.read_i32::<LittleEndian>()?;
-                        Ok(Some(TableRowColumnValue::Small(PropertyValue::Integer32(
-                            value,
-                        ))))
-                    }
-                    (PropertyType::Floating32, offset, 4) => {
-                        let mut cursor = self.read_4byte_offset(offset)?;
-                        let value = cursor.read_f32::<LittleEndian>()?;
-                        Ok(Some(TableRowColumnValue::Small(PropertyValue::Floating32(
-                            value,
-                        ))))
-                    }
-                    (PropertyType::Floating64, offset, 8) => {
-                        let mut cursor = self.read_8byte_offset(offset)?;
-                        let value = cursor.read_f64::<LittleEndian>()?;
-                        Ok(Some(TableRowColumnValue::Small(PropertyValue::Floating64(
-                            value,
-                        ))))
-                    }
-                    (PropertyType::Currency, offset, 8) => {
-                        let mut cursor = self.read_8byte_offset(offset)?;
-                        let value = cursor.read_i64::<LittleEndian>()?;
-                        Ok(Some(TableRowColumnValue::Small(PropertyValue::Currency(
-                            value,
-                        ))))
-                    }
-                    (PropertyType::FloatingTime, offset, 8) => {
-                        let mut cursor = self.read_8byte_offset(offset)?;
-                        let value = cursor.read_f64::<LittleEndian>()?;
-                        Ok(Some(TableRowColumnValue::Small(
-                            PropertyValue::FloatingTime(value),
-                        )))
-                    }
-                    (PropertyType::ErrorCode, offset, 4) => {
-                        let mut cursor = self.read_4byte_offset(offset)?;
-                        let value = cursor.read_i32::<LittleEndian>()?;
-                        Ok(Some(TableRowColumnValue::Small(PropertyValue::ErrorCode(
-                            value,
-                        ))))
-                    }
-                    (PropertyType::Boolean, offset, 1) => {
-                        let value = self.read_1byte_offset(context, offset)?;
-                        Ok(Some(TableRowColumnValue::Small(PropertyValue::Boolean(
-                            match value {
-                                0x00 => false,
-                                0x01 => true,
-                                _ => {
-                                    return Err(
-                                        LtpError::InvalidTableColumnBooleanValue(value).into()
-                                    )
-                                }
-                            },
-                        ))))
-                    }
-                    (PropertyType::Integer64, offset, 8) => {
-                        let mut cursor = self.read_8byte_offset(offset)?;
-                        let value = cursor.read_i64::<LittleEndian>()?;
-                        Ok(Some(TableRowColumnValue::Small(PropertyValue::Integer64(
-                            value,
-                        ))))
-                    }
-                    (PropertyType::Time, offset, 8) => {
-                        let mut cursor = self.read_8byte_offset(offset)?;
-                        let value = cursor.read_i64::<LittleEndian>()?;
-                        Ok(Some(TableRowColumnValue::Small(PropertyValue::Time(value))))
-                    }
-                    (
-                        PropertyType::String8
-                        | PropertyType::Unicode
-                        | PropertyType::Guid
-                        | PropertyType::Binary
-                        | PropertyType::Object
-                        | PropertyType::MultipleInteger16
-                        | PropertyType::MultipleInteger32
-                        | PropertyType::MultipleFloating32
-                        | PropertyType::MultipleFloating64
-                        | PropertyType::MultipleCurrency
-                        | PropertyType::MultipleFloatingTime
-                        | PropertyType::MultipleInteger64
-                        | PropertyType::MultipleString8
-                        | PropertyType::MultipleUnicode
-                        | PropertyType::MultipleTime
-                        | PropertyType::MultipleGuid
-                        | PropertyType::MultipleBinary,
-                        offset,
-                        4,
-                    ) => {
-                        let mut cursor = self.read_4byte_offset(offset)?;
-                        let node_id = NodeId::from(cursor.read_u32::<LittleEndian>()?);
-                        let value = match node_id.id_type() {
-                            Ok(NodeIdType::HeapNode) => {
-                                TableRowColumnValue::Heap(HeapId::from(u32::from(node_id)))
-                            }
-                            _ => TableRowColumnValue::Node(node_id),
-                        };
-                        Ok(Some(value))
+    /// Read the value of a single column, without collecting the rest of the row.
+    pub fn column(
+        &self,
+        context: &TableContextInfo,
+        column: &TableColumnDescriptor,
+    ) -> io::Result<Option<TableRowColumnValue>> {
+        let existence_bit = column.existence_bitmap_index() as usize;
+        if !check_existence_bitmap(existence_bit, &self.existence_bitmap)? {
+            return Ok(None);
+        }
+
+        match (column.prop_type(), column.offset(), column.size()) {
+            (PropertyType::Null, _, 0) => Ok(None),
+            (PropertyType::Integer16, offset, 2) => {
+                let mut cursor = self.read_2byte_offset(context, offset)?;
+                let value = cursor.read_i16::<LittleEndian>()?;
+                Ok(Some(TableRowColumnValue::Small(PropertyValue::Integer16(
+                    value,
+                ))))
+            }
+            (PropertyType::Integer32, 0, 4) => Ok(Some(TableRowColumnValue::Small(
+                PropertyValue::Integer32(u32::from(self.id) as i32),
+            ))),
+            (PropertyType::Integer32, 4, 4) => Ok(Some(TableRowColumnValue::Small(
+                PropertyValue::Integer32(self.unique as i32),
+            ))),
+            (PropertyType::Integer32, offset, 4) => {
+                let mut cursor = self.read_4byte_offset(offset)?;
+                let value = cursor.read_i32::<LittleEndian>()?;
+                Ok(Some(TableRowColumnValue::Small(PropertyValue::Integer32(
+                    value,
+                ))))
+            }
+            (PropertyType::Floating32, offset, 4) => {
+                let mut cursor = self.read_4byte_offset(offset)?;
+                let value = cursor.read_f32::<LittleEndian>()?;
+                Ok(Some(TableRowColumnValue::Small(PropertyValue::Floating32(
+                    value,
+                ))))
+            }
+            (PropertyType::Floating64, offset, 8) => {
+                let mut cursor = self.read_8byte_offset(offset)?;
+                let value = cursor.read_f64::<LittleEndian>()?;
+                Ok(Some(TableRowColumnValue::Small(PropertyValue::Floating64(
+                    value,
+                ))))
+            }
+            (PropertyType::Currency, offset, 8) => {
+                let mut cursor = self.read_8byte_offset(offset)?;
+                let value = cursor.read_i64::<LittleEndian>()?;
+                Ok(Some(TableRowColumnValue::Small(PropertyValue::Currency(
+                    value,
+                ))))
+            }
+            (PropertyType::FloatingTime, offset, 8) => {
+                let mut cursor = self.read_8byte_offset(offset)?;
+                let value = cursor.read_f64::<LittleEndian>()?;
+                Ok(Some(TableRowColumnValue::Small(
+                    PropertyValue::FloatingTime(value),
+                )))
+            }
+            (PropertyType::ErrorCode, offset, 4) => {
+                let mut cursor = self.read_4byte_offset(offset)?;
+                let value = cursor.read_i32::<LittleEndian>()?;
+                Ok(Some(TableRowColumnValue::Small(PropertyValue::ErrorCode(
+                    value,
+                ))))
+            }
+            (PropertyType::Boolean, offset, 1) => {
+                let value = self.read_1byte_offset(context, offset)?;
+                Ok(Some(TableRowColumnValue::Small(PropertyValue::Boolean(
+                    match value {
+                        0x00 => false,
+                        0x01 => true,
+                        _ => return Err(LtpError::InvalidTableColumnBooleanValue(value).into()),
+                    },
+                ))))
+            }
+            (PropertyType::Integer64, offset, 8) => {
+                let mut cursor = self.read_8byte_offset(offset)?;
+                let value = cursor.read_i64::<LittleEndian>()?;
+                Ok(Some(TableRowColumnValue::Small(PropertyValue::Integer64(
+                    value,
+                ))))
+            }
+            (PropertyType::Time, offset, 8) => {
+                let mut cursor = self.read_8byte_offset(offset)?;
+                let value = cursor.read_i64::<LittleEndian>()?;
+                Ok(Some(TableRowColumnValue::Small(PropertyValue::Time(value))))
+            }
+            (
+                PropertyType::String8
+                | PropertyType::Unicode
+                | PropertyType::Guid
+                | PropertyType::Binary
+                | PropertyType::Object
+                | PropertyType::MultipleInteger16
+                | PropertyType::MultipleInteger32
+                | PropertyType::MultipleFloating32
+                | PropertyType::MultipleFloating64
+                | PropertyType::MultipleCurrency
+                | PropertyType::MultipleFloatingTime
+                | PropertyType::MultipleInteger64
+                | PropertyType::MultipleString8
+                | PropertyType::MultipleUnicode
+                | PropertyType::MultipleTime
+                | PropertyType::MultipleGuid
+                | PropertyType::MultipleBinary,
+                offset,
+                4,
+            ) => {
+                let mut cursor = self.read_4byte_offset(offset)?;
+                let node_id = NodeId::from(cursor.read_u32::<LittleEndian>()?);
+                let value = match node_id.id_type() {
+                    Ok(NodeIdType::HeapNode) => {
+                        TableRowColumnValue::Heap(HeapId::from(u32::from(node_id)))
                     }
-                    (_, _, size) => Err(LtpError::InvalidTableColumnSize(size).into()),
-                }
-            })
-            .collect()
+                    _ => TableRowColumnValue::Node(node_id),
+                };
+                Ok(Some(value))
+            }
+            (_, _, size) => Err(LtpError::InvalidTableColumnSize(size).into()),
+        }
     }
 
     fn read_1byte_offset(&self, context: &TableContextInfo, offset: u16) -> LtpResult<u8> {
@@ -773,6 +881,52 @@ impl TableRowData {
     }
 }
 
+/// A borrowed row of a [`TableContext`], passed to [`TableContext::for_each_row`]. Column values
+/// are decoded on demand from the row data, so visiting a row does not allocate.
+#[derive(Clone, Copy)]
+pub struct TableRowView<'a> {
+    context: &'a TableContextInfo,
+    row: &'a TableRowData,
+}
+
+impl<'a> TableRowView<'a> {
+    pub fn new(context: &'a TableContextInfo, row: &'a TableRowData) -> Self {
+        Self { context, row }
+    }
+
+    pub fn id(&self) -> TableRowId {
+        self.row.id()
+    }
+
+    pub fn row(&self) -> &'a TableRowData {
+        self.row
+    }
+
+    /// Read the value of the column at `index` in [`TableContextInfo::columns`].
+    pub fn column(&self, index: usize) -> io::Result<Option<TableRowColumnValue>> {
+        let column = self
+            .context
+            .columns()
+            .get(index)
+            .ok_or(LtpError::InvalidTableColumnIndex(index))?;
+        self.row.column(self.context, column)
+    }
+
+    /// Read the value of the column with `prop_id`, or [`None`] if the table has no such column
+    /// or the row does not have a value for it.
+    pub fn column_by_prop_id(&self, prop_id: u16) -> io::Result<Option<TableRowColumnValue>> {
+        match self
+            .context
+            .columns()
+            .iter()
+            .find(|column| column.prop_id() == prop_id)
+        {
+            Some(column) => self.row.column(self.context, column),
+            None => Ok(None),
+        }
+    }
+}
+
 impl TableRowReadWrite for TableRowData {
     fn read(f: &mut dyn Read, context: &TableContextInfo) -> io::Result<Self> {
         // dwRowID
@@ -819,8 +973,401 @@ impl TableRowReadWrite for TableRowData {
     }
 }
 
+/// A column of a [`TableContext`] with the canonical name of its property, if it is known.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableColumnSchema {
+    prop_id: u16,
+    prop_type: PropertyType,
+    name: Option<String>,
+}
+
+impl TableColumnSchema {
+    pub fn new(prop_id: u16, prop_type: PropertyType, name: Option<String>) -> Self {
+        Self {
+            prop_id,
+            prop_type,
+            name,
+        }
+    }
+
+    pub fn prop_id(&self) -> u16 {
+        self.prop_id
+    }
+
+    pub fn prop_type(&self) -> PropertyType {
+        self.prop_type
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn set_name(&mut self, name: String) {
+        self.name = Some(name);
+    }
+}
+
+impl From<&TableColumnDescriptor> for TableColumnSchema {
+    fn from(column: &TableColumnDescriptor) -> Self {
+        Self::new(
+            column.prop_id(),
+            column.prop_type(),
+            property_tag_name(column.prop_id()).map(String::from),
+        )
+    }
+}
+
+/// Number of rows in a [`TableContext`] which have a value for a column, according to their
+/// existence bitmaps.
+#[derive(Clone, Copy, Debug)]
+pub struct TableColumnDensity {
+    column: TableColumnDescriptor,
+    present_count: usize,
+    row_count: usize,
+}
+
+impl TableColumnDensity {
+    pub fn column(&self) -> &TableColumnDescriptor {
+        &self.column
+    }
+
+    pub fn present_count(&self) -> usize {
+        self.present_count
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.row_count
+    }
+
+    /// Fraction of the rows which have a value for the column, or `0.0` if the table is empty.
+    pub fn density(&self) -> f64 {
+        if self.row_count == 0 {
+            0.0
+        } else {
+            self.present_count as f64 / self.row_count as f64
+        }
+    }
+}
+
+/// Rows of a TC keyed by their `dwRowID`, with the value of each column that exists in the row.
+pub type TableRows = BTreeMap<TableRowId, BTreeMap<u16, PropertyValue>>;
+
+/// Serialize the `rows` of a new TC, keyed by their `dwRowID`. The `columns` are in addition to
+/// `PidTagLtpRowId` and `PidTagLtpRowVer`, which every TC has, and `PidTagLtpRowVer` is taken from
+/// the row's properties if it is there.
+///
+/// Values for properties which are not in `columns` are ignored. Variable length values and the
+/// row matrix are allocated on the heap when they fit in [`MAX_HEAP_ALLOCATION_SIZE`], otherwise
+/// they are stored in sub-nodes of the TC. A row matrix in a sub-node is split into blocks
+/// without any rows spanning two blocks, which is why this depends on the `version`.
+pub fn build_table_context(
+    version: NdbVersion,
+    columns: &[(u16, PropertyType)],
+    rows: &TableRows,
+) -> io::Result<NodeData> {
+    let columns: BTreeMap<_, _> = columns
+        .iter()
+        .copied()
+        .filter(|(prop_id, _)| ![LTP_ROW_ID_PROP_ID, LTP_ROW_VERSION_PROP_ID].contains(prop_id))
+        .collect();
+
+    // Lay out the rgdwData values with the 8 byte values first, then rgwData and rgbData.
+    let size_of = |prop_type| match prop_type {
+        PropertyType::Floating64
+        | PropertyType::Currency
+        | PropertyType::FloatingTime
+        | PropertyType::Integer64
+        | PropertyType::Time => Ok(8),
+        PropertyType::Integer16 => Ok(2),
+        PropertyType::Boolean => Ok(1),
+        PropertyType::Null => Err(LtpError::InvalidTableColumnPropertyType(prop_type)),
+        _ => Ok(4),
+    };
+    let mut layout = columns
+        .iter()
+        .map(|(&prop_id, &prop_type)| Ok((prop_id, prop_type, size_of(prop_type)?)))
+        .collect::<LtpResult<Vec<_>>>()?;
+    layout.sort_by_key(|(_, _, size)| std::cmp::Reverse(*size));
+
+    let mut descriptors = vec![
+        TableColumnDescriptor::new(PropertyType::Integer32, LTP_ROW_ID_PROP_ID, 0, 4, 0),
+        TableColumnDescriptor::new(PropertyType::Integer32, LTP_ROW_VERSION_PROP_ID, 4, 4, 1),
+    ];
+    let (mut offset, mut end_4byte_values, mut end_2byte_values) = (8_u16, 8_u16, 8_u16);
+    for (index, (prop_id, prop_type, size)) in layout.into_iter().enumerate() {
+        let existence_bitmap_index = u8::try_from(index + 2)
+            .map_err(|_| LtpError::InvalidTableContextColumnCount(index + 2))?;
+        descriptors.push(TableColumnDescriptor::new(
+            prop_type,
+            prop_id,
+            offset,
+            size,
+            existence_bitmap_index,
+        ));
+        offset += u16::from(size);
+        if size >= 4 {
+            end_4byte_values = offset;
+        }
+        if size >= 2 {
+            end_2byte_values = offset;
+        }
+    }
+    descriptors.sort_by_key(TableColumnDescriptor::prop_id);
+    let end_1byte_values = offset;
+    let end_existence_bitmap = end_1byte_values + existence_bitmap_size(descriptors.len()) as u16;
+
+    let mut heap = HeapNodeBuilder::new(HeapNodeType::Table);
+    let mut sub_nodes = BTreeMap::new();
+    let next_sub_node = |sub_nodes: &BTreeMap<NodeId, NodeData>| {
+        NodeId::new(
+            NodeIdType::ListsTablesProperties,
+            FIRST_LOCAL_NODE_INDEX + sub_nodes.len() as u32,
+        )
+    };
+
+    let row_size = usize::from(end_existence_bitmap);
+    let mut row_matrix = Vec::with_capacity(rows.len() * row_size);
+    let mut row_index = vec![];
+    for (index, (&id, values)) in rows.iter().enumerate() {
+        let mut row = vec![0_u8; row_size];
+        let unique = match values.get(&LTP_ROW_VERSION_PROP_ID) {
+            Some(PropertyValue::Integer32(unique)) => *unique as u32,
+            _ => 0,
+        };
+        row[..4].copy_from_slice(&u32::from(id).to_le_bytes());
+        row[4..8].copy_from_slice(&unique.to_le_bytes());
+        row[usize::from(end_1byte_values)] |= 0xC0;
+
+        for column in descriptors.iter() {
+            let Some(value) = values.get(&column.prop_id()).filter(|_| {
+                ![LTP_ROW_ID_PROP_ID, LTP_ROW_VERSION_PROP_ID].contains(&column.prop_id())
+            }) else {
+                continue;
+            };
+            if PropertyType::from(value) != column.prop_type() {
+                return Err(LtpError::InvalidTableColumnValue(
+                    column.prop_id(),
+                    PropertyType::from(value),
+                )
+                .into());
+            }
+
+            let data = match value {
+                PropertyValue::Integer16(value) => value.to_le_bytes().to_vec(),
+                PropertyValue::Integer32(value) => value.to_le_bytes().to_vec(),
+                PropertyValue::Floating32(value) => value.to_le_bytes().to_vec(),
+                PropertyValue::Floating64(value) => value.to_le_bytes().to_vec(),
+                PropertyValue::Currency(value) => value.to_le_bytes().to_vec(),
+                PropertyValue::FloatingTime(value) => value.to_le_bytes().to_vec(),
+                PropertyValue::ErrorCode(value) => value.to_le_bytes().to_vec(),
+                PropertyValue::Boolean(value) => vec![u8::from(*value)],
+                PropertyValue::Integer64(value) => value.to_le_bytes().to_vec(),
+                PropertyValue::Time(value) => value.to_le_bytes().to_vec(),
+                value => {
+                    let mut data = vec![];
+                    value.write(&mut data)?;
+                    let hnid = if data.is_empty() {
+                        0
+                    } else if data.len() <= MAX_HEAP_ALLOCATION_SIZE {
+                        u32::from(heap.allocate(&data)?)
+                    } else {
+                        let sub_node = next_sub_node(&sub_nodes)?;
+                        sub_nodes.insert(sub_node, NodeData::from_bytes(&data));
+                        u32::from(sub_node)
+                    };
+                    hnid.to_le_bytes().to_vec()
+                }
+            };
+
+            let offset = usize::from(column.offset());
+            row[offset..offset + data.len()].copy_from_slice(&data);
+            let bit = usize::from(column.existence_bitmap_index());
+            row[usize::from(end_1byte_values) + bit / 8] |= 1_u8 << (7 - (bit % 8));
+        }
+
+        row_matrix.extend(row);
+        row_index.push((id, index));
+    }
+
+    let rows = if row_matrix.is_empty() {
+        None
+    } else if row_matrix.len() <= MAX_HEAP_ALLOCATION_SIZE {
+        Some(NodeId::from(u32::from(heap.allocate(&row_matrix)?)))
+    } else {
+        let rows_per_block = usize::from(max_block_data_size(version)) / row_size;
+        let blocks = row_matrix
+            .chunks(rows_per_block * row_size)
+            .map(<[u8]>::to_vec)
+            .collect();
+        let sub_node = next_sub_node(&sub_nodes)?;
+        sub_nodes.insert(sub_node, NodeData::new(blocks));
+        Some(sub_node)
+    };
+
+    let row_index = match version {
+        NdbVersion::Unicode => {
+            let entries = row_index
+                .into_iter()
+                .map(|(id, index)| {
+                    let index = u32::try_from(index)
+                        .map_err(|_| LtpError::InvalidTableContextRowIndex(index))?;
+                    Ok(UnicodeTableRowIdRecord::new(
+                        id,
+                        UnicodeTableRowIndex { index },
+                    ))
+                })
+                .collect::<LtpResult<Vec<_>>>()?;
+            build_heap_tree(&mut heap, &entries)?
+        }
+        NdbVersion::Ansi => {
+            let entries = row_index
+                .into_iter()
+                .map(|(id, index)| {
+                    let index = u16::try_from(index)
+                        .map_err(|_| LtpError::InvalidTableContextRowIndex(index))?;
+                    Ok(AnsiTableRowIdRecord::new(id, AnsiTableRowIndex { index }))
+                })
+                .collect::<LtpResult<Vec<_>>>()?;
+            build_heap_tree(&mut heap, &entries)?
+        }
+    };
+
+    let context = TableContextInfo::new(
+        end_4byte_values,
+        end_2byte_values,
+        end_1byte_values,
+        end_existence_bitmap,
+        row_index,
+        rows,
+        descriptors,
+    )?;
+    let mut data = vec![];
+    context.write(&mut data)?;
+    let user_root = heap.allocate(&data)?;
+    heap.set_user_root(user_root);
+
+    let mut data = NodeData::new(heap.build_blocks()?);
+    for (sub_node, value) in sub_nodes {
+        data.insert_sub_node(sub_node, value);
+    }
+    Ok(data)
+}
+
+/// Decode the columns and every row of a TC which has already been read into memory, including
+/// values stored in its sub-nodes. The columns do not include `PidTagLtpRowId` or
+/// `PidTagLtpRowVer`, but each row has its `PidTagLtpRowVer`, so the result can be passed back to
+/// [`build_table_context`].
+pub fn read_table_context(data: &NodeData) -> io::Result<(Vec<(u16, PropertyType)>, TableRows)> {
+    let heap = HeapNodeBlocks::new(data.blocks());
+    let header = heap.header()?;
+    if header.client_signature() != HeapNodeType::Table {
+        return Err(LtpError::InvalidHeapNodeTypeSignature(header.client_signature() as u8).into());
+    }
+    let context = TableContextInfo::read(&mut Cursor::new(heap.find_entry(header.user_root())?))?;
+
+    let mut rows = TableRows::new();
+    for row in context.read_rows(row_matrix_blocks(&context, &heap, data)?)? {
+        let mut values = BTreeMap::new();
+        for (column, value) in context.columns().iter().zip(row.columns(&context)?) {
+            let value = match value {
+                None => continue,
+                Some(TableRowColumnValue::Small(value)) => value,
+                Some(TableRowColumnValue::Heap(heap_id)) if u32::from(heap_id) == 0 => {
+                    PropertyValue::read(&mut io::empty(), column.prop_type())
+                        .unwrap_or(PropertyValue::Null)
+                }
+                Some(TableRowColumnValue::Heap(heap_id)) => {
+                    PropertyValue::read(&mut heap.find_entry(heap_id)?, column.prop_type())?
+                }
+                Some(TableRowColumnValue::Node(sub_node)) => {
+                    let value = data
+                        .sub_node(sub_node)
+                        .ok_or(LtpError::PropertySubNodeValueNotFound(u32::from(sub_node)))?;
+                    PropertyValue::read(&mut value.data().as_slice(), column.prop_type())?
+                }
+            };
+            if column.prop_id() != LTP_ROW_ID_PROP_ID {
+                values.insert(column.prop_id(), value);
+            }
+        }
+        rows.insert(row.id(), values);
+    }
+
+    let columns = context
+        .columns()
+        .iter()
+        .filter(|column| ![LTP_ROW_ID_PROP_ID, LTP_ROW_VERSION_PROP_ID].contains(&column.prop_id()))
+        .map(|column| (column.prop_id(), column.prop_type()))
+        .collect();
+    Ok((columns, rows))
+}
+
+/// The blocks of the row matrix of a TC which has already been read into memory.
+fn row_matrix_blocks(
+    context: &TableContextInfo,
+    heap: &HeapNodeBlocks,
+    data: &NodeData,
+) -> io::Result<Vec<Vec<u8>>> {
+    Ok(match context.row_storage() {
+        TableRowStorage::Empty => vec![],
+        TableRowStorage::Heap(heap_id) => vec![heap.find_entry(heap_id)?.to_vec()],
+        TableRowStorage::SubNode(sub_node) => data
+            .sub_node(sub_node)
+            .ok_or(LtpError::PropertySubNodeValueNotFound(u32::from(sub_node)))?
+            .blocks()
+            .to_vec(),
+    })
+}
+
+/// Check the heap of a TC which has already been read into memory, the order of the keys in its
+/// row index, that every `TCROWID` points at the row with the same `dwRowID`, and that every
+/// value in the row matrix can be decoded. The `TCINFO` offsets are checked when it is read.
+pub fn validate_table_context(version: NdbVersion, data: &NodeData) -> io::Result<()> {
+    let heap = HeapNodeBlocks::new(data.blocks());
+    heap.validate()?;
+    let header = heap.header()?;
+    if header.client_signature() != HeapNodeType::Table {
+        return Err(LtpError::InvalidHeapNodeTypeSignature(header.client_signature() as u8).into());
+    }
+    let context = TableContextInfo::read(&mut Cursor::new(heap.find_entry(header.user_root())?))?;
+
+    let row_index: Vec<_> = match version {
+        NdbVersion::Unicode => {
+            validate_heap_tree::<TableRowId, UnicodeTableRowIndex>(&heap, context.row_index)?
+                .into_iter()
+                .map(|entry| (entry.key(), u32::from(entry.data())))
+                .collect()
+        }
+        NdbVersion::Ansi => {
+            validate_heap_tree::<TableRowId, AnsiTableRowIndex>(&heap, context.row_index)?
+                .into_iter()
+                .map(|entry| (entry.key(), u32::from(entry.data())))
+                .collect()
+        }
+    };
+
+    let rows = context.read_rows(row_matrix_blocks(&context, &heap, data)?)?;
+    if row_index.len() != rows.len() {
+        return Err(LtpError::InvalidTableContextRowCount(row_index.len(), rows.len()).into());
+    }
+    for (id, index) in row_index {
+        let index = index as usize;
+        let row = rows
+            .get(index)
+            .ok_or(LtpError::InvalidTableContextRowIndex(index))?;
+        if row.id() != id {
+            return Err(LtpError::MismatchedTableRowIndex(u32::from(id), index).into());
+        }
+    }
+
+    read_table_context(data)?;
+    Ok(())
+}
+
 pub trait TableContext {
     fn context(&self) -> &TableContextInfo;
+    /// Every row in `dwRowIndex` order, whether the row matrix is stored on the heap or in a
+    /// sub-node, see [`TableContextInfo::row_storage`].
     fn rows_matrix<'a>(&'a self) -> Box<dyn 'a + Iterator<Item = &'a TableRowData>>;
     fn find_row(&self, id: TableRowId) -> LtpResult<&TableRowData>;
     fn read_column(
@@ -828,6 +1375,94 @@ pub trait TableContext {
         value: &TableRowColumnValue,
         prop_type: PropertyType,
     ) -> io::Result<PropertyValue>;
+
+    /// The rows whose [`RowVersion`] is greater than `version`, in `dwRowIndex` order. Rows which
+    /// were removed since then are not reported, so compare the [`TableRowId`] values with the
+    /// previous listing to find them.
+    fn rows_changed_since<'a>(
+        &'a self,
+        version: RowVersion,
+    ) -> Box<dyn 'a + Iterator<Item = &'a TableRowData>> {
+        Box::new(
+            self.rows_matrix()
+                .filter(move |row| row.version() > version),
+        )
+    }
+
+    /// Like [`TableContext::read_column`], but values stored in the heap or a sub-node are returned
+    /// as [`MaybeDecoded::Raw`] bytes, so list views only pay for decoding the cells they show.
+    fn read_column_lazy(
+        &self,
+        value: &TableRowColumnValue,
+        prop_type: PropertyType,
+    ) -> io::Result<MaybeDecoded<PropertyValue>>;
+
+    /// Like [`TableContext::read_column`], but with [`HeapIdMode::Lenient`] a value whose HID is
+    /// dangling is returned as [`None`].
+    fn read_column_with_mode(
+        &self,
+        value: &TableRowColumnValue,
+        prop_type: PropertyType,
+        mode: HeapIdMode,
+    ) -> io::Result<Option<PropertyValue>> {
+        match self.read_column(value, prop_type) {
+            Ok(value) => Ok(Some(value)),
+            Err(err) if mode == HeapIdMode::Lenient && is_dangling_heap_id(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Visit every row in `dwRowIndex` order. Unlike [`TableRowData::columns`], the
+    /// [`TableRowView`] only decodes the columns which `f` asks for, and nothing is allocated per
+    /// row. Iteration stops at the first error returned by `f`.
+    fn for_each_row(
+        &self,
+        f: &mut dyn FnMut(TableRowView<'_>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let context = self.context();
+        for row in self.rows_matrix() {
+            f(TableRowView::new(context, row))?;
+        }
+        Ok(())
+    }
+
+    /// List the columns with the canonical names of tagged properties. Named properties are left
+    /// without a name, use
+    /// [`NamedPropertyMapProperties::resolve_schema`](crate::messaging::named_prop::NamedPropertyMapProperties::resolve_schema)
+    /// to fill them in from the store's named property map.
+    fn schema(&self) -> Vec<TableColumnSchema> {
+        self.context()
+            .columns()
+            .iter()
+            .map(TableColumnSchema::from)
+            .collect()
+    }
+
+    /// Count the rows which have a value in each column from the existence bitmaps alone, without
+    /// reading any of the cell data.
+    fn column_density(&self) -> LtpResult<Vec<TableColumnDensity>> {
+        let mut densities: Vec<_> = self
+            .context()
+            .columns()
+            .iter()
+            .map(|column| TableColumnDensity {
+                column: *column,
+                present_count: 0,
+                row_count: 0,
+            })
+            .collect();
+
+        for row in self.rows_matrix() {
+            for density in densities.iter_mut() {
+                density.row_count += 1;
+                if row.column_exists(&density.column)? {
+                    density.present_count += 1;
+                }
+            }
+        }
+
+        Ok(densities)
+    }
 }
 
 struct TableContextInner<Pst, RowIndex, RowIndexTree>
@@ -885,12 +1520,8 @@ where
         store: Rc<<Pst as PstFile>::Store>,
         node: <Pst as PstFile>::NodeBTreeEntry,
     ) -> io::Result<Self> {
-        let mut file = store
-            .pst()
-            .reader()
-            .lock()
-            .map_err(|_| LtpError::FailedToLockFile)?;
-        let file = &mut *file;
+        let mut file = store.pst().reader();
+        let file = &mut file;
 
         let header = store.pst().header();
         let encoding = header.crypt_method();
@@ -914,62 +1545,39 @@ where
         let mut cursor = Cursor::new(heap.find_entry(header.user_root())?);
         let context = TableContextInfo::read(&mut cursor)?;
 
-        let rows = if let Some(rows) = context.rows {
-            match rows.id_type() {
-                Ok(NodeIdType::HeapNode) => {
-                    let rows: u32 = rows.into();
-                    vec![heap.find_entry(HeapId::from(rows))?.to_vec()]
-                }
-                _ => {
-                    let sub_node = node
-                        .sub_node()
-                        .ok_or(LtpError::PropertySubNodeValueNotFound(rows.into()))?;
-                    let block =
-                        block_btree.find_entry(file, sub_node.search_key(), &mut page_cache)?;
-                    let sub_node_tree = SubNodeTree::<Pst>::read(file, &block)?;
-                    let block =
-                        sub_node_tree.find_entry(file, &block_btree, rows, &mut page_cache)?;
-                    let block =
-                        block_btree.find_entry(file, block.search_key(), &mut page_cache)?;
-                    let data_tree = match block_cache.remove(&block.block().block()) {
-                        Some(data_tree) => data_tree,
-                        None => DataTree::read(file, encoding, &block)?,
-                    };
-                    let result = data_tree
-                        .blocks(
-                            file,
-                            encoding,
-                            &block_btree,
-                            &mut page_cache,
-                            &mut block_cache,
-                        )
-                        .map(|blocks| {
-                            blocks
-                                .map(|block| block.data().to_vec())
-                                .collect::<Vec<_>>()
-                        });
-                    block_cache.insert(block.block().block(), data_tree);
-                    result?
-                }
+        let blocks = match context.row_storage() {
+            TableRowStorage::Empty => vec![],
+            TableRowStorage::Heap(heap_id) => vec![heap.find_entry(heap_id)?.to_vec()],
+            TableRowStorage::SubNode(rows) => {
+                let sub_node = node
+                    .sub_node()
+                    .ok_or(LtpError::PropertySubNodeValueNotFound(rows.into()))?;
+                let block = block_btree.find_entry(file, sub_node.search_key(), &mut page_cache)?;
+                let sub_node_tree = SubNodeTree::<Pst>::read(file, &block)?;
+                let block = sub_node_tree.find_entry(file, &block_btree, rows, &mut page_cache)?;
+                let block = block_btree.find_entry(file, block.search_key(), &mut page_cache)?;
+                let data_tree = match block_cache.remove(&block.block().block()) {
+                    Some(data_tree) => data_tree,
+                    None => DataTree::read(file, encoding, &block)?,
+                };
+                let result = data_tree
+                    .blocks(
+                        file,
+                        encoding,
+                        &block_btree,
+                        &mut page_cache,
+                        &mut block_cache,
+                    )
+                    .map(|blocks| {
+                        blocks
+                            .map(|block| block.data().to_vec())
+                            .collect::<Vec<_>>()
+                    });
+                block_cache.insert(block.block().block(), data_tree);
+                result?
             }
-            .into_iter()
-            .map(|data| {
-                let row_count = data.len() / context.end_existence_bitmap() as usize;
-                let mut cursor = Cursor::new(data);
-                let mut rows = Vec::with_capacity(row_count);
-                for _ in 0..row_count {
-                    let row = TableRowData::read(&mut cursor, &context)?;
-                    rows.push(row);
-                }
-                Ok(rows)
-            })
-            .collect::<io::Result<Vec<_>>>()?
-            .into_iter()
-            .flatten()
-            .collect()
-        } else {
-            Default::default()
         };
+        let rows = context.read_rows(blocks)?;
 
         let row_index_tree = RowIndexTree::new(heap, context.row_index);
         let row_index = row_index_tree
@@ -991,26 +1599,43 @@ where
         })
     }
 
+    /// Look up the `dwRowIndex` of a row, which is its position in the whole row matrix, even if
+    /// the row matrix is split across the blocks of a sub-node.
+    fn find_row(&self, id: TableRowId) -> LtpResult<&TableRowData> {
+        let index = self
+            .row_index
+            .get(&id)
+            .ok_or(LtpError::TableRowIdNotFound(
+                <u32 as From<TableRowId>>::from(id),
+            ))?;
+        let index = <u32 as From<RowIndex>>::from(*index) as usize;
+        self.rows
+            .get(index)
+            .ok_or(LtpError::InvalidTableContextRowIndex(index))
+    }
+
     fn read_column(
         &self,
         value: &TableRowColumnValue,
         prop_type: PropertyType,
     ) -> io::Result<PropertyValue> {
+        self.read_column_lazy(value, prop_type)?.into_decoded()
+    }
+
+    fn read_column_lazy(
+        &self,
+        value: &TableRowColumnValue,
+        prop_type: PropertyType,
+    ) -> io::Result<MaybeDecoded<PropertyValue>> {
         match value {
-            TableRowColumnValue::Small(small) => Ok(small.clone()),
+            TableRowColumnValue::Small(small) => Ok(MaybeDecoded::Decoded(small.clone())),
             TableRowColumnValue::Heap(heap_id) => {
                 let data = self.heap.find_entry(*heap_id)?;
-                let mut cursor = Cursor::new(data);
-                PropertyValueReadWrite::read(&mut cursor, prop_type)
+                Ok(MaybeDecoded::raw(prop_type, data.to_vec()))
             }
             TableRowColumnValue::Node(sub_node_id) => {
-                let mut file = self
-                    .store
-                    .pst()
-                    .reader()
-                    .lock()
-                    .map_err(|_| LtpError::FailedToLockFile)?;
-                let file = &mut *file;
+                let mut file = self.store.pst().reader();
+                let file = &mut file;
 
                 let encoding = self.store.pst().header().crypt_method();
                 let block_btree = self.store.block_btree();
@@ -1040,9 +1665,13 @@ where
                         &mut page_cache,
                         &mut block_cache,
                     )
-                    .and_then(|mut r| PropertyValueReadWrite::read(&mut r, prop_type));
+                    .and_then(|mut r| {
+                        let mut data = vec![];
+                        r.read_to_end(&mut data)?;
+                        Ok(data)
+                    });
                 block_cache.insert(block.block().block(), data_tree);
-                result
+                Ok(MaybeDecoded::raw(prop_type, result?))
             }
         }
     }
@@ -1077,12 +1706,7 @@ impl TableContext for UnicodeTableContext {
     }
 
     fn find_row(&self, id: TableRowId) -> LtpResult<&TableRowData> {
-        let index = self
-            .inner
-            .row_index
-            .get(&id)
-            .ok_or(LtpError::TableRowIdNotFound(u32::from(id)))?;
-        Ok(&self.inner.rows[u32::from(*index) as usize])
+        self.inner.find_row(id)
     }
 
     fn read_column(
@@ -1092,6 +1716,14 @@ impl TableContext for UnicodeTableContext {
     ) -> io::Result<PropertyValue> {
         self.inner.read_column(value, prop_type)
     }
+
+    fn read_column_lazy(
+        &self,
+        value: &TableRowColumnValue,
+        prop_type: PropertyType,
+    ) -> io::Result<MaybeDecoded<PropertyValue>> {
+        self.inner.read_column_lazy(value, prop_type)
+    }
 }
 
 impl TableContextReadWrite<UnicodePstFile> for UnicodeTableContext {
@@ -1133,12 +1765,7 @@ impl TableContext for AnsiTableContext {
     }
 
     fn find_row(&self, id: TableRowId) -> LtpResult<&TableRowData> {
-        let index = self
-            .inner
-            .row_index
-            .get(&id)
-            .ok_or(LtpError::TableRowIdNotFound(u32::from(id)))?;
-        Ok(&self.inner.rows[u32::from(*index) as usize])
+        self.inner.find_row(id)
     }
 
     fn read_column(
@@ -1148,6 +1775,14 @@ impl TableContext for AnsiTableContext {
     ) -> io::Result<PropertyValue> {
         self.inner.read_column(value, prop_type)
     }
+
+    fn read_column_lazy(
+        &self,
+        value: &TableRowColumnValue,
+        prop_type: PropertyType,
+    ) -> io::Result<MaybeDecoded<PropertyValue>> {
+        self.inner.read_column_lazy(value, prop_type)
+    }
 }
 
 impl TableContextReadWrite<AnsiPstFile> for AnsiTableContext {
@@ -1156,3 +1791,166 @@ impl TableContextReadWrite<AnsiPstFile> for AnsiTableContext {
         Ok(Rc::new(Self { inner }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::message::unicode_value;
+
+    fn read_context_info(data: &NodeData) -> TableContextInfo {
+        let heap = HeapNodeBlocks::new(data.blocks());
+        let header = heap.header().unwrap();
+        TableContextInfo::read(&mut Cursor::new(
+            heap.find_entry(header.user_root()).unwrap(),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_row_storage() {
+        let columns = [
+            (0x0E08, PropertyType::Integer32),
+            (0x0037, PropertyType::Unicode),
+        ];
+        let make_rows = |count: u32| -> TableRows {
+            (0..count)
+                .map(|id| {
+                    (
+                        TableRowId::new(0x200024 + id * 0x20),
+                        BTreeMap::from([
+                            (0x0E08, PropertyValue::Integer32(id as i32)),
+                            (0x0037, unicode_value(&format!("Message {id}"))),
+                        ]),
+                    )
+                })
+                .collect()
+        };
+
+        for version in [NdbVersion::Unicode, NdbVersion::Ansi] {
+            for (count, heap) in [(0, None), (10, Some(true)), (1000, Some(false))] {
+                let rows = make_rows(count);
+                let data = build_table_context(version, &columns, &rows).unwrap();
+
+                let context = read_context_info(&data);
+                match (context.row_storage(), heap) {
+                    (TableRowStorage::Empty, None) => {}
+                    (TableRowStorage::Heap(_), Some(true)) => {}
+                    (TableRowStorage::SubNode(sub_node), Some(false)) => {
+                        assert!(data.sub_node(sub_node).unwrap().blocks().len() > 1);
+                    }
+                    (storage, _) => panic!("unexpected row storage for {count} rows: {storage:?}"),
+                }
+
+                let (_, read_rows) = read_table_context(&data).unwrap();
+                assert_eq!(read_rows.len(), rows.len());
+                for (id, values) in rows {
+                    let read_values = read_rows.get(&id).unwrap();
+                    let Some(PropertyValue::Integer32(index)) = values.get(&0x0E08) else {
+                        unreachable!();
+                    };
+                    assert!(matches!(
+                        read_values.get(&0x0E08),
+                        Some(PropertyValue::Integer32(value)) if value == index
+                    ));
+                    let Some(PropertyValue::Unicode(subject)) = read_values.get(&0x0037) else {
+                        panic!("missing PidTagSubject");
+                    };
+                    assert_eq!(subject.to_string(), format!("Message {index}"));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_row_view() {
+        let columns = [
+            (0x0E08, PropertyType::Integer32),
+            (0x0E1B, PropertyType::Boolean),
+        ];
+        let rows = TableRows::from([(
+            TableRowId::new(0x200024),
+            BTreeMap::from([(0x0E08, PropertyValue::Integer32(42))]),
+        )]);
+        let data = build_table_context(NdbVersion::Unicode, &columns, &rows).unwrap();
+        let heap = HeapNodeBlocks::new(data.blocks());
+        let context = read_context_info(&data);
+        let rows = context
+            .read_rows(row_matrix_blocks(&context, &heap, &data).unwrap())
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+
+        let row = TableRowView::new(&context, &rows[0]);
+        assert_eq!(row.id(), TableRowId::new(0x200024));
+        assert!(matches!(
+            row.column_by_prop_id(0x0E08).unwrap(),
+            Some(TableRowColumnValue::Small(PropertyValue::Integer32(42)))
+        ));
+        assert!(row.column_by_prop_id(0x0E1B).unwrap().is_none());
+        assert!(row.column_by_prop_id(0x0037).unwrap().is_none());
+
+        let index = context
+            .columns()
+            .iter()
+            .position(|column| column.prop_id() == 0x0E08)
+            .unwrap();
+        assert!(matches!(
+            row.column(index).unwrap(),
+            Some(TableRowColumnValue::Small(PropertyValue::Integer32(42)))
+        ));
+        assert!(row.column(context.columns().len()).is_err());
+    }
+
+    #[test]
+    fn test_row_version() {
+        let columns = [(0x0E08, PropertyType::Integer32)];
+        let rows = TableRows::from([
+            (
+                TableRowId::new(0x200024),
+                BTreeMap::from([(0x0E08, PropertyValue::Integer32(1))]),
+            ),
+            (
+                TableRowId::new(0x200044),
+                BTreeMap::from([
+                    (0x0E08, PropertyValue::Integer32(2)),
+                    (LTP_ROW_VERSION_PROP_ID, RowVersion::new(0x17).into()),
+                ]),
+            ),
+        ]);
+        let data = build_table_context(NdbVersion::Unicode, &columns, &rows).unwrap();
+        let heap = HeapNodeBlocks::new(data.blocks());
+        let context = read_context_info(&data);
+        let rows = context
+            .read_rows(row_matrix_blocks(&context, &heap, &data).unwrap())
+            .unwrap();
+        let versions: Vec<_> = rows.iter().map(TableRowData::version).collect();
+        assert_eq!(versions, [RowVersion::new(0), RowVersion::new(0x17)]);
+
+        let (_, read_rows) = read_table_context(&data).unwrap();
+        assert!(matches!(
+            read_rows[&TableRowId::new(0x200044)].get(&LTP_ROW_VERSION_PROP_ID),
+            Some(PropertyValue::Integer32(0x17))
+        ));
+
+        // Outlook stamps rows from the header's dwUnique, which is ahead of all of them.
+        use crate::messaging::store::{Store, UnicodeStore};
+        const EMPTY_PST: &[u8] = include_bytes!("../../examples/Empty.pst");
+        let pst = UnicodePstFile::read_from(Box::new(Cursor::new(EMPTY_PST))).unwrap();
+        let store = UnicodeStore::read(std::rc::Rc::new(pst)).unwrap();
+        let table = store.root_hierarchy_table().unwrap();
+        let latest = table
+            .rows_matrix()
+            .map(TableRowData::version)
+            .max()
+            .unwrap();
+        assert!(latest > RowVersion::default());
+        assert!(latest < store.row_version());
+        assert_eq!(table.rows_changed_since(latest).count(), 0);
+        assert_eq!(
+            table
+                .rows_changed_since(RowVersion::new(latest.value() - 1))
+                .map(TableRowData::version)
+                .collect::<Vec<_>>(),
+            [latest]
+        );
+    }
+}
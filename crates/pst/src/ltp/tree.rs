@@ -227,42 +227,130 @@ where
     }
 
     fn entries(&self) -> io::Result<Vec<HeapTreeLeafEntry<K, V>>> {
-        let header = self.header()?;
-        if header.key_size() != K::SIZE {
-            return Err(LtpError::InvalidHeapTreeKeySize(header.key_size()).into());
-        }
-        if header.entry_size() != V::SIZE {
-            return Err(LtpError::InvalidHeapTreeDataSize(header.entry_size()).into());
-        }
-
-        if u32::from(header.root()) == 0 {
-            return Ok(Default::default());
-        }
+        heap_tree_entries(&self.heap, self.user_root)
+    }
+}
 
-        let mut level = header.levels();
-        let mut next_level = vec![header.root()];
+/// Read every leaf record of the BTH whose [`HeapTreeHeader`] is at `user_root` in `heap`.
+pub fn heap_tree_entries<K, V>(
+    heap: &dyn HeapNode,
+    user_root: HeapId,
+) -> io::Result<Vec<HeapTreeLeafEntry<K, V>>>
+where
+    K: HeapTreeEntryKey + HeapNodePageReadWrite,
+    V: HeapTreeEntryValue + HeapNodePageReadWrite,
+{
+    let header = HeapTreeHeader::read(&mut Cursor::new(heap.find_entry(user_root)?))?;
+    if header.key_size() != K::SIZE {
+        return Err(LtpError::InvalidHeapTreeKeySize(header.key_size()).into());
+    }
+    if header.entry_size() != V::SIZE {
+        return Err(LtpError::InvalidHeapTreeDataSize(header.entry_size()).into());
+    }
 
-        while level > 0 {
-            for heap_id in mem::take(&mut next_level).into_iter() {
-                let mut cursor = Cursor::new(self.heap.find_entry(heap_id)?);
-                while let Ok(row) = HeapTreeIntermediateEntry::<K>::read(&mut cursor) {
-                    next_level.push(row.next_level());
-                }
-            }
+    if u32::from(header.root()) == 0 {
+        return Ok(Default::default());
+    }
 
-            level -= 1;
-        }
+    let mut level = header.levels();
+    let mut next_level = vec![header.root()];
 
-        let mut results = Vec::new();
+    while level > 0 {
         for heap_id in mem::take(&mut next_level).into_iter() {
-            let mut cursor = Cursor::new(self.heap.find_entry(heap_id)?);
-            while let Ok(row) = HeapTreeLeafEntry::<K, V>::read(&mut cursor) {
-                results.push(row);
+            let mut cursor = Cursor::new(heap.find_entry(heap_id)?);
+            while let Ok(row) = HeapTreeIntermediateEntry::<K>::read(&mut cursor) {
+                next_level.push(row.next_level());
             }
         }
 
-        Ok(results)
+        level -= 1;
     }
+
+    let mut results = Vec::new();
+    for heap_id in mem::take(&mut next_level).into_iter() {
+        let mut cursor = Cursor::new(heap.find_entry(heap_id)?);
+        while let Ok(row) = HeapTreeLeafEntry::<K, V>::read(&mut cursor) {
+            results.push(row);
+        }
+    }
+
+    Ok(results)
+}
+
+/// Read every leaf record like [`heap_tree_entries`], and check that the keys are strictly
+/// ascending, which lookups in the BTH depend on.
+pub fn validate_heap_tree<K, V>(
+    heap: &dyn HeapNode,
+    user_root: HeapId,
+) -> io::Result<Vec<HeapTreeLeafEntry<K, V>>>
+where
+    K: HeapTreeEntryKey + HeapNodePageReadWrite,
+    V: HeapTreeEntryValue + HeapNodePageReadWrite,
+{
+    let entries = heap_tree_entries(heap, user_root)?;
+    if let Some(index) = entries
+        .windows(2)
+        .position(|pair| pair[0].key() >= pair[1].key())
+    {
+        return Err(LtpError::UnsortedHeapTreeKeys(index + 1).into());
+    }
+    Ok(entries)
+}
+
+/// Allocate a BTH on `heap` holding `entries`, which must be sorted by key, and return the
+/// [`HeapId`] of its [`HeapTreeHeader`]. The records are split across as many leaf allocations
+/// as they need, with levels of intermediate records above them until one allocation holds the
+/// root.
+pub fn build_heap_tree<K, V>(
+    heap: &mut HeapNodeBuilder,
+    entries: &[HeapTreeLeafEntry<K, V>],
+) -> io::Result<HeapId>
+where
+    K: HeapTreeEntryKey + HeapNodePageReadWrite,
+    V: HeapTreeEntryValue + HeapNodePageReadWrite,
+{
+    let leaf_capacity = MAX_HEAP_ALLOCATION_SIZE / usize::from(K::SIZE + V::SIZE);
+    let mut nodes = entries
+        .chunks(leaf_capacity)
+        .map(|chunk| {
+            let mut data = vec![];
+            for entry in chunk {
+                entry.write(&mut data)?;
+            }
+            Ok(HeapTreeIntermediateEntry::new(
+                chunk[0].key(),
+                heap.allocate(&data)?,
+            ))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let intermediate_capacity = MAX_HEAP_ALLOCATION_SIZE / usize::from(K::SIZE + 4);
+    let mut levels = 0_u8;
+    while nodes.len() > 1 {
+        nodes = nodes
+            .chunks(intermediate_capacity)
+            .map(|chunk| {
+                let mut data = vec![];
+                for entry in chunk {
+                    entry.write(&mut data)?;
+                }
+                Ok(HeapTreeIntermediateEntry::new(
+                    chunk[0].key(),
+                    heap.allocate(&data)?,
+                ))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        levels += 1;
+    }
+
+    let root = nodes
+        .first()
+        .map(HeapTreeIntermediateEntry::next_level)
+        .unwrap_or_default();
+    let header = HeapTreeHeader::new(K::SIZE, V::SIZE, levels, root)?;
+    let mut data = vec![];
+    header.write(&mut data)?;
+    Ok(heap.allocate(&data)?)
 }
 
 pub struct UnicodeHeapTree<K, V>
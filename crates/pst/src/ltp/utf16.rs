@@ -0,0 +1,243 @@
+//! UTF-16LE decoding for `PtypString` and `PtypMultipleString` values, which is a hot spot when
+//! scanning the property and table contexts of a large PST file.
+//!
+//! The property and table context readers collect the encoded bytes of each value in a scratch
+//! buffer, which is kept between values on the same thread instead of being allocated each time.
+//! [`set_max_scratch_capacity`] bounds how much of it is kept after an unusually large value.
+//!
+//! [`decode_utf16_lossy`] and [`Utf16Decoder`] find runs of ASCII characters a block at a time,
+//! and push them without going through [`char::decode_utf16`]. Everything else decodes the same
+//! way as [`String::from_utf16_lossy`]. Run `cargo bench --bench utf16` to compare them.
+
+use std::{
+    cell::{Cell, RefCell},
+    char::REPLACEMENT_CHARACTER,
+    io::{self, Read},
+};
+
+/// Default for [`set_max_scratch_capacity`].
+pub const DEFAULT_MAX_SCRATCH_CAPACITY: usize = 64 * 1024;
+
+thread_local! {
+    static SCRATCH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    static MAX_SCRATCH_CAPACITY: Cell<usize> = const { Cell::new(DEFAULT_MAX_SCRATCH_CAPACITY) };
+}
+
+/// Limit the scratch buffer the property and table context readers keep on this thread between
+/// values to `bytes`. A larger value still decodes, but the buffer is shrunk back afterwards.
+pub fn set_max_scratch_capacity(bytes: usize) {
+    MAX_SCRATCH_CAPACITY.with(|max| max.set(bytes));
+    SCRATCH.with(|scratch| {
+        if let Ok(mut scratch) = scratch.try_borrow_mut() {
+            scratch.shrink_to(bytes);
+        }
+    });
+}
+
+/// The limit set with [`set_max_scratch_capacity`] for this thread.
+pub fn max_scratch_capacity() -> usize {
+    MAX_SCRATCH_CAPACITY.with(Cell::get)
+}
+
+/// Read the rest of `f` into the scratch buffer for this thread, and pass it to `decode`.
+pub(crate) fn with_scratch<T>(
+    f: &mut dyn Read,
+    decode: impl FnOnce(&[u8]) -> io::Result<T>,
+) -> io::Result<T> {
+    SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+        Ok(mut scratch) => {
+            scratch.clear();
+            f.read_to_end(&mut scratch)?;
+            let result = decode(&scratch);
+            scratch.clear();
+            scratch.shrink_to(max_scratch_capacity());
+            result
+        }
+        Err(_) => {
+            let mut buffer = Vec::new();
+            f.read_to_end(&mut buffer)?;
+            decode(&buffer)
+        }
+    })
+}
+
+/// Collect the UTF-16LE code units in `bytes` up to the first null terminator. A trailing odd
+/// byte is ignored.
+pub(crate) fn utf16le_units(bytes: &[u8]) -> Vec<u16> {
+    let units = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+    let len = units
+        .clone()
+        .position(|unit| unit == 0)
+        .unwrap_or(bytes.len() / 2);
+    let mut buffer = Vec::with_capacity(len);
+    buffer.extend(units.take(len));
+    buffer
+}
+
+/// Same as [`String::from_utf16_lossy`], with a fast path for runs of ASCII characters.
+pub fn decode_utf16_lossy(units: &[u16]) -> String {
+    let mut output = String::with_capacity(units.len());
+    push_utf16_lossy(&mut output, units);
+    output
+}
+
+/// Decodes UTF-16 into a reusable output buffer, for callers which look at many strings without
+/// keeping them, e.g. to count message classes.
+#[derive(Clone, Debug)]
+pub struct Utf16Decoder {
+    output: String,
+    max_capacity: usize,
+}
+
+impl Default for Utf16Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Utf16Decoder {
+    pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Start with room for `bytes` of UTF-8 output, and keep at least that much between calls.
+    pub fn with_capacity(bytes: usize) -> Self {
+        Self {
+            output: String::with_capacity(bytes),
+            max_capacity: bytes.max(DEFAULT_MAX_SCRATCH_CAPACITY),
+        }
+    }
+
+    /// Shrink the output buffer back to `bytes` after decoding a longer string.
+    pub fn set_max_capacity(&mut self, bytes: usize) {
+        self.max_capacity = bytes;
+        self.output.shrink_to(bytes);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.output.capacity()
+    }
+
+    /// Decode `units` like [`String::from_utf16_lossy`]. The result is only valid till the next
+    /// call.
+    pub fn decode(&mut self, units: &[u16]) -> &str {
+        if self.output.capacity() > self.max_capacity {
+            self.output = String::with_capacity(self.max_capacity);
+        }
+        self.output.clear();
+        push_utf16_lossy(&mut self.output, units);
+        &self.output
+    }
+}
+
+fn push_utf16_lossy(output: &mut String, mut units: &[u16]) {
+    while !units.is_empty() {
+        let ascii = push_ascii_prefix(output, units);
+        units = &units[ascii..];
+
+        // ASCII code units are never part of a surrogate pair, so the rest can be split at the
+        // next one without changing how it decodes.
+        let other = units
+            .iter()
+            .position(|&unit| unit < 0x80)
+            .unwrap_or(units.len());
+        push_utf16_scalar(output, &units[..other]);
+        units = &units[other..];
+    }
+}
+
+fn push_utf16_scalar(output: &mut String, units: &[u16]) {
+    output.extend(
+        char::decode_utf16(units.iter().copied()).map(|ch| ch.unwrap_or(REPLACEMENT_CHARACTER)),
+    );
+}
+
+/// Code units checked at once by the ASCII fast path.
+const ASCII_BLOCK: usize = 16;
+
+/// Append the leading ASCII characters in `units` to `output`, and return how many there were.
+fn push_ascii_prefix(output: &mut String, units: &[u16]) -> usize {
+    let blocks = units
+        .chunks_exact(ASCII_BLOCK)
+        .take_while(|block| block.iter().fold(0, |bits, unit| bits | unit) < 0x80)
+        .count();
+    let count = blocks * ASCII_BLOCK
+        + units[blocks * ASCII_BLOCK..]
+            .iter()
+            .take_while(|&&unit| unit < 0x80)
+            .count();
+
+    output.reserve(count);
+    for &unit in &units[..count] {
+        output.push(char::from(unit as u8));
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> Vec<Vec<u16>> {
+        let mut samples: Vec<Vec<u16>> = [
+            "",
+            "Inbox",
+            "IPM.Note.SMIME.MultipartSigned",
+            "Grüße aus Köln, 東京 and a long ASCII tail to cross the block boundary",
+            "emoji 😀 between ascii blocks of sixteen units 😀😀",
+        ]
+        .iter()
+        .map(|text| text.encode_utf16().collect())
+        .collect();
+
+        // Unpaired surrogates are replaced.
+        samples.push(vec![0x41, 0xD800, 0x42, 0xDC00]);
+        samples.push([0x61; 31].into_iter().chain([0xDBFF]).collect());
+        samples
+    }
+
+    #[test]
+    fn test_decode_matches_std() {
+        let mut decoder = Utf16Decoder::new();
+        for sample in samples() {
+            let expected = String::from_utf16_lossy(&sample);
+            assert_eq!(decode_utf16_lossy(&sample), expected);
+            assert_eq!(decoder.decode(&sample), expected);
+        }
+    }
+
+    #[test]
+    fn test_decoder_capacity() {
+        let mut decoder = Utf16Decoder::with_capacity(16);
+        decoder.set_max_capacity(16);
+        let long: Vec<u16> = "x".repeat(1000).encode_utf16().collect();
+        assert_eq!(decoder.decode(&long).len(), 1000);
+        assert_eq!(decoder.decode(&[0x41]), "A");
+        assert!(decoder.capacity() <= 16);
+    }
+
+    #[test]
+    fn test_utf16le_units() {
+        assert_eq!(
+            utf16le_units(&[0x41, 0, 0x42, 0, 0, 0, 0x43, 0]),
+            [0x41, 0x42]
+        );
+        assert_eq!(utf16le_units(&[0x41, 0, 0x42]), [0x41]);
+
+        let units = with_scratch(&mut [0x3D, 0xD8, 0x00, 0xDE].as_slice(), |bytes| {
+            Ok(utf16le_units(bytes))
+        })
+        .unwrap();
+        assert_eq!(String::from_utf16_lossy(&units), "😀");
+
+        set_max_scratch_capacity(8);
+        assert_eq!(max_scratch_capacity(), 8);
+        let bytes = vec![0x41; 64];
+        let len = with_scratch(&mut bytes.as_slice(), |bytes| Ok(bytes.len())).unwrap();
+        assert_eq!(len, 64);
+        SCRATCH.with(|scratch| assert!(scratch.borrow().capacity() <= 8));
+        set_max_scratch_capacity(DEFAULT_MAX_SCRATCH_CAPACITY);
+    }
+}
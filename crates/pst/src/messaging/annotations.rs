@@ -0,0 +1,217 @@
+//! Key-value annotations which applications keep in a folder, e.g. how far they have processed
+//! its messages, without adding their own properties to the messages or the folder.
+//!
+//! The annotations are saved in a hidden folder associated information (FAI) message, the same
+//! way Outlook saves view settings, with
+//! [`PstFileLockGuard::save_annotations`](crate::PstFileLockGuard::save_annotations), and read
+//! back with [`Folder::annotations`].
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    collections::BTreeMap,
+    io::{self, Cursor, Read, Write},
+};
+
+use super::{
+    folder::Folder,
+    message::{MessageBuilder, MSGFLAG_ASSOCIATED, MSGFLAG_READ},
+};
+use crate::ltp::prop_context::PropertyValue;
+
+/// `PidTagMessageClass` of the FAI message which holds the [`FolderAnnotations`].
+pub const ANNOTATIONS_MESSAGE_CLASS: &str = "IPM.Configuration.Annotations";
+
+/// `PidTagRoamingBinary`, which holds the serialized annotations in the FAI message.
+const PID_TAG_ROAMING_BINARY: u16 = 0x7C09;
+
+const ANNOTATIONS_MAGIC: &[u8; 8] = b"PSTANNO1";
+
+/// Annotations of a folder, keyed by name. Values are bytes, which can also be set and read as
+/// UTF-8 strings.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FolderAnnotations {
+    values: BTreeMap<String, Vec<u8>>,
+}
+
+impl FolderAnnotations {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Read the annotations from the FAI message in `folder`. Returns `None` if they have never
+    /// been saved, and fails if the saved annotations cannot be read.
+    pub fn load<F: Folder + ?Sized>(folder: &F) -> io::Result<Option<Self>> {
+        let Some(associated_table) = folder.associated_table() else {
+            return Ok(None);
+        };
+
+        for row in associated_table.rows_matrix() {
+            let message =
+                folder.open_message_at_row(row, Some(&[0x001A, PID_TAG_ROAMING_BINARY]))?;
+            let properties = message.properties();
+            if !is_annotations_message_class(properties.get(0x001A)) {
+                continue;
+            }
+
+            return match properties.get(PID_TAG_ROAMING_BINARY) {
+                Some(PropertyValue::Binary(value)) => {
+                    Self::read(&mut Cursor::new(value.buffer())).map(Some)
+                }
+                _ => Err(io::ErrorKind::InvalidData.into()),
+            };
+        }
+
+        Ok(None)
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.values.get(key).map(Vec::as_slice)
+    }
+
+    /// The value of `key`, if it is valid UTF-8.
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key)
+            .and_then(|value| std::str::from_utf8(value).ok())
+    }
+
+    /// Set `key` to `value`, and return the previous value.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<Vec<u8>>) -> Option<Vec<u8>> {
+        self.values.insert(key.into(), value.into())
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Vec<u8>> {
+        self.values.remove(key)
+    }
+
+    /// Each key and value, sorted by key.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.values
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_slice()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Make the FAI message which holds the annotations, for
+    /// [`PstFileLockGuard::create_associated_message`](crate::PstFileLockGuard::create_associated_message)
+    /// or [`PstFileLockGuard::update_message`](crate::PstFileLockGuard::update_message).
+    pub fn to_message_builder(&self) -> io::Result<MessageBuilder> {
+        let mut data = vec![];
+        self.write(&mut data)?;
+
+        let mut builder = MessageBuilder::new(ANNOTATIONS_MESSAGE_CLASS);
+        builder.set_property(
+            0x0E07,
+            PropertyValue::Integer32(MSGFLAG_READ | MSGFLAG_ASSOCIATED),
+        );
+        builder.set_property(PID_TAG_ROAMING_BINARY, PropertyValue::from(data));
+        Ok(builder)
+    }
+
+    pub fn read(f: &mut dyn Read) -> io::Result<Self> {
+        let mut magic = [0; ANNOTATIONS_MAGIC.len()];
+        f.read_exact(&mut magic)?;
+        if magic != *ANNOTATIONS_MAGIC {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        let count = f.read_u32::<LittleEndian>()?;
+        let mut values = BTreeMap::new();
+        for _ in 0..count {
+            let mut key = vec![0; usize::from(f.read_u16::<LittleEndian>()?)];
+            f.read_exact(&mut key)?;
+            let key =
+                String::from_utf8(key).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+
+            let mut value = vec![];
+            let size = u64::from(f.read_u32::<LittleEndian>()?);
+            if f.take(size).read_to_end(&mut value)? as u64 != size {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            if values.insert(key, value).is_some() {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+        }
+
+        Ok(Self { values })
+    }
+
+    /// Fails with [`io::ErrorKind::InvalidInput`] if a key is longer than 64 KiB, or a value is
+    /// longer than 4 GiB.
+    pub fn write(&self, f: &mut dyn Write) -> io::Result<()> {
+        let invalid_input = |_| io::Error::from(io::ErrorKind::InvalidInput);
+
+        f.write_all(ANNOTATIONS_MAGIC)?;
+        f.write_u32::<LittleEndian>(u32::try_from(self.values.len()).map_err(invalid_input)?)?;
+        for (key, value) in self.values.iter() {
+            f.write_u16::<LittleEndian>(u16::try_from(key.len()).map_err(invalid_input)?)?;
+            f.write_all(key.as_bytes())?;
+            f.write_u32::<LittleEndian>(u32::try_from(value.len()).map_err(invalid_input)?)?;
+            f.write_all(value)?;
+        }
+        Ok(())
+    }
+}
+
+fn is_annotations_message_class(value: Option<&PropertyValue>) -> bool {
+    let message_class = match value {
+        Some(PropertyValue::String8(value)) => value.to_string(),
+        Some(PropertyValue::Unicode(value)) => value.to_string(),
+        _ => return false,
+    };
+    message_class.eq_ignore_ascii_case(ANNOTATIONS_MESSAGE_CLASS)
+}
+
+/// Whether the properties of an FAI message, read with
+/// [`read_property_context`](crate::ltp::prop_context::read_property_context), are those of the
+/// saved [`FolderAnnotations`].
+pub(crate) fn is_annotations_message(properties: &BTreeMap<u16, PropertyValue>) -> bool {
+    is_annotations_message_class(properties.get(&0x001A))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_annotations_round_trip() {
+        let mut annotations = FolderAnnotations::new();
+        assert!(annotations.is_empty());
+        annotations.insert("sync.cursor", "42");
+        annotations.insert("classifier.state", vec![0, 0xFF, 7]);
+        assert_eq!(
+            annotations.insert("sync.cursor", "43"),
+            Some(b"42".to_vec())
+        );
+        assert_eq!(annotations.get_str("sync.cursor"), Some("43"));
+        assert_eq!(annotations.get_str("classifier.state"), None);
+        assert_eq!(annotations.get("classifier.state"), Some(&[0, 0xFF, 7][..]));
+        assert_eq!(
+            annotations.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            ["classifier.state", "sync.cursor"]
+        );
+
+        let mut data = vec![];
+        annotations.write(&mut data).unwrap();
+        assert_eq!(
+            FolderAnnotations::read(&mut Cursor::new(data.as_slice())).unwrap(),
+            annotations
+        );
+
+        assert!(FolderAnnotations::read(&mut Cursor::new(&data[..data.len() - 1])).is_err());
+        data[0] = b'X';
+        assert!(FolderAnnotations::read(&mut Cursor::new(data.as_slice())).is_err());
+
+        let builder = annotations.to_message_builder().unwrap();
+        assert!(is_annotations_message(builder.properties()));
+        assert_eq!(annotations.remove("sync.cursor"), Some(b"43".to_vec()));
+        assert_eq!(annotations.len(), 1);
+    }
+}
@@ -2,18 +2,22 @@
 
 use std::{collections::BTreeMap, io, rc::Rc};
 
-use super::{message::*, read_write::*, *};
+use super::{message::*, object_id::AttachmentId, read_write::*, store::Store, *};
 use crate::{
     ltp::{
         heap::HeapNode,
-        prop_context::{BinaryValue, PropertyContext, PropertyValue},
+        prop_context::{
+            build_property_context, BinaryValue, ObjectValue, PropertyContext, PropertyValue,
+            PropertyValueRecord,
+        },
         prop_type::PropertyType,
         read_write::*,
     },
     ndb::{
-        block::{DataTree, IntermediateTreeBlock},
+        block::{DataTree, IntermediateTreeBlock, LeafSubNodeTreeEntry, SubNodeTree},
         block_id::BlockId,
-        header::Header,
+        header::{Header, NdbVersion},
+        node_data::{NodeData, FIRST_LOCAL_NODE_INDEX},
         node_id::{NodeId, NodeIdType},
         page::{BTreePage, NodeBTreeEntry, RootBTree},
         read_write::*,
@@ -50,7 +54,13 @@ impl AttachmentProperties {
         }
     }
 
-    pub fn attachment_method(&self) -> io::Result<i32> {
+    /// `PidTagAttachMethod`, see [`AttachmentProperties::raw_attachment_method`] for the `i32`
+    /// value.
+    pub fn attachment_method(&self) -> io::Result<AttachmentMethod> {
+        Ok(AttachmentMethod::try_from(self.raw_attachment_method()?)?)
+    }
+
+    pub fn raw_attachment_method(&self) -> io::Result<i32> {
         let attachment_method = self
             .properties
             .get(&0x3705)
@@ -64,7 +74,13 @@ impl AttachmentProperties {
         }
     }
 
-    pub fn rendering_position(&self) -> io::Result<i32> {
+    /// `PidTagRenderingPosition`, see [`AttachmentProperties::raw_rendering_position`] for the
+    /// `i32` value.
+    pub fn rendering_position(&self) -> io::Result<RenderingPosition> {
+        self.raw_rendering_position().map(RenderingPosition::from)
+    }
+
+    pub fn raw_rendering_position(&self) -> io::Result<i32> {
         let rendering_position = self
             .properties
             .get(&0x370B)
@@ -78,6 +94,71 @@ impl AttachmentProperties {
             ),
         }
     }
+
+    /// `PidTagAttachFlags`, or `0` if it is missing.
+    pub fn attach_flags(&self) -> i32 {
+        match self.properties.get(&0x3714) {
+            Some(PropertyValue::Integer32(value)) => *value,
+            _ => 0,
+        }
+    }
+
+    /// Whether `PidTagAttachmentHidden` is set, i.e. the attachment should not be listed with
+    /// the other attachments of the message, e.g. a contact photo or an inline image.
+    pub fn is_hidden(&self) -> bool {
+        matches!(
+            self.properties.get(&0x7FFE),
+            Some(PropertyValue::Boolean(true))
+        )
+    }
+
+    /// Whether the attachment is rendered in the body of the message, which is true if
+    /// `PidTagAttachFlags` has [`ATTACH_RENDERED_IN_BODY`], or if the attachment has a
+    /// `PidTagAttachContentId` which an HTML body can reference it by.
+    pub fn is_inline(&self) -> bool {
+        let has_content_id = match self.properties.get(&0x3712) {
+            Some(PropertyValue::String8(value)) => !value.buffer().is_empty(),
+            Some(PropertyValue::Unicode(value)) => !value.buffer().is_empty(),
+            _ => false,
+        };
+        has_content_id || self.attach_flags() & ATTACH_RENDERED_IN_BODY != 0
+    }
+}
+
+/// `attInvisibleInHtml`: The attachment is not referenced by an HTML body.
+pub const ATTACH_INVISIBLE_IN_HTML: i32 = 0x0000_0001;
+
+/// `attInvisibleInRtf`: The attachment is not referenced by an RTF body.
+pub const ATTACH_INVISIBLE_IN_RTF: i32 = 0x0000_0002;
+
+/// `attRenderedInBody`: The attachment is referenced and rendered by an HTML body.
+pub const ATTACH_RENDERED_IN_BODY: i32 = 0x0000_0004;
+
+/// `PidTagRenderingPosition`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RenderingPosition {
+    /// `0xFFFFFFFF`: The attachment is not rendered in the body of the message.
+    NotRendered,
+    /// The offset in characters into the plain text body where the attachment is rendered.
+    Offset(u32),
+}
+
+impl From<i32> for RenderingPosition {
+    fn from(value: i32) -> Self {
+        match value {
+            -1 => Self::NotRendered,
+            value => Self::Offset(value as u32),
+        }
+    }
+}
+
+impl From<RenderingPosition> for i32 {
+    fn from(value: RenderingPosition) -> Self {
+        match value {
+            RenderingPosition::NotRendered => -1,
+            RenderingPosition::Offset(value) => value as i32,
+        }
+    }
 }
 
 /// [PidTagAttachMethod](https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxcmsg/252923d6-dd41-468b-9c57-d3f68051a516)
@@ -126,15 +207,152 @@ impl TryFrom<i32> for AttachmentMethod {
     }
 }
 
+/// Properties of a new [`AttachmentMethod::ByValue`] or [`AttachmentMethod::EmbeddedMessage`]
+/// attachment, which can be added to a message with [`MessageBuilder::add_attachment`].
+#[derive(Clone, Debug)]
+pub struct AttachmentBuilder {
+    properties: BTreeMap<u16, PropertyValue>,
+    embedded_message: Option<Box<MessageBuilder>>,
+}
+
+impl AttachmentBuilder {
+    /// Attach `data` as a file named `filename`. `PidTagAttachExtension` is taken from the
+    /// `filename`, and `mime_type` sets `PidTagAttachMimeTag` if it is known.
+    pub fn by_value(filename: &str, mime_type: Option<&str>, data: Vec<u8>) -> Self {
+        let size = i32::try_from(data.len()).unwrap_or(i32::MAX);
+        let mut builder = Self {
+            properties: BTreeMap::from([
+                (
+                    0x3705,
+                    PropertyValue::Integer32(AttachmentMethod::ByValue as i32),
+                ),
+                (0x3701, PropertyValue::Binary(BinaryValue::new(data))),
+                (0x3704, unicode_value(filename)),
+                (0x3707, unicode_value(filename)),
+                (0x0E20, PropertyValue::Integer32(size)),
+                (0x370B, PropertyValue::Integer32(-1)),
+            ]),
+            embedded_message: None,
+        };
+        if let Some((_, extension)) = filename.rsplit_once('.') {
+            builder.set_property(0x3703, unicode_value(&format!(".{extension}")));
+        }
+        if let Some(mime_type) = mime_type {
+            builder.set_property(0x370E, unicode_value(mime_type));
+        }
+        builder
+    }
+
+    /// Attach `message` as an embedded message named `display_name`. The message is written to a
+    /// sub-node of the attachment, which `PidTagAttachDataObject` points at, and it can be read
+    /// back with [`Attachment::open_embedded_message`].
+    pub fn embedded_message(display_name: &str, message: MessageBuilder) -> Self {
+        Self {
+            properties: BTreeMap::from([
+                (
+                    0x3705,
+                    PropertyValue::Integer32(AttachmentMethod::EmbeddedMessage as i32),
+                ),
+                (0x3001, unicode_value(display_name)),
+                (0x370B, PropertyValue::Integer32(-1)),
+            ]),
+            embedded_message: Some(Box::new(message)),
+        }
+    }
+
+    pub fn properties(&self) -> &BTreeMap<u16, PropertyValue> {
+        &self.properties
+    }
+
+    pub fn set_property(&mut self, prop_id: u16, value: PropertyValue) {
+        self.properties.insert(prop_id, value);
+    }
+
+    pub fn remove_property(&mut self, prop_id: u16) -> Option<PropertyValue> {
+        self.properties.remove(&prop_id)
+    }
+
+    /// The message attached with [`Self::embedded_message`].
+    pub fn message(&self) -> Option<&MessageBuilder> {
+        self.embedded_message.as_deref()
+    }
+
+    pub(crate) fn message_mut(&mut self) -> Option<&mut MessageBuilder> {
+        self.embedded_message.as_deref_mut()
+    }
+
+    /// Serialize the attachment property context and, for an embedded message, the message in
+    /// its sub-node. Returns the properties for the row in the attachment table along with it.
+    pub(crate) fn build(
+        &self,
+        version: NdbVersion,
+    ) -> io::Result<(BTreeMap<u16, PropertyValue>, NodeData)> {
+        let Some(message) = self.embedded_message.as_deref() else {
+            return Ok((
+                self.properties.clone(),
+                build_property_context(self.properties.iter())?,
+            ));
+        };
+
+        let sub_node = NodeId::new(NodeIdType::NormalMessage, FIRST_LOCAL_NODE_INDEX)?;
+        let message = message.build(version)?;
+        let size = u32::try_from(message.size()).unwrap_or(u32::MAX);
+        let mut properties = self.properties.clone();
+        properties.insert(
+            0x3701,
+            PropertyValue::Object(ObjectValue::new(sub_node, size)),
+        );
+        properties.insert(
+            0x0E20,
+            PropertyValue::Integer32(i32::try_from(size).unwrap_or(i32::MAX)),
+        );
+        let mut data = build_property_context(properties.iter())?;
+        data.insert_sub_node(sub_node, message);
+        Ok((properties, data))
+    }
+}
+
 pub enum AttachmentData {
     Binary(BinaryValue),
     Message(Rc<dyn Message>),
 }
 
+/// The block which holds `PidTagAttachDataBinary` or `PidTagAttachDataObject` of an attachment,
+/// see [`Message::attachment_data_block`]. Attachments which point at the same block share their
+/// data, e.g. after [`PstFileLockGuard::copy_message`](crate::PstFileLockGuard::copy_message).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AttachmentDataBlock {
+    block: u64,
+    size: u64,
+}
+
+impl AttachmentDataBlock {
+    pub(crate) fn new(block: u64, size: u64) -> Self {
+        Self { block, size }
+    }
+
+    /// The BID of the data tree which holds the value, or of the attachment property context if
+    /// the value is small enough to be allocated on its heap.
+    pub fn block(&self) -> u64 {
+        self.block
+    }
+
+    /// Size of the value in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
 pub trait Attachment {
     fn message(&self) -> Rc<dyn Message>;
     fn properties(&self) -> &AttachmentProperties;
     fn data(&self) -> Option<&AttachmentData>;
+
+    /// Open the embedded message referenced by `PidTagAttachDataObject` on an
+    /// [`AttachmentMethod::EmbeddedMessage`] attachment, loading only the properties in
+    /// `prop_ids` if specified. The result can be passed to [`Message::open_attachment`] to walk
+    /// nested attachments recursively.
+    fn open_embedded_message(&self, prop_ids: Option<&[u16]>) -> io::Result<Rc<dyn Message>>;
 }
 
 struct AttachmentInner<Pst>
@@ -143,6 +361,7 @@ where
 {
     message: Rc<Pst::Message>,
     properties: AttachmentProperties,
+    sub_nodes: MessageSubNodes<Pst>,
     data: Option<AttachmentData>,
 }
 
@@ -173,6 +392,13 @@ where
     <<Pst as PstFile>::BlockBTree as RootBTree>::LeafPage:
         RootBTreeLeafPageReadWrite<Pst> + BTreePageReadWrite,
     <Pst as PstFile>::BlockTrailer: BlockTrailerReadWrite,
+    <Pst as PstFile>::SubNodeTreeBlockHeader: IntermediateTreeHeaderReadWrite,
+    <Pst as PstFile>::SubNodeTreeBlock: IntermediateTreeBlockReadWrite,
+    <<Pst as PstFile>::SubNodeTreeBlock as IntermediateTreeBlock>::Entry:
+        IntermediateTreeEntryReadWrite,
+    <Pst as PstFile>::SubNodeBlock: IntermediateTreeBlockReadWrite,
+    <<Pst as PstFile>::SubNodeBlock as IntermediateTreeBlock>::Entry:
+        IntermediateTreeEntryReadWrite,
     <Pst as PstFile>::DataTreeBlock: IntermediateTreeBlockReadWrite,
     <<Pst as PstFile>::DataTreeBlock as IntermediateTreeBlock>::Entry:
         IntermediateTreeEntryReadWrite,
@@ -185,28 +411,19 @@ where
 {
     fn read(
         message: Rc<<Pst as PstFile>::Message>,
-        sub_node: NodeId,
+        sub_node: AttachmentId,
         prop_ids: Option<&[u16]>,
     ) -> io::Result<Self> {
-        let node_id_type = sub_node.id_type()?;
-        match node_id_type {
-            NodeIdType::Attachment => {}
-            _ => {
-                return Err(MessagingError::InvalidAttachmentNodeIdType(node_id_type).into());
-            }
-        }
-
+        let sub_node = sub_node.node_id();
         let store = message.pst_store();
         let pst = store.pst();
+        let heap_id_mode = store.heap_id_mode();
         let header = pst.header();
         let root = header.root();
 
-        let (properties, data) = {
-            let mut file = pst
-                .reader()
-                .lock()
-                .map_err(|_| MessagingError::FailedToLockFile)?;
-            let file = &mut *file;
+        let (properties, sub_nodes, data) = {
+            let mut file = pst.reader();
+            let file = &mut file;
 
             let encoding = header.crypt_method();
             let block_btree = <<Pst as PstFile>::BlockBTree as RootBTreeReadWrite>::read(
@@ -226,6 +443,19 @@ where
             );
 
             let mut page_cache = pst.block_cache();
+            let sub_nodes: MessageSubNodes<Pst> = match node.sub_node() {
+                Some(sub_node) => {
+                    let block =
+                        block_btree.find_entry(file, sub_node.search_key(), &mut page_cache)?;
+                    let sub_nodes = SubNodeTree::<Pst>::read(file, &block)?;
+                    sub_nodes
+                        .entries(file, &block_btree, &mut page_cache)?
+                        .map(|entry| (entry.node(), entry))
+                        .collect()
+                }
+                None => Default::default(),
+            };
+
             let data = node.data();
             let heap = <<Pst as PstFile>::HeapNode as HeapNodeReadWrite<Pst>>::read(
                 file,
@@ -245,13 +475,21 @@ where
                 .into_iter()
                 .map(|(prop_id, record)| {
                     prop_context
-                        .read_property(file, encoding, &block_btree, &mut page_cache, record)
-                        .map(|value| (prop_id, value))
+                        .read_property_with_mode(
+                            file,
+                            encoding,
+                            &block_btree,
+                            &mut page_cache,
+                            record,
+                            heap_id_mode,
+                        )
+                        .map(|value| value.map(|value| (prop_id, value)))
                 })
+                .filter_map(Result::transpose)
                 .collect::<io::Result<BTreeMap<_, _>>>()?;
             let properties = AttachmentProperties { properties };
 
-            let attachment_method = AttachmentMethod::try_from(properties.attachment_method()?)?;
+            let attachment_method = properties.attachment_method()?;
             let data = match attachment_method {
                 AttachmentMethod::ByValue => {
                     let binary_data = match properties
@@ -268,39 +506,8 @@ where
                     };
                     Some(AttachmentData::Binary(binary_data.clone()))
                 }
-                AttachmentMethod::EmbeddedMessage => {
-                    let object_data = match properties
-                        .get(0x3701)
-                        .ok_or(MessagingError::AttachmentMessageObjectDataNotFound)?
-                    {
-                        PropertyValue::Object(value) => value,
-                        invalid => {
-                            return Err(MessagingError::InvalidMessageObjectData(
-                                PropertyType::from(invalid),
-                            )
-                            .into())
-                        }
-                    };
-
-                    let sub_node = object_data.node();
-                    let node = message
-                        .sub_nodes()
-                        .get(&sub_node)
-                        .ok_or(MessagingError::AttachmentSubNodeNotFound(sub_node))?;
-                    let node = <<Pst as PstFile>::NodeBTreeEntry as NodeBTreeEntryReadWrite>::new(
-                        node.node(),
-                        node.block(),
-                        node.sub_node(),
-                        None,
-                    );
-                    let message =
-                        <<Pst as PstFile>::Message as MessageReadWrite<Pst>>::read_embedded(
-                            store.clone(),
-                            node,
-                            prop_ids,
-                        )?;
-                    Some(AttachmentData::Message(message))
-                }
+                // The embedded message is read once the file is unlocked again, see below.
+                AttachmentMethod::EmbeddedMessage => None,
                 AttachmentMethod::Storage => {
                     let object_data = match properties
                         .get(0x3701)
@@ -314,11 +521,7 @@ where
                             .into())
                         }
                     };
-                    let sub_node = object_data.node();
-                    let node = message
-                        .sub_nodes()
-                        .get(&sub_node)
-                        .ok_or(MessagingError::AttachmentSubNodeNotFound(sub_node))?;
+                    let node = Self::find_sub_node(&message, &sub_nodes, object_data.node())?;
                     let block =
                         block_btree.find_entry(file, node.block().search_key(), &mut page_cache)?;
                     let block = DataTree::read(file, encoding, &block)?;
@@ -337,15 +540,182 @@ where
                 _ => None,
             };
 
-            (properties, data)
+            (properties, sub_nodes, data)
+        };
+
+        // Reading the embedded message locks the file itself.
+        let data = match properties.attachment_method()? {
+            AttachmentMethod::EmbeddedMessage => {
+                let message =
+                    Self::read_embedded_message(&message, &properties, &sub_nodes, prop_ids)?;
+                Some(AttachmentData::Message(message))
+            }
+            _ => data,
         };
 
         Ok(Self {
             message,
             properties,
+            sub_nodes,
             data,
         })
     }
+
+    fn read_data_block(
+        message: &<Pst as PstFile>::Message,
+        sub_node: AttachmentId,
+    ) -> io::Result<Option<AttachmentDataBlock>> {
+        let sub_node = sub_node.node_id();
+        let store = message.pst_store();
+        let pst = store.pst();
+        let header = pst.header();
+        let root = header.root();
+
+        let mut file = pst.reader();
+        let file = &mut file;
+
+        let encoding = header.crypt_method();
+        let block_btree =
+            <<Pst as PstFile>::BlockBTree as RootBTreeReadWrite>::read(file, *root.block_btree())?;
+
+        let node = message
+            .sub_nodes()
+            .get(&sub_node)
+            .ok_or(MessagingError::AttachmentSubNodeNotFound(sub_node))?;
+        let node = <<Pst as PstFile>::NodeBTreeEntry as NodeBTreeEntryReadWrite>::new(
+            node.node(),
+            node.block(),
+            node.sub_node(),
+            None,
+        );
+
+        let mut page_cache = pst.block_cache();
+        let data = node.data();
+        let heap = <<Pst as PstFile>::HeapNode as HeapNodeReadWrite<Pst>>::read(
+            file,
+            &block_btree,
+            &mut page_cache,
+            encoding,
+            data.search_key(),
+        )?;
+        let header = heap.header()?;
+
+        let tree = <Pst as PstFile>::PropertyTree::new(heap, header.user_root());
+        let prop_context =
+            <<Pst as PstFile>::PropertyContext as PropertyContextReadWrite<Pst>>::new(node, tree);
+        let Some(record) = prop_context.properties()?.remove(&0x3701) else {
+            return Ok(None);
+        };
+
+        // Only follow the sub-node for the value, so the data itself is not read.
+        let data_node = match (record.prop_type(), record.value()) {
+            (PropertyType::Object, _) => match prop_context.read_property(
+                file,
+                encoding,
+                &block_btree,
+                &mut page_cache,
+                record,
+            )? {
+                PropertyValue::Object(value) => value.node(),
+                invalid => {
+                    return Err(MessagingError::InvalidMessageObjectData(PropertyType::from(
+                        &invalid,
+                    ))
+                    .into())
+                }
+            },
+            (_, PropertyValueRecord::Node(node_id)) => node_id,
+            (_, PropertyValueRecord::Heap(heap_id)) => {
+                let size = if u32::from(heap_id) == 0 {
+                    0
+                } else {
+                    prop_context.tree().heap().find_entry(heap_id)?.len() as u64
+                };
+                return Ok(Some(AttachmentDataBlock::new(data.into_u64(), size)));
+            }
+            (_, PropertyValueRecord::Small(_)) => return Ok(None),
+        };
+
+        let sub_nodes: MessageSubNodes<Pst> = match node.sub_node() {
+            Some(sub_node) => {
+                let block = block_btree.find_entry(file, sub_node.search_key(), &mut page_cache)?;
+                let sub_nodes = SubNodeTree::<Pst>::read(file, &block)?;
+                sub_nodes
+                    .entries(file, &block_btree, &mut page_cache)?
+                    .map(|entry| (entry.node(), entry))
+                    .collect()
+            }
+            None => Default::default(),
+        };
+        let data_node = Self::find_sub_node(message, &sub_nodes, data_node)?;
+        let block =
+            block_btree.find_entry(file, data_node.block().search_key(), &mut page_cache)?;
+        let size = DataTree::<Pst>::read(file, encoding, &block)?.total_size();
+
+        Ok(Some(AttachmentDataBlock::new(
+            data_node.block().into_u64(),
+            u64::from(size),
+        )))
+    }
+
+    /// Look up a `PtypObject` sub-node, which belongs to the sub-node tree of the attachment
+    /// itself. Some writers put it in the sub-node tree of the parent message instead, so fall
+    /// back to that.
+    fn find_sub_node(
+        message: &<Pst as PstFile>::Message,
+        sub_nodes: &MessageSubNodes<Pst>,
+        sub_node: NodeId,
+    ) -> MessagingResult<LeafSubNodeTreeEntry<<Pst as PstFile>::BlockId>> {
+        sub_nodes
+            .get(&sub_node)
+            .or_else(|| message.sub_nodes().get(&sub_node))
+            .copied()
+            .ok_or(MessagingError::AttachmentSubNodeNotFound(sub_node))
+    }
+
+    fn read_embedded_message(
+        message: &Rc<<Pst as PstFile>::Message>,
+        properties: &AttachmentProperties,
+        sub_nodes: &MessageSubNodes<Pst>,
+        prop_ids: Option<&[u16]>,
+    ) -> io::Result<Rc<<Pst as PstFile>::Message>> {
+        let object_data = match properties
+            .get(0x3701)
+            .ok_or(MessagingError::AttachmentMessageObjectDataNotFound)?
+        {
+            PropertyValue::Object(value) => value,
+            invalid => {
+                return Err(
+                    MessagingError::InvalidMessageObjectData(PropertyType::from(invalid)).into(),
+                )
+            }
+        };
+
+        let node = Self::find_sub_node(message, sub_nodes, object_data.node())?;
+        let node = <<Pst as PstFile>::NodeBTreeEntry as NodeBTreeEntryReadWrite>::new(
+            node.node(),
+            node.block(),
+            node.sub_node(),
+            None,
+        );
+        <<Pst as PstFile>::Message as MessageReadWrite<Pst>>::read_embedded(
+            message.pst_store().clone(),
+            node,
+            prop_ids,
+        )
+    }
+
+    fn open_embedded_message(
+        &self,
+        prop_ids: Option<&[u16]>,
+    ) -> io::Result<Rc<<Pst as PstFile>::Message>> {
+        let attachment_method = self.properties.attachment_method()?;
+        if attachment_method != AttachmentMethod::EmbeddedMessage {
+            return Err(MessagingError::AttachmentNotEmbeddedMessage(attachment_method).into());
+        }
+
+        Self::read_embedded_message(&self.message, &self.properties, &self.sub_nodes, prop_ids)
+    }
 }
 
 pub struct UnicodeAttachment {
@@ -355,7 +725,7 @@ pub struct UnicodeAttachment {
 impl UnicodeAttachment {
     pub fn read(
         message: Rc<UnicodeMessage>,
-        sub_node: NodeId,
+        sub_node: AttachmentId,
         prop_ids: Option<&[u16]>,
     ) -> io::Result<Rc<Self>> {
         <Self as AttachmentReadWrite<UnicodePstFile>>::read(message, sub_node, prop_ids)
@@ -374,17 +744,28 @@ impl Attachment for UnicodeAttachment {
     fn data(&self) -> Option<&AttachmentData> {
         self.inner.data.as_ref()
     }
+
+    fn open_embedded_message(&self, prop_ids: Option<&[u16]>) -> io::Result<Rc<dyn Message>> {
+        Ok(self.inner.open_embedded_message(prop_ids)?)
+    }
 }
 
 impl AttachmentReadWrite<UnicodePstFile> for UnicodeAttachment {
     fn read(
         message: Rc<UnicodeMessage>,
-        sub_node: NodeId,
+        sub_node: AttachmentId,
         prop_ids: Option<&[u16]>,
     ) -> io::Result<Rc<Self>> {
         let inner = AttachmentInner::read(message, sub_node, prop_ids)?;
         Ok(Rc::new(Self { inner }))
     }
+
+    fn read_data_block(
+        message: &UnicodeMessage,
+        sub_node: AttachmentId,
+    ) -> io::Result<Option<AttachmentDataBlock>> {
+        AttachmentInner::<UnicodePstFile>::read_data_block(message, sub_node)
+    }
 }
 
 pub struct AnsiAttachment {
@@ -394,7 +775,7 @@ pub struct AnsiAttachment {
 impl AnsiAttachment {
     pub fn read(
         message: Rc<AnsiMessage>,
-        sub_node: NodeId,
+        sub_node: AttachmentId,
         prop_ids: Option<&[u16]>,
     ) -> io::Result<Rc<Self>> {
         <Self as AttachmentReadWrite<AnsiPstFile>>::read(message, sub_node, prop_ids)
@@ -413,15 +794,71 @@ impl Attachment for AnsiAttachment {
     fn data(&self) -> Option<&AttachmentData> {
         self.inner.data.as_ref()
     }
+
+    fn open_embedded_message(&self, prop_ids: Option<&[u16]>) -> io::Result<Rc<dyn Message>> {
+        Ok(self.inner.open_embedded_message(prop_ids)?)
+    }
 }
 
 impl AttachmentReadWrite<AnsiPstFile> for AnsiAttachment {
     fn read(
         message: Rc<AnsiMessage>,
-        sub_node: NodeId,
+        sub_node: AttachmentId,
         prop_ids: Option<&[u16]>,
     ) -> io::Result<Rc<Self>> {
         let inner = AttachmentInner::read(message, sub_node, prop_ids)?;
         Ok(Rc::new(Self { inner }))
     }
+
+    fn read_data_block(
+        message: &AnsiMessage,
+        sub_node: AttachmentId,
+    ) -> io::Result<Option<AttachmentDataBlock>> {
+        AttachmentInner::<AnsiPstFile>::read_data_block(message, sub_node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attachment_properties() {
+        let builder = AttachmentBuilder::by_value("photo.png", Some("image/png"), vec![1, 2, 3]);
+        let mut properties = AttachmentProperties {
+            properties: builder.properties().clone(),
+        };
+        assert_eq!(
+            properties.attachment_method().unwrap(),
+            AttachmentMethod::ByValue
+        );
+        assert_eq!(
+            properties.rendering_position().unwrap(),
+            RenderingPosition::NotRendered
+        );
+        assert!(!properties.is_hidden());
+        assert!(!properties.is_inline());
+
+        properties
+            .properties
+            .insert(0x3714, PropertyValue::Integer32(ATTACH_RENDERED_IN_BODY));
+        properties
+            .properties
+            .insert(0x7FFE, PropertyValue::Boolean(true));
+        properties
+            .properties
+            .insert(0x370B, PropertyValue::Integer32(12));
+        assert!(properties.is_hidden());
+        assert!(properties.is_inline());
+        assert_eq!(
+            properties.rendering_position().unwrap(),
+            RenderingPosition::Offset(12)
+        );
+
+        properties
+            .properties
+            .insert(0x3705, PropertyValue::Integer32(3));
+        assert_eq!(properties.raw_attachment_method().unwrap(), 3);
+        assert!(properties.attachment_method().is_err());
+    }
 }
@@ -0,0 +1,161 @@
+//! Report which attachments in a folder share their data blocks, so the savings from a
+//! single-instance storage scheme can be estimated before the messages are exported.
+//!
+//! Attachments are grouped by the BID of the block which holds their data, see
+//! [`AttachmentDataBlock`]. A PST only shares blocks between copies of the same attachment, e.g.
+//! after [`PstFileLockGuard::copy_message`](crate::PstFileLockGuard::copy_message), so
+//! attachments with identical content which were written separately are reported as unique.
+
+use std::{collections::BTreeMap, io};
+
+use super::{
+    attachment::AttachmentDataBlock,
+    folder::Folder,
+    object_id::{AttachmentId, MessageId},
+};
+use crate::ndb::node_id::NodeId;
+
+/// The attachments whose data is held by one block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttachmentBlockGroup {
+    data_block: AttachmentDataBlock,
+    attachments: Vec<(MessageId, AttachmentId)>,
+}
+
+impl AttachmentBlockGroup {
+    pub fn block(&self) -> u64 {
+        self.data_block.block()
+    }
+
+    /// Size of the attachment data in bytes, which is only stored once in the PST.
+    pub fn size(&self) -> u64 {
+        self.data_block.size()
+    }
+
+    pub fn attachments(&self) -> &[(MessageId, AttachmentId)] {
+        &self.attachments
+    }
+
+    /// Whether more than one attachment points at the block.
+    pub fn is_shared(&self) -> bool {
+        self.attachments.len() > 1
+    }
+
+    /// Bytes written when every attachment in the group is exported separately.
+    pub fn export_size(&self) -> u64 {
+        self.size() * self.attachments.len() as u64
+    }
+}
+
+/// The attachments of every message in the contents table of a folder, grouped by data block.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AttachmentSharingReport {
+    groups: BTreeMap<u64, AttachmentBlockGroup>,
+}
+
+impl AttachmentSharingReport {
+    /// Find the data block of each attachment on the messages in the contents table of
+    /// `folder`. Attachments without any data, e.g. attached by reference, are left out.
+    pub fn build<F: Folder + ?Sized>(folder: &F) -> io::Result<Self> {
+        let mut report = Self::default();
+        let Some(contents_table) = folder.contents_table() else {
+            return Ok(report);
+        };
+
+        for row in contents_table.rows_matrix() {
+            let message_id = MessageId::try_from(NodeId::from(u32::from(row.id())))?;
+            let message = folder.open_message_at_row(row, Some(&[]))?;
+            for summary in message.attachment_summaries()? {
+                let attachment_id = summary.sub_node();
+                if let Some(data_block) = message.attachment_data_block(attachment_id)? {
+                    report.insert(data_block, message_id, attachment_id);
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    fn insert(
+        &mut self,
+        data_block: AttachmentDataBlock,
+        message_id: MessageId,
+        attachment_id: AttachmentId,
+    ) {
+        self.groups
+            .entry(data_block.block())
+            .or_insert_with(|| AttachmentBlockGroup {
+                data_block,
+                attachments: vec![],
+            })
+            .attachments
+            .push((message_id, attachment_id));
+    }
+
+    /// Every data block, sorted by BID.
+    pub fn groups(&self) -> impl Iterator<Item = &AttachmentBlockGroup> {
+        self.groups.values()
+    }
+
+    /// The data blocks which more than one attachment points at.
+    pub fn shared(&self) -> impl Iterator<Item = &AttachmentBlockGroup> {
+        self.groups().filter(|group| group.is_shared())
+    }
+
+    /// The data blocks which belong to a single attachment.
+    pub fn unique(&self) -> impl Iterator<Item = &AttachmentBlockGroup> {
+        self.groups().filter(|group| !group.is_shared())
+    }
+
+    pub fn attachment_count(&self) -> usize {
+        self.groups().map(|group| group.attachments.len()).sum()
+    }
+
+    /// Bytes of attachment data stored in the PST, counting each shared block once.
+    pub fn stored_size(&self) -> u64 {
+        self.groups().map(AttachmentBlockGroup::size).sum()
+    }
+
+    /// Bytes written when every attachment is exported separately.
+    pub fn export_size(&self) -> u64 {
+        self.groups().map(AttachmentBlockGroup::export_size).sum()
+    }
+
+    /// Bytes which an export with single-instance storage would save, compared to
+    /// [`AttachmentSharingReport::export_size`].
+    pub fn savings(&self) -> u64 {
+        self.export_size() - self.stored_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ndb::node_id::NodeIdType;
+
+    #[test]
+    fn test_attachment_sharing_totals() {
+        let message = |index| {
+            MessageId::try_from(NodeId::new(NodeIdType::NormalMessage, index).unwrap()).unwrap()
+        };
+        let attachment = |index| {
+            AttachmentId::try_from(NodeId::new(NodeIdType::Attachment, index).unwrap()).unwrap()
+        };
+        let shared = AttachmentDataBlock::new(0x100, 1000);
+        let unique = AttachmentDataBlock::new(0x104, 300);
+
+        let mut report = AttachmentSharingReport::default();
+        report.insert(shared, message(0x400), attachment(0x1));
+        report.insert(unique, message(0x400), attachment(0x2));
+        report.insert(shared, message(0x401), attachment(0x1));
+        report.insert(shared, message(0x402), attachment(0x1));
+
+        assert_eq!(report.attachment_count(), 4);
+        assert_eq!(report.shared().count(), 1);
+        assert_eq!(report.unique().count(), 1);
+        assert_eq!(report.shared().next().unwrap().attachments().len(), 3);
+        assert_eq!(report.stored_size(), 1300);
+        assert_eq!(report.export_size(), 3300);
+        assert_eq!(report.savings(), 2000);
+    }
+}
@@ -0,0 +1,535 @@
+//! The Outlook [Category List](https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxocfg/a7bd6c3c-bc7c-4f6b-9f26-a5a9d2dc9bb4),
+//! also known as the Master Category List, with the name and color of each category which can be
+//! assigned to items.
+//!
+//! Outlook saves the list as XML in `PidTagRoamingXmlStream` of a folder associated information
+//! (FAI) message in the calendar folder, or in the root folder if there is no calendar.
+//! [`Store::categories`](super::store::Store::categories) reads it, and
+//! [`PstFileLockGuard::save_categories`](crate::PstFileLockGuard::save_categories) writes it back.
+//!
+//! The categories assigned to an item are the names in its `PidNameKeywords` property, see
+//! [`MessageProperties::categories`](super::message::MessageProperties::categories) and
+//! [`MessageBuilder::set_categories`](super::message::MessageBuilder::set_categories).
+
+use std::{collections::BTreeMap, fmt::Write, io};
+
+use super::{
+    folder::Folder,
+    message::{MessageBuilder, MSGFLAG_ASSOCIATED, MSGFLAG_READ},
+    MessagingError,
+};
+use crate::{
+    export::xml::escape_xml,
+    ltp::prop_context::{BinaryValue, GuidValue, PropertyValue},
+};
+
+/// `PidTagMessageClass` of the FAI message which holds the [`CategoryList`].
+pub const CATEGORY_LIST_MESSAGE_CLASS: &str = "IPM.Configuration.CategoryList";
+
+/// `PidTagRoamingDatatypes`
+const PID_TAG_ROAMING_DATATYPES: u16 = 0x7C06;
+/// `PidTagRoamingXmlStream`, which holds the XML of the category list in the FAI message.
+const PID_TAG_ROAMING_XML_STREAM: u16 = 0x7C08;
+
+/// `PidTagRoamingDatatypes` flag for a message with a `PidTagRoamingXmlStream`.
+const ROAMING_DATATYPE_XML_STREAM: i32 = 0x0000_0004;
+
+/// Name of the `PidNameKeywords` named property in `PS_PUBLIC_STRINGS`.
+pub const KEYWORDS_PROPERTY_NAME: &str = "Keywords";
+
+/// `color` of a category which does not have a color.
+pub const CATEGORY_COLOR_NONE: i32 = -1;
+
+/// Names of the preset colors, indexed by the `color` of a category.
+pub const CATEGORY_COLOR_NAMES: [&str; 25] = [
+    "Red",
+    "Orange",
+    "Peach",
+    "Yellow",
+    "Green",
+    "Teal",
+    "Olive",
+    "Blue",
+    "Purple",
+    "Maroon",
+    "Steel",
+    "Dark Steel",
+    "Gray",
+    "Dark Gray",
+    "Black",
+    "Dark Red",
+    "Dark Orange",
+    "Dark Peach",
+    "Dark Yellow",
+    "Dark Green",
+    "Dark Teal",
+    "Dark Olive",
+    "Dark Blue",
+    "Dark Purple",
+    "Dark Maroon",
+];
+
+const CATEGORY_LIST_NAMESPACE: &str = "CategoryList.xsd";
+
+/// A category in the [`CategoryList`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Category {
+    name: String,
+    color: i32,
+    keyboard_shortcut: i32,
+    guid: Option<GuidValue>,
+    attributes: BTreeMap<String, String>,
+}
+
+impl Category {
+    /// A new category with one of the preset colors in [`CATEGORY_COLOR_NAMES`], or
+    /// [`CATEGORY_COLOR_NONE`]. The GUID is derived from the name, so adding the same category to
+    /// another list gives it the same GUID.
+    pub fn new(name: impl Into<String>, color: i32) -> Self {
+        let name = name.into();
+        let guid = GuidValue::from_hash(name.to_lowercase().as_bytes());
+        Self {
+            name,
+            color,
+            keyboard_shortcut: 0,
+            guid: Some(guid),
+            attributes: Default::default(),
+        }
+    }
+
+    /// Assign `Ctrl+F2` through `Ctrl+F12` with 1 through 11, or no shortcut with 0.
+    pub fn with_keyboard_shortcut(mut self, keyboard_shortcut: i32) -> Self {
+        self.keyboard_shortcut = keyboard_shortcut;
+        self
+    }
+
+    pub fn with_guid(mut self, guid: GuidValue) -> Self {
+        self.guid = Some(guid);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Index of the preset color in [`CATEGORY_COLOR_NAMES`], or [`CATEGORY_COLOR_NONE`].
+    pub fn color(&self) -> i32 {
+        self.color
+    }
+
+    /// The name of the preset [`Self::color`], or `None` if the category does not have a color.
+    pub fn color_name(&self) -> Option<&'static str> {
+        usize::try_from(self.color)
+            .ok()
+            .and_then(|color| CATEGORY_COLOR_NAMES.get(color).copied())
+    }
+
+    pub fn keyboard_shortcut(&self) -> i32 {
+        self.keyboard_shortcut
+    }
+
+    pub fn guid(&self) -> Option<GuidValue> {
+        self.guid
+    }
+
+    /// Any other attribute of the `category` element, e.g. `lastTimeUsed`, which is kept as it
+    /// was read so saving the list does not lose it.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+
+    fn from_element(element: XmlElement) -> io::Result<Self> {
+        let mut name = None;
+        let mut color = CATEGORY_COLOR_NONE;
+        let mut keyboard_shortcut = 0;
+        let mut guid = None;
+        let mut attributes = BTreeMap::new();
+        for (key, value) in element.attributes {
+            let invalid = || invalid_category_list(format!("invalid {key}: {value}"));
+            match key.as_str() {
+                "name" => name = Some(value),
+                "color" => color = value.trim().parse().map_err(|_| invalid())?,
+                "keyboardShortcut" => {
+                    keyboard_shortcut = value.trim().parse().map_err(|_| invalid())?
+                }
+                "guid" => guid = Some(value.trim().parse().map_err(|_| invalid())?),
+                _ => {
+                    attributes.insert(key, value);
+                }
+            }
+        }
+
+        Ok(Self {
+            name: name.ok_or_else(|| invalid_category_list("category without a name"))?,
+            color,
+            keyboard_shortcut,
+            guid,
+            attributes,
+        })
+    }
+
+    fn write_element(&self, xml: &mut String) {
+        let _ = write!(
+            xml,
+            "<category name=\"{}\" color=\"{}\" keyboardShortcut=\"{}\"",
+            escape_xml(&self.name),
+            self.color,
+            self.keyboard_shortcut
+        );
+        for (key, value) in self.attributes.iter() {
+            let _ = write!(xml, " {key}=\"{}\"", escape_xml(value));
+        }
+        if let Some(guid) = self.guid {
+            let _ = write!(xml, " guid=\"{guid}\"");
+        }
+        xml.push_str("/>\r\n");
+    }
+}
+
+/// The categories which Outlook offers to assign to items, in the order they were saved.
+/// Category names are not case sensitive.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CategoryList {
+    attributes: BTreeMap<String, String>,
+    categories: Vec<Category>,
+}
+
+impl CategoryList {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Read the list from the FAI message in `folder`. Returns `None` if there is no list in
+    /// the folder, and fails if the saved list cannot be read.
+    pub fn load<F: Folder + ?Sized>(folder: &F) -> io::Result<Option<Self>> {
+        let Some(associated_table) = folder.associated_table() else {
+            return Ok(None);
+        };
+
+        for row in associated_table.rows_matrix() {
+            let message =
+                folder.open_message_at_row(row, Some(&[0x001A, PID_TAG_ROAMING_XML_STREAM]))?;
+            let properties = message.properties();
+            if !is_category_list_message_class(properties.get(0x001A)) {
+                continue;
+            }
+
+            return match properties.get(PID_TAG_ROAMING_XML_STREAM) {
+                Some(PropertyValue::Binary(value)) => Self::read(value.buffer()).map(Some),
+                _ => Err(invalid_category_list("missing PidTagRoamingXmlStream").into()),
+            };
+        }
+
+        Ok(None)
+    }
+
+    pub fn categories(&self) -> &[Category] {
+        &self.categories
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Category> {
+        self.categories
+            .iter()
+            .find(|category| category.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Add `category` to the end of the list, or replace the category with the same name in
+    /// place and return it.
+    pub fn add(&mut self, category: Category) -> Option<Category> {
+        match self
+            .categories
+            .iter_mut()
+            .find(|existing| existing.name.eq_ignore_ascii_case(&category.name))
+        {
+            Some(existing) => Some(std::mem::replace(existing, category)),
+            None => {
+                self.categories.push(category);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Category> {
+        let index = self
+            .categories
+            .iter()
+            .position(|category| category.name.eq_ignore_ascii_case(name))?;
+        Some(self.categories.remove(index))
+    }
+
+    /// The name of the category which Outlook assigns with the Quick Click, if there is one.
+    pub fn default_category(&self) -> Option<&str> {
+        self.attribute("default")
+    }
+
+    /// Any other attribute of the `categories` element, e.g. `lastSavedTime`.
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.get(name).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.categories.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.categories.is_empty()
+    }
+
+    /// Parse the XML from `PidTagRoamingXmlStream`, which may start with a UTF-8 or UTF-16 byte
+    /// order mark.
+    pub fn read(data: &[u8]) -> io::Result<Self> {
+        let xml = decode_xml(data)?;
+        let mut elements = read_xml_elements(&xml)?.into_iter();
+        let root = elements
+            .next()
+            .filter(|element| element.name == "categories")
+            .ok_or_else(|| invalid_category_list("missing categories element"))?;
+
+        let attributes = root
+            .attributes
+            .into_iter()
+            .filter(|(key, _)| key != "xmlns")
+            .collect();
+        let categories = elements
+            .filter(|element| element.name == "category")
+            .map(Category::from_element)
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self {
+            attributes,
+            categories,
+        })
+    }
+
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\"?>\r\n<categories");
+        for (key, value) in self.attributes.iter() {
+            let _ = write!(xml, " {key}=\"{}\"", escape_xml(value));
+        }
+        let _ = write!(xml, " xmlns=\"{CATEGORY_LIST_NAMESPACE}\">\r\n");
+        for category in self.categories.iter() {
+            category.write_element(&mut xml);
+        }
+        xml.push_str("</categories>\r\n");
+        xml
+    }
+
+    /// Make the FAI message which holds the list, for
+    /// [`PstFileLockGuard::save_categories`](crate::PstFileLockGuard::save_categories).
+    pub fn to_message_builder(&self) -> MessageBuilder {
+        let mut builder = MessageBuilder::new(CATEGORY_LIST_MESSAGE_CLASS);
+        builder.set_property(
+            0x0E07,
+            PropertyValue::Integer32(MSGFLAG_READ | MSGFLAG_ASSOCIATED),
+        );
+        builder.set_property(
+            PID_TAG_ROAMING_DATATYPES,
+            PropertyValue::Integer32(ROAMING_DATATYPE_XML_STREAM),
+        );
+        builder.set_property(
+            PID_TAG_ROAMING_XML_STREAM,
+            PropertyValue::Binary(BinaryValue::new(self.to_xml().into_bytes())),
+        );
+        builder
+    }
+}
+
+fn is_category_list_message_class(value: Option<&PropertyValue>) -> bool {
+    let message_class = match value {
+        Some(PropertyValue::String8(value)) => value.to_string(),
+        Some(PropertyValue::Unicode(value)) => value.to_string(),
+        _ => return false,
+    };
+    message_class.eq_ignore_ascii_case(CATEGORY_LIST_MESSAGE_CLASS)
+}
+
+/// Whether the properties of an FAI message, read with
+/// [`read_property_context`](crate::ltp::prop_context::read_property_context), are those of the
+/// saved [`CategoryList`].
+pub(crate) fn is_category_list_message(properties: &BTreeMap<u16, PropertyValue>) -> bool {
+    is_category_list_message_class(properties.get(&0x001A))
+}
+
+fn invalid_category_list(message: impl Into<String>) -> MessagingError {
+    MessagingError::InvalidCategoryList(message.into())
+}
+
+fn decode_xml(data: &[u8]) -> io::Result<String> {
+    if let Some(data) = data.strip_prefix(b"\xFF\xFE") {
+        let data: Vec<_> = data
+            .chunks_exact(2)
+            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+            .collect();
+        return String::from_utf16(&data)
+            .map_err(|_| invalid_category_list("invalid UTF-16").into());
+    }
+    let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+    String::from_utf8(data.to_vec()).map_err(|_| invalid_category_list("invalid UTF-8").into())
+}
+
+/// A start tag or empty element tag, with its attribute values unescaped.
+struct XmlElement {
+    name: String,
+    attributes: Vec<(String, String)>,
+}
+
+/// Read the start tags and empty element tags of `xml` in document order. End tags, text,
+/// comments, and processing instructions are skipped, which is all the category list needs.
+fn read_xml_elements(xml: &str) -> io::Result<Vec<XmlElement>> {
+    let mut elements = vec![];
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let skip_to = if rest.starts_with("!--") {
+            Some("-->")
+        } else if rest.starts_with('?') {
+            Some("?>")
+        } else if rest.starts_with('!') || rest.starts_with('/') {
+            Some(">")
+        } else {
+            None
+        };
+        if let Some(end) = skip_to {
+            let index = rest
+                .find(end)
+                .ok_or_else(|| invalid_category_list("unterminated tag"))?;
+            rest = &rest[index + end.len()..];
+            continue;
+        }
+
+        let name_end = rest
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .ok_or_else(|| invalid_category_list("unterminated tag"))?;
+        let name = rest[..name_end].to_string();
+        rest = &rest[name_end..];
+
+        let mut attributes = vec![];
+        loop {
+            rest = rest.trim_start();
+            if let Some(tail) = rest.strip_prefix("/>").or_else(|| rest.strip_prefix('>')) {
+                rest = tail;
+                break;
+            }
+
+            let equals = rest
+                .find('=')
+                .ok_or_else(|| invalid_category_list(format!("invalid attribute in {name}")))?;
+            let key = rest[..equals].trim().to_string();
+            rest = rest[equals + 1..].trim_start();
+            let quote = rest
+                .chars()
+                .next()
+                .filter(|quote| *quote == '"' || *quote == '\'')
+                .ok_or_else(|| invalid_category_list(format!("unquoted attribute {key}")))?;
+            rest = &rest[1..];
+            let value_end = rest
+                .find(quote)
+                .ok_or_else(|| invalid_category_list(format!("unterminated attribute {key}")))?;
+            attributes.push((key, unescape_xml(&rest[..value_end])?));
+            rest = &rest[value_end + 1..];
+        }
+
+        elements.push(XmlElement { name, attributes });
+    }
+    Ok(elements)
+}
+
+fn unescape_xml(value: &str) -> io::Result<String> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let end = rest
+            .find(';')
+            .ok_or_else(|| invalid_category_list(format!("invalid entity in {value}")))?;
+        let entity = &rest[..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        unescaped.push(c.ok_or_else(|| invalid_category_list(format!("invalid entity {entity}")))?);
+        rest = &rest[end + 1..];
+    }
+    unescaped.push_str(rest);
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CATEGORY_LIST: &str = "\u{FEFF}<?xml version=\"1.0\"?>\r
+<categories default=\"Red Category\" lastSavedSession=\"2\" lastSavedTime=\"2024-01-01T00:00:00.000\" xmlns=\"CategoryList.xsd\">\r
+<category name=\"Red Category\" color=\"0\" keyboardShortcut=\"0\" usageCount=\"3\" guid=\"{A5D4A1D6-53F3-4F1B-9C1E-6F5D0E6C2F10}\" renameOnFirstUse=\"1\"/>\r
+<!-- a comment with <category name=\"Skipped\"/> inside -->\r
+<category name=\"R&amp;D &#x2013; &quot;Q1&quot;\" color=\"-1\" keyboardShortcut=\"3\" guid=\"{0F9D3B9C-1D2E-4C55-8E6A-4B6B2C1D0E11}\"></category>\r
+</categories>\r
+";
+
+    #[test]
+    fn test_read_category_list() {
+        let categories = CategoryList::read(CATEGORY_LIST.as_bytes()).unwrap();
+        assert_eq!(categories.len(), 2);
+        assert_eq!(categories.default_category(), Some("Red Category"));
+        assert_eq!(categories.attribute("lastSavedSession"), Some("2"));
+
+        let red = categories.find("red category").unwrap();
+        assert_eq!(red.color(), 0);
+        assert_eq!(red.color_name(), Some("Red"));
+        assert_eq!(red.attribute("usageCount"), Some("3"));
+        assert_eq!(
+            red.guid().unwrap().to_string(),
+            "{A5D4A1D6-53F3-4F1B-9C1E-6F5D0E6C2F10}"
+        );
+
+        let other = &categories.categories()[1];
+        assert_eq!(other.name(), "R&D \u{2013} \"Q1\"");
+        assert_eq!(other.color_name(), None);
+        assert_eq!(other.keyboard_shortcut(), 3);
+
+        assert!(CategoryList::read(b"<category name=\"Orphan\"/>").is_err());
+        assert!(CategoryList::read(b"<categories><category color=\"1\"/>").is_err());
+    }
+
+    #[test]
+    fn test_write_category_list() {
+        let mut categories = CategoryList::read(CATEGORY_LIST.as_bytes()).unwrap();
+        assert_eq!(categories.add(Category::new("Blue Category", 7)), None);
+        let replaced = categories
+            .add(Category::new("RED CATEGORY", 15).with_keyboard_shortcut(1))
+            .unwrap();
+        assert_eq!(replaced.color(), 0);
+        assert_eq!(categories.len(), 3);
+        assert_eq!(categories.categories()[0].color_name(), Some("Dark Red"));
+        assert_eq!(
+            Category::new("blue category", 7).guid(),
+            categories.find("Blue Category").unwrap().guid()
+        );
+
+        let xml = categories.to_xml();
+        assert!(xml.contains("xmlns=\"CategoryList.xsd\""));
+        assert_eq!(CategoryList::read(xml.as_bytes()).unwrap(), categories);
+
+        let utf16: Vec<_> = [0xFF, 0xFE]
+            .into_iter()
+            .chain(xml.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        assert_eq!(CategoryList::read(&utf16).unwrap(), categories);
+
+        assert!(categories.remove("r&d \u{2013} \"q1\"").is_some());
+        assert_eq!(categories.len(), 2);
+
+        let builder = categories.to_message_builder();
+        assert!(is_category_list_message(builder.properties()));
+    }
+}
@@ -0,0 +1,228 @@
+//! Relaxed handling of messages which are missing some of the properties that
+//! [MS-PST](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/1042af37-aaa4-4edc-bffd-90a1ede24188)
+//! requires on every message.
+//!
+//! The accessors on [`MessageProperties`] return a [`MessagingError`](super::MessagingError) when
+//! one of these properties is missing or has the wrong type, but real PST files contain
+//! partially-written items which are still worth exporting. [`RelaxedMessageProperties`] returns
+//! `None` for those properties instead, and [`MessageCompleteness`] lists which ones were
+//! affected.
+
+use super::{
+    message::MessageProperties,
+    message_flags::{MessageFlags, MessageStatus},
+};
+use crate::ltp::{prop_context::PropertyValue, prop_type::PropertyType};
+
+/// A property which every message is required to have.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MandatoryProperty {
+    /// `PidTagMessageClass`
+    MessageClass,
+    /// `PidTagMessageFlags`
+    MessageFlags,
+    /// `PidTagMessageSize`
+    MessageSize,
+    /// `PidTagMessageStatus`
+    MessageStatus,
+    /// `PidTagCreationTime`
+    CreationTime,
+    /// `PidTagLastModificationTime`
+    LastModificationTime,
+    /// `PidTagSearchKey`
+    SearchKey,
+}
+
+impl MandatoryProperty {
+    pub const ALL: [Self; 7] = [
+        Self::MessageClass,
+        Self::MessageFlags,
+        Self::MessageSize,
+        Self::MessageStatus,
+        Self::CreationTime,
+        Self::LastModificationTime,
+        Self::SearchKey,
+    ];
+
+    pub fn prop_id(&self) -> u16 {
+        match self {
+            Self::MessageClass => 0x001A,
+            Self::MessageFlags => 0x0E07,
+            Self::MessageSize => 0x0E08,
+            Self::MessageStatus => 0x0E17,
+            Self::CreationTime => 0x3007,
+            Self::LastModificationTime => 0x3008,
+            Self::SearchKey => 0x300B,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::MessageClass => "PidTagMessageClass",
+            Self::MessageFlags => "PidTagMessageFlags",
+            Self::MessageSize => "PidTagMessageSize",
+            Self::MessageStatus => "PidTagMessageStatus",
+            Self::CreationTime => "PidTagCreationTime",
+            Self::LastModificationTime => "PidTagLastModificationTime",
+            Self::SearchKey => "PidTagSearchKey",
+        }
+    }
+
+    fn has_valid_type(&self, value: &PropertyValue) -> bool {
+        matches!(
+            (self, value),
+            (
+                Self::MessageClass,
+                PropertyValue::String8(_) | PropertyValue::Unicode(_)
+            ) | (
+                Self::MessageFlags | Self::MessageSize | Self::MessageStatus,
+                PropertyValue::Integer32(_)
+            ) | (
+                Self::CreationTime | Self::LastModificationTime,
+                PropertyValue::Time(_)
+            ) | (Self::SearchKey, PropertyValue::Binary(_))
+        )
+    }
+}
+
+/// Why a [`MandatoryProperty`] could not be read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropertyProblem {
+    Missing,
+    /// The property has a value of this type instead.
+    InvalidType(PropertyType),
+}
+
+/// Which of the [`MandatoryProperty`] values a message is missing, or has with the wrong type.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MessageCompleteness {
+    problems: Vec<(MandatoryProperty, PropertyProblem)>,
+}
+
+impl MessageCompleteness {
+    /// Check each [`MandatoryProperty`] with the value returned by `get` for its property ID.
+    pub fn check<'a>(get: impl Fn(u16) -> Option<&'a PropertyValue>) -> Self {
+        let problems = MandatoryProperty::ALL
+            .into_iter()
+            .filter_map(|property| match get(property.prop_id()) {
+                None => Some((property, PropertyProblem::Missing)),
+                Some(value) if !property.has_valid_type(value) => Some((
+                    property,
+                    PropertyProblem::InvalidType(PropertyType::from(value)),
+                )),
+                Some(_) => None,
+            })
+            .collect();
+        Self { problems }
+    }
+
+    /// Whether every [`MandatoryProperty`] is present with the expected type.
+    pub fn is_complete(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn problems(&self) -> &[(MandatoryProperty, PropertyProblem)] {
+        &self.problems
+    }
+
+    pub fn problem(&self, property: MandatoryProperty) -> Option<PropertyProblem> {
+        self.problems
+            .iter()
+            .find(|(candidate, _)| *candidate == property)
+            .map(|(_, problem)| *problem)
+    }
+
+    pub fn missing(&self) -> impl Iterator<Item = MandatoryProperty> + '_ {
+        self.problems
+            .iter()
+            .filter(|(_, problem)| *problem == PropertyProblem::Missing)
+            .map(|(property, _)| *property)
+    }
+}
+
+/// Accessors for the [`MandatoryProperty`] values of a message which return `None` if the
+/// property is missing or has the wrong type, see [`MessageProperties::relaxed`].
+#[derive(Clone, Copy, Debug)]
+pub struct RelaxedMessageProperties<'a> {
+    properties: &'a MessageProperties,
+}
+
+impl<'a> RelaxedMessageProperties<'a> {
+    pub(crate) fn new(properties: &'a MessageProperties) -> Self {
+        Self { properties }
+    }
+
+    pub fn properties(&self) -> &'a MessageProperties {
+        self.properties
+    }
+
+    pub fn completeness(&self) -> MessageCompleteness {
+        self.properties.completeness()
+    }
+
+    pub fn message_class(&self) -> Option<String> {
+        self.properties.message_class().ok()
+    }
+
+    pub fn message_flags(&self) -> Option<MessageFlags> {
+        self.properties.message_flags().ok()
+    }
+
+    pub fn message_size(&self) -> Option<i32> {
+        self.properties.message_size().ok()
+    }
+
+    pub fn message_status(&self) -> Option<MessageStatus> {
+        self.properties.message_status().ok()
+    }
+
+    pub fn creation_time(&self) -> Option<i64> {
+        self.properties.creation_time().ok()
+    }
+
+    pub fn last_modification_time(&self) -> Option<i64> {
+        self.properties.last_modification_time().ok()
+    }
+
+    pub fn search_key(&self) -> Option<&'a [u8]> {
+        self.properties.search_key().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_message_completeness() {
+        let mut properties = BTreeMap::new();
+        properties.insert(0x0E07, PropertyValue::Integer32(0));
+        properties.insert(0x0E08, PropertyValue::Integer64(100));
+        properties.insert(0x0E17, PropertyValue::Integer32(0));
+        properties.insert(0x3007, PropertyValue::Time(0));
+        properties.insert(0x3008, PropertyValue::Time(0));
+
+        let completeness = MessageCompleteness::check(|prop_id| properties.get(&prop_id));
+        assert!(!completeness.is_complete());
+        assert_eq!(
+            completeness.missing().collect::<Vec<_>>(),
+            [
+                MandatoryProperty::MessageClass,
+                MandatoryProperty::SearchKey
+            ]
+        );
+        assert_eq!(
+            completeness.problem(MandatoryProperty::MessageSize),
+            Some(PropertyProblem::InvalidType(PropertyType::Integer64))
+        );
+        assert_eq!(completeness.problem(MandatoryProperty::MessageFlags), None);
+
+        properties.insert(0x001A, PropertyValue::Integer32(0));
+        properties.insert(0x0E08, PropertyValue::Integer32(100));
+        properties.insert(0x300B, PropertyValue::Integer32(0));
+        let completeness = MessageCompleteness::check(|prop_id| properties.get(&prop_id));
+        assert_eq!(completeness.missing().count(), 0);
+        assert_eq!(completeness.problems().len(), 2);
+    }
+}
@@ -0,0 +1,277 @@
+//! [PidTagConversationIndex](https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxomsg/9e994fbb-b839-495f-84e3-2c8c02c7dd9b),
+//! which threads the messages of a conversation.
+//!
+//! The index starts with a 22 byte header block, which holds the time the conversation started
+//! and a GUID which identifies it. Each reply copies the index of the message it replies to and
+//! appends a 5 byte response level, so sorting the messages of a conversation by their index puts
+//! every reply after the message it replies to.
+
+use std::io;
+
+use super::MessagingError;
+use crate::ltp::{
+    prop_context::{BinaryValue, GuidValue, PropertyValue},
+    prop_type::PropertyType,
+};
+
+/// `PidTagConversationIndex`
+pub const PID_TAG_CONVERSATION_INDEX: u16 = 0x0071;
+
+const HEADER_SIZE: usize = 22;
+const RESPONSE_LEVEL_SIZE: usize = 5;
+
+/// The header only keeps the 6 most significant bytes of the `FILETIME`.
+const HEADER_TIME_SHIFT: u32 = 16;
+/// Bits of a time delta which do not fit in `TimeDelta` with a `DeltaCode` of 0.
+const LONG_DELTA_MASK: u64 = 0x00FE_0000_0000_0000;
+const SHORT_DELTA_SHIFT: u32 = 18;
+const LONG_DELTA_SHIFT: u32 = 23;
+const TIME_DELTA_MASK: u32 = 0x7FFF_FFFF;
+
+/// A response level, which is appended to the index of the message being replied to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResponseLevel {
+    delta_code: bool,
+    time_delta: u32,
+    random: u8,
+}
+
+impl ResponseLevel {
+    /// A response level for a reply sent `delta` `FILETIME` ticks after the time in the header
+    /// block. `random` distinguishes replies sent at almost the same time.
+    pub fn new(delta: i64, random: u8) -> Self {
+        let delta = delta.max(0) as u64;
+        let delta_code = delta & LONG_DELTA_MASK != 0;
+        let shift = if delta_code {
+            LONG_DELTA_SHIFT
+        } else {
+            SHORT_DELTA_SHIFT
+        };
+        Self {
+            delta_code,
+            time_delta: (delta >> shift) as u32 & TIME_DELTA_MASK,
+            random,
+        }
+    }
+
+    fn read(data: [u8; RESPONSE_LEVEL_SIZE]) -> Self {
+        let value = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        Self {
+            delta_code: value & !TIME_DELTA_MASK != 0,
+            time_delta: value & TIME_DELTA_MASK,
+            random: data[4],
+        }
+    }
+
+    fn write(&self, data: &mut Vec<u8>) {
+        let delta_code = if self.delta_code { !TIME_DELTA_MASK } else { 0 };
+        data.extend_from_slice(&(delta_code | self.time_delta).to_be_bytes());
+        data.push(self.random);
+    }
+
+    /// `DeltaCode`: whether [`Self::time_delta`] is in units of 2^23 ticks instead of 2^18.
+    pub fn delta_code(&self) -> bool {
+        self.delta_code
+    }
+
+    /// `TimeDelta`: the 31 bit time since the header block, in the units of [`Self::delta_code`].
+    pub fn time_delta(&self) -> u32 {
+        self.time_delta
+    }
+
+    pub fn random(&self) -> u8 {
+        self.random
+    }
+
+    /// The time since the header block in `FILETIME` ticks, without the bits which were dropped.
+    pub fn delta(&self) -> i64 {
+        let shift = if self.delta_code {
+            LONG_DELTA_SHIFT
+        } else {
+            SHORT_DELTA_SHIFT
+        };
+        i64::from(self.time_delta) << shift
+    }
+}
+
+/// A parsed `PidTagConversationIndex`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConversationIndex {
+    time: i64,
+    guid: GuidValue,
+    response_levels: Vec<ResponseLevel>,
+}
+
+impl ConversationIndex {
+    /// The index of the first message in a new conversation, sent at `time`. The low 16 bits of
+    /// the `FILETIME` do not fit in the header, so they are dropped.
+    pub fn new(time: i64, guid: GuidValue) -> Self {
+        Self {
+            time: (time >> HEADER_TIME_SHIFT) << HEADER_TIME_SHIFT,
+            guid,
+            response_levels: vec![],
+        }
+    }
+
+    /// Parse an index which fills all of `data`. The reserved first byte of the header is read
+    /// as the most significant byte of the time, so it is not checked.
+    pub fn read(data: &[u8]) -> io::Result<Self> {
+        if data.len() < HEADER_SIZE || (data.len() - HEADER_SIZE) % RESPONSE_LEVEL_SIZE != 0 {
+            return Err(MessagingError::InvalidConversationIndexSize(data.len()).into());
+        }
+
+        let (header, response_levels) = data.split_at(HEADER_SIZE);
+        let mut time = [0; 8];
+        time[..6].copy_from_slice(&header[..6]);
+        let time = i64::from_be_bytes(time);
+
+        let mut guid = [0; 16];
+        guid.copy_from_slice(&header[6..]);
+        let guid = GuidValue::from_bytes_le(guid);
+
+        let response_levels = response_levels
+            .chunks_exact(RESPONSE_LEVEL_SIZE)
+            .map(|level| {
+                let mut data = [0; RESPONSE_LEVEL_SIZE];
+                data.copy_from_slice(level);
+                ResponseLevel::read(data)
+            })
+            .collect();
+
+        Ok(Self {
+            time,
+            guid,
+            response_levels,
+        })
+    }
+
+    /// The bytes of the index, which is stored as a `PtypBinary` value.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data =
+            Vec::with_capacity(HEADER_SIZE + self.response_levels.len() * RESPONSE_LEVEL_SIZE);
+        data.extend_from_slice(&self.time.to_be_bytes()[..6]);
+        data.extend_from_slice(&self.guid.to_bytes_le());
+        for level in self.response_levels.iter() {
+            level.write(&mut data);
+        }
+        data
+    }
+
+    /// The `FILETIME` when the conversation started, from the header block.
+    pub fn time(&self) -> i64 {
+        self.time
+    }
+
+    /// The GUID in the header block, which is the same for every message in the conversation.
+    pub fn guid(&self) -> GuidValue {
+        self.guid
+    }
+
+    /// One response level for each reply between the first message and this one.
+    pub fn response_levels(&self) -> &[ResponseLevel] {
+        &self.response_levels
+    }
+
+    /// The `FILETIME` of each reply in [`Self::response_levels`].
+    pub fn response_times(&self) -> impl Iterator<Item = i64> + '_ {
+        self.response_levels
+            .iter()
+            .map(|level| self.time + level.delta())
+    }
+
+    /// The index for a reply to this message, sent at `time`.
+    pub fn reply(&self, time: i64, random: u8) -> Self {
+        let mut reply = self.clone();
+        reply
+            .response_levels
+            .push(ResponseLevel::new(time - self.time, random));
+        reply
+    }
+
+    /// The index of the message this one replies to, or `None` if it started the conversation.
+    pub fn parent(&self) -> Option<Self> {
+        let (_, response_levels) = self.response_levels.split_last()?;
+        Some(Self {
+            response_levels: response_levels.to_vec(),
+            ..self.clone()
+        })
+    }
+
+    /// Whether this message replies to `other`, either directly or to one of its replies.
+    pub fn is_reply_to(&self, other: &Self) -> bool {
+        self.time == other.time
+            && self.guid == other.guid
+            && self.response_levels.len() > other.response_levels.len()
+            && self.response_levels.starts_with(&other.response_levels)
+    }
+}
+
+impl TryFrom<&PropertyValue> for ConversationIndex {
+    type Error = io::Error;
+
+    fn try_from(value: &PropertyValue) -> Result<Self, Self::Error> {
+        match value {
+            PropertyValue::Binary(value) => Self::read(value.buffer()),
+            invalid => {
+                Err(MessagingError::InvalidConversationIndex(PropertyType::from(invalid)).into())
+            }
+        }
+    }
+}
+
+impl From<&ConversationIndex> for PropertyValue {
+    fn from(value: &ConversationIndex) -> Self {
+        PropertyValue::Binary(BinaryValue::new(value.to_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUID: GuidValue = GuidValue::new(
+        0x0403_0201,
+        0x0605,
+        0x0807,
+        [0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F, 0x10],
+    );
+
+    #[test]
+    fn test_conversation_index() {
+        // 2024-01-01T00:00:00Z
+        let time = 133_485_408_000_000_000;
+        let index = ConversationIndex::new(time + 0x1234, GUID);
+        assert_eq!(index.time(), time & !0xFFFF);
+        let data = index.to_bytes();
+        assert_eq!(data.len(), HEADER_SIZE);
+        assert_eq!(data[0], 0x01);
+        assert_eq!(&data[6..10], [0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(ConversationIndex::read(&data).unwrap(), index);
+
+        // An hour later, then ten years later, which needs the long time delta.
+        let hour = 3600 * 10_000_000;
+        let reply = index.reply(index.time() + hour, 7);
+        let second = reply.reply(index.time() + 10 * 24 * 365 * hour, 9);
+        assert!(!reply.response_levels()[0].delta_code());
+        assert!(second.response_levels()[1].delta_code());
+
+        let data = second.to_bytes();
+        assert_eq!(data.len(), HEADER_SIZE + 2 * RESPONSE_LEVEL_SIZE);
+        let parsed = ConversationIndex::read(&data).unwrap();
+        assert_eq!(parsed, second);
+        let times: Vec<_> = parsed.response_times().collect();
+        assert!((index.time() + hour - times[0]) < 1 << SHORT_DELTA_SHIFT);
+        assert!((index.time() + 10 * 24 * 365 * hour - times[1]) < 1 << LONG_DELTA_SHIFT);
+        assert_eq!(parsed.response_levels()[1].random(), 9);
+
+        assert!(parsed.is_reply_to(&index));
+        assert!(parsed.is_reply_to(&reply));
+        assert!(!reply.is_reply_to(&parsed));
+        assert!(!index.is_reply_to(&index));
+        assert_eq!(parsed.parent().unwrap(), reply);
+        assert_eq!(index.parent(), None);
+
+        assert!(ConversationIndex::read(&data[..HEADER_SIZE + 3]).is_err());
+        assert!(ConversationIndex::read(&data[..HEADER_SIZE - 1]).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! Convert an [`EntryId`] to and from the `EntryId` and `HexEntryId` formats of the EWS
+//! [ConvertId](https://learn.microsoft.com/en-us/exchange/client-developer/web-service-reference/convertid-operation)
+//! operation, so items in a PST file can be correlated with IDs which were exported from the
+//! server, e.g. by passing them through `ConvertId` to get an `EwsId` or a Graph immutable ID.
+//!
+//! Both formats carry the same bytes as `PidTagEntryId`: the `rgbFlags`, the store record key,
+//! and the NID. They only differ in how the bytes are encoded as text.
+
+use std::io;
+
+use super::{
+    read_write::StoreKeyReadWrite,
+    store::{EntryId, StoreRecordKey},
+    MessagingError,
+};
+use crate::{
+    binary_text::{decode_base64, decode_hex, encode_base64},
+    ndb::node_id::NodeId,
+};
+
+/// The `IdFormat` of an ID in the `ConvertId` operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EwsIdFormat {
+    /// `EntryId`: the bytes of `PidTagEntryId`, base64 encoded.
+    EntryId,
+    /// `HexEntryId`: the bytes of `PidTagEntryId`, hex encoded with uppercase digits.
+    HexEntryId,
+}
+
+/// Build the [`EntryId`] for a node in the store with `record_key`, and encode it in `format`.
+pub fn make_ews_id(
+    record_key: &[u8; 16],
+    node_id: NodeId,
+    format: EwsIdFormat,
+) -> io::Result<String> {
+    let entry_id = EntryId::new(StoreRecordKey::new(*record_key), node_id);
+    to_ews_id(&entry_id, format)
+}
+
+/// Encode the bytes of `entry_id` in `format`.
+pub fn to_ews_id(entry_id: &EntryId, format: EwsIdFormat) -> io::Result<String> {
+    let data = Vec::try_from(entry_id)?;
+    Ok(match format {
+        EwsIdFormat::EntryId => encode_base64(&data),
+        EwsIdFormat::HexEntryId => data.iter().map(|byte| format!("{byte:02X}")).collect(),
+    })
+}
+
+/// Decode an ID in `format` into the [`EntryId`] of a PST node. IDs of objects in an Exchange
+/// mailbox use a different `PidTagEntryId` layout, so they are rejected.
+pub fn from_ews_id(id: &str, format: EwsIdFormat) -> io::Result<EntryId> {
+    let invalid = || MessagingError::InvalidEwsId(format, id.to_string());
+    let data = match format {
+        EwsIdFormat::EntryId => {
+            let data = decode_base64(id.trim().as_bytes());
+            if encode_base64(&data) != id.trim() {
+                return Err(invalid().into());
+            }
+            data
+        }
+        EwsIdFormat::HexEntryId => decode_hex(id.trim()).ok_or_else(invalid)?,
+    };
+
+    let mut reader = data.as_slice();
+    let entry_id = EntryId::read(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(invalid().into());
+    }
+    Ok(entry_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ndb::node_id::NodeIdType;
+
+    #[test]
+    fn test_ews_id_round_trip() {
+        let record_key = [
+            0x4A, 0x1C, 0x2B, 0x3D, 0x9E, 0x8F, 0x70, 0x61, 0x52, 0x43, 0x34, 0x25, 0x16, 0x07,
+            0xF8, 0xE9,
+        ];
+        let node_id = NodeId::new(NodeIdType::NormalMessage, 0x10042).unwrap();
+
+        let hex = make_ews_id(&record_key, node_id, EwsIdFormat::HexEntryId).unwrap();
+        assert_eq!(hex, "000000004A1C2B3D9E8F7061524334251607F8E944082000");
+        let entry_id = from_ews_id(&hex.to_lowercase(), EwsIdFormat::HexEntryId).unwrap();
+        assert_eq!(entry_id.record_key(), &record_key);
+        assert_eq!(entry_id.node_id(), node_id);
+
+        let base64 = to_ews_id(&entry_id, EwsIdFormat::EntryId).unwrap();
+        assert_eq!(base64, "AAAAAEocKz2ej3BhUkM0JRYH+OlECCAA");
+        let entry_id = from_ews_id(&base64, EwsIdFormat::EntryId).unwrap();
+        assert_eq!(entry_id.node_id(), node_id);
+
+        assert!(from_ews_id("AAAAAEocKz2ej3BhUkM0JRYH+OlECCA", EwsIdFormat::EntryId).is_err());
+        assert!(from_ews_id(&format!("{hex}00"), EwsIdFormat::HexEntryId).is_err());
+        assert!(from_ews_id(&format!("+{}", &hex[1..]), EwsIdFormat::HexEntryId).is_err());
+    }
+}
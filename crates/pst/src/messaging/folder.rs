@@ -2,14 +2,19 @@
 
 use std::{cell::OnceCell, collections::BTreeMap, io, rc::Rc};
 
-use super::{read_write::*, store::*, *};
+use super::{
+    annotations::FolderAnnotations, attachment_sharing::AttachmentSharingReport, folder_type::*,
+    message::*, message_class_filter::MessageClassFilter, read_write::*, store::*, sync::SyncState,
+    time_index::FolderTimeIndex, views::FolderView, *,
+};
 use crate::{
     ltp::{
+        code_page::String8Decoder,
         heap::HeapNode,
         prop_context::{BinaryValue, PropertyContext, PropertyValue},
         prop_type::PropertyType,
         read_write::*,
-        table_context::TableContext,
+        table_context::{RowVersion, TableContext, TableRowData},
     },
     ndb::{
         block_id::BlockId,
@@ -26,6 +31,7 @@ use crate::{
 pub struct FolderProperties {
     node_id: NodeId,
     properties: BTreeMap<u16, PropertyValue>,
+    string_decoder: String8Decoder,
 }
 
 impl FolderProperties {
@@ -33,6 +39,11 @@ impl FolderProperties {
         self.properties.get(&id)
     }
 
+    /// Parse the `PidTagChangeKey` and `PidTagPredecessorChangeList` of the folder.
+    pub fn sync_state(&self) -> io::Result<SyncState> {
+        SyncState::read(|prop_id| self.get(prop_id))
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&u16, &PropertyValue)> {
         self.properties.iter()
     }
@@ -48,7 +59,7 @@ impl FolderProperties {
             .ok_or(MessagingError::FolderDisplayNameNotFound)?;
 
         match display_name {
-            PropertyValue::String8(value) => Ok(value.to_string()),
+            PropertyValue::String8(value) => Ok(value.to_string_with_decoder(&self.string_decoder)),
             PropertyValue::Unicode(value) => Ok(value.to_string()),
             invalid => {
                 Err(MessagingError::InvalidFolderDisplayName(PropertyType::from(invalid)).into())
@@ -84,6 +95,23 @@ impl FolderProperties {
         }
     }
 
+    /// `PidTagContainerClass`, e.g. `IPF.Note` for a mail folder or `IPF.Appointment` for a
+    /// calendar.
+    pub fn container_class(&self) -> io::Result<String> {
+        let container_class = self
+            .properties
+            .get(&0x3613)
+            .ok_or(MessagingError::FolderContainerClassNotFound)?;
+
+        match container_class {
+            PropertyValue::String8(value) => Ok(value.to_string_with_decoder(&self.string_decoder)),
+            PropertyValue::Unicode(value) => Ok(value.to_string()),
+            invalid => {
+                Err(MessagingError::InvalidFolderContainerClass(PropertyType::from(invalid)).into())
+            }
+        }
+    }
+
     pub fn has_sub_folders(&self) -> io::Result<bool> {
         let entry_id = self
             .properties
@@ -105,6 +133,107 @@ pub trait Folder {
     fn hierarchy_table(&self) -> Option<&Rc<dyn TableContext>>;
     fn contents_table(&self) -> Option<&Rc<dyn TableContext>>;
     fn associated_table(&self) -> Option<&Rc<dyn TableContext>>;
+    /// Make an [`EntryId`] for the node referenced by a row in one of this folder's tables.
+    fn entry_id_for_row(&self, row: &TableRowData) -> EntryId;
+    /// Open the message referenced by a row in this folder's contents or associated contents
+    /// table.
+    fn open_message_at_row(
+        &self,
+        row: &TableRowData,
+        prop_ids: Option<&[u16]>,
+    ) -> io::Result<Rc<dyn Message>>;
+
+    /// The rows of the contents table which were added or changed after `version`, which is
+    /// usually a [`Store::row_version`] saved by an earlier run. See
+    /// [`TableContext::rows_changed_since`] for removed rows.
+    fn changed_rows_since(&self, version: RowVersion) -> Vec<&TableRowData> {
+        self.contents_table()
+            .map(|contents_table| contents_table.rows_changed_since(version).collect())
+            .unwrap_or_default()
+    }
+
+    /// The rows of the contents table whose `PidTagMessageClass` column passes the `filter`,
+    /// checked without opening the messages. Messages without a message class are treated as
+    /// `IPM`.
+    fn contents_rows_matching(
+        &self,
+        filter: &MessageClassFilter,
+    ) -> io::Result<Vec<&TableRowData>> {
+        let Some(contents_table) = self.contents_table() else {
+            return Ok(Default::default());
+        };
+
+        let string_decoder = self.store().string_decoder();
+        let mut row_ids = vec![];
+        contents_table.for_each_row(&mut |row| {
+            if filter.matches_row(contents_table.as_ref(), &row, &string_decoder)? {
+                row_ids.push(row.id());
+            }
+            Ok(())
+        })?;
+        row_ids
+            .into_iter()
+            .map(|row_id| Ok(contents_table.find_row(row_id)?))
+            .collect()
+    }
+
+    /// List the [`EntryId`] of each message in the contents table whose `PidTagMessageClass`
+    /// column passes the `filter`, see [`Folder::contents_rows_matching`].
+    fn message_entry_ids_matching(&self, filter: &MessageClassFilter) -> io::Result<Vec<EntryId>> {
+        Ok(self
+            .contents_rows_matching(filter)?
+            .into_iter()
+            .map(|row| self.entry_id_for_row(row))
+            .collect())
+    }
+
+    /// Index the messages in the contents table by `PidTagMessageDeliveryTime`.
+    fn build_time_index(&self) -> io::Result<FolderTimeIndex> {
+        FolderTimeIndex::build(self)
+    }
+
+    /// Use the index saved in an FAI message if it is current, otherwise build it again.
+    fn load_or_build_time_index(&self) -> io::Result<FolderTimeIndex> {
+        match FolderTimeIndex::load(self)? {
+            Some(index) => Ok(index),
+            None => self.build_time_index(),
+        }
+    }
+
+    /// The [`FolderAnnotations`] saved in an FAI message, or empty annotations if none have been
+    /// saved yet.
+    fn annotations(&self) -> io::Result<FolderAnnotations> {
+        Ok(FolderAnnotations::load(self)?.unwrap_or_default())
+    }
+
+    /// The views which Outlook saved in FAI messages, see [`FolderView::list`].
+    fn views(&self) -> io::Result<Vec<FolderView>> {
+        FolderView::list(self)
+    }
+
+    /// Group the attachments of the messages in the contents table by the block which holds
+    /// their data, to see which of them share it.
+    fn attachment_sharing_report(&self) -> io::Result<AttachmentSharingReport> {
+        AttachmentSharingReport::build(self)
+    }
+
+    /// Count the `PidTagMessageClass` column of the contents table.
+    fn message_class_histogram(&self) -> io::Result<MessageClassHistogram> {
+        let mut histogram = MessageClassHistogram::default();
+        if let Some(contents_table) = self.contents_table() {
+            histogram.add_table(contents_table.as_ref(), &self.store().string_decoder())?;
+        }
+        Ok(histogram)
+    }
+
+    /// The type of the folder from its `PidTagContainerClass`, or detected from the
+    /// [`Folder::message_class_histogram`] if it does not have one.
+    fn folder_type(&self) -> io::Result<FolderType> {
+        match self.properties().container_class() {
+            Ok(container_class) => Ok(FolderType::from_container_class(&container_class)),
+            Err(_) => Ok(self.message_class_histogram()?.dominant_folder_type()),
+        }
+    }
 }
 
 struct FolderInner<Pst>
@@ -112,6 +241,7 @@ where
     Pst: PstFile,
 {
     store: Rc<Pst::Store>,
+    record_key: StoreRecordKey,
     properties: FolderProperties,
     hierarchy_table: OnceCell<Option<Rc<dyn TableContext>>>,
     contents_table: OnceCell<Option<Rc<dyn TableContext>>>,
@@ -163,17 +293,17 @@ where
         if !store.properties().matches_record_key(entry_id)? {
             return Err(MessagingError::EntryIdWrongStore.into());
         }
+        let record_key = store.properties().record_key()?;
+        let string_decoder = store.string_decoder();
+        let heap_id_mode = store.heap_id_mode();
 
         let pst = store.pst();
         let header = pst.header();
         let root = header.root();
 
         let properties = {
-            let mut file = pst
-                .reader()
-                .lock()
-                .map_err(|_| MessagingError::FailedToLockFile)?;
-            let file = &mut *file;
+            let mut file = pst.reader();
+            let file = &mut file;
 
             let encoding = header.crypt_method();
             let node_btree = <<Pst as PstFile>::NodeBTree as RootBTreeReadWrite>::read(
@@ -214,9 +344,17 @@ where
                 .into_iter()
                 .map(|(prop_id, record)| {
                     prop_context
-                        .read_property(file, encoding, &block_btree, &mut block_page_cache, record)
-                        .map(|value| (prop_id, value))
+                        .read_property_with_mode(
+                            file,
+                            encoding,
+                            &block_btree,
+                            &mut block_page_cache,
+                            record,
+                            heap_id_mode,
+                        )
+                        .map(|value| value.map(|value| (prop_id, value)))
                 })
+                .filter_map(Result::transpose)
                 .chain([
                     Ok((0x0FFF, PropertyValue::Binary(BinaryValue::new(entry_id)))),
                     Ok((0x3601, PropertyValue::Integer32(folder_type))),
@@ -226,11 +364,13 @@ where
             FolderProperties {
                 node_id,
                 properties,
+                string_decoder,
             }
         };
 
         Ok(Self {
             store,
+            record_key,
             properties,
             hierarchy_table: Default::default(),
             contents_table: Default::default(),
@@ -244,11 +384,8 @@ where
         let root = header.root();
 
         let node = {
-            let mut file = pst
-                .reader()
-                .lock()
-                .map_err(|_| MessagingError::FailedToLockFile)?;
-            let file = &mut *file;
+            let mut file = pst.reader();
+            let file = &mut file;
 
             let node_btree = <<Pst as PstFile>::NodeBTree as RootBTreeReadWrite>::read(
                 file,
@@ -290,6 +427,19 @@ where
             .get_or_init(|| self.read_table(NodeIdType::AssociatedContentsTable).ok()?)
             .as_ref()
     }
+
+    fn entry_id_for_row(&self, row: &TableRowData) -> EntryId {
+        EntryId::new(self.record_key, NodeId::from(u32::from(row.id())))
+    }
+
+    fn open_message_at_row(
+        &self,
+        row: &TableRowData,
+        prop_ids: Option<&[u16]>,
+    ) -> io::Result<Rc<dyn Message>> {
+        self.store
+            .open_message(&self.entry_id_for_row(row), prop_ids)
+    }
 }
 
 pub struct UnicodeFolder {
@@ -322,6 +472,18 @@ impl Folder for UnicodeFolder {
     fn associated_table(&self) -> Option<&Rc<dyn TableContext>> {
         self.inner.associated_table()
     }
+
+    fn entry_id_for_row(&self, row: &TableRowData) -> EntryId {
+        self.inner.entry_id_for_row(row)
+    }
+
+    fn open_message_at_row(
+        &self,
+        row: &TableRowData,
+        prop_ids: Option<&[u16]>,
+    ) -> io::Result<Rc<dyn Message>> {
+        self.inner.open_message_at_row(row, prop_ids)
+    }
 }
 
 impl FolderReadWrite<UnicodePstFile> for UnicodeFolder {
@@ -361,6 +523,18 @@ impl Folder for AnsiFolder {
     fn associated_table(&self) -> Option<&Rc<dyn TableContext>> {
         self.inner.associated_table()
     }
+
+    fn entry_id_for_row(&self, row: &TableRowData) -> EntryId {
+        self.inner.entry_id_for_row(row)
+    }
+
+    fn open_message_at_row(
+        &self,
+        row: &TableRowData,
+        prop_ids: Option<&[u16]>,
+    ) -> io::Result<Rc<dyn Message>> {
+        self.inner.open_message_at_row(row, prop_ids)
+    }
 }
 
 impl FolderReadWrite<AnsiPstFile> for AnsiFolder {
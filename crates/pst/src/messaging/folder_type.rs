@@ -0,0 +1,303 @@
+//! Classify folders as mail, calendar, contacts, and so on, from `PidTagContainerClass`, or from
+//! the `PidTagMessageClass` column of the contents table when the folder does not have a
+//! container class, which is common in archives written by other tools.
+
+use std::{collections::BTreeMap, io};
+
+use crate::ltp::{
+    code_page::String8Decoder, prop_context::PropertyValue, table_context::TableContext,
+};
+
+/// `PidTagMessageClass`
+const PID_TAG_MESSAGE_CLASS: u16 = 0x001A;
+
+/// The kind of items a folder holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FolderType {
+    /// `IPF.Note`
+    Mail,
+    /// `IPF.Appointment`
+    Calendar,
+    /// `IPF.Contact`
+    Contacts,
+    /// `IPF.Task`
+    Tasks,
+    /// `IPF.StickyNote`
+    Notes,
+    /// `IPF.Journal`
+    Journal,
+    /// Any other container class, or a folder without messages to detect it from.
+    Other,
+}
+
+impl FolderType {
+    /// Map a `PidTagContainerClass` value, including derived classes like `IPF.Note.OutlookHomepage`.
+    pub fn from_container_class(container_class: &str) -> Self {
+        Self::from_class(container_class, "IPF")
+    }
+
+    /// Map a `PidTagMessageClass` value to the type of folder which usually holds it, e.g.
+    /// `IPM.Schedule.Meeting.Request` is a [`FolderType::Mail`] item, and `IPM.DistList` is a
+    /// [`FolderType::Contacts`] item.
+    pub fn from_message_class(message_class: &str) -> Self {
+        if class_matches(message_class, "IPM.DistList") {
+            return Self::Contacts;
+        }
+        if class_matches(message_class, "IPM.Activity") {
+            return Self::Journal;
+        }
+        if class_matches(message_class, "IPM.Schedule") || class_matches(message_class, "REPORT") {
+            return Self::Mail;
+        }
+        match Self::from_class(message_class, "IPM") {
+            // Plain `IPM` items are treated as mail, like Outlook does.
+            Self::Other if message_class.eq_ignore_ascii_case("IPM") => Self::Mail,
+            folder_type => folder_type,
+        }
+    }
+
+    fn from_class(class: &str, prefix: &str) -> Self {
+        let Some(class) = class
+            .get(..prefix.len())
+            .filter(|start| start.eq_ignore_ascii_case(prefix))
+            .and_then(|_| class[prefix.len()..].strip_prefix('.'))
+        else {
+            return Self::Other;
+        };
+
+        [
+            ("Note", Self::Mail),
+            ("Appointment", Self::Calendar),
+            ("Contact", Self::Contacts),
+            ("Task", Self::Tasks),
+            ("StickyNote", Self::Notes),
+            ("Journal", Self::Journal),
+        ]
+        .into_iter()
+        .find(|(name, _)| class_matches(class, name))
+        .map_or(Self::Other, |(_, folder_type)| folder_type)
+    }
+
+    /// The `PidTagContainerClass` for this type of folder, or `None` for [`FolderType::Other`].
+    pub fn container_class(&self) -> Option<&'static str> {
+        match self {
+            Self::Mail => Some("IPF.Note"),
+            Self::Calendar => Some("IPF.Appointment"),
+            Self::Contacts => Some("IPF.Contact"),
+            Self::Tasks => Some("IPF.Task"),
+            Self::Notes => Some("IPF.StickyNote"),
+            Self::Journal => Some("IPF.Journal"),
+            Self::Other => None,
+        }
+    }
+
+    /// Use the `container_class` if there is one, or else the most common type of message in the
+    /// `histogram`.
+    pub fn detect(container_class: Option<&str>, histogram: &MessageClassHistogram) -> Self {
+        match container_class {
+            Some(container_class) => Self::from_container_class(container_class),
+            None => histogram.dominant_folder_type(),
+        }
+    }
+}
+
+/// Whether `class` is `base`, or a class derived from it like `base.Custom`, ignoring case.
+fn class_matches(class: &str, base: &str) -> bool {
+    class
+        .get(..base.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(base))
+        && matches!(class.as_bytes().get(base.len()), None | Some(b'.'))
+}
+
+/// Number of messages with each `PidTagMessageClass`, read from the contents tables without
+/// opening the messages. Message classes are kept as they are stored, so classes which only
+/// differ by case are counted separately.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MessageClassHistogram {
+    counts: BTreeMap<String, u64>,
+    missing: u64,
+}
+
+impl MessageClassHistogram {
+    /// Count the `PidTagMessageClass` column of each row in `table`, decoding `PtypString8`
+    /// values with `decoder`. Rows without a value, or tables without the column, are counted
+    /// as [`MessageClassHistogram::missing`].
+    pub fn add_table<T: TableContext + ?Sized>(
+        &mut self,
+        table: &T,
+        decoder: &String8Decoder,
+    ) -> io::Result<()> {
+        let column = table
+            .context()
+            .columns()
+            .iter()
+            .enumerate()
+            .find(|(_, column)| column.prop_id() == PID_TAG_MESSAGE_CLASS);
+
+        table.for_each_row(&mut |row| {
+            let value = match column {
+                Some((index, column)) => match row.column(index)? {
+                    Some(value) => Some(table.read_column(&value, column.prop_type())?),
+                    None => None,
+                },
+                None => None,
+            };
+            match value {
+                Some(PropertyValue::String8(value)) => {
+                    self.add(&value.to_string_with_decoder(decoder))
+                }
+                Some(PropertyValue::Unicode(value)) => self.add(&value.to_string()),
+                _ => self.missing += 1,
+            }
+            Ok(())
+        })
+    }
+
+    pub fn add(&mut self, message_class: &str) {
+        *self.counts.entry(message_class.to_string()).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (message_class, count) in other.counts.iter() {
+            *self.counts.entry(message_class.clone()).or_default() += count;
+        }
+        self.missing += other.missing;
+    }
+
+    /// Each message class and its count, sorted by message class.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.counts
+            .iter()
+            .map(|(message_class, count)| (message_class.as_str(), *count))
+    }
+
+    pub fn get(&self, message_class: &str) -> u64 {
+        self.counts.get(message_class).copied().unwrap_or_default()
+    }
+
+    /// Messages without a `PidTagMessageClass`.
+    pub fn missing(&self) -> u64 {
+        self.missing
+    }
+
+    /// All of the messages, including the [`MessageClassHistogram::missing`] ones.
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum::<u64>() + self.missing
+    }
+
+    /// The message classes sorted by count, most common first.
+    pub fn by_count(&self) -> Vec<(&str, u64)> {
+        let mut counts: Vec<_> = self.iter().collect();
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        counts
+    }
+
+    /// Number of messages of each [`FolderType`], see [`FolderType::from_message_class`].
+    pub fn folder_types(&self) -> BTreeMap<FolderType, u64> {
+        let mut folder_types = BTreeMap::new();
+        for (message_class, count) in self.iter() {
+            *folder_types
+                .entry(FolderType::from_message_class(message_class))
+                .or_default() += count;
+        }
+        folder_types
+    }
+
+    /// The [`FolderType`] with the most messages, ignoring [`FolderType::Other`]. Ties are broken
+    /// in the order of the [`FolderType`] values, and a histogram without any typed messages is
+    /// [`FolderType::Other`].
+    pub fn dominant_folder_type(&self) -> FolderType {
+        self.folder_types()
+            .into_iter()
+            .filter(|(folder_type, _)| *folder_type != FolderType::Other)
+            .fold(
+                None,
+                |best: Option<(FolderType, u64)>, (folder_type, count)| match best {
+                    Some((_, best_count)) if best_count >= count => best,
+                    _ => Some((folder_type, count)),
+                },
+            )
+            .map_or(FolderType::Other, |(folder_type, _)| folder_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_folder_type_from_class() {
+        assert_eq!(
+            FolderType::from_container_class("IPF.Note"),
+            FolderType::Mail
+        );
+        assert_eq!(
+            FolderType::from_container_class("ipf.appointment"),
+            FolderType::Calendar
+        );
+        assert_eq!(
+            FolderType::from_container_class("IPF.Contact.MOC.QuickContacts"),
+            FolderType::Contacts
+        );
+        assert_eq!(
+            FolderType::from_container_class("IPF.Notes"),
+            FolderType::Other
+        );
+        assert_eq!(
+            FolderType::from_container_class("IPM.Note"),
+            FolderType::Other
+        );
+
+        assert_eq!(FolderType::from_message_class("IPM"), FolderType::Mail);
+        assert_eq!(
+            FolderType::from_message_class("IPM.Note.SMIME"),
+            FolderType::Mail
+        );
+        assert_eq!(
+            FolderType::from_message_class("IPM.Schedule.Meeting.Request"),
+            FolderType::Mail
+        );
+        assert_eq!(
+            FolderType::from_message_class("IPM.DistList"),
+            FolderType::Contacts
+        );
+        assert_eq!(
+            FolderType::from_message_class("IPM.StickyNote"),
+            FolderType::Notes
+        );
+        assert_eq!(
+            FolderType::from_message_class("IPM.Configuration.Autocomplete"),
+            FolderType::Other
+        );
+    }
+
+    #[test]
+    fn test_dominant_folder_type() {
+        let mut histogram = MessageClassHistogram::default();
+        assert_eq!(histogram.dominant_folder_type(), FolderType::Other);
+
+        histogram.add("IPM.Appointment");
+        histogram.add("IPM.Appointment");
+        histogram.add("IPM.Schedule.Meeting.Request");
+        histogram.add("IPM.Post");
+        histogram.add("IPM.Post");
+        histogram.add("IPM.Post");
+        assert_eq!(histogram.total(), 6);
+        assert_eq!(histogram.by_count()[0], ("IPM.Post", 3));
+        assert_eq!(histogram.dominant_folder_type(), FolderType::Calendar);
+
+        let mut other = MessageClassHistogram::default();
+        other.add("IPM.Task");
+        other.missing += 1;
+        histogram.merge(&other);
+        assert_eq!(histogram.total(), 8);
+        assert_eq!(histogram.missing(), 1);
+        assert_eq!(histogram.dominant_folder_type(), FolderType::Calendar);
+
+        assert_eq!(
+            FolderType::detect(Some("IPF.Task"), &histogram),
+            FolderType::Tasks
+        );
+        assert_eq!(FolderType::detect(None, &histogram), FolderType::Calendar);
+    }
+}
@@ -0,0 +1,393 @@
+//! Map the properties of a message, folder or attachment to the fields of a struct.
+//!
+//! [`FromProperties`] is usually implemented with `#[derive(FromProperties)]` from the
+//! `outlook-pst-derive` crate, which is re-exported as `outlook_pst::FromProperties` with the
+//! `derive` feature:
+//!
+//! ```ignore
+//! use outlook_pst::{messaging::{from_properties::FromProperties, named_prop::PS_PUBLIC_STRINGS}, FromProperties};
+//!
+//! #[derive(FromProperties)]
+//! struct Note {
+//!     #[property(tag = 0x0037)]
+//!     subject: String,
+//!     #[property(tag = 0x0E06)]
+//!     delivery_time: Option<i64>,
+//!     #[property(set = PS_PUBLIC_STRINGS, name = "Keywords")]
+//!     keywords: Option<Vec<String>>,
+//! }
+//!
+//! let note = Note::from_message(message.as_ref())?;
+//! ```
+//!
+//! Fields with an `Option` type are `None` if the property is missing, other fields fail with
+//! [`MessagingError::PropertyFieldNotFound`]. A property with a type which does not convert to
+//! the type of the field fails with [`MessagingError::InvalidPropertyField`] either way.
+
+use std::{collections::BTreeMap, io};
+
+use super::{
+    attachment::AttachmentProperties,
+    folder::{Folder, FolderProperties},
+    message::{Message, MessageProperties},
+    named_prop::{NamedPropertyMapProperties, NamedPropertyName},
+    MessagingError,
+};
+use crate::ltp::{
+    code_page::String8Decoder,
+    prop_context::{GuidValue, PropertyValue},
+    prop_type::PropertyType,
+};
+
+/// A set of properties keyed by property ID.
+pub trait PropertyBag {
+    fn property(&self, prop_id: u16) -> Option<&PropertyValue>;
+}
+
+impl PropertyBag for BTreeMap<u16, PropertyValue> {
+    fn property(&self, prop_id: u16) -> Option<&PropertyValue> {
+        self.get(&prop_id)
+    }
+}
+
+impl PropertyBag for MessageProperties {
+    fn property(&self, prop_id: u16) -> Option<&PropertyValue> {
+        self.get(prop_id)
+    }
+}
+
+impl PropertyBag for FolderProperties {
+    fn property(&self, prop_id: u16) -> Option<&PropertyValue> {
+        self.get(prop_id)
+    }
+}
+
+impl PropertyBag for AttachmentProperties {
+    fn property(&self, prop_id: u16) -> Option<&PropertyValue> {
+        self.get(prop_id)
+    }
+}
+
+/// The properties which [`FromProperties::from_properties`] reads the fields from, with the
+/// named property map to look up named properties in, and the decoder for `PtypString8` values.
+pub struct PropertyFields<'a> {
+    properties: &'a dyn PropertyBag,
+    named_properties: Option<&'a NamedPropertyMapProperties>,
+    string_decoder: String8Decoder,
+}
+
+impl<'a> PropertyFields<'a> {
+    pub fn new(properties: &'a dyn PropertyBag) -> Self {
+        Self {
+            properties,
+            named_properties: None,
+            string_decoder: Default::default(),
+        }
+    }
+
+    pub fn with_named_properties(
+        mut self,
+        named_properties: &'a NamedPropertyMapProperties,
+    ) -> Self {
+        self.named_properties = Some(named_properties);
+        self
+    }
+
+    pub fn with_string_decoder(mut self, string_decoder: String8Decoder) -> Self {
+        self.string_decoder = string_decoder;
+        self
+    }
+
+    pub fn string_decoder(&self) -> &String8Decoder {
+        &self.string_decoder
+    }
+
+    /// The property with `prop_id`.
+    pub fn tag(&self, prop_id: u16) -> Option<&'a PropertyValue> {
+        self.properties.property(prop_id)
+    }
+
+    /// The named property `name` in the property set `guid`, if it is mapped in the store and
+    /// set. Fails with [`MessagingError::PropertyFieldNeedsNamedProperties`] without a named
+    /// property map.
+    pub fn named(
+        &self,
+        field: &'static str,
+        guid: &GuidValue,
+        name: impl Into<NamedPropertyName>,
+    ) -> io::Result<Option<&'a PropertyValue>> {
+        let named_properties = self
+            .named_properties
+            .ok_or(MessagingError::PropertyFieldNeedsNamedProperties(field))?;
+        Ok(named_properties
+            .find(guid, &name.into())?
+            .and_then(|prop_id| self.properties.property(prop_id)))
+    }
+
+    /// Convert `value` to the type of `field`.
+    pub fn field<T: FromPropertyField>(
+        &self,
+        field: &'static str,
+        value: Option<&PropertyValue>,
+    ) -> io::Result<T> {
+        Ok(T::from_property_field(field, value, &self.string_decoder)?)
+    }
+}
+
+/// A struct which can be read from a [`PropertyBag`], see the [module](self) documentation.
+pub trait FromProperties: Sized {
+    fn from_properties(fields: &PropertyFields) -> io::Result<Self>;
+
+    /// Read the fields from the properties of `message`, looking up named properties in the
+    /// named property map of its store.
+    fn from_message<M: Message + ?Sized>(message: &M) -> io::Result<Self> {
+        let named_property_map = message.store().named_property_map()?;
+        let properties = message.properties();
+        let fields = PropertyFields::new(properties)
+            .with_named_properties(named_property_map.properties())
+            .with_string_decoder(properties.string_decoder().clone());
+        Self::from_properties(&fields)
+    }
+
+    /// Read the fields from the properties of `folder`, looking up named properties in the named
+    /// property map of its store.
+    fn from_folder<F: Folder + ?Sized>(folder: &F) -> io::Result<Self> {
+        let store = folder.store();
+        let named_property_map = store.named_property_map()?;
+        let fields = PropertyFields::new(folder.properties())
+            .with_named_properties(named_property_map.properties())
+            .with_string_decoder(store.string_decoder());
+        Self::from_properties(&fields)
+    }
+}
+
+/// A type which a [`PropertyValue`] can be converted to.
+pub trait FromPropertyValue: Sized {
+    /// Returns `None` if `value` has a type which does not convert to `Self`.
+    fn from_property_value(value: &PropertyValue, string_decoder: &String8Decoder) -> Option<Self>;
+}
+
+/// The type of a field in a [`FromProperties`] struct, either a [`FromPropertyValue`] type which
+/// is required, or an `Option` of one which is not.
+pub trait FromPropertyField: Sized {
+    fn from_property_field(
+        field: &'static str,
+        value: Option<&PropertyValue>,
+        string_decoder: &String8Decoder,
+    ) -> Result<Self, MessagingError>;
+}
+
+impl<T: FromPropertyValue> FromPropertyField for T {
+    fn from_property_field(
+        field: &'static str,
+        value: Option<&PropertyValue>,
+        string_decoder: &String8Decoder,
+    ) -> Result<Self, MessagingError> {
+        let value = value.ok_or(MessagingError::PropertyFieldNotFound(field))?;
+        T::from_property_value(value, string_decoder)
+            .ok_or_else(|| MessagingError::InvalidPropertyField(field, PropertyType::from(value)))
+    }
+}
+
+impl<T: FromPropertyValue> FromPropertyField for Option<T> {
+    fn from_property_field(
+        field: &'static str,
+        value: Option<&PropertyValue>,
+        string_decoder: &String8Decoder,
+    ) -> Result<Self, MessagingError> {
+        value
+            .map(|value| T::from_property_field(field, Some(value), string_decoder))
+            .transpose()
+    }
+}
+
+impl FromPropertyValue for PropertyValue {
+    fn from_property_value(value: &PropertyValue, _: &String8Decoder) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+impl FromPropertyValue for bool {
+    fn from_property_value(value: &PropertyValue, _: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::Boolean(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for i16 {
+    fn from_property_value(value: &PropertyValue, _: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::Integer16(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for i32 {
+    fn from_property_value(value: &PropertyValue, _: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::Integer16(value) => Some(i32::from(*value)),
+            PropertyValue::Integer32(value) | PropertyValue::ErrorCode(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// `PtypInteger64`, `PtypCurrency` and `PtypTime` are all 64-bit integers, and the smaller
+/// integer types are widened.
+impl FromPropertyValue for i64 {
+    fn from_property_value(value: &PropertyValue, _: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::Integer16(value) => Some(i64::from(*value)),
+            PropertyValue::Integer32(value) => Some(i64::from(*value)),
+            PropertyValue::Integer64(value)
+            | PropertyValue::Currency(value)
+            | PropertyValue::Time(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for f32 {
+    fn from_property_value(value: &PropertyValue, _: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::Floating32(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for f64 {
+    fn from_property_value(value: &PropertyValue, _: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::Floating32(value) => Some(f64::from(*value)),
+            PropertyValue::Floating64(value) | PropertyValue::FloatingTime(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for String {
+    fn from_property_value(value: &PropertyValue, string_decoder: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::String8(value) => Some(value.to_string_with_decoder(string_decoder)),
+            PropertyValue::Unicode(value) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for GuidValue {
+    fn from_property_value(value: &PropertyValue, _: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::Guid(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for Vec<u8> {
+    fn from_property_value(value: &PropertyValue, _: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::Binary(value) => Some(value.buffer().to_vec()),
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for Vec<i32> {
+    fn from_property_value(value: &PropertyValue, _: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::MultipleInteger16(values) => {
+                Some(values.iter().copied().map(i32::from).collect())
+            }
+            PropertyValue::MultipleInteger32(values) => Some(values.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for Vec<i64> {
+    fn from_property_value(value: &PropertyValue, _: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::MultipleInteger64(values)
+            | PropertyValue::MultipleCurrency(values)
+            | PropertyValue::MultipleTime(values) => Some(values.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for Vec<String> {
+    fn from_property_value(value: &PropertyValue, string_decoder: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::MultipleString8(values) => Some(
+                values
+                    .iter()
+                    .map(|value| value.to_string_with_decoder(string_decoder))
+                    .collect(),
+            ),
+            PropertyValue::MultipleUnicode(values) => {
+                Some(values.iter().map(ToString::to_string).collect())
+            }
+            _ => None,
+        }
+    }
+}
+
+impl FromPropertyValue for Vec<GuidValue> {
+    fn from_property_value(value: &PropertyValue, _: &String8Decoder) -> Option<Self> {
+        match value {
+            PropertyValue::MultipleGuid(values) => Some(values.clone()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::{message::unicode_value, named_prop::PS_PUBLIC_STRINGS};
+
+    struct Note {
+        subject: String,
+        importance: Option<i32>,
+        delivery_time: Option<i64>,
+    }
+
+    impl FromProperties for Note {
+        fn from_properties(fields: &PropertyFields) -> io::Result<Self> {
+            Ok(Self {
+                subject: fields.field("subject", fields.tag(0x0037))?,
+                importance: fields.field("importance", fields.tag(0x0017))?,
+                delivery_time: fields.field("delivery_time", fields.tag(0x0E06))?,
+            })
+        }
+    }
+
+    #[test]
+    fn test_from_properties() {
+        let mut properties = BTreeMap::from([
+            (0x0037, unicode_value("Lunch")),
+            (0x0E06, PropertyValue::Time(133_000_000_000_000_000)),
+        ]);
+        let note = Note::from_properties(&PropertyFields::new(&properties)).unwrap();
+        assert_eq!(note.subject, "Lunch");
+        assert_eq!(note.importance, None);
+        assert_eq!(note.delivery_time, Some(133_000_000_000_000_000));
+
+        properties.insert(0x0017, PropertyValue::Boolean(true));
+        assert!(Note::from_properties(&PropertyFields::new(&properties)).is_err());
+
+        properties.remove(&0x0017);
+        properties.remove(&0x0037);
+        assert!(Note::from_properties(&PropertyFields::new(&properties)).is_err());
+
+        let fields = PropertyFields::new(&properties);
+        assert!(fields
+            .named("keywords", &PS_PUBLIC_STRINGS, "Keywords")
+            .is_err());
+    }
+}
@@ -0,0 +1,385 @@
+//! Sidecar index of the folder hierarchy, so repeat opens of an unchanged PST file can show the
+//! folder tree without walking every hierarchy table again.
+//!
+//! The index is keyed by a hash of the header's `dwUnique` value and the store's
+//! `PidTagRecordKey`. `dwUnique` changes every time the header is written, so
+//! [`FolderHierarchyIndex::load`] ignores an index which was built before the file was modified.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+use tracing::warn;
+
+use super::store::*;
+use crate::ndb::node_id::NodeId;
+
+const INDEX_MAGIC: &[u8; 8] = b"PSTHIDX1";
+
+/// A folder in a [`FolderHierarchyIndex`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FolderIndexEntry {
+    node_id: NodeId,
+    parent: NodeId,
+    path: Vec<String>,
+    content_count: i32,
+    unread_count: i32,
+}
+
+impl FolderIndexEntry {
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    pub fn parent(&self) -> NodeId {
+        self.parent
+    }
+
+    /// Display names of the folders between the IPM subtree and this folder, including this one.
+    /// The IPM subtree itself has an empty path.
+    pub fn path(&self) -> &[String] {
+        &self.path
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        self.path.last().map(String::as_str)
+    }
+
+    pub fn content_count(&self) -> i32 {
+        self.content_count
+    }
+
+    pub fn unread_count(&self) -> i32 {
+        self.unread_count
+    }
+
+    fn read(f: &mut dyn Read) -> io::Result<Self> {
+        let node_id = NodeId::from(f.read_u32::<LittleEndian>()?);
+        let parent = NodeId::from(f.read_u32::<LittleEndian>()?);
+        let content_count = f.read_i32::<LittleEndian>()?;
+        let unread_count = f.read_i32::<LittleEndian>()?;
+
+        let depth = f.read_u16::<LittleEndian>()?;
+        let path = (0..depth)
+            .map(|_| {
+                let length = f.read_u16::<LittleEndian>()?;
+                let mut buffer = vec![0; usize::from(length)];
+                f.read_exact(&mut buffer)?;
+                String::from_utf8(buffer).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))
+            })
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self {
+            node_id,
+            parent,
+            path,
+            content_count,
+            unread_count,
+        })
+    }
+
+    fn write(&self, f: &mut dyn Write) -> io::Result<()> {
+        f.write_u32::<LittleEndian>(u32::from(self.node_id))?;
+        f.write_u32::<LittleEndian>(u32::from(self.parent))?;
+        f.write_i32::<LittleEndian>(self.content_count)?;
+        f.write_i32::<LittleEndian>(self.unread_count)?;
+
+        let depth = u16::try_from(self.path.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        f.write_u16::<LittleEndian>(depth)?;
+        for name in self.path.iter() {
+            let length = u16::try_from(name.len())
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+            f.write_u16::<LittleEndian>(length)?;
+            f.write_all(name.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Materialized list of the folders under the IPM subtree, in depth-first order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FolderHierarchyIndex {
+    key: u64,
+    entries: Vec<FolderIndexEntry>,
+}
+
+impl FolderHierarchyIndex {
+    /// Walk the hierarchy tables from the IPM subtree and record every folder.
+    pub fn build(store: &Rc<dyn Store>) -> io::Result<Self> {
+        let key = Self::store_key(store.as_ref())?;
+        let root = store.open_folder(&store.properties().ipm_sub_tree_entry_id()?)?;
+
+        let mut entries = vec![];
+        let mut pending = vec![(root, NodeId::from(0), vec![])];
+        while let Some((folder, parent, path)) = pending.pop() {
+            let properties = folder.properties();
+            let node_id = properties.node_id();
+
+            let mut sub_folders = vec![];
+            if let Some(hierarchy_table) = folder.hierarchy_table() {
+                for row in hierarchy_table.rows_matrix() {
+                    let sub_folder = store.open_folder(&folder.entry_id_for_row(row))?;
+                    let mut sub_path = path.clone();
+                    sub_path.push(sub_folder.properties().display_name()?);
+                    sub_folders.push((sub_folder, node_id, sub_path));
+                }
+            }
+
+            entries.push(FolderIndexEntry {
+                node_id,
+                parent,
+                path,
+                content_count: properties.content_count()?,
+                unread_count: properties.unread_count()?,
+            });
+
+            pending.extend(sub_folders.into_iter().rev());
+        }
+
+        Ok(Self { key, entries })
+    }
+
+    /// Hash of the header's `dwUnique` and the store's `PidTagRecordKey`, using 64-bit FNV-1a so
+    /// the key is stable between builds of the library.
+    pub fn store_key(store: &dyn Store) -> io::Result<u64> {
+        let record_key = store.properties().record_key()?;
+        let unique = store.unique_value().to_le_bytes();
+        Ok(unique
+            .iter()
+            .chain(record_key.record_key().iter())
+            .fold(0xCBF2_9CE4_8422_2325, |hash, &b| {
+                (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01B3)
+            }))
+    }
+
+    /// Default location of the sidecar file, which is the PST file path with `.idx` appended.
+    pub fn sidecar_path(pst_path: impl AsRef<Path>) -> PathBuf {
+        let mut path = OsString::from(pst_path.as_ref().as_os_str());
+        path.push(".idx");
+        PathBuf::from(path)
+    }
+
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    pub fn entries(&self) -> &[FolderIndexEntry] {
+        &self.entries
+    }
+
+    /// Check whether the index still matches the current state of `store`.
+    pub fn is_current(&self, store: &dyn Store) -> io::Result<bool> {
+        Ok(self.key == Self::store_key(store)?)
+    }
+
+    /// Read the index from `path`. Returns `None` if there is no index file, or if it is stale or
+    /// unreadable, in which case it should be rebuilt.
+    pub fn load(path: impl AsRef<Path>, store: &dyn Store) -> io::Result<Option<Self>> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let index = match Self::read(&mut BufReader::new(file)) {
+            Ok(index) => index,
+            Err(err) => {
+                warn!(
+                    name: "PstHierarchyIndexInvalid",
+                    "Ignoring folder hierarchy index: {err:?}"
+                );
+                return Ok(None);
+            }
+        };
+
+        Ok(if index.is_current(store)? {
+            Some(index)
+        } else {
+            None
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()
+    }
+
+    /// Use the sidecar index next to `pst_path` if it is current, otherwise build it and try to
+    /// replace the sidecar file. Failing to write the sidecar file is not an error, e.g. if the
+    /// PST file is on a read-only share.
+    pub fn load_or_build(store: &Rc<dyn Store>, pst_path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = Self::sidecar_path(pst_path);
+        if let Some(index) = Self::load(&path, store.as_ref())? {
+            return Ok(index);
+        }
+
+        let index = Self::build(store)?;
+        if let Err(err) = index.save(&path) {
+            warn!(
+                name: "PstHierarchyIndexNotSaved",
+                "Failed to save folder hierarchy index: {err:?}"
+            );
+        }
+        Ok(index)
+    }
+
+    pub fn read(f: &mut dyn Read) -> io::Result<Self> {
+        let mut magic = [0; INDEX_MAGIC.len()];
+        f.read_exact(&mut magic)?;
+        if magic != *INDEX_MAGIC {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        let key = f.read_u64::<LittleEndian>()?;
+        let count = f.read_u32::<LittleEndian>()?;
+        let entries = (0..count)
+            .map(|_| FolderIndexEntry::read(f))
+            .collect::<io::Result<_>>()?;
+
+        Ok(Self { key, entries })
+    }
+
+    pub fn write(&self, f: &mut dyn Write) -> io::Result<()> {
+        f.write_all(INDEX_MAGIC)?;
+        f.write_u64::<LittleEndian>(self.key)?;
+
+        let count = u32::try_from(self.entries.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        f.write_u32::<LittleEndian>(count)?;
+        for entry in self.entries.iter() {
+            entry.write(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messaging::{message::MessageBuilder, object_id::FolderId},
+        ndb::node_id::NodeIdType,
+        temp_path::TempPath,
+        PstFile, UnicodePstFile,
+    };
+    use std::fs;
+
+    fn open_store(path: &Path) -> Rc<dyn Store> {
+        UnicodeStore::read(Rc::new(UnicodePstFile::open(path).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn test_build() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst");
+        let store = open_store(&path);
+        let index = FolderHierarchyIndex::build(&store).unwrap();
+        assert_eq!(
+            index.key(),
+            FolderHierarchyIndex::store_key(store.as_ref()).unwrap()
+        );
+        assert!(index.is_current(store.as_ref()).unwrap());
+
+        let [ipm_sub_tree, deleted_items] = index.entries() else {
+            panic!("unexpected folders: {:?}", index.entries());
+        };
+        assert_eq!(
+            ipm_sub_tree.node_id(),
+            store
+                .properties()
+                .ipm_sub_tree_entry_id()
+                .unwrap()
+                .node_id()
+        );
+        assert_eq!(u32::from(ipm_sub_tree.parent()), 0);
+        assert!(ipm_sub_tree.path().is_empty());
+        assert_eq!(ipm_sub_tree.display_name(), None);
+
+        assert_eq!(deleted_items.parent(), ipm_sub_tree.node_id());
+        assert_eq!(deleted_items.path(), ["Deleted Items"]);
+        assert_eq!(deleted_items.display_name(), Some("Deleted Items"));
+        assert_eq!(deleted_items.content_count(), 0);
+        assert_eq!(deleted_items.unread_count(), 0);
+
+        let mut buffer = vec![];
+        index.write(&mut buffer).unwrap();
+        assert_eq!(
+            FolderHierarchyIndex::read(&mut buffer.as_slice()).unwrap(),
+            index
+        );
+    }
+
+    #[test]
+    fn test_load_or_build() {
+        let dir = TempPath::new("hierarchy-index");
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("Empty.pst");
+        fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst"),
+            &path,
+        )
+        .unwrap();
+        let sidecar = FolderHierarchyIndex::sidecar_path(&path);
+        assert_eq!(sidecar, dir.join("Empty.pst.idx"));
+
+        let store = open_store(&path);
+        assert_eq!(
+            FolderHierarchyIndex::load(&sidecar, store.as_ref()).unwrap(),
+            None
+        );
+        let index = FolderHierarchyIndex::load_or_build(&store, &path).unwrap();
+        assert!(sidecar.exists());
+        assert_eq!(
+            FolderHierarchyIndex::load(&sidecar, store.as_ref()).unwrap(),
+            Some(index.clone())
+        );
+        drop(store);
+
+        let deleted_items = index.entries()[1].node_id();
+        {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            let mut guard = pst.lock().unwrap();
+            let folder = FolderId::try_from(deleted_items).unwrap();
+            guard
+                .create_message(folder, &MessageBuilder::new("IPM.Note"))
+                .unwrap();
+            guard.flush().unwrap();
+        }
+
+        // Writing the file changes dwUnique, so the sidecar file is stale and gets rebuilt.
+        let store = open_store(&path);
+        assert!(!index.is_current(store.as_ref()).unwrap());
+        assert_eq!(
+            FolderHierarchyIndex::load(&sidecar, store.as_ref()).unwrap(),
+            None
+        );
+        let rebuilt = FolderHierarchyIndex::load_or_build(&store, &path).unwrap();
+        assert_ne!(rebuilt.key(), index.key());
+        let entry = rebuilt
+            .entries()
+            .iter()
+            .find(|entry| entry.node_id() == deleted_items)
+            .unwrap();
+        assert!(matches!(
+            entry.node_id().id_type(),
+            Ok(NodeIdType::NormalFolder)
+        ));
+        assert_eq!(entry.content_count(), 1);
+        assert_eq!(entry.unread_count(), 1);
+        assert_eq!(
+            FolderHierarchyIndex::load(&sidecar, store.as_ref()).unwrap(),
+            Some(rebuilt)
+        );
+
+        fs::write(&sidecar, b"not an index").unwrap();
+        assert_eq!(
+            FolderHierarchyIndex::load(&sidecar, store.as_ref()).unwrap(),
+            None
+        );
+    }
+}
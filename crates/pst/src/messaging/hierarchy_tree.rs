@@ -0,0 +1,259 @@
+//! Nested snapshot of the folder hierarchy, which can be written out as JSON or
+//! [OPML](https://opml.org/spec2.opml) to visualize the contents of an archive.
+
+use std::{collections::BTreeSet, fmt::Write, io, rc::Rc};
+use tracing::warn;
+
+use super::{
+    ews_id::{to_ews_id, EwsIdFormat},
+    folder::Folder,
+    store::{EntryId, Store},
+};
+use crate::export::{json::write_json_string, xml::escape_xml};
+
+/// A folder and the folders beneath it, see [`Store::hierarchy_tree`].
+#[derive(Clone, Debug)]
+pub struct FolderTreeNode {
+    entry_id: EntryId,
+    display_name: String,
+    container_class: Option<String>,
+    content_count: i32,
+    unread_count: i32,
+    sub_folders: Vec<FolderTreeNode>,
+}
+
+impl FolderTreeNode {
+    pub(crate) fn read<S: Store + ?Sized>(store: &S, folder: Rc<dyn Folder>) -> io::Result<Self> {
+        let mut visited = BTreeSet::new();
+        visited.insert(u32::from(folder.properties().node_id()));
+        Self::read_folder(store, folder, &mut visited)
+    }
+
+    fn read_folder<S: Store + ?Sized>(
+        store: &S,
+        folder: Rc<dyn Folder>,
+        visited: &mut BTreeSet<u32>,
+    ) -> io::Result<Self> {
+        let properties = folder.properties();
+        let entry_id = store.properties().make_entry_id(properties.node_id())?;
+
+        let mut sub_folders = vec![];
+        if let Some(hierarchy_table) = folder.hierarchy_table() {
+            for row in hierarchy_table.rows_matrix() {
+                let entry_id = folder.entry_id_for_row(row);
+
+                // A corrupt hierarchy table may list a folder more than once, or one of its own
+                // parents, so only descend into each folder the first time it is seen.
+                if !visited.insert(u32::from(entry_id.node_id())) {
+                    warn!(
+                        "Skipping folder {:?} already in the tree",
+                        entry_id.node_id()
+                    );
+                    continue;
+                }
+                let sub_folder = store.open_folder(&entry_id)?;
+                sub_folders.push(Self::read_folder(store, sub_folder, visited)?);
+            }
+        }
+
+        Ok(Self {
+            entry_id,
+            display_name: properties.display_name().unwrap_or_default(),
+            container_class: properties.container_class().ok(),
+            content_count: properties.content_count().unwrap_or_default(),
+            unread_count: properties.unread_count().unwrap_or_default(),
+            sub_folders,
+        })
+    }
+
+    pub fn entry_id(&self) -> &EntryId {
+        &self.entry_id
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    /// `PidTagContainerClass`, if the folder has one.
+    pub fn container_class(&self) -> Option<&str> {
+        self.container_class.as_deref()
+    }
+
+    pub fn content_count(&self) -> i32 {
+        self.content_count
+    }
+
+    pub fn unread_count(&self) -> i32 {
+        self.unread_count
+    }
+
+    pub fn sub_folders(&self) -> &[FolderTreeNode] {
+        &self.sub_folders
+    }
+
+    /// Serialize the tree as a JSON object, with the entry ID of each folder in the EWS
+    /// `HexEntryId` format and the sub-folders in a `folders` array.
+    pub fn to_json(&self) -> io::Result<String> {
+        let mut json = String::new();
+        self.write_json(&mut json)?;
+        Ok(json)
+    }
+
+    fn write_json(&self, json: &mut String) -> io::Result<()> {
+        json.push_str("{\"name\":");
+        write_json_string(json, &self.display_name);
+        json.push_str(",\"class\":");
+        match self.container_class.as_deref() {
+            Some(container_class) => write_json_string(json, container_class),
+            None => json.push_str("null"),
+        }
+        let _ = write!(
+            json,
+            ",\"contentCount\":{},\"unreadCount\":{},\"entryId\":\"{}\",\"folders\":[",
+            self.content_count,
+            self.unread_count,
+            to_ews_id(&self.entry_id, EwsIdFormat::HexEntryId)?
+        );
+        for (index, sub_folder) in self.sub_folders.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            sub_folder.write_json(json)?;
+        }
+        json.push_str("]}");
+        Ok(())
+    }
+
+    /// Serialize the tree as an OPML 2.0 outline, with an `outline` element for each folder.
+    pub fn to_opml(&self) -> io::Result<String> {
+        let mut opml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n<head>\n",
+        );
+        let _ = writeln!(
+            opml,
+            "<title>{}</title>\n</head>\n<body>",
+            escape_xml(&self.display_name)
+        );
+        self.write_opml(&mut opml, 1)?;
+        opml.push_str("</body>\n</opml>\n");
+        Ok(opml)
+    }
+
+    fn write_opml(&self, opml: &mut String, depth: usize) -> io::Result<()> {
+        let indent = "  ".repeat(depth);
+        let _ = write!(
+            opml,
+            "{indent}<outline text=\"{}\" contentCount=\"{}\" unreadCount=\"{}\" entryId=\"{}\"",
+            escape_xml(&self.display_name),
+            self.content_count,
+            self.unread_count,
+            to_ews_id(&self.entry_id, EwsIdFormat::HexEntryId)?
+        );
+        if let Some(container_class) = self.container_class.as_deref() {
+            let _ = write!(opml, " class=\"{}\"", escape_xml(container_class));
+        }
+
+        if self.sub_folders.is_empty() {
+            opml.push_str("/>\n");
+        } else {
+            opml.push_str(">\n");
+            for sub_folder in self.sub_folders.iter() {
+                sub_folder.write_opml(opml, depth + 1)?;
+            }
+            let _ = writeln!(opml, "{indent}</outline>");
+        }
+        Ok(())
+    }
+}
+
+/// Serialize the same fields as [`FolderTreeNode::to_json`], for callers which already use
+/// `serde` to write their own reports.
+#[cfg(feature = "serde")]
+impl serde::Serialize for FolderTreeNode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeStruct};
+
+        let entry_id =
+            to_ews_id(&self.entry_id, EwsIdFormat::HexEntryId).map_err(S::Error::custom)?;
+        let mut state = serializer.serialize_struct("FolderTreeNode", 6)?;
+        state.serialize_field("name", &self.display_name)?;
+        state.serialize_field("class", &self.container_class)?;
+        state.serialize_field("contentCount", &self.content_count)?;
+        state.serialize_field("unreadCount", &self.unread_count)?;
+        state.serialize_field("entryId", &entry_id)?;
+        state.serialize_field("folders", &self.sub_folders)?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messaging::store::{StoreRecordKey, UnicodeStore},
+        ndb::node_id::{NodeId, NodeIdType},
+        UnicodePstFile,
+    };
+    use std::path::Path;
+
+    fn folder(index: u32, name: &str, sub_folders: Vec<FolderTreeNode>) -> FolderTreeNode {
+        FolderTreeNode {
+            entry_id: EntryId::new(
+                StoreRecordKey::new([0; 16]),
+                NodeId::new(NodeIdType::NormalFolder, index).unwrap(),
+            ),
+            display_name: name.to_string(),
+            container_class: Some("IPF.Note".to_string()),
+            content_count: 2,
+            unread_count: 1,
+            sub_folders,
+        }
+    }
+
+    #[test]
+    fn test_folder_tree_export() {
+        let tree = folder(1, "Top", vec![folder(2, "A \"quoted\" <name>", vec![])]);
+
+        assert_eq!(
+            tree.to_json().unwrap(),
+            concat!(
+                r#"{"name":"Top","class":"IPF.Note","contentCount":2,"unreadCount":1,"#,
+                r#""entryId":"000000000000000000000000000000000000000022000000","folders":["#,
+                r#"{"name":"A \"quoted\" <name>","class":"IPF.Note","contentCount":2,"#,
+                r#""unreadCount":1,"entryId":"000000000000000000000000000000000000000042000000","#,
+                r#""folders":[]}]}"#
+            )
+        );
+
+        let opml = tree.to_opml().unwrap();
+        assert!(opml.contains("<title>Top</title>"));
+        assert!(opml.contains(
+            "    <outline text=\"A &quot;quoted&quot; &lt;name&gt;\" contentCount=\"2\""
+        ));
+        assert!(opml.contains("  </outline>\n</body>"));
+    }
+
+    #[test]
+    fn test_read_hierarchy_tree() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst");
+        let store = UnicodeStore::read(Rc::new(UnicodePstFile::open(path).unwrap())).unwrap();
+        let tree = store.hierarchy_tree().unwrap();
+
+        assert_eq!(tree.display_name(), "Top of Outlook data file");
+        let [deleted_items] = tree.sub_folders() else {
+            panic!("unexpected folders: {:?}", tree.sub_folders());
+        };
+        assert_eq!(deleted_items.display_name(), "Deleted Items");
+        assert!(deleted_items.sub_folders().is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_folder_tree_serialize() {
+        let tree = folder(1, "Top", vec![folder(2, "Inbox", vec![])]);
+        assert_eq!(
+            serde_json::to_string(&tree).unwrap(),
+            tree.to_json().unwrap()
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! Import a directory of `.eml` files, e.g. a mail export with tens of thousands of messages.
+//!
+//! Parsing MIME and serializing the property contexts, recipient tables and attachments of a
+//! message takes much longer than writing it, so with `parallel_parse` that work is spread over
+//! worker threads. The calling thread is the single writer: it allocates the nodes and blocks for
+//! each message in file name order, and adds a whole batch of messages to the contents table and
+//! the counts of the folder at once.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use super::eml;
+use crate::{
+    messaging::{
+        message::MessageBuilder,
+        object_id::{FolderId, MessageId},
+        MessagingError,
+    },
+    ndb::{header::NdbVersion, node_data::NodeData, node_id::NodeIdType},
+    PstFile,
+};
+
+/// Number of messages written in each transaction by [`import_dir`].
+pub const DEFAULT_BATCH_SIZE: usize = 500;
+
+/// Reported by [`import_dir`] after each message and each batch.
+#[derive(Debug)]
+pub enum BulkImportProgress {
+    /// The file at `path` was created as `message`.
+    Imported { path: PathBuf, message: MessageId },
+    /// The file at `path` could not be read or parsed, and was skipped.
+    Failed { path: PathBuf, error: io::Error },
+    /// A batch was flushed to the PST file, and `imported` messages have been committed so far.
+    Committed { imported: usize },
+}
+
+/// Totals for a completed [`import_dir`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BulkImportSummary {
+    imported: usize,
+    failed: usize,
+}
+
+impl BulkImportSummary {
+    pub fn imported(&self) -> usize {
+        self.imported
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+}
+
+/// Import every `.eml` file in `dir` to `folder`, in transactions of [`DEFAULT_BATCH_SIZE`]
+/// messages. See [`import_dir_in_batches`].
+pub fn import_dir<Pst>(
+    pst: &mut Pst,
+    folder: FolderId,
+    dir: impl AsRef<Path>,
+    parallel_parse: bool,
+    progress: impl FnMut(BulkImportProgress),
+) -> io::Result<BulkImportSummary>
+where
+    Pst: PstFile,
+{
+    import_dir_in_batches(
+        pst,
+        folder,
+        dir,
+        parallel_parse,
+        DEFAULT_BATCH_SIZE,
+        progress,
+    )
+}
+
+/// Import every `.eml` file in `dir` to `folder`, sorted by file name. Sub-directories are not
+/// searched. The file is locked and flushed once for every `batch_size` messages, so an
+/// interrupted import keeps every batch before the one which was in progress.
+///
+/// With `parallel_parse`, the files are read, parsed and serialized on one worker thread per
+/// available CPU, see [`thread::available_parallelism`]. The messages are still created in the
+/// same order, so the result does not depend on `parallel_parse`.
+///
+/// Files which cannot be read or parsed are reported to `progress` and skipped before anything
+/// is written for them. Errors listing `dir` or writing to the PST file stop the import.
+pub fn import_dir_in_batches<Pst>(
+    pst: &mut Pst,
+    folder: FolderId,
+    dir: impl AsRef<Path>,
+    parallel_parse: bool,
+    batch_size: usize,
+    mut progress: impl FnMut(BulkImportProgress),
+) -> io::Result<BulkImportSummary>
+where
+    Pst: PstFile,
+{
+    if folder.is_search_folder() {
+        return Err(MessagingError::InvalidFolderEntryIdType(NodeIdType::SearchFolder).into());
+    }
+
+    let paths = eml_files(dir.as_ref())?;
+    let version = pst.write_support().version();
+    let mut writer = BatchWriter {
+        pst,
+        folder,
+        batch_size: batch_size.max(1),
+        batch: Vec::new(),
+        summary: Default::default(),
+    };
+
+    let workers = if parallel_parse {
+        thread::available_parallelism().map_or(1, NonZeroUsize::get)
+    } else {
+        1
+    };
+    if workers < 2 || paths.len() < 2 {
+        for path in paths {
+            let message = parse_file(&path, version);
+            writer.push(path, message, &mut progress)?;
+        }
+        writer.flush(&mut progress)?;
+        return Ok(writer.summary);
+    }
+
+    let next_path = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::sync_channel(writer.batch_size * 2);
+    thread::scope(|scope| {
+        for _ in 0..workers.min(paths.len()) {
+            let sender = sender.clone();
+            let next_path = &next_path;
+            let paths = &paths;
+            scope.spawn(move || loop {
+                let index = next_path.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = paths.get(index) else {
+                    break;
+                };
+                if sender.send((index, parse_file(path, version))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        // Workers finish out of order, so hold on to messages till the ones before them arrive.
+        let mut pending = BTreeMap::new();
+        let mut next_index = 0;
+        for (index, message) in receiver {
+            pending.insert(index, message);
+            while let Some(message) = pending.remove(&next_index) {
+                writer.push(paths[next_index].clone(), message, &mut progress)?;
+                next_index += 1;
+            }
+        }
+        writer.flush(&mut progress)
+    })?;
+    Ok(writer.summary)
+}
+
+/// The `.eml` files in `dir`, sorted by file name.
+fn eml_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let is_eml = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("eml"));
+        if is_eml && entry.file_type()?.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// A parsed message, and its serialized node data unless it has named properties, which can only
+/// be resolved by the writer.
+type ParsedMessage = (MessageBuilder, Option<NodeData>);
+
+fn parse_file(path: &Path, version: NdbVersion) -> io::Result<ParsedMessage> {
+    let builder = eml::parse(fs::File::open(path)?)?;
+    if builder.has_named_properties() {
+        return Ok((builder, None));
+    }
+    let data = builder.build(version)?;
+    Ok((builder, Some(data)))
+}
+
+struct BatchWriter<'a, Pst: PstFile> {
+    pst: &'a mut Pst,
+    folder: FolderId,
+    batch_size: usize,
+    batch: Vec<(PathBuf, ParsedMessage)>,
+    summary: BulkImportSummary,
+}
+
+impl<Pst: PstFile> BatchWriter<'_, Pst> {
+    fn push(
+        &mut self,
+        path: PathBuf,
+        message: io::Result<ParsedMessage>,
+        progress: &mut impl FnMut(BulkImportProgress),
+    ) -> io::Result<()> {
+        match message {
+            Ok(message) => {
+                self.batch.push((path, message));
+                if self.batch.len() >= self.batch_size {
+                    self.flush(progress)?;
+                }
+            }
+            Err(error) => {
+                self.summary.failed += 1;
+                progress(BulkImportProgress::Failed { path, error });
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, progress: &mut impl FnMut(BulkImportProgress)) -> io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        let mut guard = self.pst.lock()?;
+        let mut batch = Vec::with_capacity(self.batch.len());
+        for (path, (mut builder, data)) in self.batch.drain(..) {
+            let data = match data {
+                Some(data) => data,
+                None => {
+                    guard.resolve_named_properties(&mut builder)?;
+                    builder.build(guard.ndb_version())?
+                }
+            };
+            batch.push((path, builder, data));
+        }
+
+        let messages: Vec<_> = batch
+            .iter()
+            .map(|(_, builder, data)| (builder, data))
+            .collect();
+        let created = guard.create_built_messages(self.folder, &messages)?;
+        guard.flush()?;
+
+        self.summary.imported += created.len();
+        for ((path, ..), message) in batch.into_iter().zip(created) {
+            progress(BulkImportProgress::Imported { path, message });
+        }
+        progress(BulkImportProgress::Committed {
+            imported: self.summary.imported,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        messaging::store::{Store, UnicodeStore},
+        temp_path::TempPath,
+        UnicodePstFile,
+    };
+    use std::rc::Rc;
+
+    #[test]
+    fn test_eml_files() {
+        let dir = TempPath::new("bulk");
+        fs::create_dir_all(dir.join("nested.eml")).unwrap();
+        for name in ["b.eml", "a.EML", "notes.txt"] {
+            fs::write(dir.join(name), b"Subject: test\r\n\r\nbody").unwrap();
+        }
+
+        let names: Vec<_> = eml_files(&dir)
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["a.EML", "b.eml"]);
+
+        let (builder, data) = parse_file(&dir.join("b.eml"), NdbVersion::Unicode).unwrap();
+        assert!(data.is_some());
+        assert!(builder.named_properties().is_empty());
+    }
+
+    #[test]
+    fn test_import_dir() {
+        let dir = TempPath::new("bulk-import");
+        fs::create_dir_all(&dir).unwrap();
+        for index in 0..4 {
+            let eml = format!("Subject: Message {index}\r\n\r\nbody {index}");
+            fs::write(dir.join(format!("{index}.eml")), eml).unwrap();
+        }
+        let malformed = dir.join("2-malformed.eml");
+        fs::write(&malformed, b"\r\nno header section").unwrap();
+
+        let path = TempPath::empty_pst("bulk-import");
+        let open_store =
+            || UnicodeStore::read(Rc::new(UnicodePstFile::open(&path).unwrap())).unwrap();
+        let entry_id = open_store().properties().root_folder_entry_id().unwrap();
+        let folder = FolderId::try_from(entry_id.node_id()).unwrap();
+
+        let mut failed = vec![];
+        let mut imported = vec![];
+        let mut commits = 0;
+        let summary = {
+            let mut pst = UnicodePstFile::open(&path).unwrap();
+            import_dir_in_batches(&mut pst, folder, &dir, true, 2, |progress| match progress {
+                BulkImportProgress::Imported { path, .. } => imported.push(path),
+                BulkImportProgress::Failed { path, .. } => failed.push(path),
+                BulkImportProgress::Committed { .. } => commits += 1,
+            })
+            .unwrap()
+        };
+        assert_eq!(summary.imported(), 4);
+        assert_eq!(summary.failed(), 1);
+        assert_eq!(failed, [malformed]);
+        let names: Vec<_> = imported
+            .iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["0.eml", "1.eml", "2.eml", "3.eml"]);
+        assert_eq!(commits, 2);
+
+        let store = open_store();
+        let root_folder = store.open_folder(&entry_id).unwrap();
+        assert_eq!(root_folder.properties().content_count().unwrap(), 4);
+        assert_eq!(
+            root_folder.contents_table().unwrap().rows_matrix().count(),
+            4
+        );
+    }
+}
@@ -0,0 +1,230 @@
+//! Content lines and components, which are the common syntax of
+//! [iCalendar](https://www.rfc-editor.org/rfc/rfc5545#section-3.1) and
+//! [vCard](https://www.rfc-editor.org/rfc/rfc6350#section-3.3) objects.
+
+/// One content line, after unfolding. The group prefix of the name, e.g. `item1.` in
+/// `item1.EMAIL`, is dropped.
+#[derive(Clone, Debug)]
+pub(super) struct ContentLine {
+    pub(super) name: String,
+    pub(super) parameters: Vec<(String, String)>,
+    pub(super) value: String,
+}
+
+impl ContentLine {
+    pub(super) fn parse(line: &str) -> Option<Self> {
+        let mut in_quotes = false;
+        let colon = line.char_indices().find_map(|(index, c)| match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                None
+            }
+            ':' if !in_quotes => Some(index),
+            _ => None,
+        })?;
+
+        let (head, value) = (&line[..colon], &line[colon + 1..]);
+        let mut head = split_outside_quotes(head, ';').into_iter();
+        let name = head.next()?.trim();
+        let name = name
+            .rsplit_once('.')
+            .map_or(name, |(_, name)| name)
+            .to_ascii_uppercase();
+        if name.is_empty() {
+            return None;
+        }
+        // vCard 2.1 allows parameters without a name, e.g. `TEL;WORK;VOICE`, which are types.
+        let parameters = head
+            .map(|parameter| match parameter.split_once('=') {
+                Some((name, value)) => (
+                    name.trim().to_ascii_uppercase(),
+                    value.trim().trim_matches('"').to_string(),
+                ),
+                None => (String::from("TYPE"), parameter.trim().to_string()),
+            })
+            .collect();
+
+        Some(Self {
+            name,
+            parameters,
+            value: value.to_string(),
+        })
+    }
+
+    pub(super) fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(parameter, _)| parameter == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every `TYPE` in upper case, whether they are in one list or in separate parameters.
+    pub(super) fn types(&self) -> Vec<String> {
+        self.parameters
+            .iter()
+            .filter(|(parameter, _)| parameter == "TYPE")
+            .flat_map(|(_, value)| value.split(','))
+            .map(|value| value.trim().trim_matches('"').to_ascii_uppercase())
+            .collect()
+    }
+
+    pub(super) fn has_type(&self, value: &str) -> bool {
+        self.types().iter().any(|type_name| type_name == value)
+    }
+}
+
+/// A `BEGIN:<name>` ... `END:<name>` block, e.g. `VCALENDAR`, `VEVENT` or `VCARD`.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Component {
+    pub(super) name: String,
+    pub(super) properties: Vec<ContentLine>,
+    pub(super) components: Vec<Component>,
+}
+
+impl Component {
+    /// Parse the top-level components in a stream, or describe why they are not nested
+    /// correctly.
+    pub(super) fn parse(data: &str) -> Result<Vec<Self>, String> {
+        let mut stack = vec![Component::default()];
+        for line in unfold_lines(data) {
+            let Some(line) = ContentLine::parse(&line) else {
+                continue;
+            };
+            match line.name.as_str() {
+                "BEGIN" => stack.push(Component {
+                    name: line.value.trim().to_ascii_uppercase(),
+                    ..Default::default()
+                }),
+                "END" => {
+                    let name = line.value.trim().to_ascii_uppercase();
+                    if stack.len() < 2 || stack.last().map(|c| c.name.as_str()) != Some(&name) {
+                        return Err(format!("unexpected END:{name}"));
+                    }
+                    if let Some(component) = stack.pop() {
+                        if let Some(parent) = stack.last_mut() {
+                            parent.components.push(component);
+                        }
+                    }
+                }
+                _ => {
+                    if let Some(component) = stack.last_mut() {
+                        component.properties.push(line);
+                    }
+                }
+            }
+        }
+
+        match stack.pop() {
+            Some(root) if stack.is_empty() => Ok(root.components),
+            _ => Err(String::from("missing END for component")),
+        }
+    }
+
+    pub(super) fn property(&self, name: &str) -> Option<&ContentLine> {
+        self.properties_named(name).next()
+    }
+
+    pub(super) fn properties_named<'a>(
+        &'a self,
+        name: &str,
+    ) -> impl Iterator<Item = &'a ContentLine> + 'a {
+        let name = name.to_string();
+        self.properties.iter().filter(move |line| line.name == name)
+    }
+
+    pub(super) fn components_named<'a>(
+        &'a self,
+        name: &str,
+    ) -> impl Iterator<Item = &'a Component> + 'a {
+        let name = name.to_string();
+        self.components
+            .iter()
+            .filter(move |component| component.name == name)
+    }
+}
+
+/// Join lines which are continued on the next line with a leading space or tab, and vCard 2.1
+/// quoted-printable values which end with a soft line break.
+fn unfold_lines(data: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    let mut soft_line_break = false;
+    for line in data.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (_, Some(last)) if soft_line_break => {
+                last.pop();
+                last.push_str(line);
+            }
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if line.is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+        soft_line_break = lines.last().is_some_and(|last| {
+            last.ends_with('=')
+                && last
+                    .split_once(':')
+                    .is_some_and(|(head, _)| head.to_ascii_uppercase().contains("QUOTED-PRINTABLE"))
+        });
+    }
+    lines
+}
+
+fn split_outside_quotes(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (index, c) in value.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c == separator && !in_quotes => {
+                parts.push(&value[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    parts
+}
+
+/// Remove the backslash escapes from a `TEXT` value.
+pub(super) fn unescape_text(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n' | 'N') => result.push_str("\r\n"),
+            Some(c) => result.push(c),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// Split a list of `TEXT` values on the `separator` characters which are not escaped, e.g. the
+/// commas in `CATEGORIES` or the semicolons in the structured value of `N`.
+pub(super) fn split_text_list(value: &str, separator: char) -> Vec<String> {
+    let mut values = vec![];
+    let mut start = 0;
+    let mut escaped = false;
+    for (index, c) in value.char_indices() {
+        match c {
+            '\\' if !escaped => {
+                escaped = true;
+                continue;
+            }
+            c if c == separator && !escaped => {
+                values.push(unescape_text(&value[start..index]));
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+    values.push(unescape_text(&value[start..]));
+    values
+}
@@ -0,0 +1,953 @@
+//! Import [RFC 5322](https://www.rfc-editor.org/rfc/rfc5322) messages, i.e. `.eml` files,
+//! including their [MIME](https://www.rfc-editor.org/rfc/rfc2045) bodies and attachments.
+//!
+//! [`parse`] maps the headers to message properties and recipients, the first `text/plain` and
+//! `text/html` parts to `PidTagBody` and `PidTagHtml`, and every other part to an
+//! [`AttachmentMethod::ByValue`](crate::messaging::attachment::AttachmentMethod::ByValue)
+//! attachment. [`import`] creates the message in a folder.
+
+use std::io::{self, Read};
+
+use crate::{
+    binary_text::{decode_base64, decode_hex_byte},
+    ltp::{
+        code_page::{
+            code_page_from_charset, decode_string8, BuiltinCodePageDecoder, CodePageDecoder,
+        },
+        prop_context::{BinaryValue, GuidValue, PropertyValue},
+    },
+    messaging::{
+        attachment::AttachmentBuilder,
+        conversation_index::*,
+        message::*,
+        object_id::{FolderId, MessageId},
+        MessagingError,
+    },
+    time::{self, from_unix_seconds, unix_seconds_from_civil},
+    PstFile, PstFileLockGuard,
+};
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Parse an RFC 5322 message into a new `IPM.Note` [`MessageBuilder`]. The message is marked as
+/// read, and if there is no `Date` header, the current time is used for the message times.
+pub fn parse(reader: impl Read) -> io::Result<MessageBuilder> {
+    parse_with_decoder(reader, &BuiltinCodePageDecoder)
+}
+
+/// Like [`parse`], but text in a MIME `charset` is decoded with `decoder`, e.g. the one from
+/// [`Store::code_page_decoder`](crate::messaging::store::Store::code_page_decoder), so it can
+/// handle charsets which [`decode_string8`] does not support.
+pub fn parse_with_decoder(
+    mut reader: impl Read,
+    decoder: &dyn CodePageDecoder,
+) -> io::Result<MessageBuilder> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let message = MimePart::parse(&data);
+    if message.headers.is_empty() {
+        return Err(
+            MessagingError::InvalidMimeMessage(String::from("missing header section")).into(),
+        );
+    }
+
+    let mut builder = MessageBuilder::new("IPM.Note");
+
+    let subject = message
+        .header("Subject")
+        .map(|value| decode_encoded_words(value, decoder))
+        .unwrap_or_default();
+    // PidTagSubject
+    builder.set_property(0x0037, unicode_value(&subject));
+    // PidTagConversationTopic
+    builder.set_property(0x0070, unicode_value(&subject));
+
+    let sender = message
+        .header("Sender")
+        .or_else(|| message.header("From"))
+        .and_then(|value| parse_addresses(value, decoder).into_iter().next());
+    let from = message
+        .header("From")
+        .and_then(|value| parse_addresses(value, decoder).into_iter().next());
+    // PidTagSenderName, PidTagSenderAddressType and PidTagSenderEmailAddress
+    if let Some((name, email)) = sender.as_ref().or(from.as_ref()) {
+        builder.set_property(0x0C1A, unicode_value(name));
+        builder.set_property(0x0C1E, unicode_value("SMTP"));
+        builder.set_property(0x0C1F, unicode_value(email));
+    }
+    // PidTagSentRepresentingName, PidTagSentRepresentingAddressType and
+    // PidTagSentRepresentingEmailAddress
+    if let Some((name, email)) = from.as_ref().or(sender.as_ref()) {
+        builder.set_property(0x0042, unicode_value(name));
+        builder.set_property(0x0064, unicode_value("SMTP"));
+        builder.set_property(0x0065, unicode_value(email));
+    }
+
+    for (header, recipient_type) in [
+        ("To", RecipientType::To),
+        ("Cc", RecipientType::Cc),
+        ("Bcc", RecipientType::Bcc),
+    ] {
+        for value in message.headers_named(header) {
+            for (name, email) in parse_addresses(value, decoder) {
+                builder.add_recipient(recipient_type, &name, &email);
+            }
+        }
+    }
+
+    let time = message
+        .header("Date")
+        .and_then(parse_date)
+        .unwrap_or_else(time::now);
+    // PidTagClientSubmitTime, PidTagMessageDeliveryTime, PidTagCreationTime and
+    // PidTagLastModificationTime
+    for prop_id in [0x0039, 0x0E06, 0x3007, 0x3008] {
+        builder.set_property(prop_id, PropertyValue::Time(time));
+    }
+
+    // PidTagInternetMessageId, PidTagInReplyToId and PidTagInternetReferences
+    for (header, prop_id) in [
+        ("Message-ID", 0x1035),
+        ("In-Reply-To", 0x1042),
+        ("References", 0x1039),
+    ] {
+        if let Some(value) = message.header(header) {
+            builder.set_property(prop_id, unicode_value(value));
+        }
+    }
+
+    // Outlook sends the conversation index in Thread-Index. Otherwise start a new one, with the
+    // same GUID for every message which refers to the same thread root.
+    let conversation_index = message
+        .header("Thread-Index")
+        .and_then(|value| ConversationIndex::read(&decode_base64(value.as_bytes())).ok())
+        .unwrap_or_else(|| ConversationIndex::new(time, thread_guid(&message)));
+    builder.set_property(
+        PID_TAG_CONVERSATION_INDEX,
+        PropertyValue::from(&conversation_index),
+    );
+
+    // PidTagTransportMessageHeaders
+    builder.set_property(
+        0x007D,
+        unicode_value(&String::from_utf8_lossy(message.header_section)),
+    );
+    // PidTagMessageFlags
+    builder.set_property(0x0E07, PropertyValue::Integer32(MSGFLAG_READ));
+
+    let mut content = MessageContent::default();
+    content.collect(&message, decoder);
+
+    if let Some(text) = content.text {
+        // PidTagBody
+        builder.set_property(0x1000, unicode_value(&text));
+    }
+    if let Some(html) = content.html {
+        // PidTagHtml and PidTagInternetCodepage
+        builder.set_property(
+            0x1013,
+            PropertyValue::Binary(BinaryValue::new(html.into_bytes())),
+        );
+        builder.set_property(0x3FDE, PropertyValue::Integer32(65001));
+    }
+    // PidTagHasAttachments
+    builder.set_property(
+        0x0E1B,
+        PropertyValue::Boolean(!content.attachments.is_empty()),
+    );
+    for attachment in content.attachments {
+        builder.add_attachment(attachment);
+    }
+
+    Ok(builder)
+}
+
+/// Parse an RFC 5322 message from `reader` and create it in `folder`, which must be a
+/// `NID_TYPE_NORMAL_FOLDER`. Returns the [`MessageId`] of the new message.
+pub fn import<Pst>(
+    guard: &mut PstFileLockGuard<'_, Pst>,
+    folder: FolderId,
+    reader: impl Read,
+) -> io::Result<MessageId>
+where
+    Pst: PstFile,
+{
+    let builder = parse(reader)?;
+    guard.create_message(folder, &builder)
+}
+
+/// A GUID derived from the first `Message-ID` in the thread, i.e. the first one in `References`,
+/// or else `In-Reply-To`, or else the message's own `Message-ID`, so the same thread always gets
+/// the same GUID.
+fn thread_guid(message: &MimePart) -> GuidValue {
+    let thread_root = message
+        .header("References")
+        .and_then(|value| value.split_whitespace().next())
+        .or_else(|| message.header("In-Reply-To"))
+        .or_else(|| message.header("Message-ID"))
+        .map(|value| value.trim().as_bytes())
+        .unwrap_or(message.header_section);
+    GuidValue::from_hash(thread_root)
+}
+
+/// One part of a MIME message, or the whole message, with its unfolded headers.
+struct MimePart<'a> {
+    header_section: &'a [u8],
+    headers: Vec<(String, String)>,
+    body: &'a [u8],
+}
+
+impl<'a> MimePart<'a> {
+    fn parse(data: &'a [u8]) -> Self {
+        let (header_section, body) = split_header_section(data);
+        Self {
+            header_section,
+            headers: unfold_headers(header_section),
+            body,
+        }
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers_named(name).next()
+    }
+
+    fn headers_named<'b>(&'b self, name: &str) -> impl Iterator<Item = &'b str> {
+        let name = name.to_string();
+        self.headers
+            .iter()
+            .filter(move |(header, _)| header.eq_ignore_ascii_case(&name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The lowercase MIME type and its parameters, which default to `text/plain`.
+    fn content_type(&self) -> (String, Vec<(String, String)>) {
+        match self.header("Content-Type") {
+            Some(value) => parse_header_parameters(value),
+            None => (String::from("text/plain"), vec![]),
+        }
+    }
+
+    /// The body with any `Content-Transfer-Encoding` removed.
+    fn decoded_body(&self) -> Vec<u8> {
+        let encoding = self
+            .header("Content-Transfer-Encoding")
+            .map(|value| value.trim().to_ascii_lowercase());
+        match encoding.as_deref() {
+            Some("base64") => decode_base64(self.body),
+            Some("quoted-printable") => decode_quoted_printable(self.body),
+            _ => self.body.to_vec(),
+        }
+    }
+
+    /// The decoded body of a text part, converted from its `charset`.
+    fn decoded_text(&self, decoder: &dyn CodePageDecoder) -> String {
+        let (_, parameters) = self.content_type();
+        let charset = parameter(&parameters, "charset", decoder).unwrap_or_default();
+        decode_charset(&self.decoded_body(), &charset, decoder)
+    }
+}
+
+#[derive(Default)]
+struct MessageContent {
+    text: Option<String>,
+    html: Option<String>,
+    attachments: Vec<AttachmentBuilder>,
+}
+
+impl MessageContent {
+    /// Walk the MIME tree depth-first. The first inline `text/plain` and `text/html` parts are
+    /// the bodies of the message, and everything else is an attachment.
+    fn collect(&mut self, part: &MimePart, decoder: &dyn CodePageDecoder) {
+        let (mime_type, parameters) = part.content_type();
+        if mime_type.starts_with("multipart/") {
+            if let Some(boundary) = parameter(&parameters, "boundary", decoder) {
+                for child in split_multipart(part.body, &boundary) {
+                    self.collect(&MimePart::parse(child), decoder);
+                }
+                return;
+            }
+        }
+
+        let (disposition, disposition_parameters) = part
+            .header("Content-Disposition")
+            .map(parse_header_parameters)
+            .unwrap_or_default();
+        let filename = parameter(&disposition_parameters, "filename", decoder)
+            .or_else(|| parameter(&parameters, "name", decoder));
+        let is_inline = disposition != "attachment" && filename.is_none();
+
+        match mime_type.as_str() {
+            "text/plain" if is_inline && self.text.is_none() => {
+                self.text = Some(part.decoded_text(decoder));
+            }
+            "text/html" if is_inline && self.html.is_none() => {
+                self.html = Some(part.decoded_text(decoder));
+            }
+            _ => {
+                let filename = filename.unwrap_or_else(|| match mime_type.as_str() {
+                    "message/rfc822" => String::from("message.eml"),
+                    _ => String::from("attachment"),
+                });
+                let mut attachment =
+                    AttachmentBuilder::by_value(&filename, Some(&mime_type), part.decoded_body());
+                if let Some(content_id) = part.header("Content-ID") {
+                    let content_id = content_id
+                        .trim()
+                        .trim_start_matches('<')
+                        .trim_end_matches('>');
+                    // PidTagAttachContentId
+                    attachment.set_property(0x3712, unicode_value(content_id));
+                }
+                self.attachments.push(attachment);
+            }
+        }
+    }
+}
+
+/// Split the header section from the body at the first empty line.
+fn split_header_section(data: &[u8]) -> (&[u8], &[u8]) {
+    let mut offset = 0;
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        if line == b"\n" || line == b"\r\n" {
+            return (&data[..offset], &data[offset + line.len()..]);
+        }
+        offset += line.len();
+    }
+    (data, &[])
+}
+
+/// Unfold continuation lines and split each header into its name and value. Lines which are not
+/// headers, like the `From ` separator line of an mbox file, are skipped.
+fn unfold_headers(header_section: &[u8]) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = vec![];
+    for line in String::from_utf8_lossy(header_section).lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push_str(line);
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            if !name.is_empty() && !name.contains(char::is_whitespace) {
+                headers.push((name.to_string(), value.to_string()));
+            }
+        }
+    }
+
+    for (_, value) in headers.iter_mut() {
+        *value = value.trim().to_string();
+    }
+    headers
+}
+
+/// Split a structured header like `Content-Type` into its lowercase value and its parameters.
+/// Parameter names are lowercase, and quoted values are unquoted.
+fn parse_header_parameters(value: &str) -> (String, Vec<(String, String)>) {
+    let mut items = split_outside_quotes(value, ';').into_iter();
+    let value = items.next().unwrap_or_default().trim().to_ascii_lowercase();
+    let parameters = items
+        .filter_map(|item| {
+            let (name, value) = item.split_once('=')?;
+            Some((name.trim().to_ascii_lowercase(), unquote(value.trim())))
+        })
+        .collect();
+    (value, parameters)
+}
+
+/// Look up a parameter, including [RFC 2231](https://www.rfc-editor.org/rfc/rfc2231) extended
+/// values like `filename*=utf-8''%E2%82%AC.txt` and continuations like `filename*0`.
+fn parameter(
+    parameters: &[(String, String)],
+    name: &str,
+    decoder: &dyn CodePageDecoder,
+) -> Option<String> {
+    if let Some((_, value)) = parameters.iter().find(|(key, _)| key == name) {
+        return Some(decode_encoded_words(value, decoder));
+    }
+
+    let mut sections = vec![];
+    for (key, value) in parameters {
+        let Some(suffix) = key.strip_prefix(name).and_then(|key| key.strip_prefix('*')) else {
+            continue;
+        };
+        let (index, encoded) = match suffix.strip_suffix('*') {
+            Some(index) => (index, true),
+            None if suffix.is_empty() => ("0", true),
+            None => (suffix, false),
+        };
+        let Ok(index) = index.parse::<u32>() else {
+            continue;
+        };
+        sections.push((index, encoded, value.as_str()));
+    }
+    if sections.is_empty() {
+        return None;
+    }
+    sections.sort_by_key(|(index, ..)| *index);
+
+    let mut charset = String::new();
+    let mut data = vec![];
+    for (index, encoded, value) in sections {
+        if !encoded {
+            data.extend_from_slice(value.as_bytes());
+            continue;
+        }
+        let value = match (index, value.split_once('\'')) {
+            (0, Some((value_charset, value))) => {
+                charset = value_charset.to_string();
+                value.split_once('\'').map_or(value, |(_, value)| value)
+            }
+            _ => value,
+        };
+        data.extend(decode_percent(value.as_bytes()));
+    }
+    Some(decode_charset(&data, &charset, decoder))
+}
+
+/// Split `value` at each `separator` which is not inside a quoted string.
+fn split_outside_quotes(value: &str, separator: char) -> Vec<&str> {
+    let mut items = vec![];
+    let (mut start, mut in_quotes, mut escaped) = (0, false, false);
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            c if c == separator && !in_quotes => {
+                items.push(&value[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    items.push(&value[start..]);
+    items
+}
+
+fn unquote(value: &str) -> String {
+    match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(value) => {
+            let mut result = String::with_capacity(value.len());
+            let mut chars = value.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => result.extend(chars.next()),
+                    c => result.push(c),
+                }
+            }
+            result
+        }
+        None => value.to_string(),
+    }
+}
+
+/// Split a list of addresses into `(display name, email address)` pairs. Group names are dropped
+/// and the members of the group are returned, and the email address is used as the display name
+/// if there isn't one.
+fn parse_addresses(value: &str, decoder: &dyn CodePageDecoder) -> Vec<(String, String)> {
+    let mut items = vec![];
+    let mut current = String::new();
+    let (mut in_quotes, mut in_angle, mut comment_depth, mut escaped) = (false, false, 0, false);
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_quotes || comment_depth > 0 => escaped = true,
+            '"' if comment_depth == 0 => in_quotes = !in_quotes,
+            '(' if !in_quotes => comment_depth += 1,
+            ')' if !in_quotes && comment_depth > 0 => comment_depth -= 1,
+            '<' if !in_quotes && comment_depth == 0 => in_angle = true,
+            '>' if !in_quotes && comment_depth == 0 => in_angle = false,
+            ':' if !in_quotes && !in_angle && comment_depth == 0 => {
+                current.clear();
+                continue;
+            }
+            ',' | ';' if !in_quotes && !in_angle && comment_depth == 0 => {
+                items.push(std::mem::take(&mut current));
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    items.push(current);
+
+    items
+        .iter()
+        .filter_map(|item| parse_address(item, decoder))
+        .collect()
+}
+
+fn parse_address(item: &str, decoder: &dyn CodePageDecoder) -> Option<(String, String)> {
+    let item = item.trim();
+    let (name, email) = match (item.rfind('<'), item.rfind('>')) {
+        (Some(start), Some(end)) if start < end => (&item[..start], &item[start + 1..end]),
+        _ => match (item.find('('), item.rfind(')')) {
+            (Some(start), Some(end)) if start < end => (&item[start + 1..end], &item[..start]),
+            _ => ("", item),
+        },
+    };
+
+    let email = email.trim();
+    if email.is_empty() {
+        return None;
+    }
+    let name = decode_encoded_words(&unquote(name.trim()), decoder);
+    let name = if name.is_empty() {
+        email.to_string()
+    } else {
+        name
+    };
+    Some((name, email.to_string()))
+}
+
+/// Decode [RFC 2047](https://www.rfc-editor.org/rfc/rfc2047) encoded words like
+/// `=?utf-8?B?4oKs?=` in a header value. Whitespace between two encoded words is removed.
+fn decode_encoded_words(value: &str, decoder: &dyn CodePageDecoder) -> String {
+    let mut result = String::new();
+    let mut rest = value;
+    let mut after_encoded_word = false;
+    while let Some(start) = rest.find("=?") {
+        let (before, candidate) = rest.split_at(start);
+        match decode_encoded_word(candidate, decoder) {
+            Some((decoded, length)) => {
+                if !after_encoded_word || !before.trim().is_empty() {
+                    result.push_str(before);
+                }
+                result.push_str(&decoded);
+                rest = &candidate[length..];
+                after_encoded_word = true;
+            }
+            None => {
+                result.push_str(before);
+                result.push_str("=?");
+                rest = &candidate[2..];
+                after_encoded_word = false;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Decode one encoded word at the start of `word`, and return the decoded text and the length of
+/// the encoded word.
+fn decode_encoded_word(word: &str, decoder: &dyn CodePageDecoder) -> Option<(String, usize)> {
+    let inner = word.strip_prefix("=?")?;
+    let (charset, inner) = inner.split_once('?')?;
+    let (encoding, inner) = inner.split_once('?')?;
+    let end = inner.find("?=")?;
+    let text = &inner[..end];
+    if charset.is_empty() || text.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let data = match encoding {
+        "B" | "b" => decode_base64(text.as_bytes()),
+        "Q" | "q" => decode_quoted_printable(text.replace('_', " ").as_bytes()),
+        _ => return None,
+    };
+    let charset = charset
+        .split_once('*')
+        .map_or(charset, |(charset, _)| charset);
+    let length = word.len() - inner.len() + end + 2;
+    Some((decode_charset(&data, charset, decoder), length))
+}
+
+/// Decode text in a MIME `charset` with `decoder`, falling back to [`decode_string8`] like
+/// [`String8Decoder`](crate::ltp::code_page::String8Decoder) does. Charsets which do not map to a code page, including `us-ascii` which
+/// is often mislabeled 8-bit text, are decoded as UTF-8.
+pub(super) fn decode_charset(data: &[u8], charset: &str, decoder: &dyn CodePageDecoder) -> String {
+    let code_page = code_page_from_charset(charset)
+        .filter(|&code_page| code_page != 20127)
+        .unwrap_or(65001);
+    decoder
+        .decode(data, code_page)
+        .unwrap_or_else(|| decode_string8(data, code_page))
+}
+
+pub(super) fn decode_quoted_printable(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut index = 0;
+    while index < data.len() {
+        match data[index] {
+            b'=' if data[index + 1..].starts_with(b"\r\n") => index += 3,
+            b'=' if data[index + 1..].starts_with(b"\n") => index += 2,
+            b'=' => match data.get(index + 1..index + 3).and_then(decode_hex_byte) {
+                Some(b) => {
+                    result.push(b);
+                    index += 3;
+                }
+                None => {
+                    result.push(b'=');
+                    index += 1;
+                }
+            },
+            b => {
+                result.push(b);
+                index += 1;
+            }
+        }
+    }
+    result
+}
+
+fn decode_percent(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut index = 0;
+    while index < data.len() {
+        match data[index] {
+            b'%' => match data.get(index + 1..index + 3).and_then(decode_hex_byte) {
+                Some(b) => {
+                    result.push(b);
+                    index += 3;
+                }
+                None => {
+                    result.push(b'%');
+                    index += 1;
+                }
+            },
+            b => {
+                result.push(b);
+                index += 1;
+            }
+        }
+    }
+    result
+}
+
+/// Split the body of a multipart entity at each `--boundary` delimiter line, and stop at the
+/// closing `--boundary--` line. The preamble and epilogue are skipped, and a missing closing
+/// delimiter ends the last part at the end of the body.
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = vec![];
+    let mut start = None;
+    let mut offset = 0;
+    for line in body.split_inclusive(|&b| b == b'\n') {
+        let line_start = offset;
+        offset += line.len();
+
+        let Some(rest) = line.trim_ascii_end().strip_prefix(delimiter.as_bytes()) else {
+            continue;
+        };
+        let is_closing = rest == b"--";
+        if !rest.is_empty() && !is_closing {
+            continue;
+        }
+
+        if let Some(start) = start {
+            parts.push(strip_line_break(&body[start..line_start]));
+        }
+        if is_closing {
+            return parts;
+        }
+        start = Some(offset);
+    }
+
+    if let Some(start) = start {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+/// The line break before a boundary delimiter belongs to the delimiter, not the part.
+fn strip_line_break(data: &[u8]) -> &[u8] {
+    let data = data.strip_suffix(b"\n").unwrap_or(data);
+    data.strip_suffix(b"\r").unwrap_or(data)
+}
+
+/// Convert an RFC 5322 `date-time` like `Tue, 1 Jul 2003 10:52:37 +0200` to a `FILETIME`.
+fn parse_date(value: &str) -> Option<i64> {
+    let value = value.split_once(',').map_or(value, |(_, value)| value);
+    let mut tokens = value.split_whitespace();
+
+    let day: i64 = tokens.next()?.parse().ok()?;
+    let month = tokens.next()?.get(..3)?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|name| *name == month)? as i64 + 1;
+    let year: i64 = tokens.next()?.parse().ok()?;
+    let year = match year {
+        0..=49 => year + 2000,
+        50..=999 => year + 1900,
+        _ => year,
+    };
+
+    let mut time = tokens.next()?.split(':');
+    let hour: i64 = time.next()?.parse().ok()?;
+    let minute: i64 = time.next()?.parse().ok()?;
+    let second: i64 = match time.next() {
+        Some(second) => second.parse().ok()?,
+        None => 0,
+    };
+    let zone = tokens.next().map_or(0, zone_offset_minutes);
+
+    let seconds = unix_seconds_from_civil(year, month, day, hour, minute, second) - zone * 60;
+    Some(from_unix_seconds(seconds))
+}
+
+/// Offset from UTC of a numeric zone like `-0700`, or one of the obsolete zone names.
+fn zone_offset_minutes(zone: &str) -> i64 {
+    let hours = match zone.to_ascii_uppercase().as_str() {
+        "EDT" => -4,
+        "EST" | "CDT" => -5,
+        "CST" | "MDT" => -6,
+        "MST" | "PDT" => -7,
+        "PST" => -8,
+        zone => {
+            let (sign, digits) = match zone.as_bytes().first() {
+                Some(b'+') => (1, &zone[1..]),
+                Some(b'-') => (-1, &zone[1..]),
+                _ => return 0,
+            };
+            return match (digits.get(..2), digits.get(2..4)) {
+                (Some(hours), Some(minutes)) => {
+                    match (hours.parse::<i64>(), minutes.parse::<i64>()) {
+                        (Ok(hours), Ok(minutes)) => sign * (hours * 60 + minutes),
+                        _ => 0,
+                    }
+                }
+                _ => 0,
+            };
+        }
+    };
+    hours * 60
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary_text::encode_base64;
+
+    const MESSAGE: &[u8] = b"From: =?utf-8?Q?Ren=C3=A9e?= <renee@example.com>\r
+To: Bob <bob@example.com>, \"Smith, Carol\" <carol@example.com>\r
+Cc: undisclosed-recipients:;\r
+Subject: =?utf-8?B?4oKsIHJl?=\r
+ =?utf-8?B?cG9ydA==?=\r
+Date: Tue, 1 Jul 2003 10:52:37 +0200\r
+Message-ID: <1234@example.com>\r
+Content-Type: multipart/mixed; boundary=\"outer\"\r
+\r
+preamble\r
+--outer\r
+Content-Type: multipart/alternative; boundary=inner\r
+\r
+--inner\r
+Content-Type: text/plain; charset=iso-8859-1\r
+Content-Transfer-Encoding: quoted-printable\r
+\r
+Caf=E9 =\r
+ol\xe9\r
+--inner\r
+Content-Type: text/html; charset=utf-8\r
+\r
+<p>Caf\xc3\xa9</p>\r
+--inner--\r
+--outer\r
+Content-Type: application/octet-stream\r
+Content-Disposition: attachment; filename*=utf-8''%E2%82%AC.bin\r
+Content-Transfer-Encoding: base64\r
+\r
+AAEC/w==\r
+--outer--\r
+epilogue\r
+";
+
+    #[test]
+    fn test_parse_date() {
+        // 2003-07-01T08:52:37Z
+        let expected = from_unix_seconds(1_057_049_557);
+        assert_eq!(parse_date("Tue, 1 Jul 2003 10:52:37 +0200"), Some(expected));
+        assert_eq!(parse_date("1 Jul 03 04:52:37 EDT"), Some(expected));
+        assert_eq!(parse_date("not a date"), None);
+    }
+
+    #[test]
+    fn test_decode_encoded_words() {
+        assert_eq!(
+            decode_encoded_words(
+                "=?utf-8?B?4oKs?= =?utf-8?Q?_5?= total",
+                &BuiltinCodePageDecoder
+            ),
+            "€ 5 total"
+        );
+        assert_eq!(
+            decode_encoded_words("=?iso-8859-1?q?caf=E9?= =? plain", &BuiltinCodePageDecoder),
+            "café =? plain"
+        );
+    }
+
+    #[test]
+    fn test_decode_charset() {
+        assert_eq!(
+            decode_encoded_words("=?Shift_JIS?B?jvOQTYNng4yDQw==?=", &BuiltinCodePageDecoder),
+            "受信トレイ"
+        );
+        assert_eq!(
+            decode_charset(
+                &[0xCA, 0xD5, 0xBC, 0xFE, 0xCF, 0xE4],
+                "gb2312",
+                &BuiltinCodePageDecoder
+            ),
+            "收件箱"
+        );
+        assert_eq!(
+            decode_charset(b"caf\xc3\xa9", "us-ascii", &BuiltinCodePageDecoder),
+            "café"
+        );
+
+        // KOI8-R is not built in, so it is only decoded by a custom decoder.
+        const KOI8_R: &[u8] = b"\xF0\xD2\xC9\xD7\xC5\xD4";
+        assert_eq!(
+            decode_charset(KOI8_R, "koi8-r", &BuiltinCodePageDecoder),
+            "ðÒÉ×ÅÔ"
+        );
+        assert_eq!(decode_charset(KOI8_R, "koi8-r", &Koi8RDecoder), "Привет");
+
+        let builder = parse_with_decoder(
+            &b"Subject: =?koi8-r?B?8NLJ18XU?=\r\n\r\n"[..],
+            &Koi8RDecoder,
+        )
+        .unwrap();
+        let Some(PropertyValue::Unicode(subject)) = builder.properties().get(&0x0037) else {
+            panic!("missing PidTagSubject");
+        };
+        assert_eq!(subject.to_string(), "Привет");
+    }
+
+    /// Decodes the upper case Cyrillic letters of KOI8-R, like a stand-in for an ICU converter.
+    struct Koi8RDecoder;
+
+    impl CodePageDecoder for Koi8RDecoder {
+        fn decode(&self, buffer: &[u8], code_page: u16) -> Option<String> {
+            const UPPER: &str = "ЮАБЦДЕФГХИЙКЛМНОПЯРСТУЖВЬЫЗШЭЩЧЪ";
+            if code_page != 20866 {
+                return None;
+            }
+            Some(
+                buffer
+                    .iter()
+                    .map(|&b| match b {
+                        0xC0..=0xDF => UPPER
+                            .chars()
+                            .nth(usize::from(b - 0xC0))
+                            .and_then(|c| c.to_lowercase().next()),
+                        0xE0..=0xFF => UPPER.chars().nth(usize::from(b - 0xE0)),
+                        b => Some(char::from(b)),
+                    })
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_decode_quoted_printable() {
+        assert_eq!(
+            decode_quoted_printable(b"caf=E9 =+F ok=\r\n"),
+            b"caf\xe9 =+F ok"
+        );
+        assert_eq!(decode_percent(b"%E2%82%AC %+F"), "€ %+F".as_bytes());
+    }
+
+    #[test]
+    fn test_parse_addresses() {
+        assert_eq!(
+            parse_addresses(
+                "\"Smith, Carol\" <carol@example.com>, dave@example.com (Dave)",
+                &BuiltinCodePageDecoder
+            ),
+            vec![
+                (
+                    String::from("Smith, Carol"),
+                    String::from("carol@example.com")
+                ),
+                (String::from("Dave"), String::from("dave@example.com")),
+            ]
+        );
+        assert!(parse_addresses("undisclosed-recipients:;", &BuiltinCodePageDecoder).is_empty());
+    }
+
+    #[test]
+    fn test_parse_message() {
+        let builder = parse(MESSAGE).unwrap();
+        let properties = builder.properties();
+
+        let Some(PropertyValue::Unicode(subject)) = properties.get(&0x0037) else {
+            panic!("missing PidTagSubject");
+        };
+        assert_eq!(subject.to_string(), "€ report");
+        let Some(PropertyValue::Unicode(sender)) = properties.get(&0x0C1A) else {
+            panic!("missing PidTagSenderName");
+        };
+        assert_eq!(sender.to_string(), "Renée");
+        let Some(PropertyValue::Unicode(display_to)) = properties.get(&0x0E04) else {
+            panic!("missing PidTagDisplayTo");
+        };
+        assert_eq!(display_to.to_string(), "Bob; Smith, Carol");
+        let Some(PropertyValue::Unicode(body)) = properties.get(&0x1000) else {
+            panic!("missing PidTagBody");
+        };
+        assert_eq!(body.to_string(), "Café olé");
+        let Some(PropertyValue::Binary(html)) = properties.get(&0x1013) else {
+            panic!("missing PidTagHtml");
+        };
+        assert_eq!(html.buffer(), "<p>Café</p>".as_bytes());
+        assert!(matches!(
+            properties.get(&0x0E07),
+            Some(PropertyValue::Integer32(flags)) if *flags == MSGFLAG_READ | MSGFLAG_HASATTACH
+        ));
+
+        let [attachment] = builder.attachments() else {
+            panic!("expected one attachment");
+        };
+        let Some(PropertyValue::Unicode(filename)) = attachment.properties().get(&0x3707) else {
+            panic!("missing PidTagAttachLongFilename");
+        };
+        assert_eq!(filename.to_string(), "€.bin");
+        let Some(PropertyValue::Binary(data)) = attachment.properties().get(&0x3701) else {
+            panic!("missing PidTagAttachDataBinary");
+        };
+        assert_eq!(data.buffer(), [0x00, 0x01, 0x02, 0xFF]);
+    }
+
+    #[test]
+    fn test_parse_conversation_index() {
+        let conversation_index = |message: &[u8]| {
+            let builder = parse(message).unwrap();
+            ConversationIndex::try_from(
+                builder
+                    .properties()
+                    .get(&PID_TAG_CONVERSATION_INDEX)
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+
+        let original = conversation_index(MESSAGE);
+        assert_eq!(
+            original.time(),
+            parse_date("Tue, 1 Jul 2003 10:52:37 +0200").unwrap() & !0xFFFF
+        );
+        assert!(original.response_levels().is_empty());
+
+        let reply = conversation_index(
+            b"Subject: RE: report\r
+Date: Tue, 1 Jul 2003 11:52:37 +0200\r
+Message-ID: <5678@example.com>\r
+In-Reply-To: <1234@example.com>\r
+References: <1234@example.com>\r
+\r
+",
+        );
+        assert_eq!(reply.guid(), original.guid());
+
+        let expected = original.reply(original.time() + 3600 * 10_000_000, 0x42);
+        let thread_index = format!(
+            "Subject: RE: report\r\nThread-Index: {}\r\n\r\n",
+            encode_base64(&expected.to_bytes())
+        );
+        assert_eq!(conversation_index(thread_index.as_bytes()), expected);
+    }
+}
@@ -0,0 +1,1492 @@
+//! Import [iCalendar](https://www.rfc-editor.org/rfc/rfc5545) objects, i.e. `.ics` files, as
+//! appointments.
+//!
+//! [`parse`] maps each `VEVENT` to an `IPM.Appointment` [`MessageBuilder`] with the properties
+//! from [MS-OXOCAL](https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxocal/09861fde-c8e4-4028-9346-e7c214cfdba1).
+//! Times with a `TZID` are converted to UTC with the matching `VTIMEZONE`, which is also stored
+//! as a time zone definition, and an `RRULE` is serialized to `PidLidAppointmentRecur`.
+//! [`import`] creates the appointments in a folder.
+//!
+//! Modified instances of a recurring event, i.e. a `VEVENT` with a `RECURRENCE-ID`, are not
+//! imported as exceptions, so they are skipped.
+
+use std::io::{self, Read};
+
+use super::content_line::{split_text_list, unescape_text, Component, ContentLine};
+use crate::{
+    binary_text::decode_hex_byte,
+    ltp::prop_context::{BinaryValue, PropertyValue, UnicodeValue},
+    messaging::{
+        message::*,
+        named_prop::*,
+        object_id::{FolderId, MessageId},
+        psetid::*,
+        MessagingError,
+    },
+    time::{
+        self, civil_from_days, days_from_civil, days_in_month, from_unix_seconds,
+        unix_seconds_from_civil, weekday, FILETIME_UNIX_EPOCH_SECONDS, PID_LID_TIME_ZONE_STRUCT,
+    },
+    PstFile, PstFileLockGuard,
+};
+
+/// `olBusy` in `PidLidBusyStatus`
+const BUSY_STATUS_BUSY: i32 = 2;
+
+/// `olFree` in `PidLidBusyStatus`
+const BUSY_STATUS_FREE: i32 = 0;
+
+/// `asfMeeting` in `PidLidAppointmentStateFlags`
+const APPOINTMENT_STATE_MEETING: i32 = 0x0000_0001;
+
+/// Minutes between the `FILETIME` epoch in 1601 and the Unix epoch in 1970.
+const MINUTES_BEFORE_UNIX_EPOCH: i64 = FILETIME_UNIX_EPOCH_SECONDS / 60;
+
+/// `EndDate` of a recurrence pattern which never ends, i.e. 4500-08-31.
+const RECURRENCE_NEVER_ENDS: u32 = 0x5AE9_80DF;
+
+/// `OccurrenceCount` of a recurrence pattern which never ends.
+const RECURRENCE_NEVER_ENDS_COUNT: u32 = 10;
+
+/// Limit on the number of days searched for the last instance of a recurrence.
+const MAX_RECURRENCE_DAYS: i64 = 366 * 200;
+
+/// `Byte Array ID` at the start of `PidLidGlobalObjectId`.
+const GLOBAL_OBJECT_ID_TYPE: [u8; 16] = [
+    0x04, 0x00, 0x00, 0x00, 0x82, 0x00, 0xE0, 0x00, 0x74, 0xC5, 0xB7, 0x10, 0x1A, 0x82, 0xE0, 0x08,
+];
+
+const WEEKDAYS: [&str; 7] = ["SU", "MO", "TU", "WE", "TH", "FR", "SA"];
+
+/// Parse every `VEVENT` in an iCalendar stream into a new `IPM.Appointment`
+/// [`MessageBuilder`], in the order they appear.
+pub fn parse(mut reader: impl Read) -> io::Result<Vec<MessageBuilder>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let data = String::from_utf8_lossy(&data);
+
+    let calendars: Vec<_> = Component::parse(&data)
+        .map_err(MessagingError::InvalidCalendarObject)?
+        .into_iter()
+        .filter(|component| component.name == "VCALENDAR")
+        .collect();
+    if calendars.is_empty() {
+        return Err(
+            MessagingError::InvalidCalendarObject(String::from("missing VCALENDAR")).into(),
+        );
+    }
+
+    let mut appointments = vec![];
+    for calendar in calendars {
+        let time_zones: Vec<_> = calendar
+            .components_named("VTIMEZONE")
+            .filter_map(TimeZone::parse)
+            .collect();
+        for event in calendar
+            .components_named("VEVENT")
+            .filter(|event| event.property("RECURRENCE-ID").is_none())
+        {
+            appointments.push(parse_event(event, &time_zones)?);
+        }
+    }
+    Ok(appointments)
+}
+
+/// Parse an iCalendar stream from `reader` and create each `VEVENT` in `folder`, which must be
+/// a `NID_TYPE_NORMAL_FOLDER`. Returns the [`MessageId`] of each new appointment.
+pub fn import<Pst>(
+    guard: &mut PstFileLockGuard<'_, Pst>,
+    folder: FolderId,
+    reader: impl Read,
+) -> io::Result<Vec<MessageId>>
+where
+    Pst: PstFile,
+{
+    parse(reader)?
+        .iter()
+        .map(|builder| guard.create_message(folder, builder))
+        .collect()
+}
+
+fn parse_event(event: &Component, time_zones: &[TimeZone]) -> io::Result<MessageBuilder> {
+    let time_zone = |line: &ContentLine| {
+        line.parameter("TZID").and_then(|id| {
+            let id = id.trim_start_matches('/');
+            time_zones.iter().find(|time_zone| time_zone.id == id)
+        })
+    };
+
+    let start_line = event.property("DTSTART").ok_or_else(|| {
+        MessagingError::InvalidCalendarObject(String::from("VEVENT is missing DTSTART"))
+    })?;
+    let start = CalendarTime::parse(start_line, time_zone(start_line)).ok_or_else(|| {
+        MessagingError::InvalidCalendarObject(format!("invalid DTSTART: {}", start_line.value))
+    })?;
+    let end = match (event.property("DTEND"), event.property("DURATION")) {
+        (Some(line), _) => CalendarTime::parse(line, time_zone(line)).ok_or_else(|| {
+            MessagingError::InvalidCalendarObject(format!("invalid DTEND: {}", line.value))
+        })?,
+        (None, Some(line)) => {
+            let duration = parse_duration(&line.value).ok_or_else(|| {
+                MessagingError::InvalidCalendarObject(format!("invalid DURATION: {}", line.value))
+            })?;
+            start.offset(duration)
+        }
+        (None, None) if start.all_day => start.offset(86_400),
+        (None, None) => start.clone(),
+    };
+    let duration = (end.utc - start.utc).max(0) / 60;
+
+    let mut builder = MessageBuilder::new("IPM.Appointment");
+
+    let subject = event
+        .property("SUMMARY")
+        .map(|line| unescape_text(&line.value))
+        .unwrap_or_default();
+    // PidTagSubject
+    builder.set_property(0x0037, unicode_value(&subject));
+    // PidTagConversationTopic
+    builder.set_property(0x0070, unicode_value(&subject));
+
+    if let Some(line) = event.property("DESCRIPTION") {
+        // PidTagBody
+        builder.set_property(0x1000, unicode_value(&unescape_text(&line.value)));
+    }
+    if let Some(line) = event.property("LOCATION") {
+        // PidLidLocation
+        builder.set_named_property(
+            PSETID_APPOINTMENT,
+            0x8208,
+            unicode_value(&unescape_text(&line.value)),
+        );
+    }
+
+    let start_time = PropertyValue::Time(from_unix_seconds(start.utc));
+    let end_time = PropertyValue::Time(from_unix_seconds(end.utc));
+    // PidTagStartDate and PidTagEndDate
+    builder.set_property(0x0060, start_time.clone());
+    builder.set_property(0x0061, end_time.clone());
+    // PidLidAppointmentStartWhole and PidLidAppointmentEndWhole
+    builder.set_named_property(PSETID_APPOINTMENT, 0x820D, start_time.clone());
+    builder.set_named_property(PSETID_APPOINTMENT, 0x820E, end_time.clone());
+    // PidLidCommonStart and PidLidCommonEnd
+    builder.set_named_property(PSETID_COMMON, 0x8516, start_time.clone());
+    builder.set_named_property(PSETID_COMMON, 0x8517, end_time.clone());
+    // PidLidAppointmentDuration
+    builder.set_named_property(
+        PSETID_APPOINTMENT,
+        0x8213,
+        PropertyValue::Integer32(duration as i32),
+    );
+    // PidLidAppointmentSubType
+    builder.set_named_property(
+        PSETID_APPOINTMENT,
+        0x8215,
+        PropertyValue::Boolean(start.all_day),
+    );
+
+    let busy_status = match event.property("TRANSP") {
+        Some(line) if line.value.eq_ignore_ascii_case("TRANSPARENT") => BUSY_STATUS_FREE,
+        _ => BUSY_STATUS_BUSY,
+    };
+    // PidLidBusyStatus
+    builder.set_named_property(
+        PSETID_APPOINTMENT,
+        0x8205,
+        PropertyValue::Integer32(busy_status),
+    );
+
+    if let Some(line) = event.property("CLASS") {
+        let sensitivity = match line.value.to_ascii_uppercase().as_str() {
+            "PRIVATE" => 2,
+            "CONFIDENTIAL" => 3,
+            _ => 0,
+        };
+        // PidTagSensitivity
+        builder.set_property(0x0036, PropertyValue::Integer32(sensitivity));
+    }
+
+    let categories: Vec<_> = event
+        .properties_named("CATEGORIES")
+        .flat_map(|line| split_text_list(&line.value, ','))
+        .filter(|category| !category.is_empty())
+        .map(|category| UnicodeValue::new(category.encode_utf16().collect()))
+        .collect();
+    if !categories.is_empty() {
+        // PidNameKeywords
+        builder.set_named_property(
+            PS_PUBLIC_STRINGS,
+            "Keywords",
+            PropertyValue::MultipleUnicode(categories),
+        );
+    }
+
+    if let Some(line) = event.property("UID") {
+        let global_object_id = global_object_id(&line.value);
+        let clean_global_object_id = global_object_id_clean(&global_object_id);
+        // PidLidGlobalObjectId and PidLidCleanGlobalObjectId
+        builder.set_named_property(
+            PSETID_MEETING,
+            0x0003,
+            PropertyValue::Binary(BinaryValue::new(global_object_id)),
+        );
+        builder.set_named_property(
+            PSETID_MEETING,
+            0x0023,
+            PropertyValue::Binary(BinaryValue::new(clean_global_object_id)),
+        );
+    }
+
+    if let Some((name, email)) = event.property("ORGANIZER").and_then(calendar_address) {
+        // PidTagSenderName, PidTagSenderAddressType and PidTagSenderEmailAddress
+        builder.set_property(0x0C1A, unicode_value(&name));
+        builder.set_property(0x0C1E, unicode_value("SMTP"));
+        builder.set_property(0x0C1F, unicode_value(&email));
+        // PidTagSentRepresentingName, PidTagSentRepresentingAddressType and
+        // PidTagSentRepresentingEmailAddress
+        builder.set_property(0x0042, unicode_value(&name));
+        builder.set_property(0x0064, unicode_value("SMTP"));
+        builder.set_property(0x0065, unicode_value(&email));
+    }
+
+    let mut is_meeting = false;
+    for line in event.properties_named("ATTENDEE") {
+        let Some((name, email)) = calendar_address(line) else {
+            continue;
+        };
+        let role = line.parameter("ROLE").unwrap_or_default();
+        let user_type = line.parameter("CUTYPE").unwrap_or_default();
+        let recipient_type = if role.eq_ignore_ascii_case("OPT-PARTICIPANT") {
+            RecipientType::Cc
+        } else if role.eq_ignore_ascii_case("NON-PARTICIPANT")
+            || user_type.eq_ignore_ascii_case("RESOURCE")
+            || user_type.eq_ignore_ascii_case("ROOM")
+        {
+            RecipientType::Bcc
+        } else {
+            RecipientType::To
+        };
+        builder.add_recipient(recipient_type, &name, &email);
+        is_meeting = true;
+    }
+    if is_meeting {
+        // PidLidAppointmentStateFlags
+        builder.set_named_property(
+            PSETID_APPOINTMENT,
+            0x8217,
+            PropertyValue::Integer32(APPOINTMENT_STATE_MEETING),
+        );
+    }
+
+    if let Some(alarm) = event.components_named("VALARM").next() {
+        let delta = alarm
+            .property("TRIGGER")
+            .and_then(|line| parse_duration(&line.value))
+            .map_or(15, |seconds| (-seconds / 60).max(0));
+        // PidLidReminderSet, PidLidReminderDelta, PidLidReminderTime and
+        // PidLidReminderSignalTime
+        builder.set_named_property(PSETID_COMMON, 0x8503, PropertyValue::Boolean(true));
+        builder.set_named_property(
+            PSETID_COMMON,
+            0x8501,
+            PropertyValue::Integer32(delta as i32),
+        );
+        builder.set_named_property(PSETID_COMMON, 0x8502, start_time.clone());
+        builder.set_named_property(
+            PSETID_COMMON,
+            0x8560,
+            PropertyValue::Time(from_unix_seconds(start.utc - delta * 60)),
+        );
+    }
+
+    if let Some(time_zone) = start.time_zone {
+        // PidLidTimeZoneDescription
+        builder.set_named_property(PSETID_APPOINTMENT, 0x8234, unicode_value(&time_zone.id));
+        // PidLidAppointmentTimeZoneDefinitionStartDisplay
+        builder.set_named_property(
+            PSETID_APPOINTMENT,
+            0x825E,
+            PropertyValue::Binary(BinaryValue::new(
+                time_zone.definition(TZRULE_FLAG_EFFECTIVE_TZREG),
+            )),
+        );
+    }
+    if let Some(time_zone) = end.time_zone.or(start.time_zone) {
+        // PidLidAppointmentTimeZoneDefinitionEndDisplay
+        builder.set_named_property(
+            PSETID_APPOINTMENT,
+            0x825F,
+            PropertyValue::Binary(BinaryValue::new(
+                time_zone.definition(TZRULE_FLAG_EFFECTIVE_TZREG),
+            )),
+        );
+    }
+
+    let recurrence = match event.property("RRULE") {
+        Some(line) => {
+            let rule = RecurrenceRule::parse(&line.value, start.time_zone).ok_or_else(|| {
+                MessagingError::InvalidCalendarObject(format!("unsupported RRULE: {}", line.value))
+            })?;
+            let deleted = event
+                .properties_named("EXDATE")
+                .flat_map(|line| {
+                    let time_zone = time_zone(line).or(start.time_zone);
+                    line.value
+                        .split(',')
+                        .filter_map(move |value| parse_date_time(value).map(|t| (t, time_zone)))
+                })
+                .map(|((seconds, kind), time_zone)| match (kind, time_zone) {
+                    (TimeKind::Utc, Some(time_zone)) => time_zone.to_local(seconds),
+                    _ => seconds,
+                })
+                .map(|seconds| seconds.div_euclid(86_400))
+                .collect();
+            Some(RecurrencePattern::new(&rule, &start, deleted))
+        }
+        None => None,
+    };
+    // PidLidRecurring and PidLidIsRecurring
+    builder.set_named_property(
+        PSETID_APPOINTMENT,
+        0x8223,
+        PropertyValue::Boolean(recurrence.is_some()),
+    );
+    builder.set_named_property(
+        PSETID_MEETING,
+        0x0005,
+        PropertyValue::Boolean(recurrence.is_some()),
+    );
+
+    let (clip_start, clip_end) = match &recurrence {
+        Some(recurrence) => {
+            // PidLidAppointmentRecur and PidLidRecurrenceType
+            builder.set_named_property(
+                PSETID_APPOINTMENT,
+                0x8216,
+                PropertyValue::Binary(BinaryValue::new(
+                    recurrence.appointment_pattern(start.local, duration),
+                )),
+            );
+            builder.set_named_property(
+                PSETID_APPOINTMENT,
+                0x8231,
+                PropertyValue::Integer32(recurrence.frequency.recurrence_type()),
+            );
+
+            if let Some(time_zone) = start.time_zone {
+                // PidLidTimeZoneStruct and PidLidAppointmentTimeZoneDefinitionRecur
+                builder.set_named_property(
+                    PSETID_APPOINTMENT,
+                    PID_LID_TIME_ZONE_STRUCT,
+                    PropertyValue::Binary(BinaryValue::new(time_zone.tz_struct())),
+                );
+                builder.set_named_property(
+                    PSETID_APPOINTMENT,
+                    0x8260,
+                    PropertyValue::Binary(BinaryValue::new(time_zone.definition(
+                        TZRULE_FLAG_EFFECTIVE_TZREG | TZRULE_FLAG_RECUR_CURRENT_TZREG,
+                    ))),
+                );
+            }
+
+            let clip_end = match recurrence.end_day() {
+                Some(day) => day * 86_400,
+                None => (i64::from(RECURRENCE_NEVER_ENDS) - MINUTES_BEFORE_UNIX_EPOCH) * 60,
+            };
+            (
+                from_unix_seconds(recurrence.start_day * 86_400),
+                from_unix_seconds(clip_end),
+            )
+        }
+        None => (from_unix_seconds(start.utc), from_unix_seconds(end.utc)),
+    };
+    // PidLidClipStart and PidLidClipEnd
+    builder.set_named_property(PSETID_APPOINTMENT, 0x8235, PropertyValue::Time(clip_start));
+    builder.set_named_property(PSETID_APPOINTMENT, 0x8236, PropertyValue::Time(clip_end));
+
+    let created = event
+        .property("CREATED")
+        .or_else(|| event.property("DTSTAMP"))
+        .and_then(|line| parse_date_time(&line.value))
+        .map_or_else(time::now, |(seconds, _)| from_unix_seconds(seconds));
+    let modified = event
+        .property("LAST-MODIFIED")
+        .and_then(|line| parse_date_time(&line.value))
+        .map_or(created, |(seconds, _)| from_unix_seconds(seconds));
+    // PidTagCreationTime and PidTagLastModificationTime
+    builder.set_property(0x3007, PropertyValue::Time(created));
+    builder.set_property(0x3008, PropertyValue::Time(modified));
+    // PidTagMessageFlags
+    builder.set_property(0x0E07, PropertyValue::Integer32(MSGFLAG_READ));
+
+    Ok(builder)
+}
+
+/// The display name and email address of an `ORGANIZER` or `ATTENDEE`.
+fn calendar_address(line: &ContentLine) -> Option<(String, String)> {
+    let email = line
+        .value
+        .get(..7)
+        .filter(|scheme| scheme.eq_ignore_ascii_case("mailto:"))
+        .map_or(line.value.as_str(), |_| &line.value[7..])
+        .trim();
+    if email.is_empty() {
+        return None;
+    }
+    let name = line.parameter("CN").unwrap_or(email);
+    Some((name.to_string(), email.to_string()))
+}
+
+/// Build a `PidLidGlobalObjectId` for an iCalendar `UID`. A `UID` which Outlook exported from a
+/// `PidLidGlobalObjectId` is decoded back to the original bytes, and any other `UID` is wrapped
+/// in the `vCal-Uid` format.
+fn global_object_id(uid: &str) -> Vec<u8> {
+    let hex = uid.as_bytes();
+    if hex.len() % 2 == 0 && hex.len() > 2 * GLOBAL_OBJECT_ID_TYPE.len() {
+        let bytes: Option<Vec<_>> = hex.chunks(2).map(decode_hex_byte).collect();
+        if let Some(bytes) = bytes.filter(|bytes| bytes.starts_with(&GLOBAL_OBJECT_ID_TYPE)) {
+            return bytes;
+        }
+    }
+
+    let mut data = b"vCal-Uid".to_vec();
+    data.extend_from_slice(&1_u32.to_le_bytes());
+    data.extend_from_slice(uid.as_bytes());
+    data.push(0);
+
+    let mut global_object_id = GLOBAL_OBJECT_ID_TYPE.to_vec();
+    // YH, YL, M and D are 0 in PidLidCleanGlobalObjectId and for the whole series.
+    global_object_id.extend_from_slice(&[0; 4]);
+    // Creation Time and Reserved
+    global_object_id.extend_from_slice(&[0; 16]);
+    global_object_id.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    global_object_id.extend_from_slice(&data);
+    global_object_id
+}
+
+/// `PidLidCleanGlobalObjectId` is `PidLidGlobalObjectId` without the instance date.
+fn global_object_id_clean(global_object_id: &[u8]) -> Vec<u8> {
+    let mut clean = global_object_id.to_vec();
+    if let Some(date) = clean.get_mut(16..20) {
+        date.fill(0);
+    }
+    clean
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TimeKind {
+    /// A `DATE` value, which has no time of day.
+    Date,
+    /// A `DATE-TIME` in local time, either floating or relative to a `TZID`.
+    Local,
+    /// A `DATE-TIME` ending in `Z`.
+    Utc,
+}
+
+/// Parse a `DATE` like `19970714` or a `DATE-TIME` like `19970714T173000Z` into seconds since
+/// the Unix epoch, without converting local times to UTC.
+fn parse_date_time(value: &str) -> Option<(i64, TimeKind)> {
+    let value = value.trim();
+    let (date, time) = match value.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let year: i64 = date[..4].parse().ok()?;
+    let month: i64 = date[4..6].parse().ok()?;
+    let day: i64 = date[6..].parse().ok()?;
+    let days = days_from_civil(year, month, day);
+
+    let Some(time) = time else {
+        return Some((days * 86_400, TimeKind::Date));
+    };
+    let (time, kind) = match time.strip_suffix(['Z', 'z']) {
+        Some(time) => (time, TimeKind::Utc),
+        None => (time, TimeKind::Local),
+    };
+    if time.len() != 6 || !time.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hour: i64 = time[..2].parse().ok()?;
+    let minute: i64 = time[2..4].parse().ok()?;
+    let second: i64 = time[4..].parse().ok()?;
+    Some((
+        unix_seconds_from_civil(year, month, day, hour, minute, second),
+        kind,
+    ))
+}
+
+/// Parse a `DURATION` like `PT1H30M` or `-P1W` into seconds.
+fn parse_duration(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (sign, value) = match value.as_bytes().first() {
+        Some(b'-') => (-1, &value[1..]),
+        Some(b'+') => (1, &value[1..]),
+        _ => (1, value),
+    };
+    let value = value.strip_prefix(['P', 'p'])?;
+
+    let mut seconds = 0;
+    let mut number = String::new();
+    for c in value.chars() {
+        match c.to_ascii_uppercase() {
+            c if c.is_ascii_digit() => number.push(c),
+            'T' => {}
+            unit => {
+                let count: i64 = number.parse().ok()?;
+                number.clear();
+                seconds += count
+                    * match unit {
+                        'W' => 7 * 86_400,
+                        'D' => 86_400,
+                        'H' => 3_600,
+                        'M' => 60,
+                        'S' => 1,
+                        _ => return None,
+                    };
+            }
+        }
+    }
+    if !number.is_empty() {
+        return None;
+    }
+    Some(sign * seconds)
+}
+
+/// Parse a `UTC-OFFSET` like `-0500` or `+053000` into minutes.
+fn parse_utc_offset(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let (sign, digits) = match value.as_bytes().first() {
+        Some(b'+') => (1, &value[1..]),
+        Some(b'-') => (-1, &value[1..]),
+        _ => return None,
+    };
+    let hours: i64 = digits.get(..2)?.parse().ok()?;
+    let minutes: i64 = digits.get(2..4)?.parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Parse a `BYDAY` entry like `MO`, `2TU` or `-1SU` into an optional ordinal and a weekday.
+fn parse_weekday(value: &str) -> Option<(i64, i64)> {
+    let value = value.trim();
+    let split = value.len().checked_sub(2)?;
+    let name = value.get(split..)?;
+    let weekday = WEEKDAYS
+        .iter()
+        .position(|weekday| name.eq_ignore_ascii_case(weekday))? as i64;
+    let ordinal = match value.get(..split)? {
+        "" => 0,
+        ordinal => ordinal.trim_start_matches('+').parse().ok()?,
+    };
+    Some((ordinal, weekday))
+}
+
+/// A time from `DTSTART` or `DTEND`, in UTC and in the time zone of the event.
+#[derive(Clone, Debug)]
+struct CalendarTime<'a> {
+    utc: i64,
+    local: i64,
+    all_day: bool,
+    time_zone: Option<&'a TimeZone>,
+}
+
+impl<'a> CalendarTime<'a> {
+    fn parse(line: &ContentLine, time_zone: Option<&'a TimeZone>) -> Option<Self> {
+        let (seconds, kind) = parse_date_time(&line.value)?;
+        let (utc, local) = match (kind, time_zone) {
+            (TimeKind::Utc, Some(time_zone)) => (seconds, time_zone.to_local(seconds)),
+            (TimeKind::Utc, None) => (seconds, seconds),
+            (_, Some(time_zone)) => (time_zone.to_utc(seconds), seconds),
+            (_, None) => (seconds, seconds),
+        };
+        Some(Self {
+            utc,
+            local,
+            all_day: kind == TimeKind::Date,
+            time_zone,
+        })
+    }
+
+    fn offset(&self, seconds: i64) -> Self {
+        let local = self.local + seconds;
+        let utc = match self.time_zone {
+            Some(time_zone) => time_zone.to_utc(local),
+            None => self.utc + seconds,
+        };
+        Self {
+            utc,
+            local,
+            ..self.clone()
+        }
+    }
+}
+
+/// `TZRULE_FLAG_RECUR_CURRENT_TZREG`
+const TZRULE_FLAG_RECUR_CURRENT_TZREG: u16 = 0x0001;
+
+/// `TZRULE_FLAG_EFFECTIVE_TZREG`
+const TZRULE_FLAG_EFFECTIVE_TZREG: u16 = 0x0002;
+
+/// `TZDEFINITION_FLAG_VALID_KEYNAME`
+const TZDEFINITION_FLAG_VALID_KEYNAME: u16 = 0x0002;
+
+/// A `STANDARD` or `DAYLIGHT` sub-component of a `VTIMEZONE`, reduced to the yearly transition
+/// rule which Outlook time zone definitions support.
+#[derive(Clone, Debug)]
+struct Observance {
+    /// Local start time of the observance, in the offset of the previous observance.
+    start: i64,
+    /// Offset from UTC in minutes while this observance is in effect.
+    offset: i64,
+    month: i64,
+    weekday: i64,
+    /// The week of the month from 1 to 4, or 5 for the last week.
+    week: i64,
+}
+
+impl Observance {
+    fn parse(component: &Component) -> Option<Self> {
+        let (start, _) = parse_date_time(&component.property("DTSTART")?.value)?;
+        let offset = parse_utc_offset(&component.property("TZOFFSETTO")?.value)?;
+        let days = start.div_euclid(86_400);
+        let (_, start_month, start_day) = civil_from_days(days);
+
+        let mut month = start_month;
+        let mut weekday = self::weekday(days);
+        let mut week = ((start_day - 1) / 7 + 1).min(4);
+        if let Some(rule) = component.property("RRULE") {
+            for (name, value) in rule
+                .value
+                .split(';')
+                .filter_map(|part| part.split_once('='))
+            {
+                match name.to_ascii_uppercase().as_str() {
+                    "BYMONTH" => month = value.parse().ok()?,
+                    "BYDAY" => {
+                        let (ordinal, day) = parse_weekday(value)?;
+                        weekday = day;
+                        week = if (1..=4).contains(&ordinal) {
+                            ordinal
+                        } else {
+                            5
+                        };
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Some(Self {
+            start,
+            offset,
+            month,
+            weekday,
+            week,
+        })
+    }
+
+    /// The local time of the transition to this observance in `year`.
+    fn transition(&self, year: i64) -> i64 {
+        let first = days_from_civil(year, self.month, 1);
+        let mut day = first + (self.weekday - weekday(first)).rem_euclid(7) + (self.week - 1) * 7;
+        while day >= first + days_in_month(year, self.month) {
+            day -= 7;
+        }
+        day * 86_400 + self.start.rem_euclid(86_400)
+    }
+
+    /// `SYSTEMTIME` in the relative format used by `TIME_ZONE_INFORMATION`.
+    fn system_time(&self) -> Vec<u8> {
+        let time = self.start.rem_euclid(86_400);
+        [
+            0,
+            self.month,
+            self.weekday,
+            self.week,
+            time / 3_600,
+            time / 60 % 60,
+            time % 60,
+            0,
+        ]
+        .into_iter()
+        .flat_map(|value| (value as u16).to_le_bytes())
+        .collect()
+    }
+}
+
+/// A `VTIMEZONE` with its current standard time and, optionally, daylight saving time rules.
+#[derive(Clone, Debug)]
+struct TimeZone {
+    id: String,
+    standard: Observance,
+    daylight: Option<Observance>,
+}
+
+impl TimeZone {
+    fn parse(component: &Component) -> Option<Self> {
+        let id = component.property("TZID")?.value.trim_start_matches('/');
+        let latest = |name| {
+            component
+                .components_named(name)
+                .filter_map(Observance::parse)
+                .max_by_key(|observance| observance.start)
+        };
+        let standard = latest("STANDARD");
+        let daylight = latest("DAYLIGHT");
+        let (standard, daylight) = match (standard, daylight) {
+            (Some(standard), daylight) => (standard, daylight),
+            (None, Some(daylight)) => (daylight, None),
+            (None, None) => return None,
+        };
+
+        Some(Self {
+            id: id.to_string(),
+            standard,
+            daylight,
+        })
+    }
+
+    /// Offset from UTC in minutes at a local time.
+    fn offset(&self, local: i64) -> i64 {
+        let Some(daylight) = &self.daylight else {
+            return self.standard.offset;
+        };
+        let (year, _, _) = civil_from_days(local.div_euclid(86_400));
+        let daylight_start = daylight.transition(year);
+        let daylight_end = self.standard.transition(year);
+        let is_daylight = if daylight_start < daylight_end {
+            local >= daylight_start && local < daylight_end
+        } else {
+            local >= daylight_start || local < daylight_end
+        };
+        if is_daylight {
+            daylight.offset
+        } else {
+            self.standard.offset
+        }
+    }
+
+    fn to_utc(&self, local: i64) -> i64 {
+        local - self.offset(local) * 60
+    }
+
+    fn to_local(&self, utc: i64) -> i64 {
+        let local = utc + self.standard.offset * 60;
+        utc + self.offset(local) * 60
+    }
+
+    /// `lBias`, `lStandardBias` and `lDaylightBias`, where UTC = local time + bias.
+    fn biases(&self) -> [i32; 3] {
+        let bias = -self.standard.offset;
+        let daylight_bias = self
+            .daylight
+            .as_ref()
+            .map_or(0, |daylight| self.standard.offset - daylight.offset);
+        [bias as i32, 0, daylight_bias as i32]
+    }
+
+    /// `stStandardDate` and `stDaylightDate`, which are empty without daylight saving time.
+    fn system_times(&self) -> [Vec<u8>; 2] {
+        match &self.daylight {
+            Some(daylight) => [self.standard.system_time(), daylight.system_time()],
+            None => [vec![0; 16], vec![0; 16]],
+        }
+    }
+
+    /// `PidLidTimeZoneStruct`
+    fn tz_struct(&self) -> Vec<u8> {
+        let [standard, daylight] = self.system_times();
+        let mut data: Vec<_> = self
+            .biases()
+            .into_iter()
+            .flat_map(i32::to_le_bytes)
+            .collect();
+        data.extend_from_slice(&0_u16.to_le_bytes());
+        data.extend_from_slice(&standard);
+        data.extend_from_slice(&0_u16.to_le_bytes());
+        data.extend_from_slice(&daylight);
+        data
+    }
+
+    /// A `TZDEFINITION` with a single `TZRULE`, which has `flags`.
+    fn definition(&self, flags: u16) -> Vec<u8> {
+        let key_name: Vec<u16> = self.id.encode_utf16().collect();
+        let mut data = vec![0x02, 0x01];
+        let header_size = 6 + 2 * key_name.len();
+        data.extend_from_slice(&(header_size as u16).to_le_bytes());
+        data.extend_from_slice(&TZDEFINITION_FLAG_VALID_KEYNAME.to_le_bytes());
+        data.extend_from_slice(&(key_name.len() as u16).to_le_bytes());
+        data.extend(key_name.into_iter().flat_map(u16::to_le_bytes));
+        data.extend_from_slice(&1_u16.to_le_bytes());
+
+        // TZRULE
+        let year = self
+            .daylight
+            .iter()
+            .chain([&self.standard])
+            .map(|observance| civil_from_days(observance.start.div_euclid(86_400)).0)
+            .max()
+            .unwrap_or(1601)
+            .max(1601);
+        data.extend_from_slice(&[0x02, 0x01]);
+        data.extend_from_slice(&0x003E_u16.to_le_bytes());
+        data.extend_from_slice(&flags.to_le_bytes());
+        data.extend_from_slice(&(year as u16).to_le_bytes());
+        data.extend_from_slice(&[0; 14]);
+        data.extend(self.biases().into_iter().flat_map(i32::to_le_bytes));
+        let [standard, daylight] = self.system_times();
+        data.extend_from_slice(&standard);
+        data.extend_from_slice(&daylight);
+        data
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+impl Frequency {
+    /// `RecurFrequency` in the recurrence pattern.
+    fn recur_frequency(self) -> u16 {
+        match self {
+            Self::Daily => 0x200A,
+            Self::Weekly => 0x200B,
+            Self::Monthly => 0x200C,
+            Self::Yearly => 0x200D,
+        }
+    }
+
+    /// `PidLidRecurrenceType`
+    fn recurrence_type(self) -> i32 {
+        match self {
+            Self::Daily => 1,
+            Self::Weekly => 2,
+            Self::Monthly => 3,
+            Self::Yearly => 4,
+        }
+    }
+}
+
+/// The parts of an `RRULE` which can be represented in a recurrence pattern.
+#[derive(Clone, Debug)]
+struct RecurrenceRule {
+    frequency: Frequency,
+    interval: i64,
+    count: Option<u32>,
+    /// Local time of the last possible instance.
+    until: Option<i64>,
+    by_day: Vec<(i64, i64)>,
+    by_month_day: Option<i64>,
+    by_set_position: Option<i64>,
+    week_start: i64,
+}
+
+impl RecurrenceRule {
+    fn parse(value: &str, time_zone: Option<&TimeZone>) -> Option<Self> {
+        let mut frequency = None;
+        let mut rule = Self {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: vec![],
+            by_month_day: None,
+            by_set_position: None,
+            week_start: 1,
+        };
+
+        for (name, value) in value.split(';').filter_map(|part| part.split_once('=')) {
+            match name.trim().to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return None,
+                    })
+                }
+                "INTERVAL" => rule.interval = value.parse().ok().filter(|n| *n > 0)?,
+                "COUNT" => rule.count = Some(value.parse().ok()?),
+                "UNTIL" => {
+                    let (seconds, kind) = parse_date_time(value)?;
+                    rule.until = Some(match (kind, time_zone) {
+                        (TimeKind::Utc, Some(time_zone)) => time_zone.to_local(seconds),
+                        (TimeKind::Date, _) => seconds + 86_399,
+                        _ => seconds,
+                    });
+                }
+                "BYDAY" => {
+                    rule.by_day = value.split(',').map(parse_weekday).collect::<Option<_>>()?
+                }
+                "BYMONTHDAY" => rule.by_month_day = Some(value.split(',').next()?.parse().ok()?),
+                "BYSETPOS" => rule.by_set_position = Some(value.split(',').next()?.parse().ok()?),
+                "WKST" => {
+                    rule.week_start = parse_weekday(value).map(|(_, weekday)| weekday)?;
+                }
+                _ => {}
+            }
+        }
+
+        rule.frequency = frequency?;
+        Some(rule)
+    }
+}
+
+/// `PatternType` and `PatternTypeSpecific` of a recurrence pattern.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PatternType {
+    Day,
+    /// Bitmask of the days of the week, starting with Sunday as `0x01`.
+    Week(u32),
+    /// Day of the month, where days past the end of the month mean the last day.
+    Month(i64),
+    /// Bitmask of the days of the week, and the week of the month from 1 to 4, or 5 for the last
+    /// week.
+    MonthNth(u32, i64),
+}
+
+impl PatternType {
+    fn value(self) -> u16 {
+        match self {
+            Self::Day => 0x0000,
+            Self::Week(_) => 0x0001,
+            Self::Month(_) => 0x0002,
+            Self::MonthNth(_, _) => 0x0003,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RecurrenceEnd {
+    /// `EndType` is `0x00002021`, with the last instance on or before the day.
+    Date(i64),
+    /// `EndType` is `0x00002022`.
+    Count(u32),
+    /// `EndType` is `0x00002023`.
+    Never,
+}
+
+/// A [`RecurrenceRule`] in the terms of the `RecurrencePattern` structure, with days counted
+/// from the Unix epoch in the local time of the appointment.
+#[derive(Clone, Debug)]
+struct RecurrencePattern {
+    frequency: Frequency,
+    pattern_type: PatternType,
+    /// Days for [`PatternType::Day`], weeks for [`PatternType::Week`], and months otherwise.
+    period: i64,
+    first_weekday: i64,
+    start_day: i64,
+    end: RecurrenceEnd,
+    deleted: Vec<i64>,
+}
+
+impl RecurrencePattern {
+    fn new(rule: &RecurrenceRule, start: &CalendarTime, mut deleted: Vec<i64>) -> Self {
+        let start_day = start.local.div_euclid(86_400);
+        let (_, _, start_month_day) = civil_from_days(start_day);
+        let mask = |by_day: &[(i64, i64)]| {
+            if by_day.is_empty() {
+                1 << weekday(start_day)
+            } else {
+                by_day.iter().fold(0, |mask, (_, day)| mask | (1 << day))
+            }
+        };
+        let ordinal = rule.by_set_position.or_else(|| {
+            rule.by_day
+                .iter()
+                .map(|(ordinal, _)| *ordinal)
+                .find(|n| *n != 0)
+        });
+
+        let (pattern_type, period) = match rule.frequency {
+            Frequency::Daily if rule.by_day.is_empty() => (PatternType::Day, rule.interval),
+            Frequency::Daily => (PatternType::Week(mask(&rule.by_day)), 1),
+            Frequency::Weekly => (PatternType::Week(mask(&rule.by_day)), rule.interval),
+            Frequency::Monthly | Frequency::Yearly => {
+                let months = if rule.frequency == Frequency::Yearly {
+                    12 * rule.interval
+                } else {
+                    rule.interval
+                };
+                match ordinal {
+                    Some(ordinal) if !rule.by_day.is_empty() => {
+                        let week = if (1..=4).contains(&ordinal) {
+                            ordinal
+                        } else {
+                            5
+                        };
+                        (PatternType::MonthNth(mask(&rule.by_day), week), months)
+                    }
+                    _ => {
+                        let day = rule.by_month_day.filter(|day| *day > 0);
+                        (PatternType::Month(day.unwrap_or(start_month_day)), months)
+                    }
+                }
+            }
+        };
+
+        let mut pattern = Self {
+            frequency: rule.frequency,
+            pattern_type,
+            period,
+            first_weekday: rule.week_start,
+            start_day,
+            end: RecurrenceEnd::Never,
+            deleted: vec![],
+        };
+        pattern.end = match (rule.count, rule.until) {
+            (Some(count), _) => RecurrenceEnd::Count(count),
+            (None, Some(until)) => RecurrenceEnd::Date(until.div_euclid(86_400)),
+            (None, None) => RecurrenceEnd::Never,
+        };
+
+        deleted.sort_unstable();
+        deleted.dedup();
+        pattern.deleted = deleted
+            .into_iter()
+            .filter(|day| pattern.is_instance(*day))
+            .collect();
+        pattern
+    }
+
+    fn week_start(&self, day: i64) -> i64 {
+        day - (weekday(day) - self.first_weekday).rem_euclid(7)
+    }
+
+    fn months(day: i64) -> i64 {
+        let (year, month, _) = civil_from_days(day);
+        year * 12 + month - 1
+    }
+
+    /// Whether the pattern has an instance on `day`, ignoring the end of the recurrence.
+    fn is_instance(&self, day: i64) -> bool {
+        if day < self.start_day {
+            return false;
+        }
+        let (year, month, month_day) = civil_from_days(day);
+        let in_period = |period: i64| {
+            (Self::months(day) - Self::months(self.start_day)).rem_euclid(period) == 0
+        };
+        match self.pattern_type {
+            PatternType::Day => (day - self.start_day) % self.period == 0,
+            PatternType::Week(mask) => {
+                mask & (1 << weekday(day)) != 0
+                    && ((self.week_start(day) - self.week_start(self.start_day)) / 7) % self.period
+                        == 0
+            }
+            PatternType::Month(day_of_month) => {
+                in_period(self.period) && month_day == day_of_month.min(days_in_month(year, month))
+            }
+            PatternType::MonthNth(mask, week) => {
+                in_period(self.period)
+                    && mask & (1 << weekday(day)) != 0
+                    && if week == 5 {
+                        month_day + 7 > days_in_month(year, month)
+                    } else {
+                        (month_day - 1) / 7 + 1 == week
+                    }
+            }
+        }
+    }
+
+    /// Find the number of instances and the day of the last one, or [`None`] if the recurrence
+    /// never ends.
+    fn instances(&self) -> Option<(u32, i64)> {
+        let (count_limit, day_limit) = match self.end {
+            RecurrenceEnd::Never => return None,
+            RecurrenceEnd::Count(count) => (count.max(1), self.start_day + MAX_RECURRENCE_DAYS),
+            RecurrenceEnd::Date(day) => (u32::MAX, day.min(self.start_day + MAX_RECURRENCE_DAYS)),
+        };
+
+        let mut count = 0;
+        let mut last = self.start_day;
+        for day in self.start_day..=day_limit {
+            if self.is_instance(day) {
+                count += 1;
+                last = day;
+                if count >= count_limit {
+                    break;
+                }
+            }
+        }
+        Some((count, last))
+    }
+
+    fn end_day(&self) -> Option<i64> {
+        self.instances().map(|(_, day)| day)
+    }
+
+    /// Minutes since 1601 at midnight of a day.
+    fn minutes(day: i64) -> u32 {
+        (day * 1_440 + MINUTES_BEFORE_UNIX_EPOCH) as u32
+    }
+
+    /// `FirstDateTime`, which is the start of the first period in the 1601 calendar which is
+    /// aligned with the periods of the recurrence.
+    fn first_date_time(&self) -> u32 {
+        let epoch = days_from_civil(1601, 1, 1);
+        match self.pattern_type {
+            PatternType::Day => Self::minutes(self.start_day) % (self.period as u32 * 1_440),
+            PatternType::Week(_) => {
+                Self::minutes(self.week_start(self.start_day)) % (self.period as u32 * 10_080)
+            }
+            PatternType::Month(_) | PatternType::MonthNth(_, _) => {
+                let months = (Self::months(self.start_day) - Self::months(epoch)) % self.period;
+                Self::minutes(days_from_civil(1601 + months / 12, months % 12 + 1, 1))
+            }
+        }
+    }
+
+    /// Serialize the `RecurrencePattern` structure.
+    fn pattern(&self) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&0x3004_u16.to_le_bytes());
+        data.extend_from_slice(&0x3004_u16.to_le_bytes());
+        data.extend_from_slice(&self.frequency.recur_frequency().to_le_bytes());
+        data.extend_from_slice(&self.pattern_type.value().to_le_bytes());
+        // CalendarType
+        data.extend_from_slice(&0_u16.to_le_bytes());
+        data.extend_from_slice(&self.first_date_time().to_le_bytes());
+        let period = match self.pattern_type {
+            PatternType::Day => self.period * 1_440,
+            _ => self.period,
+        };
+        data.extend_from_slice(&(period as u32).to_le_bytes());
+        // SlidingFlag
+        data.extend_from_slice(&0_u32.to_le_bytes());
+        match self.pattern_type {
+            PatternType::Day => {}
+            PatternType::Week(mask) => data.extend_from_slice(&mask.to_le_bytes()),
+            PatternType::Month(day) => data.extend_from_slice(&(day as u32).to_le_bytes()),
+            PatternType::MonthNth(mask, week) => {
+                data.extend_from_slice(&mask.to_le_bytes());
+                data.extend_from_slice(&(week as u32).to_le_bytes());
+            }
+        }
+
+        let (end_type, count, end_date) = match (self.end, self.instances()) {
+            (RecurrenceEnd::Date(_), Some((count, last))) => (0x2021, count, Self::minutes(last)),
+            (RecurrenceEnd::Count(_), Some((count, last))) => (0x2022, count, Self::minutes(last)),
+            _ => (0x2023, RECURRENCE_NEVER_ENDS_COUNT, RECURRENCE_NEVER_ENDS),
+        };
+        data.extend_from_slice(&(end_type as u32).to_le_bytes());
+        data.extend_from_slice(&count.to_le_bytes());
+        data.extend_from_slice(&(self.first_weekday as u32).to_le_bytes());
+
+        data.extend_from_slice(&(self.deleted.len() as u32).to_le_bytes());
+        for day in &self.deleted {
+            data.extend_from_slice(&Self::minutes(*day).to_le_bytes());
+        }
+        // ModifiedInstanceCount
+        data.extend_from_slice(&0_u32.to_le_bytes());
+
+        data.extend_from_slice(&Self::minutes(self.start_day).to_le_bytes());
+        data.extend_from_slice(&end_date.to_le_bytes());
+        data
+    }
+
+    /// Serialize the `AppointmentRecurrencePattern` structure for `PidLidAppointmentRecur`, with
+    /// the local start time and the duration in minutes of each instance.
+    fn appointment_pattern(&self, start: i64, duration: i64) -> Vec<u8> {
+        let mut data = self.pattern();
+        data.extend_from_slice(&0x3006_u32.to_le_bytes());
+        data.extend_from_slice(&0x3009_u32.to_le_bytes());
+        let start_offset = start.rem_euclid(86_400) / 60;
+        data.extend_from_slice(&(start_offset as u32).to_le_bytes());
+        data.extend_from_slice(&((start_offset + duration) as u32).to_le_bytes());
+        // ExceptionCount
+        data.extend_from_slice(&0_u16.to_le_bytes());
+        // ReservedBlock1Size
+        data.extend_from_slice(&0_u32.to_le_bytes());
+        // ReservedBlock2Size
+        data.extend_from_slice(&0_u32.to_le_bytes());
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::TimeZoneRule;
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//Example//EN\r
+BEGIN:VTIMEZONE\r
+TZID:America/New_York\r
+BEGIN:DAYLIGHT\r
+TZOFFSETFROM:-0500\r
+TZOFFSETTO:-0400\r
+DTSTART:20070311T020000\r
+RRULE:FREQ=YEARLY;BYMONTH=3;BYDAY=2SU\r
+END:DAYLIGHT\r
+BEGIN:STANDARD\r
+TZOFFSETFROM:-0400\r
+TZOFFSETTO:-0500\r
+DTSTART:20071104T020000\r
+RRULE:FREQ=YEARLY;BYMONTH=11;BYDAY=1SU\r
+END:STANDARD\r
+END:VTIMEZONE\r
+BEGIN:VEVENT\r
+UID:1234@example.com\r
+SUMMARY:Team sync\\, weekly\r
+LOCATION:Room 1\r
+DESCRIPTION:First line\\nsecond\r
+  line\r
+DTSTART;TZID=America/New_York:20240102T093000\r
+DTEND;TZID=America/New_York:20240102T100000\r
+RRULE:FREQ=WEEKLY;BYDAY=TU,TH;COUNT=10\r
+EXDATE;TZID=America/New_York:20240104T093000\r
+ORGANIZER;CN=\"Smith, Alice\":mailto:alice@example.com\r
+ATTENDEE;CN=Bob;ROLE=REQ-PARTICIPANT:mailto:bob@example.com\r
+ATTENDEE;ROLE=OPT-PARTICIPANT:MAILTO:carol@example.com\r
+CATEGORIES:Work,Sync\r
+BEGIN:VALARM\r
+TRIGGER:-PT10M\r
+ACTION:DISPLAY\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:1234@example.com\r
+RECURRENCE-ID;TZID=America/New_York:20240109T093000\r
+DTSTART;TZID=America/New_York:20240109T110000\r
+SUMMARY:Moved\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+DTSTART;VALUE=DATE:20240704\r
+SUMMARY:Holiday\r
+TRANSP:TRANSPARENT\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn time_zone() -> TimeZone {
+        let components = Component::parse(CALENDAR).unwrap();
+        let calendar = components.first().unwrap();
+        let time_zone = calendar.components_named("VTIMEZONE").next().unwrap();
+        TimeZone::parse(time_zone).unwrap()
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_time_zone() {
+        let time_zone = time_zone();
+        assert_eq!(time_zone.id, "America/New_York");
+
+        // 2024-01-02T09:30:00 EST is 14:30:00 UTC.
+        let (local, _) = parse_date_time("20240102T093000").unwrap();
+        assert_eq!(
+            time_zone.to_utc(local),
+            parse_date_time("20240102T143000Z").unwrap().0
+        );
+        // 2024-07-04T09:30:00 EDT is 13:30:00 UTC.
+        let (local, _) = parse_date_time("20240704T093000").unwrap();
+        assert_eq!(
+            time_zone.to_utc(local),
+            parse_date_time("20240704T133000Z").unwrap().0
+        );
+        // Daylight saving time starts on 2024-03-10 and ends on 2024-11-03.
+        let (before, _) = parse_date_time("20240310T010000").unwrap();
+        let (after, _) = parse_date_time("20240310T030000").unwrap();
+        assert_eq!(time_zone.offset(before), -300);
+        assert_eq!(time_zone.offset(after), -240);
+        let (local, _) = parse_date_time("20241103T030000").unwrap();
+        assert_eq!(time_zone.offset(local), -300);
+
+        let definition = time_zone.definition(TZRULE_FLAG_EFFECTIVE_TZREG);
+        let header_size = 4 + usize::from(u16::from_le_bytes([definition[2], definition[3]]));
+        assert_eq!(definition.len(), header_size + 66);
+        let rule = &definition[header_size..];
+        // lBias and lDaylightBias
+        assert_eq!(read_u32(rule, 22) as i32, 300);
+        assert_eq!(read_u32(rule, 30) as i32, -60);
+        // stStandardDate is the 1st Sunday in November, and stDaylightDate the 2nd in March.
+        assert_eq!(&rule[34..42], &[0, 0, 11, 0, 0, 0, 1, 0]);
+        assert_eq!(&rule[50..58], &[0, 0, 3, 0, 0, 0, 2, 0]);
+
+        assert_eq!(time_zone.tz_struct().len(), 48);
+        let rule = TimeZoneRule::read(&time_zone.tz_struct()).unwrap();
+        for local in ["20240102T093000", "20240704T093000", "20241103T030000"] {
+            let (local, _) = parse_date_time(local).unwrap();
+            assert_eq!(
+                rule.to_utc(from_unix_seconds(local)),
+                from_unix_seconds(time_zone.to_utc(local))
+            );
+            assert_eq!(
+                rule.to_local(from_unix_seconds(time_zone.to_utc(local))),
+                from_unix_seconds(local)
+            );
+        }
+    }
+
+    #[test]
+    fn test_weekly_recurrence() {
+        let time_zone = time_zone();
+        let rule =
+            RecurrenceRule::parse("FREQ=WEEKLY;BYDAY=TU,TH;COUNT=10", Some(&time_zone)).unwrap();
+        let line = ContentLine::parse("DTSTART;TZID=America/New_York:20240102T093000").unwrap();
+        let start = CalendarTime::parse(&line, Some(&time_zone)).unwrap();
+        let deleted = vec![days_from_civil(2024, 1, 4), days_from_civil(2024, 1, 5)];
+        let pattern = RecurrencePattern::new(&rule, &start, deleted);
+
+        // The 10th instance is on Thursday, 2024-02-01, and Friday is not an instance.
+        assert_eq!(pattern.end_day(), Some(days_from_civil(2024, 2, 1)));
+        assert_eq!(pattern.deleted, vec![days_from_civil(2024, 1, 4)]);
+
+        let data = pattern.appointment_pattern(start.local, 30);
+        // ReaderVersion, WriterVersion, RecurFrequency, PatternType and CalendarType
+        assert_eq!(
+            &data[..10],
+            &[0x04, 0x30, 0x04, 0x30, 0x0B, 0x20, 0x01, 0x00, 0x00, 0x00]
+        );
+        // The week of 2024-01-01 starts on Monday, and the period is 1 week.
+        let week_start = RecurrencePattern::minutes(days_from_civil(2024, 1, 1));
+        assert_eq!(read_u32(&data, 10), week_start % 10_080);
+        assert_eq!(read_u32(&data, 14), 1);
+        // PatternTypeSpecific has Tuesday and Thursday
+        assert_eq!(read_u32(&data, 22), 0x04 | 0x10);
+        // EndType, OccurrenceCount and FirstDOW
+        assert_eq!(read_u32(&data, 26), 0x2022);
+        assert_eq!(read_u32(&data, 30), 10);
+        assert_eq!(read_u32(&data, 34), 1);
+        // DeletedInstanceCount, DeletedInstanceDates and ModifiedInstanceCount
+        assert_eq!(read_u32(&data, 38), 1);
+        assert_eq!(
+            read_u32(&data, 42),
+            RecurrencePattern::minutes(days_from_civil(2024, 1, 4))
+        );
+        assert_eq!(read_u32(&data, 46), 0);
+        // StartDate and EndDate
+        assert_eq!(
+            read_u32(&data, 50),
+            RecurrencePattern::minutes(days_from_civil(2024, 1, 2))
+        );
+        assert_eq!(
+            read_u32(&data, 54),
+            RecurrencePattern::minutes(days_from_civil(2024, 2, 1))
+        );
+        // ReaderVersion2, WriterVersion2, StartTimeOffset and EndTimeOffset
+        assert_eq!(read_u32(&data, 58), 0x3006);
+        assert_eq!(read_u32(&data, 62), 0x3009);
+        assert_eq!(read_u32(&data, 66), 570);
+        assert_eq!(read_u32(&data, 70), 600);
+        assert_eq!(data.len(), 84);
+    }
+
+    #[test]
+    fn test_monthly_recurrence() {
+        let rule =
+            RecurrenceRule::parse("FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20240701T000000Z", None).unwrap();
+        let line = ContentLine::parse("DTSTART:20240126T120000Z").unwrap();
+        let start = CalendarTime::parse(&line, None).unwrap();
+        let pattern = RecurrencePattern::new(&rule, &start, vec![]);
+        assert_eq!(pattern.pattern_type, PatternType::MonthNth(0x20, 5));
+        assert_eq!(
+            pattern.end,
+            RecurrenceEnd::Date(days_from_civil(2024, 7, 1))
+        );
+        // The last Friday in June 2024 is the 28th, and there are 6 instances.
+        assert_eq!(pattern.instances(), Some((6, days_from_civil(2024, 6, 28))));
+
+        // FirstDateTime is the start of the month in 1601, since the period is 1 month.
+        assert_eq!(pattern.first_date_time(), 0);
+    }
+
+    #[test]
+    fn test_non_ascii_weekday() {
+        assert_eq!(parse_weekday("ÉX"), None);
+        assert_eq!(parse_weekday("1É"), None);
+        assert!(RecurrenceRule::parse("FREQ=WEEKLY;BYDAY=ÉX", None).is_none());
+        assert!(RecurrenceRule::parse("FREQ=WEEKLY;BYDAY=MO;WKST=É", None).is_none());
+
+        let calendar = CALENDAR.replace("BYDAY=TU,TH", "BYDAY=ÉX");
+        assert_ne!(calendar, CALENDAR);
+        assert!(parse(calendar.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_parse_calendar() {
+        let appointments = parse(CALENDAR.as_bytes()).unwrap();
+        assert_eq!(appointments.len(), 2);
+
+        let meeting = &appointments[0];
+        let Some(PropertyValue::Unicode(subject)) = meeting.properties().get(&0x0037) else {
+            panic!("missing PidTagSubject");
+        };
+        assert_eq!(subject.to_string(), "Team sync, weekly");
+        let Some(PropertyValue::Unicode(body)) = meeting.properties().get(&0x1000) else {
+            panic!("missing PidTagBody");
+        };
+        assert_eq!(body.to_string(), "First line\r\nsecond line");
+        let Some(PropertyValue::Unicode(sender)) = meeting.properties().get(&0x0C1A) else {
+            panic!("missing PidTagSenderName");
+        };
+        assert_eq!(sender.to_string(), "Smith, Alice");
+        assert_eq!(meeting.recipients().len(), 2);
+
+        let named = |guid, id: u32| meeting.named_properties().get(&lid(guid, id));
+        let start = from_unix_seconds(parse_date_time("20240102T143000Z").unwrap().0);
+        assert!(matches!(
+            named(PSETID_APPOINTMENT, 0x820D),
+            Some(PropertyValue::Time(time)) if *time == start
+        ));
+        assert!(matches!(
+            named(PSETID_APPOINTMENT, 0x8213),
+            Some(PropertyValue::Integer32(30))
+        ));
+        assert!(matches!(
+            named(PSETID_APPOINTMENT, 0x8223),
+            Some(PropertyValue::Boolean(true))
+        ));
+        assert!(matches!(
+            named(PSETID_APPOINTMENT, 0x8216),
+            Some(PropertyValue::Binary(_))
+        ));
+        assert!(matches!(
+            named(PSETID_APPOINTMENT, 0x8260),
+            Some(PropertyValue::Binary(_))
+        ));
+        assert!(matches!(
+            named(PSETID_COMMON, 0x8501),
+            Some(PropertyValue::Integer32(10))
+        ));
+        let Some(PropertyValue::Binary(global_object_id)) = named(PSETID_MEETING, 0x0003) else {
+            panic!("missing PidLidGlobalObjectId");
+        };
+        assert!(global_object_id
+            .buffer()
+            .starts_with(&GLOBAL_OBJECT_ID_TYPE));
+        assert!(global_object_id.buffer().ends_with(b"1234@example.com\0"));
+        assert!(matches!(
+            meeting
+                .named_properties()
+                .get(&string(PS_PUBLIC_STRINGS, "Keywords")),
+            Some(PropertyValue::MultipleUnicode(categories)) if categories.len() == 2
+        ));
+
+        let holiday = &appointments[1];
+        let named = |guid, id: u32| holiday.named_properties().get(&lid(guid, id));
+        assert!(matches!(
+            named(PSETID_APPOINTMENT, 0x8215),
+            Some(PropertyValue::Boolean(true))
+        ));
+        assert!(matches!(
+            named(PSETID_APPOINTMENT, 0x8213),
+            Some(PropertyValue::Integer32(1_440))
+        ));
+        assert!(matches!(
+            named(PSETID_APPOINTMENT, 0x8205),
+            Some(PropertyValue::Integer32(BUSY_STATUS_FREE))
+        ));
+    }
+}
@@ -0,0 +1,216 @@
+//! Import every message in an [mbox](https://www.rfc-editor.org/rfc/rfc4155) file.
+//!
+//! Messages are separated by lines starting with `From `, and lines in the body which start with
+//! one or more `>` followed by `From ` have one `>` removed, as in the `mboxrd` variant. Each
+//! message is parsed with [`eml::parse`].
+
+use std::io::{self, BufRead, BufReader, Read};
+
+use super::eml;
+use crate::{
+    messaging::{
+        object_id::{FolderId, MessageId},
+        MessagingError,
+    },
+    ndb::node_id::NodeIdType,
+    PstFile,
+};
+
+/// Number of messages written in each transaction by [`import`].
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+
+/// Reported by [`import`] after each message and each batch.
+#[derive(Debug)]
+pub enum MboxImportProgress {
+    /// The message at `index` in the mbox file was created as `message`.
+    Imported { index: usize, message: MessageId },
+    /// The message at `index` could not be parsed, and was skipped.
+    Failed { index: usize, error: io::Error },
+    /// A batch was flushed to the PST file, and `imported` messages have been committed so far.
+    Committed { imported: usize },
+}
+
+/// Totals for a completed [`import`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MboxImportSummary {
+    imported: usize,
+    failed: usize,
+}
+
+impl MboxImportSummary {
+    pub fn imported(&self) -> usize {
+        self.imported
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+}
+
+/// Import each message in an mbox stream to `folder`, in transactions of
+/// [`DEFAULT_BATCH_SIZE`] messages. See [`import_in_batches`].
+pub fn import<Pst>(
+    pst: &mut Pst,
+    folder: FolderId,
+    reader: impl Read,
+    progress: impl FnMut(MboxImportProgress),
+) -> io::Result<MboxImportSummary>
+where
+    Pst: PstFile,
+{
+    import_in_batches(pst, folder, reader, DEFAULT_BATCH_SIZE, progress)
+}
+
+/// Import each message in an mbox stream to `folder`. The file is locked and flushed once for
+/// every `batch_size` messages, so an interrupted import keeps every batch before the one which
+/// was in progress.
+///
+/// Messages which cannot be parsed or serialized are reported to `progress` and skipped before
+/// anything is written for them. Errors reading the mbox stream or writing to the PST file stop
+/// the import, since the current batch may be incomplete.
+pub fn import_in_batches<Pst>(
+    pst: &mut Pst,
+    folder: FolderId,
+    reader: impl Read,
+    batch_size: usize,
+    mut progress: impl FnMut(MboxImportProgress),
+) -> io::Result<MboxImportSummary>
+where
+    Pst: PstFile,
+{
+    if folder.is_search_folder() {
+        return Err(MessagingError::InvalidFolderEntryIdType(NodeIdType::SearchFolder).into());
+    }
+
+    let mut messages = MboxReader::new(BufReader::new(reader));
+    let mut summary = MboxImportSummary::default();
+    let mut index = 0;
+    loop {
+        let mut batch = Vec::with_capacity(batch_size);
+        while batch.len() < batch_size.max(1) {
+            match messages.next_message()? {
+                Some(message) => batch.push(message),
+                None => break,
+            }
+        }
+        if batch.is_empty() {
+            break;
+        }
+
+        let mut guard = pst.lock()?;
+        for message in batch {
+            let built = eml::parse(message.as_slice()).and_then(|builder| {
+                let data = builder.build(guard.ndb_version())?;
+                Ok((builder, data))
+            });
+            match built {
+                Ok((builder, data)) => {
+                    let message = guard.create_built_message(folder, &builder, &data)?;
+                    summary.imported += 1;
+                    progress(MboxImportProgress::Imported { index, message });
+                }
+                Err(error) => {
+                    summary.failed += 1;
+                    progress(MboxImportProgress::Failed { index, error });
+                }
+            }
+            index += 1;
+        }
+        guard.flush()?;
+        progress(MboxImportProgress::Committed {
+            imported: summary.imported,
+        });
+    }
+
+    Ok(summary)
+}
+
+/// Split an mbox stream into messages without reading all of it into memory.
+struct MboxReader<R: BufRead> {
+    reader: R,
+    separator_found: bool,
+}
+
+impl<R: BufRead> MboxReader<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            separator_found: false,
+        }
+    }
+
+    /// Read up to the next `From ` separator line, and unescape `>From ` lines.
+    fn next_message(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut message = vec![];
+        let mut line = vec![];
+        loop {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+
+            if line.starts_with(b"From ") {
+                if self.separator_found {
+                    return Ok(Some(trim_separator_line_break(message)));
+                }
+                self.separator_found = true;
+                continue;
+            }
+
+            let quotes = line.iter().take_while(|&&b| b == b'>').count();
+            if quotes > 0 && line[quotes..].starts_with(b"From ") {
+                message.extend_from_slice(&line[1..]);
+            } else {
+                message.extend_from_slice(&line);
+            }
+        }
+
+        self.separator_found = false;
+        Ok(if message.iter().all(u8::is_ascii_whitespace) {
+            None
+        } else {
+            Some(trim_separator_line_break(message))
+        })
+    }
+}
+
+/// Remove the empty line which separates a message from the next `From ` line.
+fn trim_separator_line_break(mut message: Vec<u8>) -> Vec<u8> {
+    for suffix in [b"\r\n".as_slice(), b"\n"] {
+        if message.ends_with(suffix) && message[..message.len() - suffix.len()].ends_with(b"\n") {
+            message.truncate(message.len() - suffix.len());
+            break;
+        }
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_mbox() {
+        let mbox = b"From alice@example.com Tue Jul  1 10:52:37 2003\n\
+Subject: one\n\
+\n\
+>From the start\n\
+>>From quoted\n\
+\n\
+From bob@example.com Tue Jul  1 10:53:37 2003\n\
+Subject: two\n\
+\n\
+body\n";
+
+        let mut reader = MboxReader::new(mbox.as_slice());
+        assert_eq!(
+            reader.next_message().unwrap().unwrap(),
+            b"Subject: one\n\nFrom the start\n>From quoted\n"
+        );
+        assert_eq!(
+            reader.next_message().unwrap().unwrap(),
+            b"Subject: two\n\nbody\n"
+        );
+        assert!(reader.next_message().unwrap().is_none());
+    }
+}
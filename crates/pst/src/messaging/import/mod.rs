@@ -0,0 +1,10 @@
+//! Create messages in a PST file from other formats. Each format is parsed into a
+//! [`MessageBuilder`](super::message::MessageBuilder), which is written to a folder with
+//! [`PstFileLockGuard::create_message`](crate::PstFileLockGuard::create_message).
+
+pub mod bulk;
+mod content_line;
+pub mod eml;
+pub mod ics;
+pub mod mbox;
+pub mod vcf;
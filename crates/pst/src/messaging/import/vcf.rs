@@ -0,0 +1,771 @@
+//! Import [vCard](https://www.rfc-editor.org/rfc/rfc6350) objects, i.e. `.vcf` files, as
+//! contacts.
+//!
+//! [`parse`] maps each `VCARD` to an `IPM.Contact` [`MessageBuilder`] with the properties from
+//! [MS-OXOCNTC](https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxocntc/9b636532-9150-4836-9635-5e9ed2e6bd4f),
+//! including the electronic address properties for up to 3 `EMAIL` values. An embedded `PHOTO`
+//! becomes the hidden contact picture attachment. vCard versions 2.1, 3.0 and 4.0 are
+//! supported. [`import`] creates the contacts in a folder.
+
+use std::io::{self, Read};
+
+use super::{
+    content_line::{split_text_list, unescape_text, Component, ContentLine},
+    eml::{decode_charset, decode_quoted_printable},
+};
+use crate::{
+    binary_text::decode_base64,
+    ltp::{
+        code_page::BuiltinCodePageDecoder,
+        prop_context::{BinaryValue, PropertyValue, UnicodeValue},
+    },
+    messaging::{
+        attachment::AttachmentBuilder,
+        message::*,
+        named_prop::*,
+        object_id::{FolderId, MessageId},
+        psetid::*,
+        MessagingError,
+    },
+    time::{self, days_from_civil, from_unix_seconds, FILETIME_TICKS_PER_SECOND},
+    PstFile, PstFileLockGuard,
+};
+
+/// `PidLidEmail1DisplayName`, `PidLidEmail2DisplayName` and `PidLidEmail3DisplayName`. The
+/// other properties of each electronic address follow at fixed offsets.
+const EMAIL_DISPLAY_NAMES: [u32; 3] = [0x8080, 0x8090, 0x80A0];
+
+/// Filename which Outlook gives the contact picture attachment.
+const CONTACT_PICTURE_NAME: &str = "ContactPicture";
+
+/// Candidate properties for each kind of `TEL`, in order of preference.
+const PHONE_NUMBERS: &[(&[&str], &[u16])] = &[
+    // PidTagHomeFaxNumber
+    (&["FAX", "HOME"], &[0x3A25]),
+    // PidTagBusinessFaxNumber
+    (&["FAX", "WORK"], &[0x3A24]),
+    // PidTagPrimaryFaxNumber
+    (&["FAX"], &[0x3A23]),
+    // PidTagMobileTelephoneNumber
+    (&["CELL"], &[0x3A1C]),
+    // PidTagPagerTelephoneNumber
+    (&["PAGER"], &[0x3A21]),
+    // PidTagCarTelephoneNumber
+    (&["CAR"], &[0x3A1E]),
+    // PidTagHomeTelephoneNumber and PidTagHome2TelephoneNumber
+    (&["HOME"], &[0x3A09, 0x3A2F]),
+    // PidTagBusinessTelephoneNumber and PidTagBusiness2TelephoneNumber
+    (&["WORK"], &[0x3A08, 0x3A1B]),
+    // PidTagPrimaryTelephoneNumber
+    (&["PREF"], &[0x3A1A]),
+];
+
+/// `PidTagOtherTelephoneNumber`, for any `TEL` which does not fit one of [`PHONE_NUMBERS`].
+const OTHER_PHONE_NUMBER: u16 = 0x3A1F;
+
+/// Properties of a postal address, in the order of the components of `ADR` after the post office
+/// box and extended address: street, locality, region, postal code and country.
+struct AddressProperties {
+    /// `PidLidPostalAddressId` value which selects this address as the mailing address.
+    postal_address_id: i32,
+    /// `PidLidHomeAddress`, `PidLidWorkAddress` or `PidLidOtherAddress`.
+    full_address: u32,
+    post_office_box: u16,
+    components: [u16; 5],
+    /// `PidLidWorkAddress*` properties, which Outlook sets along with the tagged properties.
+    named_components: Option<(u32, [u32; 5])>,
+}
+
+const HOME_ADDRESS: AddressProperties = AddressProperties {
+    postal_address_id: 1,
+    full_address: 0x801A,
+    post_office_box: 0x3A5E,
+    components: [0x3A5D, 0x3A59, 0x3A5C, 0x3A5B, 0x3A5A],
+    named_components: None,
+};
+
+const WORK_ADDRESS: AddressProperties = AddressProperties {
+    postal_address_id: 2,
+    full_address: 0x801B,
+    post_office_box: 0x3A2B,
+    components: [0x3A29, 0x3A27, 0x3A28, 0x3A2A, 0x3A26],
+    named_components: Some((0x804A, [0x8045, 0x8046, 0x8047, 0x8048, 0x8049])),
+};
+
+const OTHER_ADDRESS: AddressProperties = AddressProperties {
+    postal_address_id: 3,
+    full_address: 0x801C,
+    post_office_box: 0x3A64,
+    components: [0x3A63, 0x3A5F, 0x3A62, 0x3A61, 0x3A60],
+    named_components: None,
+};
+
+/// Parse every `VCARD` in a stream into a new `IPM.Contact` [`MessageBuilder`], in the order
+/// they appear.
+pub fn parse(mut reader: impl Read) -> io::Result<Vec<MessageBuilder>> {
+    let mut data = vec![];
+    reader.read_to_end(&mut data)?;
+    let data = String::from_utf8_lossy(&data);
+
+    let cards: Vec<_> = Component::parse(&data)
+        .map_err(MessagingError::InvalidContactObject)?
+        .into_iter()
+        .filter(|component| component.name == "VCARD")
+        .collect();
+    if cards.is_empty() {
+        return Err(MessagingError::InvalidContactObject(String::from("missing VCARD")).into());
+    }
+
+    Ok(cards.iter().map(parse_card).collect())
+}
+
+/// Parse a vCard stream from `reader` and create each `VCARD` in `folder`, which must be a
+/// `NID_TYPE_NORMAL_FOLDER`. Returns the [`MessageId`] of each new contact.
+pub fn import<Pst>(
+    guard: &mut PstFileLockGuard<'_, Pst>,
+    folder: FolderId,
+    reader: impl Read,
+) -> io::Result<Vec<MessageId>>
+where
+    Pst: PstFile,
+{
+    parse(reader)?
+        .iter()
+        .map(|builder| guard.create_message(folder, builder))
+        .collect()
+}
+
+fn parse_card(card: &Component) -> MessageBuilder {
+    let mut builder = MessageBuilder::new("IPM.Contact");
+
+    let name = card
+        .property("N")
+        .map(|line| split_text_list(&decoded_value(line), ';'))
+        .unwrap_or_default();
+    let name_part = |index: usize| {
+        name.get(index)
+            .map(|part| part.replace(',', " ").trim().to_string())
+            .filter(|part| !part.is_empty())
+    };
+    let (surname, given_name, middle_name, prefix, generation) = (
+        name_part(0),
+        name_part(1),
+        name_part(2),
+        name_part(3),
+        name_part(4),
+    );
+    // PidTagSurname, PidTagGivenName, PidTagMiddleName, PidTagDisplayNamePrefix and
+    // PidTagGeneration
+    for (prop_id, value) in [
+        (0x3A11, &surname),
+        (0x3A06, &given_name),
+        (0x3A44, &middle_name),
+        (0x3A45, &prefix),
+        (0x3A05, &generation),
+    ] {
+        if let Some(value) = value {
+            builder.set_property(prop_id, unicode_value(value));
+        }
+    }
+    let initials: String = [&given_name, &middle_name, &surname]
+        .into_iter()
+        .flatten()
+        .filter_map(|part| part.chars().next())
+        .map(|initial| format!("{initial}."))
+        .collect();
+    if !initials.is_empty() {
+        // PidTagInitials
+        builder.set_property(0x3A0A, unicode_value(&initials));
+    }
+
+    let organization = card
+        .property("ORG")
+        .map(|line| split_text_list(&decoded_value(line), ';'))
+        .unwrap_or_default();
+    let company = organization.first().filter(|value| !value.is_empty());
+    if let Some(company) = company {
+        // PidTagCompanyName
+        builder.set_property(0x3A16, unicode_value(company));
+    }
+    if let Some(department) = organization.get(1).filter(|value| !value.is_empty()) {
+        // PidTagDepartmentName
+        builder.set_property(0x3A18, unicode_value(department));
+    }
+
+    // PidTagTitle, PidTagProfession and PidTagNickname
+    for (property, prop_id) in [("TITLE", 0x3A17), ("ROLE", 0x3A46), ("NICKNAME", 0x3A4F)] {
+        if let Some(line) = card.property(property) {
+            builder.set_property(prop_id, unicode_value(&text_value(line)));
+        }
+    }
+
+    let emails: Vec<_> = card
+        .properties_named("EMAIL")
+        .map(|line| {
+            (
+                line.has_type("PREF"),
+                decoded_value(line).trim().to_string(),
+            )
+        })
+        .filter(|(_, email)| !email.is_empty())
+        .collect();
+
+    let display_name = card
+        .property("FN")
+        .map(text_value)
+        .filter(|value| !value.is_empty())
+        .or_else(|| {
+            let parts: Vec<_> = [&prefix, &given_name, &middle_name, &surname, &generation]
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect();
+            (!parts.is_empty()).then(|| parts.join(" "))
+        })
+        .or_else(|| company.cloned())
+        .or_else(|| emails.first().map(|(_, email)| email.clone()))
+        .unwrap_or_default();
+    // PidTagDisplayName, PidTagSubject and PidTagConversationTopic
+    builder.set_property(0x3001, unicode_value(&display_name));
+    builder.set_property(0x0037, unicode_value(&display_name));
+    builder.set_property(0x0070, unicode_value(&display_name));
+
+    let file_under = match (&surname, &given_name) {
+        (Some(surname), Some(given_name)) => format!("{surname}, {given_name}"),
+        (Some(name), None) | (None, Some(name)) => name.clone(),
+        (None, None) => display_name.clone(),
+    };
+    // PidLidFileUnder
+    builder.set_named_property(PSETID_ADDRESS, 0x8005, unicode_value(&file_under));
+
+    set_email_addresses(&mut builder, &display_name, emails);
+    set_phone_numbers(&mut builder, card);
+    set_addresses(&mut builder, card);
+
+    for line in card.properties_named("URL") {
+        // PidTagPersonalHomePage or PidTagBusinessHomePage
+        let prop_id = if line.has_type("HOME") {
+            0x3A50
+        } else {
+            0x3A51
+        };
+        if !builder.properties().contains_key(&prop_id) {
+            builder.set_property(prop_id, unicode_value(decoded_value(line).trim()));
+        }
+    }
+
+    if let Some(line) = card
+        .property("IMPP")
+        .or_else(|| card.property("X-MS-IMADDRESS"))
+    {
+        let address = decoded_value(line);
+        let address = address.split_once(':').map_or(address.as_str(), |(_, a)| a);
+        // PidLidInstantMessagingAddress
+        builder.set_named_property(PSETID_ADDRESS, 0x8062, unicode_value(address.trim()));
+    }
+
+    // PidTagBirthday and PidLidBirthdayLocal, PidTagWeddingAnniversary and
+    // PidLidWeddingAnniversaryLocal
+    for (property, prop_id, local_id) in [
+        ("BDAY", 0x3A42, 0x80DE),
+        ("ANNIVERSARY", 0x3A41, 0x80DF),
+        ("X-ANNIVERSARY", 0x3A41, 0x80DF),
+    ] {
+        if let Some(date) = card
+            .property(property)
+            .and_then(|line| parse_date(&line.value))
+        {
+            builder.set_property(prop_id, PropertyValue::Time(date));
+            builder.set_named_property(PSETID_ADDRESS, local_id, PropertyValue::Time(date));
+        }
+    }
+
+    if let Some(line) = card.property("NOTE") {
+        // PidTagBody
+        builder.set_property(0x1000, unicode_value(&text_value(line)));
+    }
+
+    let categories: Vec<_> = card
+        .properties_named("CATEGORIES")
+        .flat_map(|line| split_text_list(&decoded_value(line), ','))
+        .map(|category| category.trim().to_string())
+        .filter(|category| !category.is_empty())
+        .map(|category| UnicodeValue::new(category.encode_utf16().collect()))
+        .collect();
+    if !categories.is_empty() {
+        // PidNameKeywords
+        builder.set_named_property(
+            PS_PUBLIC_STRINGS,
+            "Keywords",
+            PropertyValue::MultipleUnicode(categories),
+        );
+    }
+
+    let has_picture = match card.property("PHOTO").and_then(photo) {
+        Some(attachment) => {
+            builder.add_attachment(attachment);
+            true
+        }
+        None => false,
+    };
+    // PidLidHasPicture and PidTagHasAttachments
+    builder.set_named_property(PSETID_ADDRESS, 0x8015, PropertyValue::Boolean(has_picture));
+    builder.set_property(0x0E1B, PropertyValue::Boolean(has_picture));
+
+    let time = time::now();
+    let modified = card
+        .property("REV")
+        .and_then(|line| parse_timestamp(&line.value))
+        .unwrap_or(time);
+    // PidTagCreationTime and PidTagLastModificationTime
+    builder.set_property(0x3007, PropertyValue::Time(time));
+    builder.set_property(0x3008, PropertyValue::Time(modified));
+    // PidTagMessageFlags
+    let flags = match builder.properties().get(&0x0E07) {
+        Some(PropertyValue::Integer32(flags)) => *flags,
+        _ => 0,
+    };
+    builder.set_property(0x0E07, PropertyValue::Integer32(flags | MSGFLAG_READ));
+
+    builder
+}
+
+/// Set the `PidLidEmail1*` to `PidLidEmail3*` properties, starting with the preferred addresses,
+/// and the address book provider properties which list them.
+fn set_email_addresses(
+    builder: &mut MessageBuilder,
+    display_name: &str,
+    mut emails: Vec<(bool, String)>,
+) {
+    emails.sort_by_key(|(preferred, _)| !preferred);
+
+    let mut provider_list = vec![];
+    let mut provider_types = 0;
+    for (index, ((_, email), base)) in emails.iter().zip(EMAIL_DISPLAY_NAMES).enumerate() {
+        let email_display_name = if display_name.is_empty() || display_name == email {
+            email.clone()
+        } else {
+            format!("{display_name} ({email})")
+        };
+        let entry_id = one_off_entry_id(&email_display_name, "SMTP", email);
+
+        // PidLidEmail<n>DisplayName, PidLidEmail<n>AddressType, PidLidEmail<n>EmailAddress,
+        // PidLidEmail<n>OriginalDisplayName and PidLidEmail<n>OriginalEntryId
+        builder.set_named_property(PSETID_ADDRESS, base, unicode_value(&email_display_name));
+        builder.set_named_property(PSETID_ADDRESS, base + 2, unicode_value("SMTP"));
+        builder.set_named_property(PSETID_ADDRESS, base + 3, unicode_value(email));
+        builder.set_named_property(PSETID_ADDRESS, base + 4, unicode_value(email));
+        builder.set_named_property(
+            PSETID_ADDRESS,
+            base + 5,
+            PropertyValue::Binary(BinaryValue::new(entry_id)),
+        );
+
+        provider_list.push(index as i32);
+        provider_types |= 1 << index;
+    }
+
+    if !provider_list.is_empty() {
+        // PidLidAddressBookProviderEmailList and PidLidAddressBookProviderArrayType
+        builder.set_named_property(
+            PSETID_ADDRESS,
+            0x8028,
+            PropertyValue::MultipleInteger32(provider_list),
+        );
+        builder.set_named_property(
+            PSETID_ADDRESS,
+            0x8029,
+            PropertyValue::Integer32(provider_types),
+        );
+    }
+}
+
+/// Map each `TEL` to the first free phone number property for its types.
+fn set_phone_numbers(builder: &mut MessageBuilder, card: &Component) {
+    for line in card.properties_named("TEL") {
+        let number = decoded_value(line);
+        let number = number.strip_prefix("tel:").unwrap_or(&number).trim();
+        if number.is_empty() {
+            continue;
+        }
+
+        let types = line.types();
+        let prop_id = PHONE_NUMBERS
+            .iter()
+            .filter(|(required, _)| {
+                required
+                    .iter()
+                    .all(|required| types.iter().any(|value| value == required))
+            })
+            .flat_map(|(_, prop_ids)| prop_ids.iter().copied())
+            .chain([OTHER_PHONE_NUMBER])
+            .find(|prop_id| !builder.properties().contains_key(prop_id));
+        if let Some(prop_id) = prop_id {
+            builder.set_property(prop_id, unicode_value(number));
+        }
+    }
+}
+
+/// Map the first `ADR` of each type to the home, work or other address properties, and select
+/// the preferred one as the mailing address.
+fn set_addresses(builder: &mut MessageBuilder, card: &Component) {
+    let mut postal_address_id = None;
+    for line in card.properties_named("ADR") {
+        let address = if line.has_type("HOME") {
+            &HOME_ADDRESS
+        } else if line.has_type("WORK") {
+            &WORK_ADDRESS
+        } else {
+            &OTHER_ADDRESS
+        };
+        if builder.properties().contains_key(&address.components[0])
+            || builder.properties().contains_key(&address.post_office_box)
+        {
+            continue;
+        }
+
+        let parts = split_text_list(&decoded_value(line), ';');
+        let part = |index: usize| {
+            parts
+                .get(index)
+                .map(|part| part.trim().to_string())
+                .unwrap_or_default()
+        };
+        let street = [part(2), part(1)]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        let values = [street, part(3), part(4), part(5), part(6)];
+        let post_office_box = part(0);
+
+        if !post_office_box.is_empty() {
+            builder.set_property(address.post_office_box, unicode_value(&post_office_box));
+        }
+        for (prop_id, value) in address.components.iter().zip(&values) {
+            if !value.is_empty() {
+                builder.set_property(*prop_id, unicode_value(value));
+            }
+        }
+        if let Some((post_office_box_id, named_ids)) = address.named_components {
+            if !post_office_box.is_empty() {
+                builder.set_named_property(
+                    PSETID_ADDRESS,
+                    post_office_box_id,
+                    unicode_value(&post_office_box),
+                );
+            }
+            for (prop_id, value) in named_ids.into_iter().zip(&values) {
+                if !value.is_empty() {
+                    builder.set_named_property(PSETID_ADDRESS, prop_id, unicode_value(value));
+                }
+            }
+        }
+
+        let [street, locality, region, postal_code, country] = &values;
+        let city_line = [locality, region, postal_code]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let full_address = [street, &post_office_box, &city_line, country]
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join("\r\n");
+        builder.set_named_property(
+            PSETID_ADDRESS,
+            address.full_address,
+            unicode_value(&full_address),
+        );
+
+        if postal_address_id.is_none() || line.has_type("PREF") {
+            postal_address_id = Some(address.postal_address_id);
+        }
+    }
+
+    if let Some(postal_address_id) = postal_address_id {
+        // PidLidPostalAddressId
+        builder.set_named_property(
+            PSETID_ADDRESS,
+            0x8022,
+            PropertyValue::Integer32(postal_address_id),
+        );
+    }
+}
+
+/// Build the hidden contact picture attachment from an inline `PHOTO`. Photos which are only
+/// referenced by a URL are skipped.
+fn photo(line: &ContentLine) -> Option<AttachmentBuilder> {
+    let (mime_type, data) = match line.value.trim().strip_prefix("data:") {
+        Some(uri) => {
+            let (header, data) = uri.split_once(',')?;
+            let mut header = header.split(';');
+            let mime_type = header.next().unwrap_or_default().to_ascii_lowercase();
+            let data = if header.any(|parameter| parameter.eq_ignore_ascii_case("base64")) {
+                decode_base64(data.as_bytes())
+            } else {
+                data.as_bytes().to_vec()
+            };
+            (mime_type, data)
+        }
+        None => {
+            let encoding = line.parameter("ENCODING").unwrap_or_default();
+            if !encoding.eq_ignore_ascii_case("b") && !encoding.eq_ignore_ascii_case("BASE64") {
+                return None;
+            }
+            let image_type = line
+                .types()
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| String::from("JPEG"))
+                .to_ascii_lowercase();
+            let image_type = image_type.strip_prefix("image/").unwrap_or(&image_type);
+            (
+                format!("image/{image_type}"),
+                decode_base64(line.value.as_bytes()),
+            )
+        }
+    };
+    if data.is_empty() {
+        return None;
+    }
+
+    let extension = match mime_type.as_str() {
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/bmp" => "bmp",
+        _ => "jpg",
+    };
+    let filename = format!("{CONTACT_PICTURE_NAME}.{extension}");
+    let mut attachment = AttachmentBuilder::by_value(&filename, Some(&mime_type), data);
+    // PidTagAttachmentContactPhoto and PidTagAttachmentHidden
+    attachment.set_property(0x7FFF, PropertyValue::Boolean(true));
+    attachment.set_property(0x7FFE, PropertyValue::Boolean(true));
+    Some(attachment)
+}
+
+/// The value of a content line with the vCard 2.1 quoted-printable encoding and `CHARSET`
+/// removed, but with any backslash escapes left in place.
+fn decoded_value(line: &ContentLine) -> String {
+    let encoding = line.parameter("ENCODING").unwrap_or_default();
+    if !encoding.eq_ignore_ascii_case("QUOTED-PRINTABLE") {
+        return line.value.clone();
+    }
+    let data = decode_quoted_printable(line.value.as_bytes());
+    decode_charset(
+        &data,
+        line.parameter("CHARSET").unwrap_or("utf-8"),
+        &BuiltinCodePageDecoder,
+    )
+}
+
+/// A single `TEXT` value.
+fn text_value(line: &ContentLine) -> String {
+    unescape_text(&decoded_value(line))
+}
+
+/// Parse a date like `1996-04-15`, `19960415` or `1996-04-15T00:00:00Z` into a `FILETIME` at
+/// midnight UTC. Dates without a year, like `--0415`, are skipped.
+fn parse_date(value: &str) -> Option<i64> {
+    let date = value.split(['T', 't']).next()?;
+    let digits: String = date.chars().filter(char::is_ascii_digit).collect();
+    if digits.len() != 8 || date.starts_with("--") {
+        return None;
+    }
+    let year: i64 = digits[..4].parse().ok()?;
+    let month: i64 = digits[4..6].parse().ok()?;
+    let day: i64 = digits[6..].parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(from_unix_seconds(
+        days_from_civil(year, month, day) * 86_400,
+    ))
+}
+
+/// Parse a `REV` timestamp like `1995-10-31T22:27:10Z` or `19951031T222710Z` into a `FILETIME`.
+fn parse_timestamp(value: &str) -> Option<i64> {
+    let date = parse_date(value)?;
+    let time: String = value
+        .split_once(['T', 't'])
+        .map_or("", |(_, time)| time)
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == ':')
+        .filter(char::is_ascii_digit)
+        .collect();
+    let seconds = match time.len() {
+        6 => {
+            let hours: i64 = time[..2].parse().ok()?;
+            let minutes: i64 = time[2..4].parse().ok()?;
+            let seconds: i64 = time[4..].parse().ok()?;
+            // A leap second is read as the second before it, like time::unix_seconds_from_civil.
+            hours * 3_600 + minutes * 60 + seconds.min(59)
+        }
+        _ => 0,
+    };
+    Some(date + seconds * FILETIME_TICKS_PER_SECOND)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CARDS: &str = "BEGIN:VCARD\r
+VERSION:3.0\r
+N:Smith;Alice;Marie;Dr.;\r
+FN:Dr. Alice Smith\r
+ORG:Example Corp;Research\r
+TITLE:Chief Scientist\r
+EMAIL;TYPE=INTERNET:alice@example.com\r
+EMAIL;TYPE=INTERNET,PREF:alice.smith@example.com\r
+TEL;TYPE=WORK,VOICE:+1 555 0100\r
+TEL;TYPE=CELL:+1 555 0101\r
+TEL;TYPE=WORK,FAX:+1 555 0102\r
+TEL;TYPE=WORK:+1 555 0103\r
+TEL:+1 555 0104\r
+ADR;TYPE=WORK,PREF:;Suite 5;1 Main St;Springfield;IL;62701;USA\r
+ADR;TYPE=HOME:;;2 Elm St;Shelbyville;IL;62565;USA\r
+URL:https://example.com\r
+BDAY:1980-04-15\r
+NOTE:Met at the conference\\, 2023\r
+CATEGORIES:Work,VIP\r
+PHOTO;ENCODING=b;TYPE=JPEG:/9j/4AAQ\r
+ SkZJRg==\r
+END:VCARD\r
+BEGIN:VCARD\r
+VERSION:2.1\r
+N;ENCODING=QUOTED-PRINTABLE;CHARSET=UTF-8:M=C3=BCller;J=C3=BCrgen\r
+TEL;HOME;VOICE:555-0200\r
+EMAIL;INTERNET:juergen@example.com\r
+END:VCARD\r
+";
+
+    fn unicode(builder: &MessageBuilder, prop_id: u16) -> Option<String> {
+        match builder.properties().get(&prop_id) {
+            Some(PropertyValue::Unicode(value)) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    fn named_unicode(builder: &MessageBuilder, id: u32) -> Option<String> {
+        match builder.named_properties().get(&lid(PSETID_ADDRESS, id)) {
+            Some(PropertyValue::Unicode(value)) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_parse_cards() {
+        let contacts = parse(CARDS.as_bytes()).unwrap();
+        assert_eq!(contacts.len(), 2);
+
+        let alice = &contacts[0];
+        assert_eq!(unicode(alice, 0x3001).as_deref(), Some("Dr. Alice Smith"));
+        assert_eq!(unicode(alice, 0x3A11).as_deref(), Some("Smith"));
+        assert_eq!(unicode(alice, 0x3A06).as_deref(), Some("Alice"));
+        assert_eq!(unicode(alice, 0x3A0A).as_deref(), Some("A.M.S."));
+        assert_eq!(unicode(alice, 0x3A16).as_deref(), Some("Example Corp"));
+        assert_eq!(unicode(alice, 0x3A18).as_deref(), Some("Research"));
+        assert_eq!(
+            named_unicode(alice, 0x8005).as_deref(),
+            Some("Smith, Alice")
+        );
+
+        // The preferred address is Email1.
+        assert_eq!(
+            named_unicode(alice, 0x8083).as_deref(),
+            Some("alice.smith@example.com")
+        );
+        assert_eq!(
+            named_unicode(alice, 0x8080).as_deref(),
+            Some("Dr. Alice Smith (alice.smith@example.com)")
+        );
+        assert_eq!(
+            named_unicode(alice, 0x8093).as_deref(),
+            Some("alice@example.com")
+        );
+        assert!(matches!(
+            alice.named_properties().get(&lid(PSETID_ADDRESS, 0x8029)),
+            Some(PropertyValue::Integer32(0x3))
+        ));
+
+        assert_eq!(unicode(alice, 0x3A08).as_deref(), Some("+1 555 0100"));
+        assert_eq!(unicode(alice, 0x3A1C).as_deref(), Some("+1 555 0101"));
+        assert_eq!(unicode(alice, 0x3A24).as_deref(), Some("+1 555 0102"));
+        assert_eq!(unicode(alice, 0x3A1B).as_deref(), Some("+1 555 0103"));
+        assert_eq!(unicode(alice, 0x3A1F).as_deref(), Some("+1 555 0104"));
+
+        assert_eq!(
+            unicode(alice, 0x3A29).as_deref(),
+            Some("1 Main St\r\nSuite 5")
+        );
+        assert_eq!(named_unicode(alice, 0x8046).as_deref(), Some("Springfield"));
+        assert_eq!(unicode(alice, 0x3A59).as_deref(), Some("Shelbyville"));
+        assert_eq!(
+            named_unicode(alice, 0x801B).as_deref(),
+            Some("1 Main St\r\nSuite 5\r\nSpringfield IL 62701\r\nUSA")
+        );
+        assert!(matches!(
+            alice.named_properties().get(&lid(PSETID_ADDRESS, 0x8022)),
+            Some(PropertyValue::Integer32(2))
+        ));
+
+        assert_eq!(
+            unicode(alice, 0x3A51).as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            unicode(alice, 0x1000).as_deref(),
+            Some("Met at the conference, 2023")
+        );
+        let birthday = from_unix_seconds(days_from_civil(1980, 4, 15) * 86_400);
+        assert!(matches!(
+            alice.properties().get(&0x3A42),
+            Some(PropertyValue::Time(time)) if *time == birthday
+        ));
+
+        let [photo] = alice.attachments() else {
+            panic!("expected one attachment");
+        };
+        assert_eq!(
+            unicode_attachment(photo, 0x3707).as_deref(),
+            Some("ContactPicture.jpg")
+        );
+        assert!(matches!(
+            photo.properties().get(&0x7FFF),
+            Some(PropertyValue::Boolean(true))
+        ));
+        assert!(matches!(
+            photo.properties().get(&0x3701),
+            Some(PropertyValue::Binary(data)) if data.buffer().starts_with(&[0xFF, 0xD8, 0xFF, 0xE0])
+        ));
+
+        let juergen = &contacts[1];
+        assert_eq!(unicode(juergen, 0x3A11).as_deref(), Some("Müller"));
+        assert_eq!(unicode(juergen, 0x3001).as_deref(), Some("Jürgen Müller"));
+        assert_eq!(unicode(juergen, 0x3A09).as_deref(), Some("555-0200"));
+        assert!(juergen.attachments().is_empty());
+    }
+
+    fn unicode_attachment(attachment: &AttachmentBuilder, prop_id: u16) -> Option<String> {
+        match attachment.properties().get(&prop_id) {
+            Some(PropertyValue::Unicode(value)) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_parse_dates() {
+        let expected = from_unix_seconds(days_from_civil(1995, 10, 31) * 86_400);
+        assert_eq!(parse_date("1995-10-31"), Some(expected));
+        assert_eq!(parse_date("19951031"), Some(expected));
+        assert_eq!(parse_date("--1031"), None);
+        assert_eq!(
+            parse_timestamp("1995-10-31T22:27:10Z"),
+            Some(expected + 80_830 * 10_000_000)
+        );
+    }
+}
@@ -1,31 +1,57 @@
 //! ## [Message Objects](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/1042af37-aaa4-4edc-bffd-90a1ede24188)
 
-use std::{collections::BTreeMap, io, rc::Rc};
+use std::{
+    cell::OnceCell,
+    collections::{btree_map::Entry, BTreeMap},
+    io,
+    rc::{Rc, Weak},
+    time::SystemTime,
+};
 
-use super::{read_write::*, store::*, *};
+use super::{
+    attachment::*,
+    categories::KEYWORDS_PROPERTY_NAME,
+    completeness::*,
+    conversation_index::*,
+    message_flags::*,
+    named_prop::{NamedPropertyMapProperties, NamedPropertyName, PS_PUBLIC_STRINGS},
+    object_id::AttachmentId,
+    psetid::PSETID_APPOINTMENT,
+    read_write::*,
+    store::*,
+    sync::SyncState,
+    *,
+};
 use crate::{
     ltp::{
+        code_page::String8Decoder,
         heap::HeapNode,
-        prop_context::{PropertyContext, PropertyValue},
+        prop_context::{
+            build_property_context, BinaryValue, GuidValue, PropertyContext, PropertyValue,
+            UnicodeValue,
+        },
         prop_type::PropertyType,
         read_write::*,
-        table_context::TableContext,
+        table_context::{build_table_context, TableContext, TableRowId, TableRows},
     },
     ndb::{
         block::{IntermediateTreeBlock, LeafSubNodeTreeEntry, SubNodeTree},
         block_id::BlockId,
-        header::Header,
-        node_id::{NodeId, NodeIdType},
+        header::{Header, NdbVersion},
+        node_data::{NodeData, FIRST_LOCAL_NODE_INDEX},
+        node_id::{NodeId, NodeIdType, NID_ATTACHMENT_TABLE, NID_RECIPIENT_TABLE},
         page::{AnsiNodeBTreeEntry, BTreePage, NodeBTreeEntry, RootBTree, UnicodeNodeBTreeEntry},
         read_write::*,
         root::Root,
     },
+    time::{self, TimeZoneRule, PID_LID_TIME_ZONE_STRUCT},
     AnsiPstFile, PstFile, PstFileLock, UnicodePstFile,
 };
 
 #[derive(Default, Debug)]
 pub struct MessageProperties {
     properties: BTreeMap<u16, PropertyValue>,
+    string_decoder: String8Decoder,
 }
 
 impl MessageProperties {
@@ -33,10 +59,91 @@ impl MessageProperties {
         self.properties.get(&id)
     }
 
+    /// Parse the `PidTagChangeKey` and `PidTagPredecessorChangeList` of the message.
+    pub fn sync_state(&self) -> io::Result<SyncState> {
+        SyncState::read(|prop_id| self.get(prop_id))
+    }
+
+    /// The names of the categories assigned to the message in `PidNameKeywords`, or an empty list
+    /// if it does not have any. The named property is looked up in `named_properties`, e.g. from
+    /// [`Store::named_property_map`].
+    pub fn categories(
+        &self,
+        named_properties: &NamedPropertyMapProperties,
+    ) -> io::Result<Vec<String>> {
+        let keywords = NamedPropertyName::from(KEYWORDS_PROPERTY_NAME);
+        let Some(prop_id) = named_properties.find(&PS_PUBLIC_STRINGS, &keywords)? else {
+            return Ok(Default::default());
+        };
+        match self.get(prop_id) {
+            None => Ok(Default::default()),
+            Some(PropertyValue::MultipleUnicode(values)) => {
+                Ok(values.iter().map(UnicodeValue::to_string).collect())
+            }
+            Some(PropertyValue::MultipleString8(values)) => Ok(values
+                .iter()
+                .map(|value| value.to_string_with_decoder(&self.string_decoder))
+                .collect()),
+            Some(invalid) => {
+                Err(MessagingError::InvalidMessageCategories(PropertyType::from(invalid)).into())
+            }
+        }
+    }
+
+    /// The time zone of a recurring appointment in `PidLidTimeZoneStruct`, or `None` if it does
+    /// not have one. The named property is looked up in `named_properties`, e.g. from
+    /// [`Store::named_property_map`].
+    pub fn time_zone(
+        &self,
+        named_properties: &NamedPropertyMapProperties,
+    ) -> io::Result<Option<TimeZoneRule>> {
+        let time_zone_struct = NamedPropertyName::Number(PID_LID_TIME_ZONE_STRUCT);
+        let Some(prop_id) = named_properties.find(&PSETID_APPOINTMENT, &time_zone_struct)? else {
+            return Ok(None);
+        };
+        match self.get(prop_id) {
+            None => Ok(None),
+            Some(PropertyValue::Binary(value)) => Ok(Some(TimeZoneRule::read(value.buffer())?)),
+            Some(invalid) => {
+                Err(MessagingError::InvalidMessageTimeZone(PropertyType::from(invalid)).into())
+            }
+        }
+    }
+
+    /// Parse the `PidTagConversationIndex` of the message, or `None` if it does not have one.
+    pub fn conversation_index(&self) -> io::Result<Option<ConversationIndex>> {
+        self.get(PID_TAG_CONVERSATION_INDEX)
+            .map(ConversationIndex::try_from)
+            .transpose()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&u16, &PropertyValue)> {
         self.properties.iter()
     }
 
+    /// The code page used to decode `PtypString8` values, which is `PidTagMessageCodepage` if the
+    /// message has one, or else [`Store::string_code_page`].
+    pub fn string_code_page(&self) -> u16 {
+        self.string_decoder.code_page()
+    }
+
+    /// The [`string_code_page`](Self::string_code_page) together with the
+    /// [`Store::code_page_decoder`].
+    pub fn string_decoder(&self) -> &String8Decoder {
+        &self.string_decoder
+    }
+
+    /// Accessors for the mandatory properties which return `None` instead of a
+    /// [`MessagingError`], so partially-written messages can still be read.
+    pub fn relaxed(&self) -> RelaxedMessageProperties<'_> {
+        RelaxedMessageProperties::new(self)
+    }
+
+    /// Which of the mandatory properties are missing or have the wrong type.
+    pub fn completeness(&self) -> MessageCompleteness {
+        MessageCompleteness::check(|prop_id| self.get(prop_id))
+    }
+
     pub fn message_class(&self) -> io::Result<String> {
         let message_class = self
             .properties
@@ -44,13 +151,18 @@ impl MessageProperties {
             .ok_or(MessagingError::MessageClassNotFound)?;
 
         match message_class {
-            PropertyValue::String8(value) => Ok(value.to_string()),
+            PropertyValue::String8(value) => Ok(value.to_string_with_decoder(&self.string_decoder)),
             PropertyValue::Unicode(value) => Ok(value.to_string()),
             invalid => Err(MessagingError::InvalidMessageClass(PropertyType::from(invalid)).into()),
         }
     }
 
-    pub fn message_flags(&self) -> io::Result<i32> {
+    /// `PidTagMessageFlags`, see [`MessageProperties::raw_message_flags`] for the `i32` value.
+    pub fn message_flags(&self) -> io::Result<MessageFlags> {
+        self.raw_message_flags().map(MessageFlags::from_bits)
+    }
+
+    pub fn raw_message_flags(&self) -> io::Result<i32> {
         let message_flags = self
             .properties
             .get(&0x0E07)
@@ -74,7 +186,12 @@ impl MessageProperties {
         }
     }
 
-    pub fn message_status(&self) -> io::Result<i32> {
+    /// `PidTagMessageStatus`, see [`MessageProperties::raw_message_status`] for the `i32` value.
+    pub fn message_status(&self) -> io::Result<MessageStatus> {
+        self.raw_message_status().map(MessageStatus::from_bits)
+    }
+
+    pub fn raw_message_status(&self) -> io::Result<i32> {
         let message_status = self
             .properties
             .get(&0x0E17)
@@ -132,11 +249,566 @@ impl MessageProperties {
     }
 }
 
+/// `PidTagRecipientType`
+#[repr(i32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecipientType {
+    /// `MAPI_ORIG`: The originator of the message.
+    Originator = 0,
+    /// `MAPI_TO`
+    To = 1,
+    /// `MAPI_CC`
+    Cc = 2,
+    /// `MAPI_BCC`
+    Bcc = 3,
+}
+
+impl RecipientType {
+    /// The message property which lists the display names of the recipients of this type, e.g.
+    /// `PidTagDisplayTo`.
+    pub fn display_prop_id(&self) -> Option<u16> {
+        match self {
+            RecipientType::Originator => None,
+            RecipientType::To => Some(0x0E04),
+            RecipientType::Cc => Some(0x0E03),
+            RecipientType::Bcc => Some(0x0E02),
+        }
+    }
+}
+
+/// Columns in the recipient table template at
+/// [`NID_RECIPIENT_TABLE`](crate::ndb::node_id::NID_RECIPIENT_TABLE), which every recipient
+/// table has in addition to `PidTagLtpRowId` and `PidTagLtpRowVer`.
+const RECIPIENT_TABLE_COLUMNS: &[(u16, PropertyType)] = &[
+    (0x0C15, PropertyType::Integer32),
+    (0x0E0F, PropertyType::Boolean),
+    (0x0FF9, PropertyType::Binary),
+    (0x0FFE, PropertyType::Integer32),
+    (0x0FFF, PropertyType::Binary),
+    (0x3001, PropertyType::Unicode),
+    (0x3002, PropertyType::Unicode),
+    (0x3003, PropertyType::Unicode),
+    (0x300B, PropertyType::Binary),
+    (0x3900, PropertyType::Integer32),
+    (0x39FF, PropertyType::Unicode),
+    (0x3A40, PropertyType::Boolean),
+];
+
+/// Columns in the attachment table template at
+/// [`NID_ATTACHMENT_TABLE`](crate::ndb::node_id::NID_ATTACHMENT_TABLE), which every attachment
+/// table has in addition to `PidTagLtpRowId` and `PidTagLtpRowVer`.
+const ATTACHMENT_TABLE_COLUMNS: &[(u16, PropertyType)] = &[
+    (0x0E20, PropertyType::Integer32),
+    (0x3704, PropertyType::Unicode),
+    (0x3705, PropertyType::Integer32),
+    (0x370B, PropertyType::Integer32),
+    (0x3712, PropertyType::Unicode),
+];
+
+/// `mfRead` in `PidTagMessageFlags`
+pub const MSGFLAG_READ: i32 = 0x0000_0001;
+
+/// `mfHasAttach` in `PidTagMessageFlags`
+pub const MSGFLAG_HASATTACH: i32 = 0x0000_0010;
+
+/// `mfFAI` in `PidTagMessageFlags`
+pub const MSGFLAG_ASSOCIATED: i32 = 0x0000_0040;
+
+/// `MAPI_MAILUSER`
+const MAPI_MAILUSER: i32 = 0x0000_0006;
+
+/// `DT_MAILUSER`
+const DT_MAILUSER: i32 = 0x0000_0000;
+
+/// Provider UID of a One-Off EntryID from `MS-OXCDATA`.
+const ONE_OFF_PROVIDER_UID: [u8; 16] = [
+    0x81, 0x2B, 0x1F, 0xA4, 0xBE, 0xA3, 0x10, 0x19, 0x9D, 0x6E, 0x00, 0xDD, 0x01, 0x0F, 0x54, 0x02,
+];
+
+/// `MAPI_ONE_OFF_UNICODE | MAPI_ONE_OFF_NO_RICH_INFO`
+const ONE_OFF_FLAGS: u16 = 0x8001;
+
+/// Properties, recipients and attachments of a message, which can be written to the PST file
+/// with [`PstFileLockGuard::create_message`](crate::PstFileLockGuard::create_message) or
+/// [`PstFileLockGuard::update_message`](crate::PstFileLockGuard::update_message).
+#[derive(Clone, Debug)]
+pub struct MessageBuilder {
+    properties: BTreeMap<u16, PropertyValue>,
+    string_code_page: u16,
+    recipient_columns: BTreeMap<u16, PropertyType>,
+    recipients: BTreeMap<TableRowId, BTreeMap<u16, PropertyValue>>,
+    attachments: Vec<AttachmentBuilder>,
+    named_properties: BTreeMap<(GuidValue, NamedPropertyName), PropertyValue>,
+}
+
+impl MessageBuilder {
+    pub fn new(message_class: &str) -> Self {
+        let mut builder = Self {
+            properties: Default::default(),
+            string_code_page: Default::default(),
+            recipient_columns: RECIPIENT_TABLE_COLUMNS.iter().copied().collect(),
+            recipients: Default::default(),
+            attachments: Default::default(),
+            named_properties: Default::default(),
+        };
+        builder.set_property(0x001A, unicode_value(message_class));
+        builder
+    }
+
+    /// Start with the properties and recipient table of an existing message. Any columns in the
+    /// recipient table beyond the required ones are kept.
+    pub fn from_message(message: &dyn Message) -> io::Result<Self> {
+        let properties = message.properties();
+        let mut recipient_columns: BTreeMap<_, _> =
+            RECIPIENT_TABLE_COLUMNS.iter().copied().collect();
+        let mut recipients = BTreeMap::new();
+
+        if let Some(recipient_table) = message.recipient_table() {
+            let context = recipient_table.context();
+            recipient_columns.extend(
+                context
+                    .columns()
+                    .iter()
+                    .map(|column| (column.prop_id(), column.prop_type())),
+            );
+
+            for row in recipient_table.rows_matrix() {
+                let mut values = BTreeMap::new();
+                for (column, value) in context.columns().iter().zip(row.columns(context)?) {
+                    if let Some(value) = value {
+                        let value = recipient_table.read_column(&value, column.prop_type())?;
+                        values.insert(column.prop_id(), value);
+                    }
+                }
+                recipients.insert(row.id(), values);
+            }
+        }
+
+        Ok(Self {
+            properties: properties
+                .iter()
+                .map(|(prop_id, value)| (*prop_id, value.clone()))
+                .collect(),
+            string_code_page: properties.string_code_page(),
+            recipient_columns,
+            recipients,
+            attachments: Default::default(),
+            named_properties: Default::default(),
+        })
+    }
+
+    pub fn properties(&self) -> &BTreeMap<u16, PropertyValue> {
+        &self.properties
+    }
+
+    pub fn set_property(&mut self, prop_id: u16, value: PropertyValue) {
+        self.properties.insert(prop_id, value);
+    }
+
+    pub fn remove_property(&mut self, prop_id: u16) -> Option<PropertyValue> {
+        self.properties.remove(&prop_id)
+    }
+
+    /// Named properties which have not been mapped to a property ID yet.
+    pub fn named_properties(&self) -> &BTreeMap<(GuidValue, NamedPropertyName), PropertyValue> {
+        &self.named_properties
+    }
+
+    /// Whether this message or any of its embedded messages has [`Self::named_properties`].
+    pub fn has_named_properties(&self) -> bool {
+        !self.named_properties.is_empty()
+            || self
+                .attachments
+                .iter()
+                .filter_map(AttachmentBuilder::message)
+                .any(MessageBuilder::has_named_properties)
+    }
+
+    /// Set a named property by its property set and name. It is mapped to a property ID in the
+    /// [`NamedPropertyMap`](super::named_prop::NamedPropertyMap) when the message is written with
+    /// [`PstFileLockGuard::create_message`](crate::PstFileLockGuard::create_message).
+    pub fn set_named_property(
+        &mut self,
+        guid: GuidValue,
+        name: impl Into<NamedPropertyName>,
+        value: PropertyValue,
+    ) {
+        self.named_properties.insert((guid, name.into()), value);
+    }
+
+    /// Assign categories to the message by setting `PidNameKeywords` to their names.
+    pub fn set_categories<S: AsRef<str>>(&mut self, categories: &[S]) {
+        let values = categories
+            .iter()
+            .map(|category| UnicodeValue::new(category.as_ref().encode_utf16().collect()))
+            .collect();
+        self.set_named_property(
+            PS_PUBLIC_STRINGS,
+            KEYWORDS_PROPERTY_NAME,
+            PropertyValue::MultipleUnicode(values),
+        );
+    }
+
+    /// The messages attached with [`AttachmentBuilder::embedded_message`].
+    pub(crate) fn embedded_messages_mut(&mut self) -> impl Iterator<Item = &mut MessageBuilder> {
+        self.attachments
+            .iter_mut()
+            .filter_map(AttachmentBuilder::message_mut)
+    }
+
+    pub(crate) fn take_named_properties(
+        &mut self,
+    ) -> BTreeMap<(GuidValue, NamedPropertyName), PropertyValue> {
+        std::mem::take(&mut self.named_properties)
+    }
+
+    /// Columns of the recipient table, not including `PidTagLtpRowId` and `PidTagLtpRowVer`.
+    pub fn recipient_columns(&self) -> Vec<(u16, PropertyType)> {
+        self.recipient_columns
+            .iter()
+            .map(|(prop_id, prop_type)| (*prop_id, *prop_type))
+            .collect()
+    }
+
+    /// Rows of the recipient table, keyed by their `dwRowID`.
+    pub fn recipients(&self) -> &BTreeMap<TableRowId, BTreeMap<u16, PropertyValue>> {
+        &self.recipients
+    }
+
+    /// Add an SMTP recipient with a one-off entry ID, and update the `PidTagDisplayTo`,
+    /// `PidTagDisplayCc`, or `PidTagDisplayBcc` property to match. Returns the `dwRowID` of the
+    /// new row, which is one more than the highest existing row ID.
+    pub fn add_recipient(
+        &mut self,
+        recipient_type: RecipientType,
+        display_name: &str,
+        email: &str,
+    ) -> TableRowId {
+        let id = self
+            .recipients
+            .last_key_value()
+            .map(|(id, _)| TableRowId::new(u32::from(*id) + 1))
+            .unwrap_or_default();
+
+        let entry_id = one_off_entry_id(display_name, "SMTP", email);
+        let mut search_key = format!("SMTP:{}", email.to_ascii_uppercase()).into_bytes();
+        search_key.push(0);
+        let seven_bit_display_name: String = display_name
+            .chars()
+            .map(|c| if c.is_ascii() { c } else { '?' })
+            .collect();
+
+        let row = BTreeMap::from([
+            (0x0C15, PropertyValue::Integer32(recipient_type as i32)),
+            (0x0E0F, PropertyValue::Boolean(false)),
+            (
+                0x0FF9,
+                PropertyValue::Binary(BinaryValue::new(entry_id.clone())),
+            ),
+            (0x0FFE, PropertyValue::Integer32(MAPI_MAILUSER)),
+            (0x0FFF, PropertyValue::Binary(BinaryValue::new(entry_id))),
+            (0x3001, unicode_value(display_name)),
+            (0x3002, unicode_value("SMTP")),
+            (0x3003, unicode_value(email)),
+            (0x300B, PropertyValue::Binary(BinaryValue::new(search_key))),
+            (0x3900, PropertyValue::Integer32(DT_MAILUSER)),
+            (0x39FF, unicode_value(&seven_bit_display_name)),
+            (0x3A40, PropertyValue::Boolean(false)),
+        ]);
+        self.recipients.insert(id, row);
+        self.update_display_names(recipient_type);
+        id
+    }
+
+    pub fn attachments(&self) -> &[AttachmentBuilder] {
+        &self.attachments
+    }
+
+    /// Add an attachment, and set `mfHasAttach` in `PidTagMessageFlags`.
+    pub fn add_attachment(&mut self, attachment: AttachmentBuilder) {
+        self.attachments.push(attachment);
+        let flags = match self.properties.get(&0x0E07) {
+            Some(PropertyValue::Integer32(flags)) => *flags,
+            _ => 0,
+        };
+        self.set_property(0x0E07, PropertyValue::Integer32(flags | MSGFLAG_HASATTACH));
+    }
+
+    /// Serialize the message node: the property context, the recipient table and, if there are
+    /// any attachments, the attachment table and the property context of each attachment, along
+    /// with any embedded message. Named properties are not included until they have been mapped
+    /// to property IDs.
+    pub fn build(&self, version: NdbVersion) -> io::Result<NodeData> {
+        let mut data = build_property_context(self.properties.iter())?;
+
+        let recipients = build_table_context(version, &self.recipient_columns(), &self.recipients)?;
+        data.insert_sub_node(NID_RECIPIENT_TABLE, recipients);
+
+        if self.attachments.is_empty() {
+            return Ok(data);
+        }
+
+        let mut rows = TableRows::new();
+        for (index, attachment) in self.attachments.iter().enumerate() {
+            let sub_node = NodeId::new(
+                NodeIdType::Attachment,
+                FIRST_LOCAL_NODE_INDEX + index as u32,
+            )?;
+            let (properties, attachment) = attachment.build(version)?;
+            let row = ATTACHMENT_TABLE_COLUMNS
+                .iter()
+                .filter_map(|(prop_id, prop_type)| {
+                    let value = properties.get(prop_id)?;
+                    (PropertyType::from(value) == *prop_type).then(|| (*prop_id, value.clone()))
+                })
+                .collect();
+            rows.insert(TableRowId::new(u32::from(sub_node)), row);
+            data.insert_sub_node(sub_node, attachment);
+        }
+        let attachments = build_table_context(version, ATTACHMENT_TABLE_COLUMNS, &rows)?;
+        data.insert_sub_node(NID_ATTACHMENT_TABLE, attachments);
+
+        Ok(data)
+    }
+
+    fn update_display_names(&mut self, recipient_type: RecipientType) {
+        let Some(prop_id) = recipient_type.display_prop_id() else {
+            return;
+        };
+
+        let display_names: Vec<_> = self
+            .recipients
+            .values()
+            .filter(|row| match row.get(&0x0C15) {
+                Some(PropertyValue::Integer32(value)) => *value == recipient_type as i32,
+                _ => false,
+            })
+            .filter_map(|row| match row.get(&0x3001)? {
+                PropertyValue::String8(value) => {
+                    Some(value.to_string_with_code_page(self.string_code_page))
+                }
+                PropertyValue::Unicode(value) => Some(value.to_string()),
+                _ => None,
+            })
+            .collect();
+        self.set_property(prop_id, unicode_value(&display_names.join("; ")));
+    }
+}
+
+pub(crate) fn unicode_value(value: &str) -> PropertyValue {
+    PropertyValue::Unicode(UnicodeValue::new(value.encode_utf16().collect()))
+}
+
+/// Build a One-Off EntryID from `MS-OXCDATA`, with null terminated Unicode strings.
+pub(crate) fn one_off_entry_id(display_name: &str, address_type: &str, email: &str) -> Vec<u8> {
+    let mut entry_id = vec![0; 4];
+    entry_id.extend_from_slice(&ONE_OFF_PROVIDER_UID);
+    entry_id.extend_from_slice(&0_u16.to_le_bytes());
+    entry_id.extend_from_slice(&ONE_OFF_FLAGS.to_le_bytes());
+    for value in [display_name, address_type, email] {
+        for c in value.encode_utf16().chain(std::iter::once(0)) {
+            entry_id.extend_from_slice(&c.to_le_bytes());
+        }
+    }
+    entry_id
+}
+
+/// Fill in `PidTagCreationTime`, `PidTagLastModificationTime`, `PidTagSearchKey` and
+/// `PidTagRecordKey` for a new `message`, if they are not already set, and return whether any of
+/// them were added.
+///
+/// The record key is the store's record key followed by the NID, like an entry ID without its
+/// flags. The search key is the store's record key mixed with the creation time and the NID, so
+/// it is unique in the store. Copies of a message get new values for both, see
+/// [`PstFileLockGuard::copy_message`](crate::PstFileLockGuard::copy_message).
+pub(crate) fn set_default_message_properties(
+    properties: &mut BTreeMap<u16, PropertyValue>,
+    now: SystemTime,
+    store_record_key: &[u8; 16],
+    message: NodeId,
+) -> bool {
+    let filetime = time::from_system_time(now);
+    let now = PropertyValue::Time(filetime);
+    let node_id = u32::from(message).to_le_bytes();
+
+    let mut record_key = store_record_key.to_vec();
+    record_key.extend_from_slice(&node_id);
+
+    let mut search_key = *store_record_key;
+    for (key, value) in search_key
+        .iter_mut()
+        .zip(filetime.to_le_bytes().into_iter().chain(node_id))
+    {
+        *key ^= value;
+    }
+
+    let mut modified = false;
+    for (prop_id, value) in [
+        (0x3007, now.clone()),
+        (0x3008, now),
+        (0x300B, PropertyValue::from(search_key.to_vec())),
+        (0x0FF9, PropertyValue::from(record_key)),
+    ] {
+        if let Entry::Vacant(entry) = properties.entry(prop_id) {
+            entry.insert(value);
+            modified = true;
+        }
+    }
+    modified
+}
+
+/// Summary of an attachment from its row in the attachment table of a message, see
+/// [`Message::attachment_summaries`]. The attachment table always has columns for the size,
+/// short file name, method and rendering position, other properties are only present if the
+/// PST writer added columns for them.
+#[derive(Clone, Debug)]
+pub struct AttachmentSummary {
+    sub_node: AttachmentId,
+    long_file_name: Option<String>,
+    file_name: Option<String>,
+    display_name: Option<String>,
+    content_id: Option<String>,
+    size: Option<i32>,
+    method: Option<AttachmentMethod>,
+    rendering_position: Option<RenderingPosition>,
+}
+
+impl AttachmentSummary {
+    fn new(sub_node: AttachmentId) -> Self {
+        Self {
+            sub_node,
+            long_file_name: None,
+            file_name: None,
+            display_name: None,
+            content_id: None,
+            size: None,
+            method: None,
+            rendering_position: None,
+        }
+    }
+
+    /// The `NID_TYPE_ATTACHMENT` sub-node ID to pass to [`Message::open_attachment`].
+    pub fn sub_node(&self) -> AttachmentId {
+        self.sub_node
+    }
+
+    /// `PidTagAttachLongFilename`, falling back to `PidTagAttachFilename` and then
+    /// `PidTagDisplayName`.
+    pub fn name(&self) -> Option<&str> {
+        self.long_file_name
+            .as_deref()
+            .or(self.file_name.as_deref())
+            .or(self.display_name.as_deref())
+    }
+
+    /// `PidTagAttachContentId`, which inline attachments are referenced by in an HTML body.
+    pub fn content_id(&self) -> Option<&str> {
+        self.content_id.as_deref()
+    }
+
+    /// `PidTagAttachSize`
+    pub fn size(&self) -> Option<i32> {
+        self.size
+    }
+
+    /// `PidTagAttachMethod`, or `None` if the method is missing or unknown.
+    pub fn method(&self) -> Option<AttachmentMethod> {
+        self.method
+    }
+
+    /// `PidTagRenderingPosition`
+    pub fn rendering_position(&self) -> Option<RenderingPosition> {
+        self.rendering_position
+    }
+}
+
 pub trait Message {
     fn store(&self) -> Rc<dyn Store>;
     fn properties(&self) -> &MessageProperties;
+    /// The recipient table is read the first time it is used, and then cached with the message.
     fn recipient_table(&self) -> Option<&Rc<dyn TableContext>>;
+    /// The attachment table is read the first time it is used, and then cached with the message.
+    /// Use [`Message::has_attachments`] to check for attachments without reading it.
     fn attachment_table(&self) -> Option<&Rc<dyn TableContext>>;
+
+    /// Whether `PidTagMessageFlags` has [`MSGFLAG_HASATTACH`], which does not read the
+    /// [`Message::attachment_table`]. A message without `PidTagMessageFlags`, e.g. one opened
+    /// with a list of `prop_ids` which leaves it out, does not have the flag.
+    fn has_attachments(&self) -> bool {
+        self.properties()
+            .message_flags()
+            .is_ok_and(|flags| flags.contains(MessageFlags::HAS_ATTACH))
+    }
+
+    /// Open an attachment by the `NID_TYPE_ATTACHMENT` sub-node ID in a row of the
+    /// [`Message::attachment_table`].
+    fn open_attachment(
+        &self,
+        sub_node: AttachmentId,
+        prop_ids: Option<&[u16]>,
+    ) -> io::Result<Rc<dyn Attachment>>;
+
+    /// Find the block which holds the data of an attachment, without reading the data. Returns
+    /// `None` if the attachment has no `PidTagAttachDataBinary` or `PidTagAttachDataObject`, e.g.
+    /// if it is attached by reference.
+    fn attachment_data_block(
+        &self,
+        sub_node: AttachmentId,
+    ) -> io::Result<Option<AttachmentDataBlock>>;
+
+    /// Summarize each attachment from its row in the [`Message::attachment_table`], without
+    /// reading the attachment property contexts or their data.
+    fn attachment_summaries(&self) -> io::Result<Vec<AttachmentSummary>> {
+        let Some(table) = self.attachment_table() else {
+            return Ok(Default::default());
+        };
+
+        let string_decoder = self.properties().string_decoder();
+        let heap_id_mode = self.store().heap_id_mode();
+        let context = table.context();
+        let mut attachments = Vec::new();
+        for row in table.rows_matrix() {
+            let mut summary =
+                AttachmentSummary::new(AttachmentId::try_from(NodeId::from(u32::from(row.id())))?);
+
+            for (column, value) in context.columns().iter().zip(row.columns(context)?) {
+                let Some(value) = value else {
+                    continue;
+                };
+                let Some(value) =
+                    table.read_column_with_mode(&value, column.prop_type(), heap_id_mode)?
+                else {
+                    continue;
+                };
+                let string = match &value {
+                    PropertyValue::String8(value) => {
+                        Some(value.to_string_with_decoder(string_decoder))
+                    }
+                    PropertyValue::Unicode(value) => Some(value.to_string()),
+                    _ => None,
+                };
+
+                match (column.prop_id(), value) {
+                    (0x3707, _) => summary.long_file_name = string,
+                    (0x3704, _) => summary.file_name = string,
+                    (0x3001, _) => summary.display_name = string,
+                    (0x3712, _) => summary.content_id = string,
+                    (0x0E20, PropertyValue::Integer32(value)) => summary.size = Some(value),
+                    (0x3705, PropertyValue::Integer32(value)) => {
+                        summary.method = AttachmentMethod::try_from(value).ok()
+                    }
+                    (0x370B, PropertyValue::Integer32(value)) => {
+                        summary.rendering_position = Some(RenderingPosition::from(value))
+                    }
+                    _ => {}
+                }
+            }
+
+            attachments.push(summary);
+        }
+
+        Ok(attachments)
+    }
 }
 
 struct MessageInner<Pst>
@@ -144,10 +816,13 @@ where
     Pst: PstFile,
 {
     store: Rc<Pst::Store>,
+    message: Weak<Pst::Message>,
     properties: MessageProperties,
     sub_nodes: MessageSubNodes<Pst>,
-    recipient_table: Option<Rc<dyn TableContext>>,
-    attachment_table: Option<Rc<dyn TableContext>>,
+    recipient_table_node: Option<Pst::NodeBTreeEntry>,
+    attachment_table_node: Option<Pst::NodeBTreeEntry>,
+    recipient_table: OnceCell<Option<Rc<dyn TableContext>>>,
+    attachment_table: OnceCell<Option<Rc<dyn TableContext>>>,
 }
 
 impl<Pst> MessageInner<Pst>
@@ -212,11 +887,8 @@ where
         let root = header.root();
 
         let node = {
-            let mut file = pst
-                .reader()
-                .lock()
-                .map_err(|_| MessagingError::FailedToLockFile)?;
-            let file = &mut *file;
+            let mut file = pst.reader();
+            let file = &mut file;
 
             let node_btree = <<Pst as PstFile>::NodeBTree as RootBTreeReadWrite>::read(
                 file,
@@ -236,16 +908,14 @@ where
         node: <Pst as PstFile>::NodeBTreeEntry,
         prop_ids: Option<&[u16]>,
     ) -> io::Result<Self> {
+        let heap_id_mode = store.heap_id_mode();
         let pst = store.pst();
         let header = pst.header();
         let root = header.root();
 
         let (properties, sub_nodes) = {
-            let mut file = pst
-                .reader()
-                .lock()
-                .map_err(|_| MessagingError::FailedToLockFile)?;
-            let file = &mut *file;
+            let mut file = pst.reader();
+            let file = &mut file;
 
             let encoding = header.crypt_method();
             let block_btree = <<Pst as PstFile>::BlockBTree as RootBTreeReadWrite>::read(
@@ -276,11 +946,18 @@ where
                 .filter(|(prop_id, _)| prop_ids.is_none_or(|ids| ids.contains(prop_id)))
                 .map(|(prop_id, record)| {
                     prop_context
-                        .read_property(file, encoding, &block_btree, &mut page_cache, record)
-                        .map(|value| (prop_id, value))
+                        .read_property_with_mode(
+                            file,
+                            encoding,
+                            &block_btree,
+                            &mut page_cache,
+                            record,
+                            heap_id_mode,
+                        )
+                        .map(|value| value.map(|value| (prop_id, value)))
                 })
+                .filter_map(Result::transpose)
                 .collect::<io::Result<BTreeMap<_, _>>>()?;
-            let properties = MessageProperties { properties };
 
             let block = block_btree.find_entry(file, sub_node.search_key(), &mut page_cache)?;
             let sub_nodes = SubNodeTree::<Pst>::read(file, &block)?;
@@ -292,6 +969,18 @@ where
             (properties, sub_nodes)
         };
 
+        let string_decoder = match properties.get(&0x3FFD) {
+            Some(PropertyValue::Integer32(code_page)) => u16::try_from(*code_page)
+                .ok()
+                .map(|code_page| String8Decoder::new(code_page, store.code_page_decoder())),
+            _ => None,
+        }
+        .unwrap_or_else(|| store.string_decoder());
+        let properties = MessageProperties {
+            properties,
+            string_decoder,
+        };
+
         let mut recipient_table_nodes = sub_nodes.iter().filter_map(|(node_id, entry)| {
             node_id.id_type().ok().and_then(|id_type| {
                 if id_type == NodeIdType::RecipientTable {
@@ -308,15 +997,11 @@ where
                 }
             })
         });
-        let recipient_table = match (recipient_table_nodes.next(), recipient_table_nodes.next()) {
-            (None, None) => None,
-            (Some(node), None) => {
-                Some(<<Pst as PstFile>::TableContext as TableContextReadWrite<
-                    Pst,
-                >>::read(store.clone(), node)?)
-            }
-            _ => return Err(MessagingError::MultipleMessageRecipientTables.into()),
-        };
+        let recipient_table_node =
+            match (recipient_table_nodes.next(), recipient_table_nodes.next()) {
+                (node, None) => node,
+                _ => return Err(MessagingError::MultipleMessageRecipientTables.into()),
+            };
 
         let mut attachment_table_nodes = sub_nodes.iter().filter_map(|(node_id, entry)| {
             node_id.id_type().ok().and_then(|id_type| {
@@ -334,25 +1019,50 @@ where
                 }
             })
         });
-        let attachment_table = match (attachment_table_nodes.next(), attachment_table_nodes.next())
-        {
-            (None, None) => None,
-            (Some(node), None) => {
-                Some(<<Pst as PstFile>::TableContext as TableContextReadWrite<
-                    Pst,
-                >>::read(store.clone(), node)?)
-            }
-            _ => return Err(MessagingError::MultipleMessageAttachmentTables.into()),
-        };
+        let attachment_table_node =
+            match (attachment_table_nodes.next(), attachment_table_nodes.next()) {
+                (node, None) => node,
+                _ => return Err(MessagingError::MultipleMessageAttachmentTables.into()),
+            };
 
         Ok(Self {
             store,
+            message: Default::default(),
             properties,
             sub_nodes,
-            recipient_table,
-            attachment_table,
+            recipient_table_node,
+            attachment_table_node,
+            recipient_table: Default::default(),
+            attachment_table: Default::default(),
         })
     }
+
+    fn read_table(
+        &self,
+        node: Option<<Pst as PstFile>::NodeBTreeEntry>,
+    ) -> io::Result<Option<Rc<dyn TableContext>>> {
+        let Some(node) = node else {
+            return Ok(None);
+        };
+        Ok(Some(
+            <<Pst as PstFile>::TableContext as TableContextReadWrite<Pst>>::read(
+                self.store.clone(),
+                node,
+            )?,
+        ))
+    }
+
+    fn recipient_table(&self) -> Option<&Rc<dyn TableContext>> {
+        self.recipient_table
+            .get_or_init(|| self.read_table(self.recipient_table_node).ok()?)
+            .as_ref()
+    }
+
+    fn attachment_table(&self) -> Option<&Rc<dyn TableContext>> {
+        self.attachment_table
+            .get_or_init(|| self.read_table(self.attachment_table_node).ok()?)
+            .as_ref()
+    }
 }
 
 pub type MessageSubNodes<Pst> = BTreeMap<NodeId, LeafSubNodeTreeEntry<<Pst as PstFile>::BlockId>>;
@@ -371,6 +1081,15 @@ impl UnicodeMessage {
     ) -> io::Result<Rc<Self>> {
         <Self as MessageReadWrite<UnicodePstFile>>::read(store, entry_id, prop_ids)
     }
+
+    fn new_cyclic(inner: MessageInner<UnicodePstFile>, message: &Weak<Self>) -> Self {
+        Self {
+            inner: MessageInner {
+                message: message.clone(),
+                ..inner
+            },
+        }
+    }
 }
 
 impl Message for UnicodeMessage {
@@ -383,11 +1102,33 @@ impl Message for UnicodeMessage {
     }
 
     fn recipient_table(&self) -> Option<&Rc<dyn TableContext>> {
-        self.inner.recipient_table.as_ref()
+        self.inner.recipient_table()
     }
 
     fn attachment_table(&self) -> Option<&Rc<dyn TableContext>> {
-        self.inner.attachment_table.as_ref()
+        self.inner.attachment_table()
+    }
+
+    fn open_attachment(
+        &self,
+        sub_node: AttachmentId,
+        prop_ids: Option<&[u16]>,
+    ) -> io::Result<Rc<dyn Attachment>> {
+        let message = self
+            .inner
+            .message
+            .upgrade()
+            .ok_or(MessagingError::MessageOpenAttachment(
+                "Message has been dropped".to_string(),
+            ))?;
+        Ok(UnicodeAttachment::read(message, sub_node, prop_ids)?)
+    }
+
+    fn attachment_data_block(
+        &self,
+        sub_node: AttachmentId,
+    ) -> io::Result<Option<AttachmentDataBlock>> {
+        <UnicodeAttachment as AttachmentReadWrite<UnicodePstFile>>::read_data_block(self, sub_node)
     }
 }
 
@@ -398,7 +1139,7 @@ impl MessageReadWrite<UnicodePstFile> for UnicodeMessage {
         prop_ids: Option<&[u16]>,
     ) -> io::Result<Rc<Self>> {
         let inner = MessageInner::read(store, entry_id, prop_ids)?;
-        Ok(Rc::new(Self { inner }))
+        Ok(Rc::new_cyclic(|message| Self::new_cyclic(inner, message)))
     }
 
     fn read_embedded(
@@ -407,7 +1148,7 @@ impl MessageReadWrite<UnicodePstFile> for UnicodeMessage {
         prop_ids: Option<&[u16]>,
     ) -> io::Result<Rc<Self>> {
         let inner = MessageInner::read_embedded(store, node, prop_ids)?;
-        Ok(Rc::new(Self { inner }))
+        Ok(Rc::new_cyclic(|message| Self::new_cyclic(inner, message)))
     }
 
     fn pst_store(&self) -> &Rc<UnicodeStore> {
@@ -431,6 +1172,15 @@ impl AnsiMessage {
     ) -> io::Result<Rc<Self>> {
         <Self as MessageReadWrite<AnsiPstFile>>::read(store, entry_id, prop_ids)
     }
+
+    fn new_cyclic(inner: MessageInner<AnsiPstFile>, message: &Weak<Self>) -> Self {
+        Self {
+            inner: MessageInner {
+                message: message.clone(),
+                ..inner
+            },
+        }
+    }
 }
 
 impl Message for AnsiMessage {
@@ -443,11 +1193,33 @@ impl Message for AnsiMessage {
     }
 
     fn recipient_table(&self) -> Option<&Rc<dyn TableContext>> {
-        self.inner.recipient_table.as_ref()
+        self.inner.recipient_table()
     }
 
     fn attachment_table(&self) -> Option<&Rc<dyn TableContext>> {
-        self.inner.attachment_table.as_ref()
+        self.inner.attachment_table()
+    }
+
+    fn open_attachment(
+        &self,
+        sub_node: AttachmentId,
+        prop_ids: Option<&[u16]>,
+    ) -> io::Result<Rc<dyn Attachment>> {
+        let message = self
+            .inner
+            .message
+            .upgrade()
+            .ok_or(MessagingError::MessageOpenAttachment(
+                "Message has been dropped".to_string(),
+            ))?;
+        Ok(AnsiAttachment::read(message, sub_node, prop_ids)?)
+    }
+
+    fn attachment_data_block(
+        &self,
+        sub_node: AttachmentId,
+    ) -> io::Result<Option<AttachmentDataBlock>> {
+        <AnsiAttachment as AttachmentReadWrite<AnsiPstFile>>::read_data_block(self, sub_node)
     }
 }
 
@@ -458,7 +1230,7 @@ impl MessageReadWrite<AnsiPstFile> for AnsiMessage {
         prop_ids: Option<&[u16]>,
     ) -> io::Result<Rc<Self>> {
         let inner = MessageInner::read(store, entry_id, prop_ids)?;
-        Ok(Rc::new(Self { inner }))
+        Ok(Rc::new_cyclic(|message| Self::new_cyclic(inner, message)))
     }
 
     fn read_embedded(
@@ -467,7 +1239,7 @@ impl MessageReadWrite<AnsiPstFile> for AnsiMessage {
         prop_ids: Option<&[u16]>,
     ) -> io::Result<Rc<Self>> {
         let inner = MessageInner::read_embedded(store, node, prop_ids)?;
-        Ok(Rc::new(Self { inner }))
+        Ok(Rc::new_cyclic(|message| Self::new_cyclic(inner, message)))
     }
 
     fn pst_store(&self) -> &Rc<AnsiStore> {
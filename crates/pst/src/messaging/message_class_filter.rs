@@ -0,0 +1,145 @@
+//! Include and exclude patterns for `PidTagMessageClass`, to pick the kinds of items to read or
+//! export, e.g. only mail items for a compliance export.
+//!
+//! [`Folder::contents_rows_matching`](super::folder::Folder::contents_rows_matching) and
+//! [`Store::iter_messages`](super::store::Store::iter_messages) check the message class column of
+//! the contents table, so messages which do not match are skipped without opening them.
+
+use std::io;
+
+use crate::ltp::{
+    code_page::String8Decoder,
+    prop_context::PropertyValue,
+    table_context::{TableContext, TableRowView},
+};
+
+/// `PidTagMessageClass`
+const PID_TAG_MESSAGE_CLASS: u16 = 0x001A;
+
+/// Include and exclude patterns for `PidTagMessageClass`, e.g. include `IPM.Note*` and exclude
+/// `IPM.Appointment*`. The patterns support `*` and `?` wildcards, and are matched without regard
+/// to ASCII case, like message classes are compared in MAPI.
+#[derive(Clone, Debug, Default)]
+pub struct MessageClassFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl MessageClassFilter {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Whether the filter has no patterns, so every message class passes.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// A message class passes if it matches any of the include patterns, or there are none, and
+    /// it does not match any of the exclude patterns.
+    pub fn matches(&self, message_class: &str) -> bool {
+        (self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| glob_matches(pattern, message_class)))
+            && !self
+                .exclude
+                .iter()
+                .any(|pattern| glob_matches(pattern, message_class))
+    }
+
+    /// Check the `PidTagMessageClass` column of a row in a contents table. Messages without a
+    /// message class, or tables without the column, are treated as `IPM`.
+    pub fn matches_row(
+        &self,
+        table: &dyn TableContext,
+        row: &TableRowView<'_>,
+        string_decoder: &String8Decoder,
+    ) -> io::Result<bool> {
+        if self.is_empty() {
+            return Ok(true);
+        }
+
+        let column = table
+            .context()
+            .columns()
+            .iter()
+            .find(|column| column.prop_id() == PID_TAG_MESSAGE_CLASS);
+        let value = match column {
+            Some(column) => match row.column_by_prop_id(PID_TAG_MESSAGE_CLASS)? {
+                Some(value) => Some(table.read_column(&value, column.prop_type())?),
+                None => None,
+            },
+            None => None,
+        };
+        let message_class = match value {
+            Some(PropertyValue::String8(value)) => value.to_string_with_decoder(string_decoder),
+            Some(PropertyValue::Unicode(value)) => value.to_string(),
+            _ => String::from("IPM"),
+        };
+        Ok(self.matches(&message_class))
+    }
+}
+
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<_> = pattern.chars().collect();
+    let text: Vec<_> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c.eq_ignore_ascii_case(&text[t]) => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    t = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_class_filter() {
+        let filter = MessageClassFilter::new()
+            .include("IPM.Note*")
+            .exclude("ipm.note.rules.*");
+        assert!(filter.matches("IPM.Note"));
+        assert!(filter.matches("IPM.Note.SMIME"));
+        assert!(!filter.matches("IPM.Note.Rules.OofTemplate.Microsoft"));
+        assert!(!filter.matches("IPM.Appointment"));
+
+        let filter = MessageClassFilter::new().exclude("IPM.Appointment*");
+        assert!(filter.matches("IPM.Contact"));
+        assert!(!filter.matches("IPM.Appointment"));
+        assert!(glob_matches("IPM.?ote", "IPM.Note"));
+        assert!(MessageClassFilter::new().is_empty());
+    }
+}
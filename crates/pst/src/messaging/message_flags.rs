@@ -0,0 +1,348 @@
+//! Typed sets of the bits in `PidTagMessageFlags` and `PidTagMessageStatus`, see
+//! [MS-OXCMSG] sections 2.2.1.6 and 2.2.1.8.
+//!
+//! Both keep every bit of the raw value, including bits which do not have a name, so converting
+//! back to `i32` with [`MessageFlags::bits`] or [`MessageStatus::bits`] is lossless. [`Display`]
+//! lists the symbolic names of the bits which are set, e.g. `mfRead | mfHasAttach`.
+
+use std::{
+    fmt::{self, Debug, Display},
+    ops::{BitAnd, BitOr, BitOrAssign, Not},
+};
+
+use super::message::{MSGFLAG_ASSOCIATED, MSGFLAG_HASATTACH, MSGFLAG_READ};
+
+/// `PidTagMessageFlags`
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MessageFlags(i32);
+
+impl MessageFlags {
+    /// `mfRead`: The message has been read.
+    pub const READ: Self = Self(MSGFLAG_READ);
+    /// `mfUnmodified`: The message has not been modified since it was sent or received.
+    pub const UNMODIFIED: Self = Self(0x0000_0002);
+    /// `mfSubmitted`: The message has been submitted for sending.
+    pub const SUBMITTED: Self = Self(0x0000_0004);
+    /// `mfUnsent`: The message is still being composed.
+    pub const UNSENT: Self = Self(0x0000_0008);
+    /// `mfHasAttach`: The message has at least one attachment.
+    pub const HAS_ATTACH: Self = Self(MSGFLAG_HASATTACH);
+    /// `mfFromMe`: The sending mailbox is the same as the receiving mailbox.
+    pub const FROM_ME: Self = Self(0x0000_0020);
+    /// `mfFAI`: The message is a folder associated information (FAI) message.
+    pub const ASSOCIATED: Self = Self(MSGFLAG_ASSOCIATED);
+    /// `mfResend`: The message includes a request for a resend operation with a non-delivery
+    /// report.
+    pub const RESEND: Self = Self(0x0000_0080);
+    /// `mfNotifyRead`: A read receipt has been requested and not sent yet.
+    pub const NOTIFY_READ: Self = Self(0x0000_0100);
+    /// `mfNotifyUnread`: A non-read receipt has been requested and not sent yet.
+    pub const NOTIFY_UNREAD: Self = Self(0x0000_0200);
+    /// `mfEverRead`: The message has been read at least once.
+    pub const EVER_READ: Self = Self(0x0000_0400);
+    /// `mfInternet`: The message was received from the Internet.
+    pub const INTERNET: Self = Self(0x0000_2000);
+    /// `mfUntrusted`: The message was received from outside the organization.
+    pub const UNTRUSTED: Self = Self(0x0000_8000);
+
+    const NAMES: &'static [(Self, &'static str)] = &[
+        (Self::READ, "mfRead"),
+        (Self::UNMODIFIED, "mfUnmodified"),
+        (Self::SUBMITTED, "mfSubmitted"),
+        (Self::UNSENT, "mfUnsent"),
+        (Self::HAS_ATTACH, "mfHasAttach"),
+        (Self::FROM_ME, "mfFromMe"),
+        (Self::ASSOCIATED, "mfFAI"),
+        (Self::RESEND, "mfResend"),
+        (Self::NOTIFY_READ, "mfNotifyRead"),
+        (Self::NOTIFY_UNREAD, "mfNotifyUnread"),
+        (Self::EVER_READ, "mfEverRead"),
+        (Self::INTERNET, "mfInternet"),
+        (Self::UNTRUSTED, "mfUntrusted"),
+    ];
+
+    pub const fn from_bits(bits: i32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(&self) -> i32 {
+        self.0
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether every bit in `other` is set.
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    /// The symbolic names of the bits which are set, in the order of their values.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        Self::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+    }
+
+    /// The bits which are set but do not have a symbolic name.
+    pub fn unnamed_bits(&self) -> i32 {
+        Self::NAMES
+            .iter()
+            .fold(self.0, |bits, (flag, _)| bits & !flag.0)
+    }
+}
+
+/// `PidTagMessageStatus`
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MessageStatus(i32);
+
+impl MessageStatus {
+    /// `MSGSTATUS_HIGHLIGHTED`: The message is highlighted in the client.
+    pub const HIGHLIGHTED: Self = Self(0x0000_0001);
+    /// `MSGSTATUS_TAGGED`: The message has been tagged by the client.
+    pub const TAGGED: Self = Self(0x0000_0002);
+    /// `MSGSTATUS_HIDDEN`: The message is hidden from the user.
+    pub const HIDDEN: Self = Self(0x0000_0004);
+    /// `MSGSTATUS_DELMARKED`: The message has been marked for deletion.
+    pub const DELETE_MARKED: Self = Self(0x0000_0008);
+    /// `MSGSTATUS_DRAFT`: The message is a draft.
+    pub const DRAFT: Self = Self(0x0000_0100);
+    /// `MSGSTATUS_ANSWERED`: The message has been replied to.
+    pub const ANSWERED: Self = Self(0x0000_0200);
+    /// `msInConflict`: The message is in conflict.
+    pub const IN_CONFLICT: Self = Self(0x0000_0800);
+    /// `msRemoteDownload`: The message is marked for downloading from the remote message store.
+    pub const REMOTE_DOWNLOAD: Self = Self(0x0000_1000);
+    /// `msRemoteDelete`: The message is marked for deletion at the remote message store.
+    pub const REMOTE_DELETE: Self = Self(0x0000_2000);
+
+    const NAMES: &'static [(Self, &'static str)] = &[
+        (Self::HIGHLIGHTED, "MSGSTATUS_HIGHLIGHTED"),
+        (Self::TAGGED, "MSGSTATUS_TAGGED"),
+        (Self::HIDDEN, "MSGSTATUS_HIDDEN"),
+        (Self::DELETE_MARKED, "MSGSTATUS_DELMARKED"),
+        (Self::DRAFT, "MSGSTATUS_DRAFT"),
+        (Self::ANSWERED, "MSGSTATUS_ANSWERED"),
+        (Self::IN_CONFLICT, "msInConflict"),
+        (Self::REMOTE_DOWNLOAD, "msRemoteDownload"),
+        (Self::REMOTE_DELETE, "msRemoteDelete"),
+    ];
+
+    pub const fn from_bits(bits: i32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(&self) -> i32 {
+        self.0
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether every bit in `other` is set.
+    pub const fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    /// The symbolic names of the bits which are set, in the order of their values.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        Self::NAMES
+            .iter()
+            .filter(|(status, _)| self.contains(*status))
+            .map(|(_, name)| *name)
+    }
+
+    /// The bits which are set but do not have a symbolic name.
+    pub fn unnamed_bits(&self) -> i32 {
+        Self::NAMES
+            .iter()
+            .fold(self.0, |bits, (status, _)| bits & !status.0)
+    }
+}
+
+/// Join the `names` and any `unnamed_bits` in hex with `" | "`, or write `0` if there are none.
+fn write_names<'a>(
+    f: &mut fmt::Formatter<'_>,
+    names: impl Iterator<Item = &'a str>,
+    unnamed_bits: i32,
+) -> fmt::Result {
+    let mut empty = true;
+    for name in names {
+        if !empty {
+            f.write_str(" | ")?;
+        }
+        f.write_str(name)?;
+        empty = false;
+    }
+    if unnamed_bits != 0 {
+        if !empty {
+            f.write_str(" | ")?;
+        }
+        write!(f, "0x{unnamed_bits:08X}")?;
+        empty = false;
+    }
+    if empty {
+        f.write_str("0")?;
+    }
+    Ok(())
+}
+
+impl Display for MessageFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_names(f, self.names(), self.unnamed_bits())
+    }
+}
+
+impl Debug for MessageFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MessageFlags({self})")
+    }
+}
+
+impl Display for MessageStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_names(f, self.names(), self.unnamed_bits())
+    }
+}
+
+impl Debug for MessageStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MessageStatus({self})")
+    }
+}
+
+impl From<i32> for MessageFlags {
+    fn from(value: i32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<MessageFlags> for i32 {
+    fn from(value: MessageFlags) -> Self {
+        value.0
+    }
+}
+
+impl From<i32> for MessageStatus {
+    fn from(value: i32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<MessageStatus> for i32 {
+    fn from(value: MessageStatus) -> Self {
+        value.0
+    }
+}
+
+impl BitOr for MessageFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for MessageFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for MessageFlags {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl Not for MessageFlags {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
+
+impl BitOr for MessageStatus {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for MessageStatus {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for MessageStatus {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        Self(self.0 & rhs.0)
+    }
+}
+
+impl Not for MessageStatus {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        Self(!self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_flags() {
+        let mut flags = MessageFlags::READ | MessageFlags::HAS_ATTACH;
+        assert!(flags.contains(MessageFlags::READ));
+        assert!(!flags.contains(MessageFlags::READ | MessageFlags::UNSENT));
+        assert_eq!(flags.to_string(), "mfRead | mfHasAttach");
+
+        flags.remove(MessageFlags::HAS_ATTACH);
+        flags.insert(MessageFlags::from_bits(0x0001_0000));
+        assert_eq!(flags.to_string(), "mfRead | 0x00010000");
+        assert_eq!(i32::from(flags), 0x0001_0001);
+        assert_eq!(format!("{:?}", MessageFlags::default()), "MessageFlags(0)");
+    }
+
+    #[test]
+    fn test_message_status() {
+        let status = MessageStatus::from(0x0000_1200);
+        assert_eq!(
+            status.names().collect::<Vec<_>>(),
+            ["MSGSTATUS_ANSWERED", "msRemoteDownload"]
+        );
+        assert_eq!(status.unnamed_bits(), 0);
+        assert_eq!(
+            (status & !MessageStatus::ANSWERED).to_string(),
+            "msRemoteDownload"
+        );
+    }
+}
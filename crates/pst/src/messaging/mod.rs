@@ -3,12 +3,31 @@
 use std::io;
 use thiserror::Error;
 
+pub mod annotations;
 pub mod attachment;
+pub mod attachment_sharing;
+pub mod categories;
+pub mod completeness;
+pub mod conversation_index;
+pub mod ews_id;
 pub mod folder;
+pub mod folder_type;
+pub mod from_properties;
+pub mod hierarchy_index;
+pub mod hierarchy_tree;
+pub mod import;
 pub mod message;
+pub mod message_class_filter;
+pub mod message_flags;
 pub mod named_prop;
+pub mod object_id;
+pub mod psetid;
+pub mod scrub;
 pub mod search;
 pub mod store;
+pub mod sync;
+pub mod time_index;
+pub mod views;
 
 pub(crate) mod read_write;
 
@@ -36,6 +55,22 @@ pub enum MessagingError {
     StoreNamedPropertyMap(String),
     #[error("Failed to read search update queue: {0}")]
     StoreSearchUpdateQueue(String),
+    #[error("Failed to read finder folder: {0}")]
+    StoreFinder(String),
+    #[error("Failed to read search activity list: {0}")]
+    StoreSearchActivityList(String),
+    #[error("Failed to verify parent links: {0}")]
+    StoreVerifyParentLinks(String),
+    #[error("Failed to verify table rows: {0}")]
+    StoreVerifyTableRows(String),
+    #[error("Failed to detect string code page: {0}")]
+    StoreStringCodePage(String),
+    #[error("Failed to read top level folders: {0}")]
+    StoreTopLevelFolders(String),
+    #[error("Failed to take property census: {0}")]
+    StorePropertyCensus(String),
+    #[error("Failed to count message classes: {0}")]
+    StoreMessageClassHistogram(String),
     #[error("Missing PidTagDisplayName on store")]
     StoreDisplayNameNotFound,
     #[error("Invalid PidTagDisplayName on store: {0:?}")]
@@ -62,6 +97,10 @@ pub enum MessagingError {
     FolderContentCountNotFound,
     #[error("Invalid PidTagContentCount on folder: {0:?}")]
     InvalidFolderContentCount(crate::ltp::prop_type::PropertyType),
+    #[error("Missing PidTagContainerClass on folder")]
+    FolderContainerClassNotFound,
+    #[error("Invalid PidTagContainerClass on folder: {0:?}")]
+    InvalidFolderContainerClass(crate::ltp::prop_type::PropertyType),
     #[error("Missing PidTagContentUnreadCount on folder")]
     FolderUnreadCountNotFound,
     #[error("Invalid PidTagContentUnreadCount on folder: {0:?}")]
@@ -124,12 +163,16 @@ pub enum MessagingError {
     InvalidAttachmentNodeIdType(crate::ndb::node_id::NodeIdType),
     #[error("Unrecognized PidTagAttachMethod on attachment: 0x{0:08X}")]
     UnknownAttachmentMethod(i32),
+    #[error("Failed to open an attachment: {0}")]
+    MessageOpenAttachment(String),
     #[error("Missing attachment Sub-Node on message: {0:?}")]
     AttachmentSubNodeNotFound(crate::ndb::node_id::NodeId),
     #[error("Missing PidTagAttachDataObject on afEmbeddedMessage attachment")]
     AttachmentMessageObjectDataNotFound,
     #[error("Invalid PidTagAttachDataObject on afEmbeddedMessage attachment: {0:?}")]
     InvalidMessageObjectData(crate::ltp::prop_type::PropertyType),
+    #[error("PidTagAttachMethod is not afEmbeddedMessage: {0:?}")]
+    AttachmentNotEmbeddedMessage(attachment::AttachmentMethod),
     #[error("Missing PidTagAttachDataBinary on afByValue attachment")]
     AttachmentFileBinaryDataNotFound,
     #[error("Invalid PidTagAttachDataBinary on afByValue attachment: {0:?}")]
@@ -176,6 +219,38 @@ pub enum MessagingError {
     InvalidSearchUpdateQueueOffset(u32),
     #[error("Invalid SUD queue size: {0}")]
     InvalidSearchUpdateQueueSize(usize),
+    #[error("Invalid search activity list size: {0}")]
+    InvalidSearchActivityListSize(usize),
+    #[error("Invalid MIME message: {0}")]
+    InvalidMimeMessage(String),
+    #[error("Invalid iCalendar object: {0}")]
+    InvalidCalendarObject(String),
+    #[error("Invalid vCard object: {0}")]
+    InvalidContactObject(String),
+    #[error("Invalid PidNameKeywords on message: {0:?}")]
+    InvalidMessageCategories(crate::ltp::prop_type::PropertyType),
+    #[error("Invalid PidLidTimeZoneStruct on message: {0:?}")]
+    InvalidMessageTimeZone(crate::ltp::prop_type::PropertyType),
+    #[error("Invalid category list: {0}")]
+    InvalidCategoryList(String),
+    #[error("Invalid PidTagChangeKey: {0:?}")]
+    InvalidChangeKey(crate::ltp::prop_type::PropertyType),
+    #[error("Invalid PidTagPredecessorChangeList: {0:?}")]
+    InvalidPredecessorChangeList(crate::ltp::prop_type::PropertyType),
+    #[error("Invalid XID size: {0}")]
+    InvalidXidSize(usize),
+    #[error("Invalid PidTagConversationIndex: {0:?}")]
+    InvalidConversationIndex(crate::ltp::prop_type::PropertyType),
+    #[error("Invalid PidTagConversationIndex size: {0}")]
+    InvalidConversationIndexSize(usize),
+    #[error("Invalid EWS {0:?} ID: {1}")]
+    InvalidEwsId(ews_id::EwsIdFormat, String),
+    #[error("Missing property for field {0}")]
+    PropertyFieldNotFound(&'static str),
+    #[error("Invalid property for field {0}: {1:?}")]
+    InvalidPropertyField(&'static str, crate::ltp::prop_type::PropertyType),
+    #[error("Named property field {0} needs a named property map")]
+    PropertyFieldNeedsNamedProperties(&'static str),
 }
 
 impl From<MessagingError> for io::Error {
@@ -13,9 +13,11 @@ use crate::{
     crc::compute_crc,
     ltp::{
         heap::HeapNode,
-        prop_context::{GuidValue, PropertyContext, PropertyValue},
+        prop_context::{BinaryValue, GuidValue, PropertyContext, PropertyValue},
+        prop_tags::property_tag_name,
         prop_type::PropertyType,
         read_write::*,
+        table_context::TableColumnSchema,
     },
     ndb::{
         block_id::BlockId,
@@ -249,11 +251,56 @@ impl NamedPropReadWrite for NameIdEntry {
     }
 }
 
-#[derive(Default, Debug)]
+/// The name of a named property within its property set, either a numeric `LID` or a string.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum NamedPropertyName {
+    Number(u32),
+    String(String),
+}
+
+impl From<u32> for NamedPropertyName {
+    fn from(value: u32) -> Self {
+        Self::Number(value)
+    }
+}
+
+impl From<&str> for NamedPropertyName {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+/// `PidTagNameidBucketCount` recommended by MS-PST, which Outlook uses for every map.
+pub const DEFAULT_NAMEID_BUCKET_COUNT: u16 = 251;
+
+/// Average number of `NAMEID` records per hash bucket above which
+/// [`NamedPropertyMapProperties::insert`] grows the bucket count. Outlook keeps 251 buckets even
+/// for maps with a few hundred properties, so those are not rehashed by the next insert.
+pub const MAX_NAMEID_BUCKET_LOAD: usize = 4;
+
+/// The bucket properties start at `0x1000`, and stay below the named property range at `0x8000`.
+const MAX_NAMEID_BUCKET_COUNT: u16 = 0x7000;
+
+/// The smallest prime which is at least twice `bucket_count`, up to [`MAX_NAMEID_BUCKET_COUNT`].
+/// Like the default of 251, a prime count spreads the XOR hash values evenly across the buckets.
+fn next_bucket_count(bucket_count: u16) -> u16 {
+    let is_prime = |n: u16| n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| n % d != 0);
+    (bucket_count.saturating_mul(2).max(2)..MAX_NAMEID_BUCKET_COUNT)
+        .find(|n| is_prime(*n))
+        .unwrap_or(MAX_NAMEID_BUCKET_COUNT)
+}
+
+#[derive(Clone, Default, Debug)]
 pub struct NamedPropertyMapProperties {
     properties: BTreeMap<u16, PropertyValue>,
 }
 
+impl From<BTreeMap<u16, PropertyValue>> for NamedPropertyMapProperties {
+    fn from(properties: BTreeMap<u16, PropertyValue>) -> Self {
+        Self { properties }
+    }
+}
+
 impl NamedPropertyMapProperties {
     pub fn get(&self, id: u16) -> Option<&PropertyValue> {
         self.properties.get(&id)
@@ -452,6 +499,246 @@ impl NamedPropertyMapProperties {
             .into()),
         }
     }
+
+    /// Build a display name for a named property from its property set and its name or numeric
+    /// ID, e.g. `PS_PUBLIC_STRINGS:Keywords` or `{00062008-0000-0000-C000-000000000046}:0x8580`.
+    /// Properties in `PS_MAPI` map back to their canonical tagged property name when it is known.
+    pub fn property_name(&self, prop_id: u16) -> io::Result<Option<String>> {
+        let Some(entry) = self
+            .stream_entry()?
+            .into_iter()
+            .find(|entry| entry.prop_id() == prop_id)
+        else {
+            return Ok(None);
+        };
+
+        let guid = match entry.guid() {
+            NamedPropertyGuid::None => String::new(),
+            NamedPropertyGuid::Mapi => {
+                if let NamedPropertyId::Number(id) = entry.id() {
+                    if let Some(name) = u16::try_from(id).ok().and_then(property_tag_name) {
+                        return Ok(Some(name.to_string()));
+                    }
+                }
+                String::from("PS_MAPI")
+            }
+            NamedPropertyGuid::PublicStrings => String::from("PS_PUBLIC_STRINGS"),
+            NamedPropertyGuid::GuidIndex(index) => self
+                .stream_guid()?
+                .get(usize::from(index))
+                .ok_or(MessagingError::NamedPropertyMapGuidIndexOutOfBounds(index))?
+                .to_string(),
+        };
+
+        let name = match entry.id() {
+            NamedPropertyId::Number(id) => format!("0x{id:04X}"),
+            NamedPropertyId::StringOffset(offset) => self.lookup_string(offset)?.to_string(),
+        };
+
+        Ok(Some(format!("{guid}:{name}")))
+    }
+
+    /// Find the property ID which is mapped to a named property, e.g. to read the value of
+    /// `PidLidLocation` from the properties of an appointment.
+    pub fn find(&self, guid: &GuidValue, name: &NamedPropertyName) -> io::Result<Option<u16>> {
+        let Some(guid) = self.guid_index(guid)? else {
+            return Ok(None);
+        };
+        if !self.properties.contains_key(&0x0003) {
+            return Ok(None);
+        }
+
+        for entry in self.stream_entry()? {
+            if entry.guid() != guid {
+                continue;
+            }
+            match (entry.id(), name) {
+                (NamedPropertyId::Number(id), NamedPropertyName::Number(name)) if id == *name => {
+                    return Ok(Some(entry.prop_id()));
+                }
+                (NamedPropertyId::StringOffset(offset), NamedPropertyName::String(name))
+                    if self.lookup_string(offset)?.to_string() == *name =>
+                {
+                    return Ok(Some(entry.prop_id()));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Map a named property to the next free property ID, starting at `0x8000`, and return it.
+    /// If it is already mapped, this returns the existing property ID instead.
+    ///
+    /// The new `NAMEID` record is appended to `PidTagNameidStreamEntry` and to its hash bucket,
+    /// and `guid` and `name` are appended to `PidTagNameidStreamGuid` and
+    /// `PidTagNameidStreamString` as needed. If that leaves more than [`MAX_NAMEID_BUCKET_LOAD`]
+    /// records per bucket, the bucket count is increased with [`Self::rehash`]. The caller is responsible for writing the updated
+    /// properties back to [`NID_NAME_TO_ID_MAP`].
+    pub fn insert(&mut self, guid: &GuidValue, name: &NamedPropertyName) -> io::Result<u16> {
+        if let Some(prop_id) = self.find(guid, name)? {
+            return Ok(prop_id);
+        }
+
+        let bucket_count = self.bucket_count()?;
+        let mut stream_entry =
+            self.binary_stream(0x0003, MessagingError::InvalidNamedPropertyMapStreamEntry)?;
+        let prop_index = stream_entry.len() / 8;
+        let prop_index = u16::try_from(prop_index)
+            .ok()
+            .filter(|index| *index < 0x8000)
+            .ok_or(MessagingError::NamedPropertyMapPropertyIndexOutOfBounds(
+                prop_index as u16,
+            ))?;
+        let prop_index = NamedPropertyIndex::try_from(prop_index)?;
+
+        let guid = match self.guid_index(guid)? {
+            Some(guid) => guid,
+            None => {
+                let mut stream_guid =
+                    self.binary_stream(0x0002, MessagingError::InvalidNamedPropertyMapStreamGuid)?;
+                let index = u16::try_from(stream_guid.len() / 16)
+                    .ok()
+                    .filter(|index| *index < 0x7FFF - 3)
+                    .ok_or(MessagingError::NamedPropertyMapGuidIndexOutOfBounds(
+                        u16::MAX,
+                    ))?;
+                PropertyValue::Guid(*guid).write(&mut stream_guid)?;
+                self.set_binary_stream(0x0002, stream_guid);
+                NamedPropertyGuid::GuidIndex(index)
+            }
+        };
+
+        let (id, hash_id) = match name {
+            NamedPropertyName::Number(id) => {
+                (NamedPropertyId::Number(*id), NamedPropertyId::Number(*id))
+            }
+            NamedPropertyName::String(name) => {
+                let mut stream_string = self
+                    .binary_stream(0x0004, MessagingError::InvalidNamedPropertyMapStreamString)?;
+                stream_string.resize(stream_string.len().next_multiple_of(4), 0);
+                let offset = u32::try_from(stream_string.len())
+                    .map_err(|_| MessagingError::NamedPropertyMapStringEntryOutOfBounds)?;
+                let buffer: Vec<_> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+                let size = u32::try_from(buffer.len())
+                    .map_err(|_| MessagingError::NamedPropertyMapStringEntryOutOfBounds)?;
+                let entry = StringEntry::new(size, buffer)?;
+                entry.write(&mut stream_string)?;
+                self.set_binary_stream(0x0004, stream_string);
+                (
+                    NamedPropertyId::StringOffset(offset),
+                    NamedPropertyId::StringOffset(compute_crc(0, entry.buffer())),
+                )
+            }
+        };
+
+        NameIdEntry::new(id, guid, prop_index).write(&mut stream_entry)?;
+        self.set_binary_stream(0x0003, stream_entry);
+
+        let entry_count = usize::from(u16::from(prop_index)) + 1;
+        if entry_count > usize::from(bucket_count) * MAX_NAMEID_BUCKET_LOAD {
+            self.rehash(next_bucket_count(bucket_count))?;
+            return Ok(prop_index.prop_id());
+        }
+
+        let hash_entry = NameIdEntry::new(hash_id, guid, prop_index);
+        let bucket_prop = 0x1000 + (hash_entry.hash_value() % u32::from(bucket_count)) as u16;
+        let mut hash_bucket =
+            self.binary_stream(bucket_prop, MessagingError::InvalidNamedPropertyMapBucket)?;
+        hash_entry.write(&mut hash_bucket)?;
+        self.set_binary_stream(bucket_prop, hash_bucket);
+
+        Ok(prop_index.prop_id())
+    }
+
+    /// Change `PidTagNameidBucketCount` to `bucket_count`, and rebuild every hash bucket from
+    /// `PidTagNameidStreamEntry`. Buckets which end up empty are still written, since readers
+    /// expect every bucket below the count to exist.
+    ///
+    /// [`Self::insert`] calls this when the map has more than [`MAX_NAMEID_BUCKET_LOAD`] entries
+    /// per bucket, so there is no need to call it directly unless a specific count is wanted.
+    pub fn rehash(&mut self, bucket_count: u16) -> io::Result<()> {
+        if bucket_count == 0 || bucket_count > MAX_NAMEID_BUCKET_COUNT {
+            return Err(
+                MessagingError::NamedPropertyMapBucketCountOutOfBounds(i32::from(bucket_count))
+                    .into(),
+            );
+        }
+
+        let mut buckets = vec![Vec::new(); usize::from(bucket_count)];
+        if self.properties.contains_key(&0x0003) {
+            for entry in self.stream_entry()? {
+                let hash_entry = self.hash_entry(entry)?;
+                let bucket = hash_entry.hash_value() % u32::from(bucket_count);
+                hash_entry.write(&mut buckets[bucket as usize])?;
+            }
+        }
+
+        let previous = self.bucket_count().unwrap_or_default();
+        for offset in bucket_count..previous.max(bucket_count) {
+            self.properties.remove(&(0x1000 + offset));
+        }
+        for (offset, bucket) in (0..bucket_count).zip(buckets) {
+            self.set_binary_stream(0x1000 + offset, bucket);
+        }
+        self.properties
+            .insert(0x0001, PropertyValue::Integer32(i32::from(bucket_count)));
+        Ok(())
+    }
+
+    /// The `wGuid` of a property set, if it is one of the predefined ones or it has been added to
+    /// `PidTagNameidStreamGuid`.
+    fn guid_index(&self, guid: &GuidValue) -> io::Result<Option<NamedPropertyGuid>> {
+        if *guid == PS_MAPI {
+            return Ok(Some(NamedPropertyGuid::Mapi));
+        }
+        if *guid == PS_PUBLIC_STRINGS {
+            return Ok(Some(NamedPropertyGuid::PublicStrings));
+        }
+        if !self.properties.contains_key(&0x0002) {
+            return Ok(None);
+        }
+
+        Ok(self
+            .stream_guid()?
+            .iter()
+            .position(|entry| entry == guid)
+            .and_then(|index| u16::try_from(index).ok())
+            .map(NamedPropertyGuid::GuidIndex))
+    }
+
+    /// Copy one of the binary stream properties, or start a new one if it is missing.
+    fn binary_stream(
+        &self,
+        prop_id: u16,
+        invalid: fn(PropertyType) -> MessagingError,
+    ) -> io::Result<Vec<u8>> {
+        match self.properties.get(&prop_id) {
+            Some(PropertyValue::Binary(value)) => Ok(value.buffer().to_vec()),
+            Some(value) => Err(invalid(PropertyType::from(value)).into()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn set_binary_stream(&mut self, prop_id: u16, buffer: Vec<u8>) {
+        self.properties
+            .insert(prop_id, PropertyValue::Binary(BinaryValue::new(buffer)));
+    }
+
+    /// Fill in the names of the named property columns in a
+    /// [`TableContext::schema`](crate::ltp::table_context::TableContext::schema).
+    pub fn resolve_schema(&self, schema: &mut [TableColumnSchema]) -> io::Result<()> {
+        for column in schema
+            .iter_mut()
+            .filter(|column| column.name().is_none() && column.prop_id() >= 0x8000)
+        {
+            if let Some(name) = self.property_name(column.prop_id())? {
+                column.set_name(name);
+            }
+        }
+        Ok(())
+    }
 }
 
 pub trait NamedPropertyMap {
@@ -505,11 +792,8 @@ where
         let root = header.root();
 
         let properties = {
-            let mut file = pst
-                .reader()
-                .lock()
-                .map_err(|_| MessagingError::FailedToLockFile)?;
-            let file = &mut *file;
+            let mut file = pst.reader();
+            let file = &mut file;
 
             let encoding = header.crypt_method();
             let node_btree = <<Pst as PstFile>::NodeBTree as RootBTreeReadWrite>::read(
@@ -597,3 +881,112 @@ impl NamedPropertyMapReadWrite<AnsiPstFile> for AnsiNamedPropertyMap {
         Ok(Rc::new(Self { inner }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::psetid::PSETID_COMMON;
+
+    #[test]
+    fn test_insert_named_properties() {
+        let mut map = NamedPropertyMapProperties::from(BTreeMap::from([(
+            0x0001,
+            PropertyValue::Integer32(251),
+        )]));
+
+        let number = NamedPropertyName::Number(0x8516);
+        let string = NamedPropertyName::from("X-Custom");
+        let keywords = NamedPropertyName::from("Keywords");
+        assert_eq!(map.find(&PSETID_COMMON, &number).unwrap(), None);
+        assert_eq!(map.insert(&PSETID_COMMON, &number).unwrap(), 0x8000);
+        assert_eq!(map.insert(&PSETID_COMMON, &string).unwrap(), 0x8001);
+        assert_eq!(map.insert(&PS_PUBLIC_STRINGS, &keywords).unwrap(), 0x8002);
+        assert_eq!(map.insert(&PSETID_COMMON, &number).unwrap(), 0x8000);
+        assert_eq!(map.find(&PSETID_COMMON, &string).unwrap(), Some(0x8001));
+        assert_eq!(map.find(&PS_PUBLIC_STRINGS, &string).unwrap(), None);
+
+        assert_eq!(map.stream_guid().unwrap().len(), 1);
+        assert_eq!(map.stream_entry().unwrap().len(), 3);
+        assert_eq!(
+            map.property_name(0x8002).unwrap().as_deref(),
+            Some("PS_PUBLIC_STRINGS:Keywords")
+        );
+
+        let entry = map
+            .stream_entry()
+            .unwrap()
+            .into_iter()
+            .find(|entry| entry.prop_id() == 0x8002)
+            .unwrap();
+        let NamedPropertyId::StringOffset(offset) = entry.id() else {
+            panic!("expected a string name");
+        };
+        assert_eq!(offset % 4, 0);
+        let hash_entry = NameIdEntry::new(
+            NamedPropertyId::StringOffset(compute_crc(
+                0,
+                map.lookup_string(offset).unwrap().buffer(),
+            )),
+            entry.guid(),
+            NamedPropertyIndex::try_from(2).unwrap(),
+        );
+        assert!(map.hash_bucket(&hash_entry).unwrap().contains(&hash_entry));
+    }
+
+    #[test]
+    fn test_bucket_count_growth() {
+        assert_eq!(next_bucket_count(DEFAULT_NAMEID_BUCKET_COUNT), 503);
+        assert_eq!(next_bucket_count(503), 1009);
+
+        let mut map = NamedPropertyMapProperties::from(BTreeMap::from([(
+            0x0001,
+            PropertyValue::Integer32(i32::from(DEFAULT_NAMEID_BUCKET_COUNT)),
+        )]));
+        let limit = usize::from(DEFAULT_NAMEID_BUCKET_COUNT) * MAX_NAMEID_BUCKET_LOAD;
+        let names: Vec<_> = (0..=limit)
+            .map(|index| match index % 2 {
+                0 => NamedPropertyName::Number(0x8000 + index as u32),
+                _ => NamedPropertyName::from(format!("X-Custom-{index}").as_str()),
+            })
+            .collect();
+
+        for (index, name) in names[..limit].iter().enumerate() {
+            assert_eq!(
+                map.insert(&PSETID_COMMON, name).unwrap(),
+                0x8000 + index as u16
+            );
+        }
+        assert_eq!(map.bucket_count().unwrap(), DEFAULT_NAMEID_BUCKET_COUNT);
+
+        assert_eq!(
+            map.insert(&PSETID_COMMON, &names[limit]).unwrap(),
+            0x8000 + limit as u16
+        );
+        assert_eq!(map.bucket_count().unwrap(), 503);
+        assert!((0x1000..0x1000 + 503).all(|prop_id| map.get(prop_id).is_some()));
+        assert!(map.get(0x1000 + 503).is_none());
+
+        let bucketed: usize = (0x1000..0x1000 + 503)
+            .map(|prop_id| match map.get(prop_id) {
+                Some(PropertyValue::Binary(bucket)) => bucket.buffer().len() / 8,
+                _ => 0,
+            })
+            .sum();
+        assert_eq!(bucketed, limit + 1);
+        for entry in map.stream_entry().unwrap() {
+            let hash_entry = map.hash_entry(entry).unwrap();
+            assert!(map.hash_bucket(&hash_entry).unwrap().contains(&hash_entry));
+        }
+        for (index, name) in names.iter().enumerate() {
+            assert_eq!(
+                map.find(&PSETID_COMMON, name).unwrap(),
+                Some(0x8000 + index as u16)
+            );
+        }
+
+        map.rehash(DEFAULT_NAMEID_BUCKET_COUNT).unwrap();
+        assert_eq!(map.bucket_count().unwrap(), DEFAULT_NAMEID_BUCKET_COUNT);
+        assert!(map.get(0x1000 + 251).is_none());
+        assert!(map.rehash(0).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Typed wrappers for the [`NodeId`] of each kind of object in the messaging layer, so the
+//! `nidType` is checked once when the ID is converted instead of by every API which takes it.
+
+use super::{store::EntryId, MessagingError};
+use crate::ndb::node_id::{NodeId, NodeIdType};
+
+/// The [`NodeId`] of a folder, with [`NodeIdType::NormalFolder`] or [`NodeIdType::SearchFolder`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FolderId(NodeId);
+
+impl FolderId {
+    pub fn node_id(&self) -> NodeId {
+        self.0
+    }
+
+    /// Whether this is a search folder, whose contents table lists messages in other folders.
+    pub fn is_search_folder(&self) -> bool {
+        matches!(self.0.id_type(), Ok(NodeIdType::SearchFolder))
+    }
+}
+
+impl TryFrom<NodeId> for FolderId {
+    type Error = MessagingError;
+
+    fn try_from(value: NodeId) -> Result<Self, Self::Error> {
+        match value.id_type()? {
+            NodeIdType::NormalFolder | NodeIdType::SearchFolder => Ok(Self(value)),
+            node_id_type => Err(MessagingError::InvalidFolderEntryIdType(node_id_type)),
+        }
+    }
+}
+
+impl TryFrom<&EntryId> for FolderId {
+    type Error = MessagingError;
+
+    fn try_from(value: &EntryId) -> Result<Self, Self::Error> {
+        Self::try_from(value.node_id())
+    }
+}
+
+impl From<FolderId> for NodeId {
+    fn from(value: FolderId) -> Self {
+        value.0
+    }
+}
+
+/// The [`NodeId`] of a message, with [`NodeIdType::NormalMessage`] or
+/// [`NodeIdType::AssociatedMessage`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MessageId(NodeId);
+
+impl MessageId {
+    pub fn node_id(&self) -> NodeId {
+        self.0
+    }
+
+    /// Whether this is a folder associated information (FAI) message.
+    pub fn is_associated(&self) -> bool {
+        matches!(self.0.id_type(), Ok(NodeIdType::AssociatedMessage))
+    }
+}
+
+impl TryFrom<NodeId> for MessageId {
+    type Error = MessagingError;
+
+    fn try_from(value: NodeId) -> Result<Self, Self::Error> {
+        match value.id_type()? {
+            NodeIdType::NormalMessage | NodeIdType::AssociatedMessage => Ok(Self(value)),
+            node_id_type => Err(MessagingError::InvalidMessageEntryIdType(node_id_type)),
+        }
+    }
+}
+
+impl TryFrom<&EntryId> for MessageId {
+    type Error = MessagingError;
+
+    fn try_from(value: &EntryId) -> Result<Self, Self::Error> {
+        Self::try_from(value.node_id())
+    }
+}
+
+impl From<MessageId> for NodeId {
+    fn from(value: MessageId) -> Self {
+        value.0
+    }
+}
+
+/// The `NID_TYPE_ATTACHMENT` sub-node ID of an attachment in the sub-node tree of a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AttachmentId(NodeId);
+
+impl AttachmentId {
+    pub fn node_id(&self) -> NodeId {
+        self.0
+    }
+}
+
+impl TryFrom<NodeId> for AttachmentId {
+    type Error = MessagingError;
+
+    fn try_from(value: NodeId) -> Result<Self, Self::Error> {
+        match value.id_type()? {
+            NodeIdType::Attachment => Ok(Self(value)),
+            node_id_type => Err(MessagingError::InvalidAttachmentNodeIdType(node_id_type)),
+        }
+    }
+}
+
+impl From<AttachmentId> for NodeId {
+    fn from(value: AttachmentId) -> Self {
+        value.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_id_types() {
+        let folder = NodeId::new(NodeIdType::NormalFolder, 0x400).unwrap();
+        let search_folder = NodeId::new(NodeIdType::SearchFolder, 0x401).unwrap();
+        let message = NodeId::new(NodeIdType::NormalMessage, 0x402).unwrap();
+        let associated = NodeId::new(NodeIdType::AssociatedMessage, 0x403).unwrap();
+        let attachment = NodeId::new(NodeIdType::Attachment, 0x404).unwrap();
+
+        assert_eq!(NodeId::from(FolderId::try_from(folder).unwrap()), folder);
+        assert!(FolderId::try_from(search_folder)
+            .unwrap()
+            .is_search_folder());
+        assert!(matches!(
+            FolderId::try_from(message),
+            Err(MessagingError::InvalidFolderEntryIdType(
+                NodeIdType::NormalMessage
+            ))
+        ));
+
+        assert_eq!(MessageId::try_from(message).unwrap().node_id(), message);
+        assert!(MessageId::try_from(associated).unwrap().is_associated());
+        assert!(matches!(
+            MessageId::try_from(folder),
+            Err(MessagingError::InvalidMessageEntryIdType(
+                NodeIdType::NormalFolder
+            ))
+        ));
+
+        assert_eq!(
+            AttachmentId::try_from(attachment).unwrap().node_id(),
+            attachment
+        );
+        assert!(matches!(
+            AttachmentId::try_from(message),
+            Err(MessagingError::InvalidAttachmentNodeIdType(
+                NodeIdType::NormalMessage
+            ))
+        ));
+    }
+}
@@ -0,0 +1,213 @@
+//! Well-known property sets from
+//! [MS-OXPROPS](https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxprops/cc9d955b-1492-47de-9dce-5bdea80a3323),
+//! which qualify the names of named properties.
+//!
+//! A named property is identified by its property set and either a numeric `LID` or a string.
+//! [`lid`] and [`string`] build that pair, which is the key of
+//! [`MessageBuilder::named_properties`](super::message::MessageBuilder::named_properties) and
+//! what [`NamedPropertyMapProperties::find`](super::named_prop::NamedPropertyMapProperties::find)
+//! maps to a property ID in a particular PST file.
+
+use crate::ltp::prop_context::GuidValue;
+
+use super::named_prop::NamedPropertyName;
+
+pub use super::named_prop::{PS_MAPI, PS_PUBLIC_STRINGS};
+
+/// A named property in a property set, e.g. `PidLidLocation` is `lid(PSETID_APPOINTMENT,
+/// 0x8208)`.
+pub type NamedPropertyLookup = (GuidValue, NamedPropertyName);
+
+/// Look up a named property by its numeric `LID`.
+pub fn lid(guid: GuidValue, lid: u32) -> NamedPropertyLookup {
+    (guid, NamedPropertyName::Number(lid))
+}
+
+/// Look up a named property by its string name.
+pub fn string(guid: GuidValue, name: &str) -> NamedPropertyLookup {
+    (guid, NamedPropertyName::from(name))
+}
+
+/// `PSETID_Appointment`: `{00062002-0000-0000-C000-000000000046}`
+pub const PSETID_APPOINTMENT: GuidValue = GuidValue::new(
+    0x00062002,
+    0x0000,
+    0x0000,
+    [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+);
+
+/// `PSETID_Task`: `{00062003-0000-0000-C000-000000000046}`
+pub const PSETID_TASK: GuidValue = GuidValue::new(
+    0x00062003,
+    0x0000,
+    0x0000,
+    [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+);
+
+/// `PSETID_Address`: `{00062004-0000-0000-C000-000000000046}`
+pub const PSETID_ADDRESS: GuidValue = GuidValue::new(
+    0x00062004,
+    0x0000,
+    0x0000,
+    [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+);
+
+/// `PSETID_Common`: `{00062008-0000-0000-C000-000000000046}`
+pub const PSETID_COMMON: GuidValue = GuidValue::new(
+    0x00062008,
+    0x0000,
+    0x0000,
+    [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+);
+
+/// `PSETID_Log`: `{0006200A-0000-0000-C000-000000000046}`
+pub const PSETID_LOG: GuidValue = GuidValue::new(
+    0x0006200A,
+    0x0000,
+    0x0000,
+    [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+);
+
+/// `PSETID_Note`: `{0006200E-0000-0000-C000-000000000046}`
+pub const PSETID_NOTE: GuidValue = GuidValue::new(
+    0x0006200E,
+    0x0000,
+    0x0000,
+    [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+);
+
+/// `PSETID_Sharing`: `{00062040-0000-0000-C000-000000000046}`
+pub const PSETID_SHARING: GuidValue = GuidValue::new(
+    0x00062040,
+    0x0000,
+    0x0000,
+    [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+);
+
+/// `PSETID_PostRss`: `{00062041-0000-0000-C000-000000000046}`
+pub const PSETID_POST_RSS: GuidValue = GuidValue::new(
+    0x00062041,
+    0x0000,
+    0x0000,
+    [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+);
+
+/// `PS_INTERNET_HEADERS`: `{00020386-0000-0000-C000-000000000046}`
+pub const PS_INTERNET_HEADERS: GuidValue = GuidValue::new(
+    0x00020386,
+    0x0000,
+    0x0000,
+    [0xC0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46],
+);
+
+/// `PSETID_Meeting`: `{6ED8DA90-450B-101B-98DA-00AA003F1305}`
+pub const PSETID_MEETING: GuidValue = GuidValue::new(
+    0x6ED8DA90,
+    0x450B,
+    0x101B,
+    [0x98, 0xDA, 0x00, 0xAA, 0x00, 0x3F, 0x13, 0x05],
+);
+
+/// `PSETID_Messaging`: `{41F28F13-83F4-4114-A584-EEDB5A6B0BFF}`
+pub const PSETID_MESSAGING: GuidValue = GuidValue::new(
+    0x41F28F13,
+    0x83F4,
+    0x4114,
+    [0xA5, 0x84, 0xEE, 0xDB, 0x5A, 0x6B, 0x0B, 0xFF],
+);
+
+/// `PSETID_UnifiedMessaging`: `{4442858E-A9E3-4E80-B900-317A210CC15B}`
+pub const PSETID_UNIFIED_MESSAGING: GuidValue = GuidValue::new(
+    0x4442858E,
+    0xA9E3,
+    0x4E80,
+    [0xB9, 0x00, 0x31, 0x7A, 0x21, 0x0C, 0xC1, 0x5B],
+);
+
+/// `PSETID_AirSync`: `{71035549-0739-4DCB-9163-00F0580DBBDF}`
+pub const PSETID_AIR_SYNC: GuidValue = GuidValue::new(
+    0x71035549,
+    0x0739,
+    0x4DCB,
+    [0x91, 0x63, 0x00, 0xF0, 0x58, 0x0D, 0xBB, 0xDF],
+);
+
+/// `PSETID_XmlExtractedEntities`: `{23239608-685D-4732-9C55-4C95CB4E8E33}`
+pub const PSETID_XML_EXTRACTED_ENTITIES: GuidValue = GuidValue::new(
+    0x23239608,
+    0x685D,
+    0x4732,
+    [0x9C, 0x55, 0x4C, 0x95, 0xCB, 0x4E, 0x8E, 0x33],
+);
+
+/// `PSETID_Attachment`: `{96357F7F-59E1-47D0-99A7-46515C183B54}`
+pub const PSETID_ATTACHMENT: GuidValue = GuidValue::new(
+    0x96357F7F,
+    0x59E1,
+    0x47D0,
+    [0x99, 0xA7, 0x46, 0x51, 0x5C, 0x18, 0x3B, 0x54],
+);
+
+/// `PSETID_CalendarAssistant`: `{11000E07-B51B-40D6-AF21-CAA85EDAB1D0}`
+pub const PSETID_CALENDAR_ASSISTANT: GuidValue = GuidValue::new(
+    0x11000E07,
+    0xB51B,
+    0x40D6,
+    [0xAF, 0x21, 0xCA, 0xA8, 0x5E, 0xDA, 0xB1, 0xD0],
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_property_set_guids() {
+        for (guid, expected) in [
+            (PSETID_APPOINTMENT, "{00062002-0000-0000-C000-000000000046}"),
+            (PSETID_TASK, "{00062003-0000-0000-C000-000000000046}"),
+            (PSETID_ADDRESS, "{00062004-0000-0000-C000-000000000046}"),
+            (PSETID_COMMON, "{00062008-0000-0000-C000-000000000046}"),
+            (PSETID_LOG, "{0006200A-0000-0000-C000-000000000046}"),
+            (PSETID_NOTE, "{0006200E-0000-0000-C000-000000000046}"),
+            (PSETID_SHARING, "{00062040-0000-0000-C000-000000000046}"),
+            (PSETID_POST_RSS, "{00062041-0000-0000-C000-000000000046}"),
+            (
+                PS_INTERNET_HEADERS,
+                "{00020386-0000-0000-C000-000000000046}",
+            ),
+            (PSETID_MEETING, "{6ED8DA90-450B-101B-98DA-00AA003F1305}"),
+            (PSETID_MESSAGING, "{41F28F13-83F4-4114-A584-EEDB5A6B0BFF}"),
+            (
+                PSETID_UNIFIED_MESSAGING,
+                "{4442858E-A9E3-4E80-B900-317A210CC15B}",
+            ),
+            (PSETID_AIR_SYNC, "{71035549-0739-4DCB-9163-00F0580DBBDF}"),
+            (
+                PSETID_XML_EXTRACTED_ENTITIES,
+                "{23239608-685D-4732-9C55-4C95CB4E8E33}",
+            ),
+            (PSETID_ATTACHMENT, "{96357F7F-59E1-47D0-99A7-46515C183B54}"),
+            (
+                PSETID_CALENDAR_ASSISTANT,
+                "{11000E07-B51B-40D6-AF21-CAA85EDAB1D0}",
+            ),
+        ] {
+            assert_eq!(guid.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(
+            lid(PSETID_APPOINTMENT, 0x8208),
+            (PSETID_APPOINTMENT, NamedPropertyName::Number(0x8208))
+        );
+        assert_eq!(
+            string(PS_PUBLIC_STRINGS, "Keywords"),
+            (
+                PS_PUBLIC_STRINGS,
+                NamedPropertyName::String(String::from("Keywords"))
+            )
+        );
+    }
+}
@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+use super::attachment::AttachmentDataBlock;
 use crate::*;
 use std::io::{self, Read, Write};
 
@@ -48,9 +49,13 @@ where
 {
     fn read(
         message: Rc<Pst::Message>,
-        sub_node: NodeId,
+        sub_node: AttachmentId,
         prop_ids: Option<&[u16]>,
     ) -> io::Result<Rc<Self>>;
+    fn read_data_block(
+        message: &Pst::Message,
+        sub_node: AttachmentId,
+    ) -> io::Result<Option<AttachmentDataBlock>>;
 }
 
 pub trait NamedPropReadWrite: Sized {
@@ -75,4 +80,5 @@ where
     Pst: PstFile,
 {
     fn read(store: Rc<Pst::Store>) -> io::Result<Rc<Self>>;
+    fn read_search_folder(store: Rc<Pst::Store>, folder: NodeId) -> io::Result<Rc<Self>>;
 }
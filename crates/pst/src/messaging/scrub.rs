@@ -0,0 +1,171 @@
+//! Remove the attachments or the body of a message while keeping the rest of its properties, e.g.
+//! for compliance workflows which must keep the headers of a message but delete its content.
+//!
+//! [`PstFileLockGuard::strip_attachments`](crate::PstFileLockGuard::strip_attachments) and
+//! [`PstFileLockGuard::replace_body`](crate::PstFileLockGuard::replace_body) release the blocks
+//! which held the old content, and leave a tombstone in named properties of the message which
+//! [`ScrubTombstone::read`] reads back.
+
+use std::{collections::BTreeMap, io};
+
+use super::{message::*, named_prop::PS_PUBLIC_STRINGS};
+use crate::ltp::prop_context::PropertyValue;
+
+/// Name of the `PtypInteger32` property in [`PS_PUBLIC_STRINGS`] which holds the number of
+/// attachments removed from the message.
+pub const SCRUBBED_ATTACHMENTS_NAME: &str = "x-outlook-pst-scrubbed-attachments";
+
+/// Name of the `PtypTime` property in [`PS_PUBLIC_STRINGS`] which holds when the body of the
+/// message was replaced.
+pub const SCRUBBED_BODY_NAME: &str = "x-outlook-pst-scrubbed-body";
+
+/// Properties which hold a copy or a summary of the body, and which are removed when it is
+/// replaced.
+pub(crate) const BODY_PROP_IDS: &[u16] = &[
+    0x0E1F, // PidTagRtfInSync
+    0x1006, // PidTagRtfSyncBodyCrc
+    0x1007, // PidTagRtfSyncBodyCount
+    0x1008, // PidTagRtfSyncBodyTag
+    0x1009, // PidTagRtfCompressed
+    0x1010, // PidTagRtfSyncPrefixCount
+    0x1011, // PidTagRtfSyncTrailingCount
+    0x1013, // PidTagHtml
+    0x1014, // PidTagBodyContentLocation
+    0x1015, // PidTagBodyContentId
+    0x3FD9, // PidTagPreview
+];
+
+/// Properties which [`replace_body_properties`] sets.
+pub(crate) const REPLACED_BODY_PROP_IDS: &[u16] = &[
+    0x1000, // PidTagBody
+    0x1016, // PidTagNativeBody
+];
+
+/// Properties which [`strip_attachment_properties`] changes.
+pub(crate) const STRIPPED_ATTACHMENT_PROP_IDS: &[u16] = &[
+    0x0E07, // PidTagMessageFlags
+    0x0E1B, // PidTagHasAttachments
+];
+
+/// `PidTagNativeBody` value for a plain text body.
+const NATIVE_BODY_PLAIN_TEXT: i32 = 1;
+
+/// The tombstone which scrubbing left on a message.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScrubTombstone {
+    attachments_removed: Option<i32>,
+    body_replaced: Option<i64>,
+}
+
+impl ScrubTombstone {
+    /// Look up the tombstone properties of `message` in the named property map of its store.
+    pub fn read<M: Message + ?Sized>(message: &M) -> io::Result<Self> {
+        let named_property_map = message.store().named_property_map()?;
+        let map = named_property_map.properties();
+        let get = |name: &str| -> io::Result<Option<&PropertyValue>> {
+            let prop_id = map.find(&PS_PUBLIC_STRINGS, &name.into())?;
+            Ok(prop_id.and_then(|prop_id| message.properties().get(prop_id)))
+        };
+
+        let attachments_removed = match get(SCRUBBED_ATTACHMENTS_NAME)? {
+            Some(PropertyValue::Integer32(count)) => Some(*count),
+            _ => None,
+        };
+        let body_replaced = match get(SCRUBBED_BODY_NAME)? {
+            Some(PropertyValue::Time(time)) => Some(*time),
+            _ => None,
+        };
+        Ok(Self {
+            attachments_removed,
+            body_replaced,
+        })
+    }
+
+    /// The number of attachments removed by
+    /// [`PstFileLockGuard::strip_attachments`](crate::PstFileLockGuard::strip_attachments).
+    pub fn attachments_removed(&self) -> Option<i32> {
+        self.attachments_removed
+    }
+
+    /// When the body was replaced by
+    /// [`PstFileLockGuard::replace_body`](crate::PstFileLockGuard::replace_body), as a `FILETIME`.
+    pub fn body_replaced(&self) -> Option<i64> {
+        self.body_replaced
+    }
+
+    pub fn is_scrubbed(&self) -> bool {
+        self.attachments_removed.is_some() || self.body_replaced.is_some()
+    }
+}
+
+/// Clear `mfHasAttach` in `PidTagMessageFlags` and `PidTagHasAttachments` after the attachments
+/// have been removed.
+pub(crate) fn strip_attachment_properties(properties: &mut BTreeMap<u16, PropertyValue>) {
+    if let Some(PropertyValue::Integer32(flags)) = properties.get_mut(&0x0E07) {
+        *flags &= !MSGFLAG_HASATTACH;
+    }
+    if properties.contains_key(&0x0E1B) {
+        properties.insert(0x0E1B, PropertyValue::Boolean(false));
+    }
+}
+
+/// Replace `PidTagBody` with `text`, and remove the other formats of the body and the preview.
+pub(crate) fn replace_body_properties(properties: &mut BTreeMap<u16, PropertyValue>, text: &str) {
+    for prop_id in BODY_PROP_IDS {
+        properties.remove(prop_id);
+    }
+    properties.insert(0x1000, unicode_value(text));
+    if properties.contains_key(&0x1016) {
+        properties.insert(0x1016, PropertyValue::Integer32(NATIVE_BODY_PLAIN_TEXT));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ltp::prop_context::BinaryValue;
+
+    #[test]
+    fn test_scrub_properties() {
+        let mut properties = BTreeMap::from([
+            (0x0037, PropertyValue::Integer32(0)),
+            (
+                0x0E07,
+                PropertyValue::Integer32(MSGFLAG_READ | MSGFLAG_HASATTACH),
+            ),
+            (0x0E1B, PropertyValue::Boolean(true)),
+            (
+                0x1009,
+                PropertyValue::Binary(BinaryValue::new(vec![1, 2, 3])),
+            ),
+            (0x1013, PropertyValue::Binary(BinaryValue::new(vec![4, 5]))),
+            (0x1016, PropertyValue::Integer32(3)),
+            (0x3FD9, PropertyValue::Integer32(0)),
+        ]);
+
+        strip_attachment_properties(&mut properties);
+        assert!(matches!(
+            properties.get(&0x0E07),
+            Some(PropertyValue::Integer32(MSGFLAG_READ))
+        ));
+        assert!(matches!(
+            properties.get(&0x0E1B),
+            Some(PropertyValue::Boolean(false))
+        ));
+
+        replace_body_properties(&mut properties, "[removed]");
+        assert_eq!(
+            properties.keys().copied().collect::<Vec<_>>(),
+            [0x0037, 0x0E07, 0x0E1B, 0x1000, 0x1016]
+        );
+        assert!(
+            matches!(properties.get(&0x1000), Some(PropertyValue::Unicode(body)) if body.to_string() == "[removed]")
+        );
+        assert!(matches!(
+            properties.get(&0x1016),
+            Some(PropertyValue::Integer32(NATIVE_BODY_PLAIN_TEXT))
+        ));
+
+        assert!(!ScrubTombstone::default().is_scrubbed());
+    }
+}
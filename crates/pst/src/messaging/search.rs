@@ -2,19 +2,25 @@
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::{
+    collections::BTreeMap,
     io::{self, Cursor, Read, Write},
     marker::PhantomData,
     rc::Rc,
 };
 
-use super::{read_write::*, *};
+use super::{folder::Folder, read_write::*, *};
 use crate::{
+    ltp::{
+        prop_context::{read_property_context, PropertyValue},
+        table_context::TableContext,
+    },
     messaging::store::{AnsiStore, UnicodeStore},
     ndb::{
         block::{DataTree, IntermediateTreeBlock},
         block_id::BlockId,
         header::Header,
-        node_id::{NodeId, NID_SEARCH_MANAGEMENT_QUEUE},
+        node_data::NodeData,
+        node_id::{NodeId, NodeIdType, NID_SEARCH_MANAGEMENT_QUEUE},
         page::{BTreePage, NodeBTreeEntry, RootBTree},
         read_write::*,
         root::Root,
@@ -442,6 +448,127 @@ const SEARCH_UPDATE_SIZE: u32 = 20;
 
 pub trait SearchUpdateQueue {
     fn updates(&self) -> &[SearchUpdate];
+    /// Number of updates at the start of the search management queue which were already
+    /// processed, and are not included in [`SearchUpdateQueue::updates`]. The queues of search
+    /// folders do not track this, so it is always 0 for them.
+    fn processed(&self) -> usize;
+}
+
+/// The `NID_SEARCH_ACTIVITY_LIST` node, which lists the search folders whose results are kept up
+/// to date as the store changes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchActivityList {
+    search_folders: Vec<NodeId>,
+}
+
+impl SearchActivityList {
+    /// Read the array of 4-byte NIDs in the node data.
+    pub fn parse(data: &[u8]) -> Result<Self, MessagingError> {
+        if data.len() % 4 != 0 {
+            return Err(MessagingError::InvalidSearchActivityListSize(data.len()));
+        }
+        let search_folders = data
+            .chunks_exact(4)
+            .map(|nid| NodeId::from(u32::from_le_bytes([nid[0], nid[1], nid[2], nid[3]])))
+            .collect();
+        Ok(Self { search_folders })
+    }
+
+    pub fn search_folders(&self) -> &[NodeId] {
+        &self.search_folders
+    }
+
+    pub fn contains(&self, search_folder: NodeId) -> bool {
+        self.search_folders.contains(&search_folder)
+    }
+}
+
+/// The `NID_TYPE_SEARCH_CRITERIA_OBJECT` node of a search folder. The format of this node is not
+/// documented, but Outlook writes it as a property context, so its properties are decoded when
+/// the data has the heap signature of one.
+#[derive(Clone, Debug)]
+pub struct SearchCriteria {
+    data: Vec<u8>,
+    properties: Option<BTreeMap<u16, PropertyValue>>,
+}
+
+impl SearchCriteria {
+    pub fn parse(data: Vec<u8>) -> Self {
+        let properties = read_property_context(&NodeData::new(vec![data.clone()])).ok();
+        Self { data, properties }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The decoded property context, or `None` if the node is not a property context.
+    pub fn properties(&self) -> Option<&BTreeMap<u16, PropertyValue>> {
+        self.properties.as_ref()
+    }
+
+    pub fn get(&self, prop_id: u16) -> Option<&PropertyValue> {
+        self.properties.as_ref()?.get(&prop_id)
+    }
+}
+
+/// A search [`Folder`] along with the nodes which track its search results.
+///
+/// ### See also
+/// [Search Folder Objects](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/1c3a5ebd-2bc2-45be-8ddb-6ab7f18d3c5c)
+pub struct SearchFolder {
+    pub(crate) folder: Rc<dyn Folder>,
+    pub(crate) update_queue: Rc<dyn SearchUpdateQueue>,
+    pub(crate) criteria: Option<SearchCriteria>,
+    pub(crate) contents_table: Option<Rc<dyn TableContext>>,
+    pub(crate) is_active: bool,
+}
+
+impl SearchFolder {
+    pub fn folder(&self) -> &Rc<dyn Folder> {
+        &self.folder
+    }
+
+    /// The `NID_TYPE_SEARCH_UPDATE_QUEUE` node of pending changes for this search folder.
+    pub fn update_queue(&self) -> &Rc<dyn SearchUpdateQueue> {
+        &self.update_queue
+    }
+
+    /// The raw contents of the `NID_TYPE_SEARCH_CRITERIA_OBJECT` node, see
+    /// [`SearchFolder::criteria_object`] for its properties.
+    pub fn criteria(&self) -> Option<&[u8]> {
+        self.criteria.as_ref().map(SearchCriteria::data)
+    }
+
+    pub fn criteria_object(&self) -> Option<&SearchCriteria> {
+        self.criteria.as_ref()
+    }
+
+    /// Whether the folder is in the [`SearchActivityList`] of the store.
+    pub fn is_active(&self) -> bool {
+        self.is_active
+    }
+
+    /// The `NID_TYPE_SEARCH_CONTENTS_TABLE` node listing the messages which match the search.
+    pub fn contents_table(&self) -> Option<&Rc<dyn TableContext>> {
+        self.contents_table.as_ref()
+    }
+}
+
+/// The Finder folder referenced by `PidTagFinderEntryId`, and every [`SearchFolder`] beneath it.
+pub struct SearchRoot {
+    pub(crate) folder: Rc<dyn Folder>,
+    pub(crate) search_folders: Vec<SearchFolder>,
+}
+
+impl SearchRoot {
+    pub fn folder(&self) -> &Rc<dyn Folder> {
+        &self.folder
+    }
+
+    pub fn search_folders(&self) -> impl Iterator<Item = &SearchFolder> {
+        self.search_folders.iter()
+    }
 }
 
 struct SearchUpdateQueueInner<Pst>
@@ -449,6 +576,7 @@ where
     Pst: PstFile,
 {
     updates: Vec<SearchUpdate>,
+    processed: usize,
     _phantom: PhantomData<Pst>,
 }
 
@@ -487,29 +615,38 @@ where
     <Pst as PstFile>::DataBlock: BlockReadWrite + Clone,
     <Pst as PstFile>::Store: StoreReadWrite<Pst>,
 {
-    fn read(store: Rc<Pst::Store>) -> io::Result<Self> {
+    fn read(store: Rc<Pst::Store>, node_id: NodeId) -> io::Result<Self> {
         let pst = store.pst();
         let header = pst.header();
         let encoding = header.crypt_method();
         let root = header.root();
 
-        let mut file = pst
-            .reader()
-            .lock()
-            .map_err(|_| MessagingError::FailedToLockFile)?;
-        let file = &mut *file;
+        let mut file = pst.reader();
+        let file = &mut file;
 
         let node_btree = <Pst as PstFile>::NodeBTree::read(file, *root.node_btree())?;
         let block_btree = <Pst as PstFile>::BlockBTree::read(file, *root.block_btree())?;
 
         let mut page_cache = Default::default();
-        let node = node_btree.find_entry(
-            file,
-            u32::from(NID_SEARCH_MANAGEMENT_QUEUE).into(),
-            &mut page_cache,
-        )?;
+        let node = match node_btree.find_entry(file, u32::from(node_id).into(), &mut page_cache) {
+            Ok(node) => node,
+            Err(_) if node_id != NID_SEARCH_MANAGEMENT_QUEUE => {
+                return Ok(Self {
+                    updates: Default::default(),
+                    processed: 0,
+                    _phantom: PhantomData,
+                });
+            }
+            Err(err) => return Err(err),
+        };
 
-        let start = node.parent().map(u32::from).unwrap_or_default();
+        // Only the search management queue keeps track of how much of the queue has been
+        // processed in nidParent, the search folder update queues point to their folder.
+        let start = if node_id == NID_SEARCH_MANAGEMENT_QUEUE {
+            node.parent().map(u32::from).unwrap_or_default()
+        } else {
+            0
+        };
         if start % SEARCH_UPDATE_SIZE != 0 {
             return Err(MessagingError::InvalidSearchUpdateQueueOffset(start).into());
         }
@@ -520,6 +657,7 @@ where
         if u64::from(key) == 0 {
             return Ok(Self {
                 updates: Default::default(),
+                processed: 0,
                 _phantom: PhantomData,
             });
         }
@@ -557,6 +695,7 @@ where
 
         Ok(Self {
             updates,
+            processed: start / SEARCH_UPDATE_SIZE as usize,
             _phantom: PhantomData,
         })
     }
@@ -569,11 +708,21 @@ impl SearchUpdateQueue for UnicodeSearchUpdateQueue {
     fn updates(&self) -> &[SearchUpdate] {
         &self.inner.updates
     }
+
+    fn processed(&self) -> usize {
+        self.inner.processed
+    }
 }
 
 impl SearchUpdateQueueReadWrite<UnicodePstFile> for UnicodeSearchUpdateQueue {
     fn read(store: Rc<UnicodeStore>) -> io::Result<Rc<Self>> {
-        let inner = SearchUpdateQueueInner::read(store)?;
+        let inner = SearchUpdateQueueInner::read(store, NID_SEARCH_MANAGEMENT_QUEUE)?;
+        Ok(Rc::new(Self { inner }))
+    }
+
+    fn read_search_folder(store: Rc<UnicodeStore>, folder: NodeId) -> io::Result<Rc<Self>> {
+        let node_id = NodeId::new(NodeIdType::SearchUpdateQueue, folder.index())?;
+        let inner = SearchUpdateQueueInner::read(store, node_id)?;
         Ok(Rc::new(Self { inner }))
     }
 }
@@ -586,11 +735,71 @@ impl SearchUpdateQueue for AnsiSearchUpdateQueue {
     fn updates(&self) -> &[SearchUpdate] {
         &self.inner.updates
     }
+
+    fn processed(&self) -> usize {
+        self.inner.processed
+    }
 }
 
 impl SearchUpdateQueueReadWrite<AnsiPstFile> for AnsiSearchUpdateQueue {
     fn read(store: Rc<AnsiStore>) -> io::Result<Rc<Self>> {
-        let inner = SearchUpdateQueueInner::read(store)?;
+        let inner = SearchUpdateQueueInner::read(store, NID_SEARCH_MANAGEMENT_QUEUE)?;
+        Ok(Rc::new(Self { inner }))
+    }
+
+    fn read_search_folder(store: Rc<AnsiStore>, folder: NodeId) -> io::Result<Rc<Self>> {
+        let node_id = NodeId::new(NodeIdType::SearchUpdateQueue, folder.index())?;
+        let inner = SearchUpdateQueueInner::read(store, node_id)?;
         Ok(Rc::new(Self { inner }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::open_store;
+    use std::path::Path;
+
+    #[test]
+    fn test_search_activity_list() {
+        let list = SearchActivityList::parse(&[0x23, 0x22, 0, 0, 0x43, 0x22, 0, 0]).unwrap();
+        assert_eq!(
+            list.search_folders(),
+            [NodeId::from(0x2223), NodeId::from(0x2243)]
+        );
+        assert!(matches!(
+            SearchActivityList::parse(&[0x23, 0x22, 0]),
+            Err(MessagingError::InvalidSearchActivityListSize(3))
+        ));
+    }
+
+    #[test]
+    fn test_search_criteria() {
+        // NID_TYPE_SEARCH_CRITERIA_OBJECT of the search folder in Empty.pst.
+        let data = vec![
+            0x1C, 0x00, 0xEC, 0xBC, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xB5, 0x02,
+            0x06, 0x00, 0x40, 0x00, 0x00, 0x00, 0x0B, 0x66, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x02, 0x00, 0x00, 0x00, 0x0C, 0x00, 0x14, 0x00, 0x1C, 0x00,
+        ];
+        let criteria = SearchCriteria::parse(data);
+        assert!(matches!(
+            criteria.get(0x660B),
+            Some(PropertyValue::Integer32(0))
+        ));
+        assert_eq!(criteria.properties().map(BTreeMap::len), Some(1));
+
+        let criteria = SearchCriteria::parse(vec![0; 8]);
+        assert!(criteria.properties().is_none());
+        assert_eq!(criteria.data(), [0; 8]);
+    }
+
+    #[test]
+    fn test_search_activity_list_in_store() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst");
+        let store = open_store(path).unwrap();
+
+        let activity_list = store.search_activity_list().unwrap();
+        assert_eq!(activity_list.search_folders(), [NodeId::from(0x2223)]);
+        assert_eq!(store.search_update_queue().unwrap().processed(), 0);
+    }
+}
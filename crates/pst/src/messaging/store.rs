@@ -2,30 +2,45 @@
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::{
-    cell::OnceCell,
-    collections::BTreeMap,
+    cell::{Cell, RefCell},
+    cmp,
+    collections::{BTreeMap, BTreeSet},
     fmt::Debug,
     io::{self, Read, Write},
+    iter,
     rc::{Rc, Weak},
+    sync::Arc,
 };
 
-use super::{folder::*, message::*, read_write::*, *};
+use super::{
+    categories::CategoryList, folder::*, folder_type::MessageClassHistogram,
+    hierarchy_tree::FolderTreeNode, message::*, message_class_filter::MessageClassFilter,
+    read_write::*, search::*, *,
+};
 use crate::{
     ltp::{
-        heap::HeapNode,
-        prop_context::{PropertyContext, PropertyValue},
+        code_page::*,
+        heap::{HeapIdMode, HeapNode},
+        prop_context::{
+            BinaryValue, MaybeDecoded, PropertyContext, PropertyValue, String8Value, UnicodeValue,
+        },
         prop_type::PropertyType,
         read_write::*,
-        table_context::TableContext,
+        table_context::{RowVersion, TableContext, LTP_ROW_ID_PROP_ID, LTP_ROW_VERSION_PROP_ID},
     },
     ndb::{
         block_id::BlockId,
-        header::Header,
-        node_id::{NodeId, NodeIdType, NID_MESSAGE_STORE, NID_ROOT_FOLDER},
+        header::{Header, HeaderFieldMode, NdbVersion},
+        node_id::{
+            NodeId, NodeIdType, NID_MESSAGE_STORE, NID_ROOT_FOLDER, NID_SEARCH_ACTIVITY_LIST,
+            SPECIAL_NODE_IDS,
+        },
+        optional_btree_entry,
         page::*,
         read_write::*,
         root::Root,
     },
+    positioned::ReadAt,
     *,
 };
 
@@ -159,9 +174,11 @@ impl TryFrom<&EntryId> for Vec<u8> {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct StoreProperties {
     properties: BTreeMap<u16, PropertyValue>,
+    string_decoder: RefCell<String8Decoder>,
+    version: NdbVersion,
 }
 
 impl StoreProperties {
@@ -169,6 +186,16 @@ impl StoreProperties {
         self.properties.get(&id)
     }
 
+    /// The code page used to decode `PtypString8` values, see [`Store::string_code_page`].
+    pub fn string_code_page(&self) -> u16 {
+        self.string_decoder.borrow().code_page()
+    }
+
+    /// The code page and decoder for `PtypString8` values, see [`Store::string_decoder`].
+    pub fn string_decoder(&self) -> String8Decoder {
+        self.string_decoder.borrow().clone()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&u16, &PropertyValue)> {
         self.properties.iter()
     }
@@ -192,6 +219,12 @@ impl StoreProperties {
         Ok(EntryId::new(record_key, node_id))
     }
 
+    /// The [`EntryId`] of [`NID_ROOT_FOLDER`], which is the parent of the IPM subtree and the
+    /// other top level folders.
+    pub fn root_folder_entry_id(&self) -> io::Result<EntryId> {
+        self.make_entry_id(NID_ROOT_FOLDER)
+    }
+
     pub fn matches_record_key(&self, entry_id: &EntryId) -> io::Result<bool> {
         let store_record_key = self.record_key()?;
         Ok(store_record_key == entry_id.record_key)
@@ -204,7 +237,9 @@ impl StoreProperties {
             .ok_or(MessagingError::StoreDisplayNameNotFound)?;
 
         match display_name {
-            PropertyValue::String8(value) => Ok(value.to_string()),
+            PropertyValue::String8(value) => {
+                Ok(value.to_string_with_decoder(&self.string_decoder.borrow()))
+            }
             PropertyValue::Unicode(value) => Ok(value.to_string()),
             invalid => {
                 Err(MessagingError::InvalidStoreDisplayName(PropertyType::from(invalid)).into())
@@ -256,11 +291,538 @@ impl StoreProperties {
     }
 }
 
+/// Modified copy of the [`StoreProperties`], which can be written back to the PST file with
+/// [`PstFileLockGuard::update_store_properties`].
+#[derive(Clone, Debug)]
+pub struct StorePropertiesMut {
+    properties: StoreProperties,
+}
+
+impl StorePropertiesMut {
+    pub fn properties(&self) -> &StoreProperties {
+        &self.properties
+    }
+
+    /// Set `PidTagDisplayName`. ANSI stores keep it as a `PtypString8` in the
+    /// [`StoreProperties::string_code_page`], like the other strings in the file.
+    pub fn set_display_name(&mut self, display_name: &str) {
+        let value = match self.properties.version {
+            NdbVersion::Ansi => PropertyValue::String8(String8Value::new(encode_string8(
+                display_name,
+                self.properties.string_code_page(),
+            ))),
+            NdbVersion::Unicode => {
+                PropertyValue::Unicode(UnicodeValue::new(display_name.encode_utf16().collect()))
+            }
+        };
+        self.properties.properties.insert(0x3001, value);
+    }
+
+    pub fn set_ipm_sub_tree_entry_id(&mut self, entry_id: &EntryId) -> io::Result<()> {
+        self.set_folder_entry_id(0x35E0, entry_id)
+    }
+
+    pub fn set_ipm_wastebasket_entry_id(&mut self, entry_id: &EntryId) -> io::Result<()> {
+        self.set_folder_entry_id(0x35E3, entry_id)
+    }
+
+    pub fn set_finder_entry_id(&mut self, entry_id: &EntryId) -> io::Result<()> {
+        self.set_folder_entry_id(0x35E7, entry_id)
+    }
+
+    fn set_folder_entry_id(&mut self, prop_id: u16, entry_id: &EntryId) -> io::Result<()> {
+        if !self.properties.matches_record_key(entry_id)? {
+            return Err(MessagingError::EntryIdWrongStore.into());
+        }
+
+        let node_id_type = entry_id.node_id().id_type()?;
+        if node_id_type != NodeIdType::NormalFolder {
+            return Err(MessagingError::InvalidFolderEntryIdType(node_id_type).into());
+        }
+
+        let value = BinaryValue::new(Vec::<u8>::try_from(entry_id)?);
+        self.properties
+            .properties
+            .insert(prop_id, PropertyValue::Binary(value));
+        Ok(())
+    }
+}
+
+impl From<&StoreProperties> for StorePropertiesMut {
+    fn from(value: &StoreProperties) -> Self {
+        Self {
+            properties: value.clone(),
+        }
+    }
+}
+
+/// A node whose `nidParent` in the NBT does not match the folder whose hierarchy, contents, or
+/// associated contents table references it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParentLinkMismatch {
+    node: NodeId,
+    expected_parent: NodeId,
+    actual_parent: Option<NodeId>,
+}
+
+impl ParentLinkMismatch {
+    pub fn node(&self) -> NodeId {
+        self.node
+    }
+
+    pub fn expected_parent(&self) -> NodeId {
+        self.expected_parent
+    }
+
+    pub fn actual_parent(&self) -> Option<NodeId> {
+        self.actual_parent
+    }
+}
+
+/// Why the `dwRowID` of a row in a hierarchy, contents, or associated contents table does not
+/// resolve to a node which can be opened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnresolvedRowReason {
+    /// The row ID is `0`.
+    NullRowId,
+    /// The `nidType` of the row ID is not one which belongs in the table, e.g. a folder in a
+    /// contents table.
+    InvalidNodeType,
+    /// There is no node with the row ID in the NBT.
+    MissingNode,
+}
+
+impl UnresolvedRowReason {
+    /// Check the `dwRowID` of a row in a table of `table_type`. `exists` looks up a node in the
+    /// NBT, and is only called for row IDs which have the right `nidType`.
+    pub(crate) fn classify(
+        row: u32,
+        table_type: NodeIdType,
+        exists: impl FnOnce(NodeId) -> io::Result<bool>,
+    ) -> io::Result<Option<Self>> {
+        if row == 0 {
+            return Ok(Some(Self::NullRowId));
+        }
+
+        let node = NodeId::from(row);
+        let valid_type = matches!(
+            (table_type, node.id_type()),
+            (
+                NodeIdType::HierarchyTable,
+                Ok(NodeIdType::NormalFolder | NodeIdType::SearchFolder)
+            ) | (
+                NodeIdType::ContentsTable | NodeIdType::SearchContentsTable,
+                Ok(NodeIdType::NormalMessage)
+            ) | (
+                NodeIdType::AssociatedContentsTable,
+                Ok(NodeIdType::AssociatedMessage)
+            )
+        );
+        if !valid_type {
+            return Ok(Some(Self::InvalidNodeType));
+        }
+
+        Ok((!exists(node)?).then_some(Self::MissingNode))
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::NullRowId => "dwRowID is 0",
+            Self::InvalidNodeType => "dwRowID has the wrong nidType for the table",
+            Self::MissingNode => "dwRowID is not in the NBT",
+        }
+    }
+}
+
+/// A row in the hierarchy, contents, or associated contents table of a folder which is skipped by
+/// [`Store::verify_table_rows`] and the counts written by
+/// [`PstFileLockGuard::repair_folder_counts`](crate::PstFileLockGuard::repair_folder_counts),
+/// because its `dwRowID` does not resolve to a node.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnresolvedTableRow {
+    pub(crate) folder: NodeId,
+    pub(crate) table_type: NodeIdType,
+    pub(crate) row: u32,
+    pub(crate) reason: UnresolvedRowReason,
+}
+
+impl UnresolvedTableRow {
+    pub fn folder(&self) -> NodeId {
+        self.folder
+    }
+
+    /// [`NodeIdType::HierarchyTable`], [`NodeIdType::ContentsTable`], or
+    /// [`NodeIdType::AssociatedContentsTable`].
+    pub fn table_type(&self) -> NodeIdType {
+        self.table_type
+    }
+
+    /// The `dwRowID`, which is not a valid [`NodeId`] for some of the [`UnresolvedRowReason`]
+    /// values.
+    pub fn row(&self) -> u32 {
+        self.row
+    }
+
+    pub fn reason(&self) -> UnresolvedRowReason {
+        self.reason
+    }
+}
+
+/// A `PidTagMessageSizeExtended` which was rewritten by
+/// [`PstFileLockGuard::recompute_sizes`](crate::PstFileLockGuard::recompute_sizes).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SizeCorrection {
+    pub(crate) node: NodeId,
+    pub(crate) previous: Option<i64>,
+    pub(crate) size: i64,
+}
+
+impl SizeCorrection {
+    /// The folder, or [`NID_MESSAGE_STORE`].
+    pub fn node(&self) -> NodeId {
+        self.node
+    }
+
+    /// The size before it was recalculated, or `None` if it was missing.
+    pub fn previous(&self) -> Option<i64> {
+        self.previous
+    }
+
+    pub fn size(&self) -> i64 {
+        self.size
+    }
+}
+
+/// A folder property which counts the objects in one of its tables.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FolderCounter {
+    /// `PidTagContentCount`
+    ContentCount,
+    /// `PidTagContentUnreadCount`
+    ContentUnreadCount,
+    /// `PidTagAssociatedContentCount`
+    AssociatedContentCount,
+    /// `PidTagSubfolders`, which is a `PtypBoolean` rather than a count.
+    Subfolders,
+}
+
+impl FolderCounter {
+    pub fn prop_id(&self) -> u16 {
+        match self {
+            Self::ContentCount => 0x3602,
+            Self::ContentUnreadCount => 0x3603,
+            Self::AssociatedContentCount => 0x3617,
+            Self::Subfolders => 0x360A,
+        }
+    }
+
+    /// Whether every folder should have this property. `PidTagAssociatedContentCount` is
+    /// optional, and treated as `0` if it is missing.
+    pub fn is_required(&self) -> bool {
+        !matches!(self, Self::AssociatedContentCount)
+    }
+
+    pub(crate) fn property_value(&self, value: i32) -> PropertyValue {
+        match self {
+            Self::Subfolders => PropertyValue::Boolean(value != 0),
+            _ => PropertyValue::Integer32(value),
+        }
+    }
+}
+
+/// Where a [`CountCorrection`] was written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CountLocation {
+    /// The property context of the folder.
+    FolderProperties,
+    /// The row for the folder in the hierarchy table of its parent.
+    HierarchyTable,
+}
+
+/// A [`FolderCounter`] which was rewritten by
+/// [`PstFileLockGuard::repair_folder_counts`](crate::PstFileLockGuard::repair_folder_counts).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CountCorrection {
+    pub(crate) folder: NodeId,
+    pub(crate) counter: FolderCounter,
+    pub(crate) location: CountLocation,
+    pub(crate) previous: Option<i32>,
+    pub(crate) value: i32,
+}
+
+impl CountCorrection {
+    pub fn folder(&self) -> NodeId {
+        self.folder
+    }
+
+    pub fn counter(&self) -> FolderCounter {
+        self.counter
+    }
+
+    pub fn location(&self) -> CountLocation {
+        self.location
+    }
+
+    /// The value before it was recounted, or `None` if it was missing. `PidTagSubfolders` is
+    /// `0` or `1`.
+    pub fn previous(&self) -> Option<i32> {
+        self.previous
+    }
+
+    pub fn value(&self) -> i32 {
+        self.value
+    }
+}
+
+/// Special folders which are identified by an entry ID in the [`StoreProperties`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpecialFolder {
+    /// `PidTagIpmSubTreeEntryId`: The top of the folders shown in Outlook.
+    IpmSubtree,
+    /// `PidTagIpmOutboxEntryId`
+    Outbox,
+    /// `PidTagIpmWastebasketEntryId`
+    DeletedItems,
+    /// `PidTagIpmSentMailEntryId`
+    SentItems,
+    /// `PidTagFinderEntryId`: The parent of the search folders.
+    SearchRoot,
+}
+
+impl SpecialFolder {
+    /// The store property which holds the [`EntryId`] of this folder.
+    pub fn prop_id(&self) -> u16 {
+        match self {
+            SpecialFolder::IpmSubtree => 0x35E0,
+            SpecialFolder::Outbox => 0x35E2,
+            SpecialFolder::DeletedItems => 0x35E3,
+            SpecialFolder::SentItems => 0x35E4,
+            SpecialFolder::SearchRoot => 0x35E7,
+        }
+    }
+}
+
+/// Summary of a folder from a row in the hierarchy table of its parent, see
+/// [`Store::top_level_folders`].
+#[derive(Clone, Debug)]
+pub struct FolderSummary {
+    entry_id: EntryId,
+    parent: NodeId,
+    display_name: Option<String>,
+    container_class: Option<String>,
+    content_count: Option<i32>,
+    unread_count: Option<i32>,
+    has_sub_folders: bool,
+    special_folder: Option<SpecialFolder>,
+}
+
+impl FolderSummary {
+    pub fn entry_id(&self) -> &EntryId {
+        &self.entry_id
+    }
+
+    pub fn parent(&self) -> NodeId {
+        self.parent
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        self.display_name.as_deref()
+    }
+
+    pub fn container_class(&self) -> Option<&str> {
+        self.container_class.as_deref()
+    }
+
+    pub fn content_count(&self) -> Option<i32> {
+        self.content_count
+    }
+
+    pub fn unread_count(&self) -> Option<i32> {
+        self.unread_count
+    }
+
+    pub fn has_sub_folders(&self) -> bool {
+        self.has_sub_folders
+    }
+
+    pub fn special_folder(&self) -> Option<SpecialFolder> {
+        self.special_folder
+    }
+}
+
+/// How [`Store::property_census`] reads the properties of each message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropertyCensusMode {
+    /// Only read the contents tables of each folder. This is fast, but it only counts the
+    /// properties which are columns in the contents tables.
+    ContentsTables,
+    /// Read the property context of every message, including values stored in sub-nodes.
+    PropertyContexts,
+}
+
+/// Number of messages with a property, and the total size of its values, see
+/// [`Store::property_census`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PropertyUsage {
+    count: usize,
+    total_bytes: u64,
+}
+
+impl PropertyUsage {
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Total size of the encoded values, not counting the overhead of the heap or the table.
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    fn add(&mut self, value: &MaybeDecoded<PropertyValue>) -> io::Result<()> {
+        let size = match value {
+            MaybeDecoded::Raw { buffer, .. } => buffer.len(),
+            MaybeDecoded::Decoded(value) => {
+                let mut buffer = vec![];
+                value.write(&mut buffer)?;
+                buffer.len()
+            }
+        };
+        self.count += 1;
+        self.total_bytes += size as u64;
+        Ok(())
+    }
+}
+
+/// Property usage across the messages in a [`Store`], see [`Store::property_census`].
+#[derive(Clone, Debug, Default)]
+pub struct PropertyCensus {
+    message_count: usize,
+    properties: BTreeMap<u16, PropertyUsage>,
+}
+
+impl PropertyCensus {
+    pub fn message_count(&self) -> usize {
+        self.message_count
+    }
+
+    pub fn get(&self, prop_id: u16) -> Option<&PropertyUsage> {
+        self.properties.get(&prop_id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&u16, &PropertyUsage)> {
+        self.properties.iter()
+    }
+
+    /// The properties sorted by [`PropertyUsage::total_bytes`], largest first.
+    pub fn by_total_bytes(&self) -> Vec<(u16, PropertyUsage)> {
+        let mut properties: Vec<_> = self
+            .properties
+            .iter()
+            .map(|(prop_id, usage)| (*prop_id, *usage))
+            .collect();
+        properties.sort_by_key(|(_, usage)| std::cmp::Reverse(usage.total_bytes));
+        properties
+    }
+}
+
+/// `PidTagIpmAppointmentEntryId`
+const PID_TAG_IPM_APPOINTMENT_ENTRY_ID: u16 = 0x36D0;
+
+/// The messages opened one at a time by [`Store::iter_messages`].
+pub type MessageIter<'a> = Box<dyn Iterator<Item = io::Result<Rc<dyn Message>>> + 'a>;
+
 pub trait Store {
     fn properties(&self) -> &StoreProperties;
     fn root_hierarchy_table(&self) -> io::Result<Rc<dyn TableContext>>;
     fn unique_value(&self) -> u32;
+    /// [`Store::unique_value`] as a [`RowVersion`]. Save it along with a listing of a folder, and
+    /// pass it to [`Folder::changed_rows_since`] the next time to only read the rows which were
+    /// added or changed in between.
+    fn row_version(&self) -> RowVersion {
+        RowVersion::new(self.unique_value())
+    }
     fn open_folder(&self, entry_id: &EntryId) -> io::Result<Rc<dyn Folder>>;
+    /// Open the folder at [`NID_ROOT_FOLDER`].
+    fn root_folder(&self) -> io::Result<Rc<dyn Folder>> {
+        self.open_folder(&self.properties().root_folder_entry_id()?)
+    }
+    /// Walk the hierarchy tables from the IPM subtree and collect every folder beneath it, see
+    /// [`FolderTreeNode::to_json`] and [`FolderTreeNode::to_opml`].
+    fn hierarchy_tree(&self) -> io::Result<FolderTreeNode> {
+        let root = self.open_folder(&self.properties().ipm_sub_tree_entry_id()?)?;
+        FolderTreeNode::read(self, root)
+    }
+    /// Open every message in the IPM subtree whose `PidTagMessageClass` passes the `filter`,
+    /// depth-first. The folders are opened up front, but each folder's messages are only listed
+    /// when the iterator reaches it, and messages which do not match are skipped without opening
+    /// them, see [`Folder::message_entry_ids_matching`].
+    fn iter_messages<'a>(&'a self, filter: &'a MessageClassFilter) -> io::Result<MessageIter<'a>> {
+        let mut folders = vec![];
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![self.open_folder(&self.properties().ipm_sub_tree_entry_id()?)?];
+        while let Some(folder) = pending.pop() {
+            if !visited.insert(u32::from(folder.properties().node_id())) {
+                continue;
+            }
+            if let Some(hierarchy_table) = folder.hierarchy_table() {
+                let sub_folders = hierarchy_table
+                    .rows_matrix()
+                    .map(|row| self.open_folder(&folder.entry_id_for_row(row)))
+                    .collect::<io::Result<Vec<_>>>()?;
+                pending.extend(sub_folders.into_iter().rev());
+            }
+            folders.push(folder);
+        }
+
+        Ok(Box::new(folders.into_iter().flat_map(
+            move |folder| -> Box<dyn Iterator<Item = _>> {
+                match folder.message_entry_ids_matching(filter) {
+                    Ok(entry_ids) => Box::new(
+                        entry_ids
+                            .into_iter()
+                            .map(move |entry_id| self.open_message(&entry_id, None)),
+                    ),
+                    Err(err) => Box::new(iter::once(Err(err))),
+                }
+            },
+        )))
+    }
+    /// Open the calendar folder from `PidTagIpmAppointmentEntryId` on the root folder, or return
+    /// `None` if the root folder does not have one.
+    fn calendar_folder(&self) -> io::Result<Option<Rc<dyn Folder>>> {
+        let root = self.root_folder()?;
+        match root.properties().get(PID_TAG_IPM_APPOINTMENT_ENTRY_ID) {
+            Some(PropertyValue::Binary(value)) => self
+                .open_folder(&EntryId::read(&mut value.buffer())?)
+                .map(Some),
+            _ => Ok(None),
+        }
+    }
+    /// The folder where Outlook saves the [`CategoryList`], which is the calendar folder if there
+    /// is one, or else the root folder.
+    fn category_list_folder(&self) -> io::Result<Rc<dyn Folder>> {
+        match self.calendar_folder()? {
+            Some(calendar) => Ok(calendar),
+            None => self.root_folder(),
+        }
+    }
+    /// The [`CategoryList`] saved in the calendar folder or the root folder, or an empty list if
+    /// neither of them has one.
+    fn categories(&self) -> io::Result<CategoryList> {
+        if let Some(calendar) = self.calendar_folder()? {
+            if let Some(categories) = CategoryList::load(calendar.as_ref())? {
+                return Ok(categories);
+            }
+        }
+        Ok(CategoryList::load(self.root_folder()?.as_ref())?.unwrap_or_default())
+    }
+    /// List the [`SPECIAL_NODE_IDS`] which are present in the NBT of this store, with their
+    /// names. Some of them have a `nidType` which is not a [`NodeIdType`], so they cannot be
+    /// checked with [`Store::contains`].
+    fn special_nodes(&self) -> io::Result<Vec<(NodeId, &'static str)>>;
+    /// Look up the [`EntryId`] in the NBT without reading any of the node's data, and return the
+    /// [`NodeIdType`] of the node if it exists.
+    fn contains(&self, entry_id: &EntryId) -> io::Result<Option<NodeIdType>>;
     fn open_message(
         &self,
         entry_id: &EntryId,
@@ -268,6 +830,89 @@ pub trait Store {
     ) -> io::Result<Rc<dyn Message>>;
     fn named_property_map(&self) -> io::Result<Rc<dyn NamedPropertyMap>>;
     fn search_update_queue(&self) -> io::Result<Rc<dyn SearchUpdateQueue>>;
+    /// Read the search folders which are active from the `NID_SEARCH_ACTIVITY_LIST` node. A store
+    /// without that node does not have any active search folders.
+    fn search_activity_list(&self) -> io::Result<SearchActivityList>;
+    /// Open the Finder folder and every search folder beneath it.
+    fn finder(&self) -> io::Result<SearchRoot>;
+    fn verify_parent_links(&self) -> io::Result<Vec<ParentLinkMismatch>>;
+    /// Check the `dwRowID` of every row in the hierarchy, contents, and associated contents
+    /// tables of the root folder and the normal folders beneath it, and list the rows which do
+    /// not resolve to a node. [`Store::verify_parent_links`] skips these rows, and
+    /// [`Folder::entry_id_for_row`] would return an [`EntryId`] which cannot be opened.
+    fn verify_table_rows(&self) -> io::Result<Vec<UnresolvedTableRow>>;
+    /// The code page used to decode `PtypString8` values. Unless it has been set with
+    /// [`Store::set_string_code_page`], the first call samples the folder names in the store and
+    /// guesses the code page with [`detect_code_page_with`] and the [`Store::code_page_decoder`],
+    /// falling back to [`CP_ACP`].
+    fn string_code_page(&self) -> u16;
+    /// Override the code page for `PtypString8` values, e.g. when an ANSI PST file is known to
+    /// come from a Cyrillic or CJK locale. Messages with `PidTagMessageCodepage` still use their
+    /// own code page.
+    fn set_string_code_page(&self, code_page: u16);
+    /// The decoder for `PtypString8` values. The default is [`BuiltinCodePageDecoder`].
+    fn code_page_decoder(&self) -> Rc<dyn CodePageDecoder>;
+    /// Decode `PtypString8` values in folders and messages opened after this call with `decoder`,
+    /// e.g. one backed by ICU for code pages which [`decode_string8`] does not support.
+    fn set_code_page_decoder(&self, decoder: Rc<dyn CodePageDecoder>);
+    /// The [`Store::string_code_page`] together with the [`Store::code_page_decoder`].
+    fn string_decoder(&self) -> String8Decoder {
+        String8Decoder::new(self.string_code_page(), self.code_page_decoder())
+    }
+    /// How folders, messages, and attachments opened from this store treat property values whose
+    /// HID is dangling, see [`HeapIdMode`]. The default is [`HeapIdMode::Strict`].
+    fn heap_id_mode(&self) -> HeapIdMode;
+    /// Use [`HeapIdMode::Lenient`] to open objects from a damaged file without the properties
+    /// whose values are missing from the heap, instead of failing.
+    fn set_heap_id_mode(&self, mode: HeapIdMode);
+    /// Summarize the folders beneath the root folder, followed by the folders beneath the IPM
+    /// subtree, which are the top level folders shown in Outlook. Special folders are flagged by
+    /// comparing their [`EntryId`] with the [`StoreProperties`]. PST files do not record the
+    /// Inbox in the store properties, so it is not flagged.
+    fn top_level_folders(&self) -> io::Result<Vec<FolderSummary>>;
+    /// Count the messages with each property, and the total size of its values, in the contents
+    /// and associated contents tables of every folder. Search folders are skipped, since their
+    /// messages live in other folders.
+    fn property_census(&self, mode: PropertyCensusMode) -> io::Result<PropertyCensus>;
+    /// Count the `PidTagMessageClass` column of the contents table of every folder, without
+    /// opening the messages. Search folders are skipped, since their messages live in other
+    /// folders.
+    fn message_class_histogram(&self) -> io::Result<MessageClassHistogram>;
+    /// Open a read view of this store which is pinned to the Node BTree and Block BTree roots in
+    /// the committed header when it is called. Only the header is copied: transactions write
+    /// changed BTree pages to new pages rather than in place, and while the snapshot is open they
+    /// keep the pages and blocks they release allocated, so folders and messages opened through
+    /// the snapshot keep seeing the same trees after later transactions are committed.
+    ///
+    /// Only transactions through the same [`PstFile`] know about the snapshot. Another
+    /// [`PstFile`] for the same file can reuse the space it released, so drop the snapshot once
+    /// the reads which need the old view are done.
+    fn snapshot(&self) -> io::Result<Rc<dyn Store>>;
+}
+
+/// Reads the header which was copied by [`StoreInner::snapshot_reader`] from memory, and
+/// everything else from the source of the [`PstFile`] the snapshot was taken from.
+struct SnapshotReader {
+    source: Arc<dyn ReadAt>,
+    header: Vec<u8>,
+    _pin: SnapshotPin,
+}
+
+impl ReadAt for SnapshotReader {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        match usize::try_from(offset) {
+            Ok(from) if from < self.header.len() => {
+                let size = cmp::min(buf.len(), self.header.len() - from);
+                buf[..size].copy_from_slice(&self.header[from..from + size]);
+                Ok(size)
+            }
+            _ => self.source.read_at(buf, offset),
+        }
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        self.source.len()
+    }
 }
 
 struct StoreInner<Pst>
@@ -279,7 +924,9 @@ where
     block_btree: PstFileReadWriteBlockBTree<Pst>,
     properties: StoreProperties,
     store: Weak<Pst::Store>,
-    root_hierarchy_table: OnceCell<io::Result<Rc<dyn TableContext>>>,
+    string_code_page: Cell<Option<u16>>,
+    code_page_decoder: RefCell<Rc<dyn CodePageDecoder>>,
+    heap_id_mode: Cell<HeapIdMode>,
 }
 
 impl<Pst> StoreInner<Pst>
@@ -317,17 +964,16 @@ where
     <Pst as PstFile>::Message: MessageReadWrite<Pst>,
     <Pst as PstFile>::NamedPropertyMap: NamedPropertyMapReadWrite<Pst>,
     <Pst as PstFile>::SearchUpdateQueue: SearchUpdateQueueReadWrite<Pst>,
+    <Pst as PstFile>::Header: HeaderReadWrite<Pst>,
+    <Pst as PstFile>::Root: RootReadWrite<Pst>,
 {
     fn read(pst: Rc<Pst>) -> io::Result<Self> {
         let header = pst.header();
         let root = header.root();
 
         let (node_btree, block_btree, properties) = {
-            let mut file = pst
-                .reader()
-                .lock()
-                .map_err(|_| MessagingError::FailedToLockFile)?;
-            let file = &mut *file;
+            let mut file = pst.reader();
+            let file = &mut file;
 
             let encoding = header.crypt_method();
             let node_btree = <<Pst as PstFile>::NodeBTree as RootBTreeReadWrite>::read(
@@ -365,7 +1011,11 @@ where
                         .map(|value| (prop_id, value))
                 })
                 .collect::<io::Result<BTreeMap<_, _>>>()?;
-            let properties = StoreProperties { properties };
+            let properties = StoreProperties {
+                properties,
+                string_decoder: Default::default(),
+                version: pst.header().version(),
+            };
 
             (node_btree, block_btree, properties)
         };
@@ -376,44 +1026,25 @@ where
             block_btree,
             properties,
             store: Default::default(),
-            root_hierarchy_table: Default::default(),
+            string_code_page: Default::default(),
+            code_page_decoder: RefCell::new(Rc::new(BuiltinCodePageDecoder)),
+            heap_id_mode: Default::default(),
         })
     }
 
+    /// The table holds a reference to the store, so it is read again on each call rather than
+    /// cached in the store, which would keep the store and its [`PstFile`] alive forever.
     fn root_hierarchy_table(&self) -> io::Result<Rc<dyn TableContext>> {
-        let hierarchy_table =
-            self.root_hierarchy_table
-                .get_or_init(|| {
-                    let store = self.store.upgrade().ok_or(
-                        MessagingError::StoreRootHierarchyTableFailed(
-                            "Store has been dropped".to_string(),
-                        ),
-                    )?;
-                    let mut file = self
-                        .pst
-                        .reader()
-                        .lock()
-                        .map_err(|_| MessagingError::FailedToLockFile)?;
-
-                    let file = &mut *file;
-                    let node_id = NodeId::new(NodeIdType::HierarchyTable, NID_ROOT_FOLDER.index())?;
-                    let mut page_cache = self.pst.node_cache();
-                    let node_key: <Pst as PstFile>::BTreeKey = u32::from(node_id).into();
-                    let node = self
-                        .node_btree
-                        .find_entry(file, node_key, &mut page_cache)?;
-
-                    <<Pst as PstFile>::TableContext as TableContextReadWrite<Pst>>::read(
-                        store.clone(),
-                        node,
-                    )
-                })
-                .as_ref()
-                .map_err(|err| format!("{err:?}"))
-                .cloned()
-                .map_err(MessagingError::StoreRootHierarchyTableFailed)?;
+        let store = self
+            .store
+            .upgrade()
+            .ok_or(MessagingError::StoreRootHierarchyTableFailed(
+                "Store has been dropped".to_string(),
+            ))?;
+        let node_id = NodeId::new(NodeIdType::HierarchyTable, NID_ROOT_FOLDER.index())?;
+        let node = self.find_node(node_id)?;
 
-        Ok(hierarchy_table)
+        <<Pst as PstFile>::TableContext as TableContextReadWrite<Pst>>::read(store, node)
     }
 
     fn open_folder(&self, entry_id: &EntryId) -> io::Result<Rc<dyn Folder>> {
@@ -425,6 +1056,16 @@ where
         )?)
     }
 
+    fn contains(&self, entry_id: &EntryId) -> io::Result<Option<NodeIdType>> {
+        if !self.properties.matches_record_key(entry_id)? {
+            return Err(MessagingError::EntryIdWrongStore.into());
+        }
+
+        self.find_optional_node(entry_id.node_id())?
+            .map(|node| Ok(node.node().id_type()?))
+            .transpose()
+    }
+
     fn open_message(
         &self,
         entry_id: &EntryId,
@@ -458,9 +1099,563 @@ where
         Ok(<<Pst as PstFile>::SearchUpdateQueue as SearchUpdateQueueReadWrite<Pst>>::read(store)?)
     }
 
+    fn search_activity_list(&self) -> io::Result<SearchActivityList> {
+        let Some(node) = self.find_optional_node(NID_SEARCH_ACTIVITY_LIST)? else {
+            return Ok(Default::default());
+        };
+        let search_key: u64 = node.data().search_key().into();
+        if search_key == 0 {
+            return Ok(Default::default());
+        }
+        let data = self
+            .pst
+            .read_block(node.data())
+            .map_err(|err| MessagingError::StoreSearchActivityList(format!("{err:?}")))?;
+        Ok(SearchActivityList::parse(&data)?)
+    }
+
     fn unique_value(&self) -> u32 {
         self.pst.header().unique_value()
     }
+
+    fn find_node(&self, node_id: NodeId) -> io::Result<<Pst as PstFile>::NodeBTreeEntry> {
+        let mut file = self.pst.reader();
+        let file = &mut file;
+        let mut page_cache = self.pst.node_cache();
+        let node_key: <Pst as PstFile>::BTreeKey = u32::from(node_id).into();
+        self.node_btree.find_entry(file, node_key, &mut page_cache)
+    }
+
+    /// Like [`Self::find_node`], but a node which is missing from the NBT is not an error.
+    fn find_optional_node(
+        &self,
+        node_id: NodeId,
+    ) -> io::Result<Option<<Pst as PstFile>::NodeBTreeEntry>> {
+        optional_btree_entry(self.find_node(node_id))
+    }
+
+    fn finder(&self) -> io::Result<SearchRoot> {
+        let store = self.store.upgrade().ok_or(MessagingError::StoreFinder(
+            "Store has been dropped".to_string(),
+        ))?;
+        let folder = self.open_folder(&self.properties.finder_entry_id()?)?;
+        let activity_list = self.search_activity_list()?;
+
+        let mut search_folders = Vec::new();
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![folder.clone()];
+
+        while let Some(parent) = pending.pop() {
+            if !visited.insert(u32::from(parent.properties().node_id())) {
+                continue;
+            }
+
+            let Some(hierarchy_table) = parent.hierarchy_table() else {
+                continue;
+            };
+
+            for row in hierarchy_table.rows_matrix() {
+                let entry_id = parent.entry_id_for_row(row);
+                let node_id = entry_id.node_id();
+                match node_id.id_type()? {
+                    NodeIdType::NormalFolder => pending.push(self.open_folder(&entry_id)?),
+                    NodeIdType::SearchFolder => {
+                        let folder = self.open_folder(&entry_id)?;
+                        let update_queue =
+                            <<Pst as PstFile>::SearchUpdateQueue as SearchUpdateQueueReadWrite<
+                                Pst,
+                            >>::read_search_folder(
+                                store.clone(), node_id
+                            )?;
+                        let criteria = self
+                            .find_optional_node(NodeId::new(
+                                NodeIdType::SearchCriteria,
+                                node_id.index(),
+                            )?)?
+                            .map(|node| self.pst.read_block(node.data()))
+                            .transpose()?
+                            .map(SearchCriteria::parse);
+                        let contents_table = self
+                            .find_optional_node(NodeId::new(
+                                NodeIdType::SearchContentsTable,
+                                node_id.index(),
+                            )?)?
+                            .map(|node| {
+                                <<Pst as PstFile>::TableContext as TableContextReadWrite<Pst>>::read(
+                                    store.clone(),
+                                    node,
+                                )
+                            })
+                            .transpose()?;
+
+                        search_folders.push(SearchFolder {
+                            folder,
+                            update_queue,
+                            criteria,
+                            contents_table,
+                            is_active: activity_list.contains(node_id),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(SearchRoot {
+            folder,
+            search_folders,
+        })
+    }
+
+    fn verify_parent_links(&self) -> io::Result<Vec<ParentLinkMismatch>> {
+        let store = self
+            .store
+            .upgrade()
+            .ok_or(MessagingError::StoreVerifyParentLinks(
+                "Store has been dropped".to_string(),
+            ))?;
+
+        let mut mismatches = Vec::new();
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![(NID_ROOT_FOLDER, NID_ROOT_FOLDER)];
+
+        while let Some((folder, expected_parent)) = pending.pop() {
+            if !visited.insert(u32::from(folder)) {
+                continue;
+            }
+
+            let parent = self.find_node(folder)?.parent();
+            if parent != Some(expected_parent) {
+                mismatches.push(ParentLinkMismatch {
+                    node: folder,
+                    expected_parent,
+                    actual_parent: parent,
+                });
+            }
+
+            // Search folders only reference messages which live in other folders, and they do not
+            // have sub-folders, so there is nothing more to check beneath them.
+            if !matches!(folder.id_type(), Ok(NodeIdType::NormalFolder)) {
+                continue;
+            }
+
+            for table_type in [
+                NodeIdType::HierarchyTable,
+                NodeIdType::ContentsTable,
+                NodeIdType::AssociatedContentsTable,
+            ] {
+                let node = self.find_node(NodeId::new(table_type, folder.index())?)?;
+                let table = <<Pst as PstFile>::TableContext as TableContextReadWrite<Pst>>::read(
+                    store.clone(),
+                    node,
+                )?;
+
+                for row in table.rows_matrix() {
+                    let child = NodeId::from(u32::from(row.id()));
+                    if table_type == NodeIdType::HierarchyTable {
+                        if self.find_optional_node(child)?.is_some() {
+                            pending.push((child, folder));
+                        }
+                        continue;
+                    }
+
+                    let Some(node) = self.find_optional_node(child)? else {
+                        continue;
+                    };
+                    let parent = node.parent();
+                    if parent != Some(folder) {
+                        mismatches.push(ParentLinkMismatch {
+                            node: child,
+                            expected_parent: folder,
+                            actual_parent: parent,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(mismatches)
+    }
+
+    fn verify_table_rows(&self) -> io::Result<Vec<UnresolvedTableRow>> {
+        let store = self
+            .store
+            .upgrade()
+            .ok_or(MessagingError::StoreVerifyTableRows(
+                "Store has been dropped".to_string(),
+            ))?;
+
+        let mut unresolved = Vec::new();
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![NID_ROOT_FOLDER];
+
+        while let Some(folder) = pending.pop() {
+            if !visited.insert(u32::from(folder)) {
+                continue;
+            }
+
+            for table_type in [
+                NodeIdType::HierarchyTable,
+                NodeIdType::ContentsTable,
+                NodeIdType::AssociatedContentsTable,
+            ] {
+                let node = self.find_node(NodeId::new(table_type, folder.index())?)?;
+                let table = <<Pst as PstFile>::TableContext as TableContextReadWrite<Pst>>::read(
+                    store.clone(),
+                    node,
+                )?;
+
+                for row in table.rows_matrix() {
+                    let row = u32::from(row.id());
+                    let reason = UnresolvedRowReason::classify(row, table_type, |node| {
+                        Ok(self.find_optional_node(node)?.is_some())
+                    })?;
+                    if let Some(reason) = reason {
+                        unresolved.push(UnresolvedTableRow {
+                            folder,
+                            table_type,
+                            row,
+                            reason,
+                        });
+                        continue;
+                    }
+
+                    let child = NodeId::from(row);
+                    if matches!(child.id_type(), Ok(NodeIdType::NormalFolder)) {
+                        pending.push(child);
+                    }
+                }
+            }
+        }
+
+        Ok(unresolved)
+    }
+
+    fn special_nodes(&self) -> io::Result<Vec<(NodeId, &'static str)>> {
+        let mut nodes = Vec::new();
+        for (node_id, name) in SPECIAL_NODE_IDS {
+            if self.find_optional_node(node_id)?.is_some() {
+                nodes.push((node_id, name));
+            }
+        }
+        Ok(nodes)
+    }
+
+    fn message_class_histogram(&self) -> io::Result<MessageClassHistogram> {
+        let store = self
+            .store
+            .upgrade()
+            .ok_or(MessagingError::StoreMessageClassHistogram(
+                "Store has been dropped".to_string(),
+            ))?;
+
+        let string_decoder = store.string_decoder();
+        let mut histogram = MessageClassHistogram::default();
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![NID_ROOT_FOLDER];
+
+        while let Some(folder) = pending.pop() {
+            if !visited.insert(u32::from(folder))
+                || !matches!(folder.id_type(), Ok(NodeIdType::NormalFolder))
+            {
+                continue;
+            }
+
+            let node = self.find_node(NodeId::new(NodeIdType::HierarchyTable, folder.index())?)?;
+            let table = <<Pst as PstFile>::TableContext as TableContextReadWrite<Pst>>::read(
+                store.clone(),
+                node,
+            )?;
+            pending.extend(
+                table
+                    .rows_matrix()
+                    .map(|row| NodeId::from(u32::from(row.id()))),
+            );
+
+            let node = self.find_node(NodeId::new(NodeIdType::ContentsTable, folder.index())?)?;
+            let table = <<Pst as PstFile>::TableContext as TableContextReadWrite<Pst>>::read(
+                store.clone(),
+                node,
+            )?;
+            histogram.add_table(table.as_ref(), &string_decoder)?;
+        }
+
+        Ok(histogram)
+    }
+
+    fn property_census(&self, mode: PropertyCensusMode) -> io::Result<PropertyCensus> {
+        let store = self
+            .store
+            .upgrade()
+            .ok_or(MessagingError::StorePropertyCensus(
+                "Store has been dropped".to_string(),
+            ))?;
+
+        let mut census = PropertyCensus::default();
+        let mut visited = BTreeSet::new();
+        let mut pending = vec![NID_ROOT_FOLDER];
+
+        while let Some(folder) = pending.pop() {
+            if !visited.insert(u32::from(folder))
+                || !matches!(folder.id_type(), Ok(NodeIdType::NormalFolder))
+            {
+                continue;
+            }
+
+            for table_type in [
+                NodeIdType::HierarchyTable,
+                NodeIdType::ContentsTable,
+                NodeIdType::AssociatedContentsTable,
+            ] {
+                let node = self.find_node(NodeId::new(table_type, folder.index())?)?;
+                let table = <<Pst as PstFile>::TableContext as TableContextReadWrite<Pst>>::read(
+                    store.clone(),
+                    node,
+                )?;
+                let context = table.context();
+
+                for row in table.rows_matrix() {
+                    let child = NodeId::from(u32::from(row.id()));
+                    if table_type == NodeIdType::HierarchyTable {
+                        pending.push(child);
+                        continue;
+                    }
+
+                    census.message_count += 1;
+                    match mode {
+                        PropertyCensusMode::ContentsTables => {
+                            for (column, value) in
+                                context.columns().iter().zip(row.columns(context)?)
+                            {
+                                let Some(value) = value else {
+                                    continue;
+                                };
+                                if [LTP_ROW_ID_PROP_ID, LTP_ROW_VERSION_PROP_ID]
+                                    .contains(&column.prop_id())
+                                {
+                                    continue;
+                                }
+                                let value = table.read_column_lazy(&value, column.prop_type())?;
+                                census
+                                    .properties
+                                    .entry(column.prop_id())
+                                    .or_default()
+                                    .add(&value)?;
+                            }
+                        }
+                        PropertyCensusMode::PropertyContexts => {
+                            let entry_id = self.properties.make_entry_id(child)?;
+                            let message =
+                                <<Pst as PstFile>::Message as MessageReadWrite<Pst>>::read(
+                                    store.clone(),
+                                    &entry_id,
+                                    None,
+                                )?;
+                            for (prop_id, value) in message.properties().iter() {
+                                census
+                                    .properties
+                                    .entry(*prop_id)
+                                    .or_default()
+                                    .add(&MaybeDecoded::Decoded(value.clone()))?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(census)
+    }
+
+    fn string_code_page(&self) -> u16 {
+        if let Some(code_page) = self.string_code_page.get() {
+            return code_page;
+        }
+
+        let samples = self.string8_samples().unwrap_or_default();
+        let decoder = self.code_page_decoder.borrow().clone();
+        let code_page = detect_code_page_with(samples.iter().map(Vec::as_slice), decoder.as_ref())
+            .unwrap_or(CP_ACP);
+        self.set_string_code_page(code_page);
+        code_page
+    }
+
+    fn set_string_code_page(&self, code_page: u16) {
+        self.string_code_page.set(Some(code_page));
+        let decoder = self.code_page_decoder.borrow().clone();
+        *self.properties.string_decoder.borrow_mut() = String8Decoder::new(code_page, decoder);
+    }
+
+    fn set_code_page_decoder(&self, decoder: Rc<dyn CodePageDecoder>) {
+        let code_page = self.properties.string_code_page();
+        *self.properties.string_decoder.borrow_mut() =
+            String8Decoder::new(code_page, decoder.clone());
+        *self.code_page_decoder.borrow_mut() = decoder;
+    }
+
+    fn string_decoder(&self) -> String8Decoder {
+        String8Decoder::new(
+            self.string_code_page(),
+            self.code_page_decoder.borrow().clone(),
+        )
+    }
+
+    fn top_level_folders(&self) -> io::Result<Vec<FolderSummary>> {
+        let store = self
+            .store
+            .upgrade()
+            .ok_or(MessagingError::StoreTopLevelFolders(
+                "Store has been dropped".to_string(),
+            ))?;
+
+        let special_folders: BTreeMap<_, _> = [
+            SpecialFolder::IpmSubtree,
+            SpecialFolder::Outbox,
+            SpecialFolder::DeletedItems,
+            SpecialFolder::SentItems,
+            SpecialFolder::SearchRoot,
+        ]
+        .into_iter()
+        .filter_map(
+            |special_folder| match self.properties.get(special_folder.prop_id()) {
+                Some(PropertyValue::Binary(value)) => EntryId::read(&mut value.buffer())
+                    .ok()
+                    .map(|entry_id| (u32::from(entry_id.node_id()), special_folder)),
+                _ => None,
+            },
+        )
+        .collect();
+
+        let mut tables = vec![(NID_ROOT_FOLDER, self.root_hierarchy_table()?)];
+        if let Ok(entry_id) = self.properties.ipm_sub_tree_entry_id() {
+            let node_id = NodeId::new(NodeIdType::HierarchyTable, entry_id.node_id().index())?;
+            if let Some(node) = self.find_optional_node(node_id)? {
+                let table = <<Pst as PstFile>::TableContext as TableContextReadWrite<Pst>>::read(
+                    store, node,
+                )?;
+                tables.push((entry_id.node_id(), table));
+            }
+        }
+
+        let string_decoder = self.string_decoder();
+        let mut folders = Vec::new();
+        for (parent, table) in tables {
+            let context = table.context();
+            for row in table.rows_matrix() {
+                let node_id = NodeId::from(u32::from(row.id()));
+                let mut summary = FolderSummary {
+                    entry_id: self.properties.make_entry_id(node_id)?,
+                    parent,
+                    display_name: None,
+                    container_class: None,
+                    content_count: None,
+                    unread_count: None,
+                    has_sub_folders: false,
+                    special_folder: special_folders.get(&u32::from(node_id)).copied(),
+                };
+
+                for (column, value) in context.columns().iter().zip(row.columns(context)?) {
+                    let Some(value) = value else {
+                        continue;
+                    };
+                    let value = table.read_column(&value, column.prop_type())?;
+                    let string = match &value {
+                        PropertyValue::String8(value) => {
+                            Some(value.to_string_with_decoder(&string_decoder))
+                        }
+                        PropertyValue::Unicode(value) => Some(value.to_string()),
+                        _ => None,
+                    };
+
+                    match (column.prop_id(), value) {
+                        (0x3001, _) => summary.display_name = string,
+                        (0x3613, _) => summary.container_class = string,
+                        (0x3602, PropertyValue::Integer32(value)) => {
+                            summary.content_count = Some(value)
+                        }
+                        (0x3603, PropertyValue::Integer32(value)) => {
+                            summary.unread_count = Some(value)
+                        }
+                        (0x360A, PropertyValue::Boolean(value)) => summary.has_sub_folders = value,
+                        _ => {}
+                    }
+                }
+
+                folders.push(summary);
+            }
+        }
+
+        Ok(folders)
+    }
+
+    /// Collect the `PtypString8` values in the store properties, and in the hierarchy tables of
+    /// the root folder and the IPM subtree, which hold the (localized) names of the default
+    /// folders.
+    fn string8_samples(&self) -> io::Result<Vec<Vec<u8>>> {
+        let store = self
+            .store
+            .upgrade()
+            .ok_or(MessagingError::StoreStringCodePage(
+                "Store has been dropped".to_string(),
+            ))?;
+
+        let mut samples: Vec<_> = self
+            .properties
+            .iter()
+            .filter_map(|(_, value)| match value {
+                PropertyValue::String8(value) => Some(value.buffer().to_vec()),
+                _ => None,
+            })
+            .collect();
+
+        let mut tables = vec![self.root_hierarchy_table()?];
+        if let Ok(entry_id) = self.properties.ipm_sub_tree_entry_id() {
+            let node_id = NodeId::new(NodeIdType::HierarchyTable, entry_id.node_id().index())?;
+            if let Some(node) = self.find_optional_node(node_id)? {
+                tables.push(<<Pst as PstFile>::TableContext as TableContextReadWrite<
+                    Pst,
+                >>::read(store, node)?);
+            }
+        }
+
+        for table in tables {
+            let context = table.context();
+            for row in table.rows_matrix() {
+                for (column, value) in context.columns().iter().zip(row.columns(context)?) {
+                    let (PropertyType::String8, Some(value)) = (column.prop_type(), value) else {
+                        continue;
+                    };
+                    if let PropertyValue::String8(value) =
+                        table.read_column(&value, column.prop_type())?
+                    {
+                        samples.push(value.buffer().to_vec());
+                    }
+                }
+            }
+        }
+
+        Ok(samples)
+    }
+
+    /// Copy the committed header from the file into a [`SnapshotReader`] for [`Store::snapshot`],
+    /// and pin the space it refers to.
+    fn snapshot_reader(&self) -> io::Result<SnapshotReader> {
+        let source = self.pst.source();
+        let mut header = vec![0; DENSITY_LIST_FILE_OFFSET as usize];
+        let mut offset = 0;
+        while offset < header.len() {
+            match source.read_at(&mut header[offset..], offset as u64)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                size => offset += size,
+            }
+        }
+
+        Ok(SnapshotReader {
+            source,
+            header,
+            _pin: self.pst.snapshot_pin(),
+        })
+    }
 }
 
 pub struct UnicodeStore {
@@ -500,6 +1695,10 @@ impl Store for UnicodeStore {
         self.inner.open_folder(entry_id)
     }
 
+    fn contains(&self, entry_id: &EntryId) -> io::Result<Option<NodeIdType>> {
+        self.inner.contains(entry_id)
+    }
+
     fn open_message(
         &self,
         entry_id: &EntryId,
@@ -515,6 +1714,74 @@ impl Store for UnicodeStore {
     fn search_update_queue(&self) -> io::Result<Rc<dyn SearchUpdateQueue>> {
         self.inner.search_update_queue()
     }
+
+    fn search_activity_list(&self) -> io::Result<SearchActivityList> {
+        self.inner.search_activity_list()
+    }
+
+    fn finder(&self) -> io::Result<SearchRoot> {
+        self.inner.finder()
+    }
+
+    fn verify_parent_links(&self) -> io::Result<Vec<ParentLinkMismatch>> {
+        self.inner.verify_parent_links()
+    }
+
+    fn verify_table_rows(&self) -> io::Result<Vec<UnresolvedTableRow>> {
+        self.inner.verify_table_rows()
+    }
+
+    fn string_code_page(&self) -> u16 {
+        self.inner.string_code_page()
+    }
+
+    fn set_string_code_page(&self, code_page: u16) {
+        self.inner.set_string_code_page(code_page)
+    }
+
+    fn code_page_decoder(&self) -> Rc<dyn CodePageDecoder> {
+        self.inner.code_page_decoder.borrow().clone()
+    }
+
+    fn set_code_page_decoder(&self, decoder: Rc<dyn CodePageDecoder>) {
+        self.inner.set_code_page_decoder(decoder)
+    }
+
+    fn heap_id_mode(&self) -> HeapIdMode {
+        self.inner.heap_id_mode.get()
+    }
+
+    fn set_heap_id_mode(&self, mode: HeapIdMode) {
+        self.inner.heap_id_mode.set(mode)
+    }
+
+    fn top_level_folders(&self) -> io::Result<Vec<FolderSummary>> {
+        self.inner.top_level_folders()
+    }
+
+    fn special_nodes(&self) -> io::Result<Vec<(NodeId, &'static str)>> {
+        self.inner.special_nodes()
+    }
+
+    fn property_census(&self, mode: PropertyCensusMode) -> io::Result<PropertyCensus> {
+        self.inner.property_census(mode)
+    }
+
+    fn message_class_histogram(&self) -> io::Result<MessageClassHistogram> {
+        self.inner.message_class_histogram()
+    }
+
+    fn snapshot(&self) -> io::Result<Rc<dyn Store>> {
+        let reader = self.inner.snapshot_reader()?;
+        let pst = UnicodePstFile::read_from_source(Arc::new(reader), HeaderFieldMode::Preserve)?;
+        let store = UnicodeStore::read(Rc::new(pst))?;
+        if let Some(code_page) = self.inner.string_code_page.get() {
+            store.set_string_code_page(code_page);
+        }
+        store.set_code_page_decoder(self.code_page_decoder());
+        store.set_heap_id_mode(self.heap_id_mode());
+        Ok(store)
+    }
 }
 
 impl StoreReadWrite<UnicodePstFile> for UnicodeStore {
@@ -568,6 +1835,10 @@ impl Store for AnsiStore {
         self.inner.open_folder(entry_id)
     }
 
+    fn contains(&self, entry_id: &EntryId) -> io::Result<Option<NodeIdType>> {
+        self.inner.contains(entry_id)
+    }
+
     fn open_message(
         &self,
         entry_id: &EntryId,
@@ -583,6 +1854,74 @@ impl Store for AnsiStore {
     fn search_update_queue(&self) -> io::Result<Rc<dyn SearchUpdateQueue>> {
         self.inner.search_update_queue()
     }
+
+    fn search_activity_list(&self) -> io::Result<SearchActivityList> {
+        self.inner.search_activity_list()
+    }
+
+    fn finder(&self) -> io::Result<SearchRoot> {
+        self.inner.finder()
+    }
+
+    fn verify_parent_links(&self) -> io::Result<Vec<ParentLinkMismatch>> {
+        self.inner.verify_parent_links()
+    }
+
+    fn verify_table_rows(&self) -> io::Result<Vec<UnresolvedTableRow>> {
+        self.inner.verify_table_rows()
+    }
+
+    fn string_code_page(&self) -> u16 {
+        self.inner.string_code_page()
+    }
+
+    fn set_string_code_page(&self, code_page: u16) {
+        self.inner.set_string_code_page(code_page)
+    }
+
+    fn code_page_decoder(&self) -> Rc<dyn CodePageDecoder> {
+        self.inner.code_page_decoder.borrow().clone()
+    }
+
+    fn set_code_page_decoder(&self, decoder: Rc<dyn CodePageDecoder>) {
+        self.inner.set_code_page_decoder(decoder)
+    }
+
+    fn heap_id_mode(&self) -> HeapIdMode {
+        self.inner.heap_id_mode.get()
+    }
+
+    fn set_heap_id_mode(&self, mode: HeapIdMode) {
+        self.inner.heap_id_mode.set(mode)
+    }
+
+    fn top_level_folders(&self) -> io::Result<Vec<FolderSummary>> {
+        self.inner.top_level_folders()
+    }
+
+    fn special_nodes(&self) -> io::Result<Vec<(NodeId, &'static str)>> {
+        self.inner.special_nodes()
+    }
+
+    fn property_census(&self, mode: PropertyCensusMode) -> io::Result<PropertyCensus> {
+        self.inner.property_census(mode)
+    }
+
+    fn message_class_histogram(&self) -> io::Result<MessageClassHistogram> {
+        self.inner.message_class_histogram()
+    }
+
+    fn snapshot(&self) -> io::Result<Rc<dyn Store>> {
+        let reader = self.inner.snapshot_reader()?;
+        let pst = AnsiPstFile::read_from_source(Arc::new(reader), HeaderFieldMode::Preserve)?;
+        let store = AnsiStore::read(Rc::new(pst))?;
+        if let Some(code_page) = self.inner.string_code_page.get() {
+            store.set_string_code_page(code_page);
+        }
+        store.set_code_page_decoder(self.code_page_decoder());
+        store.set_heap_id_mode(self.heap_id_mode());
+        Ok(store)
+    }
 }
 
 impl StoreReadWrite<AnsiPstFile> for AnsiStore {
@@ -598,3 +1937,64 @@ impl StoreReadWrite<AnsiPstFile> for AnsiStore {
         &self.inner.block_btree
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_path::TempPath;
+
+    #[test]
+    fn test_set_display_name() {
+        let properties = StoreProperties {
+            string_decoder: RefCell::new(String8Decoder::default().with_code_page(1251)),
+            version: NdbVersion::Ansi,
+            ..Default::default()
+        };
+        let mut properties = StorePropertiesMut::from(&properties);
+        properties.set_display_name("Почта");
+        assert!(matches!(
+            properties.properties().get(0x3001),
+            Some(PropertyValue::String8(value)) if value.buffer() == b"\xCF\xEE\xF7\xF2\xE0"
+        ));
+        assert_eq!(properties.properties().display_name().unwrap(), "Почта");
+
+        let mut properties = StorePropertiesMut::from(&StoreProperties::default());
+        properties.set_display_name("Почта");
+        assert!(matches!(
+            properties.properties().get(0x3001),
+            Some(PropertyValue::Unicode(_))
+        ));
+        assert_eq!(properties.properties().display_name().unwrap(), "Почта");
+    }
+
+    #[test]
+    fn test_iter_messages() {
+        let path = TempPath::empty_pst_with_messages(
+            "iter-messages",
+            &["IPM.Note", "IPM.Appointment", "IPM.Contact"],
+        );
+        let store = crate::open_store(&path).unwrap();
+
+        let message_classes = |filter: &MessageClassFilter| {
+            let mut message_classes = store
+                .iter_messages(filter)
+                .unwrap()
+                .map(|message| message.unwrap().properties().message_class().unwrap())
+                .collect::<Vec<_>>();
+            message_classes.sort();
+            message_classes
+        };
+        assert_eq!(
+            message_classes(&MessageClassFilter::new()),
+            ["IPM.Appointment", "IPM.Contact", "IPM.Note"]
+        );
+        assert_eq!(
+            message_classes(&MessageClassFilter::new().exclude("IPM.Appointment*")),
+            ["IPM.Contact", "IPM.Note"]
+        );
+        assert_eq!(
+            message_classes(&MessageClassFilter::new().include("ipm.note")),
+            ["IPM.Note"]
+        );
+    }
+}
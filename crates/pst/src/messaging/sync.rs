@@ -0,0 +1,260 @@
+//! Change tracking properties from
+//! [MS-OXCFXICS](https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxcfxics/b9752f3d-d50d-44b8-9e6b-608a117c8532),
+//! which Exchange uses to reconcile the versions of a folder or message in different replicas.
+//!
+//! Each change is identified by an [`Xid`], `PidTagChangeKey` holds the XID of the last change
+//! to an object, and `PidTagPredecessorChangeList` holds the latest XID from each replica whose
+//! changes the object already includes.
+
+use std::io;
+
+use super::MessagingError;
+use crate::ltp::{
+    prop_context::{GuidValue, PropertyValue},
+    prop_type::PropertyType,
+};
+
+/// `PidTagChangeKey`
+pub const PID_TAG_CHANGE_KEY: u16 = 0x65E2;
+/// `PidTagPredecessorChangeList`
+pub const PID_TAG_PREDECESSOR_CHANGE_LIST: u16 = 0x65E3;
+
+const XID_NAMESPACE_SIZE: usize = 16;
+const MAX_XID_LOCAL_ID_SIZE: usize = 8;
+
+/// [XID](https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxcfxics/18d15200-8ec5-4e8c-b8ca-2bc5bf7c0a9e):
+/// a `NamespaceGuid` which identifies the replica, followed by a `LocalId` of 1 to 8 bytes which
+/// is unique within that replica.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Xid {
+    namespace: GuidValue,
+    local_id: [u8; MAX_XID_LOCAL_ID_SIZE],
+    local_id_size: u8,
+}
+
+impl Xid {
+    /// Parse an XID which fills all of `data`, e.g. the value of `PidTagChangeKey`.
+    pub fn read(data: &[u8]) -> io::Result<Self> {
+        let local_id_size = data.len().wrapping_sub(XID_NAMESPACE_SIZE);
+        if !(1..=MAX_XID_LOCAL_ID_SIZE).contains(&local_id_size) {
+            return Err(MessagingError::InvalidXidSize(data.len()).into());
+        }
+
+        let (namespace, local_id) = data.split_at(XID_NAMESPACE_SIZE);
+        let mut bytes = [0; XID_NAMESPACE_SIZE];
+        bytes.copy_from_slice(namespace);
+        let namespace = GuidValue::from_bytes_le(bytes);
+
+        let mut buffer = [0; MAX_XID_LOCAL_ID_SIZE];
+        buffer[..local_id_size].copy_from_slice(local_id);
+
+        Ok(Self {
+            namespace,
+            local_id: buffer,
+            local_id_size: local_id_size as u8,
+        })
+    }
+
+    /// The `NamespaceGuid`, which is the replica GUID of the store that made the change.
+    pub fn namespace(&self) -> GuidValue {
+        self.namespace
+    }
+
+    pub fn local_id(&self) -> &[u8] {
+        &self.local_id[..self.local_id_size as usize]
+    }
+
+    /// The `LocalId` as a number. Change numbers are big-endian counters, so a later change from
+    /// the same replica has a greater value.
+    pub fn change_number(&self) -> u64 {
+        self.local_id()
+            .iter()
+            .fold(0, |value, byte| (value << 8) | u64::from(*byte))
+    }
+}
+
+impl TryFrom<&PropertyValue> for Xid {
+    type Error = io::Error;
+
+    fn try_from(value: &PropertyValue) -> Result<Self, Self::Error> {
+        match value {
+            PropertyValue::Binary(value) => Self::read(value.buffer()),
+            invalid => Err(MessagingError::InvalidChangeKey(PropertyType::from(invalid)).into()),
+        }
+    }
+}
+
+/// [PidTagPredecessorChangeList](https://learn.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-oxprops/ff4e4f9a-af0d-4fb6-8ab8-f3a6b5fe1d5c):
+/// a list of `SizedXid` structures, each of them a one byte size followed by an [`Xid`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PredecessorChangeList {
+    xids: Vec<Xid>,
+}
+
+impl PredecessorChangeList {
+    pub fn read(data: &[u8]) -> io::Result<Self> {
+        let mut xids = vec![];
+        let mut remaining = data;
+        while let Some((&size, rest)) = remaining.split_first() {
+            let size = size as usize;
+            if size > rest.len() {
+                return Err(MessagingError::InvalidXidSize(size).into());
+            }
+            let (xid, rest) = rest.split_at(size);
+            xids.push(Xid::read(xid)?);
+            remaining = rest;
+        }
+        Ok(Self { xids })
+    }
+
+    pub fn xids(&self) -> &[Xid] {
+        &self.xids
+    }
+
+    /// Find the latest change from the replica with this `namespace`.
+    pub fn find(&self, namespace: GuidValue) -> Option<&Xid> {
+        self.xids.iter().find(|xid| xid.namespace == namespace)
+    }
+
+    /// Check whether the object already includes the change `xid`, i.e. the list has a change
+    /// from the same replica which is at least as recent. If a replica has a change key which
+    /// the other replica's list includes, the other version of the object supersedes it;
+    /// otherwise the two versions are in conflict unless the lists include each other.
+    pub fn includes(&self, xid: &Xid) -> bool {
+        self.find(xid.namespace)
+            .is_some_and(|latest| latest.change_number() >= xid.change_number())
+    }
+}
+
+impl TryFrom<&PropertyValue> for PredecessorChangeList {
+    type Error = io::Error;
+
+    fn try_from(value: &PropertyValue) -> Result<Self, Self::Error> {
+        match value {
+            PropertyValue::Binary(value) => Self::read(value.buffer()),
+            invalid => Err(
+                MessagingError::InvalidPredecessorChangeList(PropertyType::from(invalid)).into(),
+            ),
+        }
+    }
+}
+
+/// The `PidTagChangeKey` and `PidTagPredecessorChangeList` of a folder or message. Objects which
+/// were never synchronized with Exchange have neither of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SyncState {
+    change_key: Option<Xid>,
+    predecessor_change_list: Option<PredecessorChangeList>,
+}
+
+impl SyncState {
+    /// Parse the change tracking properties out of a property `get` function, such as
+    /// [`MessageProperties::get`](super::message::MessageProperties::get).
+    pub fn read<'a>(get: impl Fn(u16) -> Option<&'a PropertyValue>) -> io::Result<Self> {
+        Ok(Self {
+            change_key: get(PID_TAG_CHANGE_KEY).map(Xid::try_from).transpose()?,
+            predecessor_change_list: get(PID_TAG_PREDECESSOR_CHANGE_LIST)
+                .map(PredecessorChangeList::try_from)
+                .transpose()?,
+        })
+    }
+
+    pub fn change_key(&self) -> Option<&Xid> {
+        self.change_key.as_ref()
+    }
+
+    pub fn predecessor_change_list(&self) -> Option<&PredecessorChangeList> {
+        self.predecessor_change_list.as_ref()
+    }
+
+    /// Check whether this version of the object already includes the last change to `other`.
+    /// A version without a `PidTagPredecessorChangeList` is treated as if its list only held its
+    /// own change key.
+    pub fn includes(&self, other: &SyncState) -> bool {
+        let Some(change_key) = other.change_key.as_ref() else {
+            return true;
+        };
+        match (&self.predecessor_change_list, &self.change_key) {
+            (Some(list), _) => list.includes(change_key),
+            (None, Some(own)) => {
+                own.namespace == change_key.namespace
+                    && own.change_number() >= change_key.change_number()
+            }
+            (None, None) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ltp::prop_context::BinaryValue;
+
+    const REPLICA: [u8; 16] = [
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+        0x10,
+    ];
+
+    fn xid(change_number: u8) -> Vec<u8> {
+        [&REPLICA[..], &[0, 0, 0, 0, 0, change_number]].concat()
+    }
+
+    #[test]
+    fn test_sync_state() {
+        let change_key = Xid::read(&xid(5)).unwrap();
+        assert_eq!(
+            change_key.namespace(),
+            GuidValue::new(
+                0x04030201,
+                0x0605,
+                0x0807,
+                [0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F, 0x10]
+            )
+        );
+        assert_eq!(change_key.local_id(), [0, 0, 0, 0, 0, 5]);
+        assert_eq!(change_key.change_number(), 5);
+        assert!(Xid::read(&REPLICA).is_err());
+
+        let other_replica = [&[0xFF; 16][..], &[1, 2]].concat();
+        let list = [
+            &[other_replica.len() as u8][..],
+            &other_replica,
+            &[22],
+            &xid(7),
+        ]
+        .concat();
+        let list = PredecessorChangeList::read(&list).unwrap();
+        assert_eq!(list.xids().len(), 2);
+        assert!(list.includes(&change_key));
+        assert!(!list.includes(&Xid::read(&xid(8)).unwrap()));
+        assert!(PredecessorChangeList::read(&[22, 0, 0]).is_err());
+
+        let newer = PropertyValue::Binary(BinaryValue::new(xid(7)));
+        let properties = [
+            (
+                PID_TAG_CHANGE_KEY,
+                PropertyValue::Binary(BinaryValue::new(xid(5))),
+            ),
+            (
+                PID_TAG_PREDECESSOR_CHANGE_LIST,
+                PropertyValue::Binary(BinaryValue::new([&[22][..], &xid(5)].concat())),
+            ),
+        ];
+        let older = SyncState::read(|prop_id| {
+            properties
+                .iter()
+                .find(|(id, _)| *id == prop_id)
+                .map(|(_, value)| value)
+        })
+        .unwrap();
+        let newer = SyncState::read(|prop_id| match prop_id {
+            PID_TAG_CHANGE_KEY => Some(&newer),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(older.change_key(), Some(&change_key));
+        assert!(!older.includes(&newer));
+        assert!(newer.includes(&older));
+        assert!(older.includes(&SyncState::default()));
+    }
+}
@@ -0,0 +1,263 @@
+//! Index of the messages in a folder by `PidTagMessageDeliveryTime`, so repeated date-range
+//! queries do not have to decode every row of the contents table again.
+//!
+//! The index can be saved in a folder associated information (FAI) message with
+//! [`PstFileLockGuard::save_time_index`](crate::PstFileLockGuard::save_time_index). It is keyed
+//! by a hash of the row IDs in the contents table, so [`FolderTimeIndex::load`] ignores an index
+//! which was built before messages were added to or removed from the folder.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    collections::BTreeMap,
+    io::{self, Cursor, Read, Write},
+};
+use tracing::warn;
+
+use super::{
+    folder::Folder,
+    message::{MessageBuilder, MSGFLAG_ASSOCIATED, MSGFLAG_READ},
+};
+use crate::ltp::{
+    prop_context::{BinaryValue, PropertyValue},
+    table_context::{TableContext, TableRowId},
+};
+
+/// `PidTagMessageClass` of the FAI message which holds a saved [`FolderTimeIndex`].
+pub const TIME_INDEX_MESSAGE_CLASS: &str = "IPM.Configuration.TimeIndex";
+
+/// `PidTagRoamingBinary`, which holds the serialized index in the FAI message.
+const PID_TAG_ROAMING_BINARY: u16 = 0x7C09;
+
+const INDEX_MAGIC: &[u8; 8] = b"PSTTIDX1";
+
+/// `(PidTagMessageDeliveryTime, row ID)` for each message in the contents table of a folder,
+/// sorted by delivery time. The row ID is the [`NodeId`](crate::ndb::node_id::NodeId) of the
+/// message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FolderTimeIndex {
+    key: u64,
+    entries: Vec<(i64, TableRowId)>,
+}
+
+impl FolderTimeIndex {
+    /// Read the `PidTagMessageDeliveryTime` column of every row in the contents table of
+    /// `folder`. Rows without a delivery time, e.g. drafts, are left out of the index.
+    pub fn build<F: Folder + ?Sized>(folder: &F) -> io::Result<Self> {
+        let Some(contents_table) = folder.contents_table() else {
+            return Ok(Self {
+                key: Self::table_key(None)?,
+                entries: Default::default(),
+            });
+        };
+
+        let column = contents_table
+            .context()
+            .columns()
+            .iter()
+            .enumerate()
+            .find(|(_, column)| column.prop_id() == 0x0E06);
+
+        let mut entries = vec![];
+        if let Some((index, column)) = column {
+            contents_table.for_each_row(&mut |row| {
+                let Some(value) = row.column(index)? else {
+                    return Ok(());
+                };
+                if let PropertyValue::Time(time) =
+                    contents_table.read_column(&value, column.prop_type())?
+                {
+                    entries.push((time, row.id()));
+                }
+                Ok(())
+            })?;
+        }
+        entries.sort();
+
+        Ok(Self {
+            key: Self::table_key(Some(contents_table.as_ref()))?,
+            entries,
+        })
+    }
+
+    /// Hash of the row IDs in the contents table of `folder`, using 64-bit FNV-1a so the key is
+    /// stable between builds of the library.
+    pub fn folder_key<F: Folder + ?Sized>(folder: &F) -> io::Result<u64> {
+        Self::table_key(folder.contents_table().map(AsRef::as_ref))
+    }
+
+    fn table_key(contents_table: Option<&dyn TableContext>) -> io::Result<u64> {
+        let mut hash = 0xCBF2_9CE4_8422_2325;
+        if let Some(contents_table) = contents_table {
+            contents_table.for_each_row(&mut |row| {
+                for b in u32::from(row.id()).to_le_bytes() {
+                    hash = (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01B3);
+                }
+                Ok(())
+            })?;
+        }
+        Ok(hash)
+    }
+
+    /// Read the index from the FAI message in `folder`. Returns `None` if there is no saved
+    /// index, or if it is stale or unreadable, in which case it should be rebuilt.
+    pub fn load<F: Folder + ?Sized>(folder: &F) -> io::Result<Option<Self>> {
+        let Some(associated_table) = folder.associated_table() else {
+            return Ok(None);
+        };
+
+        for row in associated_table.rows_matrix() {
+            let message =
+                folder.open_message_at_row(row, Some(&[0x001A, PID_TAG_ROAMING_BINARY]))?;
+            let properties = message.properties();
+            if !is_time_index_message_class(properties.get(0x001A)) {
+                continue;
+            }
+
+            let index = match properties.get(PID_TAG_ROAMING_BINARY) {
+                Some(PropertyValue::Binary(value)) => Self::read(&mut Cursor::new(value.buffer())),
+                _ => Err(io::ErrorKind::InvalidData.into()),
+            };
+            return match index {
+                Ok(index) if index.is_current(folder)? => Ok(Some(index)),
+                Ok(_) => Ok(None),
+                Err(err) => {
+                    warn!(
+                        name: "PstTimeIndexInvalid",
+                        "Ignoring folder time index: {err:?}"
+                    );
+                    Ok(None)
+                }
+            };
+        }
+
+        Ok(None)
+    }
+
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    pub fn entries(&self) -> &[(i64, TableRowId)] {
+        &self.entries
+    }
+
+    /// The entries whose delivery time is at least `start` and less than `end`, both as a
+    /// `FILETIME`.
+    pub fn range(&self, start: i64, end: i64) -> &[(i64, TableRowId)] {
+        let from = self.entries.partition_point(|(time, _)| *time < start);
+        let to = self.entries.partition_point(|(time, _)| *time < end);
+        &self.entries[from..to.max(from)]
+    }
+
+    /// Check whether the index still matches the contents table of `folder`.
+    pub fn is_current<F: Folder + ?Sized>(&self, folder: &F) -> io::Result<bool> {
+        Ok(self.key == Self::folder_key(folder)?)
+    }
+
+    /// Make the FAI message which holds the index, for
+    /// [`PstFileLockGuard::create_associated_message`](crate::PstFileLockGuard::create_associated_message)
+    /// or [`PstFileLockGuard::update_message`](crate::PstFileLockGuard::update_message).
+    pub fn to_message_builder(&self) -> io::Result<MessageBuilder> {
+        let mut data = vec![];
+        self.write(&mut data)?;
+
+        let mut builder = MessageBuilder::new(TIME_INDEX_MESSAGE_CLASS);
+        builder.set_property(
+            0x0E07,
+            PropertyValue::Integer32(MSGFLAG_READ | MSGFLAG_ASSOCIATED),
+        );
+        builder.set_property(
+            PID_TAG_ROAMING_BINARY,
+            PropertyValue::Binary(BinaryValue::new(data)),
+        );
+        Ok(builder)
+    }
+
+    pub fn read(f: &mut dyn Read) -> io::Result<Self> {
+        let mut magic = [0; INDEX_MAGIC.len()];
+        f.read_exact(&mut magic)?;
+        if magic != *INDEX_MAGIC {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        let key = f.read_u64::<LittleEndian>()?;
+        let count = f.read_u32::<LittleEndian>()?;
+        let entries = (0..count)
+            .map(|_| {
+                let time = f.read_i64::<LittleEndian>()?;
+                let row_id = TableRowId::new(f.read_u32::<LittleEndian>()?);
+                Ok((time, row_id))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        if !entries.is_sorted() {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        Ok(Self { key, entries })
+    }
+
+    pub fn write(&self, f: &mut dyn Write) -> io::Result<()> {
+        f.write_all(INDEX_MAGIC)?;
+        f.write_u64::<LittleEndian>(self.key)?;
+
+        let count = u32::try_from(self.entries.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        f.write_u32::<LittleEndian>(count)?;
+        for (time, row_id) in self.entries.iter() {
+            f.write_i64::<LittleEndian>(*time)?;
+            f.write_u32::<LittleEndian>(u32::from(*row_id))?;
+        }
+        Ok(())
+    }
+}
+
+fn is_time_index_message_class(value: Option<&PropertyValue>) -> bool {
+    let message_class = match value {
+        Some(PropertyValue::String8(value)) => value.to_string(),
+        Some(PropertyValue::Unicode(value)) => value.to_string(),
+        _ => return false,
+    };
+    message_class.eq_ignore_ascii_case(TIME_INDEX_MESSAGE_CLASS)
+}
+
+/// Whether the properties of an FAI message, read with
+/// [`read_property_context`](crate::ltp::prop_context::read_property_context), are those of a
+/// saved [`FolderTimeIndex`].
+pub(crate) fn is_time_index_message(properties: &BTreeMap<u16, PropertyValue>) -> bool {
+    is_time_index_message_class(properties.get(&0x001A))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_index_range() {
+        let index = FolderTimeIndex {
+            key: 1,
+            entries: vec![
+                (100, TableRowId::new(0x24)),
+                (200, TableRowId::new(0x44)),
+                (200, TableRowId::new(0x64)),
+                (300, TableRowId::new(0x84)),
+            ],
+        };
+
+        assert_eq!(index.range(0, 100), &[]);
+        assert_eq!(index.range(100, 101), &index.entries()[..1]);
+        assert_eq!(index.range(150, 300), &index.entries()[1..3]);
+        assert_eq!(index.range(200, i64::MAX), &index.entries()[1..]);
+        assert_eq!(index.range(300, 100), &[]);
+
+        let mut data = vec![];
+        index.write(&mut data).unwrap();
+        assert_eq!(
+            FolderTimeIndex::read(&mut Cursor::new(data.as_slice())).unwrap(),
+            index
+        );
+
+        data[INDEX_MAGIC.len() + 12..INDEX_MAGIC.len() + 20]
+            .copy_from_slice(&400_i64.to_le_bytes());
+        assert!(FolderTimeIndex::read(&mut Cursor::new(data.as_slice())).is_err());
+    }
+}
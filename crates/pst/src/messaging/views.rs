@@ -0,0 +1,412 @@
+//! Views which Outlook saves in folder associated information (FAI) messages, e.g. the named
+//! views a user created on a folder with their own sort order and grouping.
+//!
+//! Each view is an FAI message with a `PidTagMessageClass` of
+//! [`NAMED_VIEW_MESSAGE_CLASS`] or another class starting with [`FOLDER_DESIGN_MESSAGE_CLASS`].
+//! The name of the view is in `PidTagViewDescriptorName`, and the columns, sort order and
+//! grouping are serialized in `PidTagViewDescriptorBinary`, which [`ViewDescriptor::read`]
+//! decodes.
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::io::{self, Cursor, Read};
+
+use super::folder::Folder;
+use crate::ltp::{prop_context::PropertyValue, prop_type::PropertyType};
+
+/// Prefix of the `PidTagMessageClass` of every FAI message which holds a view.
+pub const FOLDER_DESIGN_MESSAGE_CLASS: &str = "IPM.Microsoft.FolderDesign.";
+
+/// `PidTagMessageClass` of the FAI messages which hold named views.
+pub const NAMED_VIEW_MESSAGE_CLASS: &str = "IPM.Microsoft.FolderDesign.NamedView";
+
+/// `PidTagViewDescriptorBinary`
+const PID_TAG_VIEW_DESCRIPTOR_BINARY: u16 = 0x7001;
+/// `PidTagViewDescriptorFlags`
+const PID_TAG_VIEW_DESCRIPTOR_FLAGS: u16 = 0x7003;
+/// `PidTagViewDescriptorName`
+const PID_TAG_VIEW_DESCRIPTOR_NAME: u16 = 0x7006;
+/// `PidTagViewDescriptorVersion`
+const PID_TAG_VIEW_DESCRIPTOR_VERSION: u16 = 0x7007;
+
+/// The only version of `PidTagViewDescriptorBinary` which [`ViewDescriptor::read`] accepts.
+pub const VIEW_DESCRIPTOR_VERSION: u32 = 0x0000_0008;
+
+/// A view saved in an FAI message of a folder.
+#[derive(Clone, Debug)]
+pub struct FolderView {
+    message_class: String,
+    name: Option<String>,
+    flags: Option<i32>,
+    version: Option<i32>,
+    descriptor: Option<ViewDescriptor>,
+}
+
+impl FolderView {
+    /// Read every view in the associated contents table of `folder`. A view with a
+    /// `PidTagViewDescriptorBinary` which cannot be decoded is still listed, without a
+    /// [`FolderView::descriptor`].
+    pub fn list<F: Folder + ?Sized>(folder: &F) -> io::Result<Vec<Self>> {
+        let Some(associated_table) = folder.associated_table() else {
+            return Ok(Default::default());
+        };
+
+        let mut views = vec![];
+        for row in associated_table.rows_matrix() {
+            let message = folder.open_message_at_row(
+                row,
+                Some(&[
+                    0x001A,
+                    PID_TAG_VIEW_DESCRIPTOR_BINARY,
+                    PID_TAG_VIEW_DESCRIPTOR_FLAGS,
+                    PID_TAG_VIEW_DESCRIPTOR_NAME,
+                    PID_TAG_VIEW_DESCRIPTOR_VERSION,
+                ]),
+            )?;
+            let properties = message.properties();
+            let Ok(message_class) = properties.message_class() else {
+                continue;
+            };
+            if !is_folder_design_message_class(&message_class) {
+                continue;
+            }
+
+            let name = match properties.get(PID_TAG_VIEW_DESCRIPTOR_NAME) {
+                Some(PropertyValue::String8(value)) => {
+                    Some(value.to_string_with_decoder(properties.string_decoder()))
+                }
+                Some(PropertyValue::Unicode(value)) => Some(value.to_string()),
+                _ => None,
+            };
+            let integer = |prop_id| match properties.get(prop_id) {
+                Some(PropertyValue::Integer32(value)) => Some(*value),
+                _ => None,
+            };
+            let descriptor = match properties.get(PID_TAG_VIEW_DESCRIPTOR_BINARY) {
+                Some(PropertyValue::Binary(value)) => {
+                    ViewDescriptor::read(&mut Cursor::new(value.buffer())).ok()
+                }
+                _ => None,
+            };
+
+            views.push(Self {
+                message_class,
+                name,
+                flags: integer(PID_TAG_VIEW_DESCRIPTOR_FLAGS),
+                version: integer(PID_TAG_VIEW_DESCRIPTOR_VERSION),
+                descriptor,
+            });
+        }
+
+        Ok(views)
+    }
+
+    /// `PidTagMessageClass` of the FAI message.
+    pub fn message_class(&self) -> &str {
+        &self.message_class
+    }
+
+    /// `PidTagViewDescriptorName`
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// `PidTagViewDescriptorFlags`
+    pub fn flags(&self) -> Option<i32> {
+        self.flags
+    }
+
+    /// `PidTagViewDescriptorVersion`
+    pub fn version(&self) -> Option<i32> {
+        self.version
+    }
+
+    /// The decoded `PidTagViewDescriptorBinary`.
+    pub fn descriptor(&self) -> Option<&ViewDescriptor> {
+        self.descriptor.as_ref()
+    }
+}
+
+fn is_folder_design_message_class(message_class: &str) -> bool {
+    message_class
+        .get(..FOLDER_DESIGN_MESSAGE_CLASS.len())
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case(FOLDER_DESIGN_MESSAGE_CLASS))
+}
+
+/// A property tag in a [`ViewDescriptor`]. Named properties use the property ID which they are
+/// mapped to in the store, see [`NamedPropertyMap`](super::named_prop::NamedPropertyMap).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ViewPropertyTag(u32);
+
+impl ViewPropertyTag {
+    pub fn new(prop_id: u16, prop_type: PropertyType) -> Self {
+        Self((u32::from(prop_id) << 16) | u32::from(u16::from(prop_type)))
+    }
+
+    pub fn prop_id(&self) -> u16 {
+        (self.0 >> 16) as u16
+    }
+
+    /// The property type, or `None` if it is not one which this crate recognizes.
+    pub fn prop_type(&self) -> Option<PropertyType> {
+        PropertyType::try_from(self.0 as u16).ok()
+    }
+}
+
+impl From<u32> for ViewPropertyTag {
+    fn from(value: u32) -> Self {
+        Self(value)
+    }
+}
+
+impl From<ViewPropertyTag> for u32 {
+    fn from(value: ViewPropertyTag) -> Self {
+        value.0
+    }
+}
+
+/// A column which the view shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ViewColumn {
+    flags: u32,
+    width: u32,
+    prop_tag: ViewPropertyTag,
+}
+
+impl ViewColumn {
+    pub fn new(prop_tag: ViewPropertyTag, width: u32, flags: u32) -> Self {
+        Self {
+            flags,
+            width,
+            prop_tag,
+        }
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Width of the column, in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn prop_tag(&self) -> ViewPropertyTag {
+        self.prop_tag
+    }
+}
+
+/// `ulOrder` of a MAPI `SSortOrder`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    /// `TABLE_SORT_ASCEND`
+    Ascending,
+    /// `TABLE_SORT_DESCEND`
+    Descending,
+    /// `TABLE_SORT_COMBINE`: Combined with the previous column into a single category.
+    Combine,
+    /// `TABLE_SORT_CATEG_MAX`: Sort the categories by the maximum value of this column.
+    CategoryMax,
+    /// `TABLE_SORT_CATEG_MIN`: Sort the categories by the minimum value of this column.
+    CategoryMin,
+    Unknown(u32),
+}
+
+impl From<u32> for SortOrder {
+    fn from(value: u32) -> Self {
+        match value {
+            0x0000_0000 => Self::Ascending,
+            0x0000_0001 => Self::Descending,
+            0x0000_0002 => Self::Combine,
+            0x0000_0004 => Self::CategoryMax,
+            0x0000_0008 => Self::CategoryMin,
+            value => Self::Unknown(value),
+        }
+    }
+}
+
+impl From<SortOrder> for u32 {
+    fn from(value: SortOrder) -> Self {
+        match value {
+            SortOrder::Ascending => 0x0000_0000,
+            SortOrder::Descending => 0x0000_0001,
+            SortOrder::Combine => 0x0000_0002,
+            SortOrder::CategoryMax => 0x0000_0004,
+            SortOrder::CategoryMin => 0x0000_0008,
+            SortOrder::Unknown(value) => value,
+        }
+    }
+}
+
+/// A column which the view is sorted or grouped by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SortColumn {
+    prop_tag: ViewPropertyTag,
+    order: SortOrder,
+}
+
+impl SortColumn {
+    pub fn new(prop_tag: ViewPropertyTag, order: SortOrder) -> Self {
+        Self { prop_tag, order }
+    }
+
+    pub fn prop_tag(&self) -> ViewPropertyTag {
+        self.prop_tag
+    }
+
+    pub fn order(&self) -> SortOrder {
+        self.order
+    }
+}
+
+/// The decoded `PidTagViewDescriptorBinary` of a view.
+///
+/// The layout is a `u32` version, which must be [`VIEW_DESCRIPTOR_VERSION`], followed by a
+/// `u32` count of columns, each with `u32` flags, width and property tag, and then a MAPI
+/// `SSortOrderSet`: `u32` counts of sort columns, categories and expanded categories, followed by
+/// the property tag and `u32` order of each sort column. The first `categories` sort columns are
+/// the ones the view is grouped by.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ViewDescriptor {
+    columns: Vec<ViewColumn>,
+    sort_columns: Vec<SortColumn>,
+    categories: usize,
+    expanded: usize,
+}
+
+impl ViewDescriptor {
+    /// Fails with [`io::ErrorKind::InvalidData`] if there are more categories than sort columns,
+    /// or more expanded categories than categories.
+    pub fn new(
+        columns: Vec<ViewColumn>,
+        sort_columns: Vec<SortColumn>,
+        categories: usize,
+        expanded: usize,
+    ) -> io::Result<Self> {
+        if categories > sort_columns.len() || expanded > categories {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        Ok(Self {
+            columns,
+            sort_columns,
+            categories,
+            expanded,
+        })
+    }
+
+    pub fn read(f: &mut dyn Read) -> io::Result<Self> {
+        if f.read_u32::<LittleEndian>()? != VIEW_DESCRIPTOR_VERSION {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+
+        // Don't trust the counts for the size of the allocation, a truncated buffer fails while
+        // reading the entries instead.
+        let column_count = f.read_u32::<LittleEndian>()?;
+        let mut columns = vec![];
+        for _ in 0..column_count {
+            let flags = f.read_u32::<LittleEndian>()?;
+            let width = f.read_u32::<LittleEndian>()?;
+            let prop_tag = ViewPropertyTag::from(f.read_u32::<LittleEndian>()?);
+            columns.push(ViewColumn::new(prop_tag, width, flags));
+        }
+
+        let sort_count = f.read_u32::<LittleEndian>()?;
+        let categories = f.read_u32::<LittleEndian>()? as usize;
+        let expanded = f.read_u32::<LittleEndian>()? as usize;
+        let mut sort_columns = vec![];
+        for _ in 0..sort_count {
+            let prop_tag = ViewPropertyTag::from(f.read_u32::<LittleEndian>()?);
+            let order = SortOrder::from(f.read_u32::<LittleEndian>()?);
+            sort_columns.push(SortColumn::new(prop_tag, order));
+        }
+
+        Self::new(columns, sort_columns, categories, expanded)
+    }
+
+    pub fn columns(&self) -> &[ViewColumn] {
+        &self.columns
+    }
+
+    /// Every sort column, including the ones the view is grouped by.
+    pub fn sort_columns(&self) -> &[SortColumn] {
+        &self.sort_columns
+    }
+
+    /// The columns the view is grouped by, outermost first.
+    pub fn group_by(&self) -> &[SortColumn] {
+        &self.sort_columns[..self.categories]
+    }
+
+    /// The columns the messages in each group are sorted by.
+    pub fn sort_by(&self) -> &[SortColumn] {
+        &self.sort_columns[self.categories..]
+    }
+
+    /// Number of [`ViewDescriptor::group_by`] columns which are expanded when the view is
+    /// opened.
+    pub fn expanded(&self) -> usize {
+        self.expanded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::WriteBytesExt;
+
+    #[test]
+    fn test_view_descriptor() {
+        let mut data = vec![];
+        for value in [
+            VIEW_DESCRIPTOR_VERSION,
+            2,
+            // PidTagSubject
+            0,
+            200,
+            0x0037_001F,
+            // PidTagMessageDeliveryTime
+            1,
+            80,
+            0x0E06_0040,
+            2,
+            1,
+            0,
+            0x0C1A_001F,
+            0,
+            0x0E06_0040,
+            1,
+        ] {
+            data.write_u32::<LittleEndian>(value).unwrap();
+        }
+
+        let descriptor = ViewDescriptor::read(&mut Cursor::new(&data)).unwrap();
+        assert_eq!(descriptor.columns().len(), 2);
+        assert_eq!(descriptor.columns()[0].prop_tag().prop_id(), 0x0037);
+        assert_eq!(
+            descriptor.columns()[1].prop_tag(),
+            ViewPropertyTag::new(0x0E06, PropertyType::Time)
+        );
+        assert_eq!(descriptor.columns()[1].width(), 80);
+        assert_eq!(
+            descriptor.group_by(),
+            [SortColumn::new(
+                ViewPropertyTag::new(0x0C1A, PropertyType::Unicode),
+                SortOrder::Ascending
+            )]
+        );
+        assert_eq!(descriptor.sort_by()[0].order(), SortOrder::Descending);
+        assert_eq!(descriptor.expanded(), 0);
+
+        // More categories than sort columns.
+        let categories = data.len() - 24;
+        data[categories..categories + 4].copy_from_slice(&3_u32.to_le_bytes());
+        assert!(ViewDescriptor::read(&mut Cursor::new(&data)).is_err());
+
+        assert!(ViewDescriptor::read(&mut Cursor::new(&data[..data.len() - 4])).is_err());
+        assert!(is_folder_design_message_class(
+            "ipm.microsoft.folderdesign.namedview"
+        ));
+        assert!(!is_folder_design_message_class(
+            "IPM.Configuration.Annotations"
+        ));
+    }
+}
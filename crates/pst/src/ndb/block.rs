@@ -8,17 +8,68 @@ use std::{
 };
 use tracing::error;
 
-use super::{block_id::*, block_ref::*, byte_index::*, node_id::*, page::*, read_write::*, *};
-use crate::{AnsiPstFile, PstFile, PstFileReadWriteBlockBTree, PstReader, UnicodePstFile};
+use super::{
+    block_id::*, block_ref::*, byte_index::*, header::NdbVersion, node_id::*, page::*,
+    read_write::*, *,
+};
+use crate::{
+    block_sig::block_signature, crc::compute_crc, AnsiPstFile, PstFile, PstFileLock,
+    PstFileReadWriteBlockBTree, PstReader, UnicodePstFile,
+};
 
+/// Largest block on disk, including the [BLOCKTRAILER](BlockTrailer) and padding.
 pub const MAX_BLOCK_SIZE: u16 = 8192;
 
+/// Round the `size` of a block, including its [BLOCKTRAILER](BlockTrailer), up to the next
+/// multiple of 64 bytes, which is how much space it occupies on disk.
+///
+/// # Panics
+///
+/// Panics if `size` is 0 or more than [`MAX_BLOCK_SIZE`].
 pub const fn block_size(size: u16) -> u16 {
     assert!(size > 0);
     assert!(size <= MAX_BLOCK_SIZE);
     size.div_ceil(64) * 64
 }
 
+/// Size of the [BLOCKTRAILER](BlockTrailer) at the end of every block: 16 bytes in Unicode files
+/// and 12 bytes in ANSI files.
+pub const fn block_trailer_size(version: NdbVersion) -> u16 {
+    match version {
+        NdbVersion::Ansi => <AnsiBlockTrailer as BlockTrailerReadWrite>::SIZE,
+        NdbVersion::Unicode => <UnicodeBlockTrailer as BlockTrailerReadWrite>::SIZE,
+    }
+}
+
+/// Maximum number of bytes of data in one block, which is [`MAX_BLOCK_SIZE`] less the
+/// [`block_trailer_size`]. Larger streams are split into a data tree of XBLOCKs and XXBLOCKs.
+pub const fn max_block_data_size(version: NdbVersion) -> u16 {
+    MAX_BLOCK_SIZE - block_trailer_size(version)
+}
+
+/// Space on disk for a block with `data_size` bytes of data, including the
+/// [BLOCKTRAILER](BlockTrailer) and the padding before it.
+///
+/// # Panics
+///
+/// Panics if `data_size` is 0 or more than [`max_block_data_size`].
+pub const fn block_size_on_disk(version: NdbVersion, data_size: u16) -> u16 {
+    assert!(data_size > 0);
+    assert!(data_size <= max_block_data_size(version));
+    block_size(data_size + block_trailer_size(version))
+}
+
+/// Offset of the [BLOCKTRAILER](BlockTrailer) from the start of a block with `data_size` bytes
+/// of data. The trailer is always in the last bytes of the block, so any padding comes between
+/// the data and the trailer.
+///
+/// # Panics
+///
+/// Panics if `data_size` is 0 or more than [`max_block_data_size`].
+pub const fn block_trailer_offset(version: NdbVersion, data_size: u16) -> u16 {
+    block_size_on_disk(version, data_size) - block_trailer_size(version)
+}
+
 /// [BLOCKTRAILER](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/a14943ef-70c2-403f-898c-5bc3747117e1)
 pub trait BlockTrailer {
     type BlockId: BlockId;
@@ -29,6 +80,26 @@ pub trait BlockTrailer {
     fn block_id(&self) -> Self::BlockId;
     fn cyclic_key(&self) -> u32;
     fn verify_block_id(&self, is_internal: bool) -> NdbResult<()>;
+
+    /// Check the trailer of a block at file offset `offset`, whose data as it is stored on disk,
+    /// i.e. still encoded and without the padding, is `data`. `cb` must be the size of `data`,
+    /// `wSig` the [`block_signature`] of `offset` and `bid`, and `dwCRC` the [`compute_crc`] of
+    /// `data`. The errors hold the recomputed `wSig` or `dwCRC`, so a mismatched field can be
+    /// rewritten with them.
+    fn validate(&self, offset: u64, data: &[u8]) -> NdbResult<()> {
+        if usize::from(self.size()) != data.len() {
+            return Err(NdbError::InvalidBlockSize(self.size()));
+        }
+        let signature = block_signature(offset, self.block_id().into_u64());
+        if signature != self.signature() {
+            return Err(NdbError::InvalidBlockSignature(signature));
+        }
+        let crc = compute_crc(0, data);
+        if crc != self.crc() {
+            return Err(NdbError::InvalidBlockCrc(crc));
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Copy, Default)]
@@ -601,6 +672,46 @@ where
         IntermediateTreeEntryReadWrite,
     <Pst as PstFile>::DataBlock: BlockReadWrite,
 {
+    /// Maximum number of bytes of data in one leaf block of a data tree.
+    pub fn max_leaf_size() -> u16 {
+        MAX_BLOCK_SIZE - <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::SIZE
+    }
+
+    /// Total size of the data in the tree, which is the `lcbTotal` of an XBLOCK or XXBLOCK, or
+    /// the size of a single data block.
+    pub fn total_size(&self) -> u32 {
+        match self {
+            Self::Intermediate(block) => block.header().total_size(),
+            Self::Leaf(block) => block.data().len() as u32,
+        }
+    }
+
+    /// Maximum number of entries which fit in one XBLOCK or XXBLOCK.
+    pub fn max_intermediate_entries() -> usize {
+        usize::from(
+            (Self::max_leaf_size() - DataTreeBlockHeader::HEADER_SIZE)
+                / <<Pst as PstFile>::DataTreeEntry as IntermediateTreeEntryReadWrite>::ENTRY_SIZE,
+        )
+    }
+
+    /// Group the `(block, size)` children of one level of a data tree under the XBLOCKs or
+    /// XXBLOCKs in the next level up, along with the `lcbTotal` of each parent block.
+    pub fn intermediate_blocks<Block>(children: &[(Block, u32)]) -> Vec<(Vec<Block>, u32)>
+    where
+        Block: Copy,
+    {
+        children
+            .chunks(Self::max_intermediate_entries())
+            .map(|chunk| {
+                let blocks = chunk.iter().map(|(block, _)| *block).collect();
+                let total_size = chunk
+                    .iter()
+                    .fold(0_u32, |total, (_, size)| total.saturating_add(*size));
+                (blocks, total_size)
+            })
+            .collect()
+    }
+
     pub fn read<R>(
         f: &mut R,
         encoding: NdbCryptMethod,
@@ -994,6 +1105,30 @@ where
 pub type UnicodeDataTree = DataTree<UnicodePstFile>;
 pub type AnsiDataTree = DataTree<AnsiPstFile>;
 
+/// Write a stream to a new data tree, splitting it into data blocks and adding an XBLOCK, or an
+/// XBLOCK and an XXBLOCK, above them when it does not fit in a single block.
+pub struct DataTreeWriter<'a, Pst>
+where
+    Pst: PstFile,
+{
+    pst: &'a mut dyn PstFileLock<Pst>,
+}
+
+impl<'a, Pst> DataTreeWriter<'a, Pst>
+where
+    Pst: PstFile,
+{
+    pub(crate) fn new(pst: &'a mut dyn PstFileLock<Pst>) -> Self {
+        Self { pst }
+    }
+
+    /// Write everything in `f` to a new data tree, and return the root block of the tree along
+    /// with the total size of the data.
+    pub fn write(&mut self, f: &mut dyn Read) -> io::Result<(<Pst as PstFile>::BlockId, u32)> {
+        self.pst.write_data_tree(f)
+    }
+}
+
 #[derive(Clone, Copy, Default)]
 struct SubNodeTreeBlockHeader {
     level: u8,
@@ -1351,6 +1486,40 @@ where
     <<Pst as PstFile>::SubNodeBlock as IntermediateTreeBlock>::Entry:
         IntermediateTreeEntryReadWrite,
 {
+    /// Maximum number of [SLENTRY](LeafSubNodeTreeEntry) records which fit in one SLBLOCK.
+    pub fn max_leaf_entries() -> usize {
+        usize::from(
+            (MAX_BLOCK_SIZE
+                - <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::SIZE
+                - <<Pst as PstFile>::SubNodeTreeBlockHeader as IntermediateTreeHeaderReadWrite>::HEADER_SIZE)
+                / <<<Pst as PstFile>::SubNodeBlock as IntermediateTreeBlock>::Entry as IntermediateTreeEntryReadWrite>::ENTRY_SIZE,
+        )
+    }
+
+    /// Maximum number of [SIENTRY](IntermediateSubNodeTreeEntry) records which fit in one
+    /// SIBLOCK.
+    pub fn max_intermediate_entries() -> usize {
+        usize::from(
+            (MAX_BLOCK_SIZE
+                - <<Pst as PstFile>::BlockTrailer as BlockTrailerReadWrite>::SIZE
+                - <<Pst as PstFile>::SubNodeTreeBlockHeader as IntermediateTreeHeaderReadWrite>::HEADER_SIZE)
+                / <<<Pst as PstFile>::SubNodeTreeBlock as IntermediateTreeBlock>::Entry as IntermediateTreeEntryReadWrite>::ENTRY_SIZE,
+        )
+    }
+
+    /// Split a sorted list of sub-node entries into the SLBLOCKs which hold them. If there is
+    /// more than one, they need an SIBLOCK above them, and since the sub-node tree only has one
+    /// level of SIBLOCK, that limits how many sub-nodes a node can have.
+    pub fn leaf_blocks(
+        entries: &[LeafSubNodeTreeEntry<<Pst as PstFile>::BlockId>],
+    ) -> NdbResult<Vec<&[LeafSubNodeTreeEntry<<Pst as PstFile>::BlockId>]>> {
+        let leaves: Vec<_> = entries.chunks(Self::max_leaf_entries()).collect();
+        if leaves.len() > Self::max_intermediate_entries() {
+            return Err(NdbError::TooManySubNodes(entries.len()));
+        }
+        Ok(leaves)
+    }
+
     pub fn read<R: PstReader>(
         f: &mut R,
         block: &<Pst as PstFile>::BlockBTreeEntry,
@@ -1499,3 +1668,181 @@ where
 
 pub type UnicodeSubNodeTree = SubNodeTree<UnicodePstFile>;
 pub type AnsiSubNodeTree = SubNodeTree<AnsiPstFile>;
+
+/// Insert `entry` into a list of sub-node entries sorted by [`NodeId`], and return the entry it
+/// replaced if there was already one for the same sub-node.
+pub fn insert_sub_node_entry<Block>(
+    entries: &mut Vec<LeafSubNodeTreeEntry<Block>>,
+    entry: LeafSubNodeTreeEntry<Block>,
+) -> Option<LeafSubNodeTreeEntry<Block>>
+where
+    Block: BlockId,
+{
+    let node = u32::from(entry.node());
+    match entries.binary_search_by_key(&node, |entry| u32::from(entry.node())) {
+        Ok(index) => Some(std::mem::replace(&mut entries[index], entry)),
+        Err(index) => {
+            entries.insert(index, entry);
+            None
+        }
+    }
+}
+
+/// Remove the entry for `node` from a list of sub-node entries sorted by [`NodeId`].
+pub fn remove_sub_node_entry<Block>(
+    entries: &mut Vec<LeafSubNodeTreeEntry<Block>>,
+    node: NodeId,
+) -> Option<LeafSubNodeTreeEntry<Block>>
+where
+    Block: BlockId,
+{
+    let node = u32::from(node);
+    entries
+        .binary_search_by_key(&node, |entry| u32::from(entry.node()))
+        .ok()
+        .map(|index| entries.remove(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sub_node_entries(count: u32) -> Vec<UnicodeLeafSubNodeTreeEntry> {
+        (0..count)
+            .map(|index| {
+                let node = NodeId::new(NodeIdType::Attachment, index + 1).unwrap();
+                let block = UnicodeBlockId::new(false, u64::from(index) + 1).unwrap();
+                LeafSubNodeTreeEntry::new(node, block, None)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sub_node_entry_insert_remove() {
+        let mut entries = sub_node_entries(3);
+        let second = entries.remove(1);
+        assert!(insert_sub_node_entry(&mut entries, second).is_none());
+        assert_eq!(u32::from(entries[1].node()), u32::from(second.node()));
+
+        let replacement = LeafSubNodeTreeEntry::new(
+            second.node(),
+            UnicodeBlockId::new(false, 100).unwrap(),
+            None,
+        );
+        let replaced = insert_sub_node_entry(&mut entries, replacement).unwrap();
+        assert_eq!(replaced.block(), second.block());
+        assert_eq!(entries.len(), 3);
+
+        let removed = remove_sub_node_entry(&mut entries, second.node()).unwrap();
+        assert_eq!(removed.block(), replacement.block());
+        assert!(remove_sub_node_entry(&mut entries, second.node()).is_none());
+        assert_eq!(entries.len(), 2);
+    }
+
+    #[test]
+    fn test_block_trailer_validate() {
+        let data = b"block data";
+        let offset = 0x4600;
+        let block_id = UnicodeBlockId::new(false, 9).unwrap();
+        let trailer = UnicodeBlockTrailer::new(
+            data.len() as u16,
+            block_signature(offset, block_id.into_u64()),
+            compute_crc(0, data),
+            block_id,
+        )
+        .unwrap();
+        assert!(trailer.validate(offset, data).is_ok());
+
+        let moved = block_signature(offset + 0x40, block_id.into_u64());
+        assert!(matches!(
+            trailer.validate(offset + 0x40, data),
+            Err(NdbError::InvalidBlockSignature(signature)) if signature == moved
+        ));
+        let changed = b"block DATA";
+        assert!(matches!(
+            trailer.validate(offset, changed),
+            Err(NdbError::InvalidBlockCrc(crc)) if crc == compute_crc(0, changed)
+        ));
+        assert!(matches!(
+            trailer.validate(offset, &data[1..]),
+            Err(NdbError::InvalidBlockSize(10))
+        ));
+    }
+
+    #[test]
+    fn test_block_layout() {
+        assert_eq!(block_size(1), 64);
+        assert_eq!(block_size(64), 64);
+        assert_eq!(block_size(65), 128);
+        assert_eq!(block_size(MAX_BLOCK_SIZE), MAX_BLOCK_SIZE);
+
+        assert_eq!(block_trailer_size(NdbVersion::Unicode), 16);
+        assert_eq!(block_trailer_size(NdbVersion::Ansi), 12);
+        assert_eq!(
+            max_block_data_size(NdbVersion::Unicode),
+            UnicodeDataTree::max_leaf_size()
+        );
+        assert_eq!(
+            max_block_data_size(NdbVersion::Ansi),
+            AnsiDataTree::max_leaf_size()
+        );
+
+        assert_eq!(block_size_on_disk(NdbVersion::Unicode, 48), 64);
+        assert_eq!(block_size_on_disk(NdbVersion::Unicode, 49), 128);
+        assert_eq!(block_size_on_disk(NdbVersion::Ansi, 52), 64);
+        assert_eq!(block_size_on_disk(NdbVersion::Ansi, 53), 128);
+        assert_eq!(
+            block_size_on_disk(NdbVersion::Unicode, 8176),
+            MAX_BLOCK_SIZE
+        );
+
+        assert_eq!(block_trailer_offset(NdbVersion::Unicode, 10), 48);
+        assert_eq!(block_trailer_offset(NdbVersion::Ansi, 10), 52);
+        assert_eq!(block_trailer_offset(NdbVersion::Unicode, 8176), 8176);
+    }
+
+    #[test]
+    fn test_data_tree_intermediate_blocks() {
+        assert_eq!(UnicodeDataTree::max_leaf_size(), 8176);
+        assert_eq!(UnicodeDataTree::max_intermediate_entries(), 1021);
+        assert_eq!(AnsiDataTree::max_leaf_size(), 8180);
+        assert_eq!(AnsiDataTree::max_intermediate_entries(), 2043);
+
+        let blocks: Vec<_> = (0..1022_u32).map(|index| (index, 8176)).collect();
+        let xblocks = UnicodeDataTree::intermediate_blocks(&blocks);
+        assert_eq!(xblocks.len(), 2);
+        assert_eq!(xblocks[0].0.len(), 1021);
+        assert_eq!(xblocks[0].1, 1021 * 8176);
+        assert_eq!(xblocks[1], (vec![1021], 8176));
+
+        let xblocks: Vec<_> = xblocks
+            .into_iter()
+            .enumerate()
+            .map(|(index, (_, size))| (index, size))
+            .collect();
+        let xxblocks = UnicodeDataTree::intermediate_blocks(&xblocks);
+        assert_eq!(xxblocks, vec![(vec![0, 1], 1022 * 8176)]);
+    }
+
+    #[test]
+    fn test_sub_node_leaf_blocks() {
+        assert_eq!(UnicodeSubNodeTree::max_leaf_entries(), 340);
+        assert_eq!(UnicodeSubNodeTree::max_intermediate_entries(), 510);
+        assert_eq!(AnsiSubNodeTree::max_leaf_entries(), 681);
+        assert_eq!(AnsiSubNodeTree::max_intermediate_entries(), 1022);
+
+        let entries = sub_node_entries(340);
+        assert_eq!(UnicodeSubNodeTree::leaf_blocks(&entries).unwrap().len(), 1);
+
+        let entries = sub_node_entries(341);
+        let leaves = UnicodeSubNodeTree::leaf_blocks(&entries).unwrap();
+        assert_eq!(leaves.len(), 2);
+        assert_eq!(leaves[1].len(), 1);
+
+        let entries = sub_node_entries(340 * 510 + 1);
+        assert!(matches!(
+            UnicodeSubNodeTree::leaf_blocks(&entries),
+            Err(NdbError::TooManySubNodes(_))
+        ));
+    }
+}
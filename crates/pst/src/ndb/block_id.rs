@@ -61,6 +61,10 @@ impl BlockId for UnicodeBlockId {
 }
 
 impl BlockIdReadWrite for UnicodeBlockId {
+    fn new(is_internal: bool, index: u64) -> NdbResult<Self> {
+        Self::new(is_internal, index)
+    }
+
     fn read(f: &mut dyn Read) -> io::Result<Self> {
         let value = f.read_u64::<LittleEndian>()?;
         Ok(Self(value))
@@ -122,6 +126,10 @@ impl BlockId for UnicodePageId {
 }
 
 impl BlockIdReadWrite for UnicodePageId {
+    fn new(_is_internal: bool, index: u64) -> NdbResult<Self> {
+        Ok(Self(index))
+    }
+
     fn read(f: &mut dyn Read) -> io::Result<Self> {
         let value = f.read_u64::<LittleEndian>()?;
         Ok(Self(value))
@@ -189,6 +197,10 @@ impl BlockId for AnsiBlockId {
 }
 
 impl BlockIdReadWrite for AnsiBlockId {
+    fn new(is_internal: bool, index: u32) -> NdbResult<Self> {
+        Self::new(is_internal, index)
+    }
+
     fn read(f: &mut dyn Read) -> io::Result<Self> {
         let value = f.read_u32::<LittleEndian>()?;
         Ok(Self(value))
@@ -250,6 +262,10 @@ impl BlockId for AnsiPageId {
 }
 
 impl BlockIdReadWrite for AnsiPageId {
+    fn new(_is_internal: bool, index: u32) -> NdbResult<Self> {
+        Ok(Self(index))
+    }
+
     fn read(f: &mut dyn Read) -> io::Result<Self> {
         let value = f.read_u32::<LittleEndian>()?;
         Ok(Self(value))
@@ -3,7 +3,7 @@
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
 
-use super::{block_id::*, read_write::*, root::*, *};
+use super::{block_id::*, node_id::*, read_write::*, root::*, *};
 use crate::{crc::compute_crc, AnsiPstFile, PstFile, UnicodePstFile};
 
 /// `dwMagic`
@@ -21,11 +21,28 @@ const HEADER_MAGIC_CLIENT: u16 = u16::from_be_bytes(*b"MS");
 #[repr(u16)]
 #[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
 pub enum NdbVersion {
+    /// ANSI files are written with `wVer` 15, but files from older versions of Outlook may have
+    /// 14. The layout of the header, including `rgnid`, is the same for both, and
+    /// [`Header::format_version`] keeps the original value so it is written back unchanged.
     Ansi = 15,
     #[default]
     Unicode = 23,
 }
 
+/// Outlook refuses to open ANSI files which are 2GB or larger.
+pub const ANSI_MAX_FILE_SIZE: u64 = 0x7FFF_FFFF;
+
+impl NdbVersion {
+    /// The largest file which can be written in this format, or `None` if the 64-bit offsets of
+    /// a Unicode file leave no practical limit.
+    pub fn max_file_size(&self) -> Option<u64> {
+        match self {
+            NdbVersion::Ansi => Some(ANSI_MAX_FILE_SIZE),
+            NdbVersion::Unicode => None,
+        }
+    }
+}
+
 impl TryFrom<u16> for NdbVersion {
     type Error = NdbError;
 
@@ -48,6 +65,36 @@ const NDB_DEFAULT_NIDS: [u32; 32] = [
 ];
 const NDB_SENTINEL: u8 = 0x80;
 
+/// Take the next `nidIndex` for `id_type` from `rgnid`, and bump `dwUnique` to record the change.
+fn allocate_nid(nids: &mut [u32; 32], unique: &mut u32, id_type: NodeIdType) -> NdbResult<NodeId> {
+    let next_index = &mut nids[id_type as usize];
+    let index = *next_index;
+    if index > MAX_NODE_INDEX {
+        return Err(NdbError::NodeIndexExhausted(id_type));
+    }
+
+    let node_id = NodeId::new(id_type, index)?;
+    *next_index = index + 1;
+    *unique = unique.wrapping_add(1);
+    Ok(node_id)
+}
+
+/// How the header is read, e.g. by
+/// [`UnicodePstFile::open_with_header_mode`](crate::UnicodePstFile::open_with_header_mode),
+/// treats the header fields which the specification says must have a fixed value: `wVerClient`,
+/// `bPlatformCreate`, `bPlatformAccess`, `dwAlign`, `rgbReserved`, and in ANSI files `ullReserved`
+/// and `dwReserved`.
+#[derive(Copy, Clone, PartialEq, Eq, Default, Debug)]
+pub enum HeaderFieldMode {
+    /// Reject a header where any of those fields has an unexpected value.
+    #[default]
+    Strict,
+    /// Accept any value in those fields, and write the same bytes back when the header is
+    /// updated, so files produced by other tools or newer versions of Outlook are not changed
+    /// in ways they might not expect.
+    Preserve,
+}
+
 /// `bCryptMethod`
 ///
 /// ### See also
@@ -82,12 +129,44 @@ where
     Pst: PstFile,
 {
     fn version(&self) -> NdbVersion;
+    /// `wVer` as it was read from the file, which distinguishes the ANSI variants that
+    /// [`Header::version`] treats the same.
+    fn format_version(&self) -> u16;
     fn crypt_method(&self) -> NdbCryptMethod;
     fn next_block(&self) -> <Pst as PstFile>::BlockId;
     fn next_page(&self) -> <Pst as PstFile>::PageId;
     fn unique_value(&self) -> u32;
     fn root(&self) -> &<Pst as PstFile>::Root;
     fn root_mut(&mut self) -> &mut <Pst as PstFile>::Root;
+    /// `rgbFM`: Deprecated copy of the first FMap entries. It is kept as it was read from the
+    /// file, except for the entries of AMap pages which are added or rebuilt.
+    fn free_map(&self) -> &[u8; 128];
+    /// `rgbFP`: Deprecated copy of the first FPMap entries. It is written back unchanged.
+    fn free_page_map(&self) -> &[u8; 128];
+    /// `wVerClient`
+    fn client_version(&self) -> u16;
+    /// `bPlatformCreate`
+    fn platform_create(&self) -> u8;
+    /// `bPlatformAccess`
+    fn platform_access(&self) -> u8;
+    /// `dwReserved1` and `dwReserved2`, which new files must initialize to zero, and which
+    /// implementations should not modify.
+    fn reserved_values(&self) -> (u32, u32);
+    /// Check that the unused and reserved fields which must be zero are: `bidUnused`,
+    /// `qwUnused`, `dwAlign`, `rgbReserved`, `rgbReserved2`, `bReserved`, and `rgbReserved3` in
+    /// Unicode files, or `rgbReserved`, `ullReserved`, `dwReserved`, `rgbReserved2`,
+    /// `bReserved`, and `rgbReserved3` in ANSI files.
+    fn reserved_fields_are_zero(&self) -> bool;
+
+    /// The specification says that [`Header::free_map`] and [`Header::free_page_map`] should be
+    /// filled with `0xFF`, but files written by older versions of Outlook or by other tools may
+    /// still have the deprecated map values in them.
+    fn has_standard_fill_maps(&self) -> bool {
+        self.free_map()
+            .iter()
+            .chain(self.free_page_map().iter())
+            .all(|&b| b == 0xFF)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -101,10 +180,15 @@ pub struct UnicodeHeader {
     crypt_method: NdbCryptMethod,
     next_block: UnicodeBlockId,
 
+    client_version: u16,
+    platform_create: u8,
+    platform_access: u8,
     reserved1: u32,
     reserved2: u32,
     unused1: u64,
     unused2: u64,
+    align: u32,
+    reserved: u16,
     reserved3: [u8; 36],
 }
 
@@ -119,10 +203,15 @@ impl UnicodeHeader {
             free_page_map: [0xFF; 128],
             crypt_method,
             next_block: UnicodeBlockId::from(4),
+            client_version: NDB_CLIENT_VERSION,
+            platform_create: NDB_PLATFORM_CREATE,
+            platform_access: NDB_PLATFORM_ACCESS,
             reserved1: 0,
             reserved2: 0,
             unused1: 0,
             unused2: 0,
+            align: 0,
+            reserved: 0,
             reserved3: [0; 36],
         }
     }
@@ -133,6 +222,10 @@ impl Header<UnicodePstFile> for UnicodeHeader {
         NdbVersion::Unicode
     }
 
+    fn format_version(&self) -> u16 {
+        NdbVersion::Unicode as u16
+    }
+
     fn crypt_method(&self) -> NdbCryptMethod {
         self.crypt_method
     }
@@ -156,10 +249,42 @@ impl Header<UnicodePstFile> for UnicodeHeader {
     fn root_mut(&mut self) -> &mut <UnicodePstFile as PstFile>::Root {
         &mut self.root
     }
+
+    fn free_map(&self) -> &[u8; 128] {
+        &self.free_map
+    }
+
+    fn free_page_map(&self) -> &[u8; 128] {
+        &self.free_page_map
+    }
+
+    fn client_version(&self) -> u16 {
+        self.client_version
+    }
+
+    fn platform_create(&self) -> u8 {
+        self.platform_create
+    }
+
+    fn platform_access(&self) -> u8 {
+        self.platform_access
+    }
+
+    fn reserved_values(&self) -> (u32, u32) {
+        (self.reserved1, self.reserved2)
+    }
+
+    fn reserved_fields_are_zero(&self) -> bool {
+        self.unused1 == 0
+            && self.unused2 == 0
+            && self.align == 0
+            && self.reserved == 0
+            && self.reserved3 == [0; 36]
+    }
 }
 
 impl HeaderReadWrite<UnicodePstFile> for UnicodeHeader {
-    fn read(f: &mut dyn Read) -> io::Result<Self> {
+    fn read_with_mode(f: &mut dyn Read, mode: HeaderFieldMode) -> io::Result<Self> {
         // dwMagic
         let magic = f.read_u32::<LittleEndian>()?;
         if magic != HEADER_MAGIC {
@@ -202,20 +327,20 @@ impl HeaderReadWrite<UnicodePstFile> for UnicodeHeader {
         cursor.seek(SeekFrom::Start(4))?;
 
         // wVerClient
-        let version = cursor.read_u16::<LittleEndian>()?;
-        if version != NDB_CLIENT_VERSION {
-            return Err(NdbError::InvalidNdbHeaderClientVersion(version).into());
+        let client_version = cursor.read_u16::<LittleEndian>()?;
+        if client_version != NDB_CLIENT_VERSION && mode == HeaderFieldMode::Strict {
+            return Err(NdbError::InvalidNdbHeaderClientVersion(client_version).into());
         }
 
         // bPlatformCreate
         let platform_create = cursor.read_u8()?;
-        if platform_create != NDB_PLATFORM_CREATE {
+        if platform_create != NDB_PLATFORM_CREATE && mode == HeaderFieldMode::Strict {
             return Err(NdbError::InvalidNdbHeaderPlatformCreate(platform_create).into());
         }
 
         // bPlatformAccess
         let platform_access = cursor.read_u8()?;
-        if platform_access != NDB_PLATFORM_ACCESS {
+        if platform_access != NDB_PLATFORM_ACCESS && mode == HeaderFieldMode::Strict {
             return Err(NdbError::InvalidNdbHeaderPlatformAccess(platform_access).into());
         }
 
@@ -248,7 +373,7 @@ impl HeaderReadWrite<UnicodePstFile> for UnicodeHeader {
 
         // dwAlign
         let align = cursor.read_u32::<LittleEndian>()?;
-        if align != 0 {
+        if align != 0 && mode == HeaderFieldMode::Strict {
             return Err(NdbError::InvalidNdbHeaderAlignValue(align).into());
         }
 
@@ -271,7 +396,7 @@ impl HeaderReadWrite<UnicodePstFile> for UnicodeHeader {
 
         // rgbReserved
         let reserved = cursor.read_u16::<LittleEndian>()?;
-        if reserved != 0 {
+        if reserved != 0 && mode == HeaderFieldMode::Strict {
             return Err(NdbError::InvalidNdbHeaderReservedValue(reserved).into());
         }
 
@@ -291,10 +416,15 @@ impl HeaderReadWrite<UnicodePstFile> for UnicodeHeader {
             free_page_map,
             crypt_method,
             next_block,
+            client_version,
+            platform_create,
+            platform_access,
             reserved1,
             reserved2,
             unused1,
             unused2,
+            align,
+            reserved,
             reserved3,
         })
     }
@@ -306,11 +436,11 @@ impl HeaderReadWrite<UnicodePstFile> for UnicodeHeader {
         // wVer
         cursor.write_u16::<LittleEndian>(NdbVersion::Unicode as u16)?;
         // wVerClient
-        cursor.write_u16::<LittleEndian>(NDB_CLIENT_VERSION)?;
+        cursor.write_u16::<LittleEndian>(self.client_version)?;
         // bPlatformCreate
-        cursor.write_u8(NDB_PLATFORM_CREATE)?;
+        cursor.write_u8(self.platform_create)?;
         // bPlatformAccess
-        cursor.write_u8(NDB_PLATFORM_ACCESS)?;
+        cursor.write_u8(self.platform_access)?;
         // dwReserved1
         cursor.write_u32::<LittleEndian>(self.reserved1)?;
         // dwReserved2
@@ -330,7 +460,7 @@ impl HeaderReadWrite<UnicodePstFile> for UnicodeHeader {
         // root
         self.root.write(&mut cursor)?;
         // dwAlign
-        cursor.write_u32::<LittleEndian>(0)?;
+        cursor.write_u32::<LittleEndian>(self.align)?;
         // rgbFM
         cursor.write_all(&self.free_map)?;
         // rgbFP
@@ -340,7 +470,7 @@ impl HeaderReadWrite<UnicodePstFile> for UnicodeHeader {
         // bCryptMethod
         cursor.write_u8(self.crypt_method as u8)?;
         // rgbReserved
-        cursor.write_u16::<LittleEndian>(0)?;
+        cursor.write_u16::<LittleEndian>(self.reserved)?;
         // bidNextB
         self.next_block.write(&mut cursor)?;
 
@@ -366,6 +496,18 @@ impl HeaderReadWrite<UnicodePstFile> for UnicodeHeader {
         self.unique = self.unique.wrapping_add(1);
     }
 
+    fn allocate_nid(&mut self, id_type: NodeIdType) -> NdbResult<NodeId> {
+        allocate_nid(&mut self.nids, &mut self.unique, id_type)
+    }
+
+    fn set_next_block(&mut self, next_block: <UnicodePstFile as PstFile>::BlockId) {
+        self.next_block = next_block;
+    }
+
+    fn set_next_page(&mut self, next_page: <UnicodePstFile as PstFile>::PageId) {
+        self.next_page = next_page;
+    }
+
     fn first_free_map(&mut self) -> &mut [u8] {
         &mut self.free_map
     }
@@ -377,6 +519,7 @@ impl HeaderReadWrite<UnicodePstFile> for UnicodeHeader {
 
 #[derive(Clone, Debug)]
 pub struct AnsiHeader {
+    format_version: u16,
     next_block: AnsiBlockId,
     next_page: AnsiPageId,
     unique: u32,
@@ -386,14 +529,20 @@ pub struct AnsiHeader {
     free_page_map: [u8; 128],
     crypt_method: NdbCryptMethod,
 
+    client_version: u16,
+    platform_create: u8,
+    platform_access: u8,
     reserved1: u32,
     reserved2: u32,
+    reserved: u16,
+    reserved4: [u8; 12],
     reserved3: [u8; 36],
 }
 
 impl AnsiHeader {
     pub fn new(root: AnsiRoot, crypt_method: NdbCryptMethod) -> Self {
         Self {
+            format_version: NdbVersion::Ansi as u16,
             next_block: AnsiBlockId::from(4),
             next_page: AnsiPageId::from(1),
             unique: 0,
@@ -402,8 +551,13 @@ impl AnsiHeader {
             free_map: [0xFF; 128],
             free_page_map: [0xFF; 128],
             crypt_method,
+            client_version: NDB_CLIENT_VERSION,
+            platform_create: NDB_PLATFORM_CREATE,
+            platform_access: NDB_PLATFORM_ACCESS,
             reserved1: 0,
             reserved2: 0,
+            reserved: 0,
+            reserved4: [0; 12],
             reserved3: [0; 36],
         }
     }
@@ -414,6 +568,10 @@ impl Header<AnsiPstFile> for AnsiHeader {
         NdbVersion::Ansi
     }
 
+    fn format_version(&self) -> u16 {
+        self.format_version
+    }
+
     fn crypt_method(&self) -> NdbCryptMethod {
         self.crypt_method
     }
@@ -437,10 +595,38 @@ impl Header<AnsiPstFile> for AnsiHeader {
     fn root_mut(&mut self) -> &mut <AnsiPstFile as PstFile>::Root {
         &mut self.root
     }
+
+    fn free_map(&self) -> &[u8; 128] {
+        &self.free_map
+    }
+
+    fn free_page_map(&self) -> &[u8; 128] {
+        &self.free_page_map
+    }
+
+    fn client_version(&self) -> u16 {
+        self.client_version
+    }
+
+    fn platform_create(&self) -> u8 {
+        self.platform_create
+    }
+
+    fn platform_access(&self) -> u8 {
+        self.platform_access
+    }
+
+    fn reserved_values(&self) -> (u32, u32) {
+        (self.reserved1, self.reserved2)
+    }
+
+    fn reserved_fields_are_zero(&self) -> bool {
+        self.reserved == 0 && self.reserved4 == [0; 12] && self.reserved3 == [0; 36]
+    }
 }
 
 impl HeaderReadWrite<AnsiPstFile> for AnsiHeader {
-    fn read(f: &mut dyn Read) -> io::Result<Self> {
+    fn read_with_mode(f: &mut dyn Read, mode: HeaderFieldMode) -> io::Result<Self> {
         // dwMagic
         let magic = f.read_u32::<LittleEndian>()?;
         if magic != HEADER_MAGIC {
@@ -465,26 +651,27 @@ impl HeaderReadWrite<AnsiPstFile> for AnsiHeader {
         }
 
         // wVer
-        let version = NdbVersion::try_from(cursor.read_u16::<LittleEndian>()?)?;
+        let format_version = cursor.read_u16::<LittleEndian>()?;
+        let version = NdbVersion::try_from(format_version)?;
         if version != NdbVersion::Ansi {
             return Err(NdbError::UnicodePstVersion(version as u16).into());
         }
 
         // wVerClient
-        let version = cursor.read_u16::<LittleEndian>()?;
-        if version != NDB_CLIENT_VERSION {
-            return Err(NdbError::InvalidNdbHeaderClientVersion(version).into());
+        let client_version = cursor.read_u16::<LittleEndian>()?;
+        if client_version != NDB_CLIENT_VERSION && mode == HeaderFieldMode::Strict {
+            return Err(NdbError::InvalidNdbHeaderClientVersion(client_version).into());
         }
 
         // bPlatformCreate
         let platform_create = cursor.read_u8()?;
-        if platform_create != NDB_PLATFORM_CREATE {
+        if platform_create != NDB_PLATFORM_CREATE && mode == HeaderFieldMode::Strict {
             return Err(NdbError::InvalidNdbHeaderPlatformCreate(platform_create).into());
         }
 
         // bPlatformAccess
         let platform_access = cursor.read_u8()?;
-        if platform_access != NDB_PLATFORM_ACCESS {
+        if platform_access != NDB_PLATFORM_ACCESS && mode == HeaderFieldMode::Strict {
             return Err(NdbError::InvalidNdbHeaderPlatformAccess(platform_access).into());
         }
 
@@ -531,14 +718,14 @@ impl HeaderReadWrite<AnsiPstFile> for AnsiHeader {
 
         // rgbReserved
         let reserved = cursor.read_u16::<LittleEndian>()?;
-        if reserved != 0 {
+        if reserved != 0 && mode == HeaderFieldMode::Strict {
             return Err(NdbError::InvalidNdbHeaderReservedValue(reserved).into());
         }
 
         // ullReserved, dwReserved (total 12 bytes)
-        let mut reserved = [0_u8; 12];
-        cursor.read_exact(&mut reserved)?;
-        if reserved != [0; 12] {
+        let mut reserved4 = [0_u8; 12];
+        cursor.read_exact(&mut reserved4)?;
+        if reserved4 != [0; 12] && mode == HeaderFieldMode::Strict {
             return Err(NdbError::InvalidNdbHeaderAnsiReservedBytes.into());
         }
 
@@ -547,6 +734,7 @@ impl HeaderReadWrite<AnsiPstFile> for AnsiHeader {
         cursor.read_exact(&mut reserved3)?;
 
         Ok(Self {
+            format_version,
             next_page,
             unique,
             nids,
@@ -555,8 +743,13 @@ impl HeaderReadWrite<AnsiPstFile> for AnsiHeader {
             free_page_map,
             crypt_method,
             next_block,
+            client_version,
+            platform_create,
+            platform_access,
             reserved1,
             reserved2,
+            reserved,
+            reserved4,
             reserved3,
         })
     }
@@ -566,13 +759,13 @@ impl HeaderReadWrite<AnsiPstFile> for AnsiHeader {
         // wMagicClient
         cursor.write_u16::<LittleEndian>(HEADER_MAGIC_CLIENT)?;
         // wVer
-        cursor.write_u16::<LittleEndian>(NdbVersion::Ansi as u16)?;
+        cursor.write_u16::<LittleEndian>(self.format_version)?;
         // wVerClient
-        cursor.write_u16::<LittleEndian>(NDB_CLIENT_VERSION)?;
+        cursor.write_u16::<LittleEndian>(self.client_version)?;
         // bPlatformCreate
-        cursor.write_u8(NDB_PLATFORM_CREATE)?;
+        cursor.write_u8(self.platform_create)?;
         // bPlatformAccess
-        cursor.write_u8(NDB_PLATFORM_ACCESS)?;
+        cursor.write_u8(self.platform_access)?;
         // dwReserved1
         cursor.write_u32::<LittleEndian>(self.reserved1)?;
         // dwReserved2
@@ -598,9 +791,9 @@ impl HeaderReadWrite<AnsiPstFile> for AnsiHeader {
         // bCryptMethod
         cursor.write_u8(self.crypt_method as u8)?;
         // rgbReserved
-        cursor.write_u16::<LittleEndian>(0)?;
+        cursor.write_u16::<LittleEndian>(self.reserved)?;
         // ullReserved, dwReserved (total 12 bytes)
-        cursor.write_all(&[0_u8; 12])?;
+        cursor.write_all(&self.reserved4)?;
         // rgbReserved2, bReserved, rgbReserved3 (total 36 bytes)
         cursor.write_all(&self.reserved3)?;
 
@@ -619,6 +812,18 @@ impl HeaderReadWrite<AnsiPstFile> for AnsiHeader {
         self.unique = self.unique.wrapping_add(1);
     }
 
+    fn allocate_nid(&mut self, id_type: NodeIdType) -> NdbResult<NodeId> {
+        allocate_nid(&mut self.nids, &mut self.unique, id_type)
+    }
+
+    fn set_next_block(&mut self, next_block: <AnsiPstFile as PstFile>::BlockId) {
+        self.next_block = next_block;
+    }
+
+    fn set_next_page(&mut self, next_page: <AnsiPstFile as PstFile>::PageId) {
+        self.next_page = next_page;
+    }
+
     fn first_free_map(&mut self) -> &mut [u8] {
         &mut self.free_map
     }
@@ -637,4 +842,108 @@ mod tests {
         assert_eq!(HEADER_MAGIC, 0x4E444221);
         assert_eq!(HEADER_MAGIC_CLIENT, 0x4D53);
     }
+
+    #[test]
+    fn test_allocate_nid() {
+        let mut nids = NDB_DEFAULT_NIDS;
+        let mut unique = 0;
+
+        let first = allocate_nid(&mut nids, &mut unique, NodeIdType::NormalMessage).unwrap();
+        let second = allocate_nid(&mut nids, &mut unique, NodeIdType::NormalMessage).unwrap();
+        assert_eq!(u32::from(first), 0x200004);
+        assert_eq!(u32::from(second), 0x200024);
+        assert_eq!(nids[NodeIdType::NormalMessage as usize], 0x10002);
+        assert_eq!(nids[NodeIdType::NormalFolder as usize], 0x400);
+        assert_eq!(unique, 2);
+
+        nids[NodeIdType::NormalFolder as usize] = MAX_NODE_INDEX;
+        allocate_nid(&mut nids, &mut unique, NodeIdType::NormalFolder).unwrap();
+        let Err(NdbError::NodeIndexExhausted(NodeIdType::NormalFolder)) =
+            allocate_nid(&mut nids, &mut unique, NodeIdType::NormalFolder)
+        else {
+            panic!("nidIndex should be exhausted");
+        };
+        assert_eq!(unique, 3);
+    }
+
+    #[test]
+    fn test_non_standard_fill_maps() {
+        const EMPTY_PST: &[u8] = include_bytes!("../../examples/Empty.pst");
+
+        let mut header = UnicodeHeader::read(&mut &EMPTY_PST[..]).unwrap();
+        header.free_map[..4].copy_from_slice(&[0x00, 0x10, 0x7F, 0xFE]);
+        header.free_page_map[0] = 0x00;
+        assert!(!header.has_standard_fill_maps());
+
+        let mut buffer = vec![];
+        header.write(&mut buffer).unwrap();
+        let reread = UnicodeHeader::read(&mut buffer.as_slice()).unwrap();
+        assert_eq!(reread.free_map(), header.free_map());
+        assert_eq!(reread.free_page_map(), header.free_page_map());
+
+        let mut rewritten = vec![];
+        reread.write(&mut rewritten).unwrap();
+        assert_eq!(rewritten, buffer);
+    }
+
+    #[test]
+    fn test_ansi_format_versions() {
+        assert_eq!(NdbVersion::try_from(14).unwrap(), NdbVersion::Ansi);
+        assert_eq!(NdbVersion::try_from(15).unwrap(), NdbVersion::Ansi);
+        assert!(NdbVersion::try_from(16).is_err());
+
+        let mut header = AnsiHeader::new(
+            AnsiRoot::new(
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+                AmapStatus::Valid2,
+            ),
+            NdbCryptMethod::Permute,
+        );
+        assert_eq!(header.format_version(), 15);
+        header.format_version = 14;
+        header.allocate_nid(NodeIdType::NormalMessage).unwrap();
+
+        let mut buffer = vec![];
+        header.write(&mut buffer).unwrap();
+        assert_eq!(u16::from_le_bytes([buffer[10], buffer[11]]), 14);
+
+        let reread = AnsiHeader::read(&mut buffer.as_slice()).unwrap();
+        assert_eq!(reread.version(), NdbVersion::Ansi);
+        assert_eq!(reread.format_version(), 14);
+        assert_eq!(reread.nids, header.nids);
+
+        let mut rewritten = vec![];
+        reread.write(&mut rewritten).unwrap();
+        assert_eq!(rewritten, buffer);
+    }
+
+    #[test]
+    fn test_preserve_reserved_fields() {
+        const EMPTY_PST: &[u8] = include_bytes!("../../examples/Empty.pst");
+
+        let mut header = UnicodeHeader::read(&mut &EMPTY_PST[..]).unwrap();
+        header.client_version = NDB_CLIENT_VERSION + 1;
+        header.platform_create = 0x02;
+        header.align = 0x1234_5678;
+        header.reserved = 0xABCD;
+
+        let mut buffer = vec![];
+        header.write(&mut buffer).unwrap();
+        let Err(err) = UnicodeHeader::read(&mut buffer.as_slice()) else {
+            panic!("strict mode should reject wVerClient");
+        };
+        assert!(err.to_string().contains("wVerClient"));
+
+        let preserved =
+            UnicodeHeader::read_with_mode(&mut buffer.as_slice(), HeaderFieldMode::Preserve)
+                .unwrap();
+        let mut rewritten = vec![];
+        preserved.write(&mut rewritten).unwrap();
+        assert_eq!(rewritten, buffer);
+    }
 }
@@ -9,6 +9,7 @@ pub mod block_id;
 pub mod block_ref;
 pub mod byte_index;
 pub mod header;
+pub mod node_data;
 pub mod node_id;
 pub mod page;
 pub mod root;
@@ -25,6 +26,8 @@ pub enum NdbError {
     InvalidNodeIdType(u8),
     #[error("Invalid nidIndex: 0x{0:08X}")]
     InvalidNodeIndex(u32),
+    #[error("No nidIndex values left for nidType: {0:?}")]
+    NodeIndexExhausted(node_id::NodeIdType),
     #[error("Invalid bidIndex: 0x{0:016X}")]
     InvalidUnicodeBlockIndex(u64),
     #[error("Invalid bidIndex: 0x{0:08X}")]
@@ -89,12 +92,18 @@ pub enum NdbError {
     InvalidBTreePagePadding(u32),
     #[error("BTENTRY not found: 0x{0:X}")]
     BTreePageNotFound(u64),
+    #[error("BTENTRY already exists: 0x{0:X}")]
+    DuplicateBTreeEntry(u64),
+    #[error("No free BTPAGE available to copy or split a page")]
+    BTreeSplitPageNotFound,
     #[error("Invalid NBTENTRY nid: 0x{0:X}")]
     InvalidNodeBTreeEntryNodeId(u64),
     #[error("Invalid BLOCKTRAILER cb: 0x{0:X}")]
     InvalidBlockSize(u16),
     #[error("Invalid BLOCKTRAILER dwCRC: 0x{0:08X}")]
     InvalidBlockCrc(u32),
+    #[error("Invalid BLOCKTRAILER wSig: 0x{0:04X}")]
+    InvalidBlockSignature(u16),
     #[error("Invalid BLOCKTRAILER bid: 0x{0:X}")]
     InvalidUnicodeBlockTrailerId(u64),
     #[error("Invalid BLOCKTRAILER bid: 0x{0:X}")]
@@ -113,6 +122,10 @@ pub enum NdbError {
     InvalidSubNodeBlockPadding(u32),
     #[error("Sub-node not found: {0:?}")]
     SubNodeNotFound(NodeId),
+    #[error("Too many sub-nodes for one SIBLOCK: {0}")]
+    TooManySubNodes(usize),
+    #[error("Too many data blocks for one XXBLOCK: {0}")]
+    DataTreeTooLarge(usize),
 }
 
 impl From<NdbError> for io::Error {
@@ -122,3 +135,20 @@ impl From<NdbError> for io::Error {
 }
 
 pub type NdbResult<T> = Result<T, NdbError>;
+
+/// Map the [`NdbError::BTreePageNotFound`] error from a lookup in the Node BTree or Block BTree to
+/// `None`, so a missing entry is not treated as an error, while any other error still is.
+pub(crate) fn optional_btree_entry<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(entry) => Ok(Some(entry)),
+        Err(err)
+            if matches!(
+                err.get_ref().and_then(|err| err.downcast_ref::<NdbError>()),
+                Some(NdbError::BTreePageNotFound(_))
+            ) =>
+        {
+            Ok(None)
+        }
+        Err(err) => Err(err),
+    }
+}
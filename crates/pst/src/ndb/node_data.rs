@@ -0,0 +1,82 @@
+//! In-memory contents of a node, i.e. the blocks of its data tree and the nodes in its sub-node
+//! tree, which can be read or written in one step.
+
+use std::collections::BTreeMap;
+
+use super::{node_id::*, *};
+
+/// Sub-nodes which are local to one node start at this `nidIndex`, e.g. the first attachment of
+/// a message is `0x8025`.
+pub const FIRST_LOCAL_NODE_INDEX: u32 = 0x401;
+
+/// The data blocks of a node and, recursively, its sub-nodes.
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct NodeData {
+    blocks: Vec<Vec<u8>>,
+    sub_nodes: BTreeMap<NodeId, NodeData>,
+}
+
+impl NodeData {
+    /// Each of the `blocks` is written to its own data block, so they have to fit in
+    /// [`max_block_data_size`](super::block::max_block_data_size). Formats like the heap of an
+    /// LTP node depend on where the data tree is split into blocks.
+    pub fn new(blocks: Vec<Vec<u8>>) -> Self {
+        Self {
+            blocks,
+            sub_nodes: Default::default(),
+        }
+    }
+
+    /// Split a stream of bytes, e.g. a large property value, into blocks which fit in either
+    /// format.
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let max_size = usize::from(block::max_block_data_size(header::NdbVersion::Unicode));
+        Self::new(data.chunks(max_size).map(<[u8]>::to_vec).collect())
+    }
+
+    pub fn blocks(&self) -> &[Vec<u8>] {
+        &self.blocks
+    }
+
+    /// Concatenate the data blocks.
+    pub fn data(&self) -> Vec<u8> {
+        self.blocks.concat()
+    }
+
+    /// Total size of the data blocks, including all of the sub-nodes.
+    pub fn size(&self) -> usize {
+        self.blocks.iter().map(Vec::len).sum::<usize>()
+            + self.sub_nodes.values().map(NodeData::size).sum::<usize>()
+    }
+
+    pub fn sub_nodes(&self) -> &BTreeMap<NodeId, NodeData> {
+        &self.sub_nodes
+    }
+
+    pub fn sub_node(&self, node: NodeId) -> Option<&NodeData> {
+        self.sub_nodes.get(&node)
+    }
+
+    /// Add or replace a sub-node, and return the previous contents if it was replaced.
+    pub fn insert_sub_node(&mut self, node: NodeId, data: NodeData) -> Option<NodeData> {
+        self.sub_nodes.insert(node, data)
+    }
+
+    pub fn remove_sub_node(&mut self, node: NodeId) -> Option<NodeData> {
+        self.sub_nodes.remove(&node)
+    }
+
+    /// The next unused local [`NodeId`] of `id_type` in the sub-node tree, starting at
+    /// [`FIRST_LOCAL_NODE_INDEX`].
+    pub fn next_sub_node_id(&self, id_type: NodeIdType) -> NdbResult<NodeId> {
+        let index = self
+            .sub_nodes
+            .keys()
+            .filter(|node| node.id_type().ok() == Some(id_type))
+            .map(|node| node.index() + 1)
+            .max()
+            .unwrap_or_default()
+            .max(FIRST_LOCAL_NODE_INDEX);
+        NodeId::new(id_type, index)
+    }
+}
@@ -223,6 +223,128 @@ pub const NID_RESERVED3: NodeId = NodeId(0x301);
 /// Search Gatherer Folder Queue (section [2.4.8.5.3](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/5dd87c45-5f2d-4945-b7e3-2612bd1a94d3)).
 pub const NID_SEARCH_GATHERER_FOLDER_QUEUE: NodeId = NodeId(0x321);
 
+/// [`NID_HIERARCHY_TABLE_TEMPLATE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Template for the hierarchy table of a new Folder object.
+pub const NID_HIERARCHY_TABLE_TEMPLATE: NodeId = NodeId(0x60D);
+
+/// [`NID_CONTENTS_TABLE_TEMPLATE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Template for the contents table of a new Folder object.
+pub const NID_CONTENTS_TABLE_TEMPLATE: NodeId = NodeId(0x60E);
+
+/// [`NID_ASSOC_CONTENTS_TABLE_TEMPLATE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Template for the FAI contents table of a new Folder object.
+pub const NID_ASSOC_CONTENTS_TABLE_TEMPLATE: NodeId = NodeId(0x60F);
+
+/// [`NID_SEARCH_CONTENTS_TABLE_TEMPLATE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Template for the contents table of a new search Folder object.
+pub const NID_SEARCH_CONTENTS_TABLE_TEMPLATE: NodeId = NodeId(0x610);
+
+/// [`NID_SMP_TEMPLATE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Template for the search criteria object of a new search Folder object.
+pub const NID_SMP_TEMPLATE: NodeId = NodeId(0x611);
+
+/// [`NID_TOMBSTONE_TABLE_TEMPLATE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Template for the tombstone table.
+pub const NID_TOMBSTONE_TABLE_TEMPLATE: NodeId = NodeId(0x612);
+
+/// [`NID_LREP_DUPS_TABLE_TEMPLATE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Template for the local replica duplicates table.
+pub const NID_LREP_DUPS_TABLE_TEMPLATE: NodeId = NodeId(0x613);
+
+/// [`NID_RECEIVE_FOLDER_TABLE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Receive Folder table, which maps message classes to the folders which receive them.
+pub const NID_RECEIVE_FOLDER_TABLE: NodeId = NodeId(0x617);
+
+/// [`NID_OUTGOING_QUEUE_TABLE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Outgoing queue table.
+pub const NID_OUTGOING_QUEUE_TABLE: NodeId = NodeId(0x64C);
+
+/// [`NID_ATTACHMENT_TABLE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Attachment table template in the NBT, and the sub-node of a message which lists its
+/// attachments.
+pub const NID_ATTACHMENT_TABLE: NodeId = NodeId(0x671);
+
+/// [`NID_RECIPIENT_TABLE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Recipient table template in the NBT, and the sub-node of a message which holds its recipients.
+pub const NID_RECIPIENT_TABLE: NodeId = NodeId(0x692);
+
+/// [`NID_CHANGE_HISTORY_TABLE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Change history table.
+pub const NID_CHANGE_HISTORY_TABLE: NodeId = NodeId(0x6B6);
+
+/// [`NID_TOMBSTONE_TABLE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Tombstone table.
+pub const NID_TOMBSTONE_TABLE: NodeId = NodeId(0x6D7);
+
+/// [`NID_TOMBSTONE_DATE_TABLE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Tombstone date table.
+pub const NID_TOMBSTONE_DATE_TABLE: NodeId = NodeId(0x6F8);
+
+/// [`NID_FOLDER_PATH_TOMBSTONE_TABLE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Folder path tombstone table.
+pub const NID_FOLDER_PATH_TOMBSTONE_TABLE: NodeId = NodeId(0x710);
+
+/// [`NID_LREP_DUPS_TABLE`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/0510ece4-6853-4bef-8cc8-8df3468e3ff1):
+/// Local replica duplicates table.
+pub const NID_LREP_DUPS_TABLE: NodeId = NodeId(0x7EC);
+
+/// Every special internal NID, with its name in the specification, in order of the NID.
+pub const SPECIAL_NODE_IDS: [(NodeId, &str); 30] = [
+    (NID_MESSAGE_STORE, "NID_MESSAGE_STORE"),
+    (NID_NAME_TO_ID_MAP, "NID_NAME_TO_ID_MAP"),
+    (NID_NORMAL_FOLDER_TEMPLATE, "NID_NORMAL_FOLDER_TEMPLATE"),
+    (NID_SEARCH_FOLDER_TEMPLATE, "NID_SEARCH_FOLDER_TEMPLATE"),
+    (NID_ROOT_FOLDER, "NID_ROOT_FOLDER"),
+    (NID_SEARCH_MANAGEMENT_QUEUE, "NID_SEARCH_MANAGEMENT_QUEUE"),
+    (NID_SEARCH_ACTIVITY_LIST, "NID_SEARCH_ACTIVITY_LIST"),
+    (NID_RESERVED1, "NID_RESERVED1"),
+    (NID_SEARCH_DOMAIN_OBJECT, "NID_SEARCH_DOMAIN_OBJECT"),
+    (NID_SEARCH_GATHERER_QUEUE, "NID_SEARCH_GATHERER_QUEUE"),
+    (
+        NID_SEARCH_GATHERER_DESCRIPTOR,
+        "NID_SEARCH_GATHERER_DESCRIPTOR",
+    ),
+    (NID_RESERVED2, "NID_RESERVED2"),
+    (NID_RESERVED3, "NID_RESERVED3"),
+    (
+        NID_SEARCH_GATHERER_FOLDER_QUEUE,
+        "NID_SEARCH_GATHERER_FOLDER_QUEUE",
+    ),
+    (NID_HIERARCHY_TABLE_TEMPLATE, "NID_HIERARCHY_TABLE_TEMPLATE"),
+    (NID_CONTENTS_TABLE_TEMPLATE, "NID_CONTENTS_TABLE_TEMPLATE"),
+    (
+        NID_ASSOC_CONTENTS_TABLE_TEMPLATE,
+        "NID_ASSOC_CONTENTS_TABLE_TEMPLATE",
+    ),
+    (
+        NID_SEARCH_CONTENTS_TABLE_TEMPLATE,
+        "NID_SEARCH_CONTENTS_TABLE_TEMPLATE",
+    ),
+    (NID_SMP_TEMPLATE, "NID_SMP_TEMPLATE"),
+    (NID_TOMBSTONE_TABLE_TEMPLATE, "NID_TOMBSTONE_TABLE_TEMPLATE"),
+    (NID_LREP_DUPS_TABLE_TEMPLATE, "NID_LREP_DUPS_TABLE_TEMPLATE"),
+    (NID_RECEIVE_FOLDER_TABLE, "NID_RECEIVE_FOLDER_TABLE"),
+    (NID_OUTGOING_QUEUE_TABLE, "NID_OUTGOING_QUEUE_TABLE"),
+    (NID_ATTACHMENT_TABLE, "NID_ATTACHMENT_TABLE"),
+    (NID_RECIPIENT_TABLE, "NID_RECIPIENT_TABLE"),
+    (NID_CHANGE_HISTORY_TABLE, "NID_CHANGE_HISTORY_TABLE"),
+    (NID_TOMBSTONE_TABLE, "NID_TOMBSTONE_TABLE"),
+    (NID_TOMBSTONE_DATE_TABLE, "NID_TOMBSTONE_DATE_TABLE"),
+    (
+        NID_FOLDER_PATH_TOMBSTONE_TABLE,
+        "NID_FOLDER_PATH_TOMBSTONE_TABLE",
+    ),
+    (NID_LREP_DUPS_TABLE, "NID_LREP_DUPS_TABLE"),
+];
+
+/// Look up the name of a special internal NID, e.g. `NID_ROOT_FOLDER`.
+pub fn special_node_name(node_id: NodeId) -> Option<&'static str> {
+    SPECIAL_NODE_IDS
+        .iter()
+        .find(|(special, _)| *special == node_id)
+        .map(|(_, name)| *name)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -236,4 +358,13 @@ mod tests {
         };
         assert_eq!(value, MAX_NODE_INDEX + 1);
     }
+
+    #[test]
+    fn test_special_node_ids() {
+        assert!(SPECIAL_NODE_IDS
+            .windows(2)
+            .all(|pair| u32::from(pair[0].0) < u32::from(pair[1].0)));
+        assert_eq!(special_node_name(NID_ROOT_FOLDER), Some("NID_ROOT_FOLDER"));
+        assert_eq!(special_node_name(NodeId::from(0x2222)), None);
+    }
 }
@@ -3,6 +3,8 @@
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use core::mem;
 use std::{
+    cmp,
+    collections::BTreeSet,
     fmt::Debug,
     io::{self, Cursor, Read, Seek, SeekFrom, Write},
     marker::PhantomData,
@@ -11,7 +13,7 @@ use std::{
 
 use super::{block_id::*, block_ref::*, byte_index::*, node_id::*, read_write::*, *};
 use crate::{
-    block_sig::compute_sig, crc::compute_crc, AnsiPstFile, PstFile, PstReader, UnicodePstFile,
+    block_sig::block_signature, crc::compute_crc, AnsiPstFile, PstFile, PstReader, UnicodePstFile,
 };
 
 /// `ptype`
@@ -59,10 +61,9 @@ impl TryFrom<u8> for PageType {
 impl PageType {
     pub fn signature(&self, index: u64, block_id: u64) -> u16 {
         match self {
-            PageType::BlockBTree | PageType::NodeBTree | PageType::DensityList => compute_sig(
-                (index & u64::from(u32::MAX)) as u32,
-                (block_id & u64::from(u32::MAX)) as u32,
-            ),
+            PageType::BlockBTree | PageType::NodeBTree | PageType::DensityList => {
+                block_signature(index, block_id)
+            }
             _ => 0,
         }
     }
@@ -109,6 +110,8 @@ impl PageTrailer for UnicodePageTrailer {
 }
 
 impl PageTrailerReadWrite for UnicodePageTrailer {
+    const SIZE: usize = 16;
+
     fn new(page_type: PageType, signature: u16, block_id: UnicodePageId, crc: u32) -> Self {
         Self {
             page_type,
@@ -174,6 +177,8 @@ impl PageTrailer for AnsiPageTrailer {
 }
 
 impl PageTrailerReadWrite for AnsiPageTrailer {
+    const SIZE: usize = 12;
+
     fn new(page_type: PageType, signature: u16, block_id: AnsiPageId, crc: u32) -> Self {
         Self {
             page_type,
@@ -424,240 +429,93 @@ impl<const PAGE_TYPE: u8> MapPageReadWrite<UnicodePstFile, PAGE_TYPE>
     }
 }
 
+/// The 4 bytes before the trailer of an ANSI map page which are not part of the [`MapBits`]. The
+/// pages have 500 bytes for the map, and where the other 4 bytes go depends on the page type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnsiMapPagePadding {
+    /// `dwPadding` before the `rgbAMapBits` of an AMAPPAGE or the `rgbPMapBits` of a PMAPPAGE. It
+    /// should be zero, but whatever was read is written back.
+    Leading(u32),
+    /// The last 4 bytes of the 500 byte `rgbFMapBits` of an FMAPPAGE or `rgbFPMapBits` of an
+    /// FPMAPPAGE, after the [`MapBits`]. They are map data, so they are written back unchanged.
+    Trailing([u8; 4]),
+}
+
 pub struct AnsiMapPage<const P: u8> {
     map_bits: MapBits,
     trailer: AnsiPageTrailer,
-    padding: u32,
-}
-
-impl<const PAGE_TYPE: u8> MapPage<AnsiPstFile, PAGE_TYPE> for AnsiMapPage<PAGE_TYPE> {
-    fn map_bits(&self) -> &MapBits {
-        &self.map_bits
-    }
-
-    fn map_bits_mut(&mut self) -> &mut MapBits {
-        &mut self.map_bits
-    }
-
-    fn trailer(&self) -> &AnsiPageTrailer {
-        &self.trailer
-    }
+    padding: [u8; 4],
 }
 
-impl MapPageReadWrite<AnsiPstFile, { PageType::AllocationMap as u8 }>
-    for AnsiMapPage<{ PageType::AllocationMap as u8 }>
-{
-    fn new(amap_bits: MapBits, trailer: AnsiPageTrailer) -> NdbResult<Self> {
-        if trailer.page_type() != PageType::AllocationMap {
-            return Err(NdbError::UnexpectedPageType(trailer.page_type()));
-        }
-        Ok(Self {
-            map_bits: amap_bits,
-            trailer,
-            padding: 0,
-        })
-    }
-
-    fn read(f: &mut dyn Read) -> io::Result<Self> {
-        let mut buffer = [0_u8; 500];
-        f.read_exact(&mut buffer)?;
-        let mut cursor = Cursor::new(buffer);
-
-        let padding = cursor.read_u32::<LittleEndian>()?;
-
-        let mut map_bits = [0_u8; mem::size_of::<MapBits>()];
-        cursor.read_exact(&mut map_bits)?;
-
-        let buffer = cursor.into_inner();
-
-        let trailer = AnsiPageTrailer::read(f)?;
-        if trailer.page_type() != PageType::AllocationMap {
-            return Err(NdbError::UnexpectedPageType(trailer.page_type()).into());
-        }
+impl<const PAGE_TYPE: u8> AnsiMapPage<PAGE_TYPE> {
+    /// AMap and PMap pages start with `dwPadding`, while the map bits of FMap and FPMap pages
+    /// start at the beginning of the page.
+    const LEADING_PADDING: bool = PAGE_TYPE == PageType::AllocationMap as u8
+        || PAGE_TYPE == PageType::AllocationPageMap as u8;
 
-        let crc = compute_crc(0, &buffer);
-        if crc != trailer.crc() {
-            return Err(NdbError::InvalidPageCrc(crc).into());
+    pub fn padding(&self) -> AnsiMapPagePadding {
+        if Self::LEADING_PADDING {
+            AnsiMapPagePadding::Leading(u32::from_le_bytes(self.padding))
+        } else {
+            AnsiMapPagePadding::Trailing(self.padding)
         }
-
-        Ok(Self {
-            map_bits,
-            trailer,
-            padding,
-        })
     }
 
-    fn write(&self, f: &mut dyn Write) -> io::Result<()> {
-        let mut cursor = Cursor::new([0_u8; 500]);
-
-        cursor.write_u32::<LittleEndian>(self.padding)?;
-        cursor.write_all(&self.map_bits)?;
-
-        let buffer = cursor.into_inner();
-        let crc = compute_crc(0, &buffer);
-
-        f.write_all(&buffer)?;
-
-        let trailer = AnsiPageTrailer {
-            crc,
-            ..self.trailer
+    fn map_bits_range() -> Range<usize> {
+        let start = if Self::LEADING_PADDING {
+            mem::size_of::<u32>()
+        } else {
+            0
         };
-        trailer.write(f)
-    }
-}
-
-impl MapPageReadWrite<AnsiPstFile, { PageType::AllocationPageMap as u8 }>
-    for AnsiMapPage<{ PageType::AllocationPageMap as u8 }>
-{
-    fn new(amap_bits: MapBits, trailer: AnsiPageTrailer) -> NdbResult<Self> {
-        if trailer.page_type() != PageType::AllocationPageMap {
-            return Err(NdbError::UnexpectedPageType(trailer.page_type()));
-        }
-        Ok(Self {
-            map_bits: amap_bits,
-            trailer,
-            padding: 0,
-        })
+        start..start + mem::size_of::<MapBits>()
     }
 
-    fn read(f: &mut dyn Read) -> io::Result<Self> {
-        let mut buffer = [0_u8; 500];
-        f.read_exact(&mut buffer)?;
-        let mut cursor = Cursor::new(buffer);
-
-        let padding = cursor.read_u32::<LittleEndian>()?;
-
-        let mut map_bits = [0_u8; mem::size_of::<MapBits>()];
-        cursor.read_exact(&mut map_bits)?;
-
-        let buffer = cursor.into_inner();
-
-        let trailer = AnsiPageTrailer::read(f)?;
-        if trailer.page_type() != PageType::AllocationPageMap {
-            return Err(NdbError::UnexpectedPageType(trailer.page_type()).into());
-        }
-
-        let crc = compute_crc(0, &buffer);
-        if crc != trailer.crc() {
-            return Err(NdbError::InvalidPageCrc(crc).into());
+    fn padding_range() -> Range<usize> {
+        if Self::LEADING_PADDING {
+            0..mem::size_of::<u32>()
+        } else {
+            mem::size_of::<MapBits>()..mem::size_of::<MapBits>() + mem::size_of::<u32>()
         }
-
-        Ok(Self {
-            map_bits,
-            trailer,
-            padding,
-        })
-    }
-
-    fn write(&self, f: &mut dyn Write) -> io::Result<()> {
-        let mut cursor = Cursor::new([0_u8; 500]);
-
-        cursor.write_u32::<LittleEndian>(self.padding)?;
-        cursor.write_all(&self.map_bits)?;
-
-        let buffer = cursor.into_inner();
-        let crc = compute_crc(0, &buffer);
-
-        f.write_all(&buffer)?;
-
-        let trailer = AnsiPageTrailer {
-            crc,
-            ..self.trailer
-        };
-        trailer.write(f)
     }
 }
 
-impl MapPageReadWrite<AnsiPstFile, { PageType::FreeMap as u8 }>
-    for AnsiMapPage<{ PageType::FreeMap as u8 }>
-{
-    fn new(amap_bits: MapBits, trailer: AnsiPageTrailer) -> NdbResult<Self> {
-        if trailer.page_type() != PageType::FreeMap {
-            return Err(NdbError::UnexpectedPageType(trailer.page_type()));
-        }
-        Ok(Self {
-            map_bits: amap_bits,
-            trailer,
-            padding: 0,
-        })
+impl<const PAGE_TYPE: u8> MapPage<AnsiPstFile, PAGE_TYPE> for AnsiMapPage<PAGE_TYPE> {
+    fn map_bits(&self) -> &MapBits {
+        &self.map_bits
     }
 
-    fn read(f: &mut dyn Read) -> io::Result<Self> {
-        let mut buffer = [0_u8; 500];
-        f.read_exact(&mut buffer)?;
-        let mut cursor = Cursor::new(buffer);
-
-        let mut map_bits = [0_u8; mem::size_of::<MapBits>()];
-        cursor.read_exact(&mut map_bits)?;
-
-        let padding = cursor.read_u32::<LittleEndian>()?;
-
-        let buffer = cursor.into_inner();
-
-        let trailer = AnsiPageTrailer::read(f)?;
-        if trailer.page_type() != PageType::FreeMap {
-            return Err(NdbError::UnexpectedPageType(trailer.page_type()).into());
-        }
-
-        let crc = compute_crc(0, &buffer);
-        if crc != trailer.crc() {
-            return Err(NdbError::InvalidPageCrc(crc).into());
-        }
-
-        Ok(Self {
-            map_bits,
-            trailer,
-            padding,
-        })
+    fn map_bits_mut(&mut self) -> &mut MapBits {
+        &mut self.map_bits
     }
 
-    fn write(&self, f: &mut dyn Write) -> io::Result<()> {
-        let mut cursor = Cursor::new([0_u8; 500]);
-
-        cursor.write_all(&self.map_bits)?;
-        cursor.write_u32::<LittleEndian>(self.padding)?;
-
-        let buffer = cursor.into_inner();
-        let crc = compute_crc(0, &buffer);
-
-        f.write_all(&buffer)?;
-
-        let trailer = AnsiPageTrailer {
-            crc,
-            ..self.trailer
-        };
-        trailer.write(f)
+    fn trailer(&self) -> &AnsiPageTrailer {
+        &self.trailer
     }
 }
 
-impl MapPageReadWrite<AnsiPstFile, { PageType::FreePageMap as u8 }>
-    for AnsiMapPage<{ PageType::FreePageMap as u8 }>
-{
-    fn new(amap_bits: MapBits, trailer: AnsiPageTrailer) -> NdbResult<Self> {
-        if trailer.page_type() != PageType::FreePageMap {
+impl<const PAGE_TYPE: u8> MapPageReadWrite<AnsiPstFile, PAGE_TYPE> for AnsiMapPage<PAGE_TYPE> {
+    fn new(map_bits: MapBits, trailer: AnsiPageTrailer) -> NdbResult<Self> {
+        if trailer.page_type() as u8 != PAGE_TYPE {
             return Err(NdbError::UnexpectedPageType(trailer.page_type()));
         }
         Ok(Self {
-            map_bits: amap_bits,
+            map_bits,
             trailer,
-            padding: 0,
+            padding: Default::default(),
         })
     }
 
     fn read(f: &mut dyn Read) -> io::Result<Self> {
         let mut buffer = [0_u8; 500];
         f.read_exact(&mut buffer)?;
-        let mut cursor = Cursor::new(buffer);
 
         let mut map_bits = [0_u8; mem::size_of::<MapBits>()];
-        cursor.read_exact(&mut map_bits)?;
-
-        let padding = cursor.read_u32::<LittleEndian>()?;
-
-        let buffer = cursor.into_inner();
+        map_bits.copy_from_slice(&buffer[Self::map_bits_range()]);
+        let mut padding = [0_u8; 4];
+        padding.copy_from_slice(&buffer[Self::padding_range()]);
 
         let trailer = AnsiPageTrailer::read(f)?;
-        if trailer.page_type() != PageType::FreePageMap {
+        if trailer.page_type() as u8 != PAGE_TYPE {
             return Err(NdbError::UnexpectedPageType(trailer.page_type()).into());
         }
 
@@ -674,12 +532,10 @@ impl MapPageReadWrite<AnsiPstFile, { PageType::FreePageMap as u8 }>
     }
 
     fn write(&self, f: &mut dyn Write) -> io::Result<()> {
-        let mut cursor = Cursor::new([0_u8; 500]);
-
-        cursor.write_all(&self.map_bits)?;
-        cursor.write_u32::<LittleEndian>(self.padding)?;
+        let mut buffer = [0_u8; 500];
+        buffer[Self::map_bits_range()].copy_from_slice(&self.map_bits);
+        buffer[Self::padding_range()].copy_from_slice(&self.padding);
 
-        let buffer = cursor.into_inner();
         let crc = compute_crc(0, &buffer);
 
         f.write_all(&buffer)?;
@@ -692,7 +548,7 @@ impl MapPageReadWrite<AnsiPstFile, { PageType::FreePageMap as u8 }>
     }
 }
 
-const DENSITY_LIST_ENTRY_PAGE_NUMBER_MASK: u32 = 0x000F_FFFF;
+pub(crate) const DENSITY_LIST_ENTRY_PAGE_NUMBER_MASK: u32 = 0x000F_FFFF;
 
 /// [DLISTPAGEENT](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/9d3c45b9-a415-446c-954f-b1b473dbb415)
 #[derive(Copy, Clone, Debug)]
@@ -1345,6 +1201,11 @@ pub trait BlockBTreeEntry: BTreeEntry {
     fn ref_count(&self) -> u16;
 }
 
+/// The `cRef` of a block which only one node or block refers to. Outlook counts one more
+/// reference than there are in the file, so a block is shared if its `cRef` is higher than this,
+/// and it is freed when it would drop below this.
+pub const UNSHARED_BLOCK_REF_COUNT: u16 = 2;
+
 #[derive(Copy, Clone, Default, Debug)]
 pub struct UnicodeBlockBTreeEntry {
     block: UnicodeBlockRef,
@@ -1358,7 +1219,7 @@ impl UnicodeBlockBTreeEntry {
         Self {
             block,
             size,
-            ref_count: 1,
+            ref_count: UNSHARED_BLOCK_REF_COUNT,
             ..Default::default()
         }
     }
@@ -1417,6 +1278,10 @@ impl BlockBTreeEntryReadWrite for UnicodeBlockBTreeEntry {
     fn new(block: UnicodeBlockRef, size: u16) -> Self {
         Self::new(block, size)
     }
+
+    fn set_ref_count(&mut self, ref_count: u16) {
+        self.ref_count = ref_count;
+    }
 }
 
 pub struct UnicodeBlockBTreePage {
@@ -1508,7 +1373,7 @@ impl AnsiBlockBTreeEntry {
         Self {
             block,
             size,
-            ref_count: 1,
+            ref_count: UNSHARED_BLOCK_REF_COUNT,
         }
     }
 }
@@ -1559,6 +1424,10 @@ impl BlockBTreeEntryReadWrite for AnsiBlockBTreeEntry {
     fn new(block: AnsiBlockRef, size: u16) -> Self {
         Self::new(block, size)
     }
+
+    fn set_ref_count(&mut self, ref_count: u16) {
+        self.ref_count = ref_count;
+    }
 }
 
 pub struct AnsiBlockBTreePage {
@@ -2336,6 +2205,557 @@ where
     }
 }
 
+impl<Pst, Entry, IntermediatePage, LeafPage> RootBTreePage<Pst, Entry, IntermediatePage, LeafPage>
+where
+    Pst: PstFile,
+    <Pst as PstFile>::BlockId: BlockIdReadWrite,
+    <Pst as PstFile>::ByteIndex: ByteIndexReadWrite,
+    <Pst as PstFile>::BlockRef: BlockRefReadWrite,
+    <Pst as PstFile>::PageRef: BlockRefReadWrite,
+    <Pst as PstFile>::PageTrailer: PageTrailerReadWrite,
+    <Pst as PstFile>::BTreeKey: BTreePageKeyReadWrite + Into<u64>,
+    Entry: BTreeEntry<Key = <Pst as PstFile>::BTreeKey> + BTreeEntryReadWrite,
+    IntermediatePage: RootBTreeIntermediatePage<Pst, Entry, LeafPage>,
+    LeafPage: RootBTreeLeafPage<Pst, Entry = Entry>,
+    <Self as RootBTree>::Entry: BTreeEntry<Key = <Pst as PstFile>::BTreeKey> + BTreeEntryReadWrite,
+    <Self as RootBTree>::IntermediatePage: RootBTreeIntermediatePageReadWrite<Pst, Entry, LeafPage>,
+    <Self as RootBTree>::LeafPage: RootBTreeLeafPageReadWrite<Pst>,
+    <IntermediatePage as BTreePage>::Entry: BTreeEntryReadWrite + BTreePageEntryReadWrite,
+{
+    pub(crate) fn level(&self) -> u8 {
+        match self {
+            Self::Intermediate(page, ..) => page.level(),
+            Self::Leaf(page) => page.level(),
+        }
+    }
+
+    /// Insert a new entry in the BTree starting at the `root` page. Every page on the path to the
+    /// entry is written to a copy taken from `pages`, unless it was already written in this
+    /// transaction, and full pages are split into pages taken from `pages` as well. This returns
+    /// the new root page, which the caller needs to store in the [`super::root::Root`].
+    pub(crate) fn insert_entry<R: PstReader, W: Write + Seek>(
+        reader: &mut R,
+        writer: &mut W,
+        root: <Pst as PstFile>::PageRef,
+        entry: Entry,
+        pages: &mut BTreePageAllocation<Pst>,
+    ) -> io::Result<<Pst as PstFile>::PageRef> {
+        let page = Self::read(reader, root)?;
+        let (first_key, block, split) =
+            page.insert_page_entry(reader, writer, root, entry, pages)?;
+        let Some((split_key, split_block)) = split else {
+            return Ok(block);
+        };
+
+        // The root page was split, so we need to add another level to the BTree.
+        let new_root = pages.allocate()?;
+        let entry_size =
+            <<IntermediatePage as BTreePage>::Entry as BTreePageEntryReadWrite>::ENTRY_SIZE;
+        let max_entries = LeafPage::BTREE_ENTRIES_SIZE / entry_size;
+        let entries = [
+            <<IntermediatePage as BTreePage>::Entry as BTreePageEntryReadWrite>::new(
+                first_key, block,
+            ),
+            <<IntermediatePage as BTreePage>::Entry as BTreePageEntryReadWrite>::new(
+                split_key,
+                split_block,
+            ),
+        ];
+        let page = <IntermediatePage as BTreePageReadWrite>::new(
+            page.level() + 1,
+            max_entries as u8,
+            entry_size as u8,
+            &entries,
+            Self::new_page_trailer(page.page_type(), new_root),
+        )?;
+        Self::Intermediate(Box::new(page), PhantomData).write(writer, new_root)?;
+
+        Ok(new_root)
+    }
+
+    /// Replace an existing entry with the same key in the BTree starting at the `root` page, and
+    /// return the new root page. Pages are copied the same way as in [`Self::insert_entry`].
+    pub(crate) fn update_entry<R: PstReader, W: Write + Seek>(
+        reader: &mut R,
+        writer: &mut W,
+        root: <Pst as PstFile>::PageRef,
+        entry: Entry,
+        pages: &mut BTreePageAllocation<Pst>,
+    ) -> io::Result<<Pst as PstFile>::PageRef> {
+        let page = Self::read(reader, root)?;
+        page.update_page_entry(reader, writer, root, entry, pages)
+    }
+
+    /// Remove an existing entry from the BTree starting at the `root` page, and return the entry
+    /// which was removed along with the new root page. Pages are copied the same way as in
+    /// [`Self::insert_entry`]. A page is merged with its neighbor when they fit in one page, and
+    /// the root is replaced by its only child once it has one, so the tree does not fill up with
+    /// empty pages.
+    pub(crate) fn remove_entry<R: PstReader, W: Write + Seek>(
+        reader: &mut R,
+        writer: &mut W,
+        root: <Pst as PstFile>::PageRef,
+        key: <Pst as PstFile>::BTreeKey,
+        pages: &mut BTreePageAllocation<Pst>,
+    ) -> io::Result<(Entry, <Pst as PstFile>::PageRef)> {
+        let page = Self::read(reader, root)?;
+        let (entry, page) = page.remove_page_entry(reader, writer, key, pages)?;
+        if let Self::Intermediate(page, ..) = &page {
+            if let [child] = page.entries() {
+                pages.retire(root);
+                return Ok((entry, child.block()));
+            }
+        }
+        let root = page.write_copy(writer, root, pages)?;
+        Ok((entry, root))
+    }
+
+    fn page_type(&self) -> PageType {
+        match self {
+            Self::Intermediate(page, ..) => page.trailer().page_type(),
+            Self::Leaf(page) => page.trailer().page_type(),
+        }
+    }
+
+    fn entry_count(&self) -> usize {
+        match self {
+            Self::Intermediate(page, ..) => page.entries().len(),
+            Self::Leaf(page) => page.entries().len(),
+        }
+    }
+
+    fn max_entries(&self) -> usize {
+        usize::from(match self {
+            Self::Intermediate(page, ..) => page.max_entries(),
+            Self::Leaf(page) => page.max_entries(),
+        })
+    }
+
+    fn new_page_trailer(
+        page_type: PageType,
+        block: <Pst as PstFile>::PageRef,
+    ) -> <Pst as PstFile>::PageTrailer {
+        let signature = page_type.signature(block.index().index().into(), block.block().into_u64());
+        <<Pst as PstFile>::PageTrailer as PageTrailerReadWrite>::new(
+            page_type,
+            signature,
+            block.block(),
+            0,
+        )
+    }
+
+    /// Build a page with the same header as `page` and `entries` instead of its own, and the
+    /// trailer for `block`.
+    fn rebuild_page<Page>(
+        page: &Page,
+        entries: &[<Page as BTreePage>::Entry],
+        block: <Pst as PstFile>::PageRef,
+    ) -> NdbResult<Page>
+    where
+        Page: BTreePageReadWrite<Trailer = <Pst as PstFile>::PageTrailer>,
+    {
+        let trailer = if page.trailer().block_id() == block.block() {
+            *page.trailer()
+        } else {
+            Self::new_page_trailer(page.trailer().page_type(), block)
+        };
+        Page::new(
+            page.level(),
+            page.max_entries(),
+            page.entry_size(),
+            entries,
+            trailer,
+        )
+    }
+
+    /// Write this page, which was read from `block` and then modified, to a copy from `pages`,
+    /// unless `block` was already written in this transaction. Returns where it was written.
+    fn write_copy<W: Write + Seek>(
+        self,
+        writer: &mut W,
+        block: <Pst as PstFile>::PageRef,
+        pages: &mut BTreePageAllocation<Pst>,
+    ) -> io::Result<<Pst as PstFile>::PageRef> {
+        let target = pages.copy_of(block)?;
+        let page = match self {
+            Self::Intermediate(page, ..) => Self::Intermediate(
+                Box::new(Self::rebuild_page(page.as_ref(), page.entries(), target)?),
+                PhantomData,
+            ),
+            Self::Leaf(page) => Self::Leaf(Box::new(Self::rebuild_page(
+                page.as_ref(),
+                page.entries(),
+                target,
+            )?)),
+        };
+        page.write(writer, target)?;
+        Ok(target)
+    }
+
+    /// Append the entries of `right` to this page, which must be at the same level.
+    fn merge(self, right: Self) -> NdbResult<Self> {
+        match (self, right) {
+            (Self::Intermediate(left, ..), Self::Intermediate(right, ..)) => {
+                let entries = [left.entries(), right.entries()].concat();
+                let page = <IntermediatePage as BTreePageReadWrite>::new(
+                    left.level(),
+                    left.max_entries(),
+                    left.entry_size(),
+                    &entries,
+                    *left.trailer(),
+                )?;
+                Ok(Self::Intermediate(Box::new(page), PhantomData))
+            }
+            (Self::Leaf(left), Self::Leaf(right)) => {
+                let entries = [left.entries(), right.entries()].concat();
+                let page = <LeafPage as BTreePageReadWrite>::new(
+                    0,
+                    left.max_entries(),
+                    left.entry_size(),
+                    &entries,
+                    *left.trailer(),
+                )?;
+                Ok(Self::Leaf(Box::new(page)))
+            }
+            (_, right) => Err(NdbError::InvalidBTreePageLevel(right.level())),
+        }
+    }
+
+    fn find_child_page(
+        page: &IntermediatePage,
+        key: u64,
+    ) -> NdbResult<(usize, <Pst as PstFile>::PageRef)> {
+        let entries = <IntermediatePage as BTreePage>::entries(page);
+        let index = entries
+            .partition_point(|entry| entry.key().into() <= key)
+            .saturating_sub(1);
+        let entry = entries.get(index).ok_or(NdbError::BTreePageNotFound(key))?;
+        Ok((index, entry.block()))
+    }
+
+    fn insert_page_entry<R: PstReader, W: Write + Seek>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        block: <Pst as PstFile>::PageRef,
+        entry: Entry,
+        pages: &mut BTreePageAllocation<Pst>,
+    ) -> io::Result<BTreeSplitResult<Pst>> {
+        let key: u64 = entry.key().into();
+        match self {
+            Self::Intermediate(page, ..) => {
+                let (index, child) = Self::find_child_page(page, key)?;
+                let child_page = Self::read(reader, child)?;
+                let (first_key, child, split) =
+                    child_page.insert_page_entry(reader, writer, child, entry, pages)?;
+
+                let mut entries = page.entries().to_vec();
+                entries[index] =
+                    <<IntermediatePage as BTreePage>::Entry as BTreePageEntryReadWrite>::new(
+                        first_key, child,
+                    );
+                let append = split.is_some() && index + 1 == entries.len();
+                if let Some((split_key, split_block)) = split {
+                    entries.insert(
+                        index + 1,
+                        <<IntermediatePage as BTreePage>::Entry as BTreePageEntryReadWrite>::new(
+                            split_key,
+                            split_block,
+                        ),
+                    );
+                }
+
+                Self::write_page_entries(
+                    writer,
+                    block,
+                    page.as_ref(),
+                    entries,
+                    append,
+                    pages,
+                    |page| Self::Intermediate(Box::new(page), PhantomData),
+                )
+            }
+            Self::Leaf(page) => {
+                let mut entries = page.entries().to_vec();
+                let index = entries.partition_point(|entry| entry.key().into() < key);
+                if entries
+                    .get(index)
+                    .is_some_and(|entry| entry.key().into() == key)
+                {
+                    return Err(NdbError::DuplicateBTreeEntry(key).into());
+                }
+                let append = index == entries.len();
+                entries.insert(index, entry);
+
+                Self::write_page_entries(
+                    writer,
+                    block,
+                    page.as_ref(),
+                    entries,
+                    append,
+                    pages,
+                    |page| Self::Leaf(Box::new(page)),
+                )
+            }
+        }
+    }
+
+    /// Write the updated `entries` of the page at `block` to a copy of it, splitting it if it no
+    /// longer fits. If the new entry was appended at the end of the page, only that entry moves to
+    /// the new page, so the pages stay full when keys are inserted in ascending order, like the
+    /// block IDs in the Block BTree. Otherwise the page is split in half.
+    fn write_page_entries<W, Page>(
+        writer: &mut W,
+        block: <Pst as PstFile>::PageRef,
+        page: &Page,
+        mut entries: Vec<<Page as BTreePage>::Entry>,
+        append: bool,
+        pages: &mut BTreePageAllocation<Pst>,
+        wrap: impl Fn(Page) -> Self,
+    ) -> io::Result<BTreeSplitResult<Pst>>
+    where
+        W: Write + Seek,
+        Page: BTreePageReadWrite<Trailer = <Pst as PstFile>::PageTrailer>,
+        <Page as BTreePage>::Entry: BTreeEntry<Key = <Pst as PstFile>::BTreeKey>,
+    {
+        let first_key = entries
+            .first()
+            .map(|entry| entry.key())
+            .ok_or(NdbError::InvalidBTreeEntryCount(0))?;
+
+        if entries.len() <= usize::from(page.max_entries()) {
+            let target = pages.copy_of(block)?;
+            wrap(Self::rebuild_page(page, &entries, target)?).write(writer, target)?;
+            return Ok((first_key, target, None));
+        }
+
+        let split_at = if append {
+            entries.len() - 1
+        } else {
+            entries.len() / 2
+        };
+        let split_entries = entries.split_off(split_at);
+        let split_key = split_entries[0].key();
+
+        let target = pages.copy_of(block)?;
+        wrap(Self::rebuild_page(page, &entries, target)?).write(writer, target)?;
+
+        let split_block = pages.allocate()?;
+        wrap(Self::rebuild_page(page, &split_entries, split_block)?).write(writer, split_block)?;
+
+        Ok((first_key, target, Some((split_key, split_block))))
+    }
+
+    fn update_page_entry<R: PstReader, W: Write + Seek>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        block: <Pst as PstFile>::PageRef,
+        entry: Entry,
+        pages: &mut BTreePageAllocation<Pst>,
+    ) -> io::Result<<Pst as PstFile>::PageRef> {
+        let key: u64 = entry.key().into();
+        let page = match self {
+            Self::Intermediate(page, ..) => {
+                let (index, child) = Self::find_child_page(page, key)?;
+                let child_page = Self::read(reader, child)?;
+                let child = child_page.update_page_entry(reader, writer, child, entry, pages)?;
+
+                let mut entries = page.entries().to_vec();
+                entries[index] =
+                    <<IntermediatePage as BTreePage>::Entry as BTreePageEntryReadWrite>::new(
+                        entries[index].key(),
+                        child,
+                    );
+                let page = Self::rebuild_page(page.as_ref(), &entries, block)?;
+                Self::Intermediate(Box::new(page), PhantomData)
+            }
+            Self::Leaf(page) => {
+                let mut entries = page.entries().to_vec();
+                let existing = entries
+                    .iter_mut()
+                    .find(|existing| existing.key().into() == key)
+                    .ok_or(NdbError::BTreePageNotFound(key))?;
+                *existing = entry;
+
+                Self::Leaf(Box::new(Self::rebuild_page(
+                    page.as_ref(),
+                    &entries,
+                    block,
+                )?))
+            }
+        };
+        page.write_copy(writer, block, pages)
+    }
+
+    /// Remove the entry with `key` from the tree beneath this page, and return it along with this
+    /// page after the change, which the caller still needs to write.
+    fn remove_page_entry<R: PstReader, W: Write + Seek>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        key: <Pst as PstFile>::BTreeKey,
+        pages: &mut BTreePageAllocation<Pst>,
+    ) -> io::Result<(Entry, Self)> {
+        let search_key: u64 = key.into();
+        match self {
+            Self::Intermediate(page, ..) => {
+                let (index, child) = Self::find_child_page(page, search_key)?;
+                let (entry, child_page) =
+                    Self::read(reader, child)?.remove_page_entry(reader, writer, key, pages)?;
+
+                let mut entries = page.entries().to_vec();
+                let neighbor = if index + 1 < entries.len() {
+                    Some(index + 1)
+                } else {
+                    index.checked_sub(1)
+                };
+                let neighbor = match neighbor {
+                    Some(neighbor) => {
+                        let neighbor_page = Self::read(reader, entries[neighbor].block())?;
+                        (child_page.entry_count() + neighbor_page.entry_count()
+                            <= child_page.max_entries())
+                        .then_some((neighbor, neighbor_page))
+                    }
+                    None => None,
+                };
+
+                match neighbor {
+                    Some((neighbor, neighbor_page)) => {
+                        let left = cmp::min(index, neighbor);
+                        let merged = if neighbor > index {
+                            child_page.merge(neighbor_page)?
+                        } else {
+                            neighbor_page.merge(child_page)?
+                        };
+                        let block = merged.write_copy(writer, entries[left].block(), pages)?;
+                        pages.retire(entries[left + 1].block());
+                        entries[left] =
+                            <<IntermediatePage as BTreePage>::Entry as BTreePageEntryReadWrite>::new(
+                                entries[left].key(),
+                                block,
+                            );
+                        entries.remove(left + 1);
+                    }
+                    None => {
+                        let block = child_page.write_copy(writer, child, pages)?;
+                        entries[index] =
+                            <<IntermediatePage as BTreePage>::Entry as BTreePageEntryReadWrite>::new(
+                                entries[index].key(),
+                                block,
+                            );
+                    }
+                }
+
+                let page = <IntermediatePage as BTreePageReadWrite>::new(
+                    page.level(),
+                    page.max_entries(),
+                    page.entry_size(),
+                    &entries,
+                    *page.trailer(),
+                )?;
+                Ok((entry, Self::Intermediate(Box::new(page), PhantomData)))
+            }
+            Self::Leaf(page) => {
+                let mut entries = page.entries().to_vec();
+                let index = entries
+                    .iter()
+                    .position(|entry| entry.key().into() == search_key)
+                    .ok_or(NdbError::BTreePageNotFound(search_key))?;
+                let entry = entries.remove(index);
+
+                let page = <LeafPage as BTreePageReadWrite>::new(
+                    0,
+                    page.max_entries(),
+                    page.entry_size(),
+                    &entries,
+                    *page.trailer(),
+                )?;
+                Ok((entry, Self::Leaf(Box::new(page))))
+            }
+        }
+    }
+}
+
+/// The pages which a copy-on-write change to a [`RootBTreePage`] tree writes to. Pages which are
+/// part of the committed tree are never written in place: the change writes a copy of each page
+/// on the path from the root, and the replaced pages are only freed once the new root has been
+/// committed. Pages which were already written earlier in the same transaction are not part of
+/// the committed tree, so they are updated in place.
+pub(crate) struct BTreePageAllocation<'a, Pst>
+where
+    Pst: PstFile,
+{
+    /// File offsets of the pages which were allocated in this transaction.
+    fresh_pages: &'a BTreeSet<u64>,
+    /// Pages which were allocated up front for copies and splits.
+    free_pages: Vec<<Pst as PstFile>::PageRef>,
+    /// Pages which were replaced by a copy or merged into another page.
+    retired_pages: Vec<<Pst as PstFile>::PageRef>,
+}
+
+impl<'a, Pst> BTreePageAllocation<'a, Pst>
+where
+    Pst: PstFile,
+{
+    pub fn new(fresh_pages: &'a BTreeSet<u64>, free_pages: Vec<<Pst as PstFile>::PageRef>) -> Self {
+        Self {
+            fresh_pages,
+            free_pages,
+            retired_pages: Default::default(),
+        }
+    }
+
+    /// Number of pages to allocate up front for one change to a tree with `level` levels above
+    /// its leaf pages: one copy of each page on the path from the root, a new page for each of
+    /// them if it needs to be split, and a new root.
+    pub fn pages_needed(level: u8) -> usize {
+        2 * usize::from(level) + 3
+    }
+
+    fn allocate(&mut self) -> NdbResult<<Pst as PstFile>::PageRef> {
+        self.free_pages
+            .pop()
+            .ok_or(NdbError::BTreeSplitPageNotFound)
+    }
+
+    /// Where to write the modified contents of the page at `block`.
+    fn copy_of(
+        &mut self,
+        block: <Pst as PstFile>::PageRef,
+    ) -> NdbResult<<Pst as PstFile>::PageRef> {
+        if self.fresh_pages.contains(&block.index().index().into()) {
+            return Ok(block);
+        }
+        let copy = self.allocate()?;
+        self.retire(block);
+        Ok(copy)
+    }
+
+    fn retire(&mut self, block: <Pst as PstFile>::PageRef) {
+        self.retired_pages.push(block);
+    }
+
+    /// The pages which were not used, followed by the pages which were replaced. Both need to be
+    /// freed by the caller.
+    pub fn into_pages(
+        self,
+    ) -> (
+        Vec<<Pst as PstFile>::PageRef>,
+        Vec<<Pst as PstFile>::PageRef>,
+    ) {
+        (self.free_pages, self.retired_pages)
+    }
+}
+
+/// The first key in a page after inserting an entry, where the page was written, and if the page
+/// had to be split, the first key and location of the new page.
+type BTreeSplitResult<Pst> = (
+    <Pst as PstFile>::BTreeKey,
+    <Pst as PstFile>::PageRef,
+    Option<(<Pst as PstFile>::BTreeKey, <Pst as PstFile>::PageRef)>,
+);
+
 pub type UnicodeBTree<Entry, LeafPage> =
     RootBTreePage<UnicodePstFile, Entry, UnicodeBTreeEntryPage, LeafPage>;
 
@@ -2379,3 +2799,65 @@ impl NodeBTreeReadWrite<UnicodePstFile, UnicodeNodeBTreeEntry> for UnicodeNodeBT
 pub type AnsiNodeBTree = AnsiBTree<AnsiNodeBTreeEntry, AnsiNodeBTreePage>;
 impl NodeBTree<AnsiPstFile, AnsiNodeBTreeEntry> for AnsiNodeBTree {}
 impl NodeBTreeReadWrite<AnsiPstFile, AnsiNodeBTreeEntry> for AnsiNodeBTree {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ANSI map page with distinct bytes in every position, so a shift of the map bits or the
+    /// padding changes the bytes which are written back.
+    fn ansi_map_page_bytes(page_type: PageType) -> Vec<u8> {
+        let data: Vec<u8> = (0..500).map(|i| (i % 251) as u8).collect();
+        let crc = compute_crc(0, &data);
+        let trailer = AnsiPageTrailer::new(page_type, 0x1234, AnsiPageId::from(0x4400), crc);
+
+        let mut page = data;
+        trailer.write(&mut page).unwrap();
+        page
+    }
+
+    fn round_trip<const PAGE_TYPE: u8>(page_type: PageType) -> AnsiMapPagePadding {
+        let bytes = ansi_map_page_bytes(page_type);
+        let page = <AnsiMapPage<PAGE_TYPE> as MapPageReadWrite<AnsiPstFile, PAGE_TYPE>>::read(
+            &mut bytes.as_slice(),
+        )
+        .unwrap();
+
+        let offset = AnsiMapPage::<PAGE_TYPE>::map_bits_range().start;
+        assert_eq!(page.map_bits()[..], bytes[offset..offset + 496]);
+
+        let mut written = vec![];
+        page.write(&mut written).unwrap();
+        assert_eq!(written, bytes);
+        page.padding()
+    }
+
+    #[test]
+    fn test_ansi_map_page_padding() {
+        assert_eq!(
+            round_trip::<{ PageType::AllocationMap as u8 }>(PageType::AllocationMap),
+            AnsiMapPagePadding::Leading(u32::from_le_bytes([0, 1, 2, 3]))
+        );
+        assert_eq!(
+            round_trip::<{ PageType::AllocationPageMap as u8 }>(PageType::AllocationPageMap),
+            AnsiMapPagePadding::Leading(u32::from_le_bytes([0, 1, 2, 3]))
+        );
+        assert_eq!(
+            round_trip::<{ PageType::FreeMap as u8 }>(PageType::FreeMap),
+            AnsiMapPagePadding::Trailing([245, 246, 247, 248])
+        );
+        assert_eq!(
+            round_trip::<{ PageType::FreePageMap as u8 }>(PageType::FreePageMap),
+            AnsiMapPagePadding::Trailing([245, 246, 247, 248])
+        );
+
+        let bytes = ansi_map_page_bytes(PageType::FreeMap);
+        assert!(
+            <AnsiMapPage<{ PageType::AllocationMap as u8 }> as MapPageReadWrite<
+                AnsiPstFile,
+                { PageType::AllocationMap as u8 },
+            >>::read(&mut bytes.as_slice())
+            .is_err()
+        );
+    }
+}
@@ -1,21 +1,19 @@
 #![allow(dead_code)]
 
 use std::{
-    cell::RefCell,
     cmp::Ordering,
     collections::BTreeMap,
     io::{self, Cursor, Read, Seek, SeekFrom, Write},
-    rc::Rc,
+    sync::{
+        atomic::{self, AtomicUsize},
+        Mutex, MutexGuard, PoisonError,
+    },
 };
 
 use super::{
     block::*, block_id::*, block_ref::*, byte_index::*, header::*, node_id::*, page::*, root::*, *,
 };
-use crate::{
-    crc::compute_crc,
-    encode::{cyclic, permute},
-    PstFile, PstReader,
-};
+use crate::{crc::compute_crc, encode::encode_block_data, PstFile, PstReader};
 
 pub trait NodeIdReadWrite: Copy + Sized {
     fn new(id_type: NodeIdType, index: u32) -> NdbResult<Self>;
@@ -24,6 +22,7 @@ pub trait NodeIdReadWrite: Copy + Sized {
 }
 
 pub trait BlockIdReadWrite: BlockId {
+    fn new(is_internal: bool, index: Self::Index) -> NdbResult<Self>;
     fn read(f: &mut dyn Read) -> io::Result<Self>;
     fn write(&self, f: &mut dyn Write) -> io::Result<()>;
 }
@@ -114,6 +113,10 @@ where
 
     fn set_amap_status(&mut self, status: AmapStatus);
     fn reset_free_size(&mut self, free_bytes: <Pst as PstFile>::ByteIndex) -> NdbResult<()>;
+    fn set_file_eof_index(&mut self, file_eof_index: <Pst as PstFile>::ByteIndex);
+    fn set_amap_last_index(&mut self, amap_last_index: <Pst as PstFile>::ByteIndex);
+    fn set_node_btree(&mut self, node_btree: <Pst as PstFile>::PageRef);
+    fn set_block_btree(&mut self, block_btree: <Pst as PstFile>::PageRef);
 }
 
 pub trait HeaderReadWrite<Pst>: Header<Pst> + Sized
@@ -121,14 +124,23 @@ where
     Pst: PstFile,
     <Pst as PstFile>::Root: Root<Pst> + RootReadWrite<Pst>,
 {
-    fn read(f: &mut dyn Read) -> io::Result<Self>;
+    fn read(f: &mut dyn Read) -> io::Result<Self> {
+        Self::read_with_mode(f, HeaderFieldMode::Strict)
+    }
+
+    fn read_with_mode(f: &mut dyn Read, mode: HeaderFieldMode) -> io::Result<Self>;
     fn write(&self, f: &mut dyn Write) -> io::Result<()>;
     fn update_unique(&mut self);
+    fn allocate_nid(&mut self, id_type: NodeIdType) -> NdbResult<NodeId>;
+    fn set_next_block(&mut self, next_block: <Pst as PstFile>::BlockId);
+    fn set_next_page(&mut self, next_page: <Pst as PstFile>::PageId);
     fn first_free_map(&mut self) -> &mut [u8];
     fn first_free_page_map(&mut self) -> &mut [u8];
 }
 
 pub trait PageTrailerReadWrite: PageTrailer + Copy + Sized {
+    const SIZE: usize;
+
     fn new(page_type: PageType, signature: u16, block_id: Self::BlockId, crc: u32) -> Self;
     fn read(f: &mut dyn Read) -> io::Result<Self>;
     fn write(&self, f: &mut dyn Write) -> io::Result<()>;
@@ -274,6 +286,7 @@ pub trait BTreeEntryReadWrite: BTreeEntry + Copy + Sized + Default {
 
 pub trait BlockBTreeEntryReadWrite: BlockBTreeEntry + BTreeEntryReadWrite {
     fn new(block: Self::Block, size: u16) -> Self;
+    fn set_ref_count(&mut self, ref_count: u16);
 }
 
 pub trait BTreePageEntryReadWrite: BTreePageEntry
@@ -587,16 +600,7 @@ where
             return Err(NdbError::InvalidBlockCrc(crc).into());
         }
 
-        match encoding {
-            NdbCryptMethod::Cyclic => {
-                let key = trailer.cyclic_key();
-                cyclic::encode_decode_block(&mut data, key);
-            }
-            NdbCryptMethod::Permute => {
-                permute::decode_block(&mut data);
-            }
-            _ => {}
-        }
+        encode_block_data(&mut data, encoding, trailer.cyclic_key(), true);
 
         Ok(Self::new(encoding, data, trailer)?)
     }
@@ -605,16 +609,7 @@ where
         let mut data = self.data().to_vec();
         let trailer = self.trailer();
 
-        match self.encoding() {
-            NdbCryptMethod::Cyclic => {
-                let key = trailer.cyclic_key();
-                cyclic::encode_decode_block(&mut data, key);
-            }
-            NdbCryptMethod::Permute => {
-                permute::encode_block(&mut data);
-            }
-            _ => {}
-        }
+        encode_block_data(&mut data, self.encoding(), trailer.cyclic_key(), false);
 
         let crc = compute_crc(0, &data);
         let trailer = Self::Trailer::new(
@@ -746,8 +741,59 @@ type RootBTreePageReadWrite<BTree> = RootBTreePage<
 pub type RootBTreePageCache<BTree> =
     BTreeMap<<<BTree as RootBTree>::Pst as PstFile>::PageId, RootBTreePageReadWrite<BTree>>;
 
-pub type BlockBTreePageCache<Pst> = Rc<RefCell<RootBTreePageCache<<Pst as PstFile>::BlockBTree>>>;
-pub type NodeBTreePageCache<Pst> = Rc<RefCell<RootBTreePageCache<<Pst as PstFile>::NodeBTree>>>;
+/// Number of shards in a [`ShardedPageCache`].
+const PAGE_CACHE_SHARDS: usize = 16;
+
+thread_local! {
+    /// The shard of every [`ShardedPageCache`] which this thread uses, handed out round-robin.
+    static PAGE_CACHE_SHARD: usize = {
+        static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+        NEXT_SHARD.fetch_add(1, atomic::Ordering::Relaxed) % PAGE_CACHE_SHARDS
+    };
+}
+
+/// A [`RootBTreePageCache`] which is split into shards by thread, so threads which read the same
+/// file each fill their own shard instead of waiting for one lock.
+pub struct ShardedPageCache<BTree>
+where
+    BTree: RootBTree,
+{
+    shards: [Mutex<RootBTreePageCache<BTree>>; PAGE_CACHE_SHARDS],
+}
+
+impl<BTree> Default for ShardedPageCache<BTree>
+where
+    BTree: RootBTree,
+{
+    fn default() -> Self {
+        Self {
+            shards: std::array::from_fn(|_| Default::default()),
+        }
+    }
+}
+
+impl<BTree> ShardedPageCache<BTree>
+where
+    BTree: RootBTree,
+{
+    /// Lock the shard for the current thread.
+    pub fn shard(&self) -> MutexGuard<'_, RootBTreePageCache<BTree>> {
+        let shard = PAGE_CACHE_SHARD.with(|shard| *shard);
+        self.shards[shard]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Forget the pages in every shard, e.g. after the BTree was modified.
+    pub fn clear(&self) {
+        for shard in &self.shards {
+            shard.lock().unwrap_or_else(PoisonError::into_inner).clear();
+        }
+    }
+}
+
+pub type BlockBTreePageCache<Pst> = ShardedPageCache<<Pst as PstFile>::BlockBTree>;
+pub type NodeBTreePageCache<Pst> = ShardedPageCache<<Pst as PstFile>::NodeBTree>;
 
 pub trait RootBTreeReadWrite: RootBTree + Sized
 where
@@ -170,6 +170,22 @@ impl RootReadWrite<UnicodePstFile> for UnicodeRoot {
         self.pmap_free_size = 0.into();
         Ok(())
     }
+
+    fn set_file_eof_index(&mut self, file_eof_index: UnicodeByteIndex) {
+        self.file_eof_index = file_eof_index;
+    }
+
+    fn set_amap_last_index(&mut self, amap_last_index: UnicodeByteIndex) {
+        self.amap_last_index = amap_last_index;
+    }
+
+    fn set_node_btree(&mut self, node_btree: UnicodePageRef) {
+        self.node_btree = node_btree;
+    }
+
+    fn set_block_btree(&mut self, block_btree: UnicodePageRef) {
+        self.block_btree = block_btree;
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -289,4 +305,20 @@ impl RootReadWrite<AnsiPstFile> for AnsiRoot {
         self.pmap_free_size = 0.into();
         Ok(())
     }
+
+    fn set_file_eof_index(&mut self, file_eof_index: AnsiByteIndex) {
+        self.file_eof_index = file_eof_index;
+    }
+
+    fn set_amap_last_index(&mut self, amap_last_index: AnsiByteIndex) {
+        self.amap_last_index = amap_last_index;
+    }
+
+    fn set_node_btree(&mut self, node_btree: AnsiPageRef) {
+        self.node_btree = node_btree;
+    }
+
+    fn set_block_btree(&mut self, block_btree: AnsiPageRef) {
+        self.block_btree = block_btree;
+    }
 }
@@ -0,0 +1,174 @@
+//! Read a PST file with positioned reads, where the platform supports them.
+//!
+//! Reading a page or block from a [`File`] takes a seek and a read, which are separate system
+//! calls. [`PositionedFile`] keeps the position in memory instead, so seeking is free and each
+//! read is a single `pread` on Unix or `ReadFile` with an offset on Windows. Its
+//! [`PositionedFile::read_at`] method only needs `&self`, so a [`PstFile`](crate::PstFile) which
+//! is shared between threads reads from it without taking a lock, see [`ReadAt`].
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+
+/// A source which can be read at any offset through a shared reference. Every reader of a
+/// [`PstFile`](crate::PstFile) reads from one of these, so reads on different threads don't wait
+/// for each other unless the source itself needs a lock, like [`SharedReader`].
+pub trait ReadAt: Send + Sync {
+    /// Read into `buf` from `offset`, and return the number of bytes read, which is only less
+    /// than the length of `buf` at the end of the source or if the read was interrupted.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// The length of the source in bytes.
+    fn len(&self) -> io::Result<u64>;
+
+    fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+}
+
+/// A [`File`] which implements [`Read`] and [`Seek`] on top of positioned reads.
+/// [`UnicodePstFile::open`](crate::UnicodePstFile::open) and
+/// [`AnsiPstFile::open`](crate::AnsiPstFile::open) read through one of these.
+#[derive(Debug)]
+pub struct PositionedFile {
+    file: File,
+    position: u64,
+}
+
+impl PositionedFile {
+    pub fn new(file: File) -> Self {
+        Self { file, position: 0 }
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::new(File::open(path)?))
+    }
+
+    /// Read into `buf` from `offset` in the file, without changing the position used by
+    /// [`Read`] and [`Seek`]. Returns the number of bytes read, which is only less than the
+    /// length of `buf` at the end of the file or if the read was interrupted.
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::FileExt::read_at(&self.file, buf, offset)
+        }
+
+        #[cfg(windows)]
+        {
+            std::os::windows::fs::FileExt::seek_read(&self.file, buf, offset)
+        }
+
+        #[cfg(not(any(unix, windows)))]
+        {
+            let mut file = &self.file;
+            file.seek(SeekFrom::Start(offset))?;
+            file.read(buf)
+        }
+    }
+
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+}
+
+impl ReadAt for PositionedFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        PositionedFile::read_at(self, buf, offset)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+}
+
+/// Wraps any [`Read`] and [`Seek`] source in a [`Mutex`], so it can be used as a [`ReadAt`].
+/// Reads from this source take turns on the lock, so sources which support positioned reads
+/// should implement [`ReadAt`] directly instead, like [`PositionedFile`].
+pub struct SharedReader<R> {
+    reader: Mutex<R>,
+}
+
+impl<R> SharedReader<R>
+where
+    R: Read + Seek + Send,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: Mutex::new(reader),
+        }
+    }
+}
+
+impl<R> ReadAt for SharedReader<R>
+where
+    R: Read + Seek + Send,
+{
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let mut reader = self.reader.lock().unwrap_or_else(PoisonError::into_inner);
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read(buf)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        let mut reader = self.reader.lock().unwrap_or_else(PoisonError::into_inner);
+        reader.seek(SeekFrom::End(0))
+    }
+}
+
+impl Read for PositionedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.read_at(buf, self.position)?;
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl Seek for PositionedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.file.metadata()?.len().checked_add_signed(offset),
+        }
+        .ok_or(io::ErrorKind::InvalidInput)?;
+        Ok(self.position)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positioned_reads() {
+        const EMPTY_PST: &[u8] = include_bytes!("../examples/Empty.pst");
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst");
+        let mut file = PositionedFile::open(path).unwrap();
+
+        let mut buf = [0_u8; 512];
+        file.seek(SeekFrom::Start(0x4400)).unwrap();
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, EMPTY_PST[0x4400..0x4600]);
+        assert_eq!(file.stream_position().unwrap(), 0x4600);
+
+        file.seek(SeekFrom::Current(-0x100)).unwrap();
+        file.read_exact(&mut buf[..4]).unwrap();
+        assert_eq!(buf[..4], EMPTY_PST[0x4500..0x4504]);
+
+        assert_eq!(file.read_at(&mut buf[..8], 0).unwrap(), 8);
+        assert_eq!(buf[..8], EMPTY_PST[..8]);
+        assert_eq!(file.stream_position().unwrap(), 0x4504);
+
+        let end = file.seek(SeekFrom::End(0)).unwrap();
+        assert_eq!(end, EMPTY_PST.len() as u64);
+        assert_eq!(file.read(&mut buf).unwrap(), 0);
+        assert!(file.seek(SeekFrom::Current(-(end as i64) - 1)).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+//! Read-ahead for scans which know which pages and blocks they are going to read next.
+//!
+//! Wrap the reader in a [`PrefetchReader`] and pass it to
+//! [`UnicodePstFile::read_from`](crate::UnicodePstFile::read_from) or
+//! [`AnsiPstFile::read_from`](crate::AnsiPstFile::read_from). Before walking a folder or exporting
+//! a batch of messages, [announce](PrefetchQueue::announce) the file ranges of the blocks which
+//! will be read. When a read lands in an announced range, the reader fetches it along with any
+//! following announced ranges in a single sequential read of up to
+//! [`PrefetchReader::chunk_size`] bytes, and serves the next reads from that buffer.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    io::{self, Read, Seek, SeekFrom},
+    ops::Range,
+    rc::Rc,
+};
+
+use crate::PstReader;
+
+/// Default size of each read-ahead, 1MB.
+pub const DEFAULT_PREFETCH_CHUNK_SIZE: usize = 0x10_0000;
+
+/// Shared handle to the ranges which a [`PrefetchReader`] should read ahead. Cloning it shares
+/// the same queue.
+#[derive(Clone, Default)]
+pub struct PrefetchQueue {
+    ranges: Rc<RefCell<VecDeque<Range<u64>>>>,
+}
+
+impl PrefetchQueue {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Announce the file ranges which are about to be read, e.g. the offset and
+    /// [`block_size`](crate::ndb::block::block_size) of each block in a folder's messages. The
+    /// queue is kept sorted by file offset, so the ranges may be announced in any order.
+    pub fn announce(&self, ranges: impl IntoIterator<Item = Range<u64>>) {
+        let mut queue = self.ranges.borrow_mut();
+        queue.extend(ranges.into_iter().filter(|range| !range.is_empty()));
+        queue
+            .make_contiguous()
+            .sort_by_key(|range| (range.start, range.end));
+    }
+
+    /// Forget any ranges which have not been read yet.
+    pub fn clear(&self) {
+        self.ranges.borrow_mut().clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.borrow().is_empty()
+    }
+
+    /// Drop every range which ends at or before `offset`, and if the next range contains
+    /// `offset`, return the span from `offset` through as many of the following ranges as fit in
+    /// `chunk_size` bytes.
+    fn next_chunk(&self, offset: u64, chunk_size: u64) -> Option<Range<u64>> {
+        let mut queue = self.ranges.borrow_mut();
+        while queue.front().is_some_and(|range| range.end <= offset) {
+            queue.pop_front();
+        }
+
+        let first = queue.front()?;
+        if first.start > offset {
+            return None;
+        }
+
+        let limit = offset + chunk_size;
+        let mut end = first.end.min(limit);
+        for range in queue.iter().skip(1) {
+            if range.end > limit {
+                break;
+            }
+            end = end.max(range.end);
+        }
+
+        while queue.front().is_some_and(|range| range.end <= end) {
+            queue.pop_front();
+        }
+
+        Some(offset..end)
+    }
+}
+
+/// Wrap a [`PstReader`] to read ahead through the ranges announced in a [`PrefetchQueue`].
+/// Reads outside of the announced ranges go straight to the underlying reader.
+pub struct PrefetchReader<R>
+where
+    R: PstReader,
+{
+    reader: R,
+    position: u64,
+    chunk_size: usize,
+    queue: PrefetchQueue,
+    buffer: Vec<u8>,
+    buffer_offset: u64,
+}
+
+impl<R> PrefetchReader<R>
+where
+    R: PstReader,
+{
+    pub fn new(mut reader: R, queue: PrefetchQueue) -> io::Result<Self> {
+        let position = reader.stream_position()?;
+        Ok(Self {
+            reader,
+            position,
+            chunk_size: DEFAULT_PREFETCH_CHUNK_SIZE,
+            queue,
+            buffer: Default::default(),
+            buffer_offset: 0,
+        })
+    }
+
+    /// Maximum number of bytes fetched in each read-ahead.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn set_chunk_size(&mut self, chunk_size: usize) {
+        self.chunk_size = chunk_size.max(1);
+    }
+
+    pub fn queue(&self) -> &PrefetchQueue {
+        &self.queue
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn buffered(&self) -> Option<&[u8]> {
+        let start = usize::try_from(self.position.checked_sub(self.buffer_offset)?).ok()?;
+        self.buffer.get(start..).filter(|data| !data.is_empty())
+    }
+
+    fn fill_buffer(&mut self, chunk: Range<u64>) -> io::Result<()> {
+        let length = usize::try_from(chunk.end - chunk.start)
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        self.buffer.resize(length, 0);
+        self.reader.seek(SeekFrom::Start(chunk.start))?;
+
+        let mut filled = 0;
+        while filled < length {
+            match self.reader.read(&mut self.buffer[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        self.buffer.truncate(filled);
+        self.buffer_offset = chunk.start;
+        Ok(())
+    }
+}
+
+impl<R> Read for PrefetchReader<R>
+where
+    R: PstReader,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffered().is_none() {
+            if let Some(chunk) = self.queue.next_chunk(self.position, self.chunk_size as u64) {
+                self.fill_buffer(chunk)?;
+            }
+        }
+
+        let length = match self.buffered() {
+            Some(data) => {
+                let length = data.len().min(buf.len());
+                buf[..length].copy_from_slice(&data[..length]);
+                length
+            }
+            None => {
+                self.reader.seek(SeekFrom::Start(self.position))?;
+                self.reader.read(buf)?
+            }
+        };
+
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+impl<R> Seek for PrefetchReader<R>
+where
+    R: PstReader,
+{
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(offset) => self
+                .position
+                .checked_add_signed(offset)
+                .ok_or(io::Error::from(io::ErrorKind::InvalidInput))?,
+            SeekFrom::End(_) => self.reader.seek(pos)?,
+        };
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io_trace::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_prefetch_announced_ranges() {
+        let data: Vec<u8> = (0..=u8::MAX).cycle().take(8192).collect();
+        let trace = IoAccessTrace::new();
+        let reader = IoAccessTraceReader::new(Cursor::new(data.clone()), trace.clone())
+            .expect("Failed to wrap reader");
+        let queue = PrefetchQueue::new();
+        let mut reader = PrefetchReader::new(reader, queue.clone()).expect("Failed to wrap reader");
+
+        queue.announce([2048..2112, 512..576, 1024..1088]);
+        let mut buffer = [0_u8; 64];
+        for offset in [512, 1024, 2048] {
+            reader
+                .seek(SeekFrom::Start(offset))
+                .expect("Failed to seek");
+            reader.read_exact(&mut buffer).expect("Failed to read");
+            assert_eq!(buffer, data[offset as usize..offset as usize + 64]);
+        }
+        assert!(queue.is_empty());
+
+        let accesses = trace.accesses();
+        assert_eq!(accesses.len(), 1);
+        assert_eq!(accesses[0].offset(), 512);
+        assert_eq!(accesses[0].length(), 2112 - 512);
+
+        reader.seek(SeekFrom::Start(4096)).expect("Failed to seek");
+        reader.read_exact(&mut buffer).expect("Failed to read");
+        assert_eq!(buffer, data[4096..4160]);
+        assert_eq!(trace.accesses().len(), 2);
+    }
+}
@@ -0,0 +1,347 @@
+//! A quick health check for a PST file which only reads a bounded sample of its pages, e.g. as a
+//! pre-flight check before ingesting a file, where reading all of it like
+//! [`PstFileLockGuard::validate_structures`](crate::PstFileLockGuard::validate_structures) or
+//! [`fix_crcs`](crate::repair::fix_crcs) would take too long.
+//!
+//! [`PstFile::quick_check`](crate::PstFile::quick_check) checks these in order, till it runs out
+//! of the IO or time budget in the [`QuickCheckOptions`]:
+//! 1. The header, which is read again to check its CRCs.
+//! 2. The root, whose offsets must be inside the file.
+//! 3. The first and the last AMap page.
+//! 4. The root pages of the Node BTree and the Block BTree.
+//! 5. Leaf pages of both BTrees, reached by picking a random entry in each intermediate page on
+//!    the way down. The intermediate pages are checked as well.
+//!
+//! Each page must have a valid trailer, CRC, and signature, and BTree pages must have keys in
+//! ascending order at the level their parent expects. A file which passes every check can still
+//! be damaged in the pages and blocks which were not sampled.
+
+use std::{
+    fmt::{self, Display},
+    time::{Duration, Instant},
+};
+
+/// Budget and sampling for [`PstFile::quick_check`](crate::PstFile::quick_check).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QuickCheckOptions {
+    max_bytes: u64,
+    max_duration: Duration,
+    leaf_samples: usize,
+    seed: u64,
+}
+
+impl Default for QuickCheckOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: 64 * 1024,
+            max_duration: Duration::from_secs(1),
+            leaf_samples: 8,
+            seed: 0,
+        }
+    }
+}
+
+impl QuickCheckOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Stop before reading more than `max_bytes` from the file. The default is 64 KiB.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Stop starting new checks after `max_duration`. The default is 1 second.
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
+
+    /// Number of random leaf pages to check, split between the Node BTree and the Block BTree.
+    /// The default is 8.
+    pub fn with_leaf_samples(mut self, leaf_samples: usize) -> Self {
+        self.leaf_samples = leaf_samples;
+        self
+    }
+
+    /// Seed for picking the leaf pages, so the same file is always sampled the same way with the
+    /// same seed. The default is 0.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    pub fn max_duration(&self) -> Duration {
+        self.max_duration
+    }
+
+    pub fn leaf_samples(&self) -> usize {
+        self.leaf_samples
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+/// The structure which was checked by a [`QuickCheckSample`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuickCheckTarget {
+    Header,
+    Root,
+    /// The AMap page at the given file offset.
+    AllocationMapPage(u64),
+    /// A page of the Node BTree at the given file offset.
+    NodeBTreePage(u64),
+    /// A page of the Block BTree at the given file offset.
+    BlockBTreePage(u64),
+}
+
+/// The result of a single check in a [`QuickCheckReport`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuickCheckSample {
+    pub(crate) target: QuickCheckTarget,
+    pub(crate) problem: Option<String>,
+}
+
+impl QuickCheckSample {
+    pub fn target(&self) -> QuickCheckTarget {
+        self.target
+    }
+
+    /// What was wrong with the target, or `None` if it passed.
+    pub fn problem(&self) -> Option<&str> {
+        self.problem.as_deref()
+    }
+
+    pub fn passed(&self) -> bool {
+        self.problem.is_none()
+    }
+}
+
+impl Display for QuickCheckSample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.target {
+            QuickCheckTarget::Header => write!(f, "Header")?,
+            QuickCheckTarget::Root => write!(f, "Root")?,
+            QuickCheckTarget::AllocationMapPage(offset) => write!(f, "AMap page at 0x{offset:X}")?,
+            QuickCheckTarget::NodeBTreePage(offset) => write!(f, "NBT page at 0x{offset:X}")?,
+            QuickCheckTarget::BlockBTreePage(offset) => write!(f, "BBT page at 0x{offset:X}")?,
+        }
+        match self.problem.as_deref() {
+            Some(problem) => write!(f, ": {problem}"),
+            None => write!(f, ": OK"),
+        }
+    }
+}
+
+/// The checks run by [`PstFile::quick_check`](crate::PstFile::quick_check).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct QuickCheckReport {
+    pub(crate) samples: Vec<QuickCheckSample>,
+    pub(crate) skipped: usize,
+    pub(crate) bytes_read: u64,
+    pub(crate) elapsed: Duration,
+}
+
+impl QuickCheckReport {
+    /// Every check which was run, in order.
+    pub fn samples(&self) -> &[QuickCheckSample] {
+        &self.samples
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &QuickCheckSample> {
+        self.samples.iter().filter(|sample| !sample.passed())
+    }
+
+    /// Number of checks which were skipped because they did not fit in the budget.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// No check failed, although some of them may have been skipped.
+    pub fn is_healthy(&self) -> bool {
+        self.samples.iter().all(QuickCheckSample::passed)
+    }
+
+    /// How sure the check is that the file is readable, from 0 to 1. This is 0 if any check
+    /// failed, otherwise the share of the planned checks which ran before the budget ran out.
+    pub fn confidence(&self) -> f64 {
+        let planned = self.samples.len() + self.skipped;
+        if planned == 0 || !self.is_healthy() {
+            return 0.0;
+        }
+        self.samples.len() as f64 / planned as f64
+    }
+}
+
+/// Tracks the budget while [`PstFile::quick_check`](crate::PstFile::quick_check) runs. Once a
+/// check does not fit, every check after it is skipped as well.
+pub(crate) struct QuickCheckRun {
+    options: QuickCheckOptions,
+    start: Instant,
+    exhausted: bool,
+    report: QuickCheckReport,
+}
+
+impl QuickCheckRun {
+    pub(crate) fn new(options: QuickCheckOptions) -> Self {
+        Self {
+            options,
+            start: Instant::now(),
+            exhausted: false,
+            report: Default::default(),
+        }
+    }
+
+    pub(crate) fn options(&self) -> &QuickCheckOptions {
+        &self.options
+    }
+
+    /// Whether a check which reads `bytes` from the file fits in the remaining budget. If it does
+    /// not, it is counted as skipped.
+    pub(crate) fn start_check(&mut self, bytes: u64) -> bool {
+        if !self.exhausted
+            && (self.report.bytes_read + bytes > self.options.max_bytes
+                || self.start.elapsed() >= self.options.max_duration)
+        {
+            self.exhausted = true;
+        }
+        if self.exhausted {
+            self.report.skipped += 1;
+        }
+        !self.exhausted
+    }
+
+    /// Count `bytes` read for a check, without recording a sample for it yet.
+    pub(crate) fn add_bytes(&mut self, bytes: u64) {
+        self.report.bytes_read += bytes;
+    }
+
+    pub(crate) fn finish_check(&mut self, target: QuickCheckTarget, problem: Option<String>) {
+        self.report
+            .samples
+            .push(QuickCheckSample { target, problem });
+    }
+
+    pub(crate) fn finish(mut self) -> QuickCheckReport {
+        self.report.elapsed = self.start.elapsed();
+        self.report
+    }
+}
+
+/// SplitMix64, which is enough to spread the leaf samples across a BTree without depending on an
+/// RNG crate.
+pub(crate) struct SampleRng(u64);
+
+impl SampleRng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// A random index less than `len`, which must not be 0.
+    pub(crate) fn next_index(&mut self, len: usize) -> usize {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z % len as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ndb::{block_ref::BlockRef, byte_index::ByteIndex, header::Header, root::Root},
+        PstFile, UnicodePstFile,
+    };
+    use std::io::Cursor;
+
+    const EMPTY_PST: &[u8] = include_bytes!("../examples/Empty.pst");
+
+    #[test]
+    fn test_quick_check() {
+        let pst = UnicodePstFile::read_from(Box::new(Cursor::new(EMPTY_PST))).unwrap();
+        let report = pst.quick_check(&QuickCheckOptions::new()).unwrap();
+        assert!(report.is_healthy(), "{:?}", report.samples());
+        assert_eq!(report.skipped(), 0);
+        assert_eq!(report.confidence(), 1.0);
+        assert!(report.bytes_read() <= QuickCheckOptions::new().max_bytes());
+
+        let report = pst
+            .quick_check(&QuickCheckOptions::new().with_max_bytes(1024))
+            .unwrap();
+        assert_eq!(report.samples().len(), 2);
+        assert!(report.skipped() > 0);
+        assert!(report.confidence() > 0.0 && report.confidence() < 1.0);
+
+        // Damage an entry in the root page of the Node BTree.
+        let node_btree = pst.header().root().node_btree().index().index() as usize;
+        let mut data = EMPTY_PST.to_vec();
+        data[node_btree + 1] ^= 0xFF;
+        let pst = UnicodePstFile::read_from(Box::new(Cursor::new(data))).unwrap();
+        let report = pst.quick_check(&QuickCheckOptions::new()).unwrap();
+        let failures: Vec<_> = report.failures().collect();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(
+            failures[0].target(),
+            QuickCheckTarget::NodeBTreePage(node_btree as u64)
+        );
+        assert_eq!(report.confidence(), 0.0);
+    }
+
+    #[test]
+    fn test_confidence() {
+        let passed = |target| QuickCheckSample {
+            target,
+            problem: None,
+        };
+        let mut report = QuickCheckReport {
+            samples: vec![
+                passed(QuickCheckTarget::Header),
+                passed(QuickCheckTarget::Root),
+            ],
+            skipped: 2,
+            ..Default::default()
+        };
+        assert!(report.is_healthy());
+        assert_eq!(report.confidence(), 0.5);
+
+        report.samples.push(QuickCheckSample {
+            target: QuickCheckTarget::NodeBTreePage(0x4600),
+            problem: Some("bad CRC".into()),
+        });
+        assert!(!report.is_healthy());
+        assert_eq!(report.confidence(), 0.0);
+        assert_eq!(
+            report.failures().next().unwrap().to_string(),
+            "NBT page at 0x4600: bad CRC"
+        );
+    }
+
+    #[test]
+    fn test_sample_rng() {
+        let mut rng = SampleRng::new(0);
+        let indices: Vec<_> = (0..64).map(|_| rng.next_index(5)).collect();
+        assert!(indices.iter().all(|index| *index < 5));
+        assert!((0..5).all(|index| indices.contains(&index)));
+
+        let mut other = SampleRng::new(0);
+        assert_eq!(other.next_index(5), indices[0]);
+    }
+}
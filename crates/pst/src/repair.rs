@@ -0,0 +1,59 @@
+//! Repairs for PST files whose contents are intact, but which fail integrity checks.
+
+use std::io;
+
+use crate::{ndb::page::PageType, PstFile, PstFileLockGuard};
+
+/// The kind of structure which was repaired by [`fix_crcs`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RepairTarget {
+    /// A [`PAGE_SIZE`](crate::ndb::page::PAGE_SIZE) page of the given [`PageType`].
+    Page(PageType),
+    /// A block in the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085)
+    /// with the given `BID`.
+    Block(u64),
+}
+
+/// A page or block trailer which was rewritten by [`fix_crcs`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RepairedLocation {
+    pub(crate) offset: u64,
+    pub(crate) target: RepairTarget,
+    pub(crate) fixed_crc: bool,
+    pub(crate) fixed_signature: bool,
+}
+
+impl RepairedLocation {
+    /// File offset of the start of the page or block.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn target(&self) -> RepairTarget {
+        self.target
+    }
+
+    /// The `dwCRC` field in the trailer did not match the contents.
+    pub fn fixed_crc(&self) -> bool {
+        self.fixed_crc
+    }
+
+    /// The `wSig` field in the trailer did not match the file offset and ID.
+    pub fn fixed_signature(&self) -> bool {
+        self.fixed_signature
+    }
+}
+
+/// Recompute the `dwCRC` and `wSig` fields in the trailers of every AMap, PMap, FMap, FPMap,
+/// NBT, and BBT page, and every block referenced by the BBT, rewriting any which do not match.
+/// This fixes files where the data is still readable, but the checksums are stale, e.g. after
+/// editing them with a hex editor or copying part of another file over them.
+///
+/// Every repaired location is logged and returned. Pages and blocks whose trailers cannot be
+/// parsed at all are not readable, and they are left alone.
+pub fn fix_crcs<Pst>(pst: &mut PstFileLockGuard<'_, Pst>) -> io::Result<Vec<RepairedLocation>>
+where
+    Pst: PstFile,
+{
+    pst.pst.fix_crcs()
+}
@@ -0,0 +1,448 @@
+//! Machine-readable report of the [`StructuralIssue`]s found by
+//! [`PstFileLockGuard::validate_structures`](crate::PstFileLockGuard::validate_structures), the
+//! [`UnresolvedTableRow`]s found by [`Store::verify_table_rows`](crate::messaging::store::Store::verify_table_rows),
+//! and the [`RepairedLocation`]s fixed by [`fix_crcs`](crate::repair::fix_crcs), so the results
+//! for many PST files can be collected and compared.
+//!
+//! [`IntegrityReport::to_json`] writes an object with this schema:
+//!
+//! ```json
+//! {
+//!   "schema": "outlook-pst/integrity-report/1",
+//!   "source": "archive.pst",
+//!   "findings": [
+//!     {
+//!       "code": "bbt_page_invalid",
+//!       "severity": "error",
+//!       "structure": "block_btree_page",
+//!       "offset": 17408,
+//!       "node": null,
+//!       "sub_node": null,
+//!       "block": null,
+//!       "message": "keys are not in ascending order"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! - `source` is `null` if the report was not given one.
+//! - `code` is one of the [`FindingCode`] values, and `severity` is one of the [`Severity`]
+//!   values, in `snake_case`.
+//! - `structure` names the kind of page or block, see [`Finding::structure`].
+//! - `offset`, `node`, `sub_node` and `block` are numbers, or `null` if they do not apply. `node`
+//!   and `sub_node` are the 32-bit `NID` values, and `block` is the `BID`.
+//! - `message` describes the finding for a person reading the report.
+
+use std::fmt::Write;
+
+use crate::{
+    export::json::write_json_string,
+    messaging::store::UnresolvedTableRow,
+    ndb::{
+        node_id::{NodeId, NodeIdType},
+        page::PageType,
+    },
+    repair::{RepairTarget, RepairedLocation},
+    validate::{StructuralIssue, ValidationTarget},
+};
+
+/// Identifies the version of the JSON written by [`IntegrityReport::to_json`].
+pub const INTEGRITY_REPORT_SCHEMA: &str = "outlook-pst/integrity-report/1";
+
+/// How serious a [`Finding`] is, in ascending order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The problem was repaired, but the file had been damaged.
+    Warning,
+    /// The structure would not be readable.
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// Stable identifier for the kind of a [`Finding`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FindingCode {
+    /// A page of the Node BTree failed validation.
+    NbtPageInvalid,
+    /// A page of the Block BTree failed validation.
+    BbtPageInvalid,
+    /// The heap, BTH, PC or TC in the data of a node or sub-node failed validation.
+    NodeDataInvalid,
+    /// The `dwCRC` of a page or block trailer was rewritten.
+    CrcRepaired,
+    /// The `wSig` of a page or block trailer was rewritten.
+    SignatureRepaired,
+    /// The `dwRowID` of a row in a folder table does not resolve to a node.
+    TableRowUnresolved,
+}
+
+impl FindingCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NbtPageInvalid => "nbt_page_invalid",
+            Self::BbtPageInvalid => "bbt_page_invalid",
+            Self::NodeDataInvalid => "node_data_invalid",
+            Self::CrcRepaired => "crc_repaired",
+            Self::SignatureRepaired => "signature_repaired",
+            Self::TableRowUnresolved => "table_row_unresolved",
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::NbtPageInvalid
+            | Self::BbtPageInvalid
+            | Self::NodeDataInvalid
+            | Self::TableRowUnresolved => Severity::Error,
+            Self::CrcRepaired | Self::SignatureRepaired => Severity::Warning,
+        }
+    }
+}
+
+/// One entry in an [`IntegrityReport`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    code: FindingCode,
+    structure: &'static str,
+    offset: Option<u64>,
+    node: Option<NodeId>,
+    sub_node: Option<NodeId>,
+    block: Option<u64>,
+    message: String,
+}
+
+impl Finding {
+    pub fn code(&self) -> FindingCode {
+        self.code
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.code.severity()
+    }
+
+    /// `node_btree_page`, `block_btree_page`, `node`, `block`, the kind of table for an unresolved
+    /// row, e.g. `contents_table`, or the kind of page for a repaired page trailer, e.g.
+    /// `allocation_map_page`.
+    pub fn structure(&self) -> &'static str {
+        self.structure
+    }
+
+    /// File offset of the page or block, if it is known.
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    pub fn node(&self) -> Option<NodeId> {
+        self.node
+    }
+
+    pub fn sub_node(&self) -> Option<NodeId> {
+        self.sub_node
+    }
+
+    pub fn block(&self) -> Option<u64> {
+        self.block
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// One finding for each field of the trailer which was rewritten.
+    fn from_repair(repair: &RepairedLocation) -> impl Iterator<Item = Self> {
+        let (structure, block) = match repair.target() {
+            RepairTarget::Page(page_type) => (page_structure(page_type), None),
+            RepairTarget::Block(block) => ("block", Some(block)),
+        };
+        let finding = move |code, message: &str| Self {
+            code,
+            structure,
+            offset: Some(repair.offset()),
+            node: None,
+            sub_node: None,
+            block,
+            message: message.to_string(),
+        };
+
+        [
+            repair
+                .fixed_crc()
+                .then(|| finding(FindingCode::CrcRepaired, "dwCRC did not match the contents")),
+            repair.fixed_signature().then(|| {
+                finding(
+                    FindingCode::SignatureRepaired,
+                    "wSig did not match the offset and ID",
+                )
+            }),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    fn write_json(&self, json: &mut String) {
+        fn write_number(json: &mut String, value: Option<u64>) {
+            match value {
+                Some(value) => {
+                    let _ = write!(json, "{value}");
+                }
+                None => json.push_str("null"),
+            }
+        }
+
+        let _ = write!(
+            json,
+            "{{\"code\":\"{}\",\"severity\":\"{}\",\"structure\":\"{}\",\"offset\":",
+            self.code.as_str(),
+            self.severity().as_str(),
+            self.structure
+        );
+        write_number(json, self.offset);
+        json.push_str(",\"node\":");
+        write_number(json, self.node.map(|node| u64::from(u32::from(node))));
+        json.push_str(",\"sub_node\":");
+        write_number(json, self.sub_node.map(|node| u64::from(u32::from(node))));
+        json.push_str(",\"block\":");
+        write_number(json, self.block);
+        json.push_str(",\"message\":");
+        write_json_string(json, &self.message);
+        json.push('}');
+    }
+}
+
+impl From<&StructuralIssue> for Finding {
+    fn from(issue: &StructuralIssue) -> Self {
+        let (code, structure, offset, node, sub_node) = match issue.target() {
+            ValidationTarget::NodeBTreePage(offset) => (
+                FindingCode::NbtPageInvalid,
+                "node_btree_page",
+                Some(offset),
+                None,
+                None,
+            ),
+            ValidationTarget::BlockBTreePage(offset) => (
+                FindingCode::BbtPageInvalid,
+                "block_btree_page",
+                Some(offset),
+                None,
+                None,
+            ),
+            ValidationTarget::Node { node, sub_node } => (
+                FindingCode::NodeDataInvalid,
+                "node",
+                None,
+                Some(node),
+                sub_node,
+            ),
+        };
+
+        Self {
+            code,
+            structure,
+            offset,
+            node,
+            sub_node,
+            block: None,
+            message: issue.description().to_string(),
+        }
+    }
+}
+
+impl From<&UnresolvedTableRow> for Finding {
+    fn from(row: &UnresolvedTableRow) -> Self {
+        let structure = match row.table_type() {
+            NodeIdType::HierarchyTable => "hierarchy_table",
+            NodeIdType::AssociatedContentsTable => "associated_contents_table",
+            _ => "contents_table",
+        };
+
+        Self {
+            code: FindingCode::TableRowUnresolved,
+            structure,
+            offset: None,
+            node: NodeId::new(row.table_type(), row.folder().index()).ok(),
+            sub_node: None,
+            block: None,
+            message: format!("row 0x{:08X}: {}", row.row(), row.reason().description()),
+        }
+    }
+}
+
+fn page_structure(page_type: PageType) -> &'static str {
+    match page_type {
+        PageType::None => "page",
+        PageType::BlockBTree => "block_btree_page",
+        PageType::NodeBTree => "node_btree_page",
+        PageType::FreeMap => "free_map_page",
+        PageType::AllocationPageMap => "allocation_page_map_page",
+        PageType::AllocationMap => "allocation_map_page",
+        PageType::FreePageMap => "free_page_map_page",
+        PageType::DensityList => "density_list_page",
+    }
+}
+
+/// The findings for one PST file, which can be written as JSON with
+/// [`IntegrityReport::to_json`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IntegrityReport {
+    source: Option<String>,
+    findings: Vec<Finding>,
+}
+
+impl IntegrityReport {
+    /// Start an empty report, where `source` identifies the file, e.g. its path.
+    pub fn new(source: Option<&str>) -> Self {
+        Self {
+            source: source.map(str::to_string),
+            findings: vec![],
+        }
+    }
+
+    pub fn add_structural_issues<'a>(
+        &mut self,
+        issues: impl IntoIterator<Item = &'a StructuralIssue>,
+    ) {
+        self.findings.extend(issues.into_iter().map(Finding::from));
+    }
+
+    pub fn add_unresolved_rows<'a>(
+        &mut self,
+        rows: impl IntoIterator<Item = &'a UnresolvedTableRow>,
+    ) {
+        self.findings.extend(rows.into_iter().map(Finding::from));
+    }
+
+    pub fn add_repairs<'a>(&mut self, repairs: impl IntoIterator<Item = &'a RepairedLocation>) {
+        self.findings
+            .extend(repairs.into_iter().flat_map(Finding::from_repair));
+    }
+
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    pub fn findings(&self) -> &[Finding] {
+        &self.findings
+    }
+
+    /// The most serious finding in the report, or `None` if the file is clean.
+    pub fn max_severity(&self) -> Option<Severity> {
+        self.findings.iter().map(Finding::severity).max()
+    }
+
+    /// Serialize the report with the schema described in the [module](self) documentation, as a
+    /// single line so reports for many files can be written as JSON Lines.
+    pub fn to_json(&self) -> String {
+        let mut json = String::new();
+        json.push_str("{\"schema\":");
+        write_json_string(&mut json, INTEGRITY_REPORT_SCHEMA);
+        json.push_str(",\"source\":");
+        match self.source.as_deref() {
+            Some(source) => write_json_string(&mut json, source),
+            None => json.push_str("null"),
+        }
+        json.push_str(",\"findings\":[");
+        for (index, finding) in self.findings.iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            finding.write_json(&mut json);
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messaging::store::UnresolvedRowReason;
+
+    #[test]
+    fn test_integrity_report_json() {
+        let node = NodeId::new(NodeIdType::NormalMessage, 0x400).unwrap();
+        let mut report = IntegrityReport::new(Some("C:\\archive.pst"));
+        assert_eq!(report.max_severity(), None);
+
+        report.add_repairs(&[RepairedLocation {
+            offset: 0x4400,
+            target: RepairTarget::Block(0x24),
+            fixed_crc: true,
+            fixed_signature: true,
+        }]);
+        assert_eq!(report.max_severity(), Some(Severity::Warning));
+
+        report.add_structural_issues(&[StructuralIssue {
+            target: ValidationTarget::Node {
+                node,
+                sub_node: None,
+            },
+            description: "bad \"heap\"".to_string(),
+        }]);
+        assert_eq!(report.max_severity(), Some(Severity::Error));
+        assert_eq!(report.findings().len(), 3);
+
+        assert_eq!(
+            report.to_json(),
+            concat!(
+                r#"{"schema":"outlook-pst/integrity-report/1","source":"C:\\archive.pst","findings":["#,
+                r#"{"code":"crc_repaired","severity":"warning","structure":"block","offset":17408,"node":null,"sub_node":null,"block":36,"message":"dwCRC did not match the contents"},"#,
+                r#"{"code":"signature_repaired","severity":"warning","structure":"block","offset":17408,"node":null,"sub_node":null,"block":36,"message":"wSig did not match the offset and ID"},"#,
+                r#"{"code":"node_data_invalid","severity":"error","structure":"node","offset":null,"node":32772,"sub_node":null,"block":null,"message":"bad \"heap\""}]}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_unresolved_row_findings() {
+        let missing = |_| Ok(false);
+        let classify =
+            |row, table_type| UnresolvedRowReason::classify(row, table_type, missing).unwrap();
+        assert_eq!(
+            classify(0, NodeIdType::ContentsTable),
+            Some(UnresolvedRowReason::NullRowId)
+        );
+        assert_eq!(
+            classify(0x8022, NodeIdType::ContentsTable),
+            Some(UnresolvedRowReason::InvalidNodeType)
+        );
+        assert_eq!(
+            classify(0x200024, NodeIdType::ContentsTable),
+            Some(UnresolvedRowReason::MissingNode)
+        );
+        assert_eq!(
+            UnresolvedRowReason::classify(0x8022, NodeIdType::HierarchyTable, |_| Ok(true))
+                .unwrap(),
+            None
+        );
+
+        let store = crate::open_store(
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst"),
+        )
+        .unwrap();
+        assert_eq!(store.verify_table_rows().unwrap(), vec![]);
+
+        let mut report = IntegrityReport::new(None);
+        report.add_unresolved_rows(&[UnresolvedTableRow {
+            folder: NodeId::new(NodeIdType::NormalFolder, 0x400).unwrap(),
+            table_type: NodeIdType::ContentsTable,
+            row: 0,
+            reason: UnresolvedRowReason::NullRowId,
+        }]);
+        assert_eq!(report.max_severity(), Some(Severity::Error));
+        assert_eq!(
+            report.to_json(),
+            concat!(
+                r#"{"schema":"outlook-pst/integrity-report/1","source":null,"findings":["#,
+                r#"{"code":"table_row_unresolved","severity":"error","structure":"contents_table","offset":null,"node":32782,"sub_node":null,"block":null,"message":"row 0x00000000: dwRowID is 0"}]}"#,
+            )
+        );
+    }
+}
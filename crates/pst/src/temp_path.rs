@@ -0,0 +1,208 @@
+//! Files and directories in the temp directory for tests, which are removed again when the
+//! [`TempPath`] is dropped, even if the test panics.
+
+use std::{
+    fs::{self, File},
+    io::{Seek, SeekFrom},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    messaging::{message::MessageBuilder, object_id::FolderId},
+    ndb::{
+        block_id::{AnsiBlockId, AnsiPageId},
+        block_ref::AnsiPageRef,
+        byte_index::AnsiByteIndex,
+        header::{AnsiHeader, Header},
+        page::*,
+        read_write::*,
+        root::{AmapStatus, AnsiRoot, Root},
+    },
+    open_store, AnsiPstFile, PstFile, PstFileLock, UnicodePstFile, AMAP_DATA_SIZE,
+    AMAP_FIRST_OFFSET, PMAP_FIRST_OFFSET,
+};
+
+/// A unique path in the temp directory, which is removed along with everything in it on drop.
+#[derive(Debug)]
+pub(crate) struct TempPath(PathBuf);
+
+impl TempPath {
+    /// Pick a new path ending with `name`, which is unique across tests running in parallel in
+    /// this process and in other processes. Nothing is created at the path yet.
+    pub fn new(name: &str) -> Self {
+        static NEXT_INDEX: AtomicUsize = AtomicUsize::new(0);
+        let index = NEXT_INDEX.fetch_add(1, Ordering::Relaxed);
+        Self(
+            std::env::temp_dir().join(format!("outlook-pst-{}-{index}-{name}", std::process::id())),
+        )
+    }
+
+    /// Copy `examples/Empty.pst` to a new path for `name`, which the test can write to.
+    pub fn empty_pst(name: &str) -> Self {
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst");
+        let path = Self::new(&format!("{name}.pst"));
+        fs::copy(source, &path).unwrap();
+        path
+    }
+
+    /// Like [`TempPath::empty_pst`], with a message of each of the `message_classes` added to the
+    /// IPM subtree.
+    pub fn empty_pst_with_messages(name: &str, message_classes: &[&str]) -> Self {
+        let path = Self::empty_pst(name);
+        let folder = {
+            let store = open_store(&path).unwrap();
+            let entry_id = store.properties().ipm_sub_tree_entry_id().unwrap();
+            FolderId::try_from(entry_id.node_id()).unwrap()
+        };
+        let mut pst = UnicodePstFile::open(&path).unwrap();
+        let mut guard = pst.lock().unwrap();
+        for message_class in message_classes {
+            guard
+                .create_message(folder, &MessageBuilder::new(message_class))
+                .unwrap();
+        }
+        guard.flush().unwrap();
+        drop(guard);
+        path
+    }
+
+    /// Build an ANSI copy of `examples/Empty.pst` for `name`, since there is no ANSI file in the
+    /// examples. The NBT and BBT start out as empty leaf pages after the first AMap and PMap pages,
+    /// and the AMap is rebuilt when the file is first locked. Then every node of the Unicode file is
+    /// written to it through [`PstFileLockGuard::create_node`](crate::PstFileLockGuard::create_node),
+    /// so building it exercises the ANSI write path. The node data is copied as-is, so the strings
+    /// in it keep their `PtypString` type.
+    pub fn empty_ansi_pst(name: &str) -> Self {
+        let source =
+            UnicodePstFile::open(Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst"))
+                .unwrap();
+        let mut nodes = vec![];
+        {
+            let reader = &mut source.reader();
+            let mut pending =
+                vec![UnicodeNodeBTree::read(reader, *source.header().root().node_btree()).unwrap()];
+            while let Some(page) = pending.pop() {
+                match page {
+                    RootBTreePage::Intermediate(page, ..) => {
+                        for entry in page.entries() {
+                            pending.push(UnicodeNodeBTree::read(reader, entry.block()).unwrap());
+                        }
+                    }
+                    RootBTreePage::Leaf(page) => nodes.extend(
+                        page.entries()
+                            .iter()
+                            .map(|entry| (entry.node(), entry.data(), entry.parent())),
+                    ),
+                }
+            }
+        }
+
+        let path = Self::new(&format!("{name}.pst"));
+        {
+            let node_btree = PMAP_FIRST_OFFSET + PAGE_SIZE as u64;
+            let block_btree = node_btree + PAGE_SIZE as u64;
+            let page_ref = |page: u32, index: u64| {
+                AnsiPageRef::new(AnsiPageId::from(page), AnsiByteIndex::new(index as u32))
+            };
+            let trailer = |page_type: PageType, page: u32, index: u64| {
+                AnsiPageTrailer::new(
+                    page_type,
+                    page_type.signature(index, u64::from(page)),
+                    AnsiPageId::from(page),
+                    0,
+                )
+            };
+
+            let root = AnsiRoot::new(
+                AnsiByteIndex::new((AMAP_FIRST_OFFSET + AMAP_DATA_SIZE) as u32),
+                AnsiByteIndex::new(AMAP_FIRST_OFFSET as u32),
+                AnsiByteIndex::new(0),
+                AnsiByteIndex::new(0),
+                page_ref(1, node_btree),
+                page_ref(2, block_btree),
+                AmapStatus::Invalid,
+            );
+            let mut header = AnsiHeader::new(root, source.header().crypt_method());
+            header.set_next_page(AnsiPageId::from(3));
+
+            let mut file = File::create(&path).unwrap();
+            file.set_len(AMAP_FIRST_OFFSET + AMAP_DATA_SIZE).unwrap();
+            header.write(&mut file).unwrap();
+            let page = AnsiNodeBTreePage::new(
+                0,
+                (ANSI_BTREE_ENTRIES_SIZE / AnsiNodeBTreeEntry::ENTRY_SIZE) as u8,
+                AnsiNodeBTreeEntry::ENTRY_SIZE as u8,
+                &[],
+                trailer(PageType::NodeBTree, 1, node_btree),
+            )
+            .unwrap();
+            file.seek(SeekFrom::Start(node_btree)).unwrap();
+            RootBTreeLeafPageReadWrite::write(&page, &mut file).unwrap();
+            let page = AnsiBlockBTreePage::new(
+                0,
+                (ANSI_BTREE_ENTRIES_SIZE / AnsiBlockBTreeEntry::ENTRY_SIZE) as u8,
+                AnsiBlockBTreeEntry::ENTRY_SIZE as u8,
+                &[],
+                trailer(PageType::BlockBTree, 2, block_btree),
+            )
+            .unwrap();
+            file.seek(SeekFrom::Start(block_btree)).unwrap();
+            RootBTreeLeafPageReadWrite::write(&page, &mut file).unwrap();
+        }
+
+        // Nodes without any data, e.g. an empty search update queue, cannot be created from a
+        // NodeData, so add their NBT entries directly.
+        let (empty, nodes): (Vec<_>, Vec<_>) = nodes
+            .into_iter()
+            .partition(|(_, data, _)| u64::from(*data) == 0);
+
+        let mut pst = AnsiPstFile::open(&path).unwrap();
+        let mut guard = pst.lock().unwrap();
+        for (node, _, parent) in nodes {
+            let data = source.read_node_data(node).unwrap();
+            guard.create_node(node, parent, &data).unwrap();
+        }
+        guard.flush().unwrap();
+        drop(guard);
+
+        pst.inner.start_write().unwrap();
+        for (node, _, parent) in empty {
+            pst.inner
+                .insert_node_btree_entry(AnsiNodeBTreeEntry::new(
+                    node,
+                    AnsiBlockId::from(0),
+                    None,
+                    parent,
+                ))
+                .unwrap();
+        }
+        pst.inner.finish_write().unwrap();
+        path
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = if self.0.is_dir() {
+            fs::remove_dir_all(&self.0)
+        } else {
+            fs::remove_file(&self.0)
+        };
+    }
+}
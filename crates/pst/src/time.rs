@@ -0,0 +1,579 @@
+//! Conversions between `FILETIME`, which is how `PtypTime` values are stored, and
+//! [`SystemTime`], Unix time, calendar dates, and local time.
+//!
+//! A `FILETIME` counts 100 nanosecond ticks since January 1, 1601 UTC. Like Unix time, it does not
+//! count leap seconds, so every day has exactly 86,400 seconds. A leap second such as `23:59:60`
+//! in a parsed date is read as `23:59:59`, so it stays in the same minute and day instead of
+//! rolling over into the next one.
+//!
+//! Some properties hold sentinel values instead of a time: 0 if the time was never set, and
+//! [`FILETIME_NONE`] or [`i64::MAX`] for "none" or "never", e.g. a task without a due date.
+//! [`FileTimeValue::classify`] tells them apart from real times, and [`to_system_time`] applies an
+//! [`InvalidTimePolicy`] to values which are neither.
+//!
+//! The times in a PST file are in UTC. [`TimeZoneRule`] converts them to and from local time,
+//! including daylight saving time, using the rule in `PidLidTimeZoneStruct`.
+
+use std::{
+    fmt::{self, Display, Formatter},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::ltp::{LtpError, LtpResult};
+
+/// Seconds between the `FILETIME` epoch in 1601 and the Unix epoch in 1970.
+pub const FILETIME_UNIX_EPOCH_SECONDS: i64 = 11_644_473_600;
+
+/// `FILETIME` ticks are 100 nanoseconds.
+pub const FILETIME_TICKS_PER_SECOND: i64 = 10_000_000;
+
+/// January 1, 4501, which Outlook uses for a time which is not set, e.g. `PidLidTaskDueDate`
+/// without a due date.
+pub const FILETIME_NONE: i64 = 0x0CB3_4557_A3DD_4000;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// What a `FILETIME` value means, see the [module](self) documentation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileTimeValue {
+    /// 0, i.e. the time was never set.
+    NotSet,
+    /// [`FILETIME_NONE`] or [`i64::MAX`].
+    Never,
+    /// A time after the 1601 epoch.
+    Time(i64),
+    /// A negative value, which is before the 1601 epoch.
+    Invalid(i64),
+}
+
+impl FileTimeValue {
+    pub fn classify(filetime: i64) -> Self {
+        match filetime {
+            0 => Self::NotSet,
+            FILETIME_NONE | i64::MAX => Self::Never,
+            filetime if filetime < 0 => Self::Invalid(filetime),
+            filetime => Self::Time(filetime),
+        }
+    }
+
+    /// The time, or `None` for the sentinel and invalid values.
+    pub fn time(&self) -> Option<i64> {
+        match self {
+            Self::Time(filetime) => Some(*filetime),
+            _ => None,
+        }
+    }
+}
+
+/// How [`to_system_time`] handles a value which is not a valid time: one before the 1601 epoch,
+/// or one which [`SystemTime`] cannot represent on this platform.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidTimePolicy {
+    /// Fail with [`LtpError::InvalidPropertyTime`].
+    #[default]
+    Error,
+    /// Use the nearest time which is valid.
+    Clamp,
+    /// Treat it like a time which is not set.
+    Ignore,
+}
+
+/// Convert a `FILETIME` to a [`SystemTime`], or `None` if it is one of the sentinel values. Invalid
+/// values are handled according to `policy`.
+pub fn to_system_time(filetime: i64, policy: InvalidTimePolicy) -> LtpResult<Option<SystemTime>> {
+    let filetime = match FileTimeValue::classify(filetime) {
+        FileTimeValue::NotSet | FileTimeValue::Never => return Ok(None),
+        FileTimeValue::Time(filetime) | FileTimeValue::Invalid(filetime) => filetime,
+    };
+    let time = if filetime < 0 {
+        None
+    } else {
+        filetime_to_system_time(filetime)
+    };
+    match (time, policy) {
+        (Some(time), _) => Ok(Some(time)),
+        (None, InvalidTimePolicy::Error) => Err(LtpError::InvalidPropertyTime(filetime)),
+        (None, InvalidTimePolicy::Clamp) => Ok(Some(clamp_to_system_time(filetime))),
+        (None, InvalidTimePolicy::Ignore) => Ok(None),
+    }
+}
+
+/// Convert any `FILETIME`, including the sentinel values and ones before 1601, to the exact same
+/// [`SystemTime`], or `None` if this platform cannot represent it.
+pub fn filetime_to_system_time(filetime: i64) -> Option<SystemTime> {
+    let ticks = filetime.unsigned_abs();
+    let ticks_per_second = FILETIME_TICKS_PER_SECOND as u64;
+    let duration = Duration::new(
+        ticks / ticks_per_second,
+        (ticks % ticks_per_second * 100) as u32,
+    );
+    let epoch = UNIX_EPOCH.checked_sub(Duration::from_secs(FILETIME_UNIX_EPOCH_SECONDS as u64))?;
+    if filetime < 0 {
+        epoch.checked_sub(duration)
+    } else {
+        epoch.checked_add(duration)
+    }
+}
+
+fn clamp_to_system_time(filetime: i64) -> SystemTime {
+    let filetime = filetime.max(0);
+    if let Some(time) = filetime_to_system_time(filetime) {
+        return time;
+    }
+    // Search for the latest representable time, which depends on the platform.
+    let (mut valid, mut invalid) = (0, filetime);
+    while invalid - valid > 1 {
+        let middle = valid + (invalid - valid) / 2;
+        if filetime_to_system_time(middle).is_some() {
+            valid = middle;
+        } else {
+            invalid = middle;
+        }
+    }
+    filetime_to_system_time(valid).unwrap_or(UNIX_EPOCH)
+}
+
+/// Convert a [`SystemTime`] to a `FILETIME`. Times outside the range of `PtypTime`, roughly
+/// 29,000 years either side of 1601, are clamped to the nearest value which fits.
+pub fn from_system_time(time: SystemTime) -> i64 {
+    let (since_epoch, before_epoch) = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (duration, false),
+        Err(err) => (err.duration(), true),
+    };
+    let ticks = i128::from(since_epoch.as_secs()) * i128::from(FILETIME_TICKS_PER_SECOND)
+        + i128::from(since_epoch.subsec_nanos() / 100);
+    let ticks = if before_epoch { -ticks } else { ticks };
+    let filetime =
+        ticks + i128::from(FILETIME_UNIX_EPOCH_SECONDS) * i128::from(FILETIME_TICKS_PER_SECOND);
+    filetime.clamp(i128::from(i64::MIN), i128::from(i64::MAX)) as i64
+}
+
+/// Convert seconds since the Unix epoch to a `FILETIME`, saturating at the limits of `i64`.
+pub fn from_unix_seconds(seconds: i64) -> i64 {
+    seconds
+        .saturating_add(FILETIME_UNIX_EPOCH_SECONDS)
+        .saturating_mul(FILETIME_TICKS_PER_SECOND)
+}
+
+/// Convert a `FILETIME` to whole seconds since the Unix epoch, rounding down.
+pub fn to_unix_seconds(filetime: i64) -> i64 {
+    filetime.div_euclid(FILETIME_TICKS_PER_SECOND) - FILETIME_UNIX_EPOCH_SECONDS
+}
+
+/// The current time as a `FILETIME`, in whole seconds.
+pub fn now() -> i64 {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs() as i64);
+    from_unix_seconds(seconds)
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The `(year, month, day)` for a number of days since 1970-01-01.
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// The day of the week for a number of days since 1970-01-01, from 0 for Sunday to 6 for
+/// Saturday.
+pub fn weekday(days: i64) -> i64 {
+    (days + 4).rem_euclid(7)
+}
+
+pub fn days_in_month(year: i64, month: i64) -> i64 {
+    let (next_year, next_month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    days_from_civil(next_year, next_month, 1) - days_from_civil(year, month, 1)
+}
+
+/// Seconds since the Unix epoch for a time of day on a calendar date. A leap second, i.e.
+/// `second == 60`, is read as the second before it.
+pub fn unix_seconds_from_civil(
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+) -> i64 {
+    days_from_civil(year, month, day) * SECONDS_PER_DAY
+        + hour * 3_600
+        + minute * 60
+        + second.min(59)
+}
+
+/// A `FILETIME` split into its UTC calendar date and time of day.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UtcDateTime {
+    pub year: i64,
+    pub month: i64,
+    pub day: i64,
+    pub hour: i64,
+    pub minute: i64,
+    pub second: i64,
+    /// 100 nanosecond ticks within the second.
+    pub ticks: i64,
+}
+
+impl UtcDateTime {
+    pub fn from_filetime(filetime: i64) -> Self {
+        let seconds = to_unix_seconds(filetime);
+        let (year, month, day) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+        let time = seconds.rem_euclid(SECONDS_PER_DAY);
+        Self {
+            year,
+            month,
+            day,
+            hour: time / 3_600,
+            minute: time / 60 % 60,
+            second: time % 60,
+            ticks: filetime.rem_euclid(FILETIME_TICKS_PER_SECOND),
+        }
+    }
+
+    /// The `FILETIME`, where a leap second is read as the second before it, see
+    /// [`unix_seconds_from_civil`].
+    pub fn to_filetime(&self) -> i64 {
+        let seconds = unix_seconds_from_civil(
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+        );
+        from_unix_seconds(seconds).saturating_add(self.ticks)
+    }
+}
+
+/// ISO 8601 in UTC, with a fraction of a second only if it has one.
+impl Display for UtcDateTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )?;
+        if self.ticks != 0 {
+            write!(f, ".{:07}", self.ticks)?;
+        }
+        f.write_str("Z")
+    }
+}
+
+/// `PidLidTimeZoneStruct` in `PSETID_Appointment`.
+pub const PID_LID_TIME_ZONE_STRUCT: u32 = 0x8233;
+
+/// The size of `PidLidTimeZoneStruct`.
+const TIME_ZONE_STRUCT_SIZE: usize = 48;
+
+/// A `SYSTEMTIME` in the format `TIME_ZONE_INFORMATION` uses for the date of a transition between
+/// standard and daylight saving time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransitionDate {
+    /// `wYear`: 0 if the transition happens every year, otherwise the only year it applies to.
+    pub year: u16,
+    /// `wMonth`: 0 if there is no transition.
+    pub month: u16,
+    /// `wDayOfWeek`: from 0 for Sunday to 6 for Saturday.
+    pub day_of_week: u16,
+    /// `wDay`: the week of the month from 1 to 4, or 5 for the last one, if [`Self::year`] is 0,
+    /// otherwise the day of the month.
+    pub day: u16,
+    pub hour: u16,
+    pub minute: u16,
+    pub second: u16,
+}
+
+impl TransitionDate {
+    fn read(data: &[u8]) -> Self {
+        let value = |index: usize| u16::from_le_bytes([data[index * 2], data[index * 2 + 1]]);
+        Self {
+            year: value(0),
+            month: value(1),
+            day_of_week: value(2),
+            day: value(3),
+            hour: value(4),
+            minute: value(5),
+            second: value(6),
+        }
+    }
+
+    /// The local time of the transition in `year`, in seconds since the Unix epoch.
+    fn local_seconds(&self, year: i64) -> i64 {
+        let month = i64::from(self.month);
+        let first = days_from_civil(year, month, 1);
+        let day = if self.year != 0 {
+            first + i64::from(self.day) - 1
+        } else {
+            let mut day = first
+                + (i64::from(self.day_of_week) - weekday(first)).rem_euclid(7)
+                + (i64::from(self.day).clamp(1, 5) - 1) * 7;
+            while day >= first + days_in_month(year, month) {
+                day -= 7;
+            }
+            day
+        };
+        day * SECONDS_PER_DAY
+            + i64::from(self.hour) * 3_600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second.min(59))
+    }
+}
+
+/// The standard and daylight saving time rule of a time zone, as stored in
+/// `PidLidTimeZoneStruct`. UTC is the local time plus [`Self::bias`] and either
+/// [`Self::standard_bias`] or [`Self::daylight_bias`], in minutes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimeZoneRule {
+    pub bias: i32,
+    pub standard_bias: i32,
+    pub daylight_bias: i32,
+    /// `stStandardDate`: when daylight saving time ends, in local daylight saving time.
+    pub standard_date: TransitionDate,
+    /// `stDaylightDate`: when daylight saving time starts, in local standard time.
+    pub daylight_date: TransitionDate,
+}
+
+impl TimeZoneRule {
+    /// Parse a `PidLidTimeZoneStruct` value.
+    pub fn read(data: &[u8]) -> LtpResult<Self> {
+        if data.len() != TIME_ZONE_STRUCT_SIZE {
+            return Err(LtpError::InvalidTimeZoneStructSize(data.len()));
+        }
+        let bias = |index: usize| {
+            i32::from_le_bytes([
+                data[index * 4],
+                data[index * 4 + 1],
+                data[index * 4 + 2],
+                data[index * 4 + 3],
+            ])
+        };
+        // wStandardYear and wDaylightYear repeat the wYear of the dates which follow them.
+        Ok(Self {
+            bias: bias(0),
+            standard_bias: bias(1),
+            daylight_bias: bias(2),
+            standard_date: TransitionDate::read(&data[14..30]),
+            daylight_date: TransitionDate::read(&data[32..48]),
+        })
+    }
+
+    pub fn has_daylight_saving_time(&self) -> bool {
+        self.standard_date.month != 0 && self.daylight_date.month != 0
+    }
+
+    /// Whether `local`, a `FILETIME` in local time, is in daylight saving time. A local time
+    /// which is skipped when the clocks go forward counts as daylight saving time, and one which
+    /// happens twice when they go back counts as standard time.
+    pub fn is_daylight_saving_time(&self, local: i64) -> bool {
+        let seconds = to_unix_seconds(local);
+        // The transition back to standard time is in local daylight saving time, so the hour
+        // which happens twice ends at the same time in local standard time.
+        let overlap = i64::from(self.daylight_bias - self.standard_bias) * 60;
+        self.in_daylight_saving_time(seconds, 0, overlap)
+    }
+
+    /// Whether `seconds` since the Unix epoch is in daylight saving time, when the local times of
+    /// the transitions are shifted by `start_shift` and `end_shift` seconds.
+    fn in_daylight_saving_time(&self, seconds: i64, start_shift: i64, end_shift: i64) -> bool {
+        if !self.has_daylight_saving_time() {
+            return false;
+        }
+        let (year, _, _) = civil_from_days(seconds.div_euclid(SECONDS_PER_DAY));
+        if !self.applies_in(year) {
+            return false;
+        }
+        let start = self.daylight_date.local_seconds(year) + start_shift;
+        let end = self.standard_date.local_seconds(year) + end_shift;
+        if start < end {
+            seconds >= start && seconds < end
+        } else {
+            seconds >= start || seconds < end
+        }
+    }
+
+    fn applies_in(&self, year: i64) -> bool {
+        [self.standard_date.year, self.daylight_date.year]
+            .into_iter()
+            .all(|rule_year| rule_year == 0 || i64::from(rule_year) == year)
+    }
+
+    /// Minutes to add to local time to get UTC.
+    fn total_bias(&self, daylight_saving_time: bool) -> i64 {
+        let bias = if daylight_saving_time {
+            self.bias + self.daylight_bias
+        } else {
+            self.bias + self.standard_bias
+        };
+        i64::from(bias)
+    }
+
+    /// Convert a local `FILETIME` to UTC, see [`Self::is_daylight_saving_time`] for the local
+    /// times around the transitions.
+    pub fn to_utc(&self, local: i64) -> i64 {
+        let bias = self.total_bias(self.is_daylight_saving_time(local));
+        local.saturating_add(bias * 60 * FILETIME_TICKS_PER_SECOND)
+    }
+
+    /// Convert a UTC `FILETIME` to local time.
+    pub fn to_local(&self, utc: i64) -> i64 {
+        let standard_bias = self.total_bias(false) * 60;
+        let daylight_bias = self.total_bias(true) * 60;
+        // Look up the year in local standard time, and shift the transitions from local time,
+        // standard time for the start and daylight saving time for the end, to UTC.
+        let seconds = to_unix_seconds(utc) - standard_bias;
+        let daylight_saving_time =
+            self.in_daylight_saving_time(seconds, 0, daylight_bias - standard_bias);
+        utc.saturating_sub(self.total_bias(daylight_saving_time) * 60 * FILETIME_TICKS_PER_SECOND)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2003-07-01 08:52:37 UTC
+    const FILETIME: i64 = 127_015_231_570_000_000;
+
+    #[test]
+    fn test_sentinels_and_policy() {
+        assert_eq!(FileTimeValue::classify(0), FileTimeValue::NotSet);
+        assert_eq!(FileTimeValue::classify(FILETIME_NONE), FileTimeValue::Never);
+        assert_eq!(FileTimeValue::classify(i64::MAX), FileTimeValue::Never);
+        assert_eq!(FileTimeValue::classify(-1), FileTimeValue::Invalid(-1));
+        assert_eq!(FileTimeValue::classify(FILETIME).time(), Some(FILETIME));
+        assert_eq!(
+            UtcDateTime::from_filetime(FILETIME_NONE).to_string(),
+            "4501-01-01T00:00:00Z"
+        );
+
+        for policy in [
+            InvalidTimePolicy::Error,
+            InvalidTimePolicy::Clamp,
+            InvalidTimePolicy::Ignore,
+        ] {
+            assert_eq!(to_system_time(0, policy).unwrap(), None);
+            assert_eq!(to_system_time(i64::MAX, policy).unwrap(), None);
+            let time = to_system_time(FILETIME, policy).unwrap().unwrap();
+            assert_eq!(
+                time.duration_since(UNIX_EPOCH).unwrap(),
+                Duration::from_secs(1_057_049_557)
+            );
+            assert_eq!(from_system_time(time), FILETIME);
+        }
+
+        assert!(matches!(
+            to_system_time(-1, InvalidTimePolicy::Error),
+            Err(LtpError::InvalidPropertyTime(-1))
+        ));
+        assert_eq!(to_system_time(-1, InvalidTimePolicy::Ignore).unwrap(), None);
+        let clamped = to_system_time(-1, InvalidTimePolicy::Clamp).unwrap();
+        assert_eq!(clamped, filetime_to_system_time(0));
+    }
+
+    #[test]
+    fn test_civil_time() {
+        let time = UtcDateTime::from_filetime(FILETIME + 5);
+        assert_eq!(
+            (time.year, time.month, time.day, time.hour, time.minute),
+            (2003, 7, 1, 8, 52)
+        );
+        assert_eq!(time.to_string(), "2003-07-01T08:52:37.0000005Z");
+        assert_eq!(time.to_filetime(), FILETIME + 5);
+        assert_eq!(to_unix_seconds(FILETIME), 1_057_049_557);
+        assert_eq!(from_unix_seconds(1_057_049_557), FILETIME);
+        assert_eq!(weekday(days_from_civil(2003, 7, 1)), 2);
+
+        // A leap second stays in the last minute of the year.
+        let leap_second = unix_seconds_from_civil(2016, 12, 31, 23, 59, 60);
+        assert_eq!(
+            leap_second,
+            unix_seconds_from_civil(2016, 12, 31, 23, 59, 59)
+        );
+        assert_eq!(civil_from_days(leap_second.div_euclid(86_400)).0, 2016);
+    }
+
+    #[test]
+    fn test_time_zone_rule() {
+        // Pacific Time: UTC-8, with daylight saving time from the second Sunday in March at 2:00
+        // till the first Sunday in November at 2:00.
+        let mut data = Vec::new();
+        for bias in [480_i32, 0, -60] {
+            data.extend_from_slice(&bias.to_le_bytes());
+        }
+        for date in [[0_u16, 11, 0, 1, 2, 0, 0, 0], [0, 3, 0, 2, 2, 0, 0, 0]] {
+            data.extend_from_slice(&0_u16.to_le_bytes());
+            data.extend(date.into_iter().flat_map(u16::to_le_bytes));
+        }
+        let rule = TimeZoneRule::read(&data).unwrap();
+        assert!(rule.has_daylight_saving_time());
+        assert!(TimeZoneRule::read(&data[1..]).is_err());
+
+        let hour = 3_600 * FILETIME_TICKS_PER_SECOND;
+        let at = |month, day, hour| {
+            from_unix_seconds(unix_seconds_from_civil(2024, month, day, hour, 0, 0))
+        };
+
+        // Summer is UTC-7 and winter is UTC-8.
+        assert_eq!(rule.to_local(at(7, 1, 12)), at(7, 1, 5));
+        assert_eq!(rule.to_utc(at(7, 1, 5)), at(7, 1, 12));
+        assert_eq!(rule.to_local(at(1, 15, 12)), at(1, 15, 4));
+        assert_eq!(rule.to_utc(at(1, 15, 4)), at(1, 15, 12));
+
+        // 2024-03-10 at 2:00 the clocks went forward to 3:00, so 1:59 and 3:00 are a minute apart.
+        assert_eq!(rule.to_local(at(3, 10, 10)), at(3, 10, 3));
+        assert_eq!(rule.to_local(at(3, 10, 10) - 1), at(3, 10, 2) - 1);
+        assert_eq!(
+            rule.to_utc(at(3, 10, 2) + hour / 2),
+            at(3, 10, 9) + hour / 2
+        );
+        // 2024-11-03 at 2:00 the clocks went back to 1:00, so 1:30 happened twice.
+        assert_eq!(
+            rule.to_local(at(11, 3, 8) + hour / 2),
+            at(11, 3, 1) + hour / 2
+        );
+        assert_eq!(
+            rule.to_local(at(11, 3, 9) + hour / 2),
+            at(11, 3, 1) + hour / 2
+        );
+        assert_eq!(
+            rule.to_utc(at(11, 3, 1) + hour / 2),
+            at(11, 3, 9) + hour / 2
+        );
+
+        let no_dst = TimeZoneRule {
+            bias: -60,
+            ..Default::default()
+        };
+        assert_eq!(no_dst.to_local(at(7, 1, 12)), at(7, 1, 13));
+        assert_eq!(no_dst.to_utc(at(7, 1, 13)), at(7, 1, 12));
+    }
+}
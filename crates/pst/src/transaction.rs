@@ -0,0 +1,381 @@
+//! Buffer the pages and blocks written in a transaction, so each of them reaches the file once,
+//! and in dependency order, when the transaction is committed.
+
+use std::{
+    cmp,
+    collections::BTreeMap,
+    io::{self, Read, Seek, SeekFrom, Write},
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, PoisonError, RwLock, RwLockReadGuard,
+    },
+};
+
+use crate::{ndb::page::PageType, positioned::ReadAt};
+
+/// What a pending write holds. Pending writes are committed in this order, so everything which a
+/// BTree entry points at is in the file before the BTree page, and the header which points at
+/// the BTree roots is written last.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum WriteKind {
+    /// Data blocks, and the intermediate blocks of data trees and sub-node trees.
+    Block,
+    /// Pages of the Block BTree.
+    BlockBTree,
+    /// Pages of the Node BTree.
+    NodeBTree,
+    /// AMap, PMap, FMap, and FPMap pages, and the density list.
+    AllocationMap,
+    /// The file header.
+    Header,
+}
+
+impl From<PageType> for WriteKind {
+    fn from(page_type: PageType) -> Self {
+        match page_type {
+            PageType::BlockBTree => Self::BlockBTree,
+            PageType::NodeBTree => Self::NodeBTree,
+            PageType::None
+            | PageType::FreeMap
+            | PageType::AllocationPageMap
+            | PageType::AllocationMap
+            | PageType::FreePageMap
+            | PageType::DensityList => Self::AllocationMap,
+        }
+    }
+}
+
+/// A pending write which is ready to commit: its kind, file offset, and data.
+pub(crate) type CommitWrite = (WriteKind, u64, Vec<u8>);
+
+struct PendingWrite {
+    kind: WriteKind,
+    data: Vec<u8>,
+}
+
+impl PendingWrite {
+    fn end(&self, offset: u64) -> u64 {
+        offset + self.data.len() as u64
+    }
+}
+
+/// The dirty pages and blocks which have not been committed to the file yet, keyed by file
+/// offset. Overlapping writes are coalesced, so a page which is updated several times in one
+/// transaction is only written once.
+#[derive(Default)]
+pub(crate) struct TransactionBuffer {
+    writes: BTreeMap<u64, PendingWrite>,
+    file_len: Option<u64>,
+}
+
+impl TransactionBuffer {
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty() && self.file_len.is_none()
+    }
+
+    /// The offsets of the pending writes which overlap `offset..end`, in descending order. The
+    /// pending writes never overlap each other, so the search stops at the first one which ends
+    /// before `offset`.
+    fn overlapping(&self, offset: u64, end: u64) -> impl Iterator<Item = (&u64, &PendingWrite)> {
+        self.writes
+            .range(..end)
+            .rev()
+            .take_while(move |(&start, write)| write.end(start) > offset)
+    }
+
+    fn write(&mut self, kind: WriteKind, offset: u64, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        let end = offset + data.len() as u64;
+        let overlapping: Vec<_> = self
+            .overlapping(offset, end)
+            .map(|(&start, _)| start)
+            .collect();
+        let mut start = offset;
+        let mut merged_end = end;
+        let mut merged_kind = kind;
+        let mut previous = Vec::with_capacity(overlapping.len());
+        for key in overlapping {
+            if let Some(write) = self.writes.remove(&key) {
+                start = cmp::min(start, key);
+                merged_end = cmp::max(merged_end, write.end(key));
+                merged_kind = cmp::max(merged_kind, write.kind);
+                previous.push((key, write.data));
+            }
+        }
+
+        let mut merged = vec![0; (merged_end - start) as usize];
+        for (key, data) in previous {
+            let from = (key - start) as usize;
+            merged[from..from + data.len()].copy_from_slice(&data);
+        }
+        let from = (offset - start) as usize;
+        merged[from..from + data.len()].copy_from_slice(data);
+
+        self.writes.insert(
+            start,
+            PendingWrite {
+                kind: merged_kind,
+                data: merged,
+            },
+        );
+    }
+
+    /// Total size of the pending writes, which is how much memory the transaction holds.
+    pub fn pending_bytes(&self) -> u64 {
+        self.writes
+            .values()
+            .map(|write| write.data.len() as u64)
+            .sum()
+    }
+
+    /// Extend the file to at least `len` bytes when the transaction is committed.
+    pub fn set_file_len(&mut self, len: u64) {
+        self.file_len = Some(
+            self.file_len
+                .map_or(len, |file_len| cmp::max(file_len, len)),
+        );
+    }
+
+    /// Copy the pending writes which overlap `buf` at file `offset` on top of it, and return the
+    /// number of bytes at the start of `buf` which were covered, including any gaps between them.
+    fn overlay(&self, offset: u64, buf: &mut [u8]) -> usize {
+        let end = offset + buf.len() as u64;
+        let mut covered = 0;
+        for (&start, write) in self.overlapping(offset, end) {
+            let from = cmp::max(start, offset);
+            let to = cmp::min(write.end(start), end);
+            buf[(from - offset) as usize..(to - offset) as usize]
+                .copy_from_slice(&write.data[(from - start) as usize..(to - start) as usize]);
+            covered = cmp::max(covered, (to - offset) as usize);
+        }
+        covered
+    }
+
+    /// Remove every pending write, sorted by [`WriteKind`] and then by file offset, along with the
+    /// length the file should be extended to.
+    pub fn take_ordered(&mut self) -> (Option<u64>, Vec<CommitWrite>) {
+        let mut writes: Vec<_> = mem::take(&mut self.writes)
+            .into_iter()
+            .map(|(offset, write)| (write.kind, offset, write.data))
+            .collect();
+        writes.sort_by_key(|(kind, offset, _)| (*kind, *offset));
+        (self.file_len.take(), writes)
+    }
+
+    /// Forget every pending write, e.g. when the transaction is rolled back.
+    pub fn clear(&mut self) {
+        self.writes.clear();
+        self.file_len = None;
+    }
+}
+
+/// The [`TransactionBuffer`] of one file, shared by its [`TransactionReader`]s and
+/// [`TransactionWriter`]s. Readers only take the lock while there are pending writes, so reads
+/// outside of a transaction never wait for each other.
+#[derive(Default)]
+pub(crate) struct SharedTransaction {
+    buffer: RwLock<TransactionBuffer>,
+    pending: AtomicBool,
+}
+
+impl SharedTransaction {
+    /// Lock the buffer for reading if there are any pending writes.
+    fn pending(&self) -> Option<RwLockReadGuard<'_, TransactionBuffer>> {
+        if self.pending.load(Ordering::Acquire) {
+            Some(self.buffer.read().unwrap_or_else(PoisonError::into_inner))
+        } else {
+            None
+        }
+    }
+
+    /// Call `f` with the buffer locked for writing, and then update the flag which tells readers
+    /// whether to look at it.
+    pub fn update<T>(&self, f: impl FnOnce(&mut TransactionBuffer) -> T) -> T {
+        let mut buffer = self.buffer.write().unwrap_or_else(PoisonError::into_inner);
+        let result = f(&mut buffer);
+        self.pending.store(!buffer.is_empty(), Ordering::Release);
+        result
+    }
+
+    pub fn pending_bytes(&self) -> u64 {
+        self.pending().map_or(0, |buffer| buffer.pending_bytes())
+    }
+}
+
+/// Reads from a [`PstFile`](crate::PstFile), with the pages and blocks written earlier in a
+/// transaction on top, so they can be read back before it is committed. Each call to
+/// [`PstFile::reader`](crate::PstFile::reader) returns a new reader with its own position, and
+/// reads go straight to the [`ReadAt`] source, so readers on different threads never wait for
+/// each other.
+pub struct TransactionReader<'a> {
+    file: &'a dyn ReadAt,
+    buffer: &'a SharedTransaction,
+    position: u64,
+}
+
+impl<'a> TransactionReader<'a> {
+    pub(crate) fn new(file: &'a dyn ReadAt, buffer: &'a SharedTransaction) -> Self {
+        Self {
+            file,
+            buffer,
+            position: 0,
+        }
+    }
+}
+
+impl Read for TransactionReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(buffer) = self.buffer.pending() else {
+            let read = self.file.read_at(buf, self.position)?;
+            self.position += read as u64;
+            return Ok(read);
+        };
+
+        let mut read = 0;
+        while read < buf.len() {
+            match self
+                .file
+                .read_at(&mut buf[read..], self.position + read as u64)
+            {
+                Ok(0) => break,
+                Ok(size) => read += size,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        buf[read..].fill(0);
+
+        let size = cmp::max(read, buffer.overlay(self.position, buf));
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl Seek for TransactionReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                let file_len = self.file.len()?;
+                let file_len = self
+                    .buffer
+                    .pending()
+                    .and_then(|buffer| buffer.file_len)
+                    .map_or(file_len, |len| cmp::max(file_len, len));
+                file_len.checked_add_signed(offset)
+            }
+        }
+        .ok_or(io::ErrorKind::InvalidInput)?;
+        Ok(self.position)
+    }
+
+    fn stream_position(&mut self) -> io::Result<u64> {
+        Ok(self.position)
+    }
+}
+
+/// Records writes of one [`WriteKind`] in the [`TransactionBuffer`] instead of writing them to
+/// the file.
+pub(crate) struct TransactionWriter {
+    buffer: Arc<SharedTransaction>,
+    kind: WriteKind,
+    position: u64,
+}
+
+impl TransactionWriter {
+    pub fn new(buffer: Arc<SharedTransaction>, kind: WriteKind) -> Self {
+        Self {
+            buffer,
+            kind,
+            position: 0,
+        }
+    }
+}
+
+impl Write for TransactionWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer
+            .update(|buffer| buffer.write(self.kind, self.position, buf));
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    /// Pending writes are only written to the file when the transaction is committed.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for TransactionWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(_) => None,
+        }
+        .ok_or(io::ErrorKind::InvalidInput)?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::positioned::SharedReader;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_coalesce_writes() {
+        let mut buffer = TransactionBuffer::default();
+        buffer.write(WriteKind::Block, 16, &[1; 8]);
+        buffer.write(WriteKind::Block, 32, &[2; 8]);
+        buffer.write(WriteKind::NodeBTree, 20, &[3; 16]);
+        buffer.write(WriteKind::Header, 0, &[4; 4]);
+
+        let (file_len, writes) = buffer.take_ordered();
+        assert_eq!(file_len, None);
+        assert_eq!(
+            writes,
+            vec![
+                (
+                    WriteKind::NodeBTree,
+                    16,
+                    [[1; 4], [3; 4], [3; 4], [3; 4], [3; 4], [2; 4]].concat()
+                ),
+                (WriteKind::Header, 0, vec![4; 4]),
+            ]
+        );
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_read_pending_writes() {
+        let buffer = Arc::new(SharedTransaction::default());
+        let file = SharedReader::new(Cursor::new(vec![1_u8; 16]));
+        let mut reader = TransactionReader::new(&file, &buffer);
+
+        let mut writer = TransactionWriter::new(buffer.clone(), WriteKind::Block);
+        writer.seek(SeekFrom::Start(12)).unwrap();
+        writer.write_all(&[2; 8]).unwrap();
+        buffer.update(|buffer| buffer.set_file_len(32));
+        assert_eq!(buffer.pending_bytes(), 8);
+
+        let mut data = vec![];
+        reader.seek(SeekFrom::Start(8)).unwrap();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, [[1; 4], [2; 4], [2; 4]].concat());
+        assert_eq!(reader.seek(SeekFrom::End(0)).unwrap(), 32);
+
+        buffer.update(TransactionBuffer::clear);
+        assert_eq!(buffer.pending_bytes(), 0);
+        let mut data = vec![];
+        reader.seek(SeekFrom::Start(8)).unwrap();
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, vec![1; 8]);
+    }
+}
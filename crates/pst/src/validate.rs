@@ -0,0 +1,232 @@
+//! Structural checks for the nodes and BTree pages written in a transaction, which run before it
+//! is committed when [`PstFileLockGuard::set_validate_before_commit`](crate::PstFileLockGuard::set_validate_before_commit)
+//! is enabled.
+
+use std::fmt::{self, Display};
+
+use crate::{
+    ltp::{
+        heap::HeapNodeType, prop_context::validate_property_context,
+        table_context::validate_table_context,
+    },
+    ndb::{header::NdbVersion, node_data::NodeData, node_id::*},
+};
+
+/// The kind of structure which failed validation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValidationTarget {
+    /// A page of the [`Node BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085)
+    /// at the given file offset.
+    NodeBTreePage(u64),
+    /// A page of the [`Block BTree`](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085)
+    /// at the given file offset.
+    BlockBTreePage(u64),
+    /// The data of a node in the Node BTree, or of a sub-node nested anywhere under it.
+    Node {
+        node: NodeId,
+        sub_node: Option<NodeId>,
+    },
+}
+
+/// A structure which was written in the current transaction, but which would not be readable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructuralIssue {
+    pub(crate) target: ValidationTarget,
+    pub(crate) description: String,
+}
+
+impl StructuralIssue {
+    pub fn target(&self) -> ValidationTarget {
+        self.target
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+impl Display for StructuralIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.target {
+            ValidationTarget::NodeBTreePage(offset) => {
+                write!(f, "NBT page at 0x{offset:X}: {}", self.description)
+            }
+            ValidationTarget::BlockBTreePage(offset) => {
+                write!(f, "BBT page at 0x{offset:X}: {}", self.description)
+            }
+            ValidationTarget::Node {
+                node,
+                sub_node: None,
+            } => write!(f, "{node:?}: {}", self.description),
+            ValidationTarget::Node {
+                node,
+                sub_node: Some(sub_node),
+            } => write!(f, "{node:?}, {sub_node:?}: {}", self.description),
+        }
+    }
+}
+
+/// Whether the data of `node` is a PC or a TC. Nodes of other types, e.g. the values in
+/// [`NodeIdType::ListsTablesProperties`] sub-nodes, hold raw data which is not checked.
+fn expected_heap_type(node: NodeId) -> Option<HeapNodeType> {
+    match node.id_type().ok()? {
+        NodeIdType::NormalFolder
+        | NodeIdType::SearchFolder
+        | NodeIdType::NormalMessage
+        | NodeIdType::AssociatedMessage
+        | NodeIdType::Attachment => Some(HeapNodeType::Properties),
+        NodeIdType::Internal if [NID_MESSAGE_STORE, NID_NAME_TO_ID_MAP].contains(&node) => {
+            Some(HeapNodeType::Properties)
+        }
+        NodeIdType::HierarchyTable
+        | NodeIdType::ContentsTable
+        | NodeIdType::AssociatedContentsTable
+        | NodeIdType::SearchContentsTable
+        | NodeIdType::AttachmentTable
+        | NodeIdType::RecipientTable
+        | NodeIdType::ReceiveFolderTable
+        | NodeIdType::OutgoingQueueTable => Some(HeapNodeType::Table),
+        _ => None,
+    }
+}
+
+/// Check the heap, BTH, and TC structures in the data of `node` and, recursively, each of its
+/// sub-nodes.
+pub(crate) fn validate_node_data(
+    version: NdbVersion,
+    node: NodeId,
+    data: &NodeData,
+) -> Vec<StructuralIssue> {
+    let mut issues = vec![];
+    validate_sub_node_data(version, node, None, data, &mut issues);
+    issues
+}
+
+fn validate_sub_node_data(
+    version: NdbVersion,
+    node: NodeId,
+    sub_node: Option<NodeId>,
+    data: &NodeData,
+    issues: &mut Vec<StructuralIssue>,
+) {
+    let result = match expected_heap_type(sub_node.unwrap_or(node)) {
+        Some(HeapNodeType::Properties) => validate_property_context(data),
+        Some(HeapNodeType::Table) => validate_table_context(version, data),
+        _ => Ok(()),
+    };
+    if let Err(err) = result {
+        issues.push(StructuralIssue {
+            target: ValidationTarget::Node { node, sub_node },
+            description: err.to_string(),
+        });
+    }
+
+    for (&nested, data) in data.sub_nodes() {
+        validate_sub_node_data(version, node, Some(nested), data, issues);
+    }
+}
+
+/// Check that a BTree page is at the `expected` level, which is one less than its parent.
+pub(crate) fn check_btree_page_level(level: u8, expected: Option<u8>) -> Option<String> {
+    match expected {
+        Some(expected) if level != expected => Some(format!(
+            "cLevel is {level}, but the parent page expects {expected}"
+        )),
+        _ => None,
+    }
+}
+
+/// Check that the `keys` of a BTree page are strictly ascending, and that they are in the range
+/// covered by the entry for the page in its parent: at least `lower` and less than `upper`.
+pub(crate) fn check_btree_page_keys(
+    keys: &[u64],
+    lower: Option<u64>,
+    upper: Option<u64>,
+) -> Option<String> {
+    if let Some(index) = keys.windows(2).position(|pair| pair[0] >= pair[1]) {
+        return Some(format!(
+            "key 0x{:X} at index {} is not greater than the key before it",
+            keys[index + 1],
+            index + 1
+        ));
+    }
+    match (keys.first(), keys.last()) {
+        (Some(&first), _) if lower.is_some_and(|lower| first < lower) => Some(format!(
+            "first key 0x{first:X} is less than the parent entry key 0x{:X}",
+            lower.unwrap_or_default()
+        )),
+        (_, Some(&last)) if upper.is_some_and(|upper| last >= upper) => Some(format!(
+            "last key 0x{last:X} is not less than the next parent entry key 0x{:X}",
+            upper.unwrap_or_default()
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ltp::{
+        heap::HeapFillLevel,
+        prop_context::{build_property_context, BinaryValue, PropertyValue},
+        prop_type::PropertyType,
+        table_context::{build_table_context, TableRowId, TableRows},
+    };
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_btree_page_keys() {
+        assert_eq!(check_btree_page_keys(&[], Some(4), Some(8)), None);
+        assert_eq!(check_btree_page_keys(&[4, 5, 7], Some(4), Some(8)), None);
+        assert!(check_btree_page_keys(&[4, 4, 7], None, None).is_some());
+        assert!(check_btree_page_keys(&[5, 4], None, None).is_some());
+        assert!(check_btree_page_keys(&[3, 5], Some(4), None).is_some());
+        assert!(check_btree_page_keys(&[4, 8], None, Some(8)).is_some());
+    }
+
+    #[test]
+    fn test_validate_node_data() {
+        let properties = BTreeMap::from([
+            (0x0037, PropertyValue::Integer32(1)),
+            (0x3001, PropertyValue::Binary(BinaryValue::new(vec![0; 64]))),
+        ]);
+        let message = NodeId::new(NodeIdType::NormalMessage, 0x400).unwrap();
+        let mut data = build_property_context(properties.iter()).unwrap();
+
+        let columns = [(0x3001, PropertyType::Integer32)];
+        let rows = TableRows::from([
+            (
+                TableRowId::new(1),
+                BTreeMap::from([(0x3001, PropertyValue::Integer32(1))]),
+            ),
+            (
+                TableRowId::new(2),
+                BTreeMap::from([(0x3001, PropertyValue::Integer32(2))]),
+            ),
+        ]);
+        for version in [NdbVersion::Unicode, NdbVersion::Ansi] {
+            data.insert_sub_node(
+                NID_RECIPIENT_TABLE,
+                build_table_context(version, &columns, &rows).unwrap(),
+            );
+            assert_eq!(validate_node_data(version, message, &data), vec![]);
+        }
+
+        // Claim that the first block of the PC is full.
+        let mut blocks = data.blocks().to_vec();
+        blocks[0][8] = HeapFillLevel::Level15 as u8;
+        let mut corrupted = NodeData::new(blocks);
+        for (sub_node, data) in data.sub_nodes() {
+            corrupted.insert_sub_node(*sub_node, data.clone());
+        }
+        let issues = validate_node_data(NdbVersion::Ansi, message, &corrupted);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            issues[0].target(),
+            ValidationTarget::Node {
+                node: message,
+                sub_node: None
+            }
+        );
+    }
+}
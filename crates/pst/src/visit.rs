@@ -0,0 +1,156 @@
+//! Read-only traversal of every block in the
+//! [Block BTree](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/7d759bcb-7864-480c-8746-f6af913ab085),
+//! for analytics which need the raw contents rather than the messaging layer, e.g. entropy scans
+//! or carving known file signatures out of attachment data.
+//!
+//! [`PstFile::visit_blocks`] calls a [`BlockVisitor`] once for each BBT entry, in file offset
+//! order so the file is read sequentially. Data blocks are decoded with the
+//! [`NdbCryptMethod`](crate::ndb::header::NdbCryptMethod) of the file first, and internal blocks
+//! are classified by their header, see [`BlockKind`]. The CRC in the block trailer is not checked,
+//! so damaged blocks are visited as they are.
+
+use std::io;
+
+use crate::PstFile;
+
+/// `btype` of an XBLOCK or XXBLOCK.
+const BTYPE_DATA_TREE: u8 = 0x01;
+/// `btype` of an SLBLOCK or SIBLOCK.
+const BTYPE_SUB_NODE_TREE: u8 = 0x02;
+
+/// What a block holds, based on whether its BID is internal and the `btype` and `cLevel` fields
+/// at the start of an internal block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockKind {
+    /// An external block with the data of a node, or part of it.
+    Data,
+    /// [XBLOCK](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/5b7a6935-e83d-4917-9f62-6ce3707f09e0):
+    /// lists the data blocks of a data tree.
+    DataTree,
+    /// [XXBLOCK](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/061b6ac4-d1da-468c-b75d-0303a0a8f468):
+    /// lists the XBLOCKs of a data tree.
+    DataTreeIndirect,
+    /// [SLBLOCK](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/5182eb24-4b0b-4816-aa3f-719cc6e6b018):
+    /// lists the sub-nodes of a node.
+    SubNodeLeaf,
+    /// [SIBLOCK](https://learn.microsoft.com/en-us/openspecs/office_file_formats/ms-pst/729fb9bd-060a-4d6a-9c43-8aa2bb1c3ab2):
+    /// lists the SLBLOCKs of a sub-node tree.
+    SubNodeIntermediate,
+    /// An internal block whose header does not match any of the other kinds.
+    UnknownInternal,
+}
+
+impl BlockKind {
+    /// Classify a block from its BID and its decoded data.
+    pub fn classify(is_internal: bool, data: &[u8]) -> Self {
+        if !is_internal {
+            return Self::Data;
+        }
+
+        match data {
+            [BTYPE_DATA_TREE, 1, ..] => Self::DataTree,
+            [BTYPE_DATA_TREE, 2, ..] => Self::DataTreeIndirect,
+            [BTYPE_SUB_NODE_TREE, 0, ..] => Self::SubNodeLeaf,
+            [BTYPE_SUB_NODE_TREE, 1, ..] => Self::SubNodeIntermediate,
+            _ => Self::UnknownInternal,
+        }
+    }
+
+    pub fn is_internal(&self) -> bool {
+        !matches!(self, Self::Data)
+    }
+}
+
+/// Callback for [`PstFile::visit_blocks`]. Any closure which takes the same arguments as
+/// [`BlockVisitor::visit_block`] is a visitor.
+pub trait BlockVisitor<Pst: PstFile> {
+    /// Called with the BBT entry of each block, its [`BlockKind`], and its decoded data without
+    /// the trailer. Returning an error stops the traversal and returns the same error from
+    /// [`PstFile::visit_blocks`].
+    fn visit_block(
+        &mut self,
+        entry: &Pst::BlockBTreeEntry,
+        kind: BlockKind,
+        data: &[u8],
+    ) -> io::Result<()>;
+}
+
+impl<Pst, F> BlockVisitor<Pst> for F
+where
+    Pst: PstFile,
+    F: FnMut(&Pst::BlockBTreeEntry, BlockKind, &[u8]) -> io::Result<()>,
+{
+    fn visit_block(
+        &mut self,
+        entry: &Pst::BlockBTreeEntry,
+        kind: BlockKind,
+        data: &[u8],
+    ) -> io::Result<()> {
+        self(entry, kind, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ndb::{
+            block_id::BlockId,
+            block_ref::BlockRef,
+            byte_index::ByteIndex,
+            page::{BlockBTreeEntry, UnicodeBlockBTreeEntry},
+        },
+        UnicodePstFile,
+    };
+    use std::path::Path;
+
+    #[test]
+    fn test_classify_block_kind() {
+        assert_eq!(BlockKind::classify(false, &[0x01, 0x01]), BlockKind::Data);
+        assert_eq!(
+            BlockKind::classify(true, &[0x01, 0x01]),
+            BlockKind::DataTree
+        );
+        assert_eq!(
+            BlockKind::classify(true, &[0x01, 0x02]),
+            BlockKind::DataTreeIndirect
+        );
+        assert_eq!(
+            BlockKind::classify(true, &[0x02, 0x00]),
+            BlockKind::SubNodeLeaf
+        );
+        assert_eq!(
+            BlockKind::classify(true, &[0x02, 0x01]),
+            BlockKind::SubNodeIntermediate
+        );
+        assert_eq!(
+            BlockKind::classify(true, &[0x03]),
+            BlockKind::UnknownInternal
+        );
+    }
+
+    #[test]
+    fn test_visit_blocks_in_offset_order() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/Empty.pst");
+        let pst = UnicodePstFile::open(path).unwrap();
+
+        let mut offsets = vec![];
+        let mut heap_blocks = 0;
+        pst.visit_blocks(
+            &mut |entry: &UnicodeBlockBTreeEntry, kind: BlockKind, data: &[u8]| {
+                assert_eq!(data.len(), usize::from(entry.size()));
+                assert_eq!(kind.is_internal(), entry.block().block().is_internal());
+                // Decoded heap blocks start with an HNHDR, where bSig is 0xEC.
+                if kind == BlockKind::Data && data.get(2) == Some(&0xEC) {
+                    heap_blocks += 1;
+                }
+                offsets.push(entry.block().index().index());
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        assert!(heap_blocks > 0);
+        assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}